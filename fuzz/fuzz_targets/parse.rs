@@ -0,0 +1,38 @@
+//! Parse arbitrary input in each encoding, which must give a tree or an error and never panic.
+//! The pre-lexer must not change the outcome.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ustar_parser::{parse, EncodingMode, ErrorFormatMode, ParserConfigBuilder};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    for encoding in [
+        EncodingMode::Ascii,
+        EncodingMode::ExtendedAscii,
+        EncodingMode::Unicode,
+    ] {
+        let config = |prelex| {
+            ParserConfigBuilder::new()
+                .encoding(encoding)
+                .error_format(ErrorFormatMode::Basic)
+                .prelex(prelex)
+                .build()
+                .unwrap()
+        };
+        let parsed = parse(input, &config(false));
+        let prelexed = parse(input, &config(true));
+        match (parsed, prelexed) {
+            (Ok(tree), Ok(prelexed)) => assert_eq!(tree, prelexed),
+            (Err(error), Err(prelexed)) => assert_eq!(error.to_string(), prelexed.to_string()),
+            (parsed, prelexed) => panic!(
+                "parsed {} but prelexed {}",
+                parsed.is_ok(),
+                prelexed.is_ok()
+            ),
+        }
+    }
+});
@@ -0,0 +1,72 @@
+//! Write any input that parses with `StarWriterHandler`, which must give text that parses to
+//! the same document.
+//!
+//! Nested loops with a short row, or an outer row holding no inner rows, are skipped: the walker
+//! recovers from the stop_ that ended them in ways the writer can't put back. So is input with
+//! control characters, which only comments can hold and the writer doesn't keep comments.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ustar_parser::sas_handlers::{Document, DocumentBuilderHandler, LoopRow, StarWriterHandler};
+use ustar_parser::{walk, ErrorFormatMode, ParserConfig, ParserConfigBuilder};
+
+fn document(input: &str, config: &ParserConfig) -> Option<Document> {
+    let mut builder = DocumentBuilderHandler::new();
+    walk(input, config, &mut builder).ok()?;
+    Some(builder.into_document())
+}
+
+/// Whether any of `rows`, at `level` of a loop with `tags`, is short or has no inner rows
+fn irregular(rows: &[LoopRow], tags: &[Vec<String>], level: usize) -> bool {
+    let innermost = level + 1 == tags.len();
+    rows.iter().any(|row| {
+        row.values.len() < tags[level].len()
+            || (!innermost && (row.rows.is_empty() || irregular(&row.rows, tags, level + 1)))
+    })
+}
+
+/// Whether a nested loop of `document` has an irregular row
+fn has_irregular_nested_rows(document: &Document) -> bool {
+    document
+        .global_blocks
+        .iter()
+        .chain(&document.data_blocks)
+        .flat_map(|block| {
+            block
+                .loops
+                .iter()
+                .chain(block.saveframes.iter().flat_map(|frame| &frame.loops))
+        })
+        .filter(|star_loop| star_loop.tags.len() > 1)
+        .any(|star_loop| irregular(&star_loop.rows, &star_loop.tags, 0))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    if input
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+    {
+        return;
+    }
+    let config = ParserConfigBuilder::new()
+        .error_format(ErrorFormatMode::Basic)
+        .build()
+        .unwrap();
+    let Some(expected) = document(input, &config) else {
+        return;
+    };
+    if has_irregular_nested_rows(&expected) {
+        return;
+    }
+
+    let mut writer = StarWriterHandler::new(Vec::new());
+    walk(input, &config, &mut writer).unwrap();
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    let document = document(&written, &config)
+        .unwrap_or_else(|| panic!("written text doesn't parse:\n{}", written));
+    assert_eq!(document, expected, "written as\n{}", written);
+});
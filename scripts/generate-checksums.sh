@@ -1,7 +1,8 @@
 #!/bin/bash
 
-# Script to generate SHA-1 checksum files for specified directories
-# Creates checksums.sha1 in each directory using platform shasum command
+# Script to generate SHA-256 and SHA-1 checksum files for specified directories
+# Creates checksums.sha256 and checksums.sha1 in each directory using platform shasum command
+# (the generate-checksums binary in ustar-test-utils writes the same files)
 #
 # Usage: 
 #   ./generate-checksums.sh <dir1> [dir2] [dir3] ...
@@ -59,7 +60,7 @@ fi
 # File extensions to include in checksums
 EXTENSIONS=("*.str" "*.cif" "*.nef" "*.dic" "*.mmcif")
 
-verbose_echo "Generating SHA-1 checksum files for specified directories..."
+verbose_echo "Generating SHA-256 and SHA-1 checksum files for specified directories..."
 
 # Process each directory argument
 for dir in "$@"; do
@@ -77,10 +78,12 @@ for dir in "$@"; do
     # Use -f flag to suppress "no matches found" errors for missing extensions
     found_files=false
     temp_checksum_file=$(mktemp)
+    temp_sha256_file=$(mktemp)
     
     for ext in "${EXTENSIONS[@]}"; do
         if ls $ext 1> /dev/null 2>&1; then
             shasum $ext >> "$temp_checksum_file"
+            shasum -a 256 $ext >> "$temp_sha256_file"
             found_files=true
         fi
     done
@@ -88,13 +91,14 @@ for dir in "$@"; do
     if [[ "$found_files" == true ]]; then
         # Sort the checksums for consistent output
         sort "$temp_checksum_file" > checksums.sha1
-        verbose_echo "  Created checksums.sha1 with $(wc -l < checksums.sha1) files"
+        sort "$temp_sha256_file" > checksums.sha256
+        verbose_echo "  Created checksums.sha256 and checksums.sha1 with $(wc -l < checksums.sha1) files"
     else
         echo "Warning: No test data files found in $dir" >&2
     fi
     
     # Clean up
-    rm -f "$temp_checksum_file"
+    rm -f "$temp_checksum_file" "$temp_sha256_file"
     
     # Go back to original directory
     cd - > /dev/null
@@ -105,4 +109,4 @@ verbose_echo "Checksum generation complete!"
 verbose_echo ""
 verbose_echo "To verify checksums later, use:"
 verbose_echo "  cd <test_data_directory>"
-verbose_echo "  shasum -c checksums.sha1"
\ No newline at end of file
+verbose_echo "  shasum -a 256 -c checksums.sha256"
\ No newline at end of file
@@ -63,6 +63,7 @@ PROJECT_ROOT="$(dirname "$SCRIPT_DIR")"
 SNAPSHOT_DIRS=(
     "ustar-parser/tests/snapshots"
     "ustar-tools/tests/snapshots"
+    "ustar-test-utils/tests/snapshots"
 )
 
 # Function to ensure decompressed files exist for any missing .snap files
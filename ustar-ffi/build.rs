@@ -0,0 +1,24 @@
+use std::env;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    // the C test harness is compiled for the target the library is built for
+    println!(
+        "cargo:rustc-env=USTAR_FFI_TARGET={}",
+        env::var("TARGET").expect("TARGET is set by cargo")
+    );
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let config = cbindgen::Config::from_file(Path::new(&crate_dir).join("cbindgen.toml"))
+        .expect("Failed to read cbindgen.toml");
+    // only written when the declarations change, so the header in the repository stays current
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(Path::new(&crate_dir).join("include/ustar.h"));
+}
@@ -0,0 +1,174 @@
+/* C interface to the ustar STAR format parser, generated by cbindgen from ustar-ffi/src/lib.rs - do not edit */
+
+#ifndef USTAR_H
+#define USTAR_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Character encoding of the input, as `EncodingMode`
+typedef enum UstarEncoding {
+  // Printable ASCII, with space and tab as whitespace
+  USTAR_ENCODING_ASCII = 0,
+  // Latin-1 / Windows-1252, read from bytes that aren't UTF-8
+  USTAR_ENCODING_EXTENDED_ASCII = 1,
+  // UTF-8 with Unicode whitespace
+  USTAR_ENCODING_UNICODE = 2,
+} UstarEncoding;
+
+// CIF syntax version, as `CifVersion`
+typedef enum UstarCifVersion {
+  // STAR / CIF 1.1 syntax
+  USTAR_CIF_VERSION_CIF1 = 0,
+  // CIF 2.0 syntax, adding list and table values
+  USTAR_CIF_VERSION_CIF2 = 1,
+} UstarCifVersion;
+
+// The result of `ustar_parse`
+typedef enum UstarStatus {
+  // The input parsed, the tree is in `*out`
+  USTAR_STATUS_OK = 0,
+  // The input, with a length that isn't 0, or `out` was NULL
+  USTAR_STATUS_NULL_ARGUMENT = 1,
+  // The options contradict each other, such as CIF 2.0 syntax in extended ASCII
+  USTAR_STATUS_INVALID_OPTIONS = 2,
+  // The input isn't valid STAR
+  USTAR_STATUS_PARSE_ERROR = 3,
+  // The parser panicked, which is a bug in ustar
+  USTAR_STATUS_PANIC = 4,
+} UstarStatus;
+
+// Why `ustar_parse` failed, with where in the input
+typedef struct UstarError UstarError;
+
+// A node of a `UstarTree`, a rule of the grammar and the text it matched
+typedef struct UstarNode UstarNode;
+
+// A parsed document, owning its nodes
+typedef struct UstarTree UstarTree;
+
+// Options for `ustar_parse`, start from `ustar_default_options` so options added later keep
+// their defaults
+typedef struct UstarOptions {
+  // Character encoding of the input
+  enum UstarEncoding encoding;
+  // CIF syntax version
+  enum UstarCifVersion cif_version;
+  // Whether quoted strings are split into delimiter, content and delimiter nodes
+  bool decomposed_strings;
+  // Whether a byte order mark selects the encoding
+  bool auto_detect_bom;
+} UstarOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The default options: ASCII, CIF 1.1, decomposed strings and no byte order mark detection
+struct UstarOptions ustar_default_options(void);
+
+// Parse `len` bytes of STAR text at `input` with `options`, the defaults when NULL
+//
+// On success the tree is stored in `*out`, otherwise `*out` is set to NULL and, for
+// `USTAR_STATUS_INVALID_OPTIONS`, `USTAR_STATUS_PARSE_ERROR` and `USTAR_STATUS_PANIC`, the
+// error is kept for `ustar_last_error`. The input needn't end with a NUL; bytes that aren't
+// UTF-8 are read as `ustar::parse_bytes` reads them.
+//
+// # Safety
+// `input` must point to `len` readable bytes, or may be NULL when `len` is 0. `options` must be
+// NULL or point to a `UstarOptions` and `out` must point to writable storage for a pointer.
+enum UstarStatus ustar_parse(const char *input,
+                             size_t len,
+                             const struct UstarOptions *options,
+                             struct UstarTree **out);
+
+// Free a tree from `ustar_parse`, and with it its nodes and their text
+//
+// # Safety
+// `tree` must be NULL or a tree from `ustar_parse` that hasn't been freed.
+void ustar_free_tree(struct UstarTree *tree);
+
+// The root node of `tree`, a `star_file`, or NULL if `tree` is NULL
+//
+// # Safety
+// `tree` must be NULL or a tree from `ustar_parse` that hasn't been freed.
+const struct UstarNode *ustar_tree_root(const struct UstarTree *tree);
+
+// The grammar rule of `node` as a NUL terminated name, such as `data_block`, or NULL if `node`
+// is NULL
+//
+// # Safety
+// `node` must be NULL or a node of a tree that hasn't been freed.
+const char *ustar_node_rule(const struct UstarNode *node);
+
+// The UTF-8 text `node` matched, which isn't NUL terminated, with its length in bytes stored
+// in `*len` when `len` isn't NULL; NULL with a length of 0 if `node` is NULL
+//
+// # Safety
+// `node` must be NULL or a node of a tree that hasn't been freed, and `len` NULL or writable.
+const char *ustar_node_text(const struct UstarNode *node, size_t *len);
+
+// Store the line and column where `node` starts, both counted from 1, in `*line` and
+// `*column`, each when it isn't NULL; 0 if `node` is NULL
+//
+// # Safety
+// `node` must be NULL or a node of a tree that hasn't been freed, and `line` and `column` NULL
+// or writable.
+void ustar_node_position(const struct UstarNode *node, size_t *line, size_t *column);
+
+// The number of children of `node`, 0 if `node` is NULL
+//
+// # Safety
+// `node` must be NULL or a node of a tree that hasn't been freed.
+size_t ustar_node_child_count(const struct UstarNode *node);
+
+// The child of `node` at `index`, counted from 0, or NULL if `node` is NULL or has no child
+// at `index`
+//
+// # Safety
+// `node` must be NULL or a node of a tree that hasn't been freed.
+const struct UstarNode *ustar_node_child(const struct UstarNode *node, size_t index);
+
+// Take the error of the last failed `ustar_parse` on this thread, NULL if it succeeded or the
+// error has already been taken; free it with `ustar_free_error`
+struct UstarError *ustar_last_error(void);
+
+// Free an error from `ustar_last_error`
+//
+// # Safety
+// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+void ustar_free_error(struct UstarError *error);
+
+// The NUL terminated message of `error`, without its position, or NULL if `error` is NULL
+//
+// # Safety
+// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+const char *ustar_error_message(const struct UstarError *error);
+
+// The code of `error`, such as `E0001`, empty for invalid options and panics, or NULL if
+// `error` is NULL
+//
+// # Safety
+// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+const char *ustar_error_code(const struct UstarError *error);
+
+// Store the line and column of `error`, both counted from 1, and its byte offset into the
+// input in `*line`, `*column` and `*offset`, each when it isn't NULL; 0 for errors that aren't
+// in the input, such as invalid options, or if `error` is NULL
+//
+// # Safety
+// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed, and `line`,
+// `column` and `offset` NULL or writable.
+void ustar_error_position(const struct UstarError *error,
+                          size_t *line,
+                          size_t *column,
+                          size_t *offset);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* USTAR_H */
@@ -0,0 +1,429 @@
+//! C bindings - parsing STAR text to a tree that C and C++ programs navigate by pointer
+//!
+//! The declarations are in `include/ustar.h`, written by cbindgen when the crate is built, and
+//! the library is built as both `libustar_ffi.so` (or `.dylib`, `.dll`) and `libustar_ffi.a`.
+//!
+//! # Ownership
+//!
+//! - `ustar_parse` gives the caller a `UstarTree`, which the caller frees with
+//!   `ustar_free_tree`.
+//! - Nodes belong to their tree: the `UstarNode` pointers from `ustar_tree_root` and
+//!   `ustar_node_child`, and the text from `ustar_node_text`, are valid until the tree is freed
+//!   and are never freed themselves.
+//! - Rule names from `ustar_node_rule` and error codes from `ustar_error_code` are static, valid
+//!   for the life of the program.
+//! - When `ustar_parse` fails its error is kept for the calling thread until the next call of
+//!   `ustar_parse` on that thread. `ustar_last_error` hands it to the caller, who frees it with
+//!   `ustar_free_error`; its message is valid until then.
+//! - The input is only read during `ustar_parse`, the tree holds its own copy of the text.
+//!
+//! Each `ustar_free_*` function accepts NULL. Panics are caught at the boundary and reported
+//! as `USTAR_STATUS_PANIC`, they never unwind into C.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Mutex;
+
+use ustar_parser::mutable_pair::MutablePair;
+use ustar_parser::{CifVersion, EncodingMode, ParserConfig, ParserConfigBuilder};
+
+/// The result of `ustar_parse`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UstarStatus {
+    /// The input parsed, the tree is in `*out`
+    Ok = 0,
+    /// The input, with a length that isn't 0, or `out` was NULL
+    NullArgument = 1,
+    /// The options contradict each other, such as CIF 2.0 syntax in extended ASCII
+    InvalidOptions = 2,
+    /// The input isn't valid STAR
+    ParseError = 3,
+    /// The parser panicked, which is a bug in ustar
+    Panic = 4,
+}
+
+/// Character encoding of the input, as `EncodingMode`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UstarEncoding {
+    /// Printable ASCII, with space and tab as whitespace
+    Ascii = 0,
+    /// Latin-1 / Windows-1252, read from bytes that aren't UTF-8
+    ExtendedAscii = 1,
+    /// UTF-8 with Unicode whitespace
+    Unicode = 2,
+}
+
+/// CIF syntax version, as `CifVersion`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UstarCifVersion {
+    /// STAR / CIF 1.1 syntax
+    Cif1 = 0,
+    /// CIF 2.0 syntax, adding list and table values
+    Cif2 = 1,
+}
+
+/// Options for `ustar_parse`, start from `ustar_default_options` so options added later keep
+/// their defaults
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UstarOptions {
+    /// Character encoding of the input
+    pub encoding: UstarEncoding,
+    /// CIF syntax version
+    pub cif_version: UstarCifVersion,
+    /// Whether quoted strings are split into delimiter, content and delimiter nodes
+    pub decomposed_strings: bool,
+    /// Whether a byte order mark selects the encoding
+    pub auto_detect_bom: bool,
+}
+
+/// A parsed document, owning its nodes
+pub struct UstarTree {
+    root: MutablePair,
+}
+
+/// A node of a `UstarTree`, a rule of the grammar and the text it matched
+pub struct UstarNode {
+    // never constructed, a UstarNode pointer points at the MutablePair of a tree
+    _private: [u8; 0],
+}
+
+/// Why `ustar_parse` failed, with where in the input
+pub struct UstarError {
+    code: &'static CStr,
+    message: CString,
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+thread_local! {
+    // the error of the last failed ustar_parse on this thread
+    static LAST_ERROR: RefCell<Option<Box<UstarError>>> = const { RefCell::new(None) };
+}
+
+// rule names and error codes as C strings, one for each name, kept for the life of the program
+static C_NAMES: Mutex<Option<HashMap<String, &'static CStr>>> = Mutex::new(None);
+
+/// The default options: ASCII, CIF 1.1, decomposed strings and no byte order mark detection
+#[no_mangle]
+pub extern "C" fn ustar_default_options() -> UstarOptions {
+    UstarOptions {
+        encoding: UstarEncoding::Ascii,
+        cif_version: UstarCifVersion::Cif1,
+        decomposed_strings: true,
+        auto_detect_bom: false,
+    }
+}
+
+/// Parse `len` bytes of STAR text at `input` with `options`, the defaults when NULL
+///
+/// On success the tree is stored in `*out`, otherwise `*out` is set to NULL and, for
+/// `USTAR_STATUS_INVALID_OPTIONS`, `USTAR_STATUS_PARSE_ERROR` and `USTAR_STATUS_PANIC`, the
+/// error is kept for `ustar_last_error`. The input needn't end with a NUL; bytes that aren't
+/// UTF-8 are read as `ustar::parse_bytes` reads them.
+///
+/// # Safety
+/// `input` must point to `len` readable bytes, or may be NULL when `len` is 0. `options` must be
+/// NULL or point to a `UstarOptions` and `out` must point to writable storage for a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_parse(
+    input: *const c_char,
+    len: usize,
+    options: *const UstarOptions,
+    out: *mut *mut UstarTree,
+) -> UstarStatus {
+    LAST_ERROR.with(|last| last.borrow_mut().take());
+    if out.is_null() || (input.is_null() && len != 0) {
+        return UstarStatus::NullArgument;
+    }
+    *out = ptr::null_mut();
+
+    let bytes: &[u8] = if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(input.cast(), len)
+    };
+    let options = options
+        .as_ref()
+        .copied()
+        .unwrap_or_else(|| ustar_default_options());
+
+    let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+        let config = config_from(&options)
+            .map_err(|message| (UstarStatus::InvalidOptions, error("", message, 0, 0, 0)))?;
+        ustar_parser::parse_bytes(bytes, &config).map_err(|parse_error| {
+            let core = parse_error.core();
+            (
+                UstarStatus::ParseError,
+                error(
+                    parse_error.code().as_str(),
+                    core.message.clone(),
+                    core.line,
+                    core.col,
+                    core.offset,
+                ),
+            )
+        })
+    }));
+
+    let failure = match parsed {
+        Ok(Ok(root)) => {
+            *out = Box::into_raw(Box::new(UstarTree { root }));
+            return UstarStatus::Ok;
+        }
+        Ok(Err(failure)) => failure,
+        Err(_) => (
+            UstarStatus::Panic,
+            error("", "the parser panicked".to_string(), 0, 0, 0),
+        ),
+    };
+    let (status, error) = failure;
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(Box::new(error)));
+    status
+}
+
+/// Free a tree from `ustar_parse`, and with it its nodes and their text
+///
+/// # Safety
+/// `tree` must be NULL or a tree from `ustar_parse` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_free_tree(tree: *mut UstarTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// The root node of `tree`, a `star_file`, or NULL if `tree` is NULL
+///
+/// # Safety
+/// `tree` must be NULL or a tree from `ustar_parse` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_tree_root(tree: *const UstarTree) -> *const UstarNode {
+    match tree.as_ref() {
+        Some(tree) => node_ptr(&tree.root),
+        None => ptr::null(),
+    }
+}
+
+/// The grammar rule of `node` as a NUL terminated name, such as `data_block`, or NULL if `node`
+/// is NULL
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_node_rule(node: *const UstarNode) -> *const c_char {
+    match pair(node) {
+        Some(pair) => c_name(pair.rule_name()).as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The UTF-8 text `node` matched, which isn't NUL terminated, with its length in bytes stored
+/// in `*len` when `len` isn't NULL; NULL with a length of 0 if `node` is NULL
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed, and `len` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_node_text(node: *const UstarNode, len: *mut usize) -> *const c_char {
+    let text = pair(node).map(|pair| pair.as_str());
+    if let Some(len) = len.as_mut() {
+        *len = text.map_or(0, str::len);
+    }
+    text.map_or(ptr::null(), |text| text.as_ptr().cast())
+}
+
+/// Store the line and column where `node` starts, both counted from 1, in `*line` and
+/// `*column`, each when it isn't NULL; 0 if `node` is NULL
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed, and `line` and `column` NULL
+/// or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_node_position(
+    node: *const UstarNode,
+    line: *mut usize,
+    column: *mut usize,
+) {
+    let position = pair(node).map(|pair| pair.start_line_column());
+    if let Some(line) = line.as_mut() {
+        *line = position.map_or(0, |position| position.line);
+    }
+    if let Some(column) = column.as_mut() {
+        *column = position.map_or(0, |position| position.column);
+    }
+}
+
+/// The number of children of `node`, 0 if `node` is NULL
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_node_child_count(node: *const UstarNode) -> usize {
+    pair(node).map_or(0, |pair| pair.children().len())
+}
+
+/// The child of `node` at `index`, counted from 0, or NULL if `node` is NULL or has no child
+/// at `index`
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_node_child(
+    node: *const UstarNode,
+    index: usize,
+) -> *const UstarNode {
+    match pair(node).and_then(|pair| pair.children().get(index)) {
+        Some(child) => node_ptr(child),
+        None => ptr::null(),
+    }
+}
+
+/// Take the error of the last failed `ustar_parse` on this thread, NULL if it succeeded or the
+/// error has already been taken; free it with `ustar_free_error`
+#[no_mangle]
+pub extern "C" fn ustar_last_error() -> *mut UstarError {
+    match LAST_ERROR.with(|last| last.borrow_mut().take()) {
+        Some(error) => Box::into_raw(error),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Free an error from `ustar_last_error`
+///
+/// # Safety
+/// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_free_error(error: *mut UstarError) {
+    if !error.is_null() {
+        drop(Box::from_raw(error));
+    }
+}
+
+/// The NUL terminated message of `error`, without its position, or NULL if `error` is NULL
+///
+/// # Safety
+/// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_error_message(error: *const UstarError) -> *const c_char {
+    match error.as_ref() {
+        Some(error) => error.message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The code of `error`, such as `E0001`, empty for invalid options and panics, or NULL if
+/// `error` is NULL
+///
+/// # Safety
+/// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_error_code(error: *const UstarError) -> *const c_char {
+    match error.as_ref() {
+        Some(error) => error.code.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Store the line and column of `error`, both counted from 1, and its byte offset into the
+/// input in `*line`, `*column` and `*offset`, each when it isn't NULL; 0 for errors that aren't
+/// in the input, such as invalid options, or if `error` is NULL
+///
+/// # Safety
+/// `error` must be NULL or an error from `ustar_last_error` that hasn't been freed, and `line`,
+/// `column` and `offset` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ustar_error_position(
+    error: *const UstarError,
+    line: *mut usize,
+    column: *mut usize,
+    offset: *mut usize,
+) {
+    let error = error.as_ref();
+    for (out, value) in [
+        (line, error.map_or(0, |error| error.line)),
+        (column, error.map_or(0, |error| error.column)),
+        (offset, error.map_or(0, |error| error.offset)),
+    ] {
+        if let Some(out) = out.as_mut() {
+            *out = value;
+        }
+    }
+}
+
+/// The configuration given by C options, or why they contradict each other (private)
+fn config_from(options: &UstarOptions) -> Result<ParserConfig, String> {
+    let encoding = match options.encoding {
+        UstarEncoding::Ascii => EncodingMode::Ascii,
+        UstarEncoding::ExtendedAscii => EncodingMode::ExtendedAscii,
+        UstarEncoding::Unicode => EncodingMode::Unicode,
+    };
+    let cif_version = match options.cif_version {
+        UstarCifVersion::Cif1 => CifVersion::Cif1,
+        UstarCifVersion::Cif2 => CifVersion::Cif2,
+    };
+    ParserConfigBuilder::new()
+        .encoding(encoding)
+        .cif_version(cif_version)
+        .decomposed_strings(options.decomposed_strings)
+        .auto_detect_bom(options.auto_detect_bom)
+        .build()
+        .map_err(|error| error.to_string())
+}
+
+/// An error to keep for `ustar_last_error` (private)
+fn error(code: &str, message: String, line: usize, column: usize, offset: usize) -> UstarError {
+    // a message can't hold a NUL in C, so it ends at one
+    let message = match CString::new(message) {
+        Ok(message) => message,
+        Err(error) => {
+            let end = error.nul_position();
+            let mut bytes = error.into_vec();
+            bytes.truncate(end);
+            CString::new(bytes).unwrap_or_default()
+        }
+    };
+    UstarError {
+        code: c_name(code),
+        message,
+        line,
+        column,
+        offset,
+    }
+}
+
+/// A rule name or error code as a C string kept for the life of the program (private)
+fn c_name(name: &str) -> &'static CStr {
+    let mut names = C_NAMES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let names = names.get_or_insert_with(HashMap::new);
+    if let Some(c_name) = names.get(name) {
+        return c_name;
+    }
+    let c_name: &'static CStr = Box::leak(
+        CString::new(name.replace('\0', ""))
+            .unwrap_or_default()
+            .into_boxed_c_str(),
+    );
+    names.insert(name.to_string(), c_name);
+    c_name
+}
+
+fn node_ptr(pair: &MutablePair) -> *const UstarNode {
+    (pair as *const MutablePair).cast()
+}
+
+/// The tree node a `UstarNode` pointer points at (private)
+///
+/// # Safety
+/// `node` must be NULL or a node of a tree that hasn't been freed.
+unsafe fn pair<'a>(node: *const UstarNode) -> Option<&'a MutablePair> {
+    node.cast::<MutablePair>().as_ref()
+}
@@ -0,0 +1,58 @@
+//! Compiles tests/harness.c against the built library and runs it, checking the C interface
+//! and its ownership rules as a C program uses them
+
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The directory cargo builds the library into, the deps directory of the test executable
+///
+/// `cargo test` only copies the library up into target/<profile> when something else asks
+/// for it, so the copy there may be missing or stale.
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().expect("test executable path");
+    exe.parent()
+        .expect("test executable in target/<profile>/deps")
+        .to_path_buf()
+}
+
+#[test]
+fn c_harness_passes() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let library_dir = library_dir();
+    let harness = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ustar_ffi_harness");
+    let target = env!("USTAR_FFI_TARGET");
+
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .target(target)
+        .host(target)
+        .opt_level(0)
+        .warnings(true)
+        .get_compiler();
+    let compiled = compiler
+        .to_command()
+        .arg(crate_dir.join("tests/harness.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg("-o")
+        .arg(&harness)
+        .arg(format!("-L{}", library_dir.display()))
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .arg("-lustar_ffi")
+        .status()
+        .expect("run the C compiler");
+    assert!(compiled.success(), "tests/harness.c didn't compile");
+
+    let output = Command::new(&harness).output().expect("run the C harness");
+    assert!(
+        output.status.success(),
+        "C harness failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "all checks passed"
+    );
+}
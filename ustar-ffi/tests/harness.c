@@ -0,0 +1,157 @@
+/* Exercises the C interface of ustar as a C program would, compiled and run by ffi_tests.rs.
+   Exits 0 when every check passes, otherwise prints the failed check and exits 1. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "ustar.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                        \
+    do {                                                                        \
+        if (!(condition)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,   \
+                    #condition);                                                \
+            failures++;                                                         \
+        }                                                                       \
+    } while (0)
+
+static int text_is(const UstarNode *node, const char *expected) {
+    size_t len = 0;
+    const char *text = ustar_node_text(node, &len);
+    return len == strlen(expected) && memcmp(text, expected, len) == 0;
+}
+
+/* the number of nodes in the tree below and including node */
+static size_t count_nodes(const UstarNode *node) {
+    size_t count = 1;
+    for (size_t i = 0; i < ustar_node_child_count(node); i++) {
+        count += count_nodes(ustar_node_child(node, i));
+    }
+    return count;
+}
+
+static void test_parse_and_navigate(void) {
+    const char *input = "data_test\n_a 1\nloop_ _b _c x \"y z\" stop_\n";
+    UstarTree *tree = NULL;
+    CHECK(ustar_parse(input, strlen(input), NULL, &tree) == USTAR_STATUS_OK);
+    CHECK(tree != NULL);
+    CHECK(ustar_last_error() == NULL);
+
+    const UstarNode *root = ustar_tree_root(tree);
+    CHECK(strcmp(ustar_node_rule(root), "star_file") == 0);
+    CHECK(text_is(root, input));
+    CHECK(count_nodes(root) == 20);
+
+    const UstarNode *block = ustar_node_child(root, 0);
+    CHECK(strcmp(ustar_node_rule(block), "data_block") == 0);
+    CHECK(ustar_node_child_count(block) == 3);
+    CHECK(ustar_node_child(block, 3) == NULL);
+
+    const UstarNode *heading = ustar_node_child(block, 0);
+    CHECK(strcmp(ustar_node_rule(heading), "data_heading") == 0);
+    CHECK(text_is(heading, "data_test"));
+    CHECK(ustar_node_child_count(heading) == 0);
+
+    const UstarNode *value = ustar_node_child(ustar_node_child(block, 1), 1);
+    size_t line = 0, column = 0;
+    ustar_node_position(value, &line, &column);
+    CHECK(line == 2 && column == 4);
+    CHECK(text_is(value, "1"));
+
+    /* the input is copied, so the tree outlives it */
+    char copy[64];
+    strcpy(copy, input);
+    UstarTree *copied = NULL;
+    CHECK(ustar_parse(copy, strlen(copy), NULL, &copied) == USTAR_STATUS_OK);
+    memset(copy, ' ', strlen(copy));
+    CHECK(text_is(ustar_tree_root(copied), input));
+
+    /* rule names outlive their tree */
+    const char *rule = ustar_node_rule(heading);
+    ustar_free_tree(tree);
+    ustar_free_tree(copied);
+    CHECK(strcmp(rule, "data_heading") == 0);
+}
+
+static void test_options(void) {
+    const char *input = "data_test\n_list [1 2]\n";
+    UstarOptions options = ustar_default_options();
+    CHECK(options.encoding == USTAR_ENCODING_ASCII);
+    CHECK(options.decomposed_strings);
+
+    options.cif_version = USTAR_CIF_VERSION_CIF2;
+    UstarTree *tree = NULL;
+    CHECK(ustar_parse(input, strlen(input), &options, &tree) == USTAR_STATUS_OK);
+    ustar_free_tree(tree);
+
+    options.encoding = USTAR_ENCODING_EXTENDED_ASCII;
+    CHECK(ustar_parse(input, strlen(input), &options, &tree) == USTAR_STATUS_INVALID_OPTIONS);
+    CHECK(tree == NULL);
+    UstarError *error = ustar_last_error();
+    CHECK(error != NULL);
+    CHECK(strlen(ustar_error_message(error)) > 0);
+    CHECK(strcmp(ustar_error_code(error), "") == 0);
+    ustar_free_error(error);
+}
+
+static void test_errors(void) {
+    const char *input = "data_test\n_a 1\n_b 'unterminated\n";
+    UstarTree *tree = (UstarTree *)&tree;
+    CHECK(ustar_parse(input, strlen(input), NULL, &tree) == USTAR_STATUS_PARSE_ERROR);
+    CHECK(tree == NULL);
+
+    UstarError *error = ustar_last_error();
+    CHECK(error != NULL);
+    size_t line = 0, column = 0, offset = 0;
+    ustar_error_position(error, &line, &column, &offset);
+    CHECK(line == 3);
+    CHECK(column > 0);
+    CHECK(offset >= 15);
+    CHECK(strncmp(ustar_error_code(error), "E", 1) == 0);
+    CHECK(strlen(ustar_error_message(error)) > 0);
+
+    /* the error is handed over once, and the next parse forgets an error not taken */
+    CHECK(ustar_last_error() == NULL);
+    ustar_free_error(error);
+    CHECK(ustar_parse(input, strlen(input), NULL, &tree) == USTAR_STATUS_PARSE_ERROR);
+    CHECK(ustar_parse("data_ok\n_a 1\n", 13, NULL, &tree) == USTAR_STATUS_OK);
+    CHECK(ustar_last_error() == NULL);
+    ustar_free_tree(tree);
+}
+
+static void test_null_arguments(void) {
+    UstarTree *tree = NULL;
+    CHECK(ustar_parse(NULL, 1, NULL, &tree) == USTAR_STATUS_NULL_ARGUMENT);
+    CHECK(ustar_parse("data_test\n", 10, NULL, NULL) == USTAR_STATUS_NULL_ARGUMENT);
+    CHECK(ustar_parse(NULL, 0, NULL, &tree) == USTAR_STATUS_OK);
+    CHECK(ustar_node_child_count(ustar_tree_root(tree)) <= 1);
+    ustar_free_tree(tree);
+
+    size_t len = 1, line = 1, column = 1, offset = 1;
+    CHECK(ustar_tree_root(NULL) == NULL);
+    CHECK(ustar_node_rule(NULL) == NULL);
+    CHECK(ustar_node_text(NULL, &len) == NULL && len == 0);
+    CHECK(ustar_node_child_count(NULL) == 0);
+    CHECK(ustar_node_child(NULL, 0) == NULL);
+    ustar_node_position(NULL, &line, &column);
+    CHECK(line == 0 && column == 0);
+    CHECK(ustar_error_message(NULL) == NULL);
+    CHECK(ustar_error_code(NULL) == NULL);
+    ustar_error_position(NULL, &line, &column, &offset);
+    CHECK(offset == 0);
+    ustar_free_tree(NULL);
+    ustar_free_error(NULL);
+}
+
+int main(void) {
+    test_parse_and_navigate();
+    test_options();
+    test_errors();
+    test_null_arguments();
+    if (failures == 0) {
+        printf("all checks passed\n");
+    }
+    return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,240 @@
+//! Criterion benchmarks of the parsers, tree construction and SAS walking.
+//!
+//! The inputs are committed test files, so the benchmarks run without the downloaded data:
+//! a small example of most STAR constructs, a 150KB NMR-STAR entry and the 140KB NEF dictionary.
+//! The `prelex` and `backend` groups also parse the mmCIF dictionary `mmcif_pdbx_v50.dic` when it
+//! has been downloaded.
+//!
+//! Run with `cargo bench -p ustar-parser`, or `cargo bench -p ustar-parser -- walk` for one
+//! group.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use pest::Parser;
+use std::fs;
+use ustar::line_column_index::LineColumn;
+use ustar::mutable_pair::PairNode;
+use ustar::parsers::{ascii, extended, unicode};
+use ustar::sas_interface::{SASContentHandler, ValueDelimiter, WalkControl};
+use ustar::sas_walker::StarWalker;
+use ustar::{default_config, ConfigKey, ConfigValue};
+
+const SMALL: &str = "tests/test_data/comprehensive_example.star";
+const MEDIUM: &str = "tests/test_data/sas_test_files/bmr18587_3.str";
+const DICTIONARY: &str = "tests/test_data/dicts/mmcif_pdbx_v50.dic";
+const NEF_DICTIONARY: &str = "tests/test_data/mmcif_nef_v1_1_ascii.dic";
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+/// The same ASCII file through the grammars for each encoding
+fn bench_encodings(c: &mut Criterion) {
+    let content = read(MEDIUM);
+    let mut group = c.benchmark_group("encoding");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+
+    group.bench_function("ascii", |b| {
+        b.iter(|| ascii::AsciiParser::parse(ascii::Rule::star_file, black_box(&content)).unwrap())
+    });
+    group.bench_function("extended", |b| {
+        b.iter(|| {
+            extended::ExtendedParser::parse(extended::Rule::star_file, black_box(&content)).unwrap()
+        })
+    });
+    group.bench_function("unicode", |b| {
+        b.iter(|| {
+            unicode::UnicodeParser::parse(unicode::Rule::star_file, black_box(&content)).unwrap()
+        })
+    });
+    group.finish();
+}
+
+/// Building a `MutablePair` tree against the pest parse it's built from
+fn bench_tree_construction(c: &mut Criterion) {
+    let config = default_config();
+    for (name, path) in [("small", SMALL), ("medium", MEDIUM)] {
+        let content = read(path);
+        let mut group = c.benchmark_group(format!("tree/{}", name));
+        group.throughput(Throughput::Bytes(content.len() as u64));
+
+        group.bench_function("pest", |b| {
+            b.iter(|| {
+                ascii::AsciiParser::parse(ascii::Rule::star_file, black_box(&content)).unwrap()
+            })
+        });
+        group.bench_function("mutable_pair", |b| {
+            b.iter(|| ustar::parse(black_box(&content), &config).unwrap())
+        });
+        group.finish();
+    }
+}
+
+/// Count the nodes of a tree of either backend with a depth-first traversal
+fn count_nodes<'a, N: PairNode<'a>>(node: N) -> usize {
+    1 + node.child_nodes().map(count_nodes).sum::<usize>()
+}
+
+/// Building and traversing a `MutablePair` tree against a `TreeArena` on dictionaries
+fn bench_tree_backends(c: &mut Criterion) {
+    let config = default_config();
+    let mut inputs = vec![("nef_dictionary", read(NEF_DICTIONARY))];
+    if std::path::Path::new(DICTIONARY).exists() {
+        inputs.push(("pdbx_dictionary", read(DICTIONARY)));
+    }
+    for (name, content) in inputs {
+        let mut group = c.benchmark_group(format!("backend/{}", name));
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.sample_size(10);
+
+        group.bench_function("mutable_pair/construction", |b| {
+            b.iter(|| ustar::parse(black_box(&content), &config).unwrap())
+        });
+        group.bench_function("arena/construction", |b| {
+            b.iter(|| ustar::parse_arena(black_box(&content), &config).unwrap())
+        });
+
+        let tree = ustar::parse(&content, &config).unwrap();
+        let arena = ustar::parse_arena(&content, &config).unwrap();
+        let root = arena.root().unwrap();
+        group.bench_function("mutable_pair/traversal", |b| {
+            b.iter(|| count_nodes(black_box(&tree)))
+        });
+        group.bench_function("arena/traversal", |b| {
+            b.iter(|| count_nodes(black_box(root)))
+        });
+        group.finish();
+    }
+}
+
+/// Parsing with and without strings split into their delimiters and text
+fn bench_decomposed_strings(c: &mut Criterion) {
+    let content = read(MEDIUM);
+    let mut group = c.benchmark_group("decomposed_strings");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+
+    for decomposed in [false, true] {
+        let mut config = default_config();
+        config.insert(ConfigKey::DecomposedStrings, ConfigValue::Bool(decomposed));
+        let name = if decomposed { "on" } else { "off" };
+        group.bench_function(name, |b| {
+            b.iter(|| ustar::parse(black_box(&content), &config).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Parsing with and without the whitespace, comments and text fields compacted before pest
+fn bench_prelex(c: &mut Criterion) {
+    let mut inputs = vec![("medium", read(MEDIUM))];
+    if std::path::Path::new(DICTIONARY).exists() {
+        inputs.push(("dictionary", read(DICTIONARY)));
+    }
+    for (name, content) in inputs {
+        let mut group = c.benchmark_group(format!("prelex/{}", name));
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.sample_size(10);
+
+        for prelex in [false, true] {
+            let mut config = default_config();
+            config.insert(ConfigKey::Prelex, ConfigValue::Bool(prelex));
+            let name = if prelex { "on" } else { "off" };
+            group.bench_function(name, |b| {
+                b.iter(|| ustar::parse(black_box(&content), &config).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+/// A SAS handler that only counts data items, so a walk costs little beyond the traversal
+#[derive(Default)]
+struct ItemCounter {
+    items: usize,
+}
+
+impl SASContentHandler for ItemCounter {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.items += 1;
+        WalkControl::Continue
+    }
+}
+
+/// Parsing alone, parsing then walking the tree buffered, and walking straight from the parse
+fn bench_walk(c: &mut Criterion) {
+    let content = read(MEDIUM);
+    let config = default_config();
+    let mut group = c.benchmark_group("walk");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+
+    group.bench_function("parse_only", |b| {
+        b.iter(|| ustar::parse(black_box(&content), &config).unwrap())
+    });
+    group.bench_function("buffered", |b| {
+        b.iter(|| {
+            let tree = ustar::parse(black_box(&content), &config).unwrap();
+            let mut counter = ItemCounter::default();
+            StarWalker::from_input(&mut counter, &content).walk_star_tree_buffered(&tree);
+            counter.items
+        })
+    });
+    group.bench_function("streaming", |b| {
+        b.iter(|| {
+            let mut counter = ItemCounter::default();
+            ustar::walk(black_box(&content), &config, &mut counter).unwrap();
+            counter.items
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_encodings,
+    bench_tree_construction,
+    bench_tree_backends,
+    bench_decomposed_strings,
+    bench_prelex,
+    bench_walk
+);
+criterion_main!(benches);
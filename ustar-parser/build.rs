@@ -19,14 +19,20 @@ fn main() {
     ]);
     generate_grammar(&base_grammar, "src/star_ascii.pest", ascii_patches);
 
-    // Generate Extended ASCII grammar
+    // Generate Extended ASCII grammar, the characters of Latin-1 and Windows-1252
+    let windows_1252 = generate_windows_1252_alternatives();
+    let extended_no_quotes = format!(
+        r#"{{ '\u{{21}}'..'\u{{21}}' | '\u{{23}}'..'\u{{26}}' | '\u{{28}}'..'\u{{FF}}' | {} }}"#,
+        windows_1252
+    );
+    let extended_no_blank = format!(r#"{{ '\u{{21}}'..'\u{{FF}}' | {} }}"#, windows_1252);
     let extended_patches = HashMap::from([
         ("BLANK___PLACEHOLDER", r#"{ " " | "\t" | "\u{00A0}" }"#), // Add non-breaking space
         (
             "NON_BLANK_CHAR_NO_QUOTES___PLACEHOLDER",
-            r#"{ '\u{21}'..'\u{26}' | '\u{28}'..'\u{FF}' }"#,
+            extended_no_quotes.as_str(),
         ), // Exclude quotes at 0x27 and 0x22
-        ("NO_BLANK_CHAR___PLACEHOLDER", r#"{ '\u{21}'..'\u{FF}' }"#),
+        ("NO_BLANK_CHAR___PLACEHOLDER", extended_no_blank.as_str()),
         ("UTF8_BOM___PLACEHOLDER", r#"{ "\u{FEFF}" }"#), // Support BOM detection in extended ASCII
     ]);
     generate_grammar(&base_grammar, "src/star_extended.pest", extended_patches);
@@ -66,6 +72,21 @@ fn generate_grammar(base: &str, output_path: &str, patches: HashMap<&str, &str>)
     println!("cargo:rerun-if-changed={}", output_path);
 }
 
+/// Generate the alternatives matching the characters Windows-1252 puts at 0x80-0x9F in place of
+/// C1 controls, the table is also in src/legacy_encoding.rs
+fn generate_windows_1252_alternatives() -> String {
+    const WINDOWS_1252_PRINTABLE: [u32; 27] = [
+        0x20AC, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+        0x0152, 0x017D, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC, 0x2122,
+        0x0161, 0x203A, 0x0153, 0x017E, 0x0178,
+    ];
+    let formatted: Vec<String> = WINDOWS_1252_PRINTABLE
+        .iter()
+        .map(|code| format!(r#""\u{{{:04X}}}""#, code))
+        .collect();
+    formatted.join(" | ")
+}
+
 /// Generate the Unicode whitespace rule dynamically from known ranges
 fn generate_unicode_whitespace_rule() -> String {
     let chars = find_unicode_whitespace_chars();
@@ -0,0 +1,228 @@
+//! Arrow tables - loops as Apache Arrow record batches for columnar analysis.
+//!
+//! `LoopTable::to_arrow` builds a `RecordBatch` with a field for each column of a loop, named
+//! by its data name. The type of each column is inferred from its values: `Int64` if they are
+//! all integers, `Float64` if they are all numbers, dropping any uncertainty, and `Utf8`
+//! otherwise. The values `.` and `?` are nulls, and a column holding only nulls has the `Null`
+//! type. A schema hint overrides the inferred type of the columns it names.
+//!
+//! Types are inferred from the value text reported by the walker, so a quoted `'1'` is read as
+//! the integer 1. Nested loops have no flat columnar form and are rejected.
+
+use crate::line_column_index::LineColumn;
+use crate::loop_table::{LoopTable, LoopTableError};
+use crate::mutable_pair::MutablePair;
+use crate::values::{parse_number, StarValue};
+use ::arrow::array::{ArrayRef, Float64Array, Int64Array, NullArray, StringArray};
+use ::arrow::datatypes::{DataType, Field, Schema};
+use ::arrow::error::ArrowError;
+use ::arrow::record_batch::RecordBatch;
+use std::fmt;
+use std::sync::Arc;
+
+/// Errors from converting loops to record batches
+#[derive(Debug)]
+pub enum ArrowTableError {
+    /// A loop couldn't be read as a table
+    Loop(LoopTableError),
+    /// The loop has a nested level, which has no columnar form
+    NestedLoop { position: LineColumn },
+    /// The schema hint gives a column a type other than `Int64`, `Float64`, `Utf8` or `Null`
+    UnsupportedType { column: String, data_type: DataType },
+    /// A value that isn't of the type the schema hint gives its column
+    Value {
+        column: String,
+        value: String,
+        data_type: DataType,
+        position: LineColumn,
+    },
+    /// Arrow rejected the batch, such as for a null in a column the hint makes non-nullable
+    Arrow(ArrowError),
+}
+
+impl fmt::Display for ArrowTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrowTableError::Loop(error) => write!(f, "{}", error),
+            ArrowTableError::NestedLoop { position } => write!(
+                f,
+                "l{}:c{} nested loops can't be converted to a record batch",
+                position.line, position.column
+            ),
+            ArrowTableError::UnsupportedType { column, data_type } => write!(
+                f,
+                "{} can't be converted to {}, only Int64, Float64, Utf8 and Null are supported",
+                column, data_type
+            ),
+            ArrowTableError::Value {
+                column,
+                value,
+                data_type,
+                position,
+            } => write!(
+                f,
+                "l{}:c{} {} has the value {} which isn't {}",
+                position.line, position.column, column, value, data_type
+            ),
+            ArrowTableError::Arrow(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ArrowTableError {}
+
+impl From<LoopTableError> for ArrowTableError {
+    fn from(error: LoopTableError) -> Self {
+        ArrowTableError::Loop(error)
+    }
+}
+
+impl From<ArrowError> for ArrowTableError {
+    fn from(error: ArrowError) -> Self {
+        ArrowTableError::Arrow(error)
+    }
+}
+
+impl LoopTable<'_> {
+    /// Build a record batch with a column for each column of the loop
+    ///
+    /// Columns named by a field of `schema_hint` take its type and nullability, the types of
+    /// the others are inferred. Fails if the loop is nested or a value doesn't fit its hinted
+    /// type.
+    pub fn to_arrow(&self, schema_hint: Option<&Schema>) -> Result<RecordBatch, ArrowTableError> {
+        if self.is_nested() {
+            return Err(ArrowTableError::NestedLoop {
+                position: self.position(),
+            });
+        }
+
+        let mut fields = Vec::new();
+        let mut arrays = Vec::new();
+        for (index, name) in self.columns().iter().enumerate() {
+            let hint = schema_hint.and_then(|schema| schema.field_with_name(name).ok());
+            let field = match hint {
+                Some(field) => field.clone(),
+                None => Field::new(name, self.infer_type(index), true),
+            };
+            arrays.push(self.column_array(index, name, field.data_type())?);
+            fields.push(field);
+        }
+
+        Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+    }
+
+    /// The values of column `index` with `.` and `?` as `None`
+    fn column_values(&self, index: usize) -> impl Iterator<Item = Option<&str>> + '_ {
+        (0..self.row_count()).map(move |row| {
+            self.value(row, index)
+                .filter(|value| !matches!(*value, "." | "?"))
+        })
+    }
+
+    /// The narrowest of `Null`, `Int64`, `Float64` and `Utf8` holding every value of column
+    /// `index`
+    fn infer_type(&self, index: usize) -> DataType {
+        let mut data_type = DataType::Null;
+        for value in self.column_values(index).flatten() {
+            data_type = match (parse_number(value), data_type) {
+                (Some(StarValue::Int(_)), DataType::Null | DataType::Int64) => DataType::Int64,
+                (Some(_), DataType::Null | DataType::Int64 | DataType::Float64) => {
+                    DataType::Float64
+                }
+                _ => return DataType::Utf8,
+            };
+        }
+        data_type
+    }
+
+    /// The values of column `index`, called `name`, as an array of `data_type`
+    fn column_array(
+        &self,
+        index: usize,
+        name: &str,
+        data_type: &DataType,
+    ) -> Result<ArrayRef, ArrowTableError> {
+        let number = |row: usize, value: &str| {
+            parse_number(value).ok_or_else(|| self.value_error(row, index, name, data_type))
+        };
+
+        Ok(match data_type {
+            DataType::Null => {
+                if let Some(row) = self.column_values(index).position(|value| value.is_some()) {
+                    return Err(self.value_error(row, index, name, data_type));
+                }
+                Arc::new(NullArray::new(self.row_count()))
+            }
+            DataType::Int64 => Arc::new(
+                self.column_values(index)
+                    .enumerate()
+                    .map(|(row, value)| {
+                        value
+                            .map(|value| match number(row, value)? {
+                                StarValue::Int(int) => Ok(int),
+                                _ => Err(self.value_error(row, index, name, data_type)),
+                            })
+                            .transpose()
+                    })
+                    .collect::<Result<Int64Array, _>>()?,
+            ),
+            DataType::Float64 => Arc::new(
+                self.column_values(index)
+                    .enumerate()
+                    .map(|(row, value)| {
+                        value
+                            .map(|value| {
+                                number(row, value).map(|number| number.as_f64().unwrap_or(f64::NAN))
+                            })
+                            .transpose()
+                    })
+                    .collect::<Result<Float64Array, _>>()?,
+            ),
+            DataType::Utf8 => Arc::new(self.column_values(index).collect::<StringArray>()),
+            _ => {
+                return Err(ArrowTableError::UnsupportedType {
+                    column: name.to_string(),
+                    data_type: data_type.clone(),
+                })
+            }
+        })
+    }
+
+    /// The error for the value of column `index` in `row` not being of `data_type`
+    fn value_error(
+        &self,
+        row: usize,
+        index: usize,
+        name: &str,
+        data_type: &DataType,
+    ) -> ArrowTableError {
+        ArrowTableError::Value {
+            column: name.to_string(),
+            value: self.value(row, index).unwrap_or_default().to_string(),
+            data_type: data_type.clone(),
+            position: self.value_position(row, index).unwrap_or(self.position()),
+        }
+    }
+}
+
+/// Build a record batch for each loop in `pair` whose first data name starts with
+/// `tag_prefix`, compared case-insensitively, in file order
+///
+/// An empty prefix selects every loop. Fails if a selected loop is ragged or nested.
+pub fn loops_to_arrow(
+    pair: &MutablePair,
+    tag_prefix: &str,
+) -> Result<Vec<RecordBatch>, ArrowTableError> {
+    let mut batches = Vec::new();
+    for data_loop in pair.find_all("data_loop") {
+        let selected = data_loop.find_first("data_name").is_some_and(|name| {
+            name.as_str()
+                .get(..tag_prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(tag_prefix))
+        });
+        if selected {
+            batches.push(LoopTable::from_pair(data_loop)?.to_arrow(None)?);
+        }
+    }
+    Ok(batches)
+}
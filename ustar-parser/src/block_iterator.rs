@@ -0,0 +1,202 @@
+//! Lazy block-level iteration over a STAR document
+//!
+//! Data and global blocks are found by scanning for their headings and parsed one at a time, so
+//! only the tree for the current block is held in memory. Each block is yielded as the same
+//! `MutablePair` that `parse` would place under the `star_file` root.
+//!
+//! Each block is checked against the dialect restrictions of the configuration, and validated when
+//! it asks for validation, as `parse` checks the whole tree. The content of a block shares a copy
+//! of its own text, so a block kept after iteration doesn't keep the rest of the input alive.
+
+use crate::config::{
+    get_auto_detect_bom, get_cif_version, get_column_unit, get_decomposed_strings, get_encoding,
+    get_max_nesting_depth, get_unescape_quotes, get_validate, ColumnUnit,
+};
+use crate::fragment::{next_keyword, parse_fragment, skip_trivia, LineTracker};
+use crate::line_column_index::{LineColumn, LineColumnIndex};
+use crate::mutable_pair::MutablePair;
+use crate::shared_text::SharedText;
+use crate::{parsers, string_decomposer, CifVersion, EncodingMode, ParserConfig, UstarError};
+use std::sync::Arc;
+
+/// Headings that start a new block
+pub(crate) const BLOCK_KEYWORDS: [&str; 2] = ["data_", "global_"];
+
+/// Iterator yielding each data or global block of a document in order
+///
+/// Blocks that fail to parse are yielded as errors and iteration continues with the next block.
+pub struct DataBlockIterator<'i> {
+    input: &'i str,
+    /// One copy of the whole input for the blocks to share, made when they are gathered in a tree
+    source: Option<Arc<String>>,
+    /// The configuration each yielded block is checked against
+    config: ParserConfig,
+    encoding: EncodingMode,
+    cif2: bool,
+    decomposed_strings: bool,
+    unescape_quotes: bool,
+    /// The nesting depth each block is checked against before it is parsed
+    max_nesting_depth: usize,
+    /// Index for recounting block positions, only built when columns aren't counted in bytes
+    line_index: Option<LineColumnIndex<'i>>,
+    column_unit: ColumnUnit,
+    pos: usize,
+    lines: LineTracker,
+}
+
+impl<'i> DataBlockIterator<'i> {
+    /// Create an iterator over the blocks of `input`; nothing is parsed until the first `next`
+    pub fn new(input: &'i str, config: &ParserConfig) -> Self {
+        let (encoding, input) = if get_auto_detect_bom(config) && input.starts_with('\u{FEFF}') {
+            (EncodingMode::Unicode, &input[3..])
+        } else {
+            (get_encoding(config), input)
+        };
+        let column_unit = get_column_unit(config);
+        let line_index = (column_unit != ColumnUnit::Bytes)
+            .then(|| LineColumnIndex::new(input).with_column_unit(column_unit));
+
+        DataBlockIterator {
+            input,
+            source: None,
+            config: config.clone(),
+            encoding,
+            cif2: get_cif_version(config) == CifVersion::Cif2,
+            decomposed_strings: get_decomposed_strings(config),
+            unescape_quotes: get_unescape_quotes(config),
+            max_nesting_depth: get_max_nesting_depth(config),
+            line_index,
+            column_unit,
+            pos: 0,
+            lines: LineTracker::new(),
+        }
+    }
+
+    /// Share one copy of the input between the content of every block parsed, for callers
+    /// gathering all the blocks in one tree (private)
+    pub(crate) fn with_shared_source(mut self) -> Self {
+        self.source = Some(Arc::new(self.input.to_string()));
+        self
+    }
+
+    /// The input without any byte order mark, shared by the content of every block; panics
+    /// unless the iterator was made `with_shared_source`
+    pub(crate) fn source(&self) -> &Arc<String> {
+        self.source
+            .as_ref()
+            .expect("the source is only shared with_shared_source")
+    }
+
+    /// The encoding blocks are parsed with, after any BOM detection
+    pub(crate) fn encoding(&self) -> EncodingMode {
+        self.encoding
+    }
+
+    /// Continue finding blocks at `pos`, which must be at or after the end of the last block
+    /// found and not inside a block (private)
+    pub(crate) fn skip_to(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// Find the next block, returning its range and the line and column where it starts
+    pub(crate) fn next_range(&mut self) -> Option<(usize, usize, LineColumn)> {
+        let start = skip_trivia(self.input, self.pos);
+        if start >= self.input.len() {
+            self.pos = self.input.len();
+            return None;
+        }
+
+        let end = next_keyword(self.input, start, &BLOCK_KEYWORDS);
+        self.pos = end;
+        Some((start, end, self.lines.position(self.input, start)))
+    }
+
+    /// Parse `input[start..end]` as a star file holding a single block and return that block,
+    /// without the dialect and validation checks
+    ///
+    /// `origin` is the line and column of `start`.
+    pub(crate) fn parse_block(
+        &self,
+        start: usize,
+        end: usize,
+        origin: LineColumn,
+    ) -> Result<MutablePair, Box<UstarError>> {
+        macro_rules! parse_with {
+            ($module:ident, $parser:ident, $fragment:ident) => {{
+                use parsers::$module::Rule;
+                let rule = if self.cif2 {
+                    Rule::cif2_star_file
+                } else {
+                    Rule::star_file
+                };
+                parse_fragment::<parsers::$module::$parser, Rule>(
+                    self.input,
+                    &$fragment,
+                    start,
+                    origin,
+                    rule,
+                    self.encoding,
+                )
+            }};
+        }
+
+        crate::limits::check_nesting(
+            self.input,
+            start,
+            end,
+            self.max_nesting_depth,
+            self.cif2,
+            self.encoding,
+        )
+        .map_err(|error| Box::new((*error).with_column_unit(self.column_unit)))?;
+
+        let fragment = match &self.source {
+            Some(source) => SharedText::new(source.clone(), start, end),
+            None => SharedText::from(&self.input[start..end]),
+        };
+        let star_file = match self.encoding {
+            EncodingMode::Ascii => parse_with!(ascii, AsciiParser, fragment),
+            EncodingMode::ExtendedAscii => parse_with!(extended, ExtendedParser, fragment),
+            EncodingMode::Unicode => parse_with!(unicode, UnicodeParser, fragment),
+        }
+        .map_err(|error| Box::new((*error).with_column_unit(self.column_unit)))?;
+
+        let mut block = star_file
+            .children
+            .into_iter()
+            .next()
+            .expect("a non empty star file starts with a block");
+        if self.decomposed_strings {
+            string_decomposer::decompose_strings_with(&mut block, self.unescape_quotes);
+        }
+        if let Some(line_index) = &self.line_index {
+            block.recount_positions(line_index);
+        }
+        Ok(block)
+    }
+
+    /// Check a parsed block as `parse` checks the tree of the whole input (private)
+    fn check_block(&self, block: &MutablePair) -> Result<(), Box<UstarError>> {
+        crate::validate::check_dialect(block, self.input, &self.config, self.encoding)
+            .and_then(|()| {
+                if get_validate(&self.config) {
+                    crate::validate::check_semantics(block, self.input, self.encoding)
+                } else {
+                    Ok(())
+                }
+            })
+            .map_err(|error| Box::new((*error).with_column_unit(self.column_unit)))
+    }
+}
+
+impl Iterator for DataBlockIterator<'_> {
+    type Item = Result<MutablePair, Box<UstarError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, end, origin) = self.next_range()?;
+        Some(
+            self.parse_block(start, end, origin)
+                .and_then(|block| self.check_block(&block).map(|()| block)),
+        )
+    }
+}
@@ -2,14 +2,16 @@ use std::collections::HashMap;
 
 /// Character encoding mode for the USTAR parser
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EncodingMode {
     /// ASCII-only mode: Characters 0x21-0x7E ('!' to '~')
     /// Whitespace: space (0x20) and tab (0x09)
     #[default]
     Ascii,
 
-    /// Extended ASCII mode: Characters 0x00-0xFF
+    /// Extended ASCII mode: Characters 0x00-0xFF and the characters of Windows-1252
     /// Whitespace: ASCII whitespace plus non-breaking space (0xA0)
+    /// Input that isn't UTF-8 is read as Latin-1 / Windows-1252 by `parse_bytes`
     ExtendedAscii,
 
     /// Full Unicode mode: All Unicode characters
@@ -18,6 +20,15 @@ pub enum EncodingMode {
     Unicode,
 }
 
+impl EncodingMode {
+    /// Every encoding mode
+    pub const ALL: [EncodingMode; 3] = [
+        EncodingMode::Ascii,
+        EncodingMode::ExtendedAscii,
+        EncodingMode::Unicode,
+    ];
+}
+
 /// Error formatting mode for runtime display
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum ErrorFormatMode {
@@ -27,6 +38,19 @@ pub enum ErrorFormatMode {
     Ascii,
     /// Full miette formatting (requires extended-errors feature)
     Fancy,
+    /// A JSON object with the error code, message, line, column, span, expected rules and the
+    /// error line as context
+    Json,
+}
+
+impl ErrorFormatMode {
+    /// Every error format
+    pub const ALL: [ErrorFormatMode; 4] = [
+        ErrorFormatMode::Basic,
+        ErrorFormatMode::Ascii,
+        ErrorFormatMode::Fancy,
+        ErrorFormatMode::Json,
+    ];
 }
 
 impl Default for ErrorFormatMode {
@@ -38,12 +62,54 @@ impl Default for ErrorFormatMode {
     }
 }
 
+/// CIF syntax version for the USTAR parser
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+pub enum CifVersion {
+    /// STAR / CIF 1.1 syntax: brackets and braces are ordinary characters in values
+    #[default]
+    Cif1,
+
+    /// CIF 2.0 syntax: adds list `[...]` and table `{...}` values
+    Cif2,
+}
+
+impl CifVersion {
+    /// Every CIF version
+    pub const ALL: [CifVersion; 2] = [CifVersion::Cif1, CifVersion::Cif2];
+}
+
+/// Unit that columns of line and column positions are counted in
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColumnUnit {
+    /// UTF-8 bytes, the column is the byte offset from the start of the line plus one
+    #[default]
+    Bytes,
+
+    /// Unicode scalar values, so `α` counts as one column although it takes two bytes
+    Chars,
+
+    /// Grapheme clusters, approximated: combining marks, variation selectors, emoji modifiers
+    /// and zero width joiner sequences extend the character before them
+    Graphemes,
+}
+
+impl ColumnUnit {
+    /// Every column unit
+    pub const ALL: [ColumnUnit; 3] = [ColumnUnit::Bytes, ColumnUnit::Chars, ColumnUnit::Graphemes];
+}
+
 /// Configuration keys for the USTAR parser
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum ConfigKey {
     /// Whether to decompose string tokens into delimiter + content + delimiter (value: bool)
     DecomposedStrings,
 
+    /// Whether the content of a single or double quoted string holds its logical value, with
+    /// doubled quotes such as `''` collapsed to one, when strings are decomposed and when a
+    /// `StarWalker` reports values (value: bool)
+    UnescapeQuotes,
+
     /// Character encoding mode (value: EncodingMode)
     Encoding,
 
@@ -55,6 +121,125 @@ pub enum ConfigKey {
 
     /// Number of context lines to display around errors (value: usize, ignored by Basic mode)
     ContextLines,
+
+    /// CIF syntax version, CIF 2.0 enables list and table values (value: CifVersion)
+    CifVersion,
+
+    /// Whether a loop may have no values, as in `loop_ _a _b stop_` (value: bool)
+    AllowEmptyLoops,
+
+    /// Whether data items and loops may appear in a data block outside any save frame (value: bool)
+    AllowDataOutsideSaveframes,
+
+    /// Whether every loop must be terminated by `stop_` (value: bool)
+    RequireStopKeyword,
+
+    /// Maximum number of characters in a line, unlimited if not set (value: usize)
+    MaxLineLength,
+
+    /// Maximum number of characters in a data name, block code or frame code, unlimited if not set (value: usize)
+    MaxNameLength,
+
+    /// Whether to check for duplicate data names and save frames and dangling frame codes (value: bool)
+    Validate,
+
+    /// Whether to reject block and frame codes containing quotes, `#`, `$` or CIF 2.0
+    /// brackets, which the grammar accepts as part of a heading (value: bool)
+    Strict,
+
+    /// Whether a `StarWalker` passes full line comments to `element_comments_with_kind` with
+    /// the element that follows them rather than as standalone `comment` events; inline
+    /// comments are always standalone (value: bool)
+    AttachComments,
+
+    /// Unit columns are counted in for tree positions, walker positions and errors (value: ColumnUnit)
+    ColumnUnit,
+
+    /// Maximum depth of nested loops and of CIF 2.0 lists and tables, 128 if not set (value: usize)
+    MaxNestingDepth,
+
+    /// Maximum size of the input in bytes, unlimited if not set (value: usize)
+    MaxInputBytes,
+
+    /// Whether `parse` first compacts the whitespace and comments between tokens so pest has
+    /// less to skip, the tree and errors are the same either way (value: bool)
+    Prelex,
+}
+
+impl ConfigKey {
+    /// Every configuration key
+    pub const ALL: [ConfigKey; 19] = [
+        ConfigKey::DecomposedStrings,
+        ConfigKey::UnescapeQuotes,
+        ConfigKey::Encoding,
+        ConfigKey::AutoDetectBom,
+        ConfigKey::ErrorFormat,
+        ConfigKey::ContextLines,
+        ConfigKey::CifVersion,
+        ConfigKey::AllowEmptyLoops,
+        ConfigKey::AllowDataOutsideSaveframes,
+        ConfigKey::RequireStopKeyword,
+        ConfigKey::MaxLineLength,
+        ConfigKey::MaxNameLength,
+        ConfigKey::Validate,
+        ConfigKey::Strict,
+        ConfigKey::AttachComments,
+        ConfigKey::ColumnUnit,
+        ConfigKey::MaxNestingDepth,
+        ConfigKey::MaxInputBytes,
+        ConfigKey::Prelex,
+    ];
+
+    /// The snake case name of the key in configuration files and environment variables
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigKey::DecomposedStrings => "decomposed_strings",
+            ConfigKey::UnescapeQuotes => "unescape_quotes",
+            ConfigKey::Encoding => "encoding",
+            ConfigKey::AutoDetectBom => "auto_detect_bom",
+            ConfigKey::ErrorFormat => "error_format",
+            ConfigKey::ContextLines => "context_lines",
+            ConfigKey::CifVersion => "cif_version",
+            ConfigKey::AllowEmptyLoops => "allow_empty_loops",
+            ConfigKey::AllowDataOutsideSaveframes => "allow_data_outside_saveframes",
+            ConfigKey::RequireStopKeyword => "require_stop_keyword",
+            ConfigKey::MaxLineLength => "max_line_length",
+            ConfigKey::MaxNameLength => "max_name_length",
+            ConfigKey::Validate => "validate",
+            ConfigKey::Strict => "strict",
+            ConfigKey::AttachComments => "attach_comments",
+            ConfigKey::ColumnUnit => "column_unit",
+            ConfigKey::MaxNestingDepth => "max_nesting_depth",
+            ConfigKey::MaxInputBytes => "max_input_bytes",
+            ConfigKey::Prelex => "prelex",
+        }
+    }
+
+    /// The key named `name` in configuration files, see `ConfigKey::name`
+    pub fn from_name(name: &str) -> Option<ConfigKey> {
+        Self::ALL.into_iter().find(|key| key.name() == name)
+    }
+}
+
+/// Dialects of STAR with presets for `ParserConfig::preset`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Dialect {
+    /// STAR as accepted by this parser with Unicode input and no further restrictions
+    Star2012,
+
+    /// CIF 1.1: ASCII input, no empty loops, lines of up to 2048 characters and names of up to 75
+    Cif1,
+
+    /// CIF 2.0: Unicode input with lists and tables, no empty loops and lines of up to 2048 characters
+    Cif2,
+
+    /// NMR-STAR as read by pynmrstar: all data inside save frames and loops closed by `stop_`,
+    /// with no empty loops
+    NmrStar,
+
+    /// NMR Exchange Format: all data inside save frames and loops closed by `stop_`, empty loops
+    /// are allowed
+    Nef,
 }
 
 /// Parser configuration as a HashMap
@@ -67,6 +252,8 @@ pub enum ConfigValue {
     Encoding(EncodingMode),
     ErrorFormat(ErrorFormatMode),
     Usize(usize),
+    CifVersion(CifVersion),
+    ColumnUnit(ColumnUnit),
 }
 
 impl ConfigValue {
@@ -97,23 +284,582 @@ impl ConfigValue {
             _ => None,
         }
     }
+
+    pub fn as_cif_version(&self) -> Option<CifVersion> {
+        match self {
+            ConfigValue::CifVersion(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_column_unit(&self) -> Option<ColumnUnit> {
+        match self {
+            ConfigValue::ColumnUnit(u) => Some(*u),
+            _ => None,
+        }
+    }
+}
+
+/// Errors from building a configuration whose options contradict each other
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// A limit of zero, which no input could meet (key: MaxLineLength, MaxNameLength, MaxNestingDepth
+    /// or MaxInputBytes)
+    ZeroLimit(ConfigKey),
+    /// CIF 2.0 input is UTF-8, so it can't be read as Latin-1 with `EncodingMode::ExtendedAscii`
+    Cif2Encoding(EncodingMode),
+    /// A key in a configuration file or environment variable that names no `ConfigKey`
+    UnknownKey(String),
+    /// A value of the wrong kind for its key, `expected` describes the values allowed
+    InvalidValue {
+        key: ConfigKey,
+        value: String,
+        expected: String,
+    },
+    /// A configuration file that couldn't be read or isn't valid TOML or JSON
+    Unreadable(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::ZeroLimit(key) => write!(f, "{} must be at least 1", key.name()),
+            ConfigError::Cif2Encoding(encoding) => {
+                write!(
+                    f,
+                    "CIF 2.0 input is UTF-8 and can't use encoding {:?}",
+                    encoding
+                )
+            }
+            ConfigError::UnknownKey(name) => write!(f, "unknown configuration key '{}'", name),
+            ConfigError::InvalidValue {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value {} for {}, expected {}",
+                value,
+                key.name(),
+                expected
+            ),
+            ConfigError::Unreadable(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Builder for a `ParserConfig` with a typed setter for each `ConfigKey`
+///
+/// Each setter takes the value type of its key, so a key can't be given a value of the wrong
+/// kind. The builder starts from the default options and `build` checks that the options set
+/// are consistent with each other.
+#[derive(Debug, Clone)]
+pub struct ParserConfigBuilder {
+    config: ParserConfig,
+}
+
+impl ParserConfigBuilder {
+    /// A builder starting from the default options: ASCII input, decomposed strings, no BOM
+    /// detection, the default error format with 3 context lines and CIF 1.1 syntax
+    pub fn new() -> Self {
+        ParserConfigBuilder {
+            config: HashMap::new(),
+        }
+        .decomposed_strings(true)
+        .encoding(EncodingMode::Ascii)
+        .auto_detect_bom(false)
+        .error_format(ErrorFormatMode::default())
+        .context_lines(3)
+        .cif_version(CifVersion::Cif1)
+    }
+
+    fn set(mut self, key: ConfigKey, value: ConfigValue) -> Self {
+        self.config.insert(key, value);
+        self
+    }
+
+    /// Whether to decompose string tokens into delimiter + content + delimiter
+    pub fn decomposed_strings(self, decomposed_strings: bool) -> Self {
+        self.set(
+            ConfigKey::DecomposedStrings,
+            ConfigValue::Bool(decomposed_strings),
+        )
+    }
+
+    /// Whether quoted strings hold their value with doubled quotes collapsed
+    pub fn unescape_quotes(self, unescape_quotes: bool) -> Self {
+        self.set(
+            ConfigKey::UnescapeQuotes,
+            ConfigValue::Bool(unescape_quotes),
+        )
+    }
+
+    /// Character encoding mode
+    pub fn encoding(self, encoding: EncodingMode) -> Self {
+        self.set(ConfigKey::Encoding, ConfigValue::Encoding(encoding))
+    }
+
+    /// Whether to auto-detect BOM and override encoding
+    pub fn auto_detect_bom(self, auto_detect_bom: bool) -> Self {
+        self.set(ConfigKey::AutoDetectBom, ConfigValue::Bool(auto_detect_bom))
+    }
+
+    /// Error format mode for runtime error display
+    pub fn error_format(self, error_format: ErrorFormatMode) -> Self {
+        self.set(
+            ConfigKey::ErrorFormat,
+            ConfigValue::ErrorFormat(error_format),
+        )
+    }
+
+    /// Number of context lines to display around errors
+    pub fn context_lines(self, context_lines: usize) -> Self {
+        self.set(ConfigKey::ContextLines, ConfigValue::Usize(context_lines))
+    }
+
+    /// CIF syntax version
+    pub fn cif_version(self, cif_version: CifVersion) -> Self {
+        self.set(ConfigKey::CifVersion, ConfigValue::CifVersion(cif_version))
+    }
+
+    /// Whether a loop may have no values
+    pub fn allow_empty_loops(self, allow_empty_loops: bool) -> Self {
+        self.set(
+            ConfigKey::AllowEmptyLoops,
+            ConfigValue::Bool(allow_empty_loops),
+        )
+    }
+
+    /// Whether data items and loops may appear in a data block outside any save frame
+    pub fn allow_data_outside_saveframes(self, allow_data_outside_saveframes: bool) -> Self {
+        self.set(
+            ConfigKey::AllowDataOutsideSaveframes,
+            ConfigValue::Bool(allow_data_outside_saveframes),
+        )
+    }
+
+    /// Whether every loop must be terminated by `stop_`
+    pub fn require_stop_keyword(self, require_stop_keyword: bool) -> Self {
+        self.set(
+            ConfigKey::RequireStopKeyword,
+            ConfigValue::Bool(require_stop_keyword),
+        )
+    }
+
+    /// Maximum number of characters in a line
+    pub fn max_line_length(self, max_line_length: usize) -> Self {
+        self.set(
+            ConfigKey::MaxLineLength,
+            ConfigValue::Usize(max_line_length),
+        )
+    }
+
+    /// Maximum number of characters in a data name, block code or frame code
+    pub fn max_name_length(self, max_name_length: usize) -> Self {
+        self.set(
+            ConfigKey::MaxNameLength,
+            ConfigValue::Usize(max_name_length),
+        )
+    }
+
+    /// Whether to check for duplicate data names and save frames and dangling frame codes
+    pub fn validate(self, validate: bool) -> Self {
+        self.set(ConfigKey::Validate, ConfigValue::Bool(validate))
+    }
+
+    /// Whether to reject block and frame codes containing quotes, `#`, `$` or CIF 2.0 brackets
+    pub fn strict(self, strict: bool) -> Self {
+        self.set(ConfigKey::Strict, ConfigValue::Bool(strict))
+    }
+
+    /// Whether a `StarWalker` passes comments with the element that follows them
+    pub fn attach_comments(self, attach_comments: bool) -> Self {
+        self.set(
+            ConfigKey::AttachComments,
+            ConfigValue::Bool(attach_comments),
+        )
+    }
+
+    /// Unit columns are counted in
+    pub fn column_unit(self, column_unit: ColumnUnit) -> Self {
+        self.set(ConfigKey::ColumnUnit, ConfigValue::ColumnUnit(column_unit))
+    }
+
+    /// Maximum depth of nested loops and of CIF 2.0 lists and tables
+    pub fn max_nesting_depth(self, max_nesting_depth: usize) -> Self {
+        self.set(
+            ConfigKey::MaxNestingDepth,
+            ConfigValue::Usize(max_nesting_depth),
+        )
+    }
+
+    /// Maximum size of the input in bytes
+    pub fn max_input_bytes(self, max_input_bytes: usize) -> Self {
+        self.set(
+            ConfigKey::MaxInputBytes,
+            ConfigValue::Usize(max_input_bytes),
+        )
+    }
+
+    /// Whether `parse` compacts the text between tokens before pest parses it
+    pub fn prelex(self, prelex: bool) -> Self {
+        self.set(ConfigKey::Prelex, ConfigValue::Bool(prelex))
+    }
+
+    /// The configuration, or the first pair of options that contradict each other
+    pub fn build(self) -> Result<ParserConfig, ConfigError> {
+        for key in [
+            ConfigKey::MaxLineLength,
+            ConfigKey::MaxNameLength,
+            ConfigKey::MaxNestingDepth,
+            ConfigKey::MaxInputBytes,
+        ] {
+            if self.config.get(&key).and_then(|v| v.as_usize()) == Some(0) {
+                return Err(ConfigError::ZeroLimit(key));
+            }
+        }
+        let encoding = get_encoding(&self.config);
+        if get_cif_version(&self.config) == CifVersion::Cif2
+            && encoding == EncodingMode::ExtendedAscii
+        {
+            return Err(ConfigError::Cif2Encoding(encoding));
+        }
+        Ok(self.config)
+    }
 }
 
 /// Create default parser configuration
 pub fn default_config() -> ParserConfig {
-    let mut config = HashMap::new();
-    config.insert(ConfigKey::DecomposedStrings, ConfigValue::Bool(true));
-    config.insert(
-        ConfigKey::Encoding,
-        ConfigValue::Encoding(EncodingMode::Ascii),
-    );
-    config.insert(ConfigKey::AutoDetectBom, ConfigValue::Bool(false));
-    config.insert(
-        ConfigKey::ErrorFormat,
-        ConfigValue::ErrorFormat(ErrorFormatMode::default()),
-    );
-    config.insert(ConfigKey::ContextLines, ConfigValue::Usize(3)); // Default to 3 lines of context
-    config
+    ParserConfigBuilder::new()
+        .build()
+        .expect("the default options are consistent")
+}
+
+/// Construction of a configuration from a dialect preset
+///
+/// `ParserConfig` is a `HashMap` alias so the constructors are provided by a trait, import it to
+/// write `ParserConfig::preset(Dialect::Nef)` or `ParserConfig::nef()`.
+pub trait DialectPreset: Sized {
+    /// The default configuration with the keys that `dialect` restricts set for it
+    fn preset(dialect: Dialect) -> Self;
+
+    /// The preset for NMR Exchange Format files, `Dialect::Nef`
+    fn nef() -> Self {
+        Self::preset(Dialect::Nef)
+    }
+
+    /// The preset for mmCIF files, which use CIF 1.1 syntax, `Dialect::Cif1`
+    fn mmcif() -> Self {
+        Self::preset(Dialect::Cif1)
+    }
+
+    /// The preset for NMR-STAR files, `Dialect::NmrStar`
+    fn nmrstar() -> Self {
+        Self::preset(Dialect::NmrStar)
+    }
+}
+
+impl DialectPreset for ParserConfig {
+    fn preset(dialect: Dialect) -> Self {
+        let (encoding, cif_version) = match dialect {
+            Dialect::Cif1 => (EncodingMode::Ascii, CifVersion::Cif1),
+            Dialect::Cif2 => (EncodingMode::Unicode, CifVersion::Cif2),
+            Dialect::Star2012 | Dialect::NmrStar | Dialect::Nef => {
+                (EncodingMode::Unicode, CifVersion::Cif1)
+            }
+        };
+        let allow_empty_loops = matches!(dialect, Dialect::Star2012 | Dialect::Nef);
+        let saveframes_only = matches!(dialect, Dialect::NmrStar | Dialect::Nef);
+
+        let mut builder = ParserConfigBuilder::new()
+            .encoding(encoding)
+            .auto_detect_bom(dialect == Dialect::Cif2)
+            .cif_version(cif_version)
+            .allow_empty_loops(allow_empty_loops)
+            .allow_data_outside_saveframes(!saveframes_only)
+            .require_stop_keyword(saveframes_only);
+        if matches!(dialect, Dialect::Cif1 | Dialect::Cif2) {
+            builder = builder.max_line_length(2048);
+        }
+        if dialect == Dialect::Cif1 {
+            builder = builder.max_name_length(75);
+        }
+        builder.build().expect("dialect presets are consistent")
+    }
+}
+
+/// Reading and writing a configuration as TOML, JSON or environment variables
+///
+/// Keys are the snake case names of `ConfigKey`, such as `context_lines`. Flags are booleans,
+/// counts are non-negative integers and modes are named in snake case: `encoding` is `ascii`,
+/// `extended_ascii` or `unicode`, `error_format` is `basic`, `ascii`, `fancy` or `json`,
+/// `cif_version` is `cif1` or `cif2` and `column_unit` is `bytes`, `chars` or `graphemes`.
+/// Keys that aren't given keep their default values and the result is checked as by
+/// `ParserConfigBuilder::build`. Like `DialectPreset` this is a trait, import it to write
+/// `ParserConfig::from_toml_str(text)`.
+#[cfg(feature = "serde")]
+pub trait ConfigFile: Sized {
+    /// Read a configuration from a TOML table of keys and values, such as `context_lines = 5`
+    fn from_toml_str(text: &str) -> Result<Self, ConfigError>;
+
+    /// Read a configuration from a JSON object of keys and values, such as `{"context_lines": 5}`
+    fn from_json_str(text: &str) -> Result<Self, ConfigError>;
+
+    /// Read a configuration from the environment variables named by `prefix`, an underscore and
+    /// the upper case key, so `from_env("USTAR")` reads `context_lines` from `USTAR_CONTEXT_LINES`
+    fn from_env(prefix: &str) -> Result<Self, ConfigError>;
+
+    /// Read a configuration file, as JSON if its extension is `json` and as TOML otherwise
+    fn from_file(path: &std::path::Path) -> Result<Self, ConfigError>;
+
+    /// The configuration as a TOML table that `from_toml_str` reads back
+    fn to_toml_string(&self) -> String;
+
+    /// The configuration as a JSON object that `from_json_str` reads back
+    fn to_json_string(&self) -> String;
+}
+
+#[cfg(feature = "serde")]
+impl ConfigFile for ParserConfig {
+    fn from_toml_str(text: &str) -> Result<Self, ConfigError> {
+        let document: toml_edit::DocumentMut = text
+            .parse()
+            .map_err(|error| ConfigError::Unreadable(format!("invalid TOML: {}", error)))?;
+        let options = document.iter().map(|(name, item)| {
+            let value = if let Some(flag) = item.as_bool() {
+                OptionValue::Bool(flag)
+            } else if let Some(number) = item.as_integer() {
+                OptionValue::Integer(number)
+            } else if let Some(text) = item.as_str() {
+                OptionValue::Text(text.to_string())
+            } else {
+                OptionValue::Other(item.type_name())
+            };
+            (name.to_string(), value)
+        });
+        build_from_options(options)
+    }
+
+    fn from_json_str(text: &str) -> Result<Self, ConfigError> {
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(text)
+            .map_err(|error| ConfigError::Unreadable(format!("invalid JSON: {}", error)))?;
+        let options = object.into_iter().map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::Bool(flag) => OptionValue::Bool(flag),
+                serde_json::Value::Number(number) => match number.as_i64() {
+                    Some(number) => OptionValue::Integer(number),
+                    None => OptionValue::Other("number"),
+                },
+                serde_json::Value::String(text) => OptionValue::Text(text),
+                serde_json::Value::Null => OptionValue::Other("null"),
+                serde_json::Value::Array(_) => OptionValue::Other("array"),
+                serde_json::Value::Object(_) => OptionValue::Other("object"),
+            };
+            (name, value)
+        });
+        build_from_options(options)
+    }
+
+    fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        let variable_prefix = format!("{}_", prefix);
+        let options = std::env::vars().filter_map(|(variable, text)| {
+            let name = variable.strip_prefix(&variable_prefix)?.to_lowercase();
+            let value = if let Ok(flag) = text.parse() {
+                OptionValue::Bool(flag)
+            } else if let Ok(number) = text.parse() {
+                OptionValue::Integer(number)
+            } else {
+                OptionValue::Text(text)
+            };
+            Some((name, value))
+        });
+        build_from_options(options)
+    }
+
+    fn from_file(path: &std::path::Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| {
+            ConfigError::Unreadable(format!("could not read {}: {}", path.display(), error))
+        })?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            Self::from_json_str(&text)
+        } else {
+            Self::from_toml_str(&text)
+        }
+    }
+
+    fn to_toml_string(&self) -> String {
+        ConfigKey::ALL
+            .iter()
+            .filter_map(|key| {
+                let value = match option_value(self.get(key)?) {
+                    OptionValue::Text(text) => format!("\"{}\"", text),
+                    value => value.to_string(),
+                };
+                Some(format!("{} = {}\n", key.name(), value))
+            })
+            .collect()
+    }
+
+    fn to_json_string(&self) -> String {
+        let object: serde_json::Map<String, serde_json::Value> = ConfigKey::ALL
+            .iter()
+            .filter_map(|key| {
+                let value = match option_value(self.get(key)?) {
+                    OptionValue::Bool(flag) => serde_json::Value::from(flag),
+                    OptionValue::Integer(number) => serde_json::Value::from(number),
+                    OptionValue::Text(text) => serde_json::Value::from(text),
+                    OptionValue::Other(_) => return None,
+                };
+                Some((key.name().to_string(), value))
+            })
+            .collect();
+        serde_json::to_string_pretty(&object).expect("configuration values are valid JSON")
+    }
+}
+
+/// A value read from a configuration file or environment variable before it's given its key's
+/// type, `Other` names a kind of value no key takes (private)
+#[cfg(feature = "serde")]
+enum OptionValue {
+    Bool(bool),
+    Integer(i64),
+    Text(String),
+    Other(&'static str),
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionValue::Bool(flag) => write!(f, "{}", flag),
+            OptionValue::Integer(number) => write!(f, "{}", number),
+            OptionValue::Text(text) => write!(f, "'{}'", text),
+            OptionValue::Other(kind) => write!(f, "of type {}", kind),
+        }
+    }
+}
+
+/// Names of the modes in configuration files (private)
+#[cfg(feature = "serde")]
+const ENCODING_NAMES: [(&str, EncodingMode); 3] = [
+    ("ascii", EncodingMode::Ascii),
+    ("extended_ascii", EncodingMode::ExtendedAscii),
+    ("unicode", EncodingMode::Unicode),
+];
+#[cfg(feature = "serde")]
+const ERROR_FORMAT_NAMES: [(&str, ErrorFormatMode); 4] = [
+    ("basic", ErrorFormatMode::Basic),
+    ("ascii", ErrorFormatMode::Ascii),
+    ("fancy", ErrorFormatMode::Fancy),
+    ("json", ErrorFormatMode::Json),
+];
+#[cfg(feature = "serde")]
+const CIF_VERSION_NAMES: [(&str, CifVersion); 2] =
+    [("cif1", CifVersion::Cif1), ("cif2", CifVersion::Cif2)];
+#[cfg(feature = "serde")]
+const COLUMN_UNIT_NAMES: [(&str, ColumnUnit); 3] = [
+    ("bytes", ColumnUnit::Bytes),
+    ("chars", ColumnUnit::Chars),
+    ("graphemes", ColumnUnit::Graphemes),
+];
+
+/// The name of `mode` in `names` (private)
+#[cfg(feature = "serde")]
+fn mode_name<T: PartialEq>(names: &[(&'static str, T)], mode: T) -> &'static str {
+    names
+        .iter()
+        .find(|(_, named)| *named == mode)
+        .map(|(name, _)| *name)
+        .expect("every mode is named")
+}
+
+/// `value` as it is written in a configuration file (private)
+#[cfg(feature = "serde")]
+fn option_value(value: &ConfigValue) -> OptionValue {
+    let text = |name: &str| OptionValue::Text(name.to_string());
+    match value {
+        ConfigValue::Bool(flag) => OptionValue::Bool(*flag),
+        ConfigValue::Usize(number) => OptionValue::Integer(*number as i64),
+        ConfigValue::Encoding(encoding) => text(mode_name(&ENCODING_NAMES, *encoding)),
+        ConfigValue::ErrorFormat(format) => text(mode_name(&ERROR_FORMAT_NAMES, *format)),
+        ConfigValue::CifVersion(version) => text(mode_name(&CIF_VERSION_NAMES, *version)),
+        ConfigValue::ColumnUnit(unit) => text(mode_name(&COLUMN_UNIT_NAMES, *unit)),
+    }
+}
+
+/// Build a configuration from the defaults and named options, each set with the typed setter of
+/// its key (private)
+#[cfg(feature = "serde")]
+fn build_from_options(
+    options: impl Iterator<Item = (String, OptionValue)>,
+) -> Result<ParserConfig, ConfigError> {
+    let mut builder = ParserConfigBuilder::new();
+    for (name, value) in options {
+        let key = ConfigKey::from_name(&name).ok_or(ConfigError::UnknownKey(name))?;
+        let invalid = |expected: String| ConfigError::InvalidValue {
+            key: key.clone(),
+            value: value.to_string(),
+            expected,
+        };
+        let flag = || match value {
+            OptionValue::Bool(flag) => Ok(flag),
+            _ => Err(invalid("true or false".to_string())),
+        };
+        let count = || match value {
+            OptionValue::Integer(number) => {
+                usize::try_from(number).map_err(|_| invalid("a non-negative integer".to_string()))
+            }
+            _ => Err(invalid("a non-negative integer".to_string())),
+        };
+        fn mode<T: Copy>(
+            value: &OptionValue,
+            names: &[(&str, T)],
+            invalid: impl Fn(String) -> ConfigError,
+        ) -> Result<T, ConfigError> {
+            names
+                .iter()
+                .find(|(name, _)| matches!(value, OptionValue::Text(text) if text == name))
+                .map(|(_, mode)| *mode)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = names.iter().map(|(name, _)| *name).collect();
+                    invalid(format!("one of {}", names.join(", ")))
+                })
+        }
+
+        builder = match key {
+            ConfigKey::DecomposedStrings => builder.decomposed_strings(flag()?),
+            ConfigKey::UnescapeQuotes => builder.unescape_quotes(flag()?),
+            ConfigKey::Encoding => builder.encoding(mode(&value, &ENCODING_NAMES, invalid)?),
+            ConfigKey::AutoDetectBom => builder.auto_detect_bom(flag()?),
+            ConfigKey::ErrorFormat => {
+                builder.error_format(mode(&value, &ERROR_FORMAT_NAMES, invalid)?)
+            }
+            ConfigKey::ContextLines => builder.context_lines(count()?),
+            ConfigKey::CifVersion => {
+                builder.cif_version(mode(&value, &CIF_VERSION_NAMES, invalid)?)
+            }
+            ConfigKey::AllowEmptyLoops => builder.allow_empty_loops(flag()?),
+            ConfigKey::AllowDataOutsideSaveframes => builder.allow_data_outside_saveframes(flag()?),
+            ConfigKey::RequireStopKeyword => builder.require_stop_keyword(flag()?),
+            ConfigKey::MaxLineLength => builder.max_line_length(count()?),
+            ConfigKey::MaxNameLength => builder.max_name_length(count()?),
+            ConfigKey::Validate => builder.validate(flag()?),
+            ConfigKey::Strict => builder.strict(flag()?),
+            ConfigKey::AttachComments => builder.attach_comments(flag()?),
+            ConfigKey::ColumnUnit => {
+                builder.column_unit(mode(&value, &COLUMN_UNIT_NAMES, invalid)?)
+            }
+            ConfigKey::MaxNestingDepth => builder.max_nesting_depth(count()?),
+            ConfigKey::MaxInputBytes => builder.max_input_bytes(count()?),
+            ConfigKey::Prelex => builder.prelex(flag()?),
+        };
+    }
+    builder.build()
 }
 
 /// Get auto_detect_bom setting from configuration
@@ -132,6 +878,14 @@ pub fn get_decomposed_strings(config: &ParserConfig) -> bool {
         .unwrap_or(true)
 }
 
+/// Get unescape_quotes setting from configuration
+pub fn get_unescape_quotes(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::UnescapeQuotes)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
 /// Get encoding mode from configuration
 pub fn get_encoding(config: &ParserConfig) -> EncodingMode {
     config
@@ -155,3 +909,108 @@ pub fn get_context_lines(config: &ParserConfig) -> usize {
         .and_then(|v| v.as_usize())
         .unwrap_or(3) // Default to 3 lines
 }
+
+/// Get CIF syntax version from configuration
+pub fn get_cif_version(config: &ParserConfig) -> CifVersion {
+    config
+        .get(&ConfigKey::CifVersion)
+        .and_then(|v| v.as_cif_version())
+        .unwrap_or_default()
+}
+
+/// Get allow_empty_loops setting from configuration
+pub fn get_allow_empty_loops(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::AllowEmptyLoops)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true)
+}
+
+/// Get allow_data_outside_saveframes setting from configuration
+pub fn get_allow_data_outside_saveframes(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::AllowDataOutsideSaveframes)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true)
+}
+
+/// Get require_stop_keyword setting from configuration
+pub fn get_require_stop_keyword(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::RequireStopKeyword)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Get the maximum line length from configuration, `None` if lines are unlimited
+pub fn get_max_line_length(config: &ParserConfig) -> Option<usize> {
+    config
+        .get(&ConfigKey::MaxLineLength)
+        .and_then(|v| v.as_usize())
+}
+
+/// Get the maximum name length from configuration, `None` if names are unlimited
+pub fn get_max_name_length(config: &ParserConfig) -> Option<usize> {
+    config
+        .get(&ConfigKey::MaxNameLength)
+        .and_then(|v| v.as_usize())
+}
+
+/// Get validate setting from configuration
+pub fn get_validate(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::Validate)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Get strict setting from configuration
+pub fn get_strict(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::Strict)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Get attach_comments setting from configuration
+pub fn get_attach_comments(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::AttachComments)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// Get the unit columns are counted in from configuration
+pub fn get_column_unit(config: &ParserConfig) -> ColumnUnit {
+    config
+        .get(&ConfigKey::ColumnUnit)
+        .and_then(|v| v.as_column_unit())
+        .unwrap_or_default()
+}
+
+/// Nesting depth allowed when `ConfigKey::MaxNestingDepth` isn't set, far deeper than real files
+/// nest but shallow enough that parsing can't exhaust the stack
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 128;
+
+/// Get the maximum nesting depth of loops, lists and tables from configuration
+pub fn get_max_nesting_depth(config: &ParserConfig) -> usize {
+    config
+        .get(&ConfigKey::MaxNestingDepth)
+        .and_then(|v| v.as_usize())
+        .unwrap_or(DEFAULT_MAX_NESTING_DEPTH)
+}
+
+/// Get the maximum input size in bytes from configuration, `None` if the input is unlimited
+pub fn get_max_input_bytes(config: &ParserConfig) -> Option<usize> {
+    config
+        .get(&ConfigKey::MaxInputBytes)
+        .and_then(|v| v.as_usize())
+}
+
+/// Get prelex setting from configuration
+pub fn get_prelex(config: &ParserConfig) -> bool {
+    config
+        .get(&ConfigKey::Prelex)
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
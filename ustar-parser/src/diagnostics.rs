@@ -0,0 +1,320 @@
+//! Diagnostics - non-fatal warnings about parsed input.
+//!
+//! Some input parses but is likely to be a mistake, or to be read differently by other STAR
+//! readers: line endings that change style part way through a file, tabs inside quoted
+//! strings, loops ended by a save frame rather than `stop_`, unquoted values that are a
+//! reserved word missing its underscore and loops cut short part way through a row by a keyword
+//! or data name that was probably meant as a value. `diagnose` finds these in a parsed tree and the text
+//! it was parsed from, `parse_with_diagnostics` reports them alongside the result of `parse`.
+
+use crate::line_column_index::{LineColumn, LineColumnIndex};
+use crate::mutable_pair::MutablePair;
+use crate::values::collides_with_keyword;
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// Worth knowing, the input is read as intended
+    Info,
+    /// Probably a mistake, or read differently by other STAR readers
+    Warning,
+}
+
+impl Severity {
+    /// The severity as written in a report, e.g. `warning`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The conditions reported by `diagnose`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiagnosticCode {
+    /// Some lines end with CR LF and others with LF
+    MixedLineEndings,
+    /// A quoted string contains a tab
+    TabInQuotedString,
+    /// A loop without `stop_` is ended by the `save_` of a save frame
+    LoopTerminatedBySave,
+    /// An unquoted value such as `stop` is a reserved word without its underscore
+    ReservedWordValue,
+    /// A loop without `stop_` ends part way through a row at a keyword or data name, such as
+    /// an unquoted `global_` or `_x` meant as a value
+    KeywordEndsLoopRow,
+}
+
+impl DiagnosticCode {
+    /// The stable code of the condition, e.g. `W001`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::MixedLineEndings => "W001",
+            DiagnosticCode::TabInQuotedString => "W002",
+            DiagnosticCode::LoopTerminatedBySave => "W003",
+            DiagnosticCode::ReservedWordValue => "W004",
+            DiagnosticCode::KeywordEndsLoopRow => "W005",
+        }
+    }
+
+    /// How serious the condition is
+    pub fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A condition in parsed input that is worth reporting but doesn't stop the parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    /// How serious the condition is
+    pub severity: Severity,
+    /// What the condition is
+    pub code: DiagnosticCode,
+    /// Description of this occurrence
+    pub message: String,
+    /// Where the offending text starts
+    pub start: LineColumn,
+    /// Where the offending text ends
+    pub end: LineColumn,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "l{}:c{} {}[{}] {}",
+            self.start.line, self.start.column, self.severity, self.code, self.message
+        )
+    }
+}
+
+/// Rules of strings delimited by quotes
+const QUOTED_STRINGS: [&str; 6] = [
+    "single_quote_string",
+    "double_quote_string",
+    "triple_single_quote_string",
+    "triple_double_quote_string",
+    "container_single_quote_string",
+    "container_double_quote_string",
+];
+
+/// Reserved words, without the underscore that makes them keywords
+const RESERVED_WORDS: [&str; 5] = ["data", "loop", "global", "save", "stop"];
+
+/// A diagnostic before its offsets are turned into positions (private)
+type Found = (usize, usize, DiagnosticCode, String);
+
+/// Find the conditions worth a warning in the text of `line_index` and the tree parsed from it
+///
+/// Without a tree, for input that failed to parse, only the text is checked. Positions are
+/// counted in the column unit of `line_index`.
+///
+/// # Returns
+/// * `Vec<Diagnostic>` - Every diagnostic found, in the order of their positions in the input
+pub fn diagnose(tree: Option<&MutablePair>, line_index: &LineColumnIndex) -> Vec<Diagnostic> {
+    let mut found = Vec::new();
+    let input = line_index.input();
+    check_line_endings(input, &mut found);
+    if let Some(tree) = tree {
+        for pair in std::iter::once(tree).chain(tree.iter_descendants()) {
+            check_pair(pair, &mut found);
+            if pair.rule_name() == "data_loop" {
+                check_loop_end(pair, input, &mut found);
+            }
+        }
+    }
+
+    found.sort_by_key(|(start, ..)| *start);
+    found
+        .into_iter()
+        .map(|(start, end, code, message)| Diagnostic {
+            severity: code.severity(),
+            code,
+            message,
+            start: line_index.offset_to_line_col(start),
+            end: line_index.offset_to_line_col(end),
+        })
+        .collect()
+}
+
+/// Report the first line ending that differs from the style of the first line (private)
+fn check_line_endings(input: &str, found: &mut Vec<Found>) {
+    let bytes = input.as_bytes();
+    let mut first_style = None;
+    for newline in memchr::memchr_iter(b'\n', bytes) {
+        let crlf = newline > 0 && bytes[newline - 1] == b'\r';
+        match first_style {
+            None => first_style = Some(crlf),
+            Some(style) if style != crlf => {
+                let (start, this, earlier) = if crlf {
+                    (newline - 1, "CR LF", "LF")
+                } else {
+                    (newline, "LF", "CR LF")
+                };
+                found.push((
+                    start,
+                    newline + 1,
+                    DiagnosticCode::MixedLineEndings,
+                    format!("line ends with {this} but earlier lines end with {earlier}"),
+                ));
+                return;
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Check a single node of the tree (private)
+fn check_pair(pair: &MutablePair, found: &mut Vec<Found>) {
+    let rule_name = pair.rule_name();
+    if QUOTED_STRINGS.contains(&rule_name) {
+        if let Some(tab) = pair.as_str().find('\t') {
+            let offset = pair.start + tab;
+            found.push((
+                offset,
+                offset + 1,
+                DiagnosticCode::TabInQuotedString,
+                "quoted string contains a tab".to_string(),
+            ));
+        }
+    } else if matches!(rule_name, "data" | "data_loop_values") {
+        // decomposing strings renames unquoted values to string, quoted values keep their rule
+        for value in pair
+            .children()
+            .iter()
+            .filter(|child| matches!(child.rule_name(), "non_quoted_string" | "string"))
+        {
+            let text = value.as_str();
+            if let Some(word) = RESERVED_WORDS
+                .iter()
+                .find(|word| text.eq_ignore_ascii_case(word))
+            {
+                found.push((
+                    value.start,
+                    value.end,
+                    DiagnosticCode::ReservedWordValue,
+                    format!("unquoted value {text} looks like the reserved word {word}_"),
+                ));
+            }
+        }
+    } else if matches!(rule_name, "data_block" | "save_frame") {
+        for siblings in pair.children().windows(2) {
+            if !is_loop_without_stop(&siblings[0]) {
+                continue;
+            }
+            let save = match siblings[1].rule_name() {
+                "save_keyword" => &siblings[1],
+                "save_frame" => &siblings[1].children()[0],
+                _ => continue,
+            };
+            found.push((
+                save.start,
+                save.start + "save_".len(),
+                DiagnosticCode::LoopTerminatedBySave,
+                "loop is terminated by save_ rather than stop_".to_string(),
+            ));
+        }
+    }
+}
+
+/// Report the keyword or data name that ends a loop without `stop_` part way through a row
+/// (private)
+///
+/// The grammar reads a value that collides with a keyword as the end of the loop, so the loop
+/// loses the rest of the row and the text after it is read as something else. Loops with
+/// nested loops are skipped as their rows have no fixed number of values.
+fn check_loop_end(data_loop: &MutablePair, input: &str, found: &mut Vec<Found>) {
+    let (Some(definition), Some(values)) = (
+        data_loop.find_first("data_loop_definition"),
+        data_loop.find_first("data_loop_values"),
+    ) else {
+        return;
+    };
+    let definition = definition.children();
+    if definition
+        .iter()
+        .any(|field| field.rule_name() == "nested_loop")
+    {
+        return;
+    }
+    let values: Vec<&MutablePair> = values
+        .children()
+        .iter()
+        .filter(|child| child.rule_name() != "comment")
+        .collect();
+    let tags = definition
+        .iter()
+        .filter(|field| field.rule_name() == "data_name")
+        .count();
+    if values
+        .last()
+        .is_none_or(|last| last.rule_name() == "stop_keyword")
+        || values.len() % tags == 0
+    {
+        return;
+    }
+
+    let Some((start, token)) = next_token(input, data_loop.end) else {
+        return;
+    };
+    if collides_with_keyword(token) {
+        found.push((
+            start,
+            start + token.len(),
+            DiagnosticCode::KeywordEndsLoopRow,
+            format!("{token} ends the loop part way through a row, quote it if it is a value"),
+        ));
+    }
+}
+
+/// The offset and text of the first token at or after `offset`, skipping blanks and comments
+/// (private)
+fn next_token(input: &str, mut offset: usize) -> Option<(usize, &str)> {
+    loop {
+        let rest = &input[offset..];
+        let token_start = offset + (rest.len() - rest.trim_start().len());
+        let rest = &input[token_start..];
+        if rest.starts_with('#') {
+            offset = token_start + rest.find('\n')?;
+            continue;
+        }
+        let token = rest.split(char::is_whitespace).next()?;
+        return (!token.is_empty()).then_some((token_start, token));
+    }
+}
+
+/// Check if `data` holds a loop whose values don't end with `stop_` (private)
+fn is_loop_without_stop(data: &MutablePair) -> bool {
+    let Some(data_loop) = data.children().first() else {
+        return false;
+    };
+    if data.rule_name() != "data" || data_loop.rule_name() != "data_loop" {
+        return false;
+    }
+    data_loop
+        .find_first("data_loop_values")
+        .and_then(|values| {
+            values
+                .children()
+                .iter()
+                .rfind(|child| child.rule_name() != "comment")
+        })
+        .is_some_and(|last| last.rule_name() != "stop_keyword")
+}
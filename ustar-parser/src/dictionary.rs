@@ -0,0 +1,471 @@
+//! Dictionaries - read DDL2 dictionaries and validate parsed trees against them.
+//!
+//! A DDL2 dictionary, such as those of mmCIF and NEF, defines every data name of its files in a
+//! save frame: the category it belongs to, whether it is mandatory and the code of its type,
+//! listed with the type's primitive (`char`, `uchar` or `numb`) in the `_item_type_list` loop.
+//! Items may also list the values they take in an `_item_enumeration.value` loop.
+//!
+//! Only the types with a common notation are checked: `int`, `float` and the date types whose
+//! codes start with `yyyy-mm-dd`. The values of every other type are text, and `.` and `?` are
+//! accepted for any item.
+
+use crate::mutable_pair::MutablePair;
+use crate::sas_handlers::{Document, DocumentBuilderHandler, Loop, Saveframe};
+use crate::validate::{ValidationIssue, ValidationIssueKind};
+use crate::values::{parse_number, StarValue};
+use crate::{ConfigKey, ConfigValue, EncodingMode, UstarError};
+use std::collections::{BTreeMap, HashSet};
+
+/// The primitive type of an item type, from `_item_type_list.primitive_code`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Primitive {
+    /// Case sensitive text
+    Char,
+    /// Case insensitive text
+    Uchar,
+    /// A number
+    Numb,
+}
+
+/// How the values of an item type are checked
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum ValueKind {
+    /// An integer, as the type `int`
+    Int,
+    /// Any number, as the type `float`
+    Float,
+    /// A date `yyyy-mm-dd` with an optional time after `:` or `T`
+    Date,
+    /// Anything, every other type
+    Text,
+}
+
+/// An item type of the `_item_type_list` loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemType {
+    /// The type code, as `float`
+    pub code: String,
+    pub primitive: Primitive,
+    /// How values of the type are checked, found from its code
+    pub kind: ValueKind,
+}
+
+/// A category defined by a save frame with `_category.id`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Category {
+    pub id: String,
+    /// Whether `_category.mandatory_code` is `yes`
+    pub mandatory: bool,
+    /// The data names of `_category_key.name`
+    pub keys: Vec<String>,
+}
+
+/// An item defined by a save frame with `_item.name`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DictionaryItem {
+    /// The data name, as `_nef_sequence.index`
+    pub name: String,
+    pub category_id: String,
+    /// Whether `_item.mandatory_code` is `yes`
+    pub mandatory: bool,
+    /// The code of the item's type in `Dictionary::types`, empty if the item has none
+    pub type_code: String,
+    /// The values of `_item_enumeration.value`, empty if the item takes any value
+    pub enumeration: Vec<String>,
+}
+
+/// The categories, items and types of a DDL2 dictionary
+///
+/// Every map is keyed by the lower case name, so look ups are case-insensitive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    pub categories: BTreeMap<String, Category>,
+    pub items: BTreeMap<String, DictionaryItem>,
+    pub types: BTreeMap<String, ItemType>,
+}
+
+impl ValueKind {
+    /// The kind of the type with `code`
+    fn from_code(code: &str) -> ValueKind {
+        match code.to_lowercase().as_str() {
+            "int" => ValueKind::Int,
+            "float" => ValueKind::Float,
+            date if date.starts_with("yyyy-mm-dd") => ValueKind::Date,
+            _ => ValueKind::Text,
+        }
+    }
+
+    /// Check if `text` is written as a value of this kind
+    fn accepts(&self, text: &str) -> bool {
+        match self {
+            ValueKind::Int => matches!(parse_number(text), Some(StarValue::Int(_))),
+            ValueKind::Float => parse_number(text).is_some(),
+            ValueKind::Date => is_date(text),
+            ValueKind::Text => true,
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ValueKind::Int => "an integer",
+            ValueKind::Float => "a number",
+            ValueKind::Date => "a date",
+            ValueKind::Text => "text",
+        }
+    }
+}
+
+impl Dictionary {
+    /// Parse the text of a DDL2 dictionary
+    ///
+    /// Dictionaries are read as Unicode, their descriptions often hold more than ASCII.
+    pub fn parse(input: &str) -> Result<Dictionary, Box<UstarError>> {
+        let mut config = crate::default_config();
+        config.insert(
+            ConfigKey::Encoding,
+            ConfigValue::Encoding(EncodingMode::Unicode),
+        );
+        let mut builder = DocumentBuilderHandler::new();
+        crate::walk(input, &config, &mut builder)?;
+        Ok(Dictionary::from_document(&builder.into_document()))
+    }
+
+    /// Collect the definitions of the save frames and type lists of every data block of a
+    /// dictionary
+    pub fn from_document(document: &Document) -> Dictionary {
+        let mut dictionary = Dictionary::default();
+        for block in &document.data_blocks {
+            for type_row in column_rows(
+                &block.loops,
+                &["_item_type_list.code", "_item_type_list.primitive_code"],
+            ) {
+                let [code, primitive] = [type_row[0], type_row[1]];
+                let primitive = match primitive.to_lowercase().as_str() {
+                    "uchar" => Primitive::Uchar,
+                    "numb" => Primitive::Numb,
+                    _ => Primitive::Char,
+                };
+                dictionary.types.insert(
+                    code.to_lowercase(),
+                    ItemType {
+                        code: code.to_string(),
+                        primitive,
+                        kind: ValueKind::from_code(code),
+                    },
+                );
+            }
+            for frame in &block.saveframes {
+                dictionary.add_saveframe(frame);
+            }
+        }
+        dictionary
+    }
+
+    /// Add the category or items defined by a save frame
+    fn add_saveframe(&mut self, frame: &Saveframe) {
+        if let Some(id) = frame.item("_category.id") {
+            self.categories.insert(
+                id.to_lowercase(),
+                Category {
+                    id: id.to_string(),
+                    mandatory: is_yes(frame.item("_category.mandatory_code")),
+                    keys: frame_values(frame, "_category_key.name"),
+                },
+            );
+        }
+
+        let type_code = frame.item("_item_type.code").unwrap_or_default();
+        let enumeration = frame_values(frame, "_item_enumeration.value");
+        let names = frame_values(frame, "_item.name");
+        let category_ids = frame_values(frame, "_item.category_id");
+        let mandatory_codes = frame_values(frame, "_item.mandatory_code");
+        // an item loop defines related items in one frame, sharing the frame's type
+        for (index, name) in names.iter().enumerate() {
+            let category_id = category_ids.get(index).cloned().unwrap_or_else(|| {
+                // the category is the data name up to its period
+                let name = name.trim_start_matches('_');
+                name.split_once('.')
+                    .map_or(name, |(category, _)| category)
+                    .to_string()
+            });
+            self.items.insert(
+                name.to_lowercase(),
+                DictionaryItem {
+                    name: name.clone(),
+                    category_id,
+                    mandatory: is_yes(mandatory_codes.get(index).map(String::as_str)),
+                    type_code: type_code.to_string(),
+                    enumeration: enumeration.clone(),
+                },
+            );
+        }
+    }
+
+    /// The item defining `name`
+    pub fn item(&self, name: &str) -> Option<&DictionaryItem> {
+        self.items.get(&name.to_lowercase())
+    }
+
+    /// The category with `id`
+    pub fn category(&self, id: &str) -> Option<&Category> {
+        self.categories.get(&id.to_lowercase())
+    }
+
+    /// The type of `item`, `None` if the dictionary doesn't list its type code
+    pub fn item_type(&self, item: &DictionaryItem) -> Option<&ItemType> {
+        self.types.get(&item.type_code.to_lowercase())
+    }
+
+    /// The mandatory items of the category with `id`, sorted by name
+    pub fn mandatory_items<'d>(&'d self, id: &'d str) -> impl Iterator<Item = &'d DictionaryItem> {
+        self.items
+            .values()
+            .filter(move |item| item.mandatory && item.category_id.eq_ignore_ascii_case(id))
+    }
+}
+
+/// Check whether a mandatory code is `yes`
+fn is_yes(code: Option<&str>) -> bool {
+    code.is_some_and(|code| code.eq_ignore_ascii_case("yes"))
+}
+
+/// The values of `tag` in a save frame, one for an item or one per row of a loop column
+fn frame_values(frame: &Saveframe, tag: &str) -> Vec<String> {
+    match frame.item(tag) {
+        Some(value) => vec![value.to_string()],
+        None => column_rows(&frame.loops, &[tag])
+            .into_iter()
+            .map(|row| row[0].to_string())
+            .collect(),
+    }
+}
+
+/// The values of the `tags` columns in each row of the first loop having them all
+fn column_rows<'l>(loops: &'l [Loop], tags: &[&str]) -> Vec<Vec<&'l str>> {
+    for star_loop in loops {
+        let Some(outer_tags) = star_loop.tags.first() else {
+            continue;
+        };
+        let columns: Option<Vec<usize>> = tags
+            .iter()
+            .map(|tag| outer_tags.iter().position(|t| t.eq_ignore_ascii_case(tag)))
+            .collect();
+        if let Some(columns) = columns {
+            return star_loop
+                .rows
+                .iter()
+                .filter_map(|row| {
+                    columns
+                        .iter()
+                        .map(|&column| row.values.get(column).map(String::as_str))
+                        .collect()
+                })
+                .collect();
+        }
+    }
+    Vec::new()
+}
+
+/// Check if `text` is a date `yyyy-mm-dd`, with a year of 2 to 4 digits, optional month and day
+/// of 1 or 2 digits and an optional time of digits, `:` and `.` after `:` or `T`
+fn is_date(text: &str) -> bool {
+    let (date, time) = match text.split_once([':', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let is_digits = |part: &str, lengths: std::ops::RangeInclusive<usize>| {
+        lengths.contains(&part.len()) && part.bytes().all(|b| b.is_ascii_digit())
+    };
+    let mut parts = date.split('-');
+    let year = parts.next().is_some_and(|year| is_digits(year, 2..=4));
+    let parts: Vec<&str> = parts.collect();
+    year && parts.len() <= 2
+        && parts.iter().all(|part| is_digits(part, 1..=2))
+        && time.is_none_or(|time| {
+            !time.is_empty()
+                && time
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || b == b':' || b == b'.')
+        })
+}
+
+/// Check a parsed tree against a dictionary
+///
+/// Each data block, outside its save frames, and each save frame is checked on its own: every
+/// data name must be defined by the dictionary, values other than `.` and `?` must match the type
+/// and any enumeration of their item, and every mandatory item of the categories used must be
+/// present. Enumerations of `uchar` types are compared case-insensitively. A missing item is
+/// reported at the first data name of its category.
+///
+/// # Returns
+/// * `Vec<ValidationIssue>` - Every issue found, in the order of their positions in the input
+pub fn validate_against(dictionary: &Dictionary, tree: &MutablePair) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let is_block = |pair: &&MutablePair| matches!(pair.rule_name(), "data_block" | "global_block");
+    for block in std::iter::once(tree)
+        .chain(tree.iter_descendants())
+        .filter(is_block)
+    {
+        check_scope(dictionary, children(block, "data"), &mut issues);
+        for frame in children(block, "save_frame") {
+            check_scope(dictionary, children(frame, "data"), &mut issues);
+        }
+    }
+    issues.sort_by_key(|(offset, _)| *offset);
+    issues.into_iter().map(|(_, issue)| issue).collect()
+}
+
+/// The children of `pair` with `rule_name`
+fn children<'p>(
+    pair: &'p MutablePair,
+    rule_name: &'p str,
+) -> impl Iterator<Item = &'p MutablePair> {
+    pair.children()
+        .iter()
+        .filter(move |child| child.rule_name() == rule_name)
+}
+
+/// Check the data items and loops of one data block or save frame
+fn check_scope<'p>(
+    dictionary: &Dictionary,
+    data: impl Iterator<Item = &'p MutablePair>,
+    issues: &mut Vec<(usize, ValidationIssue)>,
+) {
+    let mut present = HashSet::new();
+    // the categories used, in order, with their first data name
+    let mut categories: Vec<(&str, &MutablePair)> = Vec::new();
+
+    for (data_name, values) in data.flat_map(tag_values) {
+        present.insert(data_name.as_str().to_lowercase());
+        let Some(item) = dictionary.item(data_name.as_str()) else {
+            issues.push(issue(
+                ValidationIssueKind::UnknownTag,
+                data_name.as_str(),
+                data_name,
+                None,
+            ));
+            continue;
+        };
+        if !categories
+            .iter()
+            .any(|(id, _)| id.eq_ignore_ascii_case(&item.category_id))
+        {
+            categories.push((&item.category_id, data_name));
+        }
+        for value in values {
+            if let Some((kind, detail)) = check_value(dictionary, item, value) {
+                issues.push(issue(kind, data_name.as_str(), value, Some(detail)));
+            }
+        }
+    }
+
+    for (id, first) in categories {
+        for item in dictionary.mandatory_items(id) {
+            if !present.contains(&item.name.to_lowercase()) {
+                issues.push(issue(
+                    ValidationIssueKind::MissingMandatoryItem,
+                    &item.name,
+                    first,
+                    Some(id.to_string()),
+                ));
+            }
+        }
+    }
+}
+
+/// The data names of a data item or loop with their value nodes, loops with nested loops
+/// give no values
+fn tag_values(data: &MutablePair) -> Vec<(&MutablePair, Vec<&MutablePair>)> {
+    if let [name, value] = data.children() {
+        if name.rule_name() == "data_name" {
+            return vec![(name, vec![value])];
+        }
+    }
+    let Some(definition) = data.find_first("data_loop_definition") else {
+        return Vec::new();
+    };
+    let names = definition
+        .children()
+        .iter()
+        .filter(|child| child.rule_name() == "data_name");
+    if definition.find_first("nested_loop").is_some() {
+        return names.map(|name| (name, Vec::new())).collect();
+    }
+    let names: Vec<&MutablePair> = names.collect();
+    let mut columns = vec![Vec::new(); names.len()];
+    if let Some(loop_values) = data.find_first("data_loop_values") {
+        let values = loop_values
+            .children()
+            .iter()
+            .filter(|value| !matches!(value.rule_name(), "stop_keyword" | "comment"));
+        for (index, value) in values.enumerate() {
+            columns[index % names.len()].push(value);
+        }
+    }
+    names.into_iter().zip(columns).collect()
+}
+
+/// The issue with a value of `item`, with what was expected and found, if it has one
+fn check_value(
+    dictionary: &Dictionary,
+    item: &DictionaryItem,
+    value: &MutablePair,
+) -> Option<(ValidationIssueKind, String)> {
+    let text = match StarValue::from_pair(value) {
+        StarValue::Null | StarValue::Unknown => return None,
+        StarValue::Text(text) => text,
+        _ => value.as_str().to_string(),
+    };
+    let item_type = dictionary.item_type(item);
+    let kind = item_type.map_or(ValueKind::from_code(&item.type_code), |t| t.kind);
+    if !kind.accepts(&text) {
+        return Some((
+            ValidationIssueKind::TypeMismatch,
+            format!(
+                "expected {} of type {} but found {}",
+                kind.description(),
+                item.type_code,
+                text
+            ),
+        ));
+    }
+
+    let ignore_case = item_type.is_some_and(|t| t.primitive == Primitive::Uchar);
+    let enumerated = item.enumeration.iter().any(|allowed| {
+        if ignore_case {
+            allowed.eq_ignore_ascii_case(&text)
+        } else {
+            *allowed == text
+        }
+    });
+    if !item.enumeration.is_empty() && !enumerated {
+        return Some((
+            ValidationIssueKind::EnumerationViolation,
+            format!(
+                "expected one of {} but found {}",
+                item.enumeration.join(", "),
+                text
+            ),
+        ));
+    }
+    None
+}
+
+/// An issue of `kind` for `name` at `pair`, keyed by the byte offset of `pair`
+fn issue(
+    kind: ValidationIssueKind,
+    name: &str,
+    pair: &MutablePair,
+    detail: Option<String>,
+) -> (usize, ValidationIssue) {
+    (
+        pair.start_pos(),
+        ValidationIssue {
+            kind,
+            name: name.to_string(),
+            position: pair.start_position,
+            first_position: None,
+            detail,
+        },
+    )
+}
@@ -0,0 +1,493 @@
+//! Diff - comparing two parsed STAR documents by their content rather than their text.
+//!
+//! `diff_documents` matches data blocks by name, save frames by frame code, data items by data
+//! name and loops by the data names they share, all case-insensitively, so layout, comments,
+//! quoting and the order of blocks, save frames and items make no difference. Loop rows are
+//! matched by their position, or by the values of key columns given to `DiffOptions`, so rows
+//! in another order or with rows inserted before them compare equal.
+//!
+//! Each difference is a `DiffEntry` naming what was added, removed or changed with a STAR path
+//! as the `query` module reads them, such as `data_nef/save_sequence/_nef_sequence.index[3]`,
+//! the row counting from 1 in its table. In each block or save frame the entries of save
+//! frames come first, then those of data items and then of loops, each in the order of the
+//! first document followed by what only the second has.
+
+use crate::line_column_index::LineColumn;
+use crate::loop_table::LoopTable;
+use crate::mutable_pair::MutablePair;
+use crate::values::value_text;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How `diff_documents` matches loop rows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Sets of key columns, lower case
+    keys: Vec<Vec<String>>,
+}
+
+impl DiffOptions {
+    /// Match the rows of loops with all of the data names `columns` in both documents by their
+    /// values in those columns instead of by position
+    ///
+    /// The first set of key columns a loop has is used.
+    pub fn with_key_columns(mut self, columns: &[&str]) -> Self {
+        self.keys.push(
+            columns
+                .iter()
+                .map(|column| column.to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// The indexes of the key columns of `a` and `b` for the first set of key columns both
+    /// have
+    fn key_columns(&self, a: &LoopTable, b: &LoopTable) -> Option<(Vec<usize>, Vec<usize>)> {
+        self.keys.iter().find_map(|keys| {
+            let indexes = |table: &LoopTable| -> Option<Vec<usize>> {
+                keys.iter().map(|key| column_index(table, key)).collect()
+            };
+            Some((indexes(a)?, indexes(b)?))
+        })
+    }
+}
+
+/// What a `DiffEntry` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    /// Only in the second document
+    Added,
+    /// Only in the first document
+    Removed,
+    /// In both with different values
+    Changed,
+}
+
+impl DiffKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffKind::Added => "added",
+            DiffKind::Removed => "removed",
+            DiffKind::Changed => "changed",
+        }
+    }
+}
+
+/// A difference between two documents
+///
+/// The old value and position are those in the first document, the new in the second. Blocks,
+/// save frames and loop columns have no value, loop rows the values of the row separated by
+/// spaces and loops that can't be tabulated all their values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub kind: DiffKind,
+    /// The STAR path of the block, save frame, data item, loop column, row or value
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    pub old_position: Option<LineColumn>,
+    pub new_position: Option<LineColumn>,
+}
+
+impl fmt::Display for DiffEntry {
+    /// One line such as `~ data_a/_tag 1 -> 2 (l3:c7, l3:c7)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.kind {
+            DiffKind::Added => '+',
+            DiffKind::Removed => '-',
+            DiffKind::Changed => '~',
+        };
+        write!(f, "{} {}", sign, self.path)?;
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, " {} -> {}", old, new)?,
+            (Some(value), None) | (None, Some(value)) => write!(f, " {}", value)?,
+            (None, None) => {}
+        }
+        let positions: Vec<String> = [self.old_position, self.new_position]
+            .into_iter()
+            .flatten()
+            .map(|position| format!("l{}:c{}", position.line, position.column))
+            .collect();
+        write!(f, " ({})", positions.join(", "))
+    }
+}
+
+/// The differences between `a` and `b`, two parsed files, data blocks or save frames
+pub fn diff_documents(a: &MutablePair, b: &MutablePair, options: DiffOptions) -> Vec<DiffEntry> {
+    let mut differ = Differ {
+        options,
+        entries: Vec::new(),
+    };
+    match (a.rule_name(), b.rule_name()) {
+        ("star_file", "star_file") => differ.diff_scopes(a, b, "", is_block, block_name),
+        _ => differ.diff_scope(a, b, ""),
+    }
+    differ.entries
+}
+
+/// The state of a diff of two documents
+struct Differ {
+    options: DiffOptions,
+    entries: Vec<DiffEntry>,
+}
+
+impl Differ {
+    fn push(
+        &mut self,
+        kind: DiffKind,
+        path: String,
+        old: Option<(Option<String>, LineColumn)>,
+        new: Option<(Option<String>, LineColumn)>,
+    ) {
+        let (old, old_position) = old.map_or((None, None), |(value, at)| (value, Some(at)));
+        let (new, new_position) = new.map_or((None, None), |(value, at)| (value, Some(at)));
+        self.entries.push(DiffEntry {
+            kind,
+            path,
+            old,
+            new,
+            old_position,
+            new_position,
+        });
+    }
+
+    /// Compare the blocks or save frames, those children `is_scope` accepts, of `a` and `b`,
+    /// matching them by `name`
+    fn diff_scopes(
+        &mut self,
+        a: &MutablePair,
+        b: &MutablePair,
+        path: &str,
+        is_scope: fn(&MutablePair) -> bool,
+        name: fn(&MutablePair) -> &str,
+    ) {
+        let b_scopes: Vec<&MutablePair> = b.children().iter().filter(|c| is_scope(c)).collect();
+        let mut matched = vec![false; b_scopes.len()];
+        for scope in a.children().iter().filter(|child| is_scope(child)) {
+            let path = join(path, heading(scope));
+            let found = b_scopes.iter().enumerate().position(|(index, other)| {
+                !matched[index]
+                    && other.rule_name() == scope.rule_name()
+                    && name(other).eq_ignore_ascii_case(name(scope))
+            });
+            match found {
+                Some(index) => {
+                    matched[index] = true;
+                    self.diff_scope(scope, b_scopes[index], &path);
+                }
+                None => self.push(
+                    DiffKind::Removed,
+                    path,
+                    Some((None, scope.start_position)),
+                    None,
+                ),
+            }
+        }
+        for (scope, _) in b_scopes.iter().zip(matched).filter(|(_, matched)| !matched) {
+            self.push(
+                DiffKind::Added,
+                join(path, heading(scope)),
+                None,
+                Some((None, scope.start_position)),
+            );
+        }
+    }
+
+    /// Compare the save frames, data items and loops of two blocks or save frames
+    fn diff_scope(&mut self, a: &MutablePair, b: &MutablePair, path: &str) {
+        self.diff_scopes(a, b, path, is_frame, frame_name);
+        self.diff_items(a, b, path);
+        self.diff_loops(a, b, path);
+    }
+
+    /// Compare the data items of two blocks or save frames by data name
+    fn diff_items(&mut self, a: &MutablePair, b: &MutablePair, path: &str) {
+        let b_items: Vec<(&MutablePair, &MutablePair)> = items(b).collect();
+        let mut matched = vec![false; b_items.len()];
+        for (name, value) in items(a) {
+            let tag_path = join(path, name.as_str());
+            let old = Some((Some(value_text(value).to_string()), name.start_position));
+            let found = b_items
+                .iter()
+                .position(|(other, _)| other.as_str().eq_ignore_ascii_case(name.as_str()));
+            let Some(index) = found else {
+                self.push(DiffKind::Removed, tag_path, old, None);
+                continue;
+            };
+            matched[index] = true;
+            let (other, other_value) = b_items[index];
+            if value_text(value) != value_text(other_value) {
+                let new = Some((
+                    Some(value_text(other_value).to_string()),
+                    other.start_position,
+                ));
+                self.push(DiffKind::Changed, tag_path, old, new);
+            }
+        }
+        for ((name, value), _) in b_items.iter().zip(matched).filter(|(_, matched)| !matched) {
+            let new = Some((Some(value_text(*value).to_string()), name.start_position));
+            self.push(DiffKind::Added, join(path, name.as_str()), None, new);
+        }
+    }
+
+    /// Compare the loops of two blocks or save frames, matching those sharing a data name
+    fn diff_loops(&mut self, a: &MutablePair, b: &MutablePair, path: &str) {
+        let b_loops: Vec<&MutablePair> = loops(b).collect();
+        let mut matched = vec![false; b_loops.len()];
+        for data in loops(a) {
+            let names = loop_names(data);
+            let found = b_loops.iter().enumerate().position(|(index, other)| {
+                !matched[index]
+                    && loop_names(other)
+                        .iter()
+                        .any(|tag| names.iter().any(|name| name.eq_ignore_ascii_case(tag)))
+            });
+            match found {
+                Some(index) => {
+                    matched[index] = true;
+                    self.diff_loop(data, b_loops[index], path);
+                }
+                None => self.push(
+                    DiffKind::Removed,
+                    join(path, names[0]),
+                    Some((Some(loop_heading(&names)), data.start_position)),
+                    None,
+                ),
+            }
+        }
+        for (data, _) in b_loops.iter().zip(matched).filter(|(_, matched)| !matched) {
+            let names = loop_names(data);
+            self.push(
+                DiffKind::Added,
+                join(path, names[0]),
+                None,
+                Some((Some(loop_heading(&names)), data.start_position)),
+            );
+        }
+    }
+
+    /// Compare two matched loops, as a whole if either is ragged
+    fn diff_loop(&mut self, a: &MutablePair, b: &MutablePair, path: &str) {
+        match (LoopTable::from_pair(a), LoopTable::from_pair(b)) {
+            (Ok(a), Ok(b)) => self.diff_table(&a, &b, path),
+            _ => {
+                let (old, new) = (loop_values(a), loop_values(b));
+                if old != new {
+                    self.push(
+                        DiffKind::Changed,
+                        join(path, loop_names(a)[0]),
+                        Some((Some(old), a.start_position)),
+                        Some((Some(new), b.start_position)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Compare the columns and rows of one level of two matched loops
+    fn diff_table(&mut self, a: &LoopTable, b: &LoopTable, path: &str) {
+        for column in a.columns() {
+            if column_index(b, column).is_none() {
+                self.push(
+                    DiffKind::Removed,
+                    join(path, column),
+                    Some((None, a.position())),
+                    None,
+                );
+            }
+        }
+        for column in b.columns() {
+            if column_index(a, column).is_none() {
+                self.push(
+                    DiffKind::Added,
+                    join(path, column),
+                    None,
+                    Some((None, b.position())),
+                );
+            }
+        }
+
+        let keys = self.options.key_columns(a, b);
+        let b_rows: HashMap<Vec<&str>, usize> = match &keys {
+            Some((_, b_keys)) => (0..b.row_count())
+                .rev()
+                .map(|row| (row_key(b, row, b_keys), row))
+                .collect(),
+            None => HashMap::new(),
+        };
+        let mut matched = vec![false; b.row_count()];
+        for row in 0..a.row_count() {
+            let found = match &keys {
+                Some((a_keys, _)) => b_rows.get(&row_key(a, row, a_keys)).copied(),
+                None => (row < b.row_count()).then_some(row),
+            };
+            match found.filter(|&other| !matched[other]) {
+                Some(other) => {
+                    matched[other] = true;
+                    self.diff_row(a, row, b, other, path);
+                }
+                None => self.push(
+                    DiffKind::Removed,
+                    row_path(a, row, path),
+                    Some((Some(row_text(a, row)), row_position(a, row))),
+                    None,
+                ),
+            }
+        }
+        for row in (0..b.row_count()).filter(|&row| !matched[row]) {
+            self.push(
+                DiffKind::Added,
+                row_path(b, row, path),
+                None,
+                Some((Some(row_text(b, row)), row_position(b, row))),
+            );
+        }
+    }
+
+    /// Compare the values of the shared columns of two matched rows, and their nested rows
+    fn diff_row(&mut self, a: &LoopTable, row: usize, b: &LoopTable, other: usize, path: &str) {
+        for (column, name) in a.columns().iter().enumerate() {
+            let Some(other_column) = column_index(b, name) else {
+                continue;
+            };
+            let (Some(old), Some(new)) = (a.value(row, column), b.value(other, other_column))
+            else {
+                continue;
+            };
+            if old != new {
+                self.push(
+                    DiffKind::Changed,
+                    format!("{}[{}]", join(path, name), row + 1),
+                    a.value_position(row, column)
+                        .map(|at| (Some(old.to_string()), at)),
+                    b.value_position(other, other_column)
+                        .map(|at| (Some(new.to_string()), at)),
+                );
+            }
+        }
+        if let (Some(a), Some(b)) = (a.nested(row), b.nested(other)) {
+            self.diff_table(a, b, path);
+        }
+    }
+}
+
+/// `name` below `path`
+fn join(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_string(),
+        _ => format!("{}/{}", path, name),
+    }
+}
+
+fn is_block(node: &MutablePair) -> bool {
+    matches!(node.rule_name(), "data_block" | "global_block")
+}
+
+fn is_frame(node: &MutablePair) -> bool {
+    node.rule_name() == "save_frame"
+}
+
+/// The heading of a block or save frame, such as `data_nef` or `global_`
+fn heading(scope: &MutablePair) -> &str {
+    scope.children()[0].as_str()
+}
+
+/// The name of a data block without `data_`, empty for a global block
+fn block_name(block: &MutablePair) -> &str {
+    match block.rule_name() {
+        "data_block" => &heading(block)["data_".len()..],
+        _ => "",
+    }
+}
+
+/// The frame code of a save frame without `save_`
+fn frame_name(frame: &MutablePair) -> &str {
+    &heading(frame)["save_".len()..]
+}
+
+/// The data name and value nodes of the data items of a block or save frame
+fn items(scope: &MutablePair) -> impl Iterator<Item = (&MutablePair, &MutablePair)> {
+    scope
+        .children()
+        .iter()
+        .filter(|child| child.rule_name() == "data")
+        .filter_map(|data| match data.children() {
+            [name, value] if name.rule_name() == "data_name" => Some((name, value)),
+            _ => None,
+        })
+}
+
+/// The `data` nodes of the loops of a block or save frame
+fn loops(scope: &MutablePair) -> impl Iterator<Item = &MutablePair> {
+    scope.children().iter().filter(|child| {
+        child.rule_name() == "data"
+            && child
+                .children()
+                .first()
+                .is_some_and(|first| first.rule_name() == "data_loop")
+    })
+}
+
+/// The data names of a loop, including those of nested levels
+fn loop_names(data: &MutablePair) -> Vec<&str> {
+    data.find_first("data_loop_definition")
+        .map(|definition| {
+            definition
+                .find_all("data_name")
+                .into_iter()
+                .map(|name| name.as_str())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The value of a loop added or removed as a whole, `loop_` and its data names
+fn loop_heading(names: &[&str]) -> String {
+    format!("loop_ {}", names.join(" "))
+}
+
+/// The values of a loop separated by spaces
+fn loop_values(data: &MutablePair) -> String {
+    data.find_first("data_loop_values")
+        .map(|values| {
+            values
+                .children()
+                .iter()
+                .filter(|value| !matches!(value.rule_name(), "comment" | "stop_keyword"))
+                .map(value_text)
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .unwrap_or_default()
+}
+
+/// The index of the column `tag` of `table`, comparing data names case-insensitively
+fn column_index(table: &LoopTable, tag: &str) -> Option<usize> {
+    table
+        .columns()
+        .iter()
+        .position(|column| column.eq_ignore_ascii_case(tag))
+}
+
+/// The values of `row` in the key columns `keys`
+fn row_key<'a>(table: &LoopTable<'a>, row: usize, keys: &[usize]) -> Vec<&'a str> {
+    keys.iter()
+        .filter_map(|&column| table.value(row, column))
+        .collect()
+}
+
+/// The path of a row, that of its value in the first column
+fn row_path(table: &LoopTable, row: usize, path: &str) -> String {
+    format!("{}[{}]", join(path, &table.columns()[0]), row + 1)
+}
+
+fn row_text(table: &LoopTable, row: usize) -> String {
+    table.rows().nth(row).unwrap_or_default().join(" ")
+}
+
+fn row_position(table: &LoopTable, row: usize) -> LineColumn {
+    table
+        .value_position(row, 0)
+        .unwrap_or_else(|| table.position())
+}
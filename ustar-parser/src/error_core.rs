@@ -1,40 +1,312 @@
 #![allow(unused_assignments)] // Miette derive macros use fields in ways clippy can't see
-use crate::config::EncodingMode;
+use crate::config::{ColumnUnit, EncodingMode};
+use crate::line_column_index::{column_prefix, count_columns};
+use std::borrow::Borrow;
+use std::sync::Arc;
 
 /// Core error data shared between extended and simple error implementations
 #[cfg(feature = "extended-errors")]
 use miette::{Diagnostic, SourceSpan};
 
+/// Stable codes classifying parse errors, so callers needn't match on the error message
+///
+/// Syntax errors are classified from what pest expected and the input at the error, the other
+/// codes come from the checks after parsing. Codes are never renumbered or reused.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    /// Input the grammar doesn't allow at this point
+    #[default]
+    E0001UnexpectedToken,
+    /// A quoted value with no closing quote on its line
+    E0002UnterminatedQuote,
+    /// A text field opened by `;` at the start of a line and never closed
+    E0003RunawaySemicolonString,
+    /// The input ends where more was expected
+    E0004UnexpectedEndOfInput,
+    /// A data name followed by another data name or keyword rather than its value
+    E0005MissingValue,
+    /// A character outside the character set of the encoding
+    E0006InvalidCharacter,
+    /// A save frame not closed by `save_`, found while recovering
+    E0007UnterminatedSaveFrame,
+    /// A `save_` with no save frame open, found while recovering
+    E0008UnmatchedSaveKeyword,
+    /// A data or global block without data items, found while recovering
+    E0009EmptyBlock,
+    /// A data item or save frame before the first data block, found while recovering
+    E0010OutsideDataBlock,
+    /// Data outside a save frame when the dialect keeps all data in save frames
+    E0011DataOutsideSaveFrame,
+    /// A loop without values when the dialect doesn't allow empty loops
+    E0012EmptyLoop,
+    /// A loop without `stop_` when the dialect requires it
+    E0013MissingStopKeyword,
+    /// A data name, block code or frame code over the dialect's maximum length
+    E0014NameTooLong,
+    /// A line over the dialect's maximum length
+    E0015LineTooLong,
+    /// A data name defined twice in one scope
+    E0016DuplicateDataName,
+    /// Two save frames with the same name in one data block
+    E0017DuplicateSaveFrame,
+    /// A frame code naming no save frame of its data block
+    E0018DanglingFrameCode,
+    /// A file that couldn't be read
+    E0019UnreadableFile,
+    /// Input larger than the configured maximum size
+    E0020InputTooLarge,
+    /// Loops, lists or tables nested deeper than the configured maximum depth
+    E0021NestingTooDeep,
+    /// Parsing stopped by its progress callback
+    E0022Cancelled,
+    /// A data name not defined in the dictionary validated against
+    E0023UnknownDataName,
+    /// A value not of its item's type in the dictionary
+    E0024TypeMismatch,
+    /// A value not among its item's enumerated values in the dictionary
+    E0025EnumerationViolation,
+    /// A mandatory item of a category missing where the category is used
+    E0026MissingMandatoryItem,
+    /// A `data_` heading without a block code
+    E0027EmptyBlockCode,
+    /// A block or frame code with a character strict mode doesn't allow in names
+    E0028InvalidHeadingCharacter,
+}
+
+impl ErrorCode {
+    /// The code as written in reports, e.g. `E0002`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::E0001UnexpectedToken => "E0001",
+            ErrorCode::E0002UnterminatedQuote => "E0002",
+            ErrorCode::E0003RunawaySemicolonString => "E0003",
+            ErrorCode::E0004UnexpectedEndOfInput => "E0004",
+            ErrorCode::E0005MissingValue => "E0005",
+            ErrorCode::E0006InvalidCharacter => "E0006",
+            ErrorCode::E0007UnterminatedSaveFrame => "E0007",
+            ErrorCode::E0008UnmatchedSaveKeyword => "E0008",
+            ErrorCode::E0009EmptyBlock => "E0009",
+            ErrorCode::E0010OutsideDataBlock => "E0010",
+            ErrorCode::E0011DataOutsideSaveFrame => "E0011",
+            ErrorCode::E0012EmptyLoop => "E0012",
+            ErrorCode::E0013MissingStopKeyword => "E0013",
+            ErrorCode::E0014NameTooLong => "E0014",
+            ErrorCode::E0015LineTooLong => "E0015",
+            ErrorCode::E0016DuplicateDataName => "E0016",
+            ErrorCode::E0017DuplicateSaveFrame => "E0017",
+            ErrorCode::E0018DanglingFrameCode => "E0018",
+            ErrorCode::E0019UnreadableFile => "E0019",
+            ErrorCode::E0020InputTooLarge => "E0020",
+            ErrorCode::E0021NestingTooDeep => "E0021",
+            ErrorCode::E0022Cancelled => "E0022",
+            ErrorCode::E0023UnknownDataName => "E0023",
+            ErrorCode::E0024TypeMismatch => "E0024",
+            ErrorCode::E0025EnumerationViolation => "E0025",
+            ErrorCode::E0026MissingMandatoryItem => "E0026",
+            ErrorCode::E0027EmptyBlockCode => "E0027",
+            ErrorCode::E0028InvalidHeadingCharacter => "E0028",
+        }
+    }
+
+    /// Classify a syntax error at byte `offset` of `input` where pest expected `expected` (private)
+    fn classify(input: &str, offset: usize, expected: &[String], encoding: EncodingMode) -> Self {
+        // pest may report the position before the blanks that precede the offending token
+        let rest = input.get(offset..).unwrap_or_default().trim_start();
+        let Some(next) = rest.chars().next() else {
+            return ErrorCode::E0004UnexpectedEndOfInput;
+        };
+        let line = rest.split('\n').next().unwrap_or_default();
+        let token_offset = input.len() - rest.len();
+        let at_line_start = token_offset == 0 || input.as_bytes()[token_offset - 1] == b'\n';
+
+        let outside_encoding = match encoding {
+            EncodingMode::Ascii => !next.is_ascii(),
+            EncodingMode::ExtendedAscii => !crate::legacy_encoding::is_extended_ascii(next),
+            EncodingMode::Unicode => false,
+        };
+        if outside_encoding || (next.is_control() && !next.is_ascii_whitespace()) {
+            ErrorCode::E0006InvalidCharacter
+        } else if next == ';' && at_line_start && !rest.contains("\n;") {
+            ErrorCode::E0003RunawaySemicolonString
+        } else if (next == '\'' || next == '"') && !closes_quote(&line[1..], next) {
+            ErrorCode::E0002UnterminatedQuote
+        } else if is_empty_data_heading(rest) {
+            ErrorCode::E0027EmptyBlockCode
+        } else if (next == '_' || starts_with_keyword(rest))
+            && expected.iter().any(|rule| rule.contains("string"))
+        {
+            ErrorCode::E0005MissingValue
+        } else {
+            ErrorCode::E0001UnexpectedToken
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Check if `text` holds a `quote` followed by a blank or the end of the line (private)
+fn closes_quote(text: &str, quote: char) -> bool {
+    let text = text.trim_end_matches('\r');
+    text.match_indices(quote).any(|(index, _)| {
+        text[index + 1..]
+            .chars()
+            .next()
+            .is_none_or(|after| after == ' ' || after == '\t')
+    })
+}
+
+/// Check if `text` starts with a reserved word such as `loop_`, in any case (private)
+fn starts_with_keyword(text: &str) -> bool {
+    ["data_", "loop_", "global_", "save_", "stop_"]
+        .iter()
+        .any(|keyword| {
+            text.get(..keyword.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(keyword))
+        })
+}
+
+/// Check if `text` starts with a `data_` keyword that has no block code after it
+fn is_empty_data_heading(text: &str) -> bool {
+    text.get(.."data_".len())
+        .is_some_and(|start| start.eq_ignore_ascii_case("data_"))
+        && text["data_".len()..]
+            .chars()
+            .next()
+            .is_none_or(char::is_whitespace)
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "extended-errors", derive(thiserror::Error, Diagnostic))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "extended-errors", error("{message}"))]
 pub struct ErrorData {
     pub encoding: EncodingMode,
+    /// Stable classification of the error
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub code: ErrorCode,
     pub message: String,
+    /// Rules pest expected at the error, empty for errors found after parsing
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub expected: Vec<String>,
+    /// Byte offset of the error in the input
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub offset: usize,
+    /// Length in bytes of the text the error covers, 0 for a position
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub length: usize,
     pub line: usize,
     pub col: usize,
+    /// Unit `col` is counted in, pest counts characters
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub column_unit: ColumnUnit,
     pub line_content: String,
-    pub pest_error_display: String,
+    /// The message pest gave the error, shown with its context by `pest_display`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pest_message: String,
+    /// Start and end byte offsets in `src` of where pest reported the error, equal for a
+    /// position
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pest_span: (usize, usize),
+    /// Name of the file the input was read from, such as its path
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub source_name: Option<String>,
+    /// The input, shared with the variants of `UstarError` rather than copied
     #[cfg_attr(feature = "extended-errors", source_code)]
-    pub src: String,
+    pub src: Arc<str>,
     #[cfg_attr(feature = "extended-errors", label("Error occurred here"))]
     #[cfg(feature = "extended-errors")]
+    #[cfg_attr(feature = "serde", serde(with = "span_serde"))]
     pub error_span: SourceSpan,
+    /// Offset and length of the data name whose value was expected at the error, labelled as a
+    /// second span by fancy errors
+    #[cfg_attr(feature = "extended-errors", label("data name without a value"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub data_name_span: Option<(usize, usize)>,
+}
+
+/// The serialized form shared by the simple and extended `UstarError`, so both produce the same JSON
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum SerializedError {
+    ParseError(ErrorData),
+    RunawaySemicolonString(ErrorData),
+    LimitExceeded(ErrorData),
+    Cancelled(ErrorData),
+}
+
+/// Message of the error reported at the opener of a runaway semicolon-delimited string
+pub const RUNAWAY_SEMICOLON_MESSAGE: &str =
+    "semicolon-delimited string opened here is never closed";
+
+/// Message of the error reported at a `data_` heading without a block code
+pub const EMPTY_BLOCK_CODE_MESSAGE: &str = "data_ heading has no block code";
+
+/// Serialize a miette span as `{"offset", "length"}`
+#[cfg(all(feature = "serde", feature = "extended-errors"))]
+mod span_serde {
+    use miette::SourceSpan;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Span {
+        offset: usize,
+        length: usize,
+    }
+
+    pub fn serialize<S: Serializer>(span: &SourceSpan, serializer: S) -> Result<S::Ok, S::Error> {
+        Span {
+            offset: span.offset(),
+            length: span.len(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SourceSpan, D::Error> {
+        let span = Span::deserialize(deserializer)?;
+        Ok((span.offset, span.length).into())
+    }
 }
 
 impl ErrorData {
-    /// Create ErrorData from a pest error
+    /// Create ErrorData from a pest error, owned or borrowed
+    ///
+    /// Only the position, message and expected rules are kept, the pest rendering of the error
+    /// with its context is built when it is displayed.
     pub fn from_pest_error<R: pest::RuleType>(
-        error: pest::error::Error<R>,
+        error: impl Borrow<pest::error::Error<R>>,
         encoding: EncodingMode,
         input: &str,
     ) -> Self {
+        let error = error.borrow();
         let (line, col) = match error.line_col {
             pest::error::LineColLocation::Pos((line, col)) => (line, col),
             pest::error::LineColLocation::Span((line, col), _) => (line, col),
         };
-        let line_content = Self::get_line_content_from_pest(input, &error);
-        let pest_error_display = format!("{}", error);
+        let line_content = Self::get_line_content_from_pest(input, error);
+        let pest_message = error.variant.message().into_owned();
+        let pest_span = match &error.location {
+            pest::error::InputLocation::Pos(pos) => (*pos, *pos),
+            pest::error::InputLocation::Span((start, end)) => (*start, *end),
+        };
+        let (offset, length) = (pest_span.0, pest_span.1 - pest_span.0);
+        let expected: Vec<String> = match &error.variant {
+            pest::error::ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(|rule| format!("{:?}", rule)).collect()
+            }
+            pest::error::ErrorVariant::CustomError { .. } => Vec::new(),
+        };
+        let code = match &error.variant {
+            pest::error::ErrorVariant::ParsingError { .. } => {
+                ErrorCode::classify(input, offset, &expected, encoding)
+            }
+            pest::error::ErrorVariant::CustomError { .. } => ErrorCode::E0001UnexpectedToken,
+        };
+        let data_name_span = data_name_before_value(input, offset, &expected);
 
         // Extract simple error message for later formatting
         let simple_message = match &error.variant {
@@ -63,33 +335,168 @@ impl ErrorData {
             }
             pest::error::ErrorVariant::CustomError { message } => message.clone(),
         };
+        // pest only knows no rule matched there, which says nothing about the missing name
+        let simple_message = if code == ErrorCode::E0027EmptyBlockCode {
+            EMPTY_BLOCK_CODE_MESSAGE.to_string()
+        } else {
+            simple_message
+        };
 
         #[cfg(feature = "extended-errors")]
-        let error_span = match &error.location {
-            pest::error::InputLocation::Pos(pos) => (*pos, 0).into(),
-            pest::error::InputLocation::Span((start, end)) => (*start, *end - *start).into(),
-        };
+        let error_span = (offset, length).into();
 
         let result = ErrorData {
             encoding,
+            code,
             message: simple_message,
+            expected,
+            offset,
+            length,
             line,
             col,
+            column_unit: ColumnUnit::Chars,
             line_content,
-            pest_error_display,
-            src: input.to_string(),
+            pest_message,
+            pest_span,
+            src: Arc::from(input),
             #[cfg(feature = "extended-errors")]
             error_span,
+            data_name_span,
+            source_name: None,
         };
 
         result
     }
 
+    /// Create ErrorData at the `;` opening a semicolon-delimited string that is never closed,
+    /// if that explains the pest error
+    ///
+    /// A runaway string makes pest fail at its opener, or at the end of the input when an
+    /// earlier text field is closed by the opener of a later one, so the error is only moved
+    /// when it is at the opener or on the last line of the input.
+    ///
+    /// # Returns
+    /// * `Option<Self>` - The error at the opener, `None` if there is no runaway string
+    pub fn runaway_semicolon_string<R: pest::RuleType>(
+        error: &pest::error::Error<R>,
+        encoding: EncodingMode,
+        input: &str,
+    ) -> Option<Self> {
+        let (offset, expected) = match &error.variant {
+            pest::error::ErrorVariant::ParsingError { positives, .. } => (
+                match &error.location {
+                    pest::error::InputLocation::Pos(pos) => *pos,
+                    pest::error::InputLocation::Span((start, _)) => *start,
+                },
+                positives.iter().map(|rule| format!("{:?}", rule)).collect(),
+            ),
+            pest::error::ErrorVariant::CustomError { .. } => return None,
+        };
+        let opener = unclosed_semicolon_opener(input)?;
+
+        let rest = input.get(offset..).unwrap_or_default();
+        let at_opener = input.len() - rest.trim_start().len() == opener;
+        let on_last_line = opener < offset && !rest.trim_end().contains('\n');
+        if !at_opener && !on_last_line {
+            return None;
+        }
+
+        let at_opener = pest::error::Error::<R>::new_from_pos(
+            pest::error::ErrorVariant::CustomError {
+                message: RUNAWAY_SEMICOLON_MESSAGE.to_string(),
+            },
+            pest::Position::new(input, opener)?,
+        );
+        let mut result = Self::from_pest_error(at_opener, encoding, input);
+        result.code = ErrorCode::E0003RunawaySemicolonString;
+        result.expected = expected;
+        result.length = 1;
+        #[cfg(feature = "extended-errors")]
+        {
+            result.error_span = (opener, 1).into();
+        }
+        Some(result)
+    }
+
+    /// Recount `col` in another unit, using the text of the error line
+    pub fn with_column_unit(mut self, column_unit: ColumnUnit) -> Self {
+        let before = column_prefix(&self.line_content, self.col, self.column_unit);
+        self.col = count_columns(before, column_unit) + 1;
+        self.column_unit = column_unit;
+        self
+    }
+
+    /// Refer the offsets of an error in `text`, decoded one character per byte, to the bytes it
+    /// was decoded from, as are columns counted in bytes
+    pub(crate) fn in_original_bytes(mut self, text: &str) -> Self {
+        let end = crate::legacy_encoding::original_offset(text, self.offset + self.length);
+        self.offset = crate::legacy_encoding::original_offset(text, self.offset);
+        self.length = end - self.offset;
+        if self.column_unit == ColumnUnit::Bytes {
+            self.col = self.clone().with_column_unit(ColumnUnit::Chars).col;
+        }
+        self
+    }
+
+    /// The pest rendering of the error, naming the source after `-->` when it is known
+    ///
+    /// The rendering is rebuilt from the message and span pest reported over `src`, so it
+    /// matches what pest displays for the original error.
+    pub fn pest_display(&self) -> String {
+        use pest::error::{Error, ErrorVariant};
+
+        let variant = ErrorVariant::<()>::CustomError {
+            message: self.pest_message.clone(),
+        };
+        let (start, end) = self.pest_span;
+        let error = match (
+            pest::Position::new(&self.src, start),
+            pest::Position::new(&self.src, end),
+        ) {
+            (Some(start), Some(end)) if start < end => {
+                Error::new_from_span(variant, start.span(&end))
+            }
+            (Some(start), _) => Error::new_from_pos(variant, start),
+            (None, _) => return self.pest_message.clone(),
+        };
+        match &self.source_name {
+            Some(name) => error.with_path(name).to_string(),
+            None => error.to_string(),
+        }
+    }
+
+    /// Format error as a JSON object with its code, message, source name, position, span, the
+    /// rules pest expected and the text of the error line as context
+    pub fn format_json(&self) -> String {
+        let expected: Vec<String> = self.expected.iter().map(|rule| json_string(rule)).collect();
+        let source = self
+            .source_name
+            .as_deref()
+            .map_or_else(|| "null".to_string(), json_string);
+        format!(
+            "{{\"code\":{},\"message\":{},\"source\":{},\"line\":{},\"column\":{},\"span\":{{\"offset\":{},\"length\":{}}},\"expected\":[{}],\"context\":{}}}",
+            json_string(self.code.as_str()),
+            json_string(&self.message),
+            source,
+            self.line,
+            self.col,
+            self.offset,
+            self.length,
+            expected.join(","),
+            json_string(&self.line_content)
+        )
+    }
+
     /// Format error in basic format (similar to ASCII but minimal)
     pub fn format_basic(&self) -> String {
         // Use pest-style basic format with minimal context
+        let source = match &self.source_name {
+            Some(name) => format!(" in {}", name),
+            None => String::new(),
+        };
         let result = format!(
-            "Parse error at l{}:c{} because {}\n",
+            "Parse error{} at l{}:c{} because {}\n",
+            source,
             self.line,
             self.col,
             self.message.to_lowercase()
@@ -110,9 +517,14 @@ impl ErrorData {
         let max_line_num = end_line;
         let line_num_width = max_line_num.to_string().len();
 
+        let source = match &self.source_name {
+            Some(name) => format!("{}:", name),
+            None => String::new(),
+        };
         let mut result = format!(
-            "x {}\n --> {}:{}\n{} |\n",
+            "x {}\n --> {}{}:{}\n{} |\n",
             self.message,
+            source,
             self.line,
             self.col,
             " ".repeat(line_num_width)
@@ -135,7 +547,7 @@ impl ErrorData {
                 result.push_str(&format!(
                     "{} | {}^---\n",
                     " ".repeat(line_num_width),
-                    " ".repeat(self.col.saturating_sub(1))
+                    " ".repeat(self.caret_indent())
                 ));
             }
         }
@@ -143,6 +555,16 @@ impl ErrorData {
         result
     }
 
+    /// Characters before the error column on the error line, so the caret lines up whatever
+    /// unit the column is counted in (private)
+    fn caret_indent(&self) -> usize {
+        let before = column_prefix(&self.line_content, self.col, self.column_unit);
+        before.chars().count()
+            + self
+                .col
+                .saturating_sub(count_columns(before, self.column_unit) + 1)
+    }
+
     /// Get line content from pest error
     fn get_line_content_from_pest(
         input: &str,
@@ -167,3 +589,54 @@ impl ErrorData {
         "".to_string()
     }
 }
+
+/// Offset and length of the data name before `offset` when pest expected its value there
+/// (private)
+fn data_name_before_value(
+    input: &str,
+    offset: usize,
+    expected: &[String],
+) -> Option<(usize, usize)> {
+    if !expected.iter().any(|rule| rule == "non_quoted_string") {
+        return None;
+    }
+    let before = input.get(..offset)?.trim_end();
+    let name = before.rsplit(char::is_whitespace).next()?;
+    name.starts_with('_')
+        .then_some((before.len() - name.len(), name.len()))
+}
+
+/// Offset of the `;` opening the last semicolon-delimited string of `input`, if it is never
+/// closed (private)
+fn unclosed_semicolon_opener(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let line_starts =
+        std::iter::once(0).chain(memchr::memchr_iter(b'\n', bytes).map(|end| end + 1));
+    line_starts
+        .filter(|start| bytes.get(*start) == Some(&b';'))
+        .fold(None, |open, start| match open {
+            Some(_) => None,
+            None => Some(start),
+        })
+}
+
+/// Quote and escape `text` as a JSON string (private)
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            control if control.is_control() => {
+                quoted.push_str(&format!("\\u{:04x}", control as u32))
+            }
+            other => quoted.push(other),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
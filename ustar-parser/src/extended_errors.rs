@@ -1,29 +1,131 @@
 #![allow(unused_assignments)] // Miette derive macros use fields in ways clippy can't see
-use crate::config::EncodingMode;
-use crate::error_core::ErrorData;
+use crate::config::{ColumnUnit, EncodingMode};
+use crate::error_core::{ErrorCode, ErrorData};
 use crate::ErrorFormatMode;
-use miette::{Diagnostic, SourceSpan};
+use miette::{Diagnostic, LabeledSpan, NamedSource, SourceCode, SourceSpan};
+use std::sync::Arc;
 
 /// USTAR parsing error types with rich diagnostics
+///
+/// The details are boxed, and the input shared between them and the variant, so the error is
+/// small enough to return in a `Result` directly. The input is shared by an `Arc`, so the error
+/// is `Send + Sync + 'static` and can be returned from a thread or boxed as a `dyn Error`.
 #[derive(thiserror::Error, Debug, Clone, Diagnostic)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        from = "crate::error_core::SerializedError",
+        into = "crate::error_core::SerializedError"
+    )
+)]
 pub enum UstarError {
     #[error("{core}")]
     ParseError {
-        #[diagnostic(transparent)]
-        core: ErrorData,
+        core: Box<ErrorData>,
         #[source_code]
-        src: String,
-        #[label("Error occurred here")]
+        src: Arc<str>,
+        #[label(primary, "Error occurred here")]
         error_span: SourceSpan,
+        #[label("data name without a value")]
+        data_name_span: Option<SourceSpan>,
     },
+    /// A semicolon-delimited string that is never closed, labelled at its opening `;`
+    #[error("Unterminated semicolon-delimited string")]
+    RunawaySemicolonString {
+        core: Box<ErrorData>,
+        #[source_code]
+        src: Arc<str>,
+        #[label("semicolon-delimited string opened here is never closed")]
+        error_span: SourceSpan,
+    },
+    /// Input over a size or nesting limit of the configuration, labelled where it is exceeded
+    #[error("{core}")]
+    LimitExceeded {
+        core: Box<ErrorData>,
+        #[source_code]
+        src: Arc<str>,
+        #[label("limit exceeded here")]
+        error_span: SourceSpan,
+    },
+    /// Parsing stopped by its progress callback, labelled where parsing stopped
+    #[error("{core}")]
+    Cancelled {
+        core: Box<ErrorData>,
+        #[source_code]
+        src: Arc<str>,
+        #[label("parsing cancelled here")]
+        error_span: SourceSpan,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl From<crate::error_core::SerializedError> for UstarError {
+    fn from(error: crate::error_core::SerializedError) -> Self {
+        match error {
+            crate::error_core::SerializedError::ParseError(core) => UstarError::ParseError {
+                src: core.src.clone(),
+                error_span: core.error_span,
+                data_name_span: core.data_name_span.map(SourceSpan::from),
+                core: Box::new(core),
+            },
+            crate::error_core::SerializedError::RunawaySemicolonString(core) => {
+                UstarError::RunawaySemicolonString {
+                    src: core.src.clone(),
+                    error_span: core.error_span,
+                    core: Box::new(core),
+                }
+            }
+            crate::error_core::SerializedError::LimitExceeded(core) => UstarError::LimitExceeded {
+                src: core.src.clone(),
+                error_span: core.error_span,
+                core: Box::new(core),
+            },
+            crate::error_core::SerializedError::Cancelled(core) => UstarError::Cancelled {
+                src: core.src.clone(),
+                error_span: core.error_span,
+                core: Box::new(core),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<UstarError> for crate::error_core::SerializedError {
+    fn from(error: UstarError) -> Self {
+        match error {
+            UstarError::ParseError { core, .. } => {
+                crate::error_core::SerializedError::ParseError(*core)
+            }
+            UstarError::RunawaySemicolonString { core, .. } => {
+                crate::error_core::SerializedError::RunawaySemicolonString(*core)
+            }
+            UstarError::LimitExceeded { core, .. } => {
+                crate::error_core::SerializedError::LimitExceeded(*core)
+            }
+            UstarError::Cancelled { core, .. } => {
+                crate::error_core::SerializedError::Cancelled(*core)
+            }
+        }
+    }
 }
 
 impl UstarError {
+    /// Create an error from a pest error, owned or borrowed, over `input`
     pub fn from_pest_error<R: pest::RuleType>(
-        error: pest::error::Error<R>,
+        error: impl std::borrow::Borrow<pest::error::Error<R>>,
         encoding: EncodingMode,
         input: &str,
     ) -> Self {
+        let error = error.borrow();
+        if let Some(core) = ErrorData::runaway_semicolon_string(error, encoding, input) {
+            return UstarError::RunawaySemicolonString {
+                src: core.src.clone(),
+                error_span: core.error_span,
+                core: Box::new(core),
+            };
+        }
+
         let error_span = match &error.location {
             pest::error::InputLocation::Pos(pos) => (*pos, 0).into(),
             pest::error::InputLocation::Span((start, end)) => (*start, *end - *start).into(),
@@ -34,21 +136,141 @@ impl UstarError {
         let result = UstarError::ParseError {
             src: core.src.clone(),
             error_span,
-            core,
+            data_name_span: core.data_name_span.map(SourceSpan::from),
+            core: Box::new(core),
         };
 
         result
     }
 
+    /// The details of the error shared by every variant
+    pub fn core(&self) -> &ErrorData {
+        match self {
+            UstarError::ParseError { core, .. }
+            | UstarError::RunawaySemicolonString { core, .. }
+            | UstarError::LimitExceeded { core, .. }
+            | UstarError::Cancelled { core, .. } => core,
+        }
+    }
+
+    /// Apply `change` to the details of the error, keeping its variant (private)
+    fn map_core(self, change: impl FnOnce(ErrorData) -> ErrorData) -> Self {
+        match self {
+            UstarError::ParseError {
+                src,
+                error_span,
+                data_name_span,
+                core,
+            } => UstarError::ParseError {
+                src,
+                error_span,
+                data_name_span,
+                core: Box::new(change(*core)),
+            },
+            UstarError::RunawaySemicolonString {
+                src,
+                error_span,
+                core,
+            } => UstarError::RunawaySemicolonString {
+                src,
+                error_span,
+                core: Box::new(change(*core)),
+            },
+            UstarError::LimitExceeded {
+                src,
+                error_span,
+                core,
+            } => UstarError::LimitExceeded {
+                src,
+                error_span,
+                core: Box::new(change(*core)),
+            },
+            UstarError::Cancelled {
+                src,
+                error_span,
+                core,
+            } => UstarError::Cancelled {
+                src,
+                error_span,
+                core: Box::new(change(*core)),
+            },
+        }
+    }
+
+    /// The input and details of the error, taken out of its variant (private)
+    fn into_parts(self) -> (Arc<str>, Box<ErrorData>) {
+        match self {
+            UstarError::ParseError { src, core, .. }
+            | UstarError::RunawaySemicolonString { src, core, .. }
+            | UstarError::LimitExceeded { src, core, .. }
+            | UstarError::Cancelled { src, core, .. } => (src, core),
+        }
+    }
+
+    /// Report the error as a configured limit being exceeded (private)
+    pub(crate) fn into_limit_exceeded(self) -> Self {
+        let (src, core) = self.into_parts();
+        UstarError::LimitExceeded {
+            src,
+            error_span: core.error_span,
+            core,
+        }
+    }
+
+    /// Report the error as parsing cancelled by a progress callback (private)
+    pub(crate) fn into_cancelled(self) -> Self {
+        let (src, core) = self.into_parts();
+        UstarError::Cancelled {
+            src,
+            error_span: core.error_span,
+            core,
+        }
+    }
+
+    /// The stable code classifying the error
+    pub fn code(&self) -> ErrorCode {
+        self.core().code
+    }
+
+    /// Set the code of an error found after parsing (private)
+    pub(crate) fn with_code(self, code: ErrorCode) -> Self {
+        self.map_core(|core| ErrorData { code, ..core })
+    }
+
+    /// Recount the error column in another unit, errors from pest count characters
+    pub fn with_column_unit(self, column_unit: ColumnUnit) -> Self {
+        self.map_core(|core| core.with_column_unit(column_unit))
+    }
+
+    /// Refer the offsets of an error in `text`, decoded one character per byte, to the bytes it
+    /// was decoded from (private)
+    pub(crate) fn in_original_bytes(self, text: &str) -> Self {
+        self.map_core(|core| core.in_original_bytes(text))
+    }
+
+    /// Add a note to the message of the error, such as how the input was decoded (private)
+    pub(crate) fn with_note(self, note: &str) -> Self {
+        self.map_core(|core| ErrorData {
+            message: format!("{} ({})", core.message, note),
+            ..core
+        })
+    }
+
+    /// Name the file the input was read from, so formatted errors say where they are
+    pub fn with_source_name(self, source_name: impl Into<String>) -> Self {
+        let source_name = source_name.into();
+        self.map_core(|core| ErrorData {
+            source_name: Some(source_name),
+            ..core
+        })
+    }
+
     /// Format error according to specified mode
     pub fn format_error(&self, mode: ErrorFormatMode, context_lines: usize) -> String {
         match mode {
-            ErrorFormatMode::Basic => match self {
-                UstarError::ParseError { core, .. } => core.format_basic(),
-            },
-            ErrorFormatMode::Ascii => match self {
-                UstarError::ParseError { core, .. } => core.format_ascii(context_lines),
-            },
+            ErrorFormatMode::Basic => self.core().format_basic(),
+            ErrorFormatMode::Ascii => self.core().format_ascii(context_lines),
+            ErrorFormatMode::Json => self.core().format_json(),
             ErrorFormatMode::Fancy => {
                 // For fancy mode, we'll create a custom GraphicalReportHandler
                 use miette::{GraphicalReportHandler, GraphicalTheme};
@@ -57,13 +279,43 @@ impl UstarError {
                     .with_theme(GraphicalTheme::unicode());
 
                 let mut output = String::new();
-                handler
-                    .render_report(&mut output, self)
-                    .unwrap_or_else(|_| {
-                        output.push_str(&format!("{:?}", miette::Report::new(self.clone())));
-                    });
+                let rendered = match &self.core().source_name {
+                    Some(name) => {
+                        let named = NamedError {
+                            error: self,
+                            src: NamedSource::new(name, self.core().src.clone()),
+                        };
+                        handler.render_report(&mut output, &named)
+                    }
+                    None => handler.render_report(&mut output, self),
+                };
+                rendered.unwrap_or_else(|_| {
+                    output.push_str(&format!("{:?}", miette::Report::new(self.clone())));
+                });
                 output
             }
         }
     }
 }
+
+/// An error rendered with its source code named by the file it came from (private)
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+struct NamedError<'a> {
+    error: &'a UstarError,
+    src: NamedSource<Arc<str>>,
+}
+
+impl Diagnostic for NamedError<'_> {
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.src)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.error.labels()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        self.error.help()
+    }
+}
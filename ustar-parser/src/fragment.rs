@@ -0,0 +1,165 @@
+//! Fragment parsing - scans for keyword boundaries and parses pieces of a document in isolation
+//!
+//! Used by error recovery and block iteration, which both parse part of the input with a
+//! grammar rule and need the resulting tree and errors positioned in the whole input.
+
+use crate::line_column_index::LineColumn;
+use crate::mutable_pair::MutablePair;
+use crate::shared_text::SharedText;
+use crate::{EncodingMode, UstarError};
+use pest::error::{Error, InputLocation};
+use pest::{Parser, Position, RuleType};
+
+/// Parse `fragment`, the text `input[start..]` up to some end, with a single rule, with
+/// positions relative to the whole input
+///
+/// `origin` is the line and column of `start` in the whole input. The content of the nodes shares
+/// the source of `fragment`, so a fragment copied out of the input keeps only its own text alive.
+pub(crate) fn parse_fragment<P: Parser<R>, R: RuleType>(
+    input: &str,
+    fragment: &SharedText,
+    start: usize,
+    origin: LineColumn,
+    rule: R,
+    encoding: EncodingMode,
+) -> Result<MutablePair, Box<UstarError>> {
+    match P::parse(rule, fragment) {
+        Ok(mut pairs) => {
+            let pair = pairs.next().expect("a successful parse produces a pair");
+            let (source, source_start) = fragment.source_start();
+            let mut node = MutablePair::from_pest_pair_in(&pair, source, source_start);
+            shift(&mut node, start, origin);
+            Ok(node)
+        }
+        Err(error) => {
+            let relative = match error.location {
+                InputLocation::Pos(pos) => pos,
+                InputLocation::Span((pos, _)) => pos,
+            };
+            let position = Position::new(input, start + relative).expect("error inside input");
+            let error = Error::new_from_pos(error.variant, position);
+            Err(Box::new(UstarError::from_pest_error(
+                error, encoding, input,
+            )))
+        }
+    }
+}
+
+fn shift(node: &mut MutablePair, offset: usize, origin: LineColumn) {
+    node.start += offset;
+    node.end += offset;
+    node.start_position = shift_position(node.start_position, origin);
+    node.end_position = shift_position(node.end_position, origin);
+    for child in &mut node.children {
+        shift(child, offset, origin);
+    }
+}
+
+fn shift_position(position: LineColumn, origin: LineColumn) -> LineColumn {
+    match position.line {
+        _ if !position.is_defined() => position,
+        1 => LineColumn::new(origin.line, origin.column + position.column - 1),
+        line => LineColumn::new(origin.line + line - 1, position.column),
+    }
+}
+
+/// Line and column lookup for offsets that only ever increase, scanning each byte once
+pub(crate) struct LineTracker {
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl LineTracker {
+    pub(crate) fn new() -> Self {
+        LineTracker {
+            offset: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    /// Line and column of `offset`, which must not be before the previous offset
+    pub(crate) fn position(&mut self, input: &str, offset: usize) -> LineColumn {
+        for newline in memchr::memchr_iter(b'\n', &input.as_bytes()[self.offset..offset]) {
+            self.line += 1;
+            self.line_start = self.offset + newline + 1;
+        }
+        self.offset = offset;
+        LineColumn::new(self.line, offset - self.line_start + 1)
+    }
+}
+
+/// Case insensitive check that `text` starts with `keyword`
+pub(crate) fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.get(..keyword.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+}
+
+/// Skip whitespace and comments
+pub(crate) fn skip_trivia(input: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &input[pos..];
+        let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '\u{FEFF}');
+        pos += rest.len() - trimmed.len();
+        if trimmed.starts_with('#') {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else {
+            return pos;
+        }
+    }
+}
+
+/// Find the first token after the one at `from` that starts with one of `keywords`
+///
+/// Keywords inside quoted strings and semicolon text fields are not tokens, so they are never
+/// returned. Returns the length of the input if there is no such token.
+pub(crate) fn next_keyword(input: &str, from: usize, keywords: &[&str]) -> usize {
+    let mut pos = skip_token(input, from);
+    loop {
+        pos = skip_trivia(input, pos);
+        if pos >= input.len() {
+            return input.len();
+        }
+        if keywords
+            .iter()
+            .any(|keyword| starts_with_keyword(&input[pos..], keyword))
+        {
+            return pos;
+        }
+        pos = skip_token(input, pos);
+    }
+}
+
+/// Skip the token at `pos`, unterminated strings run to the end of their line or text field
+fn skip_token(input: &str, pos: usize) -> usize {
+    let rest = &input[pos..];
+
+    let at_line_start = pos == 0 || input[..pos].ends_with('\n');
+    if at_line_start && rest.starts_with(';') {
+        return rest[1..]
+            .find("\n;")
+            .map_or(input.len(), |index| pos + 1 + index + 2);
+    }
+
+    for delimiter in ["'''", "\"\"\""] {
+        if let Some(body) = rest.strip_prefix(delimiter) {
+            return body
+                .find(delimiter)
+                .map_or(input.len(), |index| pos + 3 + index + 3);
+        }
+    }
+
+    if let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+        // a quote only closes the string when followed by whitespace, and never spans lines
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        let closing = rest[1..line_end].char_indices().find_map(|(index, c)| {
+            let after = 1 + index + c.len_utf8();
+            let closes = c == quote && rest[after..].chars().next().is_none_or(char::is_whitespace);
+            closes.then_some(after)
+        });
+        return pos + closing.unwrap_or(line_end);
+    }
+
+    pos + rest.find(char::is_whitespace).unwrap_or(rest.len())
+}
@@ -0,0 +1,296 @@
+//! Incremental reparsing - keeping a parse up to date as its text is edited
+//!
+//! An editor checking a document after each keystroke needn't parse all of it again, an edit
+//! only changes the data and global blocks it touches. `IncrementalParser` keeps the range and
+//! tree of each top level block; after an edit it parses again only the blocks the edit
+//! touches and moves the blocks after them to their new offsets and lines.
+//!
+//! Edits can change where blocks start: adding or removing a `data_` heading splits or merges
+//! blocks, and opening a quoted string or text field can swallow the headings after it. The
+//! blocks around the edit are scanned again until the scan ends where an unchanged block starts,
+//! widening the reparse as far as needed, up to the whole document.
+
+use crate::block_iterator::DataBlockIterator;
+use crate::config::{get_cif_version, get_column_unit};
+use crate::fragment::{starts_with_keyword, LineTracker};
+use crate::line_column_index::LineColumn;
+use crate::mutable_pair::MutablePair;
+use crate::{CifVersion, EncodingMode, ParserConfig, UstarError};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Headings that start a new block
+const BLOCK_KEYWORDS: [&str; 2] = ["data_", "global_"];
+
+/// A parse of a document kept up to date as the document is edited
+///
+/// The tree is the same as `parse_with_progress` builds, the blocks are parsed as `iter_blocks`
+/// parses them.
+pub struct IncrementalParser {
+    text: String,
+    config: ParserConfig,
+    /// The text without any byte order mark, shared by the content of every block
+    source: Arc<String>,
+    encoding: EncodingMode,
+    blocks: Vec<ParsedBlock>,
+}
+
+/// A top level block, its range in the source and its tree or the error parsing it (private)
+struct ParsedBlock {
+    start: usize,
+    end: usize,
+    tree: Result<MutablePair, Box<UstarError>>,
+}
+
+/// The blocks an edit changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReparseReport {
+    /// Indices of the blocks parsed again, in the edited document
+    pub changed: Range<usize>,
+    /// Number of blocks of the document before the edit they replace
+    pub replaced: usize,
+    /// Whether the whole document was parsed again
+    pub full_reparse: bool,
+}
+
+impl IncrementalParser {
+    /// Parse `input` block by block, keeping the blocks for later edits
+    ///
+    /// Blocks that fail to parse are kept as errors, so a later edit can fix them.
+    pub fn new(input: &str, config: &ParserConfig) -> Self {
+        let iterator = DataBlockIterator::new(input, config).with_shared_source();
+        let mut parser = IncrementalParser {
+            text: input.to_string(),
+            config: config.clone(),
+            source: iterator.source().clone(),
+            encoding: iterator.encoding(),
+            blocks: Vec::new(),
+        };
+        parser.reparse_all();
+        parser
+    }
+
+    /// The current text of the document
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Each block of the document in order, or the error parsing it
+    pub fn blocks(&self) -> impl Iterator<Item = Result<&MutablePair, &UstarError>> {
+        self.blocks
+            .iter()
+            .map(|block| block.tree.as_ref().map_err(|error| &**error))
+    }
+
+    /// The tree of the whole document, as `parse_with_progress` builds it
+    ///
+    /// # Returns
+    /// * `Result<MutablePair, UstarError>` - The tree, or the error for the first block that fails to parse or the first check that fails
+    pub fn tree(&self) -> Result<MutablePair, Box<UstarError>> {
+        // input without blocks has no blocks to gather
+        if self.blocks.is_empty() {
+            return crate::parse(&self.text, &self.config);
+        }
+        let column_unit = get_column_unit(&self.config);
+        let cif2 = get_cif_version(&self.config) == CifVersion::Cif2;
+        crate::limits::check_limits(&self.source, &self.config, cif2, self.encoding)
+            .map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| block.tree.clone())
+            .collect::<Result<Vec<_>, _>>()?;
+        crate::block_tree(
+            &self.source,
+            self.encoding,
+            blocks,
+            &self.config,
+            column_unit,
+        )
+    }
+
+    /// Replace the text in `range` with `replacement` and parse again the blocks it changes
+    ///
+    /// The blocks from the last one starting before the edit to the last one starting in or
+    /// at the end of it are scanned and parsed again, widened when the edit merges or splits
+    /// blocks or runs a string into the next block, and when a later block would start on the
+    /// line the edit ends on. Edits of the first three bytes, which might add or remove a byte
+    /// order mark, parse the whole document again.
+    ///
+    /// Panics if `range` is out of bounds or splits a character, as `String::replace_range`
+    /// does.
+    ///
+    /// # Returns
+    /// * `Result<ReparseReport, UstarError>` - The blocks parsed again, or the error for the first of them that fails to parse; the edit is applied either way
+    pub fn apply_edit(
+        &mut self,
+        range: Range<usize>,
+        replacement: &str,
+    ) -> Result<ReparseReport, Box<UstarError>> {
+        let removed_lines = self.text[range.clone()].matches('\n').count();
+        let bom = self.text.len() - self.source.len();
+        self.text.replace_range(range.clone(), replacement);
+
+        if range.start < 3 {
+            let replaced = self.blocks.len();
+            let changed = self.reparse_all();
+            return self.report(changed, replaced, true);
+        }
+
+        let mut iterator = DataBlockIterator::new(&self.text, &self.config).with_shared_source();
+        let source = iterator.source().clone();
+        let start = range.start - bom;
+        let old_end = range.end - bom;
+        let new_end = start + replacement.len();
+        let delta = new_end as isize - old_end as isize;
+        let lines = replacement.matches('\n').count() as isize - removed_lines as isize;
+
+        // the blocks first..last are parsed again, the first starts before the edit and the
+        // block after the last starts after it
+        let mut first = self.blocks.iter().rposition(|block| block.start < start);
+        let mut last = self.blocks.partition_point(|block| block.start <= old_end);
+        // the heading of the first block may have been edited away, merging it with the block
+        // before
+        while let Some(index) = first.filter(|index| *index > 0) {
+            let heading = &source[self.blocks[index].start..];
+            if BLOCK_KEYWORDS
+                .iter()
+                .any(|keyword| starts_with_keyword(heading, keyword))
+            {
+                break;
+            }
+            first = Some(index - 1);
+        }
+        let first = first.unwrap_or(0);
+        let region_start = match first {
+            0 => 0,
+            index => self.blocks[index].start,
+        };
+        let region_end = |last: usize| match self.blocks.get(last) {
+            Some(block) => block.start.wrapping_add_signed(delta),
+            None => source.len(),
+        };
+        // columns are only kept by blocks starting on a later line than the edit ends on
+        while last < self.blocks.len() && !source[new_end..region_end(last)].contains('\n') {
+            last += 1;
+        }
+
+        // scan until a block ends where an unchanged block starts, a string run on by the
+        // edit can carry the scan past the blocks after it
+        iterator.skip_to(region_start);
+        let mut ranges = Vec::new();
+        let mut scanned_to = region_start;
+        loop {
+            while scanned_to < region_end(last) {
+                match iterator.next_range() {
+                    Some(range) => {
+                        scanned_to = range.1;
+                        ranges.push(range);
+                    }
+                    None => scanned_to = source.len(),
+                }
+            }
+            if scanned_to == region_end(last) {
+                break;
+            }
+            last += 1;
+        }
+
+        let reparsed = ranges.into_iter().map(|(start, end, origin)| ParsedBlock {
+            start,
+            end,
+            tree: iterator.parse_block(start, end, origin),
+        });
+        let following: Vec<ParsedBlock> = self.blocks.drain(last..).collect();
+        let replaced = self.blocks.drain(first..).count();
+        self.blocks.extend(reparsed);
+        let changed = first..self.blocks.len();
+        self.blocks
+            .extend(following.into_iter().map(|block| ParsedBlock {
+                start: block.start.wrapping_add_signed(delta),
+                end: block.end.wrapping_add_signed(delta),
+                tree: block.tree,
+            }));
+
+        // blocks kept from before the edit move to the new source, and later ones to their
+        // new offsets and lines; blocks that failed are parsed again, as their errors hold
+        // the old text
+        let mut lines_before = LineTracker::new();
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            let (delta, lines) = match index {
+                index if changed.contains(&index) => continue,
+                index if index < changed.start => (0, 0),
+                _ => (delta, lines),
+            };
+            block.tree = match &mut block.tree {
+                Ok(tree) => {
+                    move_tree(tree, &source, delta, lines);
+                    continue;
+                }
+                Err(_) => {
+                    let origin = lines_before.position(&source, block.start);
+                    iterator.parse_block(block.start, block.end, origin)
+                }
+            };
+        }
+        self.source = source;
+        self.encoding = iterator.encoding();
+        self.report(changed, replaced, false)
+    }
+
+    /// Parse every block of the text again, returning the indices of the blocks (private)
+    fn reparse_all(&mut self) -> Range<usize> {
+        let mut iterator = DataBlockIterator::new(&self.text, &self.config).with_shared_source();
+        self.blocks.clear();
+        while let Some((start, end, origin)) = iterator.next_range() {
+            let tree = iterator.parse_block(start, end, origin);
+            self.blocks.push(ParsedBlock { start, end, tree });
+        }
+        self.source = iterator.source().clone();
+        self.encoding = iterator.encoding();
+        0..self.blocks.len()
+    }
+
+    /// The report of parsing the blocks `changed` again, or the first error among them (private)
+    fn report(
+        &self,
+        changed: Range<usize>,
+        replaced: usize,
+        full_reparse: bool,
+    ) -> Result<ReparseReport, Box<UstarError>> {
+        if let Some(Err(error)) = self.blocks[changed.clone()]
+            .iter()
+            .map(|block| &block.tree)
+            .find(|tree| tree.is_err())
+        {
+            return Err(error.clone());
+        }
+        Ok(ReparseReport {
+            changed,
+            replaced,
+            full_reparse,
+        })
+    }
+}
+
+/// Move the nodes of `tree` to `source`, where they start `delta` bytes and `lines` lines
+/// later (private)
+fn move_tree(tree: &mut MutablePair, source: &Arc<String>, delta: isize, lines: isize) {
+    tree.content = tree.content.moved(source, delta);
+    tree.start = tree.start.wrapping_add_signed(delta);
+    tree.end = tree.end.wrapping_add_signed(delta);
+    tree.start_position = move_position(tree.start_position, lines);
+    tree.end_position = move_position(tree.end_position, lines);
+    for child in &mut tree.children {
+        move_tree(child, source, delta, lines);
+    }
+}
+
+/// `position` moved `lines` lines later, undefined positions stay undefined (private)
+fn move_position(position: LineColumn, lines: isize) -> LineColumn {
+    match position.is_defined() {
+        true => LineColumn::new(position.line.wrapping_add_signed(lines), position.column),
+        false => position,
+    }
+}
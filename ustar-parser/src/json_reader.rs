@@ -0,0 +1,309 @@
+//! JSON reader - replay the JSON written by `JsonHandler` as the events of a walk.
+//!
+//! Reading JSON back as SAS events lets any handler consume it as if it were walking a STAR
+//! file; with `StarWriterHandler` this turns the JSON back into STAR. The keys of each object
+//! are read in the order they are written, so data items, loops and save frames keep their
+//! order.
+//!
+//! The JSON records values but not their delimiters, so a delimiter is chosen for each value
+//! that reads back as the same text: unquoted where possible, otherwise quotes or a text field.
+//! `null` is the unquoted `.` and the string `"?"` the unquoted `?`, while the string `"."` is
+//! quoted. Numbers and booleans, such as an unknown sentinel set with
+//! `JsonHandler::with_unknown`, are unquoted values of their JSON text. Every event has the
+//! position 0:0, and an empty loop, written as `[]`, has no rows to give its tags and is
+//! skipped.
+
+use crate::line_column_index::LineColumn;
+use crate::sas_interface::{SASContentHandler, ValueDelimiter, WalkControl};
+use crate::values::collides_with_keyword;
+use serde::de::{Deserialize, Deserializer, Error as _, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+
+/// A JSON value keeping the order of object keys
+enum JsonNode {
+    Null,
+    /// A number or boolean, as its JSON text
+    Literal(String),
+    String(String),
+    Array(Vec<JsonNode>),
+    Object(Vec<(String, JsonNode)>),
+}
+
+struct JsonNodeVisitor;
+
+impl<'de> Visitor<'de> for JsonNodeVisitor {
+    type Value = JsonNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<JsonNode, E> {
+        Ok(JsonNode::Null)
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<JsonNode, E> {
+        Ok(JsonNode::Literal(value.to_string()))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<JsonNode, E> {
+        Ok(JsonNode::Literal(value.to_string()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<JsonNode, E> {
+        Ok(JsonNode::Literal(value.to_string()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<JsonNode, E> {
+        Ok(JsonNode::Literal(
+            serde_json::Value::from(value).to_string(),
+        ))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<JsonNode, E> {
+        Ok(JsonNode::String(value.to_string()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsonNode, A::Error> {
+        let mut nodes = Vec::new();
+        while let Some(node) = seq.next_element()? {
+            nodes.push(node);
+        }
+        Ok(JsonNode::Array(nodes))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsonNode, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(JsonNode::Object(entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<JsonNode, D::Error> {
+        deserializer.deserialize_any(JsonNodeVisitor)
+    }
+}
+
+/// The walk was stopped by the handler
+struct Stopped;
+
+/// Why replaying ended early
+enum Halt {
+    Stopped,
+    Invalid(String),
+}
+
+impl From<Stopped> for Halt {
+    fn from(_: Stopped) -> Halt {
+        Halt::Stopped
+    }
+}
+
+/// Carry on after a callback unless it stopped the walk, telling start callbacks to skip
+fn check(control: WalkControl) -> Result<bool, Stopped> {
+    match control {
+        WalkControl::Continue => Ok(false),
+        WalkControl::SkipSubtree => Ok(true),
+        WalkControl::Stop => Err(Stopped),
+    }
+}
+
+/// All events are reported at 0:0, the JSON has no positions
+const NO_POSITION: LineColumn = LineColumn { line: 0, column: 0 };
+
+/// Walk JSON in the shape `JsonHandler` writes, calling `handler` as a walk of the STAR file
+/// it was written from would, apart from comments and positions
+///
+/// # Returns
+/// * `serde_json::Result<bool>` - Whether the handler stopped the walk, or an error if the
+///   input isn't JSON or isn't in the shape `JsonHandler` writes
+pub fn walk_json<T: SASContentHandler>(json: &str, handler: &mut T) -> serde_json::Result<bool> {
+    let root: JsonNode = serde_json::from_str(json)?;
+    let JsonNode::Object(blocks) = root else {
+        return Err(serde_json::Error::custom(
+            "expected a JSON object with a key for each block",
+        ));
+    };
+    let mut replay = Replay { handler };
+    match replay.stream(&blocks) {
+        Ok(()) => Ok(false),
+        Err(Halt::Stopped) => Ok(true),
+        Err(Halt::Invalid(message)) => Err(serde_json::Error::custom(message)),
+    }
+}
+
+struct Replay<'h, T: SASContentHandler> {
+    handler: &'h mut T,
+}
+
+impl<T: SASContentHandler> Replay<'_, T> {
+    fn stream(&mut self, blocks: &[(String, JsonNode)]) -> Result<(), Halt> {
+        if !check(self.handler.start_stream(None))? {
+            for (key, node) in blocks {
+                self.block(key, node)?;
+            }
+        }
+        check(self.handler.end_stream(NO_POSITION))?;
+        Ok(())
+    }
+
+    fn block(&mut self, key: &str, node: &JsonNode) -> Result<(), Halt> {
+        let entries = object(key, node)?;
+        if key == "global_" {
+            if !check(self.handler.start_global(NO_POSITION))? {
+                self.entries(entries)?;
+            }
+            check(self.handler.end_global(NO_POSITION))?;
+        } else if let Some(name) = key.strip_prefix("data_") {
+            if !check(self.handler.start_data(NO_POSITION, name))? {
+                self.entries(entries)?;
+            }
+            check(self.handler.end_data(NO_POSITION, name))?;
+        } else {
+            return Err(Halt::Invalid(format!(
+                "expected a block key starting with data_ or global_, found {}",
+                key
+            )));
+        }
+        Ok(())
+    }
+
+    /// The data items, loops and save frames of a block or save frame
+    fn entries(&mut self, entries: &[(String, JsonNode)]) -> Result<(), Halt> {
+        for (key, node) in entries {
+            if let Some(name) = key.strip_prefix("save_") {
+                let frame = object(key, node)?;
+                if !check(self.handler.start_saveframe(NO_POSITION, name))? {
+                    self.entries(frame)?;
+                }
+                check(self.handler.end_saveframe(NO_POSITION, name))?;
+            } else if key.starts_with("loop_") {
+                self.star_loop(key, node)?;
+            } else {
+                self.data(key, node, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn star_loop(&mut self, key: &str, node: &JsonNode) -> Result<(), Halt> {
+        let JsonNode::Array(rows) = node else {
+            return Err(Halt::Invalid(format!(
+                "expected an array of rows for {}",
+                key
+            )));
+        };
+        let mut levels = Vec::new();
+        loop_tags(rows, &mut levels)?;
+        if levels.is_empty() {
+            return Ok(());
+        }
+
+        if !check(self.handler.start_loop(NO_POSITION))? {
+            let mut skip = false;
+            for (index, tags) in levels.iter().enumerate() {
+                if check(self.handler.loop_definition(NO_POSITION, tags, index + 1))? {
+                    skip = true;
+                    break;
+                }
+            }
+            if !skip {
+                self.rows(rows, 1)?;
+            }
+        }
+        check(self.handler.end_loop(NO_POSITION))?;
+        Ok(())
+    }
+
+    fn rows(&mut self, rows: &[JsonNode], level: usize) -> Result<(), Halt> {
+        for (index, row) in rows.iter().enumerate() {
+            let entries = object("a loop row", row)?;
+            check(self.handler.start_loop_row(NO_POSITION, index))?;
+            for (key, node) in entries {
+                match (key.as_str(), node) {
+                    ("loop_", JsonNode::Array(nested)) => self.rows(nested, level + 1)?,
+                    _ => self.data(key, node, level)?,
+                }
+            }
+            check(self.handler.end_loop_row(NO_POSITION, index))?;
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, tag: &str, node: &JsonNode, loop_level: usize) -> Result<(), Halt> {
+        let (value, delimiter) = match node {
+            JsonNode::Null => (".", ValueDelimiter::None),
+            JsonNode::Literal(text) => (text.as_str(), ValueDelimiter::None),
+            JsonNode::String(text) => (text.as_str(), delimiter_for(text)),
+            JsonNode::Array(_) | JsonNode::Object(_) => {
+                return Err(Halt::Invalid(format!(
+                    "expected a value for {}, found an array or object",
+                    tag
+                )))
+            }
+        };
+        check(
+            self.handler
+                .data(tag, NO_POSITION, value, NO_POSITION, delimiter, loop_level),
+        )?;
+        Ok(())
+    }
+}
+
+/// The entries of an object, or an error naming `what` was expected to be one
+fn object<'n>(what: &str, node: &'n JsonNode) -> Result<&'n [(String, JsonNode)], Halt> {
+    match node {
+        JsonNode::Object(entries) => Ok(entries),
+        _ => Err(Halt::Invalid(format!("expected an object for {}", what))),
+    }
+}
+
+/// Collect the tags of each loop level from the first row of the level, outermost first
+fn loop_tags<'n>(rows: &'n [JsonNode], levels: &mut Vec<Vec<&'n str>>) -> Result<(), Halt> {
+    let Some(first) = rows.first() else {
+        return Ok(());
+    };
+    levels.push(
+        object("a loop row", first)?
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .filter(|key| *key != "loop_")
+            .collect(),
+    );
+    // the first row with nested rows gives the tags of the next level
+    for row in rows {
+        if let Some((_, JsonNode::Array(nested))) = object("a loop row", row)?
+            .iter()
+            .find(|(key, _)| key == "loop_")
+        {
+            if !nested.is_empty() {
+                return loop_tags(nested, levels);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A delimiter that makes `value` read back as the same text
+fn delimiter_for(value: &str) -> ValueDelimiter {
+    if value.contains(['\n', '\r']) {
+        return ValueDelimiter::Semicolon;
+    }
+    let unquoted = !value.is_empty()
+        && value != "."
+        && !collides_with_keyword(value)
+        && !value.starts_with(['#', '\'', '"', '[', ']', '{', '}', ';'])
+        && !value.contains(char::is_whitespace);
+    if unquoted {
+        ValueDelimiter::None
+    } else if !value.contains('\'') {
+        ValueDelimiter::Single
+    } else if !value.contains('"') {
+        ValueDelimiter::Double
+    } else {
+        ValueDelimiter::Semicolon
+    }
+}
@@ -0,0 +1,37 @@
+//! Legacy encodings - reading the 8-bit Latin-1 and Windows-1252 bytes of older STAR files.
+//!
+//! Windows-1252 is Latin-1 with printable characters in place of most of the C1 controls at
+//! 0x80-0x9F; the five bytes it leaves undefined decode to their C1 controls as in Latin-1. One
+//! decoder therefore reads both, and every character it decodes comes from a single byte.
+
+/// The characters of the Windows-1252 bytes 0x80 to 0x9F
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Decode Latin-1 or Windows-1252 `bytes`, one character per byte
+pub(crate) fn decode_windows_1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(byte - 0x80)],
+            _ => char::from(byte),
+        })
+        .collect()
+}
+
+/// Whether `character` is one of the 8-bit characters the extended ASCII grammar accepts,
+/// those of Latin-1 and those Windows-1252 puts at 0x80-0x9F
+pub(crate) fn is_extended_ascii(character: char) -> bool {
+    character <= '\u{FF}' || WINDOWS_1252_HIGH.contains(&character)
+}
+
+/// The offset in the original bytes of byte `offset` of `text` decoded one character per byte
+pub(crate) fn original_offset(text: &str, offset: usize) -> usize {
+    text.char_indices()
+        .take_while(|&(index, _)| index < offset)
+        .count()
+}
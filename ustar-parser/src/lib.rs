@@ -1,8 +1,19 @@
 use pest::Parser as PestParser;
+use std::path::Path;
+use std::sync::Arc;
+
+#[macro_use]
+mod trace;
 
 mod config;
 mod error_core;
+mod fragment;
+mod legacy_encoding;
+mod limits;
 pub mod parsers;
+mod prelex;
+mod progress;
+mod recovery;
 
 #[cfg(feature = "extended-errors")]
 mod extended_errors;
@@ -15,31 +26,111 @@ pub use extended_errors::UstarError;
 #[cfg(not(feature = "extended-errors"))]
 pub use simple_errors::UstarError;
 
+#[cfg(feature = "serde")]
+pub use config::ConfigFile;
 pub use config::{
-    default_config, get_context_lines, get_decomposed_strings, get_encoding, get_error_format,
-    ConfigKey, ConfigValue, EncodingMode, ErrorFormatMode, ParserConfig,
+    default_config, get_allow_data_outside_saveframes, get_allow_empty_loops, get_attach_comments,
+    get_auto_detect_bom, get_cif_version, get_column_unit, get_context_lines,
+    get_decomposed_strings, get_encoding, get_error_format, get_max_input_bytes,
+    get_max_line_length, get_max_name_length, get_max_nesting_depth, get_prelex,
+    get_require_stop_keyword, get_strict, get_unescape_quotes, get_validate, CifVersion,
+    ColumnUnit, ConfigError, ConfigKey, ConfigValue, Dialect, DialectPreset, EncodingMode,
+    ErrorFormatMode, ParserConfig, ParserConfigBuilder, DEFAULT_MAX_NESTING_DEPTH,
 };
+pub use error_core::ErrorCode;
 pub use parsers::Rule;
+pub use progress::{ParseProgress, ProgressCallback};
 
 // Re-export commonly used types for external use
 pub use pest::iterators::{Pair, Pairs};
 pub use pest::RuleType;
 
+/// The version of the ustar-parser crate, for reports of which parser read an input
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // Note: dump_extractors moved to ustar-cli crate
 
 // MutablePair - mutable alternative to Pair
 pub mod mutable_pair;
 
+// TreeArena - arena-backed tree with O(1) parent/sibling navigation
+pub mod tree_arena;
+
 // Buffered handler traits and walker
 pub mod sas_interface;
 pub mod sas_walker;
 
+// Ready made SAS handlers, such as building a document of blocks, save frames, items and loops
+pub mod sas_handlers;
+
 // String decomposer - transforms MutablePair strings to decomposed strings
 pub mod string_decomposer;
 
 // Fast line/column lookup index
 pub mod line_column_index;
 
+// Shared node text and interned rule names for MutablePair
+pub mod shared_text;
+
+// Lazy block-by-block parsing for large files
+pub mod block_iterator;
+
+// Post-parse checks of dialect restrictions and STAR semantics
+pub mod validate;
+
+// Non-fatal warnings about input that parses, such as mixed line endings
+pub mod diagnostics;
+
+// Typed interpretation of values, including numbers with uncertainties
+pub mod values;
+
+// DDL2 dictionaries and validation of trees against them
+pub mod dictionary;
+
+// Loops as tables of rows and columns, with nested loops as sub-tables
+pub mod loop_table;
+
+// Hash lookup of tag values by data block, save frame and data name
+pub mod tag_index;
+
+// Selecting nodes with STAR paths such as data_NAME/save_NAME/_tag
+pub mod query;
+
+// Renaming data names, such as between dictionary versions
+pub mod transform;
+
+// Merging an overlay document into a base document
+pub mod merge;
+
+// Differences between two documents by block, save frame, data item and loop row
+pub mod diff;
+
+// Keeping a parse up to date as its text is edited, reparsing only the blocks edits touch
+pub mod incremental;
+
+// mmCIF categories and atom_site coordinate columns
+pub mod mmcif;
+
+// Typed access to the standard save frames of NMR Exchange Format files
+#[cfg(feature = "nef")]
+pub mod nef;
+
+// Loops as Apache Arrow record batches
+#[cfg(feature = "arrow")]
+pub mod arrow_tables;
+
+// Replaying the JSON written by JsonHandler as SAS events
+#[cfg(feature = "serde")]
+pub mod json_reader;
+
+// Parsing files mapped into memory rather than read into a string
+#[cfg(feature = "mmap")]
+pub mod mmap;
+
+// JavaScript bindings for parsing in a browser
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 /// Configuration options for the USTAR parser
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum UstarConfiguration {
@@ -55,7 +146,7 @@ pub fn format_parse_error<R: pest::RuleType>(
     format_mode: ErrorFormatMode,
     context_lines: usize,
 ) -> String {
-    let ustar_error = UstarError::from_pest_error(pest_error.clone(), encoding, input);
+    let ustar_error = UstarError::from_pest_error(pest_error, encoding, input);
     ustar_error.format_error(format_mode, context_lines)
 }
 
@@ -66,21 +157,64 @@ fn process_pairs<'a, R>(
 where
     R: pest::RuleType,
 {
-    pairs
-        .map(|p| mutable_pair::MutablePair::from_pest_pair(&p))
-        .collect()
+    trace_span!(span, "process_pairs", roots = tracing::field::Empty);
+    let result: Vec<_> = pairs
+        .map(|p| {
+            let mut pair = mutable_pair::MutablePair::from_pest_pair(&p);
+            // CIF2 input uses its own entry rule but produces the same tree as star_file
+            if pair.rule_name == "cif2_star_file" {
+                pair.rule_name = "star_file".into();
+            }
+            pair
+        })
+        .collect();
+    trace_record!(span, "roots", result.len());
+    result
 }
 
 fn split_pairs_if_requested(pairs: &mut [mutable_pair::MutablePair], config: &ParserConfig) {
     if get_decomposed_strings(config) {
+        trace_span!(_span, "decompose_strings", roots = pairs.len());
+        let unescape_quotes = get_unescape_quotes(config);
+        for pair in pairs.iter_mut() {
+            string_decomposer::decompose_strings_with(pair, unescape_quotes);
+        }
+    }
+}
+
+/// Recount the positions of trees parsed from `input` when columns aren't counted in bytes
+fn count_columns_if_requested(
+    pairs: &mut [mutable_pair::MutablePair],
+    input: &str,
+    config: &ParserConfig,
+) {
+    let column_unit = config::get_column_unit(config);
+    if column_unit != ColumnUnit::Bytes {
+        let line_index =
+            line_column_index::LineColumnIndex::new(input).with_column_unit(column_unit);
         for pair in pairs.iter_mut() {
-            string_decomposer::decompose_strings(pair);
+            pair.recount_positions(&line_index);
         }
     }
 }
 
 /// Parse STAR format input with configuration options
 ///
+/// After parsing, the tree is checked against the dialect restrictions of the configuration,
+/// such as `ConfigKey::AllowEmptyLoops`, and the first violation is returned as an error.
+/// With `ConfigKey::Validate` set the tree is also checked with `validate::validate_tree`.
+/// Columns in the positions of the tree and of errors are counted in `ConfigKey::ColumnUnit`,
+/// bytes by default.
+///
+/// Before parsing, the input is checked against `ConfigKey::MaxInputBytes`, unlimited by
+/// default, and `ConfigKey::MaxNestingDepth`, 128 nested loops, lists or tables by default, so
+/// untrusted input can't exhaust memory or the stack. Input over a limit is a
+/// `UstarError::LimitExceeded` error at the position where the limit is exceeded.
+///
+/// With `ConfigKey::Prelex` set the whitespace, comments and text fields pest would read a
+/// character at a time are first compacted by a lexical scan, which more than halves the time
+/// taken on mmCIF dictionaries; the tree and errors are those of parsing the input as written.
+///
 /// # Arguments
 /// * `input` - The input string to parse
 /// * `config` - A map of configuration options to their values
@@ -90,6 +224,16 @@ fn split_pairs_if_requested(pairs: &mut [mutable_pair::MutablePair], config: &Pa
 pub fn parse(
     input: &str,
     config: &ParserConfig,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    trace_span!(_span, "parse", bytes = input.len());
+    let column_unit = config::get_column_unit(config);
+    parse_tree(input, config).map_err(|error| Box::new((*error).with_column_unit(column_unit)))
+}
+
+/// Parse and check the tree for `parse`, with errors counting columns in characters (private)
+fn parse_tree(
+    input: &str,
+    config: &ParserConfig,
 ) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
     // BOM auto-detection is controlled by config
     let auto_detect_bom = config::get_auto_detect_bom(config);
@@ -99,57 +243,791 @@ pub fn parse(
         (get_encoding(config), input)
     };
 
+    // CIF2 list and table values are only enabled through the cif2_star_file entry rule
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+
+    limits::check_limits(input_clean, config, cif2, encoding)?;
+
+    // CIF2 lists and tables quote strings by their own rules, which the pre-lexer doesn't follow
+    let prelexed = if config::get_prelex(config) && !cif2 {
+        trace_span!(
+            span,
+            "prelex",
+            bytes = input_clean.len(),
+            compacted = tracing::field::Empty
+        );
+        let prelexed = prelex::prelex(input_clean, encoding);
+        trace_record!(span, "compacted", prelexed.as_ref().map(|p| p.text().len()));
+        prelexed
+    } else {
+        None
+    };
+
+    macro_rules! parse_with {
+        ($module:ident, $parser:ident) => {{
+            use parsers::$module::Rule;
+            let rule = if cif2 {
+                Rule::cif2_star_file
+            } else {
+                Rule::star_file
+            };
+            // a compacted view that fails to parse is parsed again as written for its error
+            let compacted = prelexed.as_ref().and_then(|prelexed| {
+                let pairs = {
+                    trace_span!(
+                        _span,
+                        "pest",
+                        encoding = stringify!($module),
+                        bytes = prelexed.text().len(),
+                        compacted = true
+                    );
+                    parsers::$module::$parser::parse(rule, prelexed.text()).ok()?
+                };
+                Some(prelexed.to_mutable_pairs(pairs, input_clean))
+            });
+            match compacted {
+                Some(result) => result,
+                None => {
+                    let pairs = {
+                        trace_span!(
+                            _span,
+                            "pest",
+                            encoding = stringify!($module),
+                            bytes = input_clean.len(),
+                            compacted = false
+                        );
+                        parsers::$module::$parser::parse(rule, input_clean).map_err(|e| {
+                            Box::new(UstarError::from_pest_error(e, encoding, input_clean))
+                        })?
+                    };
+                    process_pairs(pairs)
+                }
+            }
+        }};
+    }
+
     // Choose the appropriate parser based on encoding mode
     let mut result = match encoding {
+        EncodingMode::Ascii => parse_with!(ascii, AsciiParser),
+        EncodingMode::ExtendedAscii => parse_with!(extended, ExtendedParser),
+        EncodingMode::Unicode => parse_with!(unicode, UnicodeParser),
+    };
+
+    split_pairs_if_requested(&mut result, config);
+    count_columns_if_requested(&mut result, input_clean, config);
+
+    // For now, return the first root pair or create an empty one
+    let tree = if result.is_empty() {
+        mutable_pair::MutablePair::new("star_file", String::new(), 0, 0)
+    } else if result.len() == 1 {
+        result.into_iter().next().unwrap()
+    } else {
+        // Multiple root elements - wrap them in a container
+        mutable_pair::MutablePair::with_children("star_file", input, 0, input.len(), result)
+    };
+
+    // dialect restrictions the grammar can't express
+    validate::check_dialect(&tree, input_clean, config, encoding)?;
+    if config::get_validate(config) {
+        validate::check_semantics(&tree, input_clean, encoding)?;
+    }
+    Ok(tree)
+}
+
+/// Parse with default configuration (ASCII mode, decomposed strings, fancy error formatting)
+pub fn parse_default(input: &str) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    parse(input, &default_config())
+}
+
+/// Parse STAR format input given as bytes, reading UTF-16 and legacy 8-bit encodings
+///
+/// With `ConfigKey::AutoDetectBom` set, bytes starting with a UTF-16LE or UTF-16BE byte order
+/// mark are decoded to UTF-8 and parsed in Unicode mode, as a UTF-8 byte order mark is; offsets
+/// of errors refer to the decoded text and their messages note the original encoding.
+///
+/// Bytes that are UTF-8 are parsed as `parse` parses their text. With `ConfigKey::Encoding` set
+/// to `ExtendedAscii`, bytes that aren't UTF-8 are decoded as Latin-1 / Windows-1252 and parsed
+/// with the extended ASCII grammar, the tree then holds the decoded text while the offsets of
+/// errors, and their columns when counted in bytes, refer to the original bytes. Otherwise bytes
+/// that aren't UTF-8 are an error at the first invalid byte.
+///
+/// # Arguments
+/// * `bytes` - The input to parse
+/// * `config` - A map of configuration options to their values
+///
+/// # Returns
+/// * `Result<mutable_pair::MutablePair, UstarError>` - Parsed result as a MutablePair tree, or an error with diagnostics
+pub fn parse_bytes(
+    bytes: &[u8],
+    config: &ParserConfig,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    match decode_star_bytes(bytes, config, "the input")? {
+        DecodedText::Utf8(text) => parse(text, config),
+        DecodedText::Utf16 { text, note } => {
+            parse(&text, config).map_err(|error| Box::new(error.with_note(&note)))
+        }
+        DecodedText::Windows1252(text) => {
+            parse(&text, config).map_err(|error| Box::new(error.in_original_bytes(&text)))
+        }
+    }
+}
+
+/// Parse a STAR file, naming it in any error
+///
+/// The file's bytes are parsed as `parse_bytes` parses them, errors from reading or parsing it
+/// carry the path as their source name so formatted errors say which file they are in.
+///
+/// # Arguments
+/// * `path` - The file to read
+/// * `config` - A map of configuration options to their values
+///
+/// # Returns
+/// * `Result<mutable_pair::MutablePair, UstarError>` - Parsed result as a MutablePair tree, or an error naming the file
+pub fn parse_file(
+    path: &Path,
+    config: &ParserConfig,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    let bytes = std::fs::read(path).map_err(|error| unreadable_file(path, error, config))?;
+    parse_bytes(&bytes, config)
+        .map_err(|error| Box::new(error.with_source_name(path.display().to_string())))
+}
+
+/// Parse a STAR file mapped into memory, naming it in any error
+///
+/// Unlike `parse_file` the file isn't first read into memory, a UTF-8 file is checked and
+/// parsed where it is mapped, so only the copy of the text the tree shares is made. The file is
+/// otherwise parsed as `parse_bytes` parses its bytes: for UTF-8 and legacy 8-bit files the
+/// offsets of errors are byte offsets into the file, after any UTF-8 byte order mark as for
+/// `parse`. See `mmap::MappedStarFile` to parse the blocks of a mapped file one at a time.
+///
+/// # Arguments
+/// * `path` - The file to map
+/// * `config` - A map of configuration options to their values
+///
+/// # Returns
+/// * `Result<mutable_pair::MutablePair, UstarError>` - Parsed result as a MutablePair tree, or an error naming the file
+#[cfg(feature = "mmap")]
+pub fn parse_mmap(
+    path: &Path,
+    config: &ParserConfig,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    mmap::MappedStarFile::open(path, config)?.parse(config)
+}
+
+/// Read a STAR file, decoding its bytes as text as `parse_bytes` decodes them
+///
+/// A UTF-8 byte order mark is kept so `ConfigKey::AutoDetectBom` applies as it does for
+/// `parse`, and with it set a UTF-16 file is decoded keeping its byte order mark as a leading
+/// U+FEFF. Files that aren't UTF-8 are read as Latin-1 / Windows-1252 when `ConfigKey::Encoding`
+/// is `ExtendedAscii`, otherwise they are an error at the first byte that isn't. Unlike
+/// `parse_file`, the offsets of errors from parsing the text refer to the text read.
+///
+/// # Returns
+/// * `Result<String, UstarError>` - The text of the file, or an error naming the file
+pub fn read_star_file(path: &Path, config: &ParserConfig) -> Result<String, Box<UstarError>> {
+    let bytes = std::fs::read(path).map_err(|error| unreadable_file(path, error, config))?;
+    let text = decode_star_bytes(&bytes, config, "the file")
+        .map_err(|error| Box::new(error.with_source_name(path.display().to_string())))?;
+    Ok(match text {
+        DecodedText::Utf8(text) => text.to_string(),
+        DecodedText::Utf16 { text, .. } | DecodedText::Windows1252(text) => text,
+    })
+}
+
+/// The error for a file at `path` that couldn't be read, naming the file (private)
+pub(crate) fn unreadable_file(
+    path: &Path,
+    error: std::io::Error,
+    config: &ParserConfig,
+) -> Box<UstarError> {
+    let error = validate::error_at(
+        "",
+        0,
+        ErrorCode::E0019UnreadableFile,
+        format!("could not read file: {}", error),
+        get_encoding(config),
+    );
+    Box::new(
+        error
+            .with_column_unit(config::get_column_unit(config))
+            .with_source_name(path.display().to_string()),
+    )
+}
+
+/// STAR input decoded from bytes, and how it was decoded (private)
+enum DecodedText<'b> {
+    /// The bytes were UTF-8
+    Utf8(&'b str),
+    /// The bytes were UTF-16, `note` names the byte order for errors
+    Utf16 { text: String, note: String },
+    /// The bytes were Latin-1 / Windows-1252, read in extended ASCII mode
+    Windows1252(String),
+}
+
+/// Decode STAR input given as bytes, as described for `parse_bytes`, naming the input `what`
+/// in an error at a byte that isn't UTF-8 (private)
+fn decode_star_bytes<'b>(
+    bytes: &'b [u8],
+    config: &ParserConfig,
+    what: &str,
+) -> Result<DecodedText<'b>, Box<UstarError>> {
+    let encoding = get_encoding(config);
+    let column_unit = config::get_column_unit(config);
+    if let Some((name, from_utf16)) =
+        utf16_bom(bytes).filter(|_| config::get_auto_detect_bom(config))
+    {
+        let note = format!("input decoded from {}", name);
+        let text = decode_utf16(bytes, from_utf16, encoding)
+            .map_err(|error| Box::new(error.with_column_unit(column_unit).with_note(&note)))?;
+        return Ok(DecodedText::Utf16 { text, note });
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(DecodedText::Utf8(text)),
+        Err(_) if encoding == EncodingMode::ExtendedAscii => Ok(DecodedText::Windows1252(
+            legacy_encoding::decode_windows_1252(bytes),
+        )),
+        Err(error) => {
+            let error = invalid_utf8(bytes, error, what, encoding);
+            Err(Box::new(error.with_column_unit(column_unit)))
+        }
+    }
+}
+
+/// Reads a UTF-16 code unit from its two bytes in one byte order (private)
+type ReadCodeUnit = fn([u8; 2]) -> u16;
+
+/// The UTF-16 encoding named by a byte order mark at the start of `bytes`, and how to read its
+/// code units (private)
+fn utf16_bom(bytes: &[u8]) -> Option<(&'static str, ReadCodeUnit)> {
+    match bytes {
+        [0xFF, 0xFE, ..] => Some(("UTF-16LE", u16::from_le_bytes)),
+        [0xFE, 0xFF, ..] => Some(("UTF-16BE", u16::from_be_bytes)),
+        _ => None,
+    }
+}
+
+/// Decode UTF-16 `bytes`, keeping their byte order mark as a leading U+FEFF (private)
+fn decode_utf16(
+    bytes: &[u8],
+    from_utf16: ReadCodeUnit,
+    encoding: EncodingMode,
+) -> Result<String, Box<UstarError>> {
+    let pairs = bytes.chunks_exact(2);
+    let odd_byte = !pairs.remainder().is_empty();
+    let mut text = String::with_capacity(bytes.len() / 2);
+    let mut invalid = None;
+    for unit in char::decode_utf16(pairs.map(|pair| from_utf16([pair[0], pair[1]]))) {
+        if unit.is_err() {
+            invalid.get_or_insert(text.len());
+        }
+        text.push(unit.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    if odd_byte {
+        invalid.get_or_insert(text.len());
+    }
+    match invalid {
+        Some(offset) => Err(validate::error_at(
+            &text,
+            offset,
+            ErrorCode::E0006InvalidCharacter,
+            "input with a UTF-16 byte order mark is not valid UTF-16".to_string(),
+            encoding,
+        )),
+        None => Ok(text),
+    }
+}
+
+/// The error at the first invalid byte of `bytes` that aren't UTF-8, `what` names the bytes (private)
+fn invalid_utf8(
+    bytes: &[u8],
+    error: std::str::Utf8Error,
+    what: &str,
+    encoding: EncodingMode,
+) -> Box<UstarError> {
+    let offset = error.valid_up_to();
+    validate::error_at(
+        &String::from_utf8_lossy(bytes),
+        offset,
+        ErrorCode::E0006InvalidCharacter,
+        format!("byte {} of {} is not valid UTF-8", offset, what),
+        encoding,
+    )
+}
+
+/// Parse STAR format input and walk it with a SAS content handler
+///
+/// The handler is driven straight from the pest parse without building a `MutablePair` tree,
+/// so a walk holds little more memory than the parser itself. The callbacks are the same as for
+/// walking the tree `parse` builds, whether or not strings are decomposed. A tree is only
+/// built when the configuration has dialect restrictions or asks for validation, as those
+/// checks need one. With `ConfigKey::AttachComments` set comments are delivered with the
+/// element that follows them, see `StarWalker::with_config`.
+///
+/// # Returns
+/// * `Result<bool, UstarError>` - Whether the handler stopped the walk, or a parse error with diagnostics
+pub fn walk<T: sas_interface::SASContentHandler>(
+    input: &str,
+    config: &ParserConfig,
+    handler: &mut T,
+) -> Result<bool, Box<UstarError>> {
+    walk_reporting(input, config, handler, None)
+}
+
+/// Walk STAR format input with a SAS content handler, reporting progress after each block
+///
+/// As `walk`, with `progress` called after each top level data or global block has been
+/// walked. Returning `ControlFlow::Break` from it stops the walk, which then fails with a
+/// `UstarError::Cancelled` at the end of the last block walked; the handler has been given
+/// every callback up to that point but no `end_stream`.
+///
+/// # Returns
+/// * `Result<bool, UstarError>` - Whether the handler stopped the walk, or a parse error or the cancellation with diagnostics
+pub fn walk_with_progress<T: sas_interface::SASContentHandler>(
+    input: &str,
+    config: &ParserConfig,
+    handler: &mut T,
+    progress: &mut ProgressCallback,
+) -> Result<bool, Box<UstarError>> {
+    walk_reporting(input, config, handler, Some(progress))
+}
+
+/// Walk for `walk` and `walk_with_progress`, reporting to `progress` if there is one (private)
+fn walk_reporting<T: sas_interface::SASContentHandler>(
+    input: &str,
+    config: &ParserConfig,
+    handler: &mut T,
+    progress: Option<&mut ProgressCallback>,
+) -> Result<bool, Box<UstarError>> {
+    trace_span!(
+        span,
+        "walk",
+        bytes = input.len(),
+        stopped = tracing::field::Empty
+    );
+    let auto_detect_bom = config::get_auto_detect_bom(config);
+    let (encoding, input_clean) = if auto_detect_bom && input.starts_with('\u{FEFF}') {
+        (EncodingMode::Unicode, &input[3..])
+    } else {
+        (get_encoding(config), input)
+    };
+    let column_unit = config::get_column_unit(config);
+    let mut walker = sas_walker::StarWalker::from_input(handler, input_clean).with_config(config);
+    if let Some(progress) = progress {
+        walker = walker.with_progress(progress);
+    }
+    let cancelled = |progress| {
+        let error = progress::cancelled_error(input_clean, progress, encoding);
+        Box::new((*error).with_column_unit(column_unit))
+    };
+
+    if validate::needs_tree_checks(config) {
+        let tree = parse(input, config)?;
+        let stopped = walker.walk_star_tree_buffered(&tree);
+        return walker
+            .cancelled()
+            .map_or(Ok(stopped), |at| Err(cancelled(at)));
+    }
+
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+    limits::check_limits(input_clean, config, cif2, encoding)
+        .map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+    let stopped = match encoding {
         EncodingMode::Ascii => {
-            let pairs =
-                parsers::ascii::AsciiParser::parse(parsers::ascii::Rule::star_file, input_clean)
-                    .map_err(|e| Box::new(UstarError::from_pest_error(e, encoding, input)))?;
-            process_pairs(pairs)
+            let rule = if cif2 {
+                parsers::ascii::Rule::cif2_star_file
+            } else {
+                parsers::ascii::Rule::star_file
+            };
+            let pairs = {
+                trace_span!(_span, "pest", encoding = "ascii", bytes = input_clean.len());
+                parsers::ascii::AsciiParser::parse(rule, input_clean)
+            }
+            .map_err(|e| {
+                Box::new(
+                    UstarError::from_pest_error(e, encoding, input_clean)
+                        .with_column_unit(column_unit),
+                )
+            })?;
+            walker.walk_pairs(pairs)
         }
         EncodingMode::ExtendedAscii => {
-            let pairs = parsers::extended::ExtendedParser::parse(
-                parsers::extended::Rule::star_file,
-                input_clean,
-            )
-            .map_err(|e| Box::new(UstarError::from_pest_error(e, encoding, input)))?;
-            process_pairs(pairs)
+            let rule = if cif2 {
+                parsers::extended::Rule::cif2_star_file
+            } else {
+                parsers::extended::Rule::star_file
+            };
+            let pairs = {
+                trace_span!(
+                    _span,
+                    "pest",
+                    encoding = "extended",
+                    bytes = input_clean.len()
+                );
+                parsers::extended::ExtendedParser::parse(rule, input_clean)
+            }
+            .map_err(|e| {
+                Box::new(
+                    UstarError::from_pest_error(e, encoding, input_clean)
+                        .with_column_unit(column_unit),
+                )
+            })?;
+            walker.walk_pairs(pairs)
         }
         EncodingMode::Unicode => {
-            let pairs = parsers::unicode::UnicodeParser::parse(
-                parsers::unicode::Rule::star_file,
-                input_clean,
+            let rule = if cif2 {
+                parsers::unicode::Rule::cif2_star_file
+            } else {
+                parsers::unicode::Rule::star_file
+            };
+            let pairs = {
+                trace_span!(
+                    _span,
+                    "pest",
+                    encoding = "unicode",
+                    bytes = input_clean.len()
+                );
+                parsers::unicode::UnicodeParser::parse(rule, input_clean)
+            }
+            .map_err(|e| {
+                Box::new(
+                    UstarError::from_pest_error(e, encoding, input_clean)
+                        .with_column_unit(column_unit),
+                )
+            })?;
+            walker.walk_pairs(pairs)
+        }
+    };
+    trace_record!(span, "stopped", stopped);
+    walker
+        .cancelled()
+        .map_or(Ok(stopped), |at| Err(cancelled(at)))
+}
+
+/// Parse STAR format input into an arena-backed tree
+///
+/// Produces the same tree as `parse`, stored in a `TreeArena` for O(1)
+/// parent/sibling navigation and cheap subtree moves. The arena is built straight from the
+/// pest parse; a `MutablePair` tree is only built when the configuration has dialect
+/// restrictions or asks for validation, as those checks need one.
+///
+/// # Returns
+/// * `Result<tree_arena::TreeArena, UstarError>` - Parsed result as a TreeArena with a single root, or an error with diagnostics
+pub fn parse_arena(
+    input: &str,
+    config: &ParserConfig,
+) -> Result<tree_arena::TreeArena, Box<UstarError>> {
+    trace_span!(_span, "parse_arena", bytes = input.len());
+    if validate::needs_tree_checks(config) {
+        return parse(input, config).map(tree_arena::TreeArena::from_mutable_pair);
+    }
+
+    let auto_detect_bom = config::get_auto_detect_bom(config);
+    let (encoding, input_clean) = if auto_detect_bom && input.starts_with('\u{FEFF}') {
+        (EncodingMode::Unicode, &input[3..])
+    } else {
+        (get_encoding(config), input)
+    };
+    let column_unit = config::get_column_unit(config);
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+    limits::check_limits(input_clean, config, cif2, encoding)
+        .map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+
+    let line_index =
+        line_column_index::LineColumnIndex::new(input_clean).with_column_unit(column_unit);
+    let source = Arc::new(input_clean.to_string());
+    let decompose = get_decomposed_strings(config);
+    let unescape = get_unescape_quotes(config);
+    macro_rules! arena_with {
+        ($module:ident, $parser:ident) => {{
+            use parsers::$module::Rule;
+            let rule = if cif2 {
+                Rule::cif2_star_file
+            } else {
+                Rule::star_file
+            };
+            let mut pairs = {
+                trace_span!(
+                    _span,
+                    "pest",
+                    encoding = stringify!($module),
+                    bytes = input_clean.len()
+                );
+                parsers::$module::$parser::parse(rule, input_clean)
+            }
+            .map_err(|e| {
+                Box::new(
+                    UstarError::from_pest_error(e, encoding, input_clean)
+                        .with_column_unit(column_unit),
+                )
+            })?;
+            // the entry rule matches the whole input as one pair
+            let pair = pairs.next().expect("a parse without a root pair");
+            tree_arena::TreeArena::from_pest_pair_with(
+                &pair,
+                &source,
+                &line_index,
+                decompose,
+                unescape,
             )
-            .map_err(|e| Box::new(UstarError::from_pest_error(e, encoding, input)))?;
-            process_pairs(pairs)
+        }};
+    }
+
+    let mut arena = match encoding {
+        EncodingMode::Ascii => arena_with!(ascii, AsciiParser),
+        EncodingMode::ExtendedAscii => arena_with!(extended, ExtendedParser),
+        EncodingMode::Unicode => arena_with!(unicode, UnicodeParser),
+    };
+    // CIF2 input uses its own entry rule but produces the same tree as star_file
+    let root = arena.roots()[0];
+    if arena.node(root).rule_name == "cif2_star_file" {
+        arena.node_mut(root).rule_name = "star_file".into();
+    }
+    Ok(arena)
+}
+
+/// Parse STAR format input, continuing past syntax errors
+///
+/// When the input fails to parse, the error is recorded and parsing resumes at the next
+/// `data_`, `save_`, `loop_` or `global_` keyword, so a single run reports every failing
+/// region. The returned tree holds everything that parsed and is `None` only when no data
+/// block could be recovered.
+///
+/// # Returns
+/// * `(Option<mutable_pair::MutablePair>, Vec<UstarError>)` - The (possibly partial) tree and one error per failed region; valid input gives the same tree as `parse` and no errors
+pub fn parse_with_recovery(
+    input: &str,
+    config: &ParserConfig,
+) -> (Option<mutable_pair::MutablePair>, Vec<UstarError>) {
+    let parse_error = match parse(input, config) {
+        Ok(tree) => return (Some(tree), Vec::new()),
+        // recovering would parse the input that is over the limit
+        Err(error) if matches!(*error, UstarError::LimitExceeded { .. }) => {
+            return (None, vec![*error]);
         }
+        Err(error) => error,
+    };
+
+    let auto_detect_bom = config::get_auto_detect_bom(config);
+    let (encoding, input_clean) = if auto_detect_bom && input.starts_with('\u{FEFF}') {
+        (EncodingMode::Unicode, &input[3..])
+    } else {
+        (get_encoding(config), input)
+    };
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+
+    macro_rules! recover_with {
+        ($module:ident, $parser:ident) => {{
+            use parsers::$module::Rule;
+            let rules = recovery::RecoveryRules {
+                data_heading: Rule::data_heading,
+                global_keyword: Rule::global_keyword,
+                save_heading: Rule::save_heading,
+                save_keyword: Rule::save_keyword,
+                data: if cif2 { Rule::cif2_data } else { Rule::data },
+            };
+            recovery::recover::<parsers::$module::$parser, Rule>(input_clean, &rules, encoding)
+        }};
+    }
+
+    let (blocks, mut errors) = match encoding {
+        EncodingMode::Ascii => recover_with!(ascii, AsciiParser),
+        EncodingMode::ExtendedAscii => recover_with!(extended, ExtendedParser),
+        EncodingMode::Unicode => recover_with!(unicode, UnicodeParser),
     };
 
+    // the full parse failed, so never report a clean recovery, parse has already recounted
+    // the columns of its error
+    let column_unit = config::get_column_unit(config);
+    if errors.is_empty() {
+        errors.push(*parse_error);
+    } else {
+        errors = errors
+            .into_iter()
+            .map(|error| error.with_column_unit(column_unit))
+            .collect();
+    }
+
+    if blocks.is_empty() {
+        return (None, errors);
+    }
+
+    let mut result = vec![star_file_root(input_clean.into(), blocks, column_unit)];
     split_pairs_if_requested(&mut result, config);
+    count_columns_if_requested(&mut result, input_clean, config);
 
-    // For now, return the first root pair or create an empty one
-    if result.is_empty() {
-        Ok(mutable_pair::MutablePair::new(
-            "star_file",
-            String::new(),
-            0,
-            0,
-        ))
-    } else if result.len() == 1 {
-        Ok(result.into_iter().next().unwrap())
+    (result.pop(), errors)
+}
+
+/// Parse STAR format input and collect warnings about it
+///
+/// The warnings, such as mixed line endings or loops ended by `save_` rather than `stop_`, are
+/// found by `diagnostics::diagnose` after parsing. Input that fails to parse is still checked
+/// for the warnings that need only its text.
+///
+/// # Returns
+/// * `(Result<mutable_pair::MutablePair, UstarError>, Vec<diagnostics::Diagnostic>)` - The result of `parse` and the warnings in the order of their positions
+pub fn parse_with_diagnostics(
+    input: &str,
+    config: &ParserConfig,
+) -> (
+    Result<mutable_pair::MutablePair, Box<UstarError>>,
+    Vec<diagnostics::Diagnostic>,
+) {
+    let result = parse(input, config);
+
+    let auto_detect_bom = config::get_auto_detect_bom(config);
+    let input_clean = if auto_detect_bom && input.starts_with('\u{FEFF}') {
+        &input[3..]
     } else {
-        // Multiple root elements - wrap them in a container
-        Ok(mutable_pair::MutablePair::with_children(
-            "star_file",
-            input,
-            0,
-            input.len(),
-            result,
-        ))
+        input
+    };
+    let line_index = line_column_index::LineColumnIndex::new(input_clean)
+        .with_column_unit(config::get_column_unit(config));
+    let diagnostics = diagnostics::diagnose(result.as_ref().ok(), &line_index);
+
+    (result, diagnostics)
+}
+
+/// The `star_file` root that `parse` produces for `content` when it holds `blocks`
+fn star_file_root(
+    content: shared_text::SharedText,
+    blocks: Vec<mutable_pair::MutablePair>,
+    column_unit: ColumnUnit,
+) -> mutable_pair::MutablePair {
+    let len = content.len();
+    let end_position = line_column_index::LineColumnIndex::new(&content)
+        .with_column_unit(column_unit)
+        .offset_to_line_col(len);
+    let mut eoi = mutable_pair::MutablePair::new("EOI", content.slice(len, len), len, len);
+    eoi.start_position = end_position;
+    eoi.end_position = end_position;
+    let mut children = blocks;
+    children.push(eoi);
+    let mut root = mutable_pair::MutablePair::with_children("star_file", content, 0, len, children);
+    root.start_position = line_column_index::LineColumn::new(1, 1);
+    root.end_position = end_position;
+    root
+}
+
+/// Parse STAR format input one block at a time, reporting progress after each block
+///
+/// Produces the same tree as `parse`, with `progress` called after each top level data or
+/// global block has been parsed. Returning `ControlFlow::Break` from it cancels the parse,
+/// which then fails with a `UstarError::Cancelled` at the end of the last block parsed.
+///
+/// # Returns
+/// * `Result<mutable_pair::MutablePair, UstarError>` - The same tree as `parse`, or the error for the first block that fails to parse or the cancellation with diagnostics
+pub fn parse_with_progress(
+    input: &str,
+    config: &ParserConfig,
+    progress: &mut ProgressCallback,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    let mut blocks = block_iterator::DataBlockIterator::new(input, config).with_shared_source();
+    let column_unit = config::get_column_unit(config);
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+    limits::check_limits(blocks.source(), config, cif2, blocks.encoding())
+        .map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+
+    let mut parsed = Vec::new();
+    while let Some((start, end, origin)) = blocks.next_range() {
+        parsed.push(blocks.parse_block(start, end, origin)?);
+        let report = ParseProgress {
+            bytes_consumed: end,
+            total_bytes: blocks.source().len(),
+            blocks_completed: parsed.len(),
+        };
+        if progress(report).is_break() {
+            let error = progress::cancelled_error(blocks.source(), report, blocks.encoding());
+            return Err(Box::new((*error).with_column_unit(column_unit)));
+        }
     }
+    // input without blocks has no progress to report
+    if parsed.is_empty() {
+        return parse(input, config);
+    }
+    block_tree(
+        blocks.source(),
+        blocks.encoding(),
+        parsed,
+        config,
+        column_unit,
+    )
 }
 
-/// Parse with default configuration (ASCII mode, decomposed strings, fancy error formatting)
-pub fn parse_default(input: &str) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
-    parse(input, &default_config())
+/// The `star_file` tree of the blocks `parsed` from `source` in `encoding`, checked as `parse`
+/// checks it (private)
+fn block_tree(
+    source: &Arc<String>,
+    encoding: EncodingMode,
+    parsed: Vec<mutable_pair::MutablePair>,
+    config: &ParserConfig,
+    column_unit: ColumnUnit,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    let content = shared_text::SharedText::new(source.clone(), 0, source.len());
+    let tree = star_file_root(content, parsed, column_unit);
+    let checked = validate::check_dialect(&tree, source, config, encoding).and_then(|()| {
+        if config::get_validate(config) {
+            validate::check_semantics(&tree, source, encoding)
+        } else {
+            Ok(())
+        }
+    });
+    checked.map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+    Ok(tree)
+}
+
+/// Iterate over the data and global blocks of STAR format input, parsing one block at a time
+///
+/// Each block is parsed only when the iterator reaches it, so callers can process and drop a
+/// block before the next is parsed instead of holding a tree for the whole document.
+/// Blocks are checked against the dialect restrictions of `config`, and validated when it asks
+/// for validation, as `parse` checks the whole tree.
+///
+/// # Returns
+/// * `block_iterator::DataBlockIterator` - Yields each block in document order as a MutablePair, or an error with diagnostics for a block that fails to parse
+pub fn iter_blocks<'i>(
+    input: &'i str,
+    config: &ParserConfig,
+) -> block_iterator::DataBlockIterator<'i> {
+    block_iterator::DataBlockIterator::new(input, config)
+}
+
+/// Parse STAR format input, parsing its data and global blocks in parallel
+///
+/// Block boundaries are found with a cheap lexical scan and each block is parsed on a rayon
+/// worker thread, then the blocks are gathered in document order under a `star_file` root.
+///
+/// # Returns
+/// * `Result<mutable_pair::MutablePair, UstarError>` - The same tree as `parse`, or the error for the first block that fails to parse with positions in the whole input
+#[cfg(feature = "rayon")]
+pub fn parse_parallel(
+    input: &str,
+    config: &ParserConfig,
+) -> Result<mutable_pair::MutablePair, Box<UstarError>> {
+    use rayon::prelude::*;
+
+    let mut blocks = block_iterator::DataBlockIterator::new(input, config).with_shared_source();
+    let column_unit = config::get_column_unit(config);
+    let cif2 = config::get_cif_version(config) == CifVersion::Cif2;
+    limits::check_limits(blocks.source(), config, cif2, blocks.encoding())
+        .map_err(|error| Box::new((*error).with_column_unit(column_unit)))?;
+    let ranges: Vec<_> = std::iter::from_fn(|| blocks.next_range()).collect();
+    // splitting a single block only adds work
+    if ranges.len() < 2 {
+        return parse(input, config);
+    }
+
+    let parsed: Vec<_> = ranges
+        .into_par_iter()
+        .map(|(start, end, origin)| blocks.parse_block(start, end, origin))
+        .collect();
+    // report the first failing block in the document, whichever thread finished first
+    let parsed = parsed.into_iter().collect::<Result<Vec<_>, _>>()?;
+    block_tree(
+        blocks.source(),
+        blocks.encoding(),
+        parsed,
+        config,
+        column_unit,
+    )
 }
@@ -0,0 +1,186 @@
+//! Limits - checks of untrusted input made before it is parsed.
+//!
+//! pest parses nested loops and CIF 2.0 lists and tables by recursion, so deeply nested input
+//! overflows the stack inside pest before there is a tree to check. The nesting depth is
+//! therefore found by a lexical scan that skips comments, quoted strings and text fields, and
+//! the input size is checked before anything else is done with the input.
+
+use crate::config::{get_max_input_bytes, get_max_nesting_depth};
+use crate::validate::error_at;
+use crate::{EncodingMode, ErrorCode, ParserConfig, UstarError};
+
+/// Check `input` against the size and nesting limits of `config`
+///
+/// # Returns
+/// * `Result<(), UstarError>` - Nothing, or a `UstarError::LimitExceeded` at the first byte over the size limit or the opener nested too deeply
+pub(crate) fn check_limits(
+    input: &str,
+    config: &ParserConfig,
+    cif2: bool,
+    encoding: EncodingMode,
+) -> Result<(), Box<UstarError>> {
+    if let Some(max_bytes) = get_max_input_bytes(config) {
+        if input.len() > max_bytes {
+            let offset = floor_char_boundary(input, max_bytes);
+            return Err(limit_error(
+                input,
+                offset,
+                ErrorCode::E0020InputTooLarge,
+                format!(
+                    "input of {} bytes is larger than the maximum of {} bytes",
+                    input.len(),
+                    max_bytes
+                ),
+                encoding,
+            ));
+        }
+    }
+    let max_depth = get_max_nesting_depth(config);
+    check_nesting(input, 0, input.len(), max_depth, cif2, encoding)
+}
+
+/// Check that nothing in `input[start..end]` is nested more than `max_depth` deep
+pub(crate) fn check_nesting(
+    input: &str,
+    start: usize,
+    end: usize,
+    max_depth: usize,
+    cif2: bool,
+    encoding: EncodingMode,
+) -> Result<(), Box<UstarError>> {
+    match find_excess_nesting(&input[start..end], cif2, max_depth) {
+        Some((offset, what)) => Err(limit_error(
+            input,
+            start + offset,
+            ErrorCode::E0021NestingTooDeep,
+            format!("{} nested deeper than the maximum of {}", what, max_depth),
+            encoding,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// An error at `offset` reported as a limit being exceeded (private)
+fn limit_error(
+    input: &str,
+    offset: usize,
+    code: ErrorCode,
+    message: String,
+    encoding: EncodingMode,
+) -> Box<UstarError> {
+    Box::new(error_at(input, offset, code, message, encoding).into_limit_exceeded())
+}
+
+/// The largest char boundary of `text` at or before `index` (private)
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+/// Find the first `loop_`, `[` or `{` of `text` nested more than `max_depth` deep
+///
+/// Loops nest through the loop definitions, so the depth of a loop is the number of `loop_`
+/// keywords open before the first value, less those closed by `stop_`. Lists and tables are
+/// only counted for CIF 2.0, where brackets aren't ordinary characters.
+///
+/// # Returns
+/// * `Option<(usize, &str)>` - The byte offset of the opener and what it opens, `None` if nothing is nested too deeply
+fn find_excess_nesting(text: &str, cif2: bool, max_depth: usize) -> Option<(usize, &'static str)> {
+    let bytes = text.as_bytes();
+    let mut loop_depth = 0;
+    let mut in_loop_definition = false;
+    let mut bracket_depth = 0;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let byte = bytes[pos];
+        let line_start = pos == 0 || bytes[pos - 1] == b'\n';
+        match byte {
+            b' ' | b'\t' | b'\r' | b'\n' => pos += 1,
+            b'#' => pos = line_end(bytes, pos),
+            b';' if line_start => {
+                // a text field runs to the next line starting with `;`
+                pos = match memchr::memmem::find(&bytes[pos + 1..], b"\n;") {
+                    Some(close) => pos + 1 + close + 2,
+                    None => bytes.len(),
+                };
+                in_loop_definition = false;
+            }
+            b'\'' | b'"' => {
+                pos = quoted_end(bytes, pos, cif2);
+                in_loop_definition = false;
+            }
+            b'[' | b'{' if cif2 => {
+                bracket_depth += 1;
+                if bracket_depth > max_depth {
+                    return Some((pos, if byte == b'[' { "list" } else { "table" }));
+                }
+                in_loop_definition = false;
+                pos += 1;
+            }
+            b']' | b'}' if cif2 => {
+                bracket_depth = usize::saturating_sub(bracket_depth, 1);
+                pos += 1;
+            }
+            _ => {
+                let token_end = bytes[pos..]
+                    .iter()
+                    .position(|&byte| {
+                        matches!(byte, b' ' | b'\t' | b'\r' | b'\n')
+                            || (cif2 && b"[]{}".contains(&byte))
+                    })
+                    .map_or(bytes.len(), |length| pos + length);
+                let token = &bytes[pos..token_end];
+                if token.eq_ignore_ascii_case(b"loop_") {
+                    loop_depth = if in_loop_definition {
+                        loop_depth + 1
+                    } else {
+                        1
+                    };
+                    in_loop_definition = true;
+                    if loop_depth > max_depth {
+                        return Some((pos, "loop"));
+                    }
+                } else if token.eq_ignore_ascii_case(b"stop_") {
+                    loop_depth = usize::saturating_sub(loop_depth, 1);
+                } else if token[0] != b'_' {
+                    in_loop_definition = false;
+                }
+                pos = token_end;
+            }
+        }
+    }
+    None
+}
+
+/// The offset of the newline ending the line holding `pos`, or the end of `bytes` (private)
+fn line_end(bytes: &[u8], pos: usize) -> usize {
+    memchr::memchr(b'\n', &bytes[pos..]).map_or(bytes.len(), |length| pos + length)
+}
+
+/// The offset after the quoted string opened at `pos` (private)
+///
+/// Triple quotes close at the next triple quote. A single quote closes at the next quote on its
+/// line, which for STAR 1 must be followed by a blank, an unclosed quote ends at its line end.
+fn quoted_end(bytes: &[u8], pos: usize, cif2: bool) -> usize {
+    let quote = bytes[pos];
+    let triple = [quote; 3];
+    if bytes[pos..].starts_with(&triple) {
+        return match memchr::memmem::find(&bytes[pos + 3..], &triple) {
+            Some(close) => pos + 3 + close + 3,
+            None => bytes.len(),
+        };
+    }
+    let end = line_end(bytes, pos);
+    (pos + 1..end)
+        .find(|&index| {
+            bytes[index] == quote
+                && (cif2
+                    || bytes
+                        .get(index + 1)
+                        .is_none_or(|after| after.is_ascii_whitespace()))
+        })
+        .map_or(end, |close| close + 1)
+}
@@ -2,6 +2,11 @@
 //!
 //! This module provides efficient O(log n) line and column lookups from byte offsets
 //! by precomputing line start positions once and using binary search with optimizations.
+//! Build one with `LineColumnIndex::new` to convert offsets of your own, for instance the
+//! `start` and `end` of a `MutablePair`, and use `line_text` to show the line they are on.
+//! Columns are counted in bytes unless another `ColumnUnit` is chosen with `with_column_unit`.
+
+use crate::config::ColumnUnit;
 
 /// Sentinel value for undefined line number (since line numbers are 1-based)
 const UNDEFINED_LINE: usize = 0;
@@ -11,6 +16,7 @@ const UNDEFINED_COLUMN: usize = 0;
 
 /// Line and column position in a text file (1-based)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LineColumn {
     /// Line number (1-based, 0 indicates undefined)
     pub line: usize,
@@ -39,17 +45,26 @@ impl LineColumn {
 }
 
 /// A fast index for converting byte offsets to line and column numbers
+///
+/// The offsets of the line starts are found once, each lookup is then a binary search over
+/// them. `offset_to_line_col` counts columns in the column unit, bytes by default as in the
+/// positions stored in parse trees; `position` counts them in characters, or grapheme clusters
+/// for `ColumnUnit::Graphemes`, with tabs expanded to the tab width.
 #[derive(Debug, Clone)]
-pub struct LineColumnIndex {
+pub struct LineColumnIndex<'a> {
+    /// The indexed input
+    input: &'a str,
     /// Byte offsets where each line starts (including 0 for line 1)
     line_starts: Vec<usize>,
-    /// Total input length for bounds checking
-    input_len: usize,
+    /// Columns from one tab stop to the next, 1 counts a tab as a single column
+    tab_width: usize,
+    /// Unit `offset_to_line_col` counts columns in
+    column_unit: ColumnUnit,
 }
 
-impl LineColumnIndex {
+impl<'a> LineColumnIndex<'a> {
     /// Create a new LineColumnIndex by scanning the input once
-    pub fn new(input: &str) -> Self {
+    pub fn new(input: &'a str) -> Self {
         let mut line_starts = Vec::with_capacity(input.len() / 50); // Estimate ~50 chars per line
         line_starts.push(0);
 
@@ -62,36 +77,185 @@ impl LineColumnIndex {
         }
 
         Self {
+            input,
             line_starts,
-            input_len: input.len(),
+            tab_width: 1,
+            column_unit: ColumnUnit::Bytes,
         }
     }
 
-    /// Convert a byte offset to LineColumn coordinates (1-based)
+    /// Set the columns between tab stops used by `position`, the default of 1 counts a tab as
+    /// one column; a width of 0 is treated as 1
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// Set the unit columns are counted in, the default counts bytes
+    pub fn with_column_unit(mut self, column_unit: ColumnUnit) -> Self {
+        self.column_unit = column_unit;
+        self
+    }
+
+    /// The unit columns are counted in
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.column_unit
+    }
+
+    /// The indexed input
+    pub fn input(&self) -> &'a str {
+        self.input
+    }
+
+    /// Number of lines, a final line without a newline counts, an empty input has one line
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Text of a line (1-based) without its line ending, `None` for lines outside the input
+    pub fn line_text(&self, line: usize) -> Option<&'a str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.input.len(), |&next| next - 1);
+        let text = &self.input[start..end];
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// Line (1-based) holding a byte offset, found by binary search (private)
+    fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line_idx) => line_idx + 1,
+            Err(line_idx) => line_idx,
+        }
+    }
+
+    /// Convert a byte offset to LineColumn coordinates (1-based) with the column counted in the
+    /// column unit. An offset inside a character or grapheme cluster gives its column.
     pub fn offset_to_line_col(&self, offset: usize) -> LineColumn {
-        if offset > self.input_len {
+        if offset > self.input.len() {
             // Handle out-of-bounds gracefully
             return LineColumn::new(self.line_starts.len(), 1);
         }
 
-        // Binary search to find the line
-        match self.line_starts.binary_search(&offset) {
-            Ok(line_idx) => {
-                // Exact match - this offset is at the start of a line
-                LineColumn::new(line_idx + 1, 1)
-            }
-            Err(line_idx) => {
-                // offset falls between line_starts[line_idx-1] and line_starts[line_idx]
-                if line_idx == 0 {
-                    // Before first line (shouldn't happen with valid input)
-                    LineColumn::undefined()
-                } else {
-                    let line_number = line_idx; // 1-based line number
-                    let line_start = self.line_starts[line_idx - 1];
-                    let column = offset - line_start + 1; // 1-based column
-                    LineColumn::new(line_number, column)
+        let line_number = self.line_of(offset);
+        let line_start = self.line_starts[line_number - 1];
+        let before = &self.input.as_bytes()[line_start..offset];
+        if self.column_unit == ColumnUnit::Bytes || before.is_ascii() {
+            return LineColumn::new(line_number, before.len() + 1); // 1-based column
+        }
+
+        let mut boundary = offset;
+        while !self.input.is_char_boundary(boundary) {
+            boundary -= 1;
+        }
+        let before = &self.input[line_start..boundary];
+        let mut column = count_columns(before, self.column_unit) + 1;
+        if self.column_unit == ColumnUnit::Graphemes {
+            let next = self.input[boundary..].chars().next();
+            if let (Some(previous), Some(next)) = (before.chars().next_back(), next) {
+                if extends_cluster(previous, next) {
+                    column -= 1;
                 }
             }
         }
+        LineColumn::new(line_number, column)
     }
+
+    /// Convert a byte offset to LineColumn coordinates (1-based) with the column counted in
+    /// characters, a tab moving to the next tab stop. An offset inside a multibyte character
+    /// gives the column of that character. With `ColumnUnit::Graphemes` characters that extend
+    /// a grapheme cluster take no column of their own.
+    pub fn position(&self, offset: usize) -> LineColumn {
+        if offset > self.input.len() {
+            return LineColumn::new(self.line_starts.len(), 1);
+        }
+
+        let line_number = self.line_of(offset);
+        let line_start = self.line_starts[line_number - 1];
+        let before = &self.input.as_bytes()[line_start..offset];
+        if before.is_ascii() && (self.tab_width == 1 || memchr::memchr(b'\t', before).is_none()) {
+            return LineColumn::new(line_number, before.len() + 1);
+        }
+
+        let graphemes = self.column_unit == ColumnUnit::Graphemes;
+        let mut column = 1;
+        let mut previous = None;
+        for (index, character) in self.input[line_start..].char_indices() {
+            if line_start + index + character.len_utf8() > offset {
+                break;
+            }
+            let extends = graphemes && previous.is_some_and(|p| extends_cluster(p, character));
+            column = match character {
+                _ if extends => column,
+                '\t' => (column - 1) / self.tab_width * self.tab_width + self.tab_width + 1,
+                _ => column + 1,
+            };
+            previous = Some(character);
+        }
+        LineColumn::new(line_number, column)
+    }
+}
+
+/// Number of columns `text` takes in `unit`, with no special treatment of tabs
+pub fn count_columns(text: &str, unit: ColumnUnit) -> usize {
+    match unit {
+        ColumnUnit::Bytes => text.len(),
+        ColumnUnit::Chars => text.chars().count(),
+        ColumnUnit::Graphemes => {
+            let mut previous = None;
+            text.chars()
+                .filter(|&character| {
+                    let starts = !previous.is_some_and(|p| extends_cluster(p, character));
+                    previous = Some(character);
+                    starts
+                })
+                .count()
+        }
+    }
+}
+
+/// The start of `line` before `column` (1-based) counted in `unit`, the whole line if the
+/// column is past its end (private)
+pub(crate) fn column_prefix(line: &str, column: usize, unit: ColumnUnit) -> &str {
+    let mut columns = column.saturating_sub(1);
+    let mut previous = None;
+    for (index, character) in line.char_indices() {
+        let width = match unit {
+            ColumnUnit::Bytes => character.len_utf8(),
+            ColumnUnit::Chars => 1,
+            ColumnUnit::Graphemes => {
+                usize::from(!previous.is_some_and(|p| extends_cluster(p, character)))
+            }
+        };
+        if width > columns {
+            return &line[..index];
+        }
+        columns -= width;
+        previous = Some(character);
+    }
+    line
+}
+
+/// Whether `character` continues the grapheme cluster of the `previous` character (private)
+///
+/// An approximation of the Unicode segmentation rules that covers the common cases: combining
+/// marks, variation selectors, emoji modifiers and tags, zero width joiner sequences and CR LF.
+fn extends_cluster(previous: char, character: char) -> bool {
+    previous == '\u{200D}'
+        || (previous == '\r' && character == '\n')
+        || matches!(
+            character,
+            '\u{0300}'..='\u{036F}'
+                | '\u{1AB0}'..='\u{1AFF}'
+                | '\u{1DC0}'..='\u{1DFF}'
+                | '\u{200D}'
+                | '\u{20D0}'..='\u{20FF}'
+                | '\u{FE00}'..='\u{FE0F}'
+                | '\u{FE20}'..='\u{FE2F}'
+                | '\u{1F3FB}'..='\u{1F3FF}'
+                | '\u{E0020}'..='\u{E007F}'
+                | '\u{E0100}'..='\u{E01EF}'
+        )
 }
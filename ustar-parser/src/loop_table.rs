@@ -0,0 +1,254 @@
+//! Loop tables - the values of a loop as rows and columns.
+//!
+//! A loop in the parse tree is a flat list of values after its data names. `LoopTable` splits
+//! them into rows of one value per column, so values can be read by row or by column without
+//! counting. In a nested loop each row of an outer level is followed by the rows of the next
+//! level, ended by `stop_`; these are the sub-table of that row, see `LoopTable::nested`.
+//!
+//! Values are the text reported by the walker: the content of quoted strings and text fields
+//! without their delimiters.
+
+use crate::line_column_index::LineColumn;
+use crate::mutable_pair::{MutablePair, PairNode};
+use crate::values::value_text;
+use std::fmt;
+
+/// The values of one level of a loop arranged as rows of its columns, of a `MutablePair` tree
+/// or of any other `PairNode` backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopTable<'a, N = &'a MutablePair> {
+    columns: Vec<String>,
+    values: Vec<&'a str>,
+    /// The node of each value
+    nodes: Vec<N>,
+    /// Where each value starts
+    positions: Vec<LineColumn>,
+    /// The sub-table of each row when the loop has a nested level, empty otherwise
+    nested: Vec<LoopTable<'a, N>>,
+    has_nested_level: bool,
+    position: LineColumn,
+}
+
+/// Errors from building a `LoopTable`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopTableError {
+    /// The node is neither a loop nor holds one
+    NotALoop { rule: String, position: LineColumn },
+    /// A level of the loop whose values don't fill its last row
+    RaggedLoop {
+        /// Where the incomplete row starts
+        position: LineColumn,
+        /// The nesting level of the values, 1 for the outermost loop
+        level: usize,
+        columns: usize,
+        /// The values of the (sub-)table including the incomplete row
+        values: usize,
+    },
+}
+
+impl fmt::Display for LoopTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopTableError::NotALoop { rule, position } => write!(
+                f,
+                "l{}:c{} {} is not a loop",
+                position.line, position.column, rule
+            ),
+            LoopTableError::RaggedLoop {
+                position,
+                level,
+                columns,
+                values,
+            } => write!(
+                f,
+                "l{}:c{} loop level {} has {} values, which is not a multiple of its {} columns",
+                position.line, position.column, level, values, columns
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoopTableError {}
+
+/// The data names and position of each level of a loop, outermost first
+type Levels = Vec<(Vec<String>, LineColumn)>;
+
+impl<'a, N: PairNode<'a>> LoopTable<'a, N> {
+    /// Build the table of a `data_loop` node, or of the first loop in `pair`, such as a `data`
+    ///
+    /// Fails if the values of any level don't fill its last row.
+    pub fn from_pair(pair: N) -> Result<LoopTable<'a, N>, LoopTableError> {
+        let data_loop = if pair.rule_name() == "data_loop" {
+            Some(pair)
+        } else {
+            pair.find_first_node("data_loop")
+        };
+        let Some(data_loop) = data_loop else {
+            return Err(LoopTableError::NotALoop {
+                rule: pair.rule_name().to_string(),
+                position: pair.start_line_column(),
+            });
+        };
+
+        let mut levels = Levels::new();
+        if let Some(definition) = data_loop.find_first_node("data_loop_definition") {
+            collect_levels(definition, data_loop.start_line_column(), &mut levels);
+        }
+        let values: Vec<N> = data_loop
+            .find_first_node("data_loop_values")
+            .map(|values| {
+                values
+                    .child_nodes()
+                    .filter(|value| value.rule_name() != "comment")
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut index = 0;
+        read_rows(&levels, 0, &values, &mut index)
+    }
+
+    /// The data names of the columns
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Where the loop keyword of this level starts
+    pub fn position(&self) -> LineColumn {
+        self.position
+    }
+
+    /// The number of rows
+    pub fn row_count(&self) -> usize {
+        self.values.len() / self.columns.len().max(1)
+    }
+
+    /// The rows, each holding a value for every column
+    pub fn rows(&self) -> impl Iterator<Item = &[&'a str]> {
+        self.values.chunks(self.columns.len().max(1))
+    }
+
+    /// The values of the column of `tag`, comparing data names case-insensitively, or `None`
+    /// if the table has no such column
+    pub fn column(&self, tag: &str) -> Option<impl Iterator<Item = &'a str> + '_> {
+        let index = self
+            .columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(tag))?;
+        Some(
+            self.values
+                .iter()
+                .skip(index)
+                .step_by(self.columns.len())
+                .copied(),
+        )
+    }
+
+    /// The value of `column` in row `row_index`, `None` if there is no such value
+    pub fn value(&self, row_index: usize, column: usize) -> Option<&'a str> {
+        self.value_index(row_index, column)
+            .map(|index| self.values[index])
+    }
+
+    /// The node of the value of `column` in row `row_index`, `None` if there is no such value
+    pub fn value_node(&self, row_index: usize, column: usize) -> Option<N> {
+        self.value_index(row_index, column)
+            .map(|index| self.nodes[index])
+    }
+
+    /// Where the value of `column` in row `row_index` starts, `None` if there is no such value
+    pub fn value_position(&self, row_index: usize, column: usize) -> Option<LineColumn> {
+        self.value_index(row_index, column)
+            .map(|index| self.positions[index])
+    }
+
+    /// The index in `values` of `column` in row `row_index`, if there is such a value
+    fn value_index(&self, row_index: usize, column: usize) -> Option<usize> {
+        let index = row_index * self.columns.len() + column;
+        (column < self.columns.len() && index < self.values.len()).then_some(index)
+    }
+
+    /// The sub-table of the nested loop rows following row `row_index`, `None` if the loop has
+    /// no nested level or there is no such row
+    pub fn nested(&self, row_index: usize) -> Option<&LoopTable<'a, N>> {
+        self.nested.get(row_index)
+    }
+
+    /// Check if the loop has a nested level below this one, even if it has no rows
+    pub fn is_nested(&self) -> bool {
+        self.has_nested_level
+    }
+}
+
+/// Add the data names of `definition` and its nested loops to `levels`, `position` being
+/// where the loop keyword of `definition` starts
+fn collect_levels<'a, N: PairNode<'a>>(definition: N, position: LineColumn, levels: &mut Levels) {
+    levels.push((Vec::new(), position));
+    let level = levels.len() - 1;
+    for child in definition.child_nodes() {
+        match child.rule_name() {
+            "data_name" => levels[level].0.push(child.as_str().to_string()),
+            "nested_loop" => collect_levels(child, child.start_line_column(), levels),
+            _ => {}
+        }
+    }
+}
+
+/// Read the rows of `level` from `values[index..]`, up to the `stop_` that ends a nested level
+/// or the end of the values
+fn read_rows<'a, N: PairNode<'a>>(
+    levels: &Levels,
+    level: usize,
+    values: &[N],
+    index: &mut usize,
+) -> Result<LoopTable<'a, N>, LoopTableError> {
+    let (columns, position) = &levels[level];
+    let mut table = LoopTable {
+        columns: columns.clone(),
+        values: Vec::new(),
+        nodes: Vec::new(),
+        positions: Vec::new(),
+        nested: Vec::new(),
+        has_nested_level: level + 1 < levels.len(),
+        position: *position,
+    };
+    let is_stop = |value: N| value.rule_name() == "stop_keyword";
+
+    while let Some(&first) = values.get(*index) {
+        if is_stop(first) {
+            *index += 1;
+            // a stop_ ends a nested level, the outermost level runs to the end of the values
+            if level > 0 {
+                break;
+            }
+            continue;
+        }
+
+        for _ in 0..columns.len() {
+            match values.get(*index) {
+                Some(&value) if !is_stop(value) => {
+                    table.values.push(value_text(value));
+                    table.nodes.push(value);
+                    table.positions.push(value.start_line_column());
+                    *index += 1;
+                }
+                _ => {
+                    return Err(LoopTableError::RaggedLoop {
+                        position: first.start_line_column(),
+                        level: level + 1,
+                        columns: columns.len(),
+                        values: table.values.len(),
+                    })
+                }
+            }
+        }
+
+        if table.has_nested_level {
+            table
+                .nested
+                .push(read_rows(levels, level + 1, values, index)?);
+        }
+    }
+
+    Ok(table)
+}
@@ -0,0 +1,421 @@
+//! Merge - overlaying one parsed STAR document on another.
+//!
+//! `merge` combines a base and an overlay tree, such as a NEF restraint file and the NEF file it
+//! belongs to. Data blocks are matched by name and save frames by frame code within their
+//! block, both case-insensitively; blocks and save frames only in the overlay are added after
+//! those of the base. In a matched block or save frame the data items and loops of the overlay
+//! are added unless they define a data name the base already defines, a collision resolved by
+//! the `MergePolicy`. A save frame may instead be resolved as a whole, and loops with the same
+//! data names in both can be concatenated, the base rows first.
+//!
+//! Byte offsets index the input a node was parsed from and the result holds nodes of two
+//! inputs, so the offsets of every node of the result are cleared to 0. Line and column
+//! positions are kept, and are those of the file each node came from. Walk the result with a
+//! walker created from an empty input, `StarWalker::from_input(&mut handler, "")`.
+
+use crate::line_column_index::LineColumn;
+use crate::loop_table::{LoopTable, LoopTableError};
+use crate::mutable_pair::MutablePair;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What to do when the base and the overlay both define a data name or save frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resolution {
+    /// Keep the definition of the base and drop the overlay's
+    KeepBase,
+    /// Replace the definition of the base with the overlay's
+    TakeOverlay,
+    /// Fail with `MergeError::Conflict`
+    #[default]
+    Error,
+}
+
+/// How `merge` resolves collisions, see `Resolution`
+///
+/// Names are compared case-insensitively. Without overrides every collision is an error and
+/// save frames in both documents are merged item by item.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    default: Resolution,
+    frames: HashMap<String, Resolution>,
+    tags: HashMap<String, Resolution>,
+    concatenate_loops: bool,
+}
+
+impl MergePolicy {
+    /// Resolve every collision with `default`
+    pub fn new(default: Resolution) -> Self {
+        MergePolicy {
+            default,
+            ..MergePolicy::default()
+        }
+    }
+
+    /// Resolve the save frame `name`, with or without `save_`, as a whole instead of merging it
+    /// item by item
+    pub fn with_frame(mut self, name: &str, resolution: Resolution) -> Self {
+        let name = strip_keyword(name, "save_");
+        self.frames.insert(name.to_ascii_lowercase(), resolution);
+        self
+    }
+
+    /// Resolve collisions of the data item `tag`, or of the loop with `tag` as a column, with
+    /// `resolution`
+    pub fn with_tag(mut self, tag: &str, resolution: Resolution) -> Self {
+        self.tags.insert(tag.to_ascii_lowercase(), resolution);
+        self
+    }
+
+    /// Concatenate loops with the same data names in both documents, in any order, instead of
+    /// resolving them as collisions
+    ///
+    /// Loops with one of their data names given to `with_tag`, and loops with a nested level,
+    /// are still resolved as collisions.
+    pub fn with_concatenated_loops(mut self) -> Self {
+        self.concatenate_loops = true;
+        self
+    }
+
+    /// The resolution of a collision of the save frame `name`, `None` to merge it
+    fn frame(&self, name: &str) -> Option<Resolution> {
+        self.frames.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    /// The resolution of a collision of a data item or loop defining `tags`
+    fn tags(&self, tags: &[&str]) -> Option<Resolution> {
+        tags.iter()
+            .find_map(|tag| self.tags.get(&tag.to_ascii_lowercase()).copied())
+    }
+}
+
+/// Errors from merging two documents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// The base and the overlay both define a data name or save frame resolved as an error
+    Conflict {
+        /// The data name, or the save frame heading
+        name: String,
+        base: LineColumn,
+        overlay: LineColumn,
+    },
+    /// The base and the overlay are different kinds of node, such as a file and a save frame
+    Mismatch { base: String, overlay: String },
+    /// A loop to concatenate is ragged
+    Loop(LoopTableError),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::Conflict {
+                name,
+                base,
+                overlay,
+            } => write!(
+                f,
+                "l{}:c{} {} in the overlay is already defined at l{}:c{} in the base",
+                overlay.line, overlay.column, name, base.line, base.column
+            ),
+            MergeError::Mismatch { base, overlay } => {
+                write!(f, "can't merge {} into {}", overlay, base)
+            }
+            MergeError::Loop(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<LoopTableError> for MergeError {
+    fn from(error: LoopTableError) -> Self {
+        MergeError::Loop(error)
+    }
+}
+
+/// Merge `overlay` into `base`, two parsed files, data blocks, global blocks or save frames
+///
+/// The result is the base with the blocks, save frames, data items and loops of the overlay
+/// added as `policy` says. Fails on a collision resolved as an error, or if the roots are
+/// different kinds of node.
+pub fn merge(
+    base: &MutablePair,
+    overlay: &MutablePair,
+    policy: MergePolicy,
+) -> Result<MutablePair, MergeError> {
+    if base.rule_name() != overlay.rule_name() {
+        return Err(MergeError::Mismatch {
+            base: base.rule_name().to_string(),
+            overlay: overlay.rule_name().to_string(),
+        });
+    }
+    let mut merged = match base.rule_name() {
+        "star_file" => merge_file(base, overlay, &policy)?,
+        _ => merge_scope(base, overlay, &policy)?,
+    };
+    clear_offsets(&mut merged);
+    Ok(merged)
+}
+
+/// Merge the blocks of two files, matching them by name
+fn merge_file(
+    base: &MutablePair,
+    overlay: &MutablePair,
+    policy: &MergePolicy,
+) -> Result<MutablePair, MergeError> {
+    let mut merged = base.clone();
+    for block in overlay.children().iter().filter(|child| is_block(child)) {
+        let name = block_name(block);
+        match merged
+            .children()
+            .iter()
+            .position(|child| is_block(child) && block_name(child).eq_ignore_ascii_case(name))
+        {
+            Some(index) => {
+                let block = merge_scope(&merged.children()[index], block, policy)?;
+                merged.replace_child(index, block);
+            }
+            None => insert_before_end(&mut merged, "EOI", block.clone()),
+        }
+    }
+    Ok(merged)
+}
+
+/// Merge the save frames, data items and loops of two blocks or save frames
+fn merge_scope(
+    base: &MutablePair,
+    overlay: &MutablePair,
+    policy: &MergePolicy,
+) -> Result<MutablePair, MergeError> {
+    let mut merged = base.clone();
+    for child in overlay.children() {
+        match child.rule_name() {
+            "save_frame" => merge_frame(&mut merged, child, policy)?,
+            "data" => merge_data(&mut merged, child, policy)?,
+            _ => {}
+        }
+    }
+    Ok(merged)
+}
+
+/// Add the save frame `frame` of the overlay to the block `merged`
+fn merge_frame(
+    merged: &mut MutablePair,
+    frame: &MutablePair,
+    policy: &MergePolicy,
+) -> Result<(), MergeError> {
+    let name = frame_name(frame);
+    let Some(index) = merged.children().iter().position(|child| {
+        child.rule_name() == "save_frame" && frame_name(child).eq_ignore_ascii_case(name)
+    }) else {
+        merged.push_child(frame.clone());
+        return Ok(());
+    };
+
+    let base = &merged.children()[index];
+    let frame = match policy.frame(name) {
+        None => merge_scope(base, frame, policy)?,
+        Some(Resolution::KeepBase) => return Ok(()),
+        Some(Resolution::TakeOverlay) => frame.clone(),
+        Some(Resolution::Error) => {
+            return Err(MergeError::Conflict {
+                name: base.children()[0].as_str().to_string(),
+                base: base.start_position,
+                overlay: frame.start_position,
+            })
+        }
+    };
+    merged.replace_child(index, frame);
+    Ok(())
+}
+
+/// Add the data item or loop `data` of the overlay to the block or save frame `merged`
+fn merge_data(
+    merged: &mut MutablePair,
+    data: &MutablePair,
+    policy: &MergePolicy,
+) -> Result<(), MergeError> {
+    let tags = data_names(data);
+    let colliding: Vec<usize> = merged
+        .children()
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.rule_name() == "data")
+        .filter(|(_, child)| {
+            data_names(child)
+                .iter()
+                .any(|name| tags.iter().any(|tag| tag.eq_ignore_ascii_case(name)))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let Some(&first) = colliding.first() else {
+        insert_before_end(merged, "save_keyword", data.clone());
+        return Ok(());
+    };
+
+    let resolution = policy.tags(&tags);
+    if resolution.is_none() && policy.concatenate_loops {
+        if let [index] = colliding[..] {
+            if let Some(concatenated) = concatenate(&merged.children()[index], data)? {
+                merged.replace_child(index, concatenated);
+                return Ok(());
+            }
+        }
+    }
+
+    match resolution.unwrap_or(policy.default) {
+        Resolution::KeepBase => {}
+        Resolution::TakeOverlay => {
+            merged.replace_child(first, data.clone());
+            for &index in colliding[1..].iter().rev() {
+                merged.remove_child(index);
+            }
+        }
+        Resolution::Error => {
+            let base = &merged.children()[first];
+            let name = tags
+                .iter()
+                .find(|tag| {
+                    data_names(base)
+                        .iter()
+                        .any(|name| tag.eq_ignore_ascii_case(name))
+                })
+                .expect("colliding data defines a data name of the overlay");
+            return Err(MergeError::Conflict {
+                name: name.to_string(),
+                base: base.start_position,
+                overlay: data.start_position,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The loop `base` with the rows of the loop `overlay` added, `None` if they aren't flat loops
+/// with the same data names
+///
+/// The rows of the overlay are reordered to the columns of the base. Fails if either loop is
+/// ragged.
+fn concatenate(
+    base: &MutablePair,
+    overlay: &MutablePair,
+) -> Result<Option<MutablePair>, MergeError> {
+    let (base_names, overlay_names) = (data_names(base), data_names(overlay));
+    let is_flat = |data: &MutablePair| data.find_first("nested_loop").is_none();
+    if base_names.len() != overlay_names.len() || !is_flat(base) || !is_flat(overlay) {
+        return Ok(None);
+    }
+    let Some(order) = base_names
+        .iter()
+        .map(|name| {
+            overlay_names
+                .iter()
+                .position(|tag| tag.eq_ignore_ascii_case(name))
+        })
+        .collect::<Option<Vec<usize>>>()
+    else {
+        return Ok(None);
+    };
+    LoopTable::from_pair(base)?;
+    LoopTable::from_pair(overlay)?;
+
+    let values = |data: &MutablePair| -> Vec<MutablePair> {
+        data.find_first("data_loop_values")
+            .map(|values| {
+                values
+                    .children()
+                    .iter()
+                    .filter(|value| !matches!(value.rule_name(), "comment" | "stop_keyword"))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let added: Vec<MutablePair> = values(overlay)
+        .chunks(order.len())
+        .flat_map(|row| order.iter().map(|&column| row[column].clone()))
+        .collect();
+
+    let mut merged = base.clone();
+    let Some(loop_values) = merged
+        .children_mut()
+        .iter_mut()
+        .find(|child| child.rule_name() == "data_loop")
+        .and_then(|data_loop| {
+            data_loop
+                .children_mut()
+                .iter_mut()
+                .find(|child| child.rule_name() == "data_loop_values")
+        })
+    else {
+        return Ok(None);
+    };
+    let stop = match loop_values.children().last() {
+        Some(last) if last.rule_name() == "stop_keyword" => loop_values.children_mut().pop(),
+        _ => None,
+    };
+    loop_values.children_mut().extend(added);
+    loop_values.children_mut().extend(stop);
+    Ok(Some(merged))
+}
+
+/// Insert `child` before the last child of `parent` if it is a `rule` node, else after it
+fn insert_before_end(parent: &mut MutablePair, rule: &str, child: MutablePair) {
+    match parent.children().last() {
+        Some(last) if last.rule_name() == rule => {
+            let index = parent.children().len() - 1;
+            parent.insert_child(index, child);
+        }
+        _ => parent.push_child(child),
+    }
+}
+
+/// The data names defined by a data item or loop, including those of nested levels
+fn data_names(data: &MutablePair) -> Vec<&str> {
+    match data.children() {
+        [name, _] if name.rule_name() == "data_name" => vec![name.as_str()],
+        _ => data
+            .find_first("data_loop_definition")
+            .map(|definition| {
+                definition
+                    .find_all("data_name")
+                    .into_iter()
+                    .map(|name| name.as_str())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+fn is_block(node: &MutablePair) -> bool {
+    matches!(node.rule_name(), "data_block" | "global_block")
+}
+
+/// The name of a data block without `data_`, empty for a global block
+fn block_name(block: &MutablePair) -> &str {
+    match block.rule_name() {
+        "data_block" => strip_keyword(block.children()[0].as_str(), "data_"),
+        _ => "",
+    }
+}
+
+/// The frame code of a save frame without `save_`
+fn frame_name(frame: &MutablePair) -> &str {
+    strip_keyword(frame.children()[0].as_str(), "save_")
+}
+
+/// `name` without a leading `keyword`, compared case-insensitively
+fn strip_keyword<'n>(name: &'n str, keyword: &str) -> &'n str {
+    match name.get(..keyword.len()) {
+        Some(start) if start.eq_ignore_ascii_case(keyword) => &name[keyword.len()..],
+        _ => name,
+    }
+}
+
+fn clear_offsets(node: &mut MutablePair) {
+    node.start = 0;
+    node.end = 0;
+    for child in node.children_mut() {
+        clear_offsets(child);
+    }
+}
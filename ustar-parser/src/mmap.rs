@@ -0,0 +1,86 @@
+//! Memory-mapped STAR files - parsing a file where it is mapped rather than reading it first
+//!
+//! Reading a file with `read_star_file` copies all of it into a string, which the parser then
+//! copies again for the nodes of the tree to share. Mapping the file leaves its bytes in the page
+//! cache, so a UTF-8 file is checked and parsed in place and only the copy the tree shares is
+//! made; parsing the mapped file block by block also holds only one block's tree at a time.
+
+use crate::block_iterator::DataBlockIterator;
+use crate::config::{get_column_unit, get_encoding};
+use crate::mutable_pair::MutablePair;
+use crate::{ParserConfig, UstarError};
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// A STAR file mapped into memory
+///
+/// The file must not be changed, by this or another process, while it is mapped: the text the
+/// parser reads would change under it.
+pub struct MappedStarFile {
+    path: PathBuf,
+    /// The mapping, `None` for an empty file as there is nothing to map
+    map: Option<Mmap>,
+}
+
+impl MappedStarFile {
+    /// Map the file at `path`, or an error naming the file if it can't be opened or mapped
+    pub fn open(path: &Path, config: &ParserConfig) -> Result<Self, Box<UstarError>> {
+        let unreadable = |error| crate::unreadable_file(path, error, config);
+        let file = File::open(path).map_err(unreadable)?;
+        let map = match file.metadata().map_err(unreadable)?.len() {
+            0 => None,
+            // SAFETY: the mapping is only read, and the file must not change while it is mapped
+            _ => Some(unsafe { Mmap::map(&file) }.map_err(unreadable)?),
+        };
+        Ok(MappedStarFile {
+            path: path.to_path_buf(),
+            map,
+        })
+    }
+
+    /// The path of the mapped file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The bytes of the file
+    pub fn bytes(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+
+    /// The text of the file, checked to be UTF-8 without copying it
+    ///
+    /// # Returns
+    /// * `Result<&str, UstarError>` - The text, or an error naming the file at its first byte that isn't UTF-8
+    pub fn text(&self, config: &ParserConfig) -> Result<&str, Box<UstarError>> {
+        std::str::from_utf8(self.bytes()).map_err(|error| {
+            let error = crate::invalid_utf8(self.bytes(), error, "the file", get_encoding(config));
+            self.named(error.with_column_unit(get_column_unit(config)))
+        })
+    }
+
+    /// Parse the whole file as `parse_bytes` parses its bytes, naming the file in any error
+    pub fn parse(&self, config: &ParserConfig) -> Result<MutablePair, Box<UstarError>> {
+        crate::parse_bytes(self.bytes(), config).map_err(|error| self.named(*error))
+    }
+
+    /// Iterate over the blocks of a UTF-8 file, parsing each as it is reached
+    ///
+    /// The blocks are parsed as `iter_blocks` parses the text, the offsets of their errors are
+    /// byte offsets into the file, after any UTF-8 byte order mark.
+    ///
+    /// # Returns
+    /// * `Result<DataBlockIterator, UstarError>` - The blocks, or an error if the file isn't UTF-8
+    pub fn blocks<'m>(
+        &'m self,
+        config: &ParserConfig,
+    ) -> Result<DataBlockIterator<'m>, Box<UstarError>> {
+        Ok(DataBlockIterator::new(self.text(config)?, config))
+    }
+
+    /// `error` naming the mapped file (private)
+    fn named(&self, error: UstarError) -> Box<UstarError> {
+        Box::new(error.with_source_name(self.path.display().to_string()))
+    }
+}
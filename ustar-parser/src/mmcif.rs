@@ -0,0 +1,300 @@
+//! mmCIF - data items grouped by category and fast access to atom coordinates.
+//!
+//! mmCIF data names are written `_category.item`, and a category is either a set of items or
+//! one loop holding all of its items as columns. `MmcifBlock::from_block` groups the items and
+//! loops of a data block by category so they can be read as a `CategoryView`, whichever form
+//! the file uses. Data names without a `.`, as in DDL1 CIF, are grouped under the category with
+//! the empty name. Category and item names are compared case-insensitively.
+//!
+//! `atom_sites` reads the coordinates, elements, occupancies and B-factors of the `_atom_site`
+//! loop straight into parallel columns without grouping the rest of the file.
+
+use crate::line_column_index::LineColumn;
+use crate::loop_table::{LoopTable, LoopTableError};
+use crate::mutable_pair::MutablePair;
+use crate::values::{parse_number, value_text};
+
+/// Split a data name into its category and item, the category being empty without a `.`
+fn split_tag(tag: &str) -> (&str, &str) {
+    let name = tag.strip_prefix('_').unwrap_or(tag);
+    name.split_once('.').unwrap_or(("", name))
+}
+
+/// Where the values of a category are held
+#[derive(Debug, Clone, PartialEq)]
+enum Source<'a> {
+    /// The value node of each item outside a loop
+    Items(Vec<&'a MutablePair>),
+    Loop(LoopTable<'a>),
+}
+
+/// The items of one category of a data block, given as items or as a loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryView<'a> {
+    name: &'a str,
+    items: Vec<&'a str>,
+    source: Source<'a>,
+    position: LineColumn,
+}
+
+impl<'a> CategoryView<'a> {
+    /// The name of the category as first written, without the leading `_`
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The names of the items without the category, in file order
+    pub fn items(&self) -> &[&'a str] {
+        &self.items
+    }
+
+    /// Where the first item or the loop keyword of the category starts
+    pub fn position(&self) -> LineColumn {
+        self.position
+    }
+
+    /// Check if the category is given as a loop
+    pub fn is_loop(&self) -> bool {
+        matches!(self.source, Source::Loop(_))
+    }
+
+    /// The number of rows, one for a category given as items
+    pub fn row_count(&self) -> usize {
+        match &self.source {
+            Source::Items(_) => 1,
+            Source::Loop(table) => table.row_count(),
+        }
+    }
+
+    /// The loop of the category, `None` for a category given as items
+    pub fn table(&self) -> Option<&LoopTable<'a>> {
+        match &self.source {
+            Source::Items(_) => None,
+            Source::Loop(table) => Some(table),
+        }
+    }
+
+    /// The value of `item` in the first row, `None` if the category has no such item or no rows
+    pub fn value(&self, item: &str) -> Option<&'a str> {
+        self.value_at(0, item)
+    }
+
+    /// The value of `item` in row `row_index`, `None` if there is no such value
+    pub fn value_at(&self, row_index: usize, item: &str) -> Option<&'a str> {
+        let column = self.item_index(item)?;
+        match &self.source {
+            Source::Items(values) => (row_index == 0).then(|| value_text(values[column])),
+            Source::Loop(table) => table.value(row_index, column),
+        }
+    }
+
+    /// The values of `item`, one per row, `None` if the category has no such item
+    pub fn column(&self, item: &str) -> Option<Vec<&'a str>> {
+        let column = self.item_index(item)?;
+        Some(match &self.source {
+            Source::Items(values) => vec![value_text(values[column])],
+            Source::Loop(table) => (0..table.row_count())
+                .filter_map(|row| table.value(row, column))
+                .collect(),
+        })
+    }
+
+    /// The index of `item` in `items`, the first if it is given twice
+    fn item_index(&self, item: &str) -> Option<usize> {
+        self.items
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(item))
+    }
+}
+
+/// The categories of an mmCIF data block
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmcifBlock<'a> {
+    name: &'a str,
+    categories: Vec<CategoryView<'a>>,
+}
+
+impl<'a> MmcifBlock<'a> {
+    /// Group the data items and loops of `block` by category
+    ///
+    /// Save frames aren't read, and a category given both as items and as a loop, which mmCIF
+    /// doesn't allow, is read from its first definition. Fails if a loop is ragged, see
+    /// `LoopTable::from_pair`.
+    pub fn from_block(block: &'a MutablePair) -> Result<MmcifBlock<'a>, LoopTableError> {
+        let name = match block.rule_name() {
+            "data_block" => &block.children()[0].as_str()["data_".len()..],
+            _ => "",
+        };
+        let mut mmcif = MmcifBlock {
+            name,
+            categories: Vec::new(),
+        };
+
+        for data in block.children().iter().filter(|c| c.rule_name() == "data") {
+            if let [name, value] = data.children() {
+                if name.rule_name() == "data_name" {
+                    mmcif.add_item(name, value);
+                    continue;
+                }
+            }
+            let Some(definition) = data.find_first("data_loop_definition") else {
+                continue;
+            };
+            let Some(first) = definition.find_first("data_name") else {
+                continue;
+            };
+            let (category, _) = split_tag(first.as_str());
+            if mmcif.category(category).is_some() {
+                continue;
+            }
+            let table = LoopTable::from_pair(data)?;
+            mmcif.categories.push(CategoryView {
+                name: category,
+                items: definition
+                    .children()
+                    .iter()
+                    .filter(|child| child.rule_name() == "data_name")
+                    .map(|name| split_tag(name.as_str()).1)
+                    .collect(),
+                position: table.position(),
+                source: Source::Loop(table),
+            });
+        }
+
+        Ok(mmcif)
+    }
+
+    /// The name of the block without `data_`, empty for a node that isn't a data block
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The categories in the order they first appear
+    pub fn categories(&self) -> impl Iterator<Item = &CategoryView<'a>> {
+        self.categories.iter()
+    }
+
+    /// The category called `name`, with or without its leading `_`
+    pub fn category(&self, name: &str) -> Option<&CategoryView<'a>> {
+        let name = name.strip_prefix('_').unwrap_or(name);
+        self.categories
+            .iter()
+            .find(|category| category.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Add the data item `name` with `value` to its category
+    fn add_item(&mut self, name: &'a MutablePair, value: &'a MutablePair) {
+        let (category, item) = split_tag(name.as_str());
+        let index = match self
+            .categories
+            .iter()
+            .position(|view| view.name.eq_ignore_ascii_case(category))
+        {
+            Some(index) => index,
+            None => {
+                self.categories.push(CategoryView {
+                    name: category,
+                    items: Vec::new(),
+                    source: Source::Items(Vec::new()),
+                    position: name.start_position,
+                });
+                self.categories.len() - 1
+            }
+        };
+        let view = &mut self.categories[index];
+        if let Source::Items(values) = &mut view.source {
+            view.items.push(item);
+            values.push(value);
+        }
+    }
+}
+
+/// The coordinates and properties of the atoms of an `_atom_site` loop, one entry per row
+///
+/// Numbers given as `.` or `?`, or missing from the loop, are NaN, elements are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AtomSiteTable {
+    /// `_atom_site.Cartn_x`
+    pub x: Vec<f64>,
+    /// `_atom_site.Cartn_y`
+    pub y: Vec<f64>,
+    /// `_atom_site.Cartn_z`
+    pub z: Vec<f64>,
+    /// `_atom_site.type_symbol`
+    pub element: Vec<Option<String>>,
+    /// `_atom_site.occupancy`
+    pub occupancy: Vec<f64>,
+    /// `_atom_site.B_iso_or_equiv`
+    pub b_factor: Vec<f64>,
+}
+
+impl AtomSiteTable {
+    /// The number of atoms
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    /// Check if there are no atoms
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+}
+
+/// Read the first `_atom_site` loop of `pair`, a parsed file or data block
+///
+/// The table is empty if there is no `_atom_site` loop. Fails if the loop is ragged.
+pub fn atom_sites(pair: &MutablePair) -> Result<AtomSiteTable, LoopTableError> {
+    let data_loop = pair.find_where(|node| {
+        node.rule_name() == "data_loop"
+            && node
+                .find_first("data_name")
+                .is_some_and(|name| split_tag(name.as_str()).0.eq_ignore_ascii_case("atom_site"))
+    });
+    let Some(data_loop) = data_loop else {
+        return Ok(AtomSiteTable::default());
+    };
+
+    let table = LoopTable::from_pair(data_loop)?;
+    let numbers = |item: &str| -> Vec<f64> {
+        match column(&table, item) {
+            Some(values) => values.map(parse_coordinate).collect(),
+            None => vec![f64::NAN; table.row_count()],
+        }
+    };
+    let element = match column(&table, "type_symbol") {
+        Some(values) => values
+            .map(|value| (!matches!(value, "." | "?")).then(|| value.to_string()))
+            .collect(),
+        None => vec![None; table.row_count()],
+    };
+
+    Ok(AtomSiteTable {
+        x: numbers("Cartn_x"),
+        y: numbers("Cartn_y"),
+        z: numbers("Cartn_z"),
+        element,
+        occupancy: numbers("occupancy"),
+        b_factor: numbers("B_iso_or_equiv"),
+    })
+}
+
+/// The values of the `_atom_site` column `item`, `None` if the loop doesn't have it
+fn column<'t, 'a>(
+    table: &'t LoopTable<'a>,
+    item: &str,
+) -> Option<impl Iterator<Item = &'a str> + 't> {
+    table.column(&format!("_atom_site.{}", item))
+}
+
+/// Read a number, dropping any uncertainty, as NaN if it isn't one
+fn parse_coordinate(value: &str) -> f64 {
+    // most coordinates are plain decimals, which str::parse reads without allocating
+    if value.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) {
+        if let Ok(number) = value.parse::<f64>() {
+            return number;
+        }
+    }
+    parse_number(value)
+        .and_then(|number| number.as_f64())
+        .unwrap_or(f64::NAN)
+}
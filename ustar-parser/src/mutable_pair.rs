@@ -3,19 +3,48 @@
 //! This structure contains the minimum information needed to replicate a Pair's
 //! functionality, but with strings instead of pest grammar tokens. This makes it
 //! suitable for representing transformed/patched parse trees.
+//!
+//! The `start`/`end` offsets and `content` of a node always refer to the original input.
+//! Mutating the children of a node does not update them, on that node or its ancestors, so
+//! after an edit an ancestor may still report text that was removed or miss text that was
+//! inserted. Untouched nodes keep their original offsets. `children_span` recomputes the span
+//! actually covered by the current children when it is needed.
+//!
+//! Nodes built by parsing also store the line and column of their start and end, so consumers
+//! don't need to rescan the input to report positions. Nodes constructed directly have
+//! undefined positions.
+//!
+//! The content of every node built by parsing is a `SharedText` slice of a single copy of the
+//! input, and rule names are interned, so a node costs the same however much text it covers.
+//!
+//! Trees are `Send + Sync`: the shared input is held by an `Arc` and everything else is owned,
+//! so a tree parsed on one thread can be moved to, or read from, any number of others, for
+//! example to process its saveframes on a rayon pool.
 
+use crate::line_column_index::{LineColumn, LineColumnIndex};
+use crate::shared_text::{RuleNames, SharedText};
+use crate::string_decomposer;
+use crate::values::StarValue;
 use pest::RuleType;
+use std::borrow::Cow;
+use std::sync::Arc;
 
 /// A mutable pair-like structure that mimics pest's Pair but with plain strings.
 /// Unlike `Pair<Rule>`, this can be constructed and modified freely.
-/// Uses String for rule names to allow synthetic rules not in the grammar.
+/// Rule names are interned for grammar rules and may be owned for synthetic rules not in the
+/// grammar.
+///
+/// With the `serde` feature this serializes as `{"rule", "text", "start", "end", "children"}`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MutablePair {
     /// The rule name as a string (allows synthetic rules)
-    pub rule_name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "rule"))]
+    pub rule_name: Cow<'static, str>,
 
     /// The string content (token) for this pair
-    pub content: String,
+    #[cfg_attr(feature = "serde", serde(rename = "text"))]
+    pub content: SharedText,
 
     /// Starting position in the original input
     pub start: usize,
@@ -23,6 +52,14 @@ pub struct MutablePair {
     /// Ending position in the original input
     pub end: usize,
 
+    /// Line and column of `start`, undefined unless the node was built by parsing
+    #[cfg_attr(feature = "serde", serde(skip, default = "LineColumn::undefined"))]
+    pub start_position: LineColumn,
+
+    /// Line and column of `end`, undefined unless the node was built by parsing
+    #[cfg_attr(feature = "serde", serde(skip, default = "LineColumn::undefined"))]
+    pub end_position: LineColumn,
+
     /// Child pairs
     pub children: Vec<MutablePair>,
 }
@@ -30,8 +67,8 @@ pub struct MutablePair {
 impl MutablePair {
     /// Create a new MutablePair without children
     pub fn new(
-        rule_name: impl Into<String>,
-        content: impl Into<String>,
+        rule_name: impl Into<Cow<'static, str>>,
+        content: impl Into<SharedText>,
         start: usize,
         end: usize,
     ) -> Self {
@@ -40,14 +77,16 @@ impl MutablePair {
             content: content.into(),
             start,
             end,
+            start_position: LineColumn::undefined(),
+            end_position: LineColumn::undefined(),
             children: Vec::new(),
         }
     }
 
     /// Create a new MutablePair with children
     pub fn with_children(
-        rule_name: impl Into<String>,
-        content: impl Into<String>,
+        rule_name: impl Into<Cow<'static, str>>,
+        content: impl Into<SharedText>,
         start: usize,
         end: usize,
         children: Vec<MutablePair>,
@@ -57,6 +96,8 @@ impl MutablePair {
             content: content.into(),
             start,
             end,
+            start_position: LineColumn::undefined(),
+            end_position: LineColumn::undefined(),
             children,
         }
     }
@@ -83,6 +124,14 @@ impl MutablePair {
         &self.content
     }
 
+    /// The value a string node stands for: the text between the delimiters of a quoted string or
+    /// text field, with the doubled quotes of single and double quoted strings collapsed, and the
+    /// text of any other node as it is
+    pub fn logical_value(&self) -> Cow<'_, str> {
+        string_decomposer::logical_string_value(&self.rule_name, &self.content)
+            .unwrap_or(Cow::Borrowed(self.as_str()))
+    }
+
     /// Get the start position
     pub fn start_pos(&self) -> usize {
         self.start
@@ -93,6 +142,34 @@ impl MutablePair {
         self.end
     }
 
+    /// Get the line and column of the start position
+    pub fn start_line_column(&self) -> LineColumn {
+        self.start_position
+    }
+
+    /// Get the line and column of the end position
+    pub fn end_line_column(&self) -> LineColumn {
+        self.end_position
+    }
+
+    /// Line and column of a byte offset into this node's content, undefined if the node's is
+    pub(crate) fn line_column_at(&self, offset: usize) -> LineColumn {
+        if !self.start_position.is_defined() {
+            return LineColumn::undefined();
+        }
+        let before = &self.content.as_bytes()[..offset];
+        match memchr::memrchr(b'\n', before) {
+            Some(newline) => LineColumn::new(
+                self.start_position.line + memchr::memchr_iter(b'\n', before).count(),
+                offset - newline,
+            ),
+            None => LineColumn::new(
+                self.start_position.line,
+                self.start_position.column + offset,
+            ),
+        }
+    }
+
     /// Check if this pair has children
     pub fn has_children(&self) -> bool {
         !self.children.is_empty()
@@ -113,24 +190,421 @@ impl MutablePair {
         self.children
     }
 
+    /// Append a child
+    pub fn push_child(&mut self, child: MutablePair) {
+        self.children.push(child);
+    }
+
+    /// Insert a child at `index`, shifting later children right; panics if `index > len`
+    pub fn insert_child(&mut self, index: usize, child: MutablePair) {
+        self.children.insert(index, child);
+    }
+
+    /// Remove and return the child at `index`, shifting later children left; panics if out of bounds
+    pub fn remove_child(&mut self, index: usize) -> MutablePair {
+        self.children.remove(index)
+    }
+
+    /// Replace the child at `index`, returning the old child; panics if out of bounds
+    pub fn replace_child(&mut self, index: usize, child: MutablePair) -> MutablePair {
+        std::mem::replace(&mut self.children[index], child)
+    }
+
+    /// Keep only the children that match the predicate
+    pub fn retain_children<F>(&mut self, predicate: F)
+    where
+        F: FnMut(&MutablePair) -> bool,
+    {
+        self.children.retain(predicate);
+    }
+
+    /// The span of the input covered by the current children, or `None` without children
+    ///
+    /// Unlike `start`/`end` this reflects any children inserted or removed since parsing.
+    pub fn children_span(&self) -> Option<(usize, usize)> {
+        PairNode::children_span(self)
+    }
+
+    /// Serialize this tree as JSON
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserialize a tree from JSON produced by `to_json`
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Traverse this node and its descendants depth-first, calling the visitor's `enter` before
+    /// and `exit` after each node's children
+    ///
+    /// Returns true if the visitor stopped the traversal.
+    pub fn accept(&self, visitor: &mut impl Visitor) -> bool {
+        self.accept_node(visitor)
+    }
+
+    /// Iterate over all descendants depth-first, parents before their children (self excluded)
+    pub fn iter_descendants(&self) -> Descendants<'_> {
+        self.descendant_nodes()
+    }
+
+    /// Find the first descendant, depth-first, with the given rule name
+    pub fn find_first(&self, rule_name: &str) -> Option<&MutablePair> {
+        self.find_node_where(|pair| pair.rule_name == rule_name)
+    }
+
+    /// Find all descendants with the given rule name, in depth-first order
+    pub fn find_all(&self, rule_name: &str) -> Vec<&MutablePair> {
+        self.find_all_nodes(rule_name)
+    }
+
+    /// Find the first descendant, depth-first, that matches the predicate
+    pub fn find_where<F>(&self, predicate: F) -> Option<&MutablePair>
+    where
+        F: FnMut(&MutablePair) -> bool,
+    {
+        self.find_node_where(predicate)
+    }
+
+    /// The typed values of a data name in this subtree, in document order
+    ///
+    /// Data names compare case-insensitively. A data item gives one value and a loop column one
+    /// value per row; loops containing nested loops are skipped.
+    pub fn typed_values(&self, data_name: &str) -> Vec<StarValue> {
+        self.typed_node_values(data_name)
+    }
+
+    /// Recompute the line and column positions of this node and its descendants from their byte
+    /// offsets, in the column unit of `line_index`
+    pub(crate) fn recount_positions(&mut self, line_index: &LineColumnIndex) {
+        self.start_position = line_index.offset_to_line_col(self.start);
+        self.end_position = line_index.offset_to_line_col(self.end);
+        for child in &mut self.children {
+            child.recount_positions(line_index);
+        }
+    }
+
     /// Create a MutablePair from a pest Pair
+    ///
+    /// Line and columns are looked up in a `LineColumnIndex` built once for the parsed input.
     pub fn from_pest_pair<R: RuleType>(pair: &pest::iterators::Pair<R>) -> Self {
-        let children: Vec<MutablePair> = pair
-            .clone()
-            .into_inner()
-            .map(|child| MutablePair::from_pest_pair(&child))
-            .collect();
+        let source = Arc::new(pair.get_input().to_string());
+        Self::from_pest_pair_in(pair, &source, 0)
+    }
+
+    /// Convert a pair parsed from `source[offset..]`, with the content of every node sharing
+    /// `source`
+    pub(crate) fn from_pest_pair_in<R: RuleType>(
+        pair: &pest::iterators::Pair<R>,
+        source: &Arc<String>,
+        offset: usize,
+    ) -> Self {
+        let line_index = LineColumnIndex::new(pair.get_input());
+        let mut rule_names = RuleNames::new();
+        Self::from_pest_pair_indexed(pair, &line_index, source, offset, &mut rule_names)
+    }
+
+    fn from_pest_pair_indexed<R: RuleType>(
+        pair: &pest::iterators::Pair<R>,
+        line_index: &LineColumnIndex,
+        source: &Arc<String>,
+        offset: usize,
+        rule_names: &mut RuleNames<R>,
+    ) -> Self {
+        let rule_name = rule_names.get(pair.as_rule());
+        let span = pair.as_span();
+        #[cfg(feature = "trace")]
+        let block = crate::trace::enter_block(rule_name, span.start());
 
+        // allocate exactly, collect would round small vectors up to a capacity of four
+        let inner = pair.clone().into_inner();
+        let mut children = Vec::with_capacity(inner.len());
+        children.extend(inner.map(|child| {
+            MutablePair::from_pest_pair_indexed(&child, line_index, source, offset, rule_names)
+        }));
+
+        #[cfg(feature = "trace")]
+        crate::trace::exit_block(block, span.end(), children.len());
         MutablePair {
-            rule_name: format!("{:?}", pair.as_rule()),
-            content: pair.as_str().to_string(),
-            start: pair.as_span().start(),
-            end: pair.as_span().end(),
+            rule_name: Cow::Borrowed(rule_name),
+            content: SharedText::new(source.clone(), offset + span.start(), offset + span.end()),
+            start: span.start(),
+            end: span.end(),
+            start_position: line_index.offset_to_line_col(span.start()),
+            end_position: line_index.offset_to_line_col(span.end()),
             children,
         }
     }
 }
 
+/// What a `Visitor` wants to happen after entering a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitControl {
+    /// Visit the node's children, then exit the node
+    Continue,
+    /// Don't visit the node's children, but still exit the node
+    SkipChildren,
+    /// End the traversal, no further `enter` or `exit` calls are made
+    Stop,
+}
+
+/// Callbacks for a pre- and post-order traversal with `MutablePair::accept`
+pub trait Visitor {
+    /// Called before the children of a node are visited
+    fn enter(&mut self, _pair: &MutablePair) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called after the children of a node have been visited, or skipped
+    fn exit(&mut self, _pair: &MutablePair) {}
+}
+
+/// Callbacks for a pre- and post-order traversal of either tree backend with
+/// `PairNode::accept_node`; every `Visitor` is a `NodeVisitor` of `&MutablePair`
+pub trait NodeVisitor<'a, N: PairNode<'a>> {
+    /// Called before the children of a node are visited
+    fn enter(&mut self, _node: N) -> VisitControl {
+        VisitControl::Continue
+    }
+
+    /// Called after the children of a node have been visited, or skipped
+    fn exit(&mut self, _node: N) {}
+}
+
+impl<'a, V: Visitor> NodeVisitor<'a, &'a MutablePair> for V {
+    fn enter(&mut self, pair: &'a MutablePair) -> VisitControl {
+        Visitor::enter(self, pair)
+    }
+
+    fn exit(&mut self, pair: &'a MutablePair) {
+        Visitor::exit(self, pair)
+    }
+}
+
+/// Depth-first iterator over the descendants of a `MutablePair`
+pub type Descendants<'a> = DescendantNodes<'a, &'a MutablePair>;
+
+/// Read-only view of a parse tree node.
+///
+/// Implemented by `&MutablePair` and by the arena handle `tree_arena::PairRef`,
+/// so tree consumers such as the SAS walker can work over either backend.
+pub trait PairNode<'a>: Copy {
+    /// Get the rule name
+    fn rule_name(self) -> &'a str;
+
+    /// Get the content as a string slice
+    fn as_str(self) -> &'a str;
+
+    /// Get the start position
+    fn start_pos(self) -> usize;
+
+    /// Get the end position
+    fn end_pos(self) -> usize;
+
+    /// Get the number of children
+    fn child_count(self) -> usize;
+
+    /// Get the child at `index`, if present
+    fn child(self, index: usize) -> Option<Self>;
+
+    /// Get the line and column of the start position, undefined if not known
+    fn start_line_column(self) -> LineColumn {
+        LineColumn::undefined()
+    }
+
+    /// Get the line and column of the end position, undefined if not known
+    fn end_line_column(self) -> LineColumn {
+        LineColumn::undefined()
+    }
+
+    /// Check if this node has children
+    fn has_children(self) -> bool {
+        self.child_count() > 0
+    }
+
+    /// Get an iterator over the children
+    fn child_nodes(self) -> ChildNodes<'a, Self> {
+        ChildNodes {
+            node: self,
+            index: 0,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Iterate over all descendants depth-first, parents before their children (self excluded)
+    fn descendant_nodes(self) -> DescendantNodes<'a, Self> {
+        DescendantNodes {
+            stack: vec![self.child_nodes()],
+        }
+    }
+
+    /// Find the first descendant, depth-first, with the given rule name
+    fn find_first_node(self, rule_name: &str) -> Option<Self> {
+        self.find_node_where(|node| node.rule_name() == rule_name)
+    }
+
+    /// Find all descendants with the given rule name, in depth-first order
+    fn find_all_nodes(self, rule_name: &str) -> Vec<Self> {
+        self.descendant_nodes()
+            .filter(|node| node.rule_name() == rule_name)
+            .collect()
+    }
+
+    /// Find the first descendant, depth-first, that matches the predicate
+    fn find_node_where<F>(self, mut predicate: F) -> Option<Self>
+    where
+        F: FnMut(Self) -> bool,
+    {
+        self.descendant_nodes().find(|&node| predicate(node))
+    }
+
+    /// Traverse this node and its descendants depth-first, calling the visitor's `enter` before
+    /// and `exit` after each node's children
+    ///
+    /// Returns true if the visitor stopped the traversal.
+    fn accept_node(self, visitor: &mut impl NodeVisitor<'a, Self>) -> bool {
+        match visitor.enter(self) {
+            VisitControl::Stop => return true,
+            VisitControl::SkipChildren => {}
+            VisitControl::Continue => {
+                for child in self.child_nodes() {
+                    if child.accept_node(visitor) {
+                        return true;
+                    }
+                }
+            }
+        }
+        visitor.exit(self);
+        false
+    }
+
+    /// The span of the input covered by the current children, or `None` without children
+    fn children_span(self) -> Option<(usize, usize)> {
+        let start = self.child_nodes().map(|child| child.start_pos()).min()?;
+        let end = self.child_nodes().map(|child| child.end_pos()).max()?;
+        Some((start, end))
+    }
+
+    /// The typed values of a data name in this subtree, in document order, as
+    /// `MutablePair::typed_values` gives them
+    fn typed_node_values(self, data_name: &str) -> Vec<StarValue> {
+        let mut values = Vec::new();
+        let is_tag = |node: &Self| node.as_str().eq_ignore_ascii_case(data_name);
+        for data in self.find_all_nodes("data") {
+            match (data.child_count(), data.child(0), data.child(1)) {
+                (2, Some(name), Some(value)) if name.rule_name() == "data_name" => {
+                    if is_tag(&name) {
+                        values.push(StarValue::from_pair(value));
+                    }
+                }
+                _ => {
+                    let (Some(definition), Some(loop_values)) = (
+                        data.find_first_node("data_loop_definition"),
+                        data.find_first_node("data_loop_values"),
+                    ) else {
+                        continue;
+                    };
+                    if definition.find_first_node("nested_loop").is_some() {
+                        continue;
+                    }
+                    let Some(column) = definition.child_nodes().position(|name| is_tag(&name))
+                    else {
+                        continue;
+                    };
+                    let columns = definition.child_count();
+                    values.extend(
+                        loop_values
+                            .child_nodes()
+                            .filter(|value| {
+                                !matches!(value.rule_name(), "stop_keyword" | "comment")
+                            })
+                            .skip(column)
+                            .step_by(columns)
+                            .map(StarValue::from_pair),
+                    );
+                }
+            }
+        }
+        values
+    }
+}
+
+/// Iterator over the children of a `PairNode`
+pub struct ChildNodes<'a, N: PairNode<'a>> {
+    node: N,
+    index: usize,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, N: PairNode<'a>> Iterator for ChildNodes<'a, N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        let child = self.node.child(self.index)?;
+        self.index += 1;
+        Some(child)
+    }
+}
+
+/// Depth-first iterator over the descendants of a `PairNode`
+pub struct DescendantNodes<'a, N: PairNode<'a>> {
+    stack: Vec<ChildNodes<'a, N>>,
+}
+
+impl<'a, N: PairNode<'a>> Iterator for DescendantNodes<'a, N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<N> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(node) => {
+                    self.stack.push(node.child_nodes());
+                    return Some(node);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a> PairNode<'a> for &'a MutablePair {
+    fn rule_name(self) -> &'a str {
+        &self.rule_name
+    }
+
+    fn as_str(self) -> &'a str {
+        &self.content
+    }
+
+    fn start_pos(self) -> usize {
+        self.start
+    }
+
+    fn end_pos(self) -> usize {
+        self.end
+    }
+
+    fn start_line_column(self) -> LineColumn {
+        self.start_position
+    }
+
+    fn end_line_column(self) -> LineColumn {
+        self.end_position
+    }
+
+    fn child_count(self) -> usize {
+        self.children.len()
+    }
+
+    fn child(self, index: usize) -> Option<Self> {
+        self.children.get(index)
+    }
+}
+
 impl std::fmt::Display for MutablePair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
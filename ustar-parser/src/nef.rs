@@ -0,0 +1,464 @@
+//! NEF - typed access to the standard save frames of NMR Exchange Format files.
+//!
+//! `NefFile::from_tree` reads the metadata, molecular system and chemical shift lists of a
+//! parsed NEF file into typed structures. Save frames are recognised by their `sf_category`.
+//! The metadata and molecular system frames are mandatory, files holding only restraints may
+//! have no chemical shift lists. The mandatory tags and loop columns of the frames read are
+//! checked along with the types of their values; every violation is reported as a
+//! `ValidationIssue` at the position of the offending value, or of the save frame or loop
+//! missing a tag. Other save frames aren't checked.
+//!
+//! Values of `.` and `?` in optional columns read as `None`. The `uuid` of the metadata is
+//! optional, as NEF 1.0 files don't have one.
+
+use crate::line_column_index::LineColumn;
+use crate::loop_table::{LoopTable, LoopTableError};
+use crate::mutable_pair::MutablePair;
+use crate::validate::{ValidationIssue, ValidationIssueKind};
+use crate::values::{value_text, StarValue};
+use std::fmt;
+
+/// The `nef_nmr_meta_data` save frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NefMetadata {
+    pub format_name: String,
+    pub format_version: String,
+    pub program_name: String,
+    pub program_version: String,
+    pub creation_date: String,
+    pub uuid: Option<String>,
+    pub coordinate_file_name: Option<String>,
+}
+
+/// A residue of the `_nef_sequence` loop of the molecular system
+#[derive(Debug, Clone, PartialEq)]
+pub struct NefResidue {
+    pub index: i64,
+    pub chain_code: String,
+    pub sequence_code: String,
+    pub residue_name: String,
+    pub linking: Option<String>,
+    pub residue_variant: Option<String>,
+    pub cis_peptide: Option<bool>,
+}
+
+/// A `nef_chemical_shift_list` save frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct NefShiftList {
+    /// The `sf_framecode` of the list
+    pub framecode: String,
+    pub shifts: Vec<NefShift>,
+}
+
+/// A row of a `_nef_chemical_shift` loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct NefShift {
+    pub chain_code: String,
+    pub sequence_code: String,
+    pub residue_name: Option<String>,
+    pub atom_name: String,
+    pub value: f64,
+    pub uncertainty: Option<f64>,
+    pub element: Option<String>,
+    pub isotope_number: Option<i64>,
+}
+
+/// Errors from reading a NEF file
+#[derive(Debug, Clone, PartialEq)]
+pub enum NefError {
+    /// A loop whose values don't fill its last row
+    Loop(LoopTableError),
+    /// Violations of the NEF specification in file order
+    Invalid(Vec<ValidationIssue>),
+}
+
+impl fmt::Display for NefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NefError::Loop(error) => write!(f, "{}", error),
+            NefError::Invalid(issues) => {
+                let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+                write!(f, "{}", issues.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for NefError {}
+
+impl From<LoopTableError> for NefError {
+    fn from(error: LoopTableError) -> Self {
+        NefError::Loop(error)
+    }
+}
+
+/// The standard save frames of a NEF file
+#[derive(Debug, Clone, PartialEq)]
+pub struct NefFile {
+    metadata: NefMetadata,
+    molecular_system: Vec<NefResidue>,
+    chemical_shift_lists: Vec<NefShiftList>,
+}
+
+impl NefFile {
+    /// Read the standard save frames of a parsed NEF file
+    ///
+    /// Fails with every violation of the mandatory frames, tags and value types found.
+    pub fn from_tree(tree: &MutablePair) -> Result<NefFile, NefError> {
+        let frames = frames(tree);
+        let mut reader = Reader::default();
+
+        let metadata = match reader.frame(&frames, "nef_nmr_meta_data", tree) {
+            Some(frame) => reader.metadata(frame),
+            None => NefMetadata::default(),
+        };
+        let molecular_system = match reader.frame(&frames, "nef_molecular_system", tree) {
+            Some(frame) => reader.molecular_system(frame),
+            None => Vec::new(),
+        };
+        let chemical_shift_lists: Vec<NefShiftList> = frames
+            .iter()
+            .filter(|frame| frame.category == "nef_chemical_shift_list")
+            .map(|frame| reader.shift_list(frame))
+            .collect();
+
+        if let Some(error) = reader.loop_error {
+            return Err(NefError::Loop(error));
+        }
+        if !reader.issues.is_empty() {
+            let mut issues = reader.issues;
+            issues.sort_by_key(|issue| (issue.position.line, issue.position.column));
+            return Err(NefError::Invalid(issues));
+        }
+        Ok(NefFile {
+            metadata,
+            molecular_system,
+            chemical_shift_lists,
+        })
+    }
+
+    /// The `nef_nmr_meta_data` save frame
+    pub fn metadata(&self) -> &NefMetadata {
+        &self.metadata
+    }
+
+    /// The residues of the `nef_molecular_system` save frame in file order
+    pub fn molecular_system(&self) -> &[NefResidue] {
+        &self.molecular_system
+    }
+
+    /// The `nef_chemical_shift_list` save frames in file order
+    pub fn chemical_shift_lists(&self) -> &[NefShiftList] {
+        &self.chemical_shift_lists
+    }
+}
+
+/// A save frame with its items and loops
+struct Frame<'a> {
+    pair: &'a MutablePair,
+    /// The value of its `sf_category` item, empty without one
+    category: &'a str,
+    items: Vec<(&'a str, &'a MutablePair)>,
+    /// The `data` nodes of its loops, their tables are only built for the loops read
+    loops: Vec<&'a MutablePair>,
+}
+
+/// The save frames of the data blocks of `tree` in file order
+fn frames(tree: &MutablePair) -> Vec<Frame<'_>> {
+    let mut frames = Vec::new();
+    let blocks = tree
+        .children()
+        .iter()
+        .filter(|block| block.rule_name() == "data_block");
+    for save_frame in blocks.flat_map(|block| block.children()) {
+        if save_frame.rule_name() != "save_frame" {
+            continue;
+        }
+        let mut frame = Frame {
+            pair: save_frame,
+            category: "",
+            items: Vec::new(),
+            loops: Vec::new(),
+        };
+        for data in save_frame
+            .children()
+            .iter()
+            .filter(|c| c.rule_name() == "data")
+        {
+            match data.children() {
+                [name, value] if name.rule_name() == "data_name" => {
+                    frame.items.push((name.as_str(), value))
+                }
+                _ => frame.loops.push(data),
+            }
+        }
+        frame.category = frame
+            .items
+            .iter()
+            .find(|(tag, _)| tag.to_ascii_lowercase().ends_with(".sf_category"))
+            .map_or("", |(_, value)| value_text(*value));
+        frames.push(frame);
+    }
+    frames
+}
+
+/// Reads typed values from save frames, collecting the violations found
+#[derive(Default)]
+struct Reader {
+    issues: Vec<ValidationIssue>,
+    /// The first ragged loop read, which stops its rows being read
+    loop_error: Option<LoopTableError>,
+}
+
+impl Reader {
+    /// Record that the mandatory `name` of `category` is missing at `position`
+    fn missing(&mut self, name: &str, category: &str, position: LineColumn) {
+        self.issues.push(ValidationIssue {
+            kind: ValidationIssueKind::MissingMandatoryItem,
+            name: name.to_string(),
+            position,
+            first_position: None,
+            detail: Some(category.to_string()),
+        });
+    }
+
+    /// Record that the value of `tag` at `position` isn't `expected`
+    fn mismatch(&mut self, tag: &str, position: LineColumn, expected: &str, found: &str) {
+        self.issues.push(ValidationIssue {
+            kind: ValidationIssueKind::TypeMismatch,
+            name: tag.to_string(),
+            position,
+            first_position: None,
+            detail: Some(format!("expected {} but found {}", expected, found)),
+        });
+    }
+
+    /// The first save frame of the mandatory `category`
+    fn frame<'f, 'a>(
+        &mut self,
+        frames: &'f [Frame<'a>],
+        category: &str,
+        tree: &MutablePair,
+    ) -> Option<&'f Frame<'a>> {
+        let frame = frames.iter().find(|frame| frame.category == category);
+        if frame.is_none() {
+            self.missing(&format!("save_{}", category), category, tree.start_position);
+        }
+        frame
+    }
+
+    /// The text of the mandatory item `tag` of `frame`, empty if it is missing
+    fn text(&mut self, frame: &Frame, tag: &str) -> String {
+        match item(frame, tag) {
+            Some(value) => value_text(value).to_string(),
+            None => {
+                self.missing(tag, frame.category, frame.pair.start_position);
+                String::new()
+            }
+        }
+    }
+
+    /// The loop of `frame` with the tags of `category`, checking it has the mandatory
+    /// `columns`
+    fn table<'a>(
+        &mut self,
+        frame: &Frame<'a>,
+        category: &str,
+        columns: &[&str],
+    ) -> Option<LoopTable<'a>> {
+        let prefix = format!("{}.", category);
+        let data = frame.loops.iter().find(|data| {
+            data.find_first("data_name")
+                .is_some_and(|column| column.as_str().to_ascii_lowercase().starts_with(&prefix))
+        });
+        let Some(data) = data else {
+            self.missing(category, frame.category, frame.pair.start_position);
+            return None;
+        };
+        let table = match LoopTable::from_pair(*data) {
+            Ok(table) => table,
+            Err(error) => {
+                self.loop_error.get_or_insert(error);
+                return None;
+            }
+        };
+        for column in columns {
+            let tag = format!("{}{}", prefix, column);
+            if table.column(&tag).is_none() {
+                self.missing(&tag, frame.category, table.position());
+            }
+        }
+        Some(table)
+    }
+
+    fn metadata(&mut self, frame: &Frame) -> NefMetadata {
+        NefMetadata {
+            format_name: self.text(frame, "_nef_nmr_meta_data.format_name"),
+            format_version: self.text(frame, "_nef_nmr_meta_data.format_version"),
+            program_name: self.text(frame, "_nef_nmr_meta_data.program_name"),
+            program_version: self.text(frame, "_nef_nmr_meta_data.program_version"),
+            creation_date: self.text(frame, "_nef_nmr_meta_data.creation_date"),
+            uuid: item(frame, "_nef_nmr_meta_data.uuid")
+                .and_then(|value| optional(value_text(value))),
+            coordinate_file_name: item(frame, "_nef_nmr_meta_data.coordinate_file_name")
+                .and_then(|value| optional(value_text(value))),
+        }
+    }
+
+    fn molecular_system(&mut self, frame: &Frame) -> Vec<NefResidue> {
+        let columns = ["index", "chain_code", "sequence_code", "residue_name"];
+        let Some(table) = self.table(frame, "_nef_sequence", &columns) else {
+            return Vec::new();
+        };
+        let row = Row::new(&table, "_nef_sequence");
+        (0..table.row_count())
+            .map(|index| NefResidue {
+                index: row.required_int(self, index, "index").unwrap_or_default(),
+                chain_code: row.text(index, "chain_code").unwrap_or_default(),
+                sequence_code: row.text(index, "sequence_code").unwrap_or_default(),
+                residue_name: row.text(index, "residue_name").unwrap_or_default(),
+                linking: row.text(index, "linking"),
+                residue_variant: row.text(index, "residue_variant"),
+                cis_peptide: row.bool(self, index, "cis_peptide"),
+            })
+            .collect()
+    }
+
+    fn shift_list(&mut self, frame: &Frame) -> NefShiftList {
+        let framecode = self.text(frame, "_nef_chemical_shift_list.sf_framecode");
+        let columns = [
+            "chain_code",
+            "sequence_code",
+            "residue_name",
+            "atom_name",
+            "value",
+        ];
+        let Some(table) = self.table(frame, "_nef_chemical_shift", &columns) else {
+            return NefShiftList {
+                framecode,
+                shifts: Vec::new(),
+            };
+        };
+        let row = Row::new(&table, "_nef_chemical_shift");
+        let shifts = (0..table.row_count())
+            .map(|index| NefShift {
+                chain_code: row.text(index, "chain_code").unwrap_or_default(),
+                sequence_code: row.text(index, "sequence_code").unwrap_or_default(),
+                residue_name: row.text(index, "residue_name"),
+                atom_name: row.text(index, "atom_name").unwrap_or_default(),
+                value: row.required_float(self, index, "value").unwrap_or(f64::NAN),
+                uncertainty: row.float(self, index, "value_uncertainty"),
+                element: row.text(index, "element"),
+                isotope_number: row.int(self, index, "isotope_number"),
+            })
+            .collect();
+        NefShiftList { framecode, shifts }
+    }
+}
+
+/// The value of the item `tag` of `frame`, compared case-insensitively
+fn item<'a>(frame: &Frame<'a>, tag: &str) -> Option<&'a MutablePair> {
+    frame
+        .items
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+        .map(|(_, value)| *value)
+}
+
+/// `text` unless it is `.` or `?`
+fn optional(text: &str) -> Option<String> {
+    (!matches!(text, "." | "?")).then(|| text.to_string())
+}
+
+/// Typed reads of the values in the rows of a loop of `category`
+struct Row<'t, 'a> {
+    table: &'t LoopTable<'a>,
+    category: &'static str,
+}
+
+impl<'t, 'a> Row<'t, 'a> {
+    fn new(table: &'t LoopTable<'a>, category: &'static str) -> Self {
+        Row { table, category }
+    }
+
+    /// The value of `column` in row `index` with its position, `None` without the column
+    fn value(&self, index: usize, column: &str) -> Option<(String, &'a str, LineColumn)> {
+        let tag = format!("{}.{}", self.category, column);
+        let position = self
+            .table
+            .columns()
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(&tag))?;
+        let value = self.table.value(index, position)?;
+        Some((tag, value, self.table.value_position(index, position)?))
+    }
+
+    fn text(&self, index: usize, column: &str) -> Option<String> {
+        self.value(index, column)
+            .and_then(|(_, value, _)| optional(value))
+    }
+
+    fn int(&self, reader: &mut Reader, index: usize, column: &str) -> Option<i64> {
+        self.typed(
+            reader,
+            index,
+            column,
+            false,
+            "an integer",
+            StarValue::as_i64,
+        )
+    }
+
+    fn required_int(&self, reader: &mut Reader, index: usize, column: &str) -> Option<i64> {
+        self.typed(reader, index, column, true, "an integer", StarValue::as_i64)
+    }
+
+    fn float(&self, reader: &mut Reader, index: usize, column: &str) -> Option<f64> {
+        self.typed(reader, index, column, false, "a number", StarValue::as_f64)
+    }
+
+    fn required_float(&self, reader: &mut Reader, index: usize, column: &str) -> Option<f64> {
+        self.typed(reader, index, column, true, "a number", StarValue::as_f64)
+    }
+
+    fn bool(&self, reader: &mut Reader, index: usize, column: &str) -> Option<bool> {
+        self.typed(
+            reader,
+            index,
+            column,
+            false,
+            "true or false",
+            |value| match value.as_str()?.to_ascii_lowercase().as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            },
+        )
+    }
+
+    /// The value of `column` in row `index` read by `convert`, recording a mismatch if it
+    /// can't be; `.` and `?` are `None`, and a mismatch too when the value is `required`
+    fn typed<T>(
+        &self,
+        reader: &mut Reader,
+        index: usize,
+        column: &str,
+        required: bool,
+        expected: &str,
+        convert: impl Fn(&StarValue) -> Option<T>,
+    ) -> Option<T> {
+        let (tag, value, position) = self.value(index, column)?;
+        let typed = StarValue::parse(value);
+        if typed.is_null_or_unknown() {
+            if required {
+                reader.mismatch(&tag, position, expected, value);
+            }
+            return None;
+        }
+        let converted = convert(&typed);
+        if converted.is_none() {
+            reader.mismatch(&tag, position, expected, value);
+        }
+        converted
+    }
+}
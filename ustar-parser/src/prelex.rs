@@ -0,0 +1,362 @@
+//! Prelex - a fast lexical pass that compacts the input before pest parses it.
+//!
+//! pest spends most of its time on the whitespace and comment rules it tries between every pair
+//! of tokens, and on text fields, which it reads a character at a time. The pre-lexer scans the
+//! input once and builds a compacted view for pest to parse: each run of blanks, line ends and
+//! comments between tokens becomes a single space, or a single newline if the run holds one, each
+//! text field becomes an empty one and every other token is copied as it is written. The spans
+//! of the pairs pest produces are mapped back to the input, so the tree is the one `parse` builds
+//! from the input itself.
+//!
+//! Comments are kept in a side table. Those pest would have kept as `comment` nodes, the comments
+//! inside a loop, are put back into the tree where they were. A comment on a line before a text
+//! field is copied rather than removed, since pest only treats it as a comment in a loop.
+//!
+//! The pass gives up, and the input is parsed as it is, whenever it can't be sure of finding the
+//! tokens pest would: for characters outside printable ASCII other than those the encoding reads
+//! as ordinary characters, for quoted strings, text fields and frame codes that end in the middle
+//! of a run of characters and for strings or text fields that aren't closed. Input that fails to
+//! parse once compacted is parsed again as it is, so errors are always those of the input.
+
+use crate::line_column_index::LineColumnIndex;
+use crate::mutable_pair::MutablePair;
+use crate::shared_text::{RuleNames, SharedText};
+use crate::EncodingMode;
+use pest::RuleType;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Rules whose children include the comments inside them
+const COMMENT_HOLDERS: [&str; 4] = [
+    "data_loop",
+    "data_loop_definition",
+    "nested_loop",
+    "data_loop_values",
+];
+
+/// Keywords that pest may split from characters written directly after them
+const KEYWORDS: [&[u8]; 5] = [b"data_", b"global_", b"loop_", b"save_", b"stop_"];
+
+/// The text field pest is given in place of each text field of the input
+const EMPTY_TEXT_FIELD: &str = "\n;\n;";
+
+/// A compacted view of an input and the offsets needed to map it back
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Prelexed {
+    /// The input with the runs between its tokens compacted
+    text: String,
+    /// Offsets in `text` and the input where a run of copied text starts, the text between one
+    /// anchor and the next maps to the input one to one
+    anchors: Vec<(usize, usize)>,
+    /// The byte ranges of the input holding the comments removed, in order
+    comments: Vec<(usize, usize)>,
+}
+
+impl Prelexed {
+    /// The compacted text for pest to parse
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The offset in the input of `offset` in the compacted text
+    ///
+    /// Offsets at either end of a compacted run map to the ends of the run it replaced. `anchor`
+    /// is the anchor the last offset mapped was found at, offsets are mapped in document order so
+    /// the search usually moves on by a few anchors from there.
+    fn original_offset(&self, offset: usize, anchor: &mut usize) -> usize {
+        if self.anchors[*anchor].0 > offset {
+            *anchor = self
+                .anchors
+                .partition_point(|&(compact, _)| compact <= offset)
+                - 1;
+        }
+        while self
+            .anchors
+            .get(*anchor + 1)
+            .is_some_and(|&(compact, _)| compact <= offset)
+        {
+            *anchor += 1;
+        }
+        let (compact, original) = self.anchors[*anchor];
+        original + (offset - compact)
+    }
+
+    /// The trees of `pairs` parsed from the compacted text, with the spans, content and positions
+    /// of the nodes those of `input` and the comments pest would have kept restored
+    pub(crate) fn to_mutable_pairs<'a, R: RuleType>(
+        &self,
+        pairs: impl Iterator<Item = pest::iterators::Pair<'a, R>>,
+        input: &str,
+    ) -> Vec<MutablePair> {
+        trace_span!(span, "process_pairs", roots = tracing::field::Empty);
+        let source = Arc::new(input.to_string());
+        let line_index = LineColumnIndex::new(input);
+        let mut rule_names = RuleNames::new();
+        let mut anchor = 0;
+        let mut trees: Vec<MutablePair> = pairs
+            .map(|pair| {
+                let mut names = (&mut rule_names, &mut anchor);
+                self.to_mutable_pair(&pair, &source, &line_index, &mut names)
+            })
+            .collect();
+        let mut rest = self.comments.as_slice();
+        for tree in &mut trees {
+            let inside = rest.partition_point(|&(_, end)| end <= tree.end);
+            let outside = rest[..inside].partition_point(|&(_, end)| end <= tree.start);
+            restore_comments(tree, &rest[outside..inside], &source, &line_index);
+            rest = &rest[inside..];
+        }
+        trace_record!(span, "roots", trees.len());
+        trees
+    }
+
+    /// Convert `pair` and its descendants, mapping each start before the node's children and
+    /// each end after them so the offsets are mapped in order
+    fn to_mutable_pair<R: RuleType>(
+        &self,
+        pair: &pest::iterators::Pair<R>,
+        source: &Arc<String>,
+        line_index: &LineColumnIndex,
+        state: &mut (&mut RuleNames<R>, &mut usize),
+    ) -> MutablePair {
+        let rule_name = state.0.get(pair.as_rule());
+        let span = pair.as_span();
+        let start = self.original_offset(span.start(), state.1);
+        #[cfg(feature = "trace")]
+        let block = crate::trace::enter_block(rule_name, start);
+        let inner = pair.clone().into_inner();
+        let mut children = Vec::with_capacity(inner.len());
+        children.extend(inner.map(|child| self.to_mutable_pair(&child, source, line_index, state)));
+        let end = self.original_offset(span.end(), state.1);
+
+        #[cfg(feature = "trace")]
+        crate::trace::exit_block(block, end, children.len());
+        MutablePair {
+            rule_name: Cow::Borrowed(rule_name),
+            content: SharedText::new(source.clone(), start, end),
+            start,
+            end,
+            start_position: line_index.offset_to_line_col(start),
+            end_position: line_index.offset_to_line_col(end),
+            children,
+        }
+    }
+}
+
+/// Put the `comments` that lie within `pair` back as `comment` nodes of the innermost loop rule
+/// holding them, comments outside any loop are dropped as pest drops them (private)
+fn restore_comments(
+    pair: &mut MutablePair,
+    comments: &[(usize, usize)],
+    source: &Arc<String>,
+    line_index: &LineColumnIndex,
+) {
+    if comments.is_empty() {
+        return;
+    }
+    let mut between = Vec::new();
+    let mut rest = comments;
+    for child in &mut pair.children {
+        let before = rest.partition_point(|&(_, end)| end <= child.start);
+        between.extend_from_slice(&rest[..before]);
+        rest = &rest[before..];
+        let inside = rest.partition_point(|&(_, end)| end <= child.end);
+        restore_comments(child, &rest[..inside], source, line_index);
+        rest = &rest[inside..];
+    }
+    between.extend_from_slice(rest);
+
+    if !COMMENT_HOLDERS.contains(&pair.rule_name.as_ref()) {
+        return;
+    }
+    for (start, end) in between {
+        let index = pair.children.partition_point(|child| child.start < start);
+        let mut comment = MutablePair::new(
+            "comment",
+            SharedText::new(source.clone(), start, end),
+            start,
+            end,
+        );
+        comment.start_position = line_index.offset_to_line_col(start);
+        comment.end_position = line_index.offset_to_line_col(end);
+        pair.insert_child(index, comment);
+    }
+}
+
+/// The compacted view of `input` for pest to parse in `encoding`, `None` if the pre-lexer can't
+/// be sure of finding the tokens pest would
+///
+/// CIF 2.0 input isn't compacted, its lists and tables have their own rules for quoted strings.
+pub(crate) fn prelex(input: &str, encoding: EncodingMode) -> Option<Prelexed> {
+    if !only_plain_characters(input, encoding) {
+        return None;
+    }
+    let bytes = input.as_bytes();
+    let mut prelexed = Prelexed {
+        text: String::with_capacity(input.len()),
+        anchors: vec![(0, 0)],
+        comments: Vec::new(),
+    };
+    // the start of the input not yet copied to the compacted text
+    let mut copied_to = 0;
+    let mut pos = 0;
+
+    loop {
+        let gap_start = pos;
+        let mut has_newline = false;
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b' ' | b'\t' => pos += 1,
+                b'\r' | b'\n' => {
+                    let newline = newline_length(bytes, pos);
+                    if bytes.get(pos + newline) == Some(&b';') {
+                        break;
+                    }
+                    has_newline = true;
+                    pos += newline;
+                }
+                b'#' => {
+                    let end = line_end(bytes, pos);
+                    // pest only reads a comment before a text field as one inside a loop
+                    if end < bytes.len()
+                        && bytes.get(end + newline_length(bytes, end)) == Some(&b';')
+                    {
+                        break;
+                    }
+                    prelexed.comments.push((pos, end));
+                    pos = end;
+                }
+                _ => break,
+            }
+        }
+
+        // a `;` after blanks isn't at a line start, it mustn't become the start of a text field
+        let replacement = match (has_newline, bytes.get(pos)) {
+            (true, Some(b';')) => "\n ",
+            (true, _) => "\n",
+            (false, _) => " ",
+        };
+        if pos > gap_start && &input[gap_start..pos] != replacement {
+            prelexed.text.push_str(&input[copied_to..gap_start]);
+            prelexed.text.push_str(replacement);
+            prelexed.anchors.push((prelexed.text.len(), pos));
+            copied_to = pos;
+        }
+        if pos == bytes.len() {
+            break;
+        }
+
+        let end = match bytes[pos] {
+            b'\r' | b'\n' => text_field_end(bytes, pos)?,
+            b'#' => line_end(bytes, pos),
+            b'\'' | b'"' => quoted_end(bytes, pos)?,
+            _ => plain_end(bytes, pos)?,
+        };
+        // pest reads a token directly after a string or text field, the pre-lexer doesn't
+        if end < bytes.len() && !matches!(bytes[end], b' ' | b'\t' | b'\r' | b'\n') {
+            return None;
+        }
+        // a text field is a single token, so pest only needs to see an empty one in its place
+        if matches!(bytes[pos], b'\r' | b'\n') && end - pos > EMPTY_TEXT_FIELD.len() {
+            prelexed.text.push_str(&input[copied_to..pos]);
+            prelexed.text.push_str(EMPTY_TEXT_FIELD);
+            prelexed.anchors.push((prelexed.text.len(), end));
+            copied_to = end;
+        }
+        pos = end;
+    }
+
+    prelexed.text.push_str(&input[copied_to..]);
+    Some(prelexed)
+}
+
+/// Whether every character of `input` is a blank, a line end or a character that `encoding`
+/// reads as an ordinary non-blank character (private)
+///
+/// Extended ASCII input is only compacted if it is ASCII and Unicode input only if its blanks are
+/// ASCII, so the pre-lexer only has to know spaces and tabs as blanks.
+fn only_plain_characters(input: &str, encoding: EncodingMode) -> bool {
+    let bytes = input.as_bytes();
+    let plain_bytes = bytes.iter().enumerate().all(|(index, &byte)| match byte {
+        b'\t' | b'\n' => true,
+        b'\r' => bytes.get(index + 1) == Some(&b'\n'),
+        0..=0x1F => false,
+        0x7F => encoding != EncodingMode::Ascii,
+        0x80.. => encoding == EncodingMode::Unicode,
+        _ => true,
+    });
+    plain_bytes && (input.is_ascii() || !input.chars().any(|c| c.is_whitespace() && !c.is_ascii()))
+}
+
+/// The length of the line end at `pos`, `\n` or `\r\n` (private)
+fn newline_length(bytes: &[u8], pos: usize) -> usize {
+    if bytes[pos] == b'\r' {
+        2
+    } else {
+        1
+    }
+}
+
+/// The offset of the line end after `pos`, or the end of `bytes` (private)
+fn line_end(bytes: &[u8], pos: usize) -> usize {
+    memchr::memchr2(b'\r', b'\n', &bytes[pos..]).map_or(bytes.len(), |length| pos + length)
+}
+
+/// The offset after the text field whose opening line end is at `pos`, `None` if it isn't closed
+/// (private)
+fn text_field_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let content = pos + newline_length(bytes, pos) + 1;
+    memchr::memmem::find(&bytes[content..], b"\n;").map(|close| content + close + 2)
+}
+
+/// The offset after the quoted string opened at `pos` as the grammar reads it, `None` if it isn't
+/// closed on its line (private)
+///
+/// Triple quotes close at the next triple quote. Otherwise a quote closes the string when it is
+/// followed by a blank or a line end; before any other character, or doubled, it is part of the
+/// string.
+fn quoted_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let quote = bytes[pos];
+    let triple = [quote; 3];
+    if bytes[pos..].starts_with(&triple) {
+        return memchr::memmem::find(&bytes[pos + 3..], &triple).map(|close| pos + 3 + close + 3);
+    }
+    let ends_string = |index: usize| {
+        bytes
+            .get(index)
+            .is_none_or(|&byte| matches!(byte, b' ' | b'\t' | b'\r' | b'\n'))
+    };
+    let mut index = pos + 1;
+    loop {
+        match *bytes.get(index)? {
+            b'\r' | b'\n' => return None,
+            byte if byte == quote => match bytes.get(index + 1) {
+                _ if ends_string(index + 1) => return Some(index + 1),
+                Some(&next) if next == quote => index += if ends_string(index + 2) { 1 } else { 2 },
+                _ => index += 2,
+            },
+            _ => index += 1,
+        }
+    }
+}
+
+/// The offset after the token of ordinary characters at `pos`, `None` if pest might end the token
+/// at a quote or comment within it (private)
+///
+/// Data names, headings and values run to the next blank. A frame code stops at a quote and a
+/// keyword can be directly followed by a string or comment, so those are left to pest.
+fn plain_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let end = bytes[pos..]
+        .iter()
+        .position(|&byte| matches!(byte, b' ' | b'\t' | b'\r' | b'\n'))
+        .map_or(bytes.len(), |length| pos + length);
+    let token = &bytes[pos..end];
+    let splittable = token[0] == b'$'
+        || KEYWORDS.iter().any(|keyword| {
+            token.len() > keyword.len() && token[..keyword.len()].eq_ignore_ascii_case(keyword)
+        });
+    if splittable && token.iter().any(|byte| matches!(byte, b'\'' | b'"' | b'#')) {
+        return None;
+    }
+    Some(end)
+}
@@ -0,0 +1,45 @@
+//! Progress - reports on long parses and walks, and their cancellation.
+//!
+//! Progress is reported once for each top level data or global block, after the block has been
+//! parsed or walked. A callback returning `ControlFlow::Break` cancels the rest of the input,
+//! which is then reported as a `UstarError::Cancelled` at the end of the last block completed.
+
+use crate::validate::error_at;
+use crate::{EncodingMode, ErrorCode, UstarError};
+use std::ops::ControlFlow;
+
+/// How far a parse or walk has got through its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseProgress {
+    /// Bytes of the input up to the end of the last block completed
+    pub bytes_consumed: usize,
+    /// Bytes of the whole input, without any byte order mark
+    pub total_bytes: usize,
+    /// Top level data and global blocks completed
+    pub blocks_completed: usize,
+}
+
+/// A callback given each progress report, returning `ControlFlow::Break(())` to cancel
+pub type ProgressCallback<'p> = dyn FnMut(ParseProgress) -> ControlFlow<()> + 'p;
+
+/// The error for a parse or walk of `input` cancelled after `progress` (private)
+pub(crate) fn cancelled_error(
+    input: &str,
+    progress: ParseProgress,
+    encoding: EncodingMode,
+) -> Box<UstarError> {
+    let blocks = progress.blocks_completed;
+    let message = format!(
+        "parsing cancelled after {} {}",
+        blocks,
+        if blocks == 1 { "block" } else { "blocks" }
+    );
+    let error = error_at(
+        input,
+        progress.bytes_consumed,
+        ErrorCode::E0022Cancelled,
+        message,
+        encoding,
+    );
+    Box::new(error.into_cancelled())
+}
@@ -0,0 +1,312 @@
+//! Query - selecting nodes of a parsed tree with STAR paths.
+//!
+//! A path is a list of segments separated by `/`, each selecting among the children of the
+//! nodes the previous segments selected, starting from the root:
+//!
+//! - `data_NAME` selects data blocks, `global_` global blocks and `save_NAME` save frames
+//! - `_NAME` selects data items, as their `data` node, and loop columns, as their values
+//! - `*` selects any of them, and a `*` in a name matches any run of characters
+//! - `//` before a segment selects at any depth below, so `//_atom_site.Cartn_x` is every
+//!   `_atom_site.Cartn_x` of the file
+//! - `[N]` after a segment keeps only row N, counting from 1, of each loop column, an item
+//!   being a single row, and only the N-th of the blocks or save frames it selects
+//!
+//! Names are compared case-insensitively, and a leading `/` is optional. Loops that are ragged
+//! have no columns to select.
+
+use crate::loop_table::LoopTable;
+use crate::mutable_pair::PairNode;
+use std::fmt;
+
+/// What a segment of a path matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Any,
+    Block(String),
+    Global,
+    Frame(String),
+    Tag(String),
+}
+
+/// A segment of a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    /// Whether the segment matches at any depth, after `//`
+    descendant: bool,
+    pattern: Pattern,
+    /// The row or match to keep, counting from 1
+    index: Option<usize>,
+}
+
+/// A parsed STAR path, see the module documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+/// Errors from parsing a STAR path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// The path has no segments
+    Empty,
+    /// A segment that is empty, has three or more `/` before it, or isn't a block, save frame,
+    /// data name or `*`
+    Segment { segment: String, column: usize },
+    /// An index predicate that isn't a number from 1
+    Index { predicate: String, column: usize },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Empty => write!(f, "the query has no segments"),
+            QueryError::Segment { segment, column } => write!(
+                f,
+                "c{} '{}' isn't a data_, global_, save_ or data name segment or *",
+                column, segment
+            ),
+            QueryError::Index { predicate, column } => write!(
+                f,
+                "c{} {} isn't a row number counting from 1",
+                column, predicate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Parse a STAR path such as `data_experiment/save_fragment_1/_molecular_weight`
+pub fn parse_query(query: &str) -> Result<Query, QueryError> {
+    if query.is_empty() {
+        return Err(QueryError::Empty);
+    }
+    let mut steps = Vec::new();
+    let mut position = 0;
+    while position < query.len() {
+        let rest = &query[position..];
+        let slashes = rest.len() - rest.trim_start_matches('/').len();
+        let start = position + slashes;
+        let end = query[start..]
+            .find('/')
+            .map_or(query.len(), |end| start + end);
+        if slashes > 2 || start == end {
+            return Err(QueryError::Segment {
+                segment: query[position..end].to_string(),
+                column: position + 1,
+            });
+        }
+        steps.push(parse_step(&query[start..end], slashes == 2, start + 1)?);
+        position = end;
+    }
+    Ok(Query { steps })
+}
+
+/// Parse one segment of a path, starting at `column` of the query
+fn parse_step(segment: &str, descendant: bool, column: usize) -> Result<Step, QueryError> {
+    let (name, index) = match segment
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once('['))
+    {
+        Some((name, predicate)) => match predicate.parse::<usize>() {
+            Ok(index) if index > 0 => (name, Some(index)),
+            _ => {
+                return Err(QueryError::Index {
+                    predicate: format!("[{}]", predicate),
+                    column: column + name.len(),
+                })
+            }
+        },
+        None => (segment, None),
+    };
+
+    let keyword = |keyword: &str| {
+        name.get(..keyword.len())
+            .filter(|start| start.eq_ignore_ascii_case(keyword))
+            .map(|_| name[keyword.len()..].to_string())
+            .filter(|rest| !rest.is_empty())
+    };
+    let pattern = if name == "*" {
+        Pattern::Any
+    } else if name.eq_ignore_ascii_case("global_") {
+        Pattern::Global
+    } else if let Some(block) = keyword("data_") {
+        Pattern::Block(block)
+    } else if let Some(frame) = keyword("save_") {
+        Pattern::Frame(frame)
+    } else if name.len() > 1 && name.starts_with('_') {
+        Pattern::Tag(name.to_string())
+    } else {
+        return Err(QueryError::Segment {
+            segment: segment.to_string(),
+            column,
+        });
+    };
+
+    Ok(Step {
+        descendant,
+        pattern,
+        index,
+    })
+}
+
+impl Query {
+    /// The nodes of `root`, a parsed file, block or save frame, the path selects, in the order
+    /// of the segments and then of the document; `root` is a node of a `MutablePair` tree or
+    /// of any other `PairNode` backend, such as a `TreeArena`
+    pub fn select<'a, N: PairNode<'a>>(&self, root: N) -> Vec<N> {
+        let mut selected = vec![root];
+        for step in &self.steps {
+            if step.descendant {
+                let mut scopes = Vec::new();
+                for node in selected {
+                    scopes.push(node);
+                    scopes_below(node, &mut scopes);
+                }
+                selected = scopes;
+            }
+            let mut matches = Vec::new();
+            for node in selected {
+                step.select(node, &mut matches);
+            }
+            selected = matches;
+        }
+        selected
+    }
+}
+
+impl Step {
+    /// Add the children of `parent` this segment selects to `matches`
+    fn select<'a, N: PairNode<'a>>(&self, parent: N, matches: &mut Vec<N>) {
+        let mut scopes = 0;
+        for child in parent.child_nodes() {
+            match child.rule_name() {
+                "data_block" | "global_block" | "save_frame" if self.matches_scope(child) => {
+                    scopes += 1;
+                    if self.index.is_none_or(|index| index == scopes) {
+                        matches.push(child);
+                    }
+                }
+                "data" => self.select_data(child, matches),
+                _ => {}
+            }
+        }
+    }
+
+    /// Add the item or loop columns of `data` this segment selects to `matches`
+    fn select_data<'a, N: PairNode<'a>>(&self, data: N, matches: &mut Vec<N>) {
+        if let (2, Some(name)) = (data.child_count(), data.child(0)) {
+            if name.rule_name() == "data_name" {
+                if self.matches_tag(name.as_str()) && self.index.is_none_or(|index| index == 1) {
+                    matches.push(data);
+                }
+                return;
+            }
+        }
+
+        let Ok(table) = LoopTable::from_pair(data) else {
+            return;
+        };
+        let Some(definition) = data.find_first_node("data_loop_definition") else {
+            return;
+        };
+        let mut names = Vec::new();
+        nodes_below(definition, "data_name", &mut names);
+        for name in names {
+            if self.matches_tag(name.as_str()) {
+                let mut column = Vec::new();
+                column_nodes(&table, name.as_str(), &mut column);
+                match self.index {
+                    Some(index) => matches.extend(column.get(index - 1)),
+                    None => matches.extend(column),
+                }
+            }
+        }
+    }
+
+    /// Whether this segment selects the block or save frame `scope`
+    fn matches_scope<'a, N: PairNode<'a>>(&self, scope: N) -> bool {
+        let heading = scope.child(0).map_or("", |heading| heading.as_str());
+        match (&self.pattern, scope.rule_name()) {
+            (Pattern::Any, _) | (Pattern::Global, "global_block") => true,
+            // data_ and save_ are the same length
+            (Pattern::Block(name), "data_block") | (Pattern::Frame(name), "save_frame") => {
+                glob_matches(name, &heading["data_".len()..])
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether this segment selects the data name `tag`
+    fn matches_tag(&self, tag: &str) -> bool {
+        match &self.pattern {
+            Pattern::Any => true,
+            Pattern::Tag(pattern) => glob_matches(pattern, tag),
+            _ => false,
+        }
+    }
+}
+
+/// Add the value nodes of the column `tag` of `table`, or of its nested sub-tables, to `nodes`
+/// in document order
+fn column_nodes<'a, N: PairNode<'a>>(table: &LoopTable<'a, N>, tag: &str, nodes: &mut Vec<N>) {
+    if let Some(column) = table.columns().iter().position(|name| name == tag) {
+        nodes.extend((0..table.row_count()).filter_map(|row| table.value_node(row, column)));
+        return;
+    }
+    for row in 0..table.row_count() {
+        if let Some(nested) = table.nested(row) {
+            column_nodes(nested, tag, nodes);
+        }
+    }
+}
+
+/// Whether a node holds blocks, save frames or data items that segments can select
+fn is_scope<'a, N: PairNode<'a>>(node: N) -> bool {
+    matches!(
+        node.rule_name(),
+        "star_file" | "data_block" | "global_block" | "save_frame"
+    )
+}
+
+/// Add the descendants of `node` that are scopes to `scopes`, depth-first
+fn scopes_below<'a, N: PairNode<'a>>(node: N, scopes: &mut Vec<N>) {
+    for child in node.child_nodes() {
+        if is_scope(child) {
+            scopes.push(child);
+        }
+        scopes_below(child, scopes);
+    }
+}
+
+/// Add the descendants of `node` with the rule `rule_name` to `nodes`, depth-first
+fn nodes_below<'a, N: PairNode<'a>>(node: N, rule_name: &str, nodes: &mut Vec<N>) {
+    for child in node.child_nodes() {
+        if child.rule_name() == rule_name {
+            nodes.push(child);
+        }
+        nodes_below(child, rule_name, nodes);
+    }
+}
+
+/// Whether `text` matches `pattern`, in which `*` matches any run of characters, ignoring
+/// ASCII case
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.to_ascii_lowercase(), text.to_ascii_lowercase());
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(found) => rest = &rest[found + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
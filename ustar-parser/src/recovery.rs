@@ -0,0 +1,281 @@
+//! Error recovery - drives the grammar one data item at a time so that parsing can continue after
+//! a syntax error.
+//!
+//! Block and save frame structure is tracked here while headings, data items and `save_`
+//! terminators are parsed individually with the sub-rules of the grammar. When a piece fails to
+//! parse, an error is recorded and parsing resumes at the next `data_`, `save_`, `loop_` or
+//! `global_` keyword outside a quoted string or semicolon text field.
+
+use crate::fragment::{
+    next_keyword, parse_fragment, skip_trivia, starts_with_keyword, LineTracker,
+};
+use crate::mutable_pair::MutablePair;
+use crate::shared_text::SharedText;
+use crate::{EncodingMode, ErrorCode, UstarError};
+use pest::error::{Error, ErrorVariant};
+use pest::{Parser, Position, RuleType};
+use std::sync::Arc;
+
+/// The grammar rules parsed individually by the recovery driver
+pub(crate) struct RecoveryRules<R> {
+    pub data_heading: R,
+    pub global_keyword: R,
+    pub save_heading: R,
+    pub save_keyword: R,
+    pub data: R,
+}
+
+/// Keywords that resynchronise parsing after an error
+const BOUNDARY_KEYWORDS: [&str; 4] = ["data_", "save_", "loop_", "global_"];
+
+/// What starts at the current position
+enum Start {
+    DataHeading,
+    Global,
+    SaveHeading,
+    SaveEnd,
+    Item,
+}
+
+/// A data block, global block or save frame that is still collecting children
+struct OpenNode {
+    rule_name: &'static str,
+    start: usize,
+    children: Vec<MutablePair>,
+}
+
+impl OpenNode {
+    fn new(rule_name: &'static str, heading: MutablePair) -> Self {
+        OpenNode {
+            rule_name,
+            start: heading.start,
+            children: vec![heading],
+        }
+    }
+
+    fn close(self, source: &Arc<String>) -> MutablePair {
+        let first = &self.children[0];
+        let last = self.children.last().expect("nodes open with a heading");
+        let (start_position, end_position) = (first.start_position, last.end_position);
+        let end = last.end;
+        let mut node = MutablePair::with_children(
+            self.rule_name,
+            SharedText::new(source.clone(), self.start, end),
+            self.start,
+            end,
+            self.children,
+        );
+        node.start_position = start_position;
+        node.end_position = end_position;
+        node
+    }
+}
+
+struct Driver<'i, R> {
+    input: &'i str,
+    source: Arc<String>,
+    encoding: EncodingMode,
+    blocks: Vec<MutablePair>,
+    errors: Vec<UstarError>,
+    block: Option<OpenNode>,
+    frame: Option<OpenNode>,
+    _rule: std::marker::PhantomData<R>,
+}
+
+impl<'i, R: RuleType> Driver<'i, R> {
+    fn error(&mut self, position: usize, code: ErrorCode, message: String) {
+        let position = Position::new(self.input, position).expect("error inside input");
+        let error = Error::<R>::new_from_pos(ErrorVariant::CustomError { message }, position);
+        self.errors
+            .push(UstarError::from_pest_error(error, self.encoding, self.input).with_code(code));
+    }
+
+    fn close_frame(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            let closed =
+                frame.children.last().map(|child| child.rule_name()) == Some("save_keyword");
+            if !closed {
+                let name = frame.children[0].content[5..].to_string();
+                self.error(
+                    frame.start,
+                    ErrorCode::E0007UnterminatedSaveFrame,
+                    format!("Save frame save_{} is not closed by save_", name),
+                );
+            }
+            let frame = frame.close(&self.source);
+            match self.block.as_mut() {
+                Some(block) => block.children.push(frame),
+                None => self.blocks.push(frame),
+            }
+        }
+    }
+
+    fn close_block(&mut self) {
+        self.close_frame();
+        if let Some(block) = self.block.take() {
+            if block.children.len() == 1 {
+                let heading = block.children[0].content.clone();
+                self.error(
+                    block.start,
+                    ErrorCode::E0009EmptyBlock,
+                    format!("Block {} contains no data items", heading),
+                );
+            }
+            self.blocks.push(block.close(&self.source));
+        }
+    }
+
+    fn add_item(&mut self, item: MutablePair) {
+        let item_start = item.start;
+        match (self.frame.as_mut(), self.block.as_mut()) {
+            (Some(frame), _) => frame.children.push(item),
+            (None, Some(block)) => block.children.push(item),
+            (None, None) => self.error(
+                item_start,
+                ErrorCode::E0010OutsideDataBlock,
+                "Data item outside a data block".to_string(),
+            ),
+        }
+    }
+}
+
+/// Parse `input`, returning the blocks that parsed and one error per failed region
+pub(crate) fn recover<P: Parser<R>, R: RuleType>(
+    input: &str,
+    rules: &RecoveryRules<R>,
+    encoding: EncodingMode,
+) -> (Vec<MutablePair>, Vec<UstarError>) {
+    let mut driver = Driver::<R> {
+        input,
+        source: Arc::new(input.to_string()),
+        encoding,
+        blocks: Vec::new(),
+        errors: Vec::new(),
+        block: None,
+        frame: None,
+        _rule: std::marker::PhantomData,
+    };
+
+    // each fragment runs from its start to the end of the input, sharing the one copy of it
+    let source = driver.source.clone();
+    let fragment = |pos: usize| SharedText::new(source.clone(), pos, input.len());
+    let mut lines = LineTracker::new();
+    let mut pos = skip_trivia(input, 0);
+    while pos < input.len() {
+        let origin = lines.position(input, pos);
+        let parsed = match start_at(&input[pos..]) {
+            Start::DataHeading => parse_fragment::<P, R>(
+                input,
+                &fragment(pos),
+                pos,
+                origin,
+                rules.data_heading,
+                encoding,
+            )
+            .map(|heading| {
+                driver.close_block();
+                let end = heading.end;
+                driver.block = Some(OpenNode::new("data_block", heading));
+                end
+            }),
+            Start::Global => parse_fragment::<P, R>(
+                input,
+                &fragment(pos),
+                pos,
+                origin,
+                rules.global_keyword,
+                encoding,
+            )
+            .map(|keyword| {
+                driver.close_block();
+                let end = keyword.end;
+                driver.block = Some(OpenNode::new("global_block", keyword));
+                end
+            }),
+            Start::SaveHeading => parse_fragment::<P, R>(
+                input,
+                &fragment(pos),
+                pos,
+                origin,
+                rules.save_heading,
+                encoding,
+            )
+            .map(|heading| {
+                driver.close_frame();
+                if driver.block.as_ref().map(|block| block.rule_name) != Some("data_block") {
+                    driver.error(
+                        heading.start,
+                        ErrorCode::E0010OutsideDataBlock,
+                        "Save frame outside a data block".to_string(),
+                    );
+                }
+                let end = heading.end;
+                driver.frame = Some(OpenNode::new("save_frame", heading));
+                end
+            }),
+            Start::SaveEnd => parse_fragment::<P, R>(
+                input,
+                &fragment(pos),
+                pos,
+                origin,
+                rules.save_keyword,
+                encoding,
+            )
+            .map(|keyword| {
+                let end = keyword.end;
+                match driver.frame.as_mut() {
+                    Some(frame) => {
+                        frame.children.push(keyword);
+                        driver.close_frame();
+                    }
+                    None => driver.error(
+                        keyword.start,
+                        ErrorCode::E0008UnmatchedSaveKeyword,
+                        "save_ without an open save frame".to_string(),
+                    ),
+                }
+                end
+            }),
+            Start::Item => {
+                parse_fragment::<P, R>(input, &fragment(pos), pos, origin, rules.data, encoding)
+                    .map(|mut item| {
+                        // CIF2 items are parsed through a wrapper rule that enables lists and tables
+                        if item.rule_name != "data" {
+                            item = item.children.remove(0);
+                        }
+                        let end = item.end;
+                        driver.add_item(item);
+                        end
+                    })
+            }
+        };
+
+        pos = match parsed {
+            Ok(end) => skip_trivia(input, end),
+            Err(error) => {
+                driver.errors.push(*error);
+                skip_trivia(input, next_keyword(input, pos, &BOUNDARY_KEYWORDS))
+            }
+        };
+    }
+    driver.close_block();
+
+    (driver.blocks, driver.errors)
+}
+
+fn start_at(text: &str) -> Start {
+    let token = text.split(char::is_whitespace).next().unwrap_or("");
+    if starts_with_keyword(token, "data_") {
+        Start::DataHeading
+    } else if starts_with_keyword(token, "global_") {
+        Start::Global
+    } else if starts_with_keyword(token, "save_") {
+        if token.len() > "save_".len() {
+            Start::SaveHeading
+        } else {
+            Start::SaveEnd
+        }
+    } else {
+        Start::Item
+    }
+}
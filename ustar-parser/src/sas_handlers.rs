@@ -0,0 +1,1662 @@
+//! Ready made SAS content handlers.
+//!
+//! `DocumentBuilderHandler` collects everything a `StarWalker` reports into a `Document`: data
+//! and global blocks holding their items, loops and save frames, with the values unquoted. It
+//! replaces the handler most applications would otherwise write to gather data into maps.
+//!
+//! `StarWriterHandler` writes the events back out as STAR text, keeping each value's original
+//! delimiter, so walking its output gives the same events apart from positions and comments.
+//! It can also keep the comments and align loop columns, laying a file out afresh.
+//!
+//! `JsonHandler`, with the `serde` feature, writes the events as a JSON object of blocks, save
+//! frames, items and loops for tools that read JSON rather than STAR.
+//!
+//! `CsvLoopHandler` writes the rows of the loops whose tags match a prefix as CSV or TSV, for
+//! pulling a table such as `_atom_site` into a spreadsheet.
+//!
+//! `StatsHandler` counts the blocks, save frames, loops, values and comments of a file, with
+//! how its values are delimited, for a summary of a file too large to read through.
+
+use crate::line_column_index::LineColumn;
+use crate::sas_interface::{CommentKind, SASContentHandler, ValueDelimiter, WalkControl};
+use crate::values::collides_with_keyword;
+use std::io::{self, Write};
+
+/// A STAR file as collected by `DocumentBuilderHandler`
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Document {
+    /// The global blocks in file order, their names are empty
+    pub global_blocks: Vec<DataBlock>,
+    /// The data blocks in file order
+    pub data_blocks: Vec<DataBlock>,
+}
+
+/// A data or global block
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataBlock {
+    /// The block code without `data_`
+    pub name: String,
+    /// Items outside loops and save frames
+    pub items: Vec<Item>,
+    /// Loops outside save frames
+    pub loops: Vec<Loop>,
+    pub saveframes: Vec<Saveframe>,
+}
+
+/// A save frame
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Saveframe {
+    /// The frame code without `save_`
+    pub name: String,
+    pub items: Vec<Item>,
+    pub loops: Vec<Loop>,
+}
+
+/// A data item outside a loop
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Item {
+    pub tag: String,
+    /// The value without quotes or text field delimiters
+    pub value: String,
+}
+
+/// A loop, possibly with nested levels
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Loop {
+    /// The tags of each level, outermost first
+    pub tags: Vec<Vec<String>>,
+    /// The rows of the outermost level, empty for an empty loop
+    pub rows: Vec<LoopRow>,
+}
+
+/// A row of one loop level
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopRow {
+    /// The values for the tags of the row's level, shorter than the tags for a partial last row
+    pub values: Vec<String>,
+    /// The rows of the next level nested in this row, empty for the innermost level
+    pub rows: Vec<LoopRow>,
+}
+
+impl Document {
+    /// The first data block named `name`, compared case-insensitively
+    pub fn data_block(&self, name: &str) -> Option<&DataBlock> {
+        self.data_blocks
+            .iter()
+            .find(|block| block.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl DataBlock {
+    /// The first save frame named `name`, compared case-insensitively
+    pub fn saveframe(&self, name: &str) -> Option<&Saveframe> {
+        self.saveframes
+            .iter()
+            .find(|frame| frame.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The value of the first item outside loops and save frames with `tag`
+    pub fn item(&self, tag: &str) -> Option<&str> {
+        find_item(&self.items, tag)
+    }
+}
+
+impl Saveframe {
+    /// The value of the first item outside loops with `tag`
+    pub fn item(&self, tag: &str) -> Option<&str> {
+        find_item(&self.items, tag)
+    }
+}
+
+fn find_item<'a>(items: &'a [Item], tag: &str) -> Option<&'a str> {
+    items
+        .iter()
+        .find(|item| item.tag.eq_ignore_ascii_case(tag))
+        .map(|item| item.value.as_str())
+}
+
+/// Builds a `Document` from the callbacks of a walk, retrieve it with `into_document`
+#[derive(Debug, Default)]
+pub struct DocumentBuilderHandler {
+    document: Document,
+    block: Option<DataBlock>,
+    saveframe: Option<Saveframe>,
+    current_loop: Option<Loop>,
+    rows: Vec<LoopRow>, // Rows started and not yet ended, outermost first
+}
+
+impl DocumentBuilderHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The document built by the walk
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+
+    /// The items and loops of the open save frame, or of the open block outside save frames
+    fn container(&mut self) -> Option<(&mut Vec<Item>, &mut Vec<Loop>)> {
+        match (&mut self.saveframe, &mut self.block) {
+            (Some(frame), _) => Some((&mut frame.items, &mut frame.loops)),
+            (None, Some(block)) => Some((&mut block.items, &mut block.loops)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl SASContentHandler for DocumentBuilderHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.block = Some(DataBlock::default());
+        WalkControl::Continue
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        if let Some(block) = self.block.take() {
+            self.document.global_blocks.push(block);
+        }
+        WalkControl::Continue
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.block = Some(DataBlock {
+            name: name.to_string(),
+            ..DataBlock::default()
+        });
+        WalkControl::Continue
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        if let Some(block) = self.block.take() {
+            self.document.data_blocks.push(block);
+        }
+        WalkControl::Continue
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.saveframe = Some(Saveframe {
+            name: name.to_string(),
+            ..Saveframe::default()
+        });
+        WalkControl::Continue
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        if let (Some(frame), Some(block)) = (self.saveframe.take(), &mut self.block) {
+            block.saveframes.push(frame);
+        }
+        WalkControl::Continue
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.current_loop = Some(Loop::default());
+        WalkControl::Continue
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        if let Some(current_loop) = self.current_loop.take() {
+            if let Some((_, loops)) = self.container() {
+                loops.push(current_loop);
+            }
+        }
+        WalkControl::Continue
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        _nesting_level: usize,
+    ) -> WalkControl {
+        if let Some(current_loop) = &mut self.current_loop {
+            current_loop
+                .tags
+                .push(tags.iter().map(|tag| tag.to_string()).collect());
+        }
+        WalkControl::Continue
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        self.rows.push(LoopRow::default());
+        WalkControl::Continue
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        // a finished row belongs to the row it is nested in, or to the loop at the outermost level
+        if let Some(row) = self.rows.pop() {
+            match (self.rows.last_mut(), &mut self.current_loop) {
+                (Some(parent), _) => parent.rows.push(row),
+                (None, Some(current_loop)) => current_loop.rows.push(row),
+                (None, None) => {}
+            }
+        }
+        WalkControl::Continue
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        if loop_level > 0 {
+            // the tags of an empty loop are already known from its definition
+            if delimiter != ValueDelimiter::EmptyLoop {
+                if let Some(row) = self.rows.last_mut() {
+                    row.values.push(value.to_string());
+                }
+            }
+        } else if let Some((items, _)) = self.container() {
+            items.push(Item {
+                tag: tag.to_string(),
+                value: value.to_string(),
+            });
+        }
+        WalkControl::Continue
+    }
+}
+
+/// Writes the events of a walk as STAR text, one loop row per line
+///
+/// Comments are not written. Values keep the delimiter they were read with, a text field value
+/// starts a new line and the rest of its loop row follows on the line after it. An unquoted
+/// value that would read back as a keyword or data name, such as `stop_` or `_x` passed by a
+/// handler that built it, is written in single quotes.
+///
+/// `with_comments`, `with_aligned_loops` and `with_blank_lines` lay the text out for reading
+/// rather than just for parsing, as `ustar-dumper --reformat` does; `with_pynmrstar_style` lays
+/// NMR-STAR out as pynmrstar writes it. None of them change the events a walk of the output
+/// gives, other than their positions.
+pub struct StarWriterHandler<W: Write> {
+    writer: W,
+    indent: String,
+    comments: bool,                               // Write comments
+    align: bool,             // Pad loop values so the columns of each loop level line up
+    spacing: bool,           // Blank lines around save frames and loops
+    pynmrstar: bool,         // Lay out blocks, save frames and loops as pynmrstar does
+    depth: usize,            // Indentation of the current block or save frame content
+    started: bool,           // A block has been written, later blocks are preceded by a blank line
+    gap: Gap,                // What was last written in the current block or save frame
+    in_loop: bool,           // Between start_loop and end_loop
+    loop_levels: usize,      // Levels of the current loop
+    open_rows: usize,        // Rows started and not yet ended
+    columns: Vec<usize>,     // Values seen in the open row of each loop level
+    tag_counts: Vec<usize>,  // Tags of each level of the current loop
+    open_definitions: usize, // Nested loop definitions written and not yet closed by a stop_
+    stop_owed: bool,         // A short row ended, the next row must follow a stop_
+    widths: Vec<Vec<usize>>, // Widths of the columns of each level of the current loop
+    line_open: bool,         // The current row line has values and no newline yet
+    newline_owed: bool,      // The last line written is held open for an inline comment
+    pad: usize,              // Spaces owed after the last value on the row line to reach its width
+    pending: Vec<String>,    // Comments waiting for the element they were attached to
+    held: Option<Vec<LoopEvent>>, // Rows of the current loop, held back to measure its columns
+    items: Vec<(String, String, ValueDelimiter)>, // Items held back to align their values
+    error: Option<io::Error>,
+}
+
+/// What a `StarWriterHandler` last wrote in a block, which decides where blank lines go (private)
+#[derive(Clone, Copy, PartialEq)]
+enum Gap {
+    Heading, // A block or save frame heading, or nothing yet
+    Item,
+    Section, // The end of a loop or save frame
+}
+
+/// A loop row event as a `StarWriterHandler` writes it (private)
+enum LoopEvent {
+    StartRow,
+    EndRow {
+        open_rows: usize,
+        short: bool,
+    },
+    Value {
+        value: String,
+        delimiter: ValueDelimiter,
+        level: usize,
+        column: usize,
+    },
+    Comment {
+        text: String,
+        open_rows: usize,
+        inline: bool,
+    },
+}
+
+impl<W: Write> StarWriterHandler<W> {
+    /// Write to `writer`, indenting each level by four spaces
+    pub fn new(writer: W) -> Self {
+        StarWriterHandler {
+            writer,
+            indent: "    ".to_string(),
+            comments: false,
+            align: false,
+            spacing: false,
+            pynmrstar: false,
+            depth: 0,
+            started: false,
+            gap: Gap::Heading,
+            in_loop: false,
+            loop_levels: 0,
+            open_rows: 0,
+            columns: Vec::new(),
+            tag_counts: Vec::new(),
+            open_definitions: 0,
+            stop_owed: false,
+            widths: Vec::new(),
+            line_open: false,
+            newline_owed: false,
+            pad: 0,
+            pending: Vec::new(),
+            held: None,
+            items: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Use `indent` for each level of indentation instead of four spaces
+    pub fn with_indent(mut self, indent: &str) -> Self {
+        self.indent = indent.to_string();
+        self
+    }
+
+    /// Write comments, each on a line of its own unless it followed other text on its line; walk
+    /// with `ConfigKey::AttachComments` set to
+    /// write the comments before an element just before it, after any blank line
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
+    /// Pad the values of each loop level so its columns line up, holding back the rows of each
+    /// loop until its end to measure them
+    pub fn with_aligned_loops(mut self) -> Self {
+        self.align = true;
+        self
+    }
+
+    /// Separate save frames and loops from the items and other sections around them with a
+    /// blank line
+    pub fn with_blank_lines(mut self) -> Self {
+        self.spacing = true;
+        self
+    }
+
+    /// Lay the text out as pynmrstar 3 writes NMR-STAR, as in the files BMRB distributes
+    ///
+    /// Block headings and save frames are followed by a blank line. The items of a save frame
+    /// are indented by three spaces with their values aligned three spaces after the longest
+    /// tag. Loops are preceded by a blank line, their
+    /// tags are indented by six spaces and followed by a blank line, and their rows are indented
+    /// by six spaces with each column padded to three spaces more than its widest value. Text
+    /// fields start on a line of their own, and the values after one in a loop row continue at
+    /// the column they would have started at. Comments aren't written, as pynmrstar doesn't keep
+    /// them. Loops nested in loops, which pynmrstar doesn't read, are written as `new` does.
+    pub fn with_pynmrstar_style(mut self) -> Self {
+        self.indent = "   ".to_string();
+        self.pynmrstar = true;
+        self.align = true;
+        self.comments = false;
+        self.spacing = false;
+        self
+    }
+
+    /// Flush and return the writer, or the first error met while writing
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if std::mem::take(&mut self.newline_owed) {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Write the items held back for pynmrstar style, aligning their values
+    fn write_items(&mut self) -> WalkControl {
+        let items = std::mem::take(&mut self.items);
+        let width = items
+            .iter()
+            .map(|(tag, _, _)| tag.chars().count() + 3)
+            .max()
+            .unwrap_or(0);
+        for (tag, value, delimiter) in items {
+            let line = if delimiter == ValueDelimiter::Semicolon {
+                format!("{}\n{}\n", tag, value)
+            } else {
+                format!("{:<width$}{}\n", tag, value)
+            };
+            if self.write(self.depth, &line) == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+        }
+        WalkControl::Continue
+    }
+
+    /// Write `text` at indentation `level`, stopping the walk once a write has failed
+    ///
+    /// A newline ending `text` is held back until the next write, so an inline comment can
+    /// still follow the text on its line.
+    fn write(&mut self, level: usize, text: &str) -> WalkControl {
+        if self.error.is_none() {
+            let owed = std::mem::take(&mut self.newline_owed);
+            let text = match text.strip_suffix('\n') {
+                Some(line) => {
+                    self.newline_owed = true;
+                    line
+                }
+                None => text,
+            };
+            let result = (if owed {
+                self.writer.write_all(b"\n")
+            } else {
+                Ok(())
+            })
+            .and_then(|_| {
+                (0..level).try_for_each(|_| self.writer.write_all(self.indent.as_bytes()))
+            })
+            .and_then(|_| self.writer.write_all(text.as_bytes()));
+            self.error = result.err();
+        }
+        if self.error.is_some() {
+            WalkControl::Stop
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    /// Write a block heading, separated from any earlier block by a blank line
+    fn heading(&mut self, heading: &str) -> WalkControl {
+        // in pynmrstar style the blank line comes after the heading
+        let (separator, after) = match (self.started, self.pynmrstar) {
+            (_, true) => ("", "\n"),
+            (true, false) => ("\n", ""),
+            (false, false) => ("", ""),
+        };
+        self.started = true;
+        self.depth = 0;
+        self.gap = Gap::Heading;
+        if self.write(0, separator) == WalkControl::Stop
+            || self.write_pending() == WalkControl::Stop
+        {
+            return WalkControl::Stop;
+        }
+        self.write(0, &format!("{}\n{}", heading, after))
+    }
+
+    /// Start an item, or a save frame or loop if `section`, with the blank line due before it
+    /// and the comments attached to it
+    fn open_element(&mut self, section: bool) -> WalkControl {
+        let blank = self.spacing
+            && match self.gap {
+                Gap::Heading => false,
+                Gap::Item => section,
+                Gap::Section => true,
+            };
+        self.gap = Gap::Item;
+        if blank && self.write(0, "\n") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.write_pending()
+    }
+
+    /// Write the comments waiting for the next element
+    fn write_pending(&mut self) -> WalkControl {
+        for text in std::mem::take(&mut self.pending) {
+            if self.write(self.depth, &format!("{}\n", text)) == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+        }
+        WalkControl::Continue
+    }
+
+    /// Write `text` at the end of the last line written, if it hasn't been ended yet, and end
+    /// the line
+    fn append_to_line(&mut self, text: &str) -> Option<WalkControl> {
+        if !(self.line_open || self.newline_owed) {
+            return None;
+        }
+        self.line_open = false;
+        self.newline_owed = false;
+        self.pad = 0;
+        Some(self.write(0, &format!(" {}\n", text)))
+    }
+
+    /// End the current row line, if there is one
+    fn end_line(&mut self) -> WalkControl {
+        self.pad = 0;
+        if std::mem::take(&mut self.line_open) {
+            self.write(0, "\n")
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    /// Close the nested loop definitions, innermost first, once all the levels are defined
+    fn close_definitions(&mut self) -> WalkControl {
+        while self.open_definitions > 0 {
+            let level = self.depth + self.open_definitions;
+            self.open_definitions -= 1;
+            if self.write(level, "stop_\n") == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+        }
+        WalkControl::Continue
+    }
+
+    /// Write a loop row event now, or hold it back until the end of the loop when aligning
+    fn loop_event(&mut self, event: LoopEvent) -> WalkControl {
+        if self.close_definitions() == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        match &mut self.held {
+            Some(held) => {
+                held.push(event);
+                WalkControl::Continue
+            }
+            None => self.write_loop_event(event),
+        }
+    }
+
+    fn write_loop_event(&mut self, event: LoopEvent) -> WalkControl {
+        match event {
+            LoopEvent::StartRow => {
+                if self.end_line() == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+                // a row after a short one only starts where it did after a stop_
+                if std::mem::take(&mut self.stop_owed) {
+                    self.write(self.depth + 1, "stop_\n")
+                } else {
+                    WalkControl::Continue
+                }
+            }
+            LoopEvent::EndRow { open_rows, short } => {
+                if self.end_line() == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+                self.stop_owed = short;
+                // the rows nested in a row of an outer level are ended by a stop_
+                if open_rows + 1 < self.loop_levels {
+                    self.write(self.depth + open_rows + 2, "stop_\n")
+                } else {
+                    WalkControl::Continue
+                }
+            }
+            LoopEvent::Value {
+                value,
+                delimiter,
+                level,
+                column,
+            } => self.write_loop_value(&value, delimiter, level, column),
+            LoopEvent::Comment {
+                text,
+                open_rows,
+                inline,
+            } => {
+                if let Some(control) = inline.then(|| self.append_to_line(&text)).flatten() {
+                    return control;
+                }
+                if self.end_line() == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+                self.write(self.depth + open_rows.max(1), &format!("{}\n", text))
+            }
+        }
+    }
+
+    fn write_loop_value(
+        &mut self,
+        value: &str,
+        delimiter: ValueDelimiter,
+        level: usize,
+        column: usize,
+    ) -> WalkControl {
+        let value = delimited(value, delimiter);
+        if delimiter == ValueDelimiter::EmptyLoop {
+            return WalkControl::Continue;
+        }
+        if self.pynmrstar {
+            return self.write_pynmrstar_value(&value, delimiter, level, column);
+        }
+        if delimiter == ValueDelimiter::Semicolon {
+            if self.end_line() == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+            return self.write(0, &format!("{}\n", value));
+        }
+
+        let control = if std::mem::replace(&mut self.line_open, true) {
+            self.write(0, &format!("{}{}", " ".repeat(self.pad + 1), value))
+        } else {
+            self.write(self.depth + level, &value)
+        };
+        let width = self
+            .widths
+            .get(level)
+            .and_then(|widths| widths.get(column))
+            .copied()
+            .unwrap_or(0);
+        self.pad = width.saturating_sub(value.chars().count());
+        control
+    }
+
+    /// Write a loop value in pynmrstar style, where each column is three spaces wider than its
+    /// widest value and at least four wide
+    fn write_pynmrstar_value(
+        &mut self,
+        value: &str,
+        delimiter: ValueDelimiter,
+        level: usize,
+        column: usize,
+    ) -> WalkControl {
+        let widths = self.widths.get(level).cloned().unwrap_or_default();
+        let width = |column: usize| (widths.get(column).copied().unwrap_or(0) + 3).max(4);
+        // rows start six spaces in unless they start with a text field, the padding owed is
+        // only zero at the start of a row
+        let lead = if self.line_open || self.pad > 0 {
+            self.pad
+        } else if delimiter == ValueDelimiter::Semicolon {
+            0
+        } else {
+            6
+        };
+        let control = self.write(0, &" ".repeat(lead));
+        if control == WalkControl::Stop {
+            return control;
+        }
+
+        if delimiter == ValueDelimiter::Semicolon {
+            // the rest of the row continues below the text field where its next column starts
+            self.line_open = false;
+            self.pad = 6 + (0..=column).map(width).sum::<usize>();
+            return self.write(0, &format!("\n{}\n", value));
+        }
+        self.line_open = true;
+        self.pad = width(column).saturating_sub(value.chars().count());
+        self.write(0, value)
+    }
+
+    /// Find the width of each column of each level of the held back rows
+    fn measure(&mut self, held: &[LoopEvent]) {
+        self.widths.clear();
+        for event in held {
+            if let LoopEvent::Value {
+                value,
+                delimiter,
+                level,
+                column,
+            } = event
+            {
+                if matches!(
+                    delimiter,
+                    ValueDelimiter::Semicolon | ValueDelimiter::EmptyLoop
+                ) {
+                    continue;
+                }
+                if self.widths.len() <= *level {
+                    self.widths.resize(level + 1, Vec::new());
+                }
+                let widths = &mut self.widths[*level];
+                if widths.len() <= *column {
+                    widths.resize(column + 1, 0);
+                }
+                let width = delimited(value, *delimiter).chars().count();
+                widths[*column] = widths[*column].max(width);
+            }
+        }
+    }
+}
+
+/// A value as written with its delimiter, text fields are written from the start of a line
+fn delimited(value: &str, delimiter: ValueDelimiter) -> String {
+    match delimiter {
+        ValueDelimiter::None if collides_with_keyword(value) => format!("'{}'", value),
+        ValueDelimiter::None | ValueDelimiter::EmptyLoop => value.to_string(),
+        ValueDelimiter::List => format!("[{}]", value),
+        ValueDelimiter::Table => format!("{{{}}}", value),
+        ValueDelimiter::Semicolon => format!(";{}\n;", value),
+        _ => format!("{}{}{}", delimiter, value, delimiter),
+    }
+}
+
+impl<W: Write> SASContentHandler for StarWriterHandler<W> {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        // writes the newline held back from the last line
+        if self.write(0, "") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        match self.writer.flush() {
+            Ok(()) => WalkControl::Continue,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                WalkControl::Stop
+            }
+        }
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.heading("global_")
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.write_items()
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.heading(&format!("data_{}", name))
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.write_items()
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        if self.write_items() == WalkControl::Stop || self.open_element(true) == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        let control = self.write(self.depth, &format!("save_{}\n", name));
+        self.depth += 1;
+        self.gap = Gap::Heading;
+        control
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        if self.write_items() == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.depth = self.depth.saturating_sub(1);
+        self.gap = Gap::Section;
+        let end = if self.pynmrstar {
+            "save_\n\n"
+        } else {
+            "save_\n"
+        };
+        self.write(self.depth, end)
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        if self.write_items() == WalkControl::Stop || self.open_element(true) == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        if self.pynmrstar && self.write(0, "\n") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.loop_levels = 0;
+        self.tag_counts.clear();
+        self.in_loop = true;
+        self.widths.clear();
+        if self.align {
+            self.held = Some(Vec::new());
+        }
+        self.write(self.depth, "loop_\n")
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.in_loop = false;
+        if self.close_definitions() == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        if let Some(held) = self.held.take() {
+            self.measure(&held);
+            for event in held {
+                if self.write_loop_event(event) == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+            }
+        }
+        // the stop_ ending the loop also ends a short last row
+        self.stop_owed = false;
+        if self.end_line() == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.gap = Gap::Section;
+        self.write(self.depth, "stop_\n")
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        // nested levels open with their own loop_ inside the definition of the level above, so a
+        // level of one tag is followed by a field, and are closed by stop_ after the innermost
+        let level = self.depth + nesting_level;
+        if nesting_level > 1 && self.write(level - 1, "loop_\n") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        for tag in tags {
+            if self.write(level, &format!("{}\n", tag)) == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+        }
+        self.loop_levels = nesting_level;
+        self.tag_counts.push(tags.len());
+        if nesting_level > 1 {
+            self.open_definitions += 1;
+            WalkControl::Continue
+        } else if self.pynmrstar {
+            self.write(0, "\n")
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        self.open_rows += 1;
+        if self.columns.len() <= self.open_rows {
+            self.columns.resize(self.open_rows + 1, 0);
+        }
+        self.columns[self.open_rows] = 0;
+        self.loop_event(LoopEvent::StartRow)
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        // a stop_ ends the short rows of a loop of one level, in nested loops it also ends the
+        // outer row and is written for that
+        let short = self.loop_levels == 1
+            && self.columns.get(self.open_rows).copied().unwrap_or(0)
+                < self.tag_counts.first().copied().unwrap_or(0);
+        self.open_rows = self.open_rows.saturating_sub(1);
+        let open_rows = self.open_rows;
+        self.loop_event(LoopEvent::EndRow { open_rows, short })
+    }
+
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        let text = text.strip_prefix('#').unwrap_or(text);
+        self.comment_with_kind(position, text, CommentKind::FullLine)
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        _position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        if !self.comments {
+            return WalkControl::Continue;
+        }
+        let text = format!("#{}", text);
+        let inline = kind == CommentKind::Inline;
+        if self.in_loop {
+            let open_rows = self.open_rows;
+            return self.loop_event(LoopEvent::Comment {
+                text,
+                open_rows,
+                inline,
+            });
+        }
+        if let Some(control) = inline.then(|| self.append_to_line(&text)).flatten() {
+            return control;
+        }
+        if self.open_element(false) == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.write(self.depth, &format!("{}\n", text))
+    }
+
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        if !self.comments {
+            return WalkControl::Continue;
+        }
+        if self.in_loop {
+            for &(position, text) in comments {
+                if self.comment(position, text) == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+            }
+        } else {
+            // written by the start or data callback of the element, after any blank line
+            self.pending
+                .extend(comments.iter().map(|(_, text)| text.to_string()));
+        }
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        if loop_level == 0 {
+            if self.pynmrstar {
+                let value = delimited(value, delimiter);
+                self.items.push((tag.to_string(), value, delimiter));
+                return WalkControl::Continue;
+            }
+            if self.open_element(false) == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+            let value = delimited(value, delimiter);
+            if delimiter == ValueDelimiter::Semicolon {
+                self.write(self.depth, &format!("{}\n{}\n", tag, value))
+            } else {
+                self.write(self.depth, &format!("{}  {}\n", tag, value))
+            }
+        } else {
+            if self.columns.len() <= loop_level {
+                self.columns.resize(loop_level + 1, 0);
+            }
+            let column = self.columns[loop_level];
+            self.columns[loop_level] += 1;
+            self.loop_event(LoopEvent::Value {
+                value: value.to_string(),
+                delimiter,
+                level: loop_level,
+                column,
+            })
+        }
+    }
+}
+
+/// Writes the events of a walk as a JSON object, streaming it to a writer as the walk goes
+///
+/// The document object has a key for each block, `global_` or `data_<name>`. A block or save
+/// frame object holds its items as `"tag": value`, its save frames as `"save_<name>": {...}` and
+/// its loops as `"loop_1": [...]`, `"loop_2": [...]` and so on in file order. A loop is an array
+/// of row objects keyed by tag, and a row of a nested loop holds its inner rows under `"loop_"`.
+///
+/// All values are JSON strings apart from the unquoted values `.`, which is written as `null`,
+/// and `?`, which is written as the unknown sentinel, `"?"` unless set with `with_unknown`.
+/// Comments are not written. The JSON is compact unless an indent is set with `with_indent`;
+/// `json_reader::walk_json` reads it back as the events of a walk.
+#[cfg(feature = "serde")]
+pub struct JsonHandler<W: Write> {
+    writer: W,
+    unknown: serde_json::Value,
+    indent: Option<String>,
+    containers: Vec<bool>, // Open objects and arrays, true once they have an entry
+    loop_counts: Vec<usize>, // Loops seen in each open block or save frame
+    open_rows: Vec<bool>,  // Open loop rows, true once their nested rows are opened
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "serde")]
+impl JsonHandler<Vec<u8>> {
+    /// Collect the JSON in memory, to be read with `into_value`
+    pub fn buffered() -> Self {
+        JsonHandler::new(Vec::new())
+    }
+
+    /// The JSON of the walk as a `serde_json::Value`
+    pub fn into_value(self) -> serde_json::Result<serde_json::Value> {
+        let buffer = self.into_inner().map_err(serde_json::Error::io)?;
+        serde_json::from_slice(&buffer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<W: Write> JsonHandler<W> {
+    /// Stream the JSON to `writer`
+    pub fn new(writer: W) -> Self {
+        JsonHandler {
+            writer,
+            unknown: serde_json::Value::String("?".to_string()),
+            indent: None,
+            containers: Vec::new(),
+            loop_counts: Vec::new(),
+            open_rows: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Write the unquoted value `?` as `unknown` instead of `"?"`
+    pub fn with_unknown(mut self, unknown: serde_json::Value) -> Self {
+        self.unknown = unknown;
+        self
+    }
+
+    /// Write one entry per line, indented by `indent` for each level, as
+    /// `serde_json::to_string_pretty` does with two spaces
+    pub fn with_indent(mut self, indent: &str) -> Self {
+        self.indent = Some(indent.to_string());
+        self
+    }
+
+    /// Flush and return the writer, or the first error met while writing
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// A line break and the indentation of the innermost open container, empty for compact JSON
+    fn line_break(&self) -> String {
+        match &self.indent {
+            Some(indent) => format!("\n{}", indent.repeat(self.containers.len())),
+            None => String::new(),
+        }
+    }
+
+    /// Write `text`, stopping the walk once a write has failed
+    fn write(&mut self, text: &str) -> WalkControl {
+        if self.error.is_none() {
+            self.error = self.writer.write_all(text.as_bytes()).err();
+        }
+        if self.error.is_some() {
+            WalkControl::Stop
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    /// Start an entry of the innermost container, with its key if the container is an object
+    fn entry(&mut self, key: Option<&str>) -> WalkControl {
+        let has_entries = self
+            .containers
+            .last_mut()
+            .is_some_and(|has_entries| std::mem::replace(has_entries, true));
+        let separator = if has_entries { "," } else { "" };
+        // the document object is the only entry outside a container
+        let line_break = if self.containers.is_empty() {
+            String::new()
+        } else {
+            self.line_break()
+        };
+        let colon = if self.indent.is_some() { ": " } else { ":" };
+        let key = match key {
+            Some(key) => format!("{}{}", serde_json::Value::from(key), colon),
+            None => String::new(),
+        };
+        self.write(&format!("{}{}{}", separator, line_break, key))
+    }
+
+    /// Open an object or array as a new entry of the innermost container
+    fn open(&mut self, key: Option<&str>, bracket: &str) -> WalkControl {
+        let control = self.entry(key);
+        self.containers.push(false);
+        if control == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.write(bracket)
+    }
+
+    /// Close the innermost container, on a line of its own if it has entries
+    fn close(&mut self, bracket: &str) -> WalkControl {
+        let line_break = match self.containers.pop() {
+            Some(true) => self.line_break(),
+            _ => String::new(),
+        };
+        self.write(&format!("{}{}", line_break, bracket))
+    }
+
+    fn open_block(&mut self, key: &str) -> WalkControl {
+        self.loop_counts.push(0);
+        self.open(Some(key), "{")
+    }
+
+    fn close_block(&mut self) -> WalkControl {
+        self.loop_counts.pop();
+        self.close("}")
+    }
+
+    fn value(&self, value: &str, delimiter: ValueDelimiter) -> serde_json::Value {
+        match (value, delimiter.is_quoted()) {
+            (".", false) => serde_json::Value::Null,
+            ("?", false) => self.unknown.clone(),
+            _ => serde_json::Value::from(value),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<W: Write> SASContentHandler for JsonHandler<W> {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.open(None, "{")
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        if self.close("}\n") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        match self.writer.flush() {
+            Ok(()) => WalkControl::Continue,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                WalkControl::Stop
+            }
+        }
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.open_block("global_")
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.close_block()
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.open_block(&format!("data_{}", name))
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.close_block()
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.open_block(&format!("save_{}", name))
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.close_block()
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        let count = match self.loop_counts.last_mut() {
+            Some(count) => {
+                *count += 1;
+                *count
+            }
+            None => 1,
+        };
+        self.open(Some(&format!("loop_{}", count)), "[")
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.close("]")
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        // the first nested row of a row opens the array of its nested rows
+        if let Some(nested_open) = self.open_rows.last_mut() {
+            if !std::mem::replace(nested_open, true)
+                && self.open(Some("loop_"), "[") == WalkControl::Stop
+            {
+                return WalkControl::Stop;
+            }
+        }
+        self.open_rows.push(false);
+        self.open(None, "{")
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        if self.open_rows.pop() == Some(true) && self.close("]") == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.close("}")
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        if delimiter == ValueDelimiter::EmptyLoop {
+            return WalkControl::Continue;
+        }
+        let value = self.value(value, delimiter);
+        if self.entry(Some(tag)) == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.write(&value.to_string())
+    }
+}
+
+/// Writes the rows of selected loops as CSV, or with `with_separator` as TSV
+///
+/// A loop is written if one of its outer tags starts with one of the prefixes, compared without
+/// case. Each written loop starts with a header row of its tags and loops after the first are
+/// preceded by a blank line. The rows of a nested loop are flattened, each inner row is written
+/// with the values of its outer rows repeated before its own, and an outer row without inner rows
+/// is written with empty inner cells.
+///
+/// Cells holding the separator, a double quote or a line break are quoted, doubling any quotes.
+/// The unquoted values `.` and `?` are written as empty cells.
+pub struct CsvLoopHandler<W: Write> {
+    writer: W,
+    prefixes: Vec<String>,
+    separator: char,
+    capturing: bool,     // The current loop matches a prefix
+    header: Vec<String>, // Tags of all levels of the current loop
+    header_written: bool,
+    rows: Vec<(Vec<String>, bool)>, // Values of open rows, true once a row has written inner rows
+    loops_written: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> CsvLoopHandler<W> {
+    /// Write the loops with a tag starting with one of `prefixes` to `writer` as CSV
+    pub fn new(writer: W, prefixes: &[&str]) -> Self {
+        CsvLoopHandler {
+            writer,
+            prefixes: prefixes
+                .iter()
+                .map(|prefix| prefix.to_lowercase())
+                .collect(),
+            separator: ',',
+            capturing: false,
+            header: Vec::new(),
+            header_written: false,
+            rows: Vec::new(),
+            loops_written: 0,
+            error: None,
+        }
+    }
+
+    /// Separate cells with `separator` instead of a comma, `'\t'` for TSV
+    pub fn with_separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// The number of loops written so far
+    pub fn loops_written(&self) -> usize {
+        self.loops_written
+    }
+
+    /// Flush and return the writer, or the first error met while writing
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Write one line of cells, stopping the walk once a write has failed
+    fn write_line<'a>(&mut self, cells: impl Iterator<Item = &'a str>) -> WalkControl {
+        if self.error.is_none() {
+            let separator = self.separator.to_string();
+            let line = cells
+                .map(|cell| self.quoted(cell))
+                .collect::<Vec<_>>()
+                .join(&separator);
+            self.error = writeln!(self.writer, "{}", line).err();
+        }
+        if self.error.is_some() {
+            WalkControl::Stop
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn quoted(&self, cell: &str) -> String {
+        if cell.contains([self.separator, '"', '\n', '\r']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.to_string()
+        }
+    }
+
+    /// Write the header of the current loop once its tags are all known
+    fn write_header(&mut self) -> WalkControl {
+        if std::mem::replace(&mut self.header_written, true) {
+            return WalkControl::Continue;
+        }
+        if self.loops_written > 0 && self.write_line(std::iter::empty()) == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.loops_written += 1;
+        let header = std::mem::take(&mut self.header);
+        let control = self.write_line(header.iter().map(String::as_str));
+        self.header = header;
+        control
+    }
+
+    /// Write the values of the open rows, padded with empty cells for the inner levels
+    fn write_row(&mut self) -> WalkControl {
+        let values: Vec<String> = self
+            .rows
+            .iter()
+            .flat_map(|(values, _)| values.iter().cloned())
+            .collect();
+        let padding = self.header.len().saturating_sub(values.len());
+        let cells = values
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::repeat_n("", padding));
+        self.write_line(cells)
+    }
+}
+
+impl<W: Write> SASContentHandler for CsvLoopHandler<W> {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        match self.writer.flush() {
+            Ok(()) => WalkControl::Continue,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                WalkControl::Stop
+            }
+        }
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.capturing = false;
+        self.header.clear();
+        self.header_written = false;
+        self.rows.clear();
+        WalkControl::Continue
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        // a loop without rows is written as its header
+        if std::mem::take(&mut self.capturing) {
+            self.write_header()
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        if nesting_level == 1 {
+            self.capturing = tags.iter().any(|tag| {
+                let tag = tag.to_lowercase();
+                self.prefixes.iter().any(|prefix| tag.starts_with(prefix))
+            });
+        }
+        if !self.capturing {
+            return WalkControl::SkipSubtree;
+        }
+        self.header.extend(tags.iter().map(|tag| tag.to_string()));
+        WalkControl::Continue
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        if !self.capturing {
+            return WalkControl::Continue;
+        }
+        self.rows.push((Vec::new(), false));
+        self.write_header()
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        let Some((_, wrote_inner_rows)) = self.rows.last() else {
+            return WalkControl::Continue;
+        };
+        let control = if *wrote_inner_rows {
+            WalkControl::Continue
+        } else {
+            self.write_row()
+        };
+        self.rows.pop();
+        if let Some((_, wrote_inner_rows)) = self.rows.last_mut() {
+            *wrote_inner_rows = true;
+        }
+        control
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        if !self.capturing || loop_level == 0 || delimiter == ValueDelimiter::EmptyLoop {
+            return WalkControl::Continue;
+        }
+        let value = match (value, delimiter.is_quoted()) {
+            ("." | "?", false) => "",
+            _ => value,
+        };
+        if let Some((values, _)) = self.rows.last_mut() {
+            values.push(value.to_string());
+        }
+        WalkControl::Continue
+    }
+}
+
+/// Counts of the contents of a STAR file as collected by `StatsHandler`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarStats {
+    pub data_blocks: usize,
+    pub global_blocks: usize,
+    pub saveframes: usize,
+    pub loops: usize,
+    /// Loop levels inside another loop, each level counted once per loop
+    pub nested_loops: usize,
+    /// Values outside loops
+    pub items: usize,
+    /// Values in loop rows
+    pub loop_values: usize,
+    pub comments: usize,
+    /// The number of values with each delimiter, for every delimiter but `EmptyLoop`
+    pub delimiters: Vec<(ValueDelimiter, usize)>,
+    /// The deepest nesting of blocks, save frames and loop levels, a loop in a data block is 2
+    pub max_depth: usize,
+}
+
+impl Default for StarStats {
+    fn default() -> Self {
+        StarStats {
+            data_blocks: 0,
+            global_blocks: 0,
+            saveframes: 0,
+            loops: 0,
+            nested_loops: 0,
+            items: 0,
+            loop_values: 0,
+            comments: 0,
+            delimiters: [
+                ValueDelimiter::None,
+                ValueDelimiter::Single,
+                ValueDelimiter::Double,
+                ValueDelimiter::Semicolon,
+                ValueDelimiter::TripleSingle,
+                ValueDelimiter::TripleDouble,
+                ValueDelimiter::List,
+                ValueDelimiter::Table,
+            ]
+            .into_iter()
+            .map(|delimiter| (delimiter, 0))
+            .collect(),
+            max_depth: 0,
+        }
+    }
+}
+
+/// Counts what a walk reports into `StarStats`
+///
+/// Only the events are used, so the counts can be collected with `walk` without building a
+/// parse tree.
+#[derive(Debug, Default)]
+pub struct StatsHandler {
+    stats: StarStats,
+    depth: usize, // Blocks and save frames open around the current event
+}
+
+impl StatsHandler {
+    pub fn new() -> Self {
+        StatsHandler::default()
+    }
+
+    /// The counts collected so far
+    pub fn stats(&self) -> &StarStats {
+        &self.stats
+    }
+
+    pub fn into_stats(self) -> StarStats {
+        self.stats
+    }
+
+    fn open(&mut self) -> WalkControl {
+        self.depth += 1;
+        self.stats.max_depth = self.stats.max_depth.max(self.depth);
+        WalkControl::Continue
+    }
+
+    fn close(&mut self) -> WalkControl {
+        self.depth = self.depth.saturating_sub(1);
+        WalkControl::Continue
+    }
+}
+
+impl SASContentHandler for StatsHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.stats.global_blocks += 1;
+        self.open()
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.close()
+    }
+
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.stats.data_blocks += 1;
+        self.open()
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.close()
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.stats.saveframes += 1;
+        self.open()
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.close()
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.stats.loops += 1;
+        WalkControl::Continue
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        _tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        if nesting_level > 1 {
+            self.stats.nested_loops += 1;
+        }
+        self.stats.max_depth = self.stats.max_depth.max(self.depth + nesting_level);
+        WalkControl::Continue
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        self.stats.comments += 1;
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        if delimiter == ValueDelimiter::EmptyLoop {
+            return WalkControl::Continue;
+        }
+        if loop_level == 0 {
+            self.stats.items += 1;
+        } else {
+            self.stats.loop_values += 1;
+        }
+        if let Some((_, count)) = self
+            .stats
+            .delimiters
+            .iter_mut()
+            .find(|(known, _)| *known == delimiter)
+        {
+            *count += 1;
+        }
+        WalkControl::Continue
+    }
+}
@@ -1,27 +1,190 @@
 use crate::line_column_index::LineColumn;
+use std::fmt;
 
-/// Delimiter used to indicate an empty loop (no data values)
-/// When a loop has tags defined but no data values, each tag is emitted
-/// with this delimiter, an empty value "", and position for the values (-1, -1)
+/// Text of `ValueDelimiter::EmptyLoop`, see `ValueDelimiter::as_str`
 pub const EMPTY_LOOP_DELIMITER: &str = "EMPTY_LOOP";
 
+/// How the value passed to `SASContentHandler::data` was delimited in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValueDelimiter {
+    /// An unquoted value or a frame code
+    None,
+    /// 'single quotes'
+    Single,
+    /// "double quotes"
+    Double,
+    /// A text field between lines starting with `;`
+    Semicolon,
+    /// '''triple single quotes'''
+    TripleSingle,
+    /// """triple double quotes"""
+    TripleDouble,
+    /// A CIF2 list, the value is the text between the outer `[` and `]`
+    List,
+    /// A CIF2 table, the value is the text between the outer `{` and `}`
+    Table,
+    /// A tag of a loop level with no values: the value is empty and its position is 0:0
+    EmptyLoop,
+}
+
+impl ValueDelimiter {
+    /// The opening delimiter as written in the input, "" for `None` and `EMPTY_LOOP_DELIMITER`
+    /// for `EmptyLoop`; these were the delimiter strings `data` received before this enum
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueDelimiter::None => "",
+            ValueDelimiter::Single => "'",
+            ValueDelimiter::Double => "\"",
+            ValueDelimiter::Semicolon => ";",
+            ValueDelimiter::TripleSingle => "'''",
+            ValueDelimiter::TripleDouble => "\"\"\"",
+            ValueDelimiter::List => "[",
+            ValueDelimiter::Table => "{",
+            ValueDelimiter::EmptyLoop => EMPTY_LOOP_DELIMITER,
+        }
+    }
+
+    /// Whether the value was written between delimiters, so `.` and `?` are literal text
+    /// rather than the null values inapplicable and unknown
+    pub fn is_quoted(self) -> bool {
+        !matches!(self, ValueDelimiter::None | ValueDelimiter::EmptyLoop)
+    }
+}
+
+impl fmt::Display for ValueDelimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a comment passed to `SASContentHandler::comment_with_kind` is on its line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CommentKind {
+    /// Nothing but blanks before the `#` on its line
+    FullLine,
+    /// After a value, tag or keyword on the same line, as in `_tag value # note`
+    Inline,
+}
+
+/// A point at which a walk can be resumed, emitted just before each data block and save frame
+/// heading. `path` holds the data block code and, for a save frame, the frame code, as written
+/// in their headings; `line` and `column` give the position of the heading which starts at
+/// `byte_offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamCheckpoint {
+    pub byte_offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub path: Vec<String>,
+}
+
+/// What a `SASContentHandler` wants the walker to do after a callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+    /// Carry on walking
+    Continue,
+    /// From a start callback or loop_definition: don't walk the contents of the stream, block,
+    /// save frame or loop just started but still report its end and carry on with what follows;
+    /// other callbacks treat this as `Continue`
+    SkipSubtree,
+    /// End the walk, no further callbacks are made
+    Stop,
+}
+
 /// SAS-style ContentHandler trait for STAR file parsing
-/// Returns true to stop parsing, false to continue (SAS convention)
+/// Each callback returns a `WalkControl` to continue, skip what was just started or stop
 pub trait SASContentHandler {
     // Stream callbacks
-    fn start_stream(&mut self, name: Option<&str>) -> bool;
-    fn end_stream(&mut self, position: LineColumn) -> bool;
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl;
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl;
 
     // Structure callbacks
-    fn start_global(&mut self, position: LineColumn) -> bool;
-    fn end_global(&mut self, position: LineColumn) -> bool;
-    fn start_data(&mut self, position: LineColumn, name: &str) -> bool;
-    fn end_data(&mut self, position: LineColumn, name: &str) -> bool;
-    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> bool;
-    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> bool;
-    fn start_loop(&mut self, position: LineColumn) -> bool;
-    fn end_loop(&mut self, position: LineColumn) -> bool;
-    fn comment(&mut self, position: LineColumn, text: &str) -> bool;
+    fn start_global(&mut self, position: LineColumn) -> WalkControl;
+    fn end_global(&mut self, position: LineColumn) -> WalkControl;
+    // names are block and frame codes cleaned by sas_walker::heading_name, the codes as written
+    // are the text of the tree's headings after data_ or save_ and the paths of checkpoints
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl;
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl;
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl;
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl;
+    // the position of end_loop is the end of the loop's stop_, or for a loop ended without one
+    // the start of the token that ends it, such as a data name, loop_, save_ or heading, or the
+    // end of the input
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl;
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl;
+
+    // Loop structure callback, called after start_loop and before the loop's first value with
+    // the tags of each loop level, outermost first; nesting_level counts from 1 like loop_level
+    // in data and position is that of the level's loop_ keyword
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        _tags: &[&str],
+        _nesting_level: usize,
+    ) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    // Loop row callbacks, a row is one pass through the tags of a loop level and row_index
+    // counts from 0 within the enclosing row of the next level out; a row of an outer level
+    // holds the rows of the levels nested in it and ends when their stop_ is reached
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    // Comment callbacks, position is that of the # and the line terminator is never part of
+    // the text; comment's text starts with the #, comment_with_kind's is the text after it.
+    // The walker calls comment_with_kind, by default it passes the comment on to comment
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl;
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        _kind: CommentKind,
+    ) -> WalkControl {
+        self.comment(position, &format!("#{}", text))
+    }
+
+    // Attached comments callbacks, with ConfigKey::AttachComments set the full line comments
+    // before a data block, save frame, loop or value are delivered here just before its start
+    // or data callback; inline comments and comments with no element following them in their
+    // scope still go to comment_with_kind. As for comment and comment_with_kind, element_comments'
+    // texts start with the # and element_comments_with_kind's are the text after it. The walker
+    // calls element_comments_with_kind, by default it passes the comments on to element_comments,
+    // which by default passes each on to comment
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        for &(position, text) in comments {
+            if self.comment(position, text) == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+        }
+        WalkControl::Continue
+    }
+    fn element_comments_with_kind(
+        &mut self,
+        comments: &[(LineColumn, &str, CommentKind)],
+    ) -> WalkControl {
+        let texts: Vec<String> = comments
+            .iter()
+            .map(|(_, text, _)| format!("#{}", text))
+            .collect();
+        let comments: Vec<(LineColumn, &str)> = comments
+            .iter()
+            .zip(&texts)
+            .map(|(&(position, _, _), text)| (position, text.as_str()))
+            .collect();
+        self.element_comments(&comments)
+    }
+
+    // Resumption callback, see sas_walker::resume_from
+    fn checkpoint(&mut self, _checkpoint: &StreamCheckpoint) -> WalkControl {
+        WalkControl::Continue
+    }
 
     // Data item callback (buffered)
     fn data(
@@ -30,9 +193,489 @@ pub trait SASContentHandler {
         tag_position: LineColumn,
         value: &str,
         value_position: LineColumn,
-        delimiter: &str,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl;
+}
+
+/// A handler of a `TeeHandler` with the depth of the subtree it asked to skip (private)
+struct TeeBranch<H> {
+    handler: H,
+    skipped: usize,
+}
+
+impl<H: SASContentHandler> TeeBranch<H> {
+    /// Forward a start callback, `None` if the branch is skipping
+    fn start(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> Option<WalkControl> {
+        if self.skipped > 0 {
+            self.skipped += 1;
+            return None;
+        }
+        self.open(callback)
+    }
+
+    /// Forward a callback that may skip what follows up to the next end callback
+    fn open(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> Option<WalkControl> {
+        if self.skipped > 0 {
+            return None;
+        }
+        let control = callback(&mut self.handler);
+        if control == WalkControl::SkipSubtree {
+            self.skipped = 1;
+        }
+        Some(control)
+    }
+
+    /// Forward an end callback, which ends a skip started by its start callback
+    fn end(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> Option<WalkControl> {
+        if self.skipped > 1 {
+            self.skipped -= 1;
+            return None;
+        }
+        self.skipped = 0;
+        Some(callback(&mut self.handler))
+    }
+
+    /// Forward any other callback
+    fn event(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> Option<WalkControl> {
+        if self.skipped > 0 {
+            return None;
+        }
+        Some(callback(&mut self.handler))
+    }
+}
+
+/// Forwards every callback to two handlers, so one walk can feed both
+///
+/// The walk stops when either handler returns `Stop`. A subtree is skipped by the walker only
+/// when both handlers skip it; when just one does, that handler gets no callbacks until the
+/// end of the subtree it skipped while the other carries on.
+pub struct TeeHandler<A, B> {
+    first: TeeBranch<A>,
+    second: TeeBranch<B>,
+}
+
+impl<A: SASContentHandler, B: SASContentHandler> TeeHandler<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        TeeHandler {
+            first: TeeBranch {
+                handler: first,
+                skipped: 0,
+            },
+            second: TeeBranch {
+                handler: second,
+                skipped: 0,
+            },
+        }
+    }
+
+    /// Return the two handlers
+    pub fn into_inner(self) -> (A, B) {
+        (self.first.handler, self.second.handler)
+    }
+
+    /// The control for the walker from the controls of the branches that were called
+    fn combine(first: Option<WalkControl>, second: Option<WalkControl>) -> WalkControl {
+        match (first, second) {
+            (Some(WalkControl::Stop), _) | (_, Some(WalkControl::Stop)) => WalkControl::Stop,
+            (Some(WalkControl::Continue), _) | (_, Some(WalkControl::Continue)) => {
+                WalkControl::Continue
+            }
+            (None, None) => WalkControl::Continue,
+            _ => WalkControl::SkipSubtree,
+        }
+    }
+}
+
+impl<A: SASContentHandler, B: SASContentHandler> SASContentHandler for TeeHandler<A, B> {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        let first = self.first.start(|handler| handler.start_stream(name));
+        let second = self.second.start(|handler| handler.start_stream(name));
+        Self::combine(first, second)
+    }
+
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
+        let first = self.first.end(|handler| handler.end_stream(position));
+        let second = self.second.end(|handler| handler.end_stream(position));
+        Self::combine(first, second)
+    }
+
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
+        let first = self.first.start(|handler| handler.start_global(position));
+        let second = self.second.start(|handler| handler.start_global(position));
+        Self::combine(first, second)
+    }
+
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
+        let first = self.first.end(|handler| handler.end_global(position));
+        let second = self.second.end(|handler| handler.end_global(position));
+        Self::combine(first, second)
+    }
+
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        let first = self
+            .first
+            .start(|handler| handler.start_data(position, name));
+        let second = self
+            .second
+            .start(|handler| handler.start_data(position, name));
+        Self::combine(first, second)
+    }
+
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        let first = self.first.end(|handler| handler.end_data(position, name));
+        let second = self.second.end(|handler| handler.end_data(position, name));
+        Self::combine(first, second)
+    }
+
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        let first = self
+            .first
+            .start(|handler| handler.start_saveframe(position, name));
+        let second = self
+            .second
+            .start(|handler| handler.start_saveframe(position, name));
+        Self::combine(first, second)
+    }
+
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        let first = self
+            .first
+            .end(|handler| handler.end_saveframe(position, name));
+        let second = self
+            .second
+            .end(|handler| handler.end_saveframe(position, name));
+        Self::combine(first, second)
+    }
+
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
+        let first = self.first.start(|handler| handler.start_loop(position));
+        let second = self.second.start(|handler| handler.start_loop(position));
+        Self::combine(first, second)
+    }
+
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        let first = self.first.end(|handler| handler.end_loop(position));
+        let second = self.second.end(|handler| handler.end_loop(position));
+        Self::combine(first, second)
+    }
+
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        // a skip from here lasts until end_loop
+        let first = self
+            .first
+            .open(|handler| handler.loop_definition(position, tags, nesting_level));
+        let second = self
+            .second
+            .open(|handler| handler.loop_definition(position, tags, nesting_level));
+        Self::combine(first, second)
+    }
+
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        let first = self
+            .first
+            .event(|handler| handler.start_loop_row(position, row_index));
+        let second = self
+            .second
+            .event(|handler| handler.start_loop_row(position, row_index));
+        Self::combine(first, second)
+    }
+
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        let first = self
+            .first
+            .event(|handler| handler.end_loop_row(position, row_index));
+        let second = self
+            .second
+            .event(|handler| handler.end_loop_row(position, row_index));
+        Self::combine(first, second)
+    }
+
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        let first = self.first.event(|handler| handler.comment(position, text));
+        let second = self.second.event(|handler| handler.comment(position, text));
+        Self::combine(first, second)
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        let first = self
+            .first
+            .event(|handler| handler.comment_with_kind(position, text, kind));
+        let second = self
+            .second
+            .event(|handler| handler.comment_with_kind(position, text, kind));
+        Self::combine(first, second)
+    }
+
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        let first = self
+            .first
+            .event(|handler| handler.element_comments(comments));
+        let second = self
+            .second
+            .event(|handler| handler.element_comments(comments));
+        Self::combine(first, second)
+    }
+
+    fn element_comments_with_kind(
+        &mut self,
+        comments: &[(LineColumn, &str, CommentKind)],
+    ) -> WalkControl {
+        let first = self
+            .first
+            .event(|handler| handler.element_comments_with_kind(comments));
+        let second = self
+            .second
+            .event(|handler| handler.element_comments_with_kind(comments));
+        Self::combine(first, second)
+    }
+
+    fn checkpoint(&mut self, checkpoint: &StreamCheckpoint) -> WalkControl {
+        let first = self.first.event(|handler| handler.checkpoint(checkpoint));
+        let second = self.second.event(|handler| handler.checkpoint(checkpoint));
+        Self::combine(first, second)
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        let forward = |handler: &mut dyn SASContentHandler| {
+            handler.data(
+                tag,
+                tag_position,
+                value,
+                value_position,
+                delimiter,
+                loop_level,
+            )
+        };
+        let first = self.first.event(|handler| forward(handler));
+        let second = self.second.event(|handler| forward(handler));
+        Self::combine(first, second)
+    }
+}
+
+/// Forwards only the callbacks inside the data blocks and save frames whose names match a
+/// predicate
+///
+/// A matching block or save frame is forwarded whole, from its start to its end callback and
+/// including any save frames in it. Everything outside a match is suppressed, including the
+/// start and end of the block holding a matching save frame, apart from the stream start and end
+/// which are always forwarded. A checkpoint is forwarded when its block or save frame matches,
+/// as are the comments attached to it.
+pub struct FilterHandler<H, F> {
+    handler: H,
+    predicate: F,
+    depth: usize, // Open blocks and save frames inside the current match
+    comments: Vec<(LineColumn, String, CommentKind)>, // Attached comments outside a match, for the next start
+}
+
+impl<H: SASContentHandler, F: FnMut(&str) -> bool> FilterHandler<H, F> {
+    pub fn new(handler: H, predicate: F) -> Self {
+        FilterHandler {
+            handler,
+            predicate,
+            depth: 0,
+            comments: Vec::new(),
+        }
+    }
+
+    /// Return the filtered handler
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    /// Forward a start callback if it is inside a match or starts one
+    fn start(&mut self, name: &str, callback: impl FnOnce(&mut H) -> WalkControl) -> WalkControl {
+        if self.depth == 0 && !(self.predicate)(name) {
+            self.comments.clear();
+            return WalkControl::Continue;
+        }
+        if self.forward_comments() == WalkControl::Stop {
+            return WalkControl::Stop;
+        }
+        self.depth += 1;
+        callback(&mut self.handler)
+    }
+
+    /// Forward the attached comments kept for the match being started
+    fn forward_comments(&mut self) -> WalkControl {
+        let comments = std::mem::take(&mut self.comments);
+        if comments.is_empty() {
+            return WalkControl::Continue;
+        }
+        let comments: Vec<(LineColumn, &str, CommentKind)> = comments
+            .iter()
+            .map(|(position, text, kind)| (*position, text.as_str(), *kind))
+            .collect();
+        self.handler.element_comments_with_kind(&comments)
+    }
+
+    /// Forward an end callback if it is inside a match, the last one ends the match
+    fn end(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> WalkControl {
+        if self.depth == 0 {
+            self.comments.clear();
+            return WalkControl::Continue;
+        }
+        self.depth -= 1;
+        callback(&mut self.handler)
+    }
+
+    /// Forward any other callback if it is inside a match
+    fn event(&mut self, callback: impl FnOnce(&mut H) -> WalkControl) -> WalkControl {
+        if self.depth == 0 {
+            self.comments.clear();
+            WalkControl::Continue
+        } else {
+            callback(&mut self.handler)
+        }
+    }
+}
+
+impl<H: SASContentHandler, F: FnMut(&str) -> bool> SASContentHandler for FilterHandler<H, F> {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        self.handler.start_stream(name)
+    }
+
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
+        self.handler.end_stream(position)
+    }
+
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
+        // global blocks have no name, the save frames in them can still match
+        self.event(|handler| handler.start_global(position))
+    }
+
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
+        self.event(|handler| handler.end_global(position))
+    }
+
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.start(name, |handler| handler.start_data(position, name))
+    }
+
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.end(|handler| handler.end_data(position, name))
+    }
+
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.start(name, |handler| handler.start_saveframe(position, name))
+    }
+
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.end(|handler| handler.end_saveframe(position, name))
+    }
+
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.event(|handler| handler.start_loop(position))
+    }
+
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.event(|handler| handler.end_loop(position))
+    }
+
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.event(|handler| handler.loop_definition(position, tags, nesting_level))
+    }
+
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.event(|handler| handler.start_loop_row(position, row_index))
+    }
+
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.event(|handler| handler.end_loop_row(position, row_index))
+    }
+
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        self.event(|handler| handler.comment(position, text))
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        self.event(|handler| handler.comment_with_kind(position, text, kind))
+    }
+
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        self.event(|handler| handler.element_comments(comments))
+    }
+
+    fn element_comments_with_kind(
+        &mut self,
+        comments: &[(LineColumn, &str, CommentKind)],
+    ) -> WalkControl {
+        if self.depth == 0 {
+            // kept until the next callback in case it starts a match
+            self.comments = comments
+                .iter()
+                .map(|&(position, text, kind)| (position, text.to_string(), kind))
+                .collect();
+            return WalkControl::Continue;
+        }
+        self.handler.element_comments_with_kind(comments)
+    }
+
+    fn checkpoint(&mut self, checkpoint: &StreamCheckpoint) -> WalkControl {
+        // checkpoints come just before the heading of their block or save frame
+        let matches = match checkpoint.path.last() {
+            Some(name) => self.depth > 0 || (self.predicate)(name),
+            None => self.depth > 0,
+        };
+        if matches {
+            // the comments attached to the heading come before its checkpoint
+            if self.forward_comments() == WalkControl::Stop {
+                return WalkControl::Stop;
+            }
+            self.handler.checkpoint(checkpoint)
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
         loop_level: usize,
-    ) -> bool;
+    ) -> WalkControl {
+        self.event(|handler| {
+            handler.data(
+                tag,
+                tag_position,
+                value,
+                value_position,
+                delimiter,
+                loop_level,
+            )
+        })
+    }
 }
 
 // Example skeleton for a parse-tree walker function
@@ -1,19 +1,490 @@
+use crate::block_iterator::BLOCK_KEYWORDS;
+use crate::config::{get_column_unit, get_encoding};
+use crate::fragment::next_keyword;
+use crate::legacy_encoding::{decode_windows_1252, original_offset};
 use crate::line_column_index::{LineColumn, LineColumnIndex};
-use crate::mutable_pair::MutablePair;
-use crate::sas_interface::{SASContentHandler, EMPTY_LOOP_DELIMITER};
+use crate::mutable_pair::{MutablePair, PairNode};
+use crate::progress::{ParseProgress, ProgressCallback};
+use crate::sas_interface::{
+    CommentKind, SASContentHandler, StreamCheckpoint, ValueDelimiter, WalkControl,
+};
+use crate::shared_text::RuleNames;
+use crate::string_decomposer::unescape_quotes;
+use crate::{EncodingMode, ParserConfig, UstarError};
+use pest::iterators::{Pair, Pairs};
+use pest::RuleType;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+
+/// The node operations the walker needs, so one walk serves trees and pest pairs (private)
+trait WalkNode: Clone {
+    fn rule_name(&self) -> &str;
+    fn as_str(&self) -> &str;
+    fn start_pos(&self) -> usize;
+    fn end_pos(&self) -> usize;
+    fn start_line_column(&self) -> LineColumn;
+    fn end_line_column(&self) -> LineColumn;
+    fn children(&self) -> impl Iterator<Item = Self>;
+
+    fn child(&self, index: usize) -> Option<Self> {
+        self.children().nth(index)
+    }
+
+    fn child_count(&self) -> usize {
+        self.children().count()
+    }
+}
+
+/// A node of a tree backend implementing `PairNode` (private)
+#[derive(Clone, Copy)]
+struct TreeNode<'n, N>(N, PhantomData<&'n ()>);
+
+impl<'n, N: PairNode<'n>> TreeNode<'n, N> {
+    fn new(node: N) -> Self {
+        TreeNode(node, PhantomData)
+    }
+}
+
+impl<'n, N: PairNode<'n>> WalkNode for TreeNode<'n, N> {
+    fn rule_name(&self) -> &str {
+        self.0.rule_name()
+    }
+
+    fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    fn start_pos(&self) -> usize {
+        self.0.start_pos()
+    }
+
+    fn end_pos(&self) -> usize {
+        self.0.end_pos()
+    }
+
+    fn start_line_column(&self) -> LineColumn {
+        self.0.start_line_column()
+    }
+
+    fn end_line_column(&self) -> LineColumn {
+        self.0.end_line_column()
+    }
+
+    fn children(&self) -> impl Iterator<Item = Self> {
+        self.0.child_nodes().map(TreeNode::new)
+    }
+
+    fn child(&self, index: usize) -> Option<Self> {
+        self.0.child(index).map(TreeNode::new)
+    }
+
+    fn child_count(&self) -> usize {
+        self.0.child_count()
+    }
+}
+
+/// A pair of a pest parse, named the way `MutablePair::from_pest_pair` names it (private)
+struct PestNode<'i, 'r, R> {
+    pair: Pair<'i, R>,
+    rule_names: &'r RefCell<RuleNames<R>>,
+}
+
+impl<R: RuleType> Clone for PestNode<'_, '_, R> {
+    fn clone(&self) -> Self {
+        PestNode {
+            pair: self.pair.clone(),
+            rule_names: self.rule_names,
+        }
+    }
+}
+
+impl<R: RuleType> WalkNode for PestNode<'_, '_, R> {
+    fn rule_name(&self) -> &str {
+        // CIF2 input uses its own entry rule but produces the same tree as star_file
+        match self.rule_names.borrow_mut().get(self.pair.as_rule()) {
+            "cif2_star_file" => "star_file",
+            name => name,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        self.pair.as_str()
+    }
+
+    fn start_pos(&self) -> usize {
+        self.pair.as_span().start()
+    }
+
+    fn end_pos(&self) -> usize {
+        self.pair.as_span().end()
+    }
+
+    fn start_line_column(&self) -> LineColumn {
+        LineColumn::undefined()
+    }
+
+    fn end_line_column(&self) -> LineColumn {
+        LineColumn::undefined()
+    }
+
+    fn children(&self) -> impl Iterator<Item = Self> {
+        let rule_names = self.rule_names;
+        self.pair
+            .clone()
+            .into_inner()
+            .map(move |pair| PestNode { pair, rule_names })
+    }
+
+    fn child_count(&self) -> usize {
+        self.pair.clone().into_inner().len()
+    }
+}
+
+/// Whether the values of a data_loop end with stop_, rather than at the token after them
+fn ends_with_stop<N: WalkNode>(data_loop: &N) -> bool {
+    data_loop
+        .children()
+        .last()
+        .filter(|values| values.rule_name() == "data_loop_values")
+        .and_then(|values| values.children().last())
+        .is_some_and(|item| item.rule_name() == "stop_keyword")
+}
+
+/// Length of the opening delimiter of a quoted or semicolon string, matching string_decomposer
+fn opening_length<N: WalkNode>(node: &N) -> usize {
+    match node.rule_name() {
+        "semi_colon_string" if node.as_str().starts_with("\r\n;") => 3,
+        "semi_colon_string" => 2,
+        "triple_double_quote_string" | "triple_single_quote_string" => 3,
+        _ => 1,
+    }
+}
+
+/// Delimiter of a value node from its rule
+fn value_delimiter(rule_name: &str) -> ValueDelimiter {
+    match rule_name {
+        "single_quote_string" => ValueDelimiter::Single,
+        "double_quote_string" => ValueDelimiter::Double,
+        "semi_colon_string" => ValueDelimiter::Semicolon,
+        "triple_single_quote_string" => ValueDelimiter::TripleSingle,
+        "triple_double_quote_string" => ValueDelimiter::TripleDouble,
+        "list_value" => ValueDelimiter::List,
+        "table_value" => ValueDelimiter::Table,
+        _ => ValueDelimiter::None,
+    }
+}
+
+/// Whether a node is a value or tag, whose text is reported whole and holds no comments
+fn is_comment_free(rule_name: &str) -> bool {
+    matches!(
+        rule_name,
+        "data_name" | "frame_code" | "non_quoted_string" | "string"
+    ) || value_delimiter(rule_name) != ValueDelimiter::None
+}
 
 /// Walks a MutablePair parse tree and calls the BufferedContentHandler methods.
 pub struct StarWalker<'a, T: SASContentHandler> {
-    line_index: LineColumnIndex, // Fast line/column index (always present)
-    stream_name: Option<String>, // Optional name for the stream (file name, etc.)
+    line_index: LineColumnIndex<'a>, // Fast line/column index (always present)
+    stream_name: Option<String>,     // Optional name for the stream (file name, etc.)
     pub tag_table: Vec<Vec<String>>,
     pub tag_level: usize,
     pub tag_index: usize,
     pub tag_positions: Vec<Vec<LineColumn>>,
-    pub loop_level: usize,        // 0 = not in loop, 1+ = loop nesting level
-    pub values_emitted: usize,    // Count of values emitted in current loop
-    pub max_depth_reached: usize, // Deepest tag_level that had values emitted
+    loop_positions: Vec<LineColumn>, // Position of the loop_ keyword of each tag_table level
+    row_counts: Vec<usize>,          // Rows started at each loop level within the enclosing row
+    open_rows: Vec<(usize, usize)>,  // Level and index of each started row not yet ended
+    last_value_end: LineColumn,      // End of the last loop value walked
+    pub loop_level: usize,           // 0 = not in loop, 1+ = loop nesting level
+    pub values_emitted: usize,       // Count of values emitted in current loop
+    pub max_depth_reached: usize,    // Deepest tag_level that had values emitted
     pub handler: &'a mut T,
+    data_block_name: String, // Code of the enclosing data block, for checkpoint paths
+    resume: Option<ResumeState>, // Set when the walk was started by resume_from
+    attach_comments: bool,   // Deliver comments with the element after them, not on their own
+    unescape_quotes: bool,   // Collapse the doubled quotes of quoted values
+    pending_comments: Vec<(LineColumn, String, CommentKind)>, // Comments waiting for the next element
+    scanned_to: Option<usize>, // Offset up to which the input has been searched for comments
+    progress: Option<&'a mut ProgressCallback<'a>>, // Told about each top level block walked
+    blocks_completed: usize,   // Top level blocks walked, for progress reports
+    cancelled: Option<ParseProgress>, // The report the progress callback cancelled the walk at
+}
+
+/// How the input of a block of a resumed walk maps back onto the original stream
+struct ResumeState {
+    prefix_len: usize,          // Bytes synthesised in front of the text of the block
+    prefix_lines: usize,        // Lines synthesised in front of the block's first line
+    byte_offset: usize,         // Offset in the stream where the block starts
+    line: usize,                // Line of the stream the block starts on
+    one_byte_characters: bool,  // The block was decoded from Windows-1252, one byte per character
+    synthetic_data_block: bool, // The first data block only exists to hold a resumed save frame
+    skip_checkpoint: bool,      // The checkpoint being resumed from has already been delivered
+}
+
+/// Errors from resuming a walk at a checkpoint
+#[derive(Debug)]
+pub enum ResumeError {
+    Io(std::io::Error),
+    Parse(Box<UstarError>),
+    /// The stream holds a byte at `byte_offset` that doesn't decode in the configured encoding
+    InvalidUtf8 {
+        byte_offset: usize,
+    },
+    /// The checkpoint path is neither [data block] nor [data block, save frame]
+    InvalidCheckpoint(StreamCheckpoint),
+    /// The heading at the checkpoint offset isn't the one recorded, the input has probably changed
+    HeadingMismatch {
+        byte_offset: usize,
+        expected: String,
+        found: String,
+    },
+}
+
+impl std::fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResumeError::Io(e) => write!(f, "IO error: {}", e),
+            ResumeError::Parse(e) => write!(f, "Parse error: {}", e),
+            ResumeError::InvalidUtf8 { byte_offset } => {
+                write!(f, "Byte {} of the stream is not valid UTF-8", byte_offset)
+            }
+            ResumeError::InvalidCheckpoint(checkpoint) => {
+                write!(f, "Invalid checkpoint path: {:?}", checkpoint.path)
+            }
+            ResumeError::HeadingMismatch {
+                byte_offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} at byte {} but found '{}', has the input changed since the checkpoint?",
+                expected, byte_offset, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+impl From<std::io::Error> for ResumeError {
+    fn from(err: std::io::Error) -> Self {
+        ResumeError::Io(err)
+    }
+}
+
+/// The name the walker reports for a block or frame code: `code` without the quotes around it.
+///
+/// The grammar takes everything up to the next blank as the code, so `data_"ABC` and
+/// `save_'ABC'` are headings with codes `"ABC` and `'ABC'`, which strict mode rejects. Leading
+/// and trailing quotes are dropped, quotes inside the code and a code of only quotes are kept.
+pub fn heading_name(code: &str) -> &str {
+    let name = code.trim_matches(['\'', '"']);
+    if name.is_empty() {
+        code
+    } else {
+        name
+    }
+}
+
+/// Resume a walk from a checkpoint previously delivered to `SASContentHandler::checkpoint`.
+///
+/// The reader is positioned at the checkpoint offset and the heading found there must match the
+/// checkpoint path. Events then continue as they would have in the uninterrupted walk, starting
+/// with the data block or save frame the checkpoint precedes and ending with end_stream.
+///
+/// The rest of the stream is read, parsed and walked one top level block at a time, so resuming
+/// holds no more than a couple of blocks in memory however long the stream is. Bytes are decoded
+/// as UTF-8; with `ConfigKey::Encoding` set to `ExtendedAscii` a block that isn't UTF-8 is read as
+/// Latin-1 / Windows-1252, as `parse_bytes` reads such input, otherwise a byte that isn't UTF-8
+/// is an error. Returns true if the handler stopped the walk.
+pub fn resume_from<R: Read + Seek, T: SASContentHandler>(
+    reader: &mut R,
+    checkpoint: &StreamCheckpoint,
+    handler: &mut T,
+    config: &ParserConfig,
+) -> Result<bool, ResumeError> {
+    let (keyword, name) = match checkpoint.path.as_slice() {
+        [data_name] => ("data_", data_name),
+        [_, frame_name] => ("save_", frame_name),
+        _ => return Err(ResumeError::InvalidCheckpoint(checkpoint.clone())),
+    };
+
+    reader.seek(SeekFrom::Start(checkpoint.byte_offset as u64))?;
+    let extended_ascii = get_encoding(config) == EncodingMode::ExtendedAscii;
+    let mut blocks = StreamBlocks::new(reader, checkpoint.byte_offset, extended_ascii);
+    let mut block = blocks.next_block()?;
+
+    let found = block
+        .as_ref()
+        .and_then(|block| block.text.split(char::is_whitespace).next())
+        .unwrap_or("");
+    let heading_matches = found
+        .get(..keyword.len())
+        .is_some_and(|k| k.eq_ignore_ascii_case(keyword))
+        && &found[keyword.len()..] == name;
+    if !heading_matches {
+        return Err(ResumeError::HeadingMismatch {
+            byte_offset: checkpoint.byte_offset,
+            expected: format!("{}{}", keyword, name),
+            found: found.to_string(),
+        });
+    }
+
+    let column_unit = get_column_unit(config);
+    let mut line = checkpoint.line;
+    let mut column = checkpoint.column;
+    let mut pending_comments = Vec::new();
+    let mut first = true;
+    while let Some(current) = block {
+        // the block after is read first, the last block ends the stream
+        let next = blocks.next_block()?;
+
+        // a save frame only parses inside a data block so one is synthesised in front of it,
+        // padding keeps the columns on the first line the same as in the original stream
+        let padding = " ".repeat(column.saturating_sub(1));
+        let prefix = match checkpoint.path.as_slice() {
+            [data_name, _] if first => format!("data_{}\n{}", data_name, padding),
+            _ => padding,
+        };
+        let input = format!("{}{}", prefix, current.text);
+        let tree = crate::parse(&input, config).map_err(ResumeError::Parse)?;
+
+        let mut walker = StarWalker::from_input(handler, &input).with_config(config);
+        walker.resume = Some(ResumeState {
+            prefix_len: prefix.len(),
+            prefix_lines: prefix.matches('\n').count(),
+            byte_offset: current.byte_offset,
+            line,
+            one_byte_characters: current.one_byte_characters,
+            synthetic_data_block: first && checkpoint.path.len() == 2,
+            skip_checkpoint: first,
+        });
+        // comments after the previous block wait for the element that follows them
+        walker.pending_comments = std::mem::take(&mut pending_comments);
+        let stopped = match next {
+            None => walker.walk_node(&tree),
+            Some(_) => tree.children.iter().any(|child| walker.walk_node(child)),
+        };
+        if stopped {
+            return Ok(true);
+        }
+        pending_comments = std::mem::take(&mut walker.pending_comments);
+
+        // the next block starts where this one ends
+        line += memchr::memchr_iter(b'\n', current.text.as_bytes()).count();
+        let line_start = match current.text.rfind('\n') {
+            Some(newline) => {
+                column = 1;
+                newline + 1
+            }
+            None => 0,
+        };
+        let last_line = &current.text[line_start..];
+        column += LineColumnIndex::new(last_line)
+            .with_column_unit(column_unit)
+            .offset_to_line_col(last_line.len())
+            .column
+            - 1;
+        first = false;
+        block = next;
+    }
+    Ok(false)
+}
+
+/// Bytes read at a time while looking for the end of a block; reads grow with the bytes held so
+/// a long block isn't scanned again for every chunk
+const RESUME_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The top level blocks of a stream, read and decoded one at a time (private)
+struct StreamBlocks<'r, R> {
+    reader: &'r mut R,
+    pending: Vec<u8>, // Bytes read but not yet returned, starting at a block heading
+    byte_offset: usize, // Offset in the stream of the first pending byte
+    end_of_stream: bool, // Everything has been read into pending
+    extended_ascii: bool, // Blocks that aren't UTF-8 are read as Latin-1 / Windows-1252
+}
+
+/// A top level block of a stream and trivia after it (private)
+struct StreamBlock {
+    text: String,
+    byte_offset: usize,        // Offset in the stream where the block starts
+    one_byte_characters: bool, // Decoded from Windows-1252, each character from one byte
+}
+
+impl<'r, R: Read> StreamBlocks<'r, R> {
+    fn new(reader: &'r mut R, byte_offset: usize, extended_ascii: bool) -> Self {
+        StreamBlocks {
+            reader,
+            pending: Vec::new(),
+            byte_offset,
+            end_of_stream: false,
+            extended_ascii,
+        }
+    }
+
+    /// The next block, running up to the heading of the block after it or the end of the stream
+    fn next_block(&mut self) -> Result<Option<StreamBlock>, ResumeError> {
+        loop {
+            if self.pending.is_empty() && self.end_of_stream {
+                return Ok(None);
+            }
+            if let Some(block) = self.split_block()? {
+                return Ok(Some(block));
+            }
+            let wanted = self.pending.len().max(RESUME_CHUNK_SIZE) as u64;
+            if (&mut *self.reader)
+                .take(wanted)
+                .read_to_end(&mut self.pending)?
+                == 0
+            {
+                self.end_of_stream = true;
+            }
+        }
+    }
+
+    /// Split the first block off the bytes read, if they hold all of it (private)
+    fn split_block(&mut self) -> Result<Option<StreamBlock>, ResumeError> {
+        // until the stream ends only whole lines are scanned, a token cut off at the end of the
+        // bytes read could hide a heading or seem to hold one
+        let scanned = match memchr::memrchr(b'\n', &self.pending) {
+            _ if self.end_of_stream => self.pending.len(),
+            Some(newline) => newline + 1,
+            None => return Ok(None),
+        };
+        let (text, one_byte_characters) = self.decode(&self.pending[..scanned])?;
+        let end = next_keyword(&text, 0, &BLOCK_KEYWORDS);
+        if end == text.len() && !self.end_of_stream {
+            return Ok(None);
+        }
+        // the bytes after the block may not be UTF-8 when the block's own bytes are
+        let (length, (text, one_byte_characters)) = if one_byte_characters {
+            let length = text[..end].chars().count();
+            (length, self.decode(&self.pending[..length])?)
+        } else {
+            (end, (Cow::Borrowed(&text[..end]), false))
+        };
+        let block = StreamBlock {
+            text: text.into_owned(),
+            byte_offset: self.byte_offset,
+            one_byte_characters,
+        };
+        self.pending.drain(..length);
+        self.byte_offset += length;
+        Ok(Some(block))
+    }
+
+    /// Decode `bytes` from the start of the pending bytes, and whether they were read one byte per
+    /// character (private)
+    fn decode<'b>(&self, bytes: &'b [u8]) -> Result<(Cow<'b, str>, bool), ResumeError> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok((Cow::Borrowed(text), false)),
+            Err(_) if self.extended_ascii => Ok((Cow::Owned(decode_windows_1252(bytes)), true)),
+            Err(error) => Err(ResumeError::InvalidUtf8 {
+                byte_offset: self.byte_offset + error.valid_up_to(),
+            }),
+        }
+    }
 }
 
 impl<'a, T: SASContentHandler> StarWalker<'a, T> {
@@ -47,7 +518,7 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
         }
     }
 
-    pub fn from_input(handler: &'a mut T, input: &str) -> Self {
+    pub fn from_input(handler: &'a mut T, input: &'a str) -> Self {
         let line_index = LineColumnIndex::new(input);
         StarWalker {
             line_index,
@@ -56,14 +527,27 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
             tag_level: 0,
             tag_index: 0,
             tag_positions: Vec::new(),
+            loop_positions: Vec::new(),
+            row_counts: Vec::new(),
+            open_rows: Vec::new(),
+            last_value_end: LineColumn::new(0, 0),
             loop_level: 0,
             values_emitted: 0,
             max_depth_reached: 0,
             handler,
+            data_block_name: String::new(),
+            resume: None,
+            attach_comments: false,
+            unescape_quotes: false,
+            pending_comments: Vec::new(),
+            scanned_to: None,
+            progress: None,
+            blocks_completed: 0,
+            cancelled: None,
         }
     }
 
-    pub fn from_input_with_name(handler: &'a mut T, input: &str, name: Option<String>) -> Self {
+    pub fn from_input_with_name(handler: &'a mut T, input: &'a str, name: Option<String>) -> Self {
         let line_index = LineColumnIndex::new(input);
         StarWalker {
             line_index,
@@ -72,34 +556,354 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
             tag_level: 0,
             tag_index: 0,
             tag_positions: Vec::new(),
+            loop_positions: Vec::new(),
+            row_counts: Vec::new(),
+            open_rows: Vec::new(),
+            last_value_end: LineColumn::new(0, 0),
             loop_level: 0,
             values_emitted: 0,
             max_depth_reached: 0,
             handler,
+            data_block_name: String::new(),
+            resume: None,
+            attach_comments: false,
+            unescape_quotes: false,
+            pending_comments: Vec::new(),
+            scanned_to: None,
+            progress: None,
+            blocks_completed: 0,
+            cancelled: None,
+        }
+    }
+
+    /// Apply the walker settings of a parser configuration, `ConfigKey::AttachComments`,
+    /// `ConfigKey::UnescapeQuotes` and `ConfigKey::ColumnUnit`; the unit applies to positions
+    /// looked up in the input, a walked tree keeps the positions it was parsed with
+    pub fn with_config(mut self, config: &ParserConfig) -> Self {
+        self.attach_comments = crate::config::get_attach_comments(config);
+        self.unescape_quotes = crate::config::get_unescape_quotes(config);
+        self.line_index = self
+            .line_index
+            .with_column_unit(crate::config::get_column_unit(config));
+        self
+    }
+
+    /// Report progress after each top level data or global block is walked; a callback that
+    /// returns `ControlFlow::Break` stops the walk as the handler can, see `cancelled`
+    pub fn with_progress(mut self, progress: &'a mut ProgressCallback<'a>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The progress report the progress callback stopped the walk at, `None` if it didn't
+    pub fn cancelled(&self) -> Option<ParseProgress> {
+        self.cancelled
+    }
+
+    /// Report a top level block ending at `offset` to the progress callback (private)
+    fn report_block(&mut self, offset: usize) -> bool {
+        let Some(progress) = self.progress.as_mut() else {
+            return false;
+        };
+        self.blocks_completed += 1;
+        let report = ParseProgress {
+            bytes_consumed: offset,
+            total_bytes: self.line_index.input().len(),
+            blocks_completed: self.blocks_completed,
+        };
+        if progress(report).is_break() {
+            self.cancelled = Some(report);
+            return true;
+        }
+        false
+    }
+
+    /// Deliver the comments waiting for an element to element_comments_with_kind, just before
+    /// its start or data callback (private)
+    fn attach_pending_comments(&mut self) -> bool {
+        if self.pending_comments.is_empty() {
+            return false;
+        }
+        let pending = std::mem::take(&mut self.pending_comments);
+        let comments: Vec<(LineColumn, &str, CommentKind)> = pending
+            .iter()
+            .map(|(position, text, kind)| (*position, &text[1..], *kind))
+            .collect();
+        self.handler.element_comments_with_kind(&comments) == WalkControl::Stop
+    }
+
+    /// Queue a full line comment for the next element when comments are attached, otherwise,
+    /// and for an inline comment which belongs to the text before it, deliver it at once.
+    /// Returns true if the handler stopped the walk (private)
+    fn add_comment(&mut self, position: LineColumn, text: &str, kind: CommentKind) -> bool {
+        if self.attach_comments && kind == CommentKind::FullLine {
+            self.pending_comments
+                .push((position, text.to_string(), kind));
+            return false;
+        }
+        self.handler.comment_with_kind(position, &text[1..], kind) == WalkControl::Stop
+    }
+
+    /// Find the comments between the last node walked and `offset`, queued for the next element
+    /// or delivered as standalone comments at once as `add_comment` decides; the
+    /// parser only keeps comments inside loops as nodes, the others are found in the gaps
+    /// between nodes. Returns true if the handler stopped the walk (private)
+    fn collect_comments(&mut self, offset: usize) -> bool {
+        let gap_start = self.scanned_to.unwrap_or(offset);
+        if offset <= gap_start {
+            return false;
+        }
+        // a tree walked without the input it was parsed from has no gaps to search
+        let Some(gap) = self.line_index.input().get(gap_start..offset) else {
+            return false;
+        };
+        let mut found = Vec::new();
+        let mut searched = 0;
+        while let Some(hash) = gap[searched..].find('#') {
+            let start = searched + hash;
+            let end = gap[start..]
+                .find('\n')
+                .map_or(gap.len(), |newline| start + newline);
+            found.push((
+                gap_start + start,
+                gap[start..end].trim_end_matches('\r').to_string(),
+            ));
+            searched = end;
+        }
+        self.scanned_to = Some(offset);
+        for (comment_start, text) in found {
+            let position = self.get_line_column(comment_start);
+            let kind = self.comment_kind(comment_start);
+            if self.add_comment(position, &text, kind) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Deliver the comments left waiting at the end of a scope as standalone comments (private)
+    fn release_pending_comments(&mut self) -> bool {
+        for (position, text, kind) in std::mem::take(&mut self.pending_comments) {
+            if self.handler.comment_with_kind(position, &text[1..], kind) == WalkControl::Stop {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the comment whose `#` is at `offset` follows other text on its line, `FullLine`
+    /// when the input doesn't hold the comment, as for a tree walked without its input (private)
+    fn comment_kind(&self, offset: usize) -> CommentKind {
+        let input = self.line_index.input();
+        if input.as_bytes().get(offset) != Some(&b'#') {
+            return CommentKind::FullLine;
+        }
+        let line_start = input[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        if input[line_start..offset].trim().is_empty() {
+            CommentKind::FullLine
+        } else {
+            CommentKind::Inline
         }
     }
 
     /// Get line and column for a byte offset (private)
     fn get_line_column(&self, offset: usize) -> LineColumn {
-        self.line_index.offset_to_line_col(offset)
+        self.map_position(self.line_index.offset_to_line_col(offset))
     }
 
+    /// Line and column of the first token at or after `offset`, past whitespace and comments,
+    /// or of the end of the input if there is none (private)
+    fn next_token_position(&self, offset: usize) -> LineColumn {
+        let input = self.line_index.input();
+        let mut rest = &input[offset..];
+        loop {
+            rest = rest.trim_start();
+            if !rest.starts_with('#') {
+                break;
+            }
+            rest = rest.find('\n').map_or("", |newline| &rest[newline..]);
+        }
+        self.get_line_column(input.len() - rest.len())
+    }
+
+    /// Line and column of a node's start, using the position stored in the tree when there is one
+    fn start_position<N: WalkNode>(&self, node: &N) -> LineColumn {
+        match node.start_line_column() {
+            position if position.is_defined() => self.map_position(position),
+            _ => self.get_line_column(node.start_pos()),
+        }
+    }
+
+    /// Line and column of a node's end, using the position stored in the tree when there is one
+    fn end_position<N: WalkNode>(&self, node: &N) -> LineColumn {
+        match node.end_line_column() {
+            position if position.is_defined() => self.map_position(position),
+            _ => self.get_line_column(node.end_pos()),
+        }
+    }
+
+    /// Map a position in the walked input back onto the original stream of a resumed walk
+    fn map_position(&self, position: LineColumn) -> LineColumn {
+        match &self.resume {
+            Some(resume) if position.is_defined() => LineColumn::new(
+                (position.line + resume.line).saturating_sub(resume.prefix_lines + 1),
+                position.column,
+            ),
+            _ => position,
+        }
+    }
+
+    /// Deliver a checkpoint for a heading starting at a byte offset, after the comments before
+    /// the heading so a walk resumed there has none left to deliver (private)
+    fn checkpoint(&mut self, offset: usize, path: Vec<String>) -> bool {
+        let byte_offset = match &mut self.resume {
+            Some(resume) if resume.skip_checkpoint => {
+                resume.skip_checkpoint = false;
+                return false;
+            }
+            Some(resume) => {
+                let block = &self.line_index.input()[resume.prefix_len..];
+                let offset = offset - resume.prefix_len;
+                let bytes = if resume.one_byte_characters {
+                    original_offset(block, offset)
+                } else {
+                    offset
+                };
+                resume.byte_offset + bytes
+            }
+            None => offset,
+        };
+        let position = self.get_line_column(offset);
+        let control = self.handler.checkpoint(&StreamCheckpoint {
+            byte_offset,
+            line: position.line,
+            column: position.column,
+            path,
+        });
+        control == WalkControl::Stop
+    }
+
+    /// Report the tags of each level of the current loop, outermost first (private)
+    fn loop_definitions(&mut self) -> WalkControl {
+        for (level, level_tags) in self.tag_table.iter().enumerate() {
+            let tags: Vec<&str> = level_tags.iter().map(String::as_str).collect();
+            let control =
+                self.handler
+                    .loop_definition(self.loop_positions[level], &tags, level + 1);
+            if control != WalkControl::Continue {
+                return control;
+            }
+        }
+        WalkControl::Continue
+    }
+
+    /// Start a loop row if the next value is the first of its level's tags (private)
+    fn start_row<N: WalkNode>(&mut self, node: &N) -> bool {
+        if self.loop_level == 0 || self.tag_index != 0 {
+            return false;
+        }
+        let level = self.tag_level;
+        // a new row restarts the row counts of the levels nested in it
+        self.row_counts.resize(level + 1, 0);
+        let row_index = self.row_counts[level];
+        self.row_counts[level] += 1;
+        self.open_rows.push((level, row_index));
+        let position = self.start_position(node);
+        self.handler.start_loop_row(position, row_index) == WalkControl::Stop
+    }
+
+    /// Account for a walked value and end the row it completes, if any (private)
+    fn end_value<N: WalkNode>(&mut self, node: &N) -> bool {
+        if self.loop_level == 0 {
+            self.increment_tag_pointers();
+            return false;
+        }
+        self.values_emitted += 1;
+        if self.tag_level + 1 > self.max_depth_reached {
+            self.max_depth_reached = self.tag_level + 1;
+        }
+        self.last_value_end = self.end_position(node);
+
+        // rows of the innermost level end with their last tag, outer rows end at a stop_
+        let level = self.tag_level;
+        let innermost = level + 1 == self.tag_table.len();
+        self.increment_tag_pointers();
+        if innermost && self.tag_index == 0 {
+            self.end_rows_from(level)
+        } else {
+            false
+        }
+    }
+
+    /// End the open rows at `level` and deeper, innermost first (private)
+    fn end_rows_from(&mut self, level: usize) -> bool {
+        while let Some(&(row_level, row_index)) = self.open_rows.last() {
+            if row_level < level {
+                break;
+            }
+            self.open_rows.pop();
+            if self.handler.end_loop_row(self.last_value_end, row_index) == WalkControl::Stop {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Walk a MutablePair tree
     pub fn walk_star_tree_buffered(&mut self, node: &MutablePair) -> bool {
+        self.walk_node(node)
+    }
+
+    /// Walk any tree backend implementing `PairNode` (e.g. `&MutablePair` or `tree_arena::PairRef`)
+    pub fn walk_node<'n, N: PairNode<'n>>(&mut self, node: N) -> bool {
+        self.walk(TreeNode::new(node))
+    }
+
+    /// Walk the pairs of a pest parse directly, without building a tree
+    ///
+    /// The callbacks are the same as for walking the tree `parse` builds from the same input.
+    /// The walker should be created from the input that was parsed.
+    pub fn walk_pairs<R: RuleType>(&mut self, pairs: Pairs<'_, R>) -> bool {
+        let rule_names = RefCell::new(RuleNames::new());
+        for pair in pairs {
+            let node = PestNode {
+                pair,
+                rule_names: &rule_names,
+            };
+            if self.walk(node) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Walk a node of any backend (private)
+    fn walk<N: WalkNode>(&mut self, node: N) -> bool {
         let mut should_stop = false;
 
-        // Check if this is the root of the tree (star_file rule)
-        if node.rule_name.as_str() == "star_file" {
+        // comments before the first node walked belong to no element of the walk
+        self.scanned_to.get_or_insert(node.start_pos());
+        if self.collect_comments(node.start_pos()) {
+            return true;
+        }
+
+        // Check if this is the root of the tree (star_file rule), a resumed walk has already started
+        if node.rule_name() == "star_file" && self.resume.is_none() {
             // Call start_stream at the beginning of parsing
-            should_stop = self.handler.start_stream(self.stream_name.as_deref());
-            if should_stop {
-                return true;
+            match self.handler.start_stream(self.stream_name.as_deref()) {
+                WalkControl::Continue => {}
+                WalkControl::SkipSubtree => {
+                    let position = self.end_position(&node);
+                    return self.handler.end_stream(position) == WalkControl::Stop;
+                }
+                WalkControl::Stop => return true,
             }
         }
 
-        match node.rule_name.as_str() {
+        match node.rule_name() {
             "data" => {
-                for child in &node.children {
-                    should_stop = self.walk_star_tree_buffered(child);
+                for child in node.children() {
+                    should_stop = self.walk(child);
                     if should_stop {
                         break;
                     }
@@ -107,128 +911,179 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                 if self.loop_level == 0 {
                     self.tag_table.clear();
                     self.tag_positions.clear();
+                    self.loop_positions.clear();
                 }
             }
-            "semi_colon_string" | "double_quote_string" | "single_quote_string" => {
-                if node.children.len() < 2 {
-                    return false;
+            "semi_colon_string"
+            | "double_quote_string"
+            | "single_quote_string"
+            | "triple_double_quote_string"
+            | "triple_single_quote_string" => {
+                if self.start_row(&node) || self.attach_pending_comments() {
+                    return true;
                 }
-                let value_node = &node.children[1];
                 let tag = self.tag_table[self.tag_level][self.tag_index].as_str();
                 let tag_position = self.tag_positions[self.tag_level][self.tag_index];
-                let value_position = self.get_line_column(value_node.start);
-                let children = &value_node.children;
-                if children.len() == 3 {
-                    let delimiter = &children[0].content;
-                    let value = &children[1].content;
-                    should_stop = self.handler.data(
-                        tag,
-                        tag_position,
-                        value,
-                        value_position,
-                        delimiter,
-                        self.current_loop_level(),
-                    );
-                } else {
-                    // For semicolon strings, content starts with "\n;" so we need the semicolon
-                    let delimiter = if node.rule_name == "semi_colon_string" {
-                        ";"
-                    } else {
-                        &node.content[0..1]
-                    };
-                    let value = if delimiter == ";" {
-                        &node.content[2..node.content.len() - 2]
-                    } else {
-                        &node.content[1..node.content.len() - 1]
-                    };
-                    should_stop = self.handler.data(
-                        tag,
-                        tag_position,
-                        value,
-                        value_position,
-                        delimiter,
-                        self.current_loop_level(),
-                    );
-                }
-
-                if self.loop_level > 0 {
-                    self.values_emitted += 1;
-                    if self.tag_level + 1 > self.max_depth_reached {
-                        self.max_depth_reached = self.tag_level + 1;
+                // a decomposed string holds its content as the middle child, strings that
+                // aren't decomposed and pest pairs have no children
+                let value_node = node.child(1);
+                let value_position = match &value_node {
+                    Some(value_node) => self.start_position(value_node),
+                    None => self.get_line_column(node.start_pos() + opening_length(&node)),
+                };
+                let delimiter = value_delimiter(node.rule_name());
+                let content = node.as_str();
+                let decomposed = value_node.as_ref().and_then(|value_node| {
+                    (value_node.child_count() == 3)
+                        .then(|| value_node.child(1))
+                        .flatten()
+                });
+                let value = match &decomposed {
+                    Some(decomposed) => decomposed.as_str(),
+                    // For semicolon strings, content starts with "\n;" and ends with "\n;"
+                    None if delimiter == ValueDelimiter::Semicolon => {
+                        &content[2..content.len() - 2]
+                    }
+                    None => {
+                        let length = delimiter.as_str().len();
+                        &content[length..content.len() - length]
                     }
+                };
+                // the logical value of a quoted string, whatever the tree holds as its content
+                let value = match delimiter {
+                    ValueDelimiter::Single if self.unescape_quotes => unescape_quotes(value, '\''),
+                    ValueDelimiter::Double if self.unescape_quotes => unescape_quotes(value, '"'),
+                    _ => Cow::Borrowed(value),
+                };
+                should_stop = self.handler.data(
+                    tag,
+                    tag_position,
+                    &value,
+                    value_position,
+                    delimiter,
+                    self.current_loop_level(),
+                ) == WalkControl::Stop;
+
+                if !should_stop {
+                    should_stop = self.end_value(&node);
+                }
+            }
+            // CIF2 lists and tables are reported as a single composite value: the text between the
+            // outer brackets with "[" or "{" as the delimiter; elements are not walked individually
+            "list_value" | "table_value" => {
+                if self.start_row(&node) || self.attach_pending_comments() {
+                    return true;
+                }
+                let tag = self.tag_table[self.tag_level][self.tag_index].as_str();
+                let tag_position = self.tag_positions[self.tag_level][self.tag_index];
+                let value_position = self.start_position(&node);
+                let content = node.as_str();
+                let delimiter = value_delimiter(node.rule_name());
+                let value = &content[1..content.len() - 1];
+                should_stop = self.handler.data(
+                    tag,
+                    tag_position,
+                    value,
+                    value_position,
+                    delimiter,
+                    self.current_loop_level(),
+                ) == WalkControl::Stop;
+                if !should_stop {
+                    should_stop = self.end_value(&node);
                 }
-                self.increment_tag_pointers();
             }
             // TODO: would it be better to make a non_quoted_string decompose to un_quoted_string->string for consistency
             "non_quoted_string" | "string" => {
+                if self.start_row(&node) || self.attach_pending_comments() {
+                    return true;
+                }
                 let tag = self.tag_table[self.tag_level][self.tag_index].as_str();
                 let tag_position = self.tag_positions[self.tag_level][self.tag_index];
-                let value_position = self.get_line_column(node.start);
-                let value = node.content.as_str();
+                let value_position = self.start_position(&node);
+                let value = node.as_str();
                 should_stop = self.handler.data(
                     tag,
                     tag_position,
                     value,
                     value_position,
-                    "",
+                    ValueDelimiter::None,
                     self.current_loop_level(),
-                );
-                if self.loop_level > 0 {
-                    self.values_emitted += 1;
-                    if self.tag_level + 1 > self.max_depth_reached {
-                        self.max_depth_reached = self.tag_level + 1;
-                    }
+                ) == WalkControl::Stop;
+                if !should_stop {
+                    should_stop = self.end_value(&node);
                 }
-                self.increment_tag_pointers();
             }
             "frame_code" => {
+                if self.start_row(&node) || self.attach_pending_comments() {
+                    return true;
+                }
                 let tag = self.tag_table[self.tag_level][self.tag_index].as_str();
                 let tag_position = self.tag_positions[self.tag_level][self.tag_index];
-                let value = node.content.as_str();
-                let value_position = self.get_line_column(node.start);
+                let value = node.as_str();
+                let value_position = self.start_position(&node);
                 should_stop = self.handler.data(
                     tag,
                     tag_position,
                     value,
                     value_position,
-                    "",
+                    ValueDelimiter::None,
                     self.current_loop_level(),
-                );
-                if self.loop_level > 0 {
-                    self.values_emitted += 1;
-                    if self.tag_level + 1 > self.max_depth_reached {
-                        self.max_depth_reached = self.tag_level + 1;
-                    }
+                ) == WalkControl::Stop;
+                if !should_stop {
+                    should_stop = self.end_value(&node);
                 }
-                self.increment_tag_pointers();
             }
             "stop_keyword" => {
                 self.decrement_tag_pointers();
+                // a stop_ ends the rows of the level it closes and the row that holds them
+                if self.loop_level > 0 {
+                    should_stop = self.end_rows_from(self.tag_level);
+                }
             }
 
             "loop_keyword" => {
                 // Each time a loop keyword is seen, add an empty tag list for this loop level
                 self.tag_table.push(Vec::new());
                 self.tag_positions.push(Vec::new());
+                self.loop_positions.push(self.start_position(&node));
             }
 
             "data_loop" => {
-                should_stop = self.handler.start_loop(self.get_line_column(node.start));
+                if self.attach_pending_comments() {
+                    return true;
+                }
+                let control = self.handler.start_loop(self.start_position(&node));
+                should_stop = control == WalkControl::Stop;
 
-                if !should_stop {
+                if control == WalkControl::Continue {
                     self.loop_level = 1; // Enter first loop level
                     self.values_emitted = 0; // Reset value counter
                     self.max_depth_reached = 0; // Reset max depth tracker
-                    for child in &node.children {
-                        should_stop = self.walk_star_tree_buffered(child);
-                        if should_stop {
+                    let mut skip_values = false;
+                    for child in node.children() {
+                        let definition = child.rule_name() == "data_loop_definition";
+                        should_stop = self.walk(child);
+                        // the tag table is complete once the definition has been walked
+                        if !should_stop && definition {
+                            match self.loop_definitions() {
+                                WalkControl::Continue => {}
+                                WalkControl::SkipSubtree => skip_values = true,
+                                WalkControl::Stop => should_stop = true,
+                            }
+                        }
+                        if should_stop || skip_values {
                             break;
                         }
                     }
 
+                    // rows left open by a loop without a final stop_ or with a partial last row
+                    if !should_stop && !skip_values {
+                        should_stop = self.end_rows_from(0);
+                    }
+
                     // Check for empty loops: emit EMPTY_LOOP for any tag levels that had no values
                     // This handles both completely empty loops and nested loops that were never filled
-                    if !should_stop && !self.tag_table.is_empty() {
+                    if !should_stop && !skip_values && !self.tag_table.is_empty() {
                         let empty_position = LineColumn { line: 0, column: 0 };
                         // Emit EMPTY_LOOP for levels beyond max_depth_reached
                         for level_idx in self.max_depth_reached..self.tag_table.len() {
@@ -240,9 +1095,9 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                                     tag_position,
                                     "",
                                     empty_position,
-                                    EMPTY_LOOP_DELIMITER,
+                                    ValueDelimiter::EmptyLoop,
                                     level_idx + 1, // loop_level is 1-indexed
-                                );
+                                ) == WalkControl::Stop;
                                 if should_stop {
                                     break;
                                 }
@@ -254,35 +1109,50 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                     }
 
                     self.loop_level = 0; // Exit loop
+                }
 
-                    if !should_stop {
-                        should_stop = self.handler.end_loop(self.get_line_column(node.end));
-                    }
+                if !should_stop {
+                    // a loop without a final stop_ ends where the token that follows it starts
+                    let end = if ends_with_stop(&node) {
+                        self.end_position(&node)
+                    } else {
+                        self.next_token_position(node.end_pos())
+                    };
+                    should_stop = self.release_pending_comments()
+                        || self.handler.end_loop(end) == WalkControl::Stop;
                 }
 
                 self.tag_table.clear();
                 self.tag_positions.clear();
+                self.loop_positions.clear();
+                self.row_counts.clear();
+                self.open_rows.clear();
                 self.tag_level = 0;
                 self.tag_index = 0;
             }
 
             "data_name" => {
-                let tag_position = self.get_line_column(node.start);
+                let tag_position = self.start_position(&node);
                 if self.loop_level > 0 {
                     let last = self.tag_table.len() - 1;
-                    self.tag_table[last].push(node.content.to_string());
+                    self.tag_table[last].push(node.as_str().to_string());
                     self.tag_positions[last].push(tag_position);
                 } else {
-                    self.tag_table.push(vec![node.content.to_string()]);
+                    self.tag_table.push(vec![node.as_str().to_string()]);
                     self.tag_positions.push(vec![tag_position]);
                 }
             }
             "global_block" => {
-                should_stop = self.handler.start_global(self.get_line_column(node.start));
+                // global_ isn't an element comments attach to, they end the scope before it
+                if self.release_pending_comments() {
+                    return true;
+                }
+                let control = self.handler.start_global(self.start_position(&node));
+                should_stop = control == WalkControl::Stop;
 
-                if !should_stop {
-                    for child in &node.children[1..] {
-                        should_stop = self.walk_star_tree_buffered(child);
+                if control == WalkControl::Continue {
+                    for child in node.children().skip(1) {
+                        should_stop = self.walk(child);
                         if should_stop {
                             break;
                         }
@@ -290,19 +1160,38 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                 }
 
                 if !should_stop {
-                    should_stop = self.handler.end_global(self.get_line_column(node.end));
+                    should_stop = self.release_pending_comments()
+                        || self.handler.end_global(self.end_position(&node)) == WalkControl::Stop
+                        || self.report_block(node.end_pos());
                 }
             }
             "data_block" => {
-                let data_heading = &node.children[0];
-                let data_name = &data_heading.content[5..];
-                should_stop = self
-                    .handler
-                    .start_data(self.get_line_column(node.start), data_name);
+                let data_heading = node.child(0).expect("data_block without heading");
+                let data_code = &data_heading.as_str()[5..];
+                let data_name = heading_name(data_code);
+                self.data_block_name = data_code.to_string();
+                // the heading isn't walked, a # in its code is text
+                self.scanned_to = Some(data_heading.end_pos());
 
-                if !should_stop {
-                    for child in &node.children[1..] {
-                        should_stop = self.walk_star_tree_buffered(child);
+                let synthetic = self
+                    .resume
+                    .as_mut()
+                    .is_some_and(|resume| std::mem::take(&mut resume.synthetic_data_block));
+                let control = if synthetic {
+                    WalkControl::Continue
+                } else if self.attach_pending_comments()
+                    || self.checkpoint(node.start_pos(), vec![data_code.to_string()])
+                {
+                    WalkControl::Stop
+                } else {
+                    self.handler
+                        .start_data(self.start_position(&node), data_name)
+                };
+                should_stop = control == WalkControl::Stop;
+
+                if control == WalkControl::Continue {
+                    for child in node.children().skip(1) {
+                        should_stop = self.walk(child);
                         if should_stop {
                             break;
                         }
@@ -310,21 +1199,30 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                 }
 
                 if !should_stop {
-                    should_stop = self
-                        .handler
-                        .end_data(self.get_line_column(node.end), data_name);
+                    should_stop = self.release_pending_comments()
+                        || self.handler.end_data(self.end_position(&node), data_name)
+                            == WalkControl::Stop
+                        || self.report_block(node.end_pos());
                 }
             }
             "save_frame" => {
-                let save_heading = &node.children[0];
-                let frame_name = &save_heading.content[5..];
-                should_stop = self
-                    .handler
-                    .start_saveframe(self.get_line_column(node.start), frame_name);
+                let save_heading = node.child(0).expect("save_frame without heading");
+                let frame_code = &save_heading.as_str()[5..];
+                let frame_name = heading_name(frame_code);
+                let path = vec![self.data_block_name.clone(), frame_code.to_string()];
+                self.scanned_to = Some(save_heading.end_pos());
+                let control =
+                    if self.attach_pending_comments() || self.checkpoint(node.start_pos(), path) {
+                        WalkControl::Stop
+                    } else {
+                        self.handler
+                            .start_saveframe(self.start_position(&node), frame_name)
+                    };
+                should_stop = control == WalkControl::Stop;
 
-                if !should_stop {
-                    for child in &node.children[1..] {
-                        should_stop = self.walk_star_tree_buffered(child);
+                if control == WalkControl::Continue {
+                    for child in node.children().skip(1) {
+                        should_stop = self.walk(child);
                         if should_stop {
                             break;
                         }
@@ -332,19 +1230,24 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
                 }
 
                 if !should_stop {
-                    should_stop = self
-                        .handler
-                        .end_saveframe(self.get_line_column(node.end), frame_name);
+                    should_stop = self.release_pending_comments()
+                        || self
+                            .handler
+                            .end_saveframe(self.end_position(&node), frame_name)
+                            == WalkControl::Stop;
                 }
             }
+            // comments inside loops arrive between values, they don't advance the tag pointers
+            // or values_emitted so row and column accounting is unaffected
             "comment" => {
-                should_stop = self
-                    .handler
-                    .comment(self.get_line_column(node.start), &node.content);
+                let position = self.start_position(&node);
+                let kind = self.comment_kind(node.start_pos());
+                self.scanned_to = Some(node.end_pos());
+                should_stop = self.add_comment(position, node.as_str(), kind);
             }
             _ => {
-                for child in &node.children {
-                    should_stop = self.walk_star_tree_buffered(child);
+                for child in node.children() {
+                    should_stop = self.walk(child);
                     if should_stop {
                         break;
                     }
@@ -352,10 +1255,20 @@ impl<'a, T: SASContentHandler> StarWalker<'a, T> {
             }
         }
 
+        if !should_stop {
+            if is_comment_free(node.rule_name()) {
+                // a # in a value or tag, such as a text field line starting with #, is text
+                self.scanned_to = Some(node.end_pos());
+            } else {
+                should_stop = self.collect_comments(node.end_pos());
+            }
+        }
+
         // Check if this is the root of the tree (star_file rule) and we're finishing
-        if node.rule_name.as_str() == "star_file" && !should_stop {
+        if node.rule_name() == "star_file" && !should_stop {
             // Call end_stream at the end of parsing
-            should_stop = self.handler.end_stream(self.get_line_column(node.end));
+            should_stop = self.release_pending_comments()
+                || self.handler.end_stream(self.end_position(&node)) == WalkControl::Stop;
         }
 
         should_stop
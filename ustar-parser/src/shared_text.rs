@@ -0,0 +1,193 @@
+//! SharedText - node text that shares a single copy of the parsed input.
+//!
+//! Every node of a parse tree covers a slice of the same input and nested nodes cover the same
+//! text again, so giving each node an owned `String` copies the text once per level of nesting.
+//! A `SharedText` is a reference counted handle to one copy of the input plus a byte range,
+//! which makes the text of a node a pointer and two offsets however long it is.
+//!
+//! Rule names come from a small fixed set, so the names of grammar rules are interned once as
+//! `&'static str` rather than allocated for every node.
+
+use pest::RuleType;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// A slice of a reference counted string, cheap to clone
+///
+/// The source is an `Arc<String>` rather than an `Arc<str>` so that the handle is a thin pointer,
+/// which keeps nodes holding a `SharedText` small. Equality, ordering and hashing compare the
+/// text, not where it is stored.
+///
+/// The count is atomic, so `SharedText`, and the nodes and decomposed strings slicing the same
+/// input, are `Send + Sync`.
+///
+/// With the `serde` feature this serializes as a plain string.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "String", into = "String")
+)]
+pub struct SharedText {
+    source: Arc<String>,
+    start: usize,
+    end: usize,
+}
+
+impl SharedText {
+    /// The text `source[start..end]`; panics if the range is out of bounds or splits a character
+    pub fn new(source: Arc<String>, start: usize, end: usize) -> Self {
+        assert!(
+            source.get(start..end).is_some(),
+            "{}..{} is not a valid range of the source text",
+            start,
+            end
+        );
+        SharedText { source, start, end }
+    }
+
+    /// Get the text as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.source[self.start..self.end]
+    }
+
+    /// The text `self[start..end]`, sharing the same source
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        SharedText::new(self.source.clone(), self.start + start, self.start + end)
+    }
+
+    /// Check if two texts are slices of the same source
+    pub fn shares_source(&self, other: &SharedText) -> bool {
+        Arc::ptr_eq(&self.source, &other.source)
+    }
+
+    /// The source this text is a slice of and the offset in it where the text starts (private)
+    pub(crate) fn source_start(&self) -> (&Arc<String>, usize) {
+        (&self.source, self.start)
+    }
+
+    /// The same text in `source`, where it starts `delta` bytes later than in this source
+    /// (private)
+    pub(crate) fn moved(&self, source: &Arc<String>, delta: isize) -> Self {
+        SharedText::new(
+            source.clone(),
+            self.start.wrapping_add_signed(delta),
+            self.end.wrapping_add_signed(delta),
+        )
+    }
+}
+
+impl Deref for SharedText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for SharedText {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for SharedText {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for SharedText {}
+
+impl PartialEq<str> for SharedText {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for SharedText {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl std::hash::Hash for SharedText {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl fmt::Debug for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<String> for SharedText {
+    fn from(text: String) -> Self {
+        let end = text.len();
+        SharedText {
+            source: Arc::new(text),
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl From<&str> for SharedText {
+    fn from(text: &str) -> Self {
+        SharedText::from(text.to_string())
+    }
+}
+
+impl From<SharedText> for String {
+    fn from(text: SharedText) -> Self {
+        text.as_str().to_string()
+    }
+}
+
+/// Interned `Debug` names of the rules of a grammar, cached per conversion to skip the lock
+pub(crate) struct RuleNames<R> {
+    names: HashMap<R, &'static str>,
+}
+
+impl<R: RuleType> RuleNames<R> {
+    pub(crate) fn new() -> Self {
+        RuleNames {
+            names: HashMap::new(),
+        }
+    }
+
+    /// The name of `rule`, as produced by its `Debug` implementation
+    pub(crate) fn get(&mut self, rule: R) -> &'static str {
+        self.names
+            .entry(rule)
+            .or_insert_with(|| intern(format!("{:?}", rule)))
+    }
+}
+
+/// Return the one static copy of `name`, leaking it the first time it is seen
+///
+/// Only rule names are interned, so the leaked set is bounded by the rules of the grammars.
+fn intern(name: String) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(interned) = names.get(name.as_str()) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(name.into_boxed_str());
+    names.insert(interned);
+    interned
+}
@@ -1,48 +1,181 @@
-use crate::config::EncodingMode;
-use crate::error_core::ErrorData;
+use crate::config::{ColumnUnit, EncodingMode};
+use crate::error_core::{ErrorCode, ErrorData};
 use crate::ErrorFormatMode;
 
 /// USTAR parsing error types (simple version without miette dependencies)
+///
+/// The details are boxed so the error is the size of a pointer and a tag, small enough to
+/// return in a `Result` directly. The error owns its details and shares the input by an `Arc`, so
+/// it is `Send + Sync + 'static`.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        from = "crate::error_core::SerializedError",
+        into = "crate::error_core::SerializedError"
+    )
+)]
 pub enum UstarError {
-    ParseError(ErrorData),
+    ParseError(Box<ErrorData>),
+    /// A semicolon-delimited string that is never closed, reported at its opening `;`
+    RunawaySemicolonString(Box<ErrorData>),
+    /// Input over a size or nesting limit of the configuration, reported where it is exceeded
+    LimitExceeded(Box<ErrorData>),
+    /// Parsing stopped by its progress callback, reported where parsing stopped
+    Cancelled(Box<ErrorData>),
 }
 
-impl std::fmt::Display for UstarError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            UstarError::ParseError(core) => {
-                write!(f, "{}", core.pest_error_display)
+#[cfg(feature = "serde")]
+impl From<crate::error_core::SerializedError> for UstarError {
+    fn from(error: crate::error_core::SerializedError) -> Self {
+        match error {
+            crate::error_core::SerializedError::ParseError(core) => {
+                UstarError::ParseError(Box::new(core))
+            }
+            crate::error_core::SerializedError::RunawaySemicolonString(core) => {
+                UstarError::RunawaySemicolonString(Box::new(core))
+            }
+            crate::error_core::SerializedError::LimitExceeded(core) => {
+                UstarError::LimitExceeded(Box::new(core))
+            }
+            crate::error_core::SerializedError::Cancelled(core) => {
+                UstarError::Cancelled(Box::new(core))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<UstarError> for crate::error_core::SerializedError {
+    fn from(error: UstarError) -> Self {
+        match error {
+            UstarError::ParseError(core) => crate::error_core::SerializedError::ParseError(*core),
+            UstarError::RunawaySemicolonString(core) => {
+                crate::error_core::SerializedError::RunawaySemicolonString(*core)
+            }
+            UstarError::LimitExceeded(core) => {
+                crate::error_core::SerializedError::LimitExceeded(*core)
             }
+            UstarError::Cancelled(core) => crate::error_core::SerializedError::Cancelled(*core),
         }
     }
 }
 
+impl std::fmt::Display for UstarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.core().pest_display())
+    }
+}
+
 impl std::error::Error for UstarError {}
 
 impl UstarError {
+    /// Create an error from a pest error, owned or borrowed, over `input`
     pub fn from_pest_error<R: pest::RuleType>(
-        error: pest::error::Error<R>,
+        error: impl std::borrow::Borrow<pest::error::Error<R>>,
         encoding: EncodingMode,
         input: &str,
     ) -> Self {
+        let error = error.borrow();
+        if let Some(core) = ErrorData::runaway_semicolon_string(error, encoding, input) {
+            return UstarError::RunawaySemicolonString(Box::new(core));
+        }
         let core = ErrorData::from_pest_error(error, encoding, input);
-        UstarError::ParseError(core)
+        UstarError::ParseError(Box::new(core))
+    }
+
+    /// The details of the error shared by every variant
+    pub fn core(&self) -> &ErrorData {
+        match self {
+            UstarError::ParseError(core)
+            | UstarError::RunawaySemicolonString(core)
+            | UstarError::LimitExceeded(core)
+            | UstarError::Cancelled(core) => core,
+        }
+    }
+
+    /// Apply `change` to the details of the error, keeping its variant (private)
+    fn map_core(self, change: impl FnOnce(ErrorData) -> ErrorData) -> Self {
+        match self {
+            UstarError::ParseError(core) => UstarError::ParseError(Box::new(change(*core))),
+            UstarError::RunawaySemicolonString(core) => {
+                UstarError::RunawaySemicolonString(Box::new(change(*core)))
+            }
+            UstarError::LimitExceeded(core) => UstarError::LimitExceeded(Box::new(change(*core))),
+            UstarError::Cancelled(core) => UstarError::Cancelled(Box::new(change(*core))),
+        }
+    }
+
+    /// The details of the error, taken out of its variant (private)
+    fn into_core(self) -> Box<ErrorData> {
+        match self {
+            UstarError::ParseError(core)
+            | UstarError::RunawaySemicolonString(core)
+            | UstarError::LimitExceeded(core)
+            | UstarError::Cancelled(core) => core,
+        }
+    }
+
+    /// Report the error as a configured limit being exceeded (private)
+    pub(crate) fn into_limit_exceeded(self) -> Self {
+        UstarError::LimitExceeded(self.into_core())
+    }
+
+    /// Report the error as parsing cancelled by a progress callback (private)
+    pub(crate) fn into_cancelled(self) -> Self {
+        UstarError::Cancelled(self.into_core())
+    }
+
+    /// The stable code classifying the error
+    pub fn code(&self) -> ErrorCode {
+        self.core().code
+    }
+
+    /// Set the code of an error found after parsing (private)
+    pub(crate) fn with_code(self, code: ErrorCode) -> Self {
+        self.map_core(|core| ErrorData { code, ..core })
+    }
+
+    /// Recount the error column in another unit, errors from pest count characters
+    pub fn with_column_unit(self, column_unit: ColumnUnit) -> Self {
+        self.map_core(|core| core.with_column_unit(column_unit))
+    }
+
+    /// Refer the offsets of an error in `text`, decoded one character per byte, to the bytes it
+    /// was decoded from (private)
+    pub(crate) fn in_original_bytes(self, text: &str) -> Self {
+        self.map_core(|core| core.in_original_bytes(text))
+    }
+
+    /// Add a note to the message of the error, such as how the input was decoded (private)
+    pub(crate) fn with_note(self, note: &str) -> Self {
+        self.map_core(|core| ErrorData {
+            message: format!("{} ({})", core.message, note),
+            ..core
+        })
+    }
+
+    /// Name the file the input was read from, so formatted errors say where they are
+    pub fn with_source_name(self, source_name: impl Into<String>) -> Self {
+        let source_name = source_name.into();
+        self.map_core(|core| ErrorData {
+            source_name: Some(source_name),
+            ..core
+        })
     }
 
     /// Format error according to specified mode
     pub fn format_error(&self, mode: ErrorFormatMode, context_lines: usize) -> String {
-        match self {
-            UstarError::ParseError(core) => {
-                match mode {
-                    ErrorFormatMode::Basic => core.format_basic(),
-                    ErrorFormatMode::Ascii => core.format_ascii(context_lines),
-                    ErrorFormatMode::Fancy => {
-                        // Fallback to pest error display when extended-errors feature is disabled
-                        core.pest_error_display.clone()
-                    }
-                }
+        let core = self.core();
+        match mode {
+            ErrorFormatMode::Basic => core.format_basic(),
+            ErrorFormatMode::Ascii => core.format_ascii(context_lines),
+            ErrorFormatMode::Fancy => {
+                // Fallback to pest error display when extended-errors feature is disabled
+                core.pest_display()
             }
+            ErrorFormatMode::Json => core.format_json(),
         }
     }
 }
@@ -1,83 +1,171 @@
 //! Transform MutablePairs to decompose strings into delimiter + content + delimiter tokens
 //!
 //! This module provides in-place transformation of string MutablePairs into three separate tokens:
-//! 1. Opening delimiter (DOUBLE_QUOTE, SINGLE_QUOTE, or NEWLINE_SEMICOLON)
+//! 1. Opening delimiter (DOUBLE_QUOTE, SINGLE_QUOTE, TRIPLE_DOUBLE_QUOTE, TRIPLE_SINGLE_QUOTE
+//!    or NEWLINE_SEMICOLON)
 //! 2. Content (as "string" rule name)
-//! 3. Closing delimiter (DOUBLE_QUOTE, SINGLE_QUOTE, or NEWLINE_SEMICOLON)
+//! 3. Closing delimiter (matching the opening delimiter)
 //!
-//! All offsets are preserved from the original string.
+//! Strings inside CIF2 list and table values are decomposed in the same way.
+//!
+//! All offsets are preserved from the original string, and the new tokens get line and column
+//! positions derived from it.
+//!
+//! Inside a single or double quoted string a doubled quote, as in `'He said ''Hello'''`, stands
+//! for one quote. `decompose_strings_with` can store the content with the doubled quotes
+//! collapsed, the logical value of the string; its offsets still span the raw text.
 
 use crate::mutable_pair::MutablePair;
+use std::borrow::Cow;
 
 /// Decompose string MutablePairs in-place
 pub fn decompose_strings(pair: &mut MutablePair) {
+    decompose_strings_with(pair, false);
+}
+
+/// Decompose string MutablePairs in-place, with `unescape` the content of single and double
+/// quoted strings holds their logical value, see `unescape_quotes`
+pub fn decompose_strings_with(pair: &mut MutablePair, unescape: bool) {
     match pair.rule_name() {
-        "double_quote_string" => {
-            decompose_delimited_string(pair, &["\""], "DOUBLE_QUOTE");
-        }
-        "single_quote_string" => {
-            decompose_delimited_string(pair, &["'"], "SINGLE_QUOTE");
-        }
-        "semi_colon_string" => {
-            decompose_delimited_string(pair, &["\r\n;", "\n;"], "NEWLINE_SEMICOLON");
-        }
-        "non_quoted_string" => {
+        "non_quoted_string" | "container_non_quoted_string" => {
             // Convert non_quoted_string to string rule
-            pair.rule_name = "string".to_owned();
+            pair.rule_name = "string".into();
         }
-        _ => {
-            // Recursively process children
-            for child in &mut pair.children {
-                decompose_strings(child);
+        rule_name => match string_delimiters(rule_name) {
+            Some(_) => decompose_delimited_string(pair, unescape),
+            None => {
+                // Recursively process children
+                for child in &mut pair.children {
+                    decompose_strings_with(child, unescape);
+                }
             }
+        },
+    }
+}
+
+/// The text of a string with each doubled `quote` collapsed to one, `''` reads as `'` and
+/// `''''` as `''`; a quote on its own is kept
+pub fn unescape_quotes(text: &str, quote: char) -> Cow<'_, str> {
+    if !text.contains(&String::from_iter([quote, quote])) {
+        return Cow::Borrowed(text);
+    }
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        unescaped.push(c);
+        if c == quote && chars.peek() == Some(&quote) {
+            chars.next();
         }
     }
+    Cow::Owned(unescaped)
+}
+
+/// The logical value of a string token of rule `rule_name`: the text between its delimiters,
+/// unescaped for single and double quoted strings; `None` if the rule isn't a delimited string
+pub fn logical_string_value<'a>(rule_name: &str, content: &'a str) -> Option<Cow<'a, str>> {
+    split_string(rule_name, content, true).map(|(_, _, _, value)| value)
+}
+
+/// A delimited string split into its tokens: the rule name of its delimiters, the range of the
+/// text between them and the content, unescaped if asked for; `None` if `rule_name` isn't a
+/// delimited string or `text` isn't delimited by it (crate)
+pub(crate) fn split_string<'a>(
+    rule_name: &str,
+    text: &'a str,
+    unescape: bool,
+) -> Option<(&'static str, usize, usize, Cow<'a, str>)> {
+    let (delimiters, delimiter_name) = string_delimiters(rule_name)?;
+    let (start, end) = delimited_range(text, delimiters)?;
+    let inner = &text[start..end];
+    let content = match escaped_quote(rule_name).filter(|_| unescape) {
+        Some(quote) => unescape_quotes(inner, quote),
+        None => Cow::Borrowed(inner),
+    };
+    Some((delimiter_name, start, end, content))
+}
+
+/// The delimiters a string rule may be written with, tried in order, and the rule name of
+/// their tokens; `None` for rules that aren't delimited strings (private)
+fn string_delimiters(rule_name: &str) -> Option<(&'static [&'static str], &'static str)> {
+    match rule_name {
+        "double_quote_string" | "container_double_quote_string" => Some((&["\""], "DOUBLE_QUOTE")),
+        "single_quote_string" | "container_single_quote_string" => Some((&["'"], "SINGLE_QUOTE")),
+        "triple_double_quote_string" => Some((&["\"\"\""], "TRIPLE_DOUBLE_QUOTE")),
+        "triple_single_quote_string" => Some((&["'''"], "TRIPLE_SINGLE_QUOTE")),
+        "semi_colon_string" => Some((&["\r\n;", "\n;"], "NEWLINE_SEMICOLON")),
+        _ => None,
+    }
+}
+
+/// The quote a string rule escapes by doubling it; CIF 2.0 container strings can't hold their
+/// quote, and triple quoted strings and text fields have no escapes (private)
+fn escaped_quote(rule_name: &str) -> Option<char> {
+    match rule_name {
+        "single_quote_string" => Some('\''),
+        "double_quote_string" => Some('"'),
+        _ => None,
+    }
+}
+
+/// The range of `content` between the first of `delimiters` that opens and closes it, `None`
+/// if none does (private)
+fn delimited_range(content: &str, delimiters: &[&str]) -> Option<(usize, usize)> {
+    delimiters.iter().find_map(|delimiter| {
+        let delimiter_len = delimiter.len();
+        (content.len() >= 2 * delimiter_len
+            && content.starts_with(delimiter)
+            && content.ends_with(delimiter))
+        .then(|| (delimiter_len, content.len() - delimiter_len))
+    })
 }
 
 /// Decompose delimited string into [delimiter, string, delimiter]
 /// Works for single-char delimiters (quotes) and multi-char delimiters (newline-semicolon)
-/// Tries multiple possible delimiters in order
-fn decompose_delimited_string(pair: &mut MutablePair, delimiters: &[&str], delimiter_name: &str) {
+/// Tries multiple possible delimiters in order, with `unescape` the content is unescaped
+fn decompose_delimited_string(pair: &mut MutablePair, unescape: bool) {
     let content = &pair.content;
     let start_pos = pair.start;
 
-    // Try each delimiter in order
-    for delimiter in delimiters {
-        let delimiter_len = delimiter.len();
+    let Some((delimiter_name, delimiter_len, inner_end, unescaped)) =
+        split_string(pair.rule_name(), content, unescape)
+    else {
+        return;
+    };
 
-        if content.len() >= 2 * delimiter_len
-            && content.starts_with(delimiter)
-            && content.ends_with(delimiter)
-        {
-            let inner_content = &content[delimiter_len..content.len() - delimiter_len];
-
-            // Create three new children
-            let opening_delimiter = MutablePair::new(
-                delimiter_name,
-                delimiter.to_string(),
-                start_pos,
-                start_pos + delimiter_len,
-            );
+    // Create three new children, their content shares the text of the string
+    let opening_delimiter = MutablePair::new(
+        delimiter_name,
+        content.slice(0, delimiter_len),
+        start_pos,
+        start_pos + delimiter_len,
+    );
 
-            let string_content = MutablePair::new(
-                "string",
-                inner_content.to_string(),
-                start_pos + delimiter_len,
-                start_pos + delimiter_len + inner_content.len(),
-            );
+    // unescaped content that differs from the raw text needs a copy of its own
+    let inner = match unescaped {
+        Cow::Owned(unescaped) => unescaped.into(),
+        Cow::Borrowed(_) => content.slice(delimiter_len, inner_end),
+    };
+    let string_content = MutablePair::new(
+        "string",
+        inner,
+        start_pos + delimiter_len,
+        start_pos + inner_end,
+    );
 
-            let closing_delimiter = MutablePair::new(
-                delimiter_name,
-                delimiter.to_string(),
-                start_pos + delimiter_len + inner_content.len(),
-                start_pos + content.len(),
-            );
+    let closing_delimiter = MutablePair::new(
+        delimiter_name,
+        content.slice(inner_end, content.len()),
+        start_pos + inner_end,
+        start_pos + content.len(),
+    );
 
-            // Replace children with decomposed tokens
-            pair.children = vec![opening_delimiter, string_content, closing_delimiter];
-            return; // Found matching delimiter, stop trying
-        }
+    // Replace children with decomposed tokens
+    let mut children = vec![opening_delimiter, string_content, closing_delimiter];
+    for child in &mut children {
+        child.start_position = pair.line_column_at(child.start - start_pos);
+        child.end_position = pair.line_column_at(child.end - start_pos);
     }
+    pair.children = children;
 }
 
 #[cfg(test)]
@@ -127,6 +215,51 @@ mod tests {
         assert_eq!(format!("{}", pair), format!("{}", expected));
     }
 
+    #[test]
+    fn test_decompose_triple_quoted() {
+        let mut single = MutablePair::new(
+            "triple_single_quote_string",
+            "'''it's\nhere'''".to_string(),
+            0,
+            15,
+        );
+        let mut double = MutablePair::new(
+            "triple_double_quote_string",
+            "\"\"\"say \"hi\" now\"\"\"".to_string(),
+            20,
+            38,
+        );
+
+        decompose_strings(&mut single);
+        decompose_strings(&mut double);
+
+        let expected_single = MutablePair::with_children(
+            "triple_single_quote_string",
+            "'''it's\nhere'''".to_string(),
+            0,
+            15,
+            vec![
+                MutablePair::new("TRIPLE_SINGLE_QUOTE", "'''".to_string(), 0, 3),
+                MutablePair::new("string", "it's\nhere".to_string(), 3, 12),
+                MutablePair::new("TRIPLE_SINGLE_QUOTE", "'''".to_string(), 12, 15),
+            ],
+        );
+        let expected_double = MutablePair::with_children(
+            "triple_double_quote_string",
+            "\"\"\"say \"hi\" now\"\"\"".to_string(),
+            20,
+            38,
+            vec![
+                MutablePair::new("TRIPLE_DOUBLE_QUOTE", "\"\"\"".to_string(), 20, 23),
+                MutablePair::new("string", "say \"hi\" now".to_string(), 23, 35),
+                MutablePair::new("TRIPLE_DOUBLE_QUOTE", "\"\"\"".to_string(), 35, 38),
+            ],
+        );
+
+        assert_eq!(single, expected_single);
+        assert_eq!(double, expected_double);
+    }
+
     #[test]
     fn test_convert_non_quoted_to_string() {
         let mut pair = MutablePair::new("non_quoted_string", "simple".to_string(), 10, 16);
@@ -195,4 +328,87 @@ mod tests {
 
         assert_eq!(format!("{}", loop_pair), format!("{}", expected));
     }
+
+    #[test]
+    fn test_decompose_unescapes_doubled_quotes() {
+        let token = "'He said''Hello''to''me'";
+        let mut escaped = MutablePair::new("single_quote_string", token, 4, 28);
+        let mut unescaped = escaped.clone();
+        let mut double = MutablePair::new("double_quote_string", "\"x\"\"y\"", 0, 6);
+        let mut triple = MutablePair::new("triple_single_quote_string", "'''it''s'''", 0, 11);
+
+        decompose_strings(&mut escaped);
+        decompose_strings_with(&mut unescaped, true);
+        decompose_strings_with(&mut double, true);
+        decompose_strings_with(&mut triple, true);
+
+        // the content holds the logical value and still spans the raw text between the quotes
+        assert_eq!(
+            escaped.children[1],
+            MutablePair::new("string", "He said''Hello''to''me", 5, 27)
+        );
+        assert_eq!(
+            unescaped.children[1],
+            MutablePair::new("string", "He said'Hello'to'me", 5, 27)
+        );
+        assert_eq!(unescaped.children[0], escaped.children[0]);
+        assert_eq!(unescaped.children[2], escaped.children[2]);
+        assert_eq!(double.children[1], MutablePair::new("string", "x\"y", 1, 5));
+        // triple quoted strings have no escapes
+        assert_eq!(
+            triple.children[1],
+            MutablePair::new("string", "it''s", 3, 8)
+        );
+    }
+
+    #[test]
+    fn test_logical_value_of_pathological_quotes() {
+        // single quoted tokens from the quote tests in parser_tests and their logical values,
+        // each is checked double quoted too
+        let cases = [
+            ("''", ""),
+            ("'hello world'", "hello world"),
+            ("'test'a more text'", "test'a more text"),
+            ("'x'y'", "x'y"),
+            ("''x'", "'x"),
+            ("'a'b'c'd'", "a'b'c'd"),
+            ("'He said''Hello''to''me'", "He said'Hello'to'me"),
+            ("'test'abc'def'xyz'", "test'abc'def'xyz"),
+            ("'Hello world'''", "Hello world'"),
+            ("'text''more''data'''", "text'more'data'"),
+            ("'test'''''", "test''"),
+            ("'''Hello world'", "'Hello world"),
+            ("'''''data''more''text'", "''data'more'text"),
+            ("' {*}(Hb*/Hg*),Cb  '", " {*}(Hb*/Hg*),Cb  "),
+        ];
+
+        for (token, logical) in cases {
+            let single = MutablePair::new("single_quote_string", token, 0, token.len());
+            assert_eq!(single.logical_value(), logical, "{}", token);
+
+            let token = token.replace('\'', "\"");
+            let double = MutablePair::new("double_quote_string", token.clone(), 0, token.len());
+            assert_eq!(
+                double.logical_value(),
+                logical.replace('\'', "\""),
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn test_logical_value_of_other_nodes() {
+        let text_field = MutablePair::new("semi_colon_string", "\n;it''s\n;", 0, 9);
+        let triple = MutablePair::new("triple_double_quote_string", "\"\"\"a\"\"b\"\"\"", 0, 10);
+        let container = MutablePair::new("container_single_quote_string", "'a\"\"b'", 0, 6);
+        let unquoted = MutablePair::new("non_quoted_string", "it''s", 0, 5);
+        let content = MutablePair::new("string", "it''s", 1, 6);
+
+        assert_eq!(text_field.logical_value(), "it''s");
+        assert_eq!(triple.logical_value(), "a\"\"b");
+        assert_eq!(container.logical_value(), "a\"\"b");
+        assert_eq!(unquoted.logical_value(), "it''s");
+        assert_eq!(content.logical_value(), "it''s");
+    }
 }
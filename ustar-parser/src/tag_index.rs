@@ -0,0 +1,225 @@
+//! Tag index - constant time lookup of values by data block, save frame and data name.
+//!
+//! `TagIndex::build` walks a parsed tree once and records every data item and loop column
+//! under its block, save frame (`None` outside save frames) and data name, so interactive tools
+//! can answer "what is `_sample.shape` in block `synthesis`" without scanning the tree.
+//!
+//! STAR block codes, frame codes and data names are case-insensitive, which is the default;
+//! `CaseSensitivity::Sensitive` matches names exactly. Global blocks are indexed under the empty
+//! block name, as in `sas_handlers::Document`. When a name is defined twice in the same scope
+//! the first definition is indexed, `validate::validate_tree` reports the duplicate.
+
+use crate::loop_table::{LoopTable, LoopTableError};
+use crate::mutable_pair::MutablePair;
+use crate::values::value_text;
+use std::collections::HashMap;
+
+/// How block, frame and data names are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    /// Names match ignoring ASCII case, as STAR specifies
+    #[default]
+    Insensitive,
+    /// Names match exactly
+    Sensitive,
+}
+
+/// The value of an indexed data name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagValue<'i, 'a> {
+    /// A data item outside a loop, holding its value node
+    Item(&'a MutablePair),
+    /// A loop column, `tag` is in `table` or one of its nested sub-tables
+    Column {
+        table: &'i LoopTable<'a>,
+        tag: &'a str,
+    },
+}
+
+impl<'a> TagValue<'_, 'a> {
+    /// The values without quotes, one for an item and one per row for a column, with the rows
+    /// of a nested level in document order
+    pub fn values(&self) -> Vec<&'a str> {
+        match self {
+            TagValue::Item(value) => vec![value_text(*value)],
+            TagValue::Column { table, tag } => {
+                let mut values = Vec::new();
+                column_values(table, tag, &mut values);
+                values
+            }
+        }
+    }
+
+    /// The value of an item, `None` for a loop column
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            TagValue::Item(value) => Some(value_text(*value)),
+            TagValue::Column { .. } => None,
+        }
+    }
+}
+
+/// Add the values of `tag` in `table`, or in its nested sub-tables, to `values`
+fn column_values<'a>(table: &LoopTable<'a>, tag: &str, values: &mut Vec<&'a str>) {
+    if let Some(column) = table.column(tag) {
+        values.extend(column);
+        return;
+    }
+    for row in 0..table.row_count() {
+        if let Some(nested) = table.nested(row) {
+            column_values(nested, tag, values);
+        }
+    }
+}
+
+/// Where an indexed data name is defined
+#[derive(Debug, Clone, Copy)]
+enum Entry<'a> {
+    Item(&'a MutablePair),
+    /// A loop column, holding the index of its table
+    Column(usize),
+}
+
+/// The block and save frame of a data name, normalised for lookup
+type ScopeKey = (String, Option<String>);
+
+/// Hash maps from block, save frame and data name to the values of a parsed tree
+#[derive(Debug, Clone)]
+pub struct TagIndex<'a> {
+    case: CaseSensitivity,
+    blocks: Vec<&'a str>,
+    frames: HashMap<String, Vec<&'a str>>,
+    tags: HashMap<ScopeKey, Vec<&'a str>>,
+    entries: HashMap<(ScopeKey, String), (&'a str, Entry<'a>)>,
+    tables: Vec<LoopTable<'a>>,
+}
+
+impl<'a> TagIndex<'a> {
+    /// Index the blocks of `tree`, comparing names case-insensitively
+    ///
+    /// Fails if a loop is ragged, see `LoopTable::from_pair`.
+    pub fn build(tree: &'a MutablePair) -> Result<TagIndex<'a>, LoopTableError> {
+        TagIndex::build_with(tree, CaseSensitivity::default())
+    }
+
+    /// Index the blocks of `tree`, comparing names as `case` says
+    pub fn build_with(
+        tree: &'a MutablePair,
+        case: CaseSensitivity,
+    ) -> Result<TagIndex<'a>, LoopTableError> {
+        let mut index = TagIndex {
+            case,
+            blocks: Vec::new(),
+            frames: HashMap::new(),
+            tags: HashMap::new(),
+            entries: HashMap::new(),
+            tables: Vec::new(),
+        };
+
+        for block in tree.children() {
+            let name = match block.rule_name() {
+                "data_block" => &block.children()[0].as_str()["data_".len()..],
+                "global_block" => "",
+                _ => continue,
+            };
+            let block_key = index.key(name);
+            if !index.frames.contains_key(&block_key) {
+                index.blocks.push(name);
+                index.frames.insert(block_key.clone(), Vec::new());
+            }
+
+            for child in block.children() {
+                match child.rule_name() {
+                    "data" => index.add_data(child, (block_key.clone(), None))?,
+                    "save_frame" => {
+                        let frame = &child.children()[0].as_str()["save_".len()..];
+                        let scope = (block_key.clone(), Some(index.key(frame)));
+                        if !index.tags.contains_key(&scope) {
+                            index.frames.get_mut(&block_key).unwrap().push(frame);
+                            index.tags.insert(scope.clone(), Vec::new());
+                        }
+                        for data in child.children().iter().filter(|c| c.rule_name() == "data") {
+                            index.add_data(data, scope.clone())?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// The value of `tag` in the save frame `frame` of `block`, or outside save frames when
+    /// `frame` is `None`
+    pub fn get(&self, block: &str, frame: Option<&str>, tag: &str) -> Option<TagValue<'_, 'a>> {
+        let scope = (self.key(block), frame.map(|frame| self.key(frame)));
+        let (tag, entry) = self.entries.get(&(scope, self.key(tag)))?;
+        Some(match *entry {
+            Entry::Item(value) => TagValue::Item(value),
+            Entry::Column(table) => TagValue::Column {
+                table: &self.tables[table],
+                tag,
+            },
+        })
+    }
+
+    /// The names of the blocks in file order, the empty name for global blocks
+    pub fn blocks(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.blocks.iter().copied()
+    }
+
+    /// The names of the save frames of `block` in file order, none for an unknown block
+    pub fn frames(&self, block: &str) -> impl Iterator<Item = &'a str> + '_ {
+        self.frames
+            .get(&self.key(block))
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// The data names in the save frame `frame` of `block`, or outside save frames when `frame`
+    /// is `None`, in file order
+    pub fn tags(&self, block: &str, frame: Option<&str>) -> impl Iterator<Item = &'a str> + '_ {
+        let scope = (self.key(block), frame.map(|frame| self.key(frame)));
+        self.tags.get(&scope).into_iter().flatten().copied()
+    }
+
+    /// `name` as compared by the index
+    fn key(&self, name: &str) -> String {
+        match self.case {
+            CaseSensitivity::Insensitive => name.to_ascii_lowercase(),
+            CaseSensitivity::Sensitive => name.to_string(),
+        }
+    }
+
+    /// Index the data item or loop `data` in `scope`
+    fn add_data(&mut self, data: &'a MutablePair, scope: ScopeKey) -> Result<(), LoopTableError> {
+        if let [name, value] = data.children() {
+            if name.rule_name() == "data_name" {
+                self.add_tag(scope, name.as_str(), Entry::Item(value));
+                return Ok(());
+            }
+        }
+        let Some(definition) = data.find_first("data_loop_definition") else {
+            return Ok(());
+        };
+
+        self.tables.push(LoopTable::from_pair(data)?);
+        let table = self.tables.len() - 1;
+        for name in definition.find_all("data_name") {
+            self.add_tag(scope.clone(), name.as_str(), Entry::Column(table));
+        }
+        Ok(())
+    }
+
+    /// Record `tag` in `scope` unless it is already defined there
+    fn add_tag(&mut self, scope: ScopeKey, tag: &'a str, entry: Entry<'a>) {
+        let key = (scope, self.key(tag));
+        if self.entries.contains_key(&key) {
+            return;
+        }
+        self.tags.entry(key.0.clone()).or_default().push(tag);
+        self.entries.insert(key, (tag, entry));
+    }
+}
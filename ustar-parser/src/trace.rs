@@ -0,0 +1,70 @@
+//! Optional `tracing` instrumentation of parsing and walking.
+//!
+//! With the `trace` feature each stage of a parse is a `tracing` span at debug level, so a
+//! subscriber can show where the time goes without adding prints:
+//!
+//! ```text
+//! parse{bytes}
+//! ├── prelex{bytes, compacted}          with ConfigKey::Prelex
+//! ├── pest{encoding, bytes, compacted}
+//! ├── process_pairs{roots}
+//! │   └── block{rule, start, end, children}   one per top level data or global block
+//! └── decompose_strings{roots}          with ConfigKey::DecomposedStrings
+//! walk{bytes, stopped}
+//! └── pest{encoding, bytes}
+//! ```
+//!
+//! Without the feature the macros expand to nothing, so none of the fields are computed.
+
+/// Enter a span named `$name` with `tracing` fields, held by `$span` until the end of the scope
+#[cfg(feature = "trace")]
+macro_rules! trace_span {
+    ($span:ident, $($args:tt)*) => {
+        let $span = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+/// Enter a span named `$name` with `tracing` fields, held by `$span` until the end of the scope
+#[cfg(not(feature = "trace"))]
+macro_rules! trace_span {
+    ($span:ident, $($args:tt)*) => {};
+}
+
+/// Record `$value` for `$field`, declared `tracing::field::Empty`, of the entered `$span`
+#[cfg(feature = "trace")]
+macro_rules! trace_record {
+    ($span:ident, $field:literal, $value:expr) => {
+        $span.record($field, $value);
+    };
+}
+
+/// Record `$value` for `$field`, declared `tracing::field::Empty`, of the entered `$span`
+#[cfg(not(feature = "trace"))]
+macro_rules! trace_record {
+    ($span:ident, $field:literal, $value:expr) => {};
+}
+
+/// Enter a span for the node of `rule_name` starting at byte `start` if it is a data or global
+/// block, blocks are always at the top level of a tree
+#[cfg(feature = "trace")]
+pub(crate) fn enter_block(rule_name: &str, start: usize) -> Option<tracing::span::EnteredSpan> {
+    matches!(rule_name, "data_block" | "global_block").then(|| {
+        tracing::debug_span!(
+            "block",
+            rule = rule_name,
+            start,
+            end = tracing::field::Empty,
+            children = tracing::field::Empty
+        )
+        .entered()
+    })
+}
+
+/// Record the end and number of children of a block entered with `enter_block` and exit it
+#[cfg(feature = "trace")]
+pub(crate) fn exit_block(span: Option<tracing::span::EnteredSpan>, end: usize, children: usize) {
+    if let Some(span) = span {
+        span.record("end", end);
+        span.record("children", children);
+    }
+}
@@ -0,0 +1,190 @@
+//! Transform - rewriting the data names of a parsed tree.
+//!
+//! `rename_tags` renames data names as a mapping from old to new names says, such as the tag
+//! renames between two versions of a dictionary. Items and the data names of loop definitions,
+//! nested loops included, are renamed in place, and every other node is left as it is, so
+//! writing the tree again changes nothing but the renamed names.
+//!
+//! Renamed nodes hold their new name as their content but keep the offsets and positions of
+//! the old one, see `mutable_pair`. `RenameReport::apply_to` makes the same renames in the
+//! text the tree was parsed from, keeping its layout and comments byte for byte.
+
+use crate::line_column_index::LineColumn;
+use crate::mutable_pair::MutablePair;
+use crate::tag_index::CaseSensitivity;
+use std::collections::{BTreeSet, HashMap};
+
+/// How `rename_tags` matches and renames data names
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenameOptions {
+    /// How old names are compared with the data names of the tree, case-insensitively by
+    /// default as STAR specifies
+    pub case: CaseSensitivity,
+    /// Read mapping entries without a `.`, such as `_nef_old` to `_nef_new`, as renames of a
+    /// category: every `_nef_old.item` becomes `_nef_new.item` and `sf_category` values of
+    /// `nef_old` become `nef_new`
+    pub rename_categories: bool,
+}
+
+/// A data name or save frame category `rename_tags` renamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedRename {
+    /// The name as it was written
+    pub from: String,
+    pub to: String,
+    /// Where the name starts
+    pub position: LineColumn,
+    /// The byte offsets of the name in the input the tree was parsed from
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What `rename_tags` renamed, in document order, and the old names of the mapping it didn't
+/// find
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenameReport {
+    pub applied: Vec<AppliedRename>,
+    /// Sorted old names
+    pub unmatched: Vec<String>,
+}
+
+impl RenameReport {
+    /// The text `input`, which the renamed tree was parsed from, with the renamed names and
+    /// values replaced and everything else unchanged
+    pub fn apply_to(&self, input: &str) -> String {
+        let mut renamed = String::with_capacity(input.len());
+        let mut copied = 0;
+        for rename in &self.applied {
+            renamed.push_str(&input[copied..rename.start]);
+            renamed.push_str(&rename.to);
+            copied = rename.end;
+        }
+        renamed.push_str(&input[copied..]);
+        renamed
+    }
+}
+
+/// Rename the data names of `tree` that are keys of `mapping` to their values
+///
+/// With `RenameOptions::rename_categories` keys without a `.` rename categories.
+pub fn rename_tags(
+    tree: &mut MutablePair,
+    mapping: &HashMap<String, String>,
+    options: RenameOptions,
+) -> RenameReport {
+    let mut renamer = Renamer {
+        case: options.case,
+        tags: HashMap::new(),
+        categories: HashMap::new(),
+        report: RenameReport::default(),
+        matched: BTreeSet::new(),
+    };
+    for (from, to) in mapping {
+        let entry = (from.as_str(), to.as_str());
+        if options.rename_categories && !from.contains('.') {
+            renamer.categories.insert(renamer.key(from), entry);
+        } else {
+            renamer.tags.insert(renamer.key(from), entry);
+        }
+    }
+
+    renamer.rename(tree);
+    renamer.report.unmatched = mapping
+        .keys()
+        .filter(|from| !renamer.matched.contains(from.as_str()))
+        .cloned()
+        .collect();
+    renamer.report.unmatched.sort();
+    renamer.report
+}
+
+/// The state of a rename of one tree
+struct Renamer<'m> {
+    case: CaseSensitivity,
+    /// Old data names by lookup key, with the mapping's old and new names
+    tags: HashMap<String, (&'m str, &'m str)>,
+    /// Old categories, with their `_`, by lookup key
+    categories: HashMap<String, (&'m str, &'m str)>,
+    report: RenameReport,
+    /// The old names of the mapping that were found
+    matched: BTreeSet<&'m str>,
+}
+
+impl<'m> Renamer<'m> {
+    /// The key `name` is looked up by
+    fn key(&self, name: &str) -> String {
+        match self.case {
+            CaseSensitivity::Insensitive => name.to_ascii_lowercase(),
+            CaseSensitivity::Sensitive => name.to_string(),
+        }
+    }
+
+    fn rename(&mut self, node: &mut MutablePair) {
+        if node.rule_name() == "data_name" {
+            self.rename_tag(node);
+            return;
+        }
+        for child in node.children_mut() {
+            self.rename(child);
+        }
+        // after its data name, keeping the report in document order
+        if !self.categories.is_empty() && node.rule_name() == "data" {
+            self.rename_sf_category(node);
+        }
+    }
+
+    /// Rename the data name `name`, by its own entry or that of its category
+    fn rename_tag(&mut self, name: &mut MutablePair) {
+        let old = name.as_str();
+        let new = match self.tags.get(&self.key(old)) {
+            Some(&(from, to)) => {
+                self.matched.insert(from);
+                to.to_string()
+            }
+            None => {
+                let Some((category, item)) = old.split_once('.') else {
+                    return;
+                };
+                let Some(&(from, to)) = self.categories.get(&self.key(category)) else {
+                    return;
+                };
+                self.matched.insert(from);
+                format!("{}.{}", to, item)
+            }
+        };
+        self.replace(name, new);
+    }
+
+    /// Rename the value of the `sf_category` item `data` of a renamed category
+    fn rename_sf_category(&mut self, data: &mut MutablePair) {
+        let [name, value] = data.children_mut().as_mut_slice() else {
+            return;
+        };
+        let is_sf_category = name.rule_name() == "data_name"
+            && name
+                .as_str()
+                .split_once('.')
+                .is_some_and(|(_, item)| item.eq_ignore_ascii_case("sf_category"));
+        if !is_sf_category || value.has_children() {
+            return;
+        }
+        let category = format!("_{}", value.as_str());
+        if let Some(&(from, to)) = self.categories.get(&self.key(&category)) {
+            self.matched.insert(from);
+            let to = to.strip_prefix('_').unwrap_or(to).to_string();
+            self.replace(value, to);
+        }
+    }
+
+    /// Give `node` the content `new`, recording the rename
+    fn replace(&mut self, node: &mut MutablePair, new: String) {
+        self.report.applied.push(AppliedRename {
+            from: node.as_str().to_string(),
+            to: new.clone(),
+            position: node.start_position,
+            start: node.start,
+            end: node.end,
+        });
+        node.content = new.into();
+    }
+}
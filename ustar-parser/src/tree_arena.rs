@@ -0,0 +1,785 @@
+//! TreeArena - an arena-backed alternative to the owned MutablePair tree.
+//!
+//! All nodes live in a single `Vec<NodeData>` and are addressed by `NodeId`.
+//! Each node records its parent and its position among its siblings, so
+//! parent and sibling navigation is O(1) and whole subtrees can be moved by
+//! relinking a single node rather than copying.
+//!
+//! As in a `MutablePair` tree, the content of every node is a `SharedText` slice of one copy
+//! of the input and rule names are interned, so a node is a few words however deep it is.
+//!
+//! `PairRef` is a lightweight `(arena, NodeId)` handle that offers the same
+//! read accessors and searches as `MutablePair`, and the arena offers the same
+//! child edits. Both implement `PairNode`, so consumers such as the SAS walker
+//! work over either backend.
+
+use crate::line_column_index::{LineColumn, LineColumnIndex};
+use crate::mutable_pair::{DescendantNodes, MutablePair, NodeVisitor, PairNode};
+use crate::shared_text::{RuleNames, SharedText};
+use crate::string_decomposer::split_string;
+use crate::values::StarValue;
+use pest::RuleType;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Index of a node within a `TreeArena`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Get the raw index of this node in the arena
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// The data stored for each node in a `TreeArena`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeData {
+    /// The rule name, interned for grammar rules (allows synthetic rules)
+    pub rule_name: Cow<'static, str>,
+
+    /// The string content (token) for this node
+    pub content: SharedText,
+
+    /// Starting position in the original input
+    pub start: usize,
+
+    /// Ending position in the original input
+    pub end: usize,
+
+    /// Line and column of `start`, undefined unless the node was built by parsing
+    pub start_position: LineColumn,
+
+    /// Line and column of `end`, undefined unless the node was built by parsing
+    pub end_position: LineColumn,
+
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    sibling_index: Option<usize>, // Index among the parent's children or the roots, None if detached
+}
+
+impl NodeData {
+    /// Get the id of the parent node, if any
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// Get the ids of the child nodes
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Check if the node has been detached and is neither a child nor a root
+    pub fn is_detached(&self) -> bool {
+        self.sibling_index.is_none()
+    }
+}
+
+/// What building an arena from pest pairs reads from and how (private)
+struct PestSource<'s, R> {
+    source: &'s Arc<String>,
+    line_index: &'s LineColumnIndex<'s>,
+    rule_names: RuleNames<R>,
+    decompose: bool,
+    unescape: bool,
+}
+
+/// An arena holding one or more parse trees as flat node storage
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeArena {
+    nodes: Vec<NodeData>,
+    roots: Vec<NodeId>,
+}
+
+impl TreeArena {
+    /// Create an empty arena
+    pub fn new() -> Self {
+        TreeArena::default()
+    }
+
+    /// Build an arena from a MutablePair tree, consuming it so its content is moved, not copied
+    pub fn from_mutable_pair(pair: MutablePair) -> Self {
+        let mut arena = TreeArena::new();
+        arena.insert_mutable_pair(None, pair);
+        arena
+    }
+
+    /// Build an arena directly from a pest Pair, the content of its nodes sharing one copy of
+    /// the input
+    pub fn from_pest_pair<R: RuleType>(pair: &pest::iterators::Pair<R>) -> Self {
+        let source = Arc::new(pair.get_input().to_string());
+        let line_index = LineColumnIndex::new(pair.get_input());
+        TreeArena::from_pest_pair_with(pair, &source, &line_index, false, false)
+    }
+
+    /// Build an arena from a pest Pair of `source` with positions from `line_index` and, with
+    /// `decompose`, strings decomposed as `string_decomposer::decompose_strings_with` decomposes
+    /// them (crate)
+    pub(crate) fn from_pest_pair_with<R: RuleType>(
+        pair: &pest::iterators::Pair<R>,
+        source: &Arc<String>,
+        line_index: &LineColumnIndex,
+        decompose: bool,
+        unescape: bool,
+    ) -> Self {
+        let mut arena = TreeArena::new();
+        let mut from = PestSource {
+            source,
+            line_index,
+            rule_names: RuleNames::new(),
+            decompose,
+            unescape,
+        };
+        arena.insert_pest_pair(None, pair, &mut from);
+        arena
+    }
+
+    /// Append a new node under `parent`, or as a new root if `parent` is None
+    pub fn add_node(
+        &mut self,
+        parent: Option<NodeId>,
+        rule_name: impl Into<Cow<'static, str>>,
+        content: impl Into<SharedText>,
+        start: usize,
+        end: usize,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let siblings = match parent {
+            Some(parent_id) => &mut self.nodes[parent_id.0].children,
+            None => &mut self.roots,
+        };
+        let sibling_index = siblings.len();
+        siblings.push(id);
+
+        self.nodes.push(NodeData {
+            rule_name: rule_name.into(),
+            content: content.into(),
+            start,
+            end,
+            start_position: LineColumn::undefined(),
+            end_position: LineColumn::undefined(),
+            parent,
+            children: Vec::new(),
+            sibling_index: Some(sibling_index),
+        });
+        id
+    }
+
+    fn insert_mutable_pair(&mut self, parent: Option<NodeId>, pair: MutablePair) -> NodeId {
+        let id = self.add_node(parent, pair.rule_name, pair.content, pair.start, pair.end);
+        let node = &mut self.nodes[id.0];
+        node.start_position = pair.start_position;
+        node.end_position = pair.end_position;
+        node.children.reserve(pair.children.len());
+        for child in pair.children {
+            self.insert_mutable_pair(Some(id), child);
+        }
+        id
+    }
+
+    fn insert_pest_pair<R: RuleType>(
+        &mut self,
+        parent: Option<NodeId>,
+        pair: &pest::iterators::Pair<R>,
+        from: &mut PestSource<'_, R>,
+    ) -> NodeId {
+        let span = pair.as_span();
+        let rule_name = match from.rule_names.get(pair.as_rule()) {
+            "non_quoted_string" | "container_non_quoted_string" if from.decompose => "string",
+            name => name,
+        };
+        let id = self.add_positioned_node(parent, rule_name, span.start(), span.end(), from);
+
+        let split = from
+            .decompose
+            .then(|| split_string(rule_name, pair.as_str(), from.unescape))
+            .flatten();
+        match split {
+            // a string is replaced by its delimiters and content, as in a decomposed MutablePair
+            Some((delimiter_name, inner_start, inner_end, content)) => {
+                let start = span.start();
+                let end = span.end();
+                let inner = (start + inner_start, start + inner_end);
+                self.add_positioned_node(Some(id), delimiter_name, start, inner.0, from);
+                let string = self.add_positioned_node(Some(id), "string", inner.0, inner.1, from);
+                // unescaped content that differs from the raw text needs a copy of its own
+                if let Cow::Owned(unescaped) = content {
+                    self.nodes[string.0].content = unescaped.into();
+                }
+                self.add_positioned_node(Some(id), delimiter_name, inner.1, end, from);
+            }
+            None => {
+                for child in pair.clone().into_inner() {
+                    self.insert_pest_pair(Some(id), &child, from);
+                }
+            }
+        }
+        id
+    }
+
+    /// Append a node for `start..end` of the pest input as `add_node` does, its content sharing
+    /// the source and its positions looked up in the line index (private)
+    fn add_positioned_node<R>(
+        &mut self,
+        parent: Option<NodeId>,
+        rule_name: &'static str,
+        start: usize,
+        end: usize,
+        from: &PestSource<'_, R>,
+    ) -> NodeId {
+        let content = SharedText::new(from.source.clone(), start, end);
+        let id = self.add_node(parent, rule_name, content, start, end);
+        let node = &mut self.nodes[id.0];
+        node.start_position = from.line_index.offset_to_line_col(start);
+        node.end_position = from.line_index.offset_to_line_col(end);
+        id
+    }
+
+    /// Number of nodes stored in the arena (including detached nodes)
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the arena holds no nodes
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Get the ids of the root nodes
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Get a handle to the first root node, if any
+    pub fn root(&self) -> Option<PairRef<'_>> {
+        self.roots.first().map(|&id| self.get(id))
+    }
+
+    /// Get a handle to a node
+    pub fn get(&self, id: NodeId) -> PairRef<'_> {
+        PairRef { arena: self, id }
+    }
+
+    /// Get the data for a node
+    pub fn node(&self, id: NodeId) -> &NodeData {
+        &self.nodes[id.0]
+    }
+
+    /// Get mutable data for a node; tree links can only be changed through the arena
+    pub fn node_mut(&mut self, id: NodeId) -> &mut NodeData {
+        &mut self.nodes[id.0]
+    }
+
+    /// Check if `ancestor` is `id` or one of its ancestors
+    pub fn is_ancestor_of(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node_id) = current {
+            if node_id == ancestor {
+                return true;
+            }
+            current = self.nodes[node_id.0].parent;
+        }
+        false
+    }
+
+    /// Unlink a node (and its subtree) from its parent or from the root list.
+    /// The node stays in the arena and can be reattached with `move_subtree`.
+    pub fn detach(&mut self, id: NodeId) {
+        let Some(sibling_index) = self.nodes[id.0].sibling_index.take() else {
+            // already detached
+            return;
+        };
+        let siblings = match self.nodes[id.0].parent.take() {
+            Some(parent_id) => &mut self.nodes[parent_id.0].children,
+            None => &mut self.roots,
+        };
+        siblings.remove(sibling_index);
+        let moved: Vec<NodeId> = siblings[sibling_index..].to_vec();
+        for (offset, sibling) in moved.into_iter().enumerate() {
+            self.nodes[sibling.0].sibling_index = Some(sibling_index + offset);
+        }
+    }
+
+    /// Move a node and its subtree so it becomes child `index` of `new_parent`.
+    /// Positions and content of the old and new ancestors are not updated.
+    ///
+    /// # Panics
+    /// Panics if `new_parent` is inside the subtree being moved or `index` is
+    /// greater than the number of children of `new_parent`.
+    pub fn move_subtree(&mut self, id: NodeId, new_parent: NodeId, index: usize) {
+        assert!(
+            !self.is_ancestor_of(id, new_parent),
+            "cannot move a node into its own subtree"
+        );
+        self.detach(id);
+
+        let siblings = &mut self.nodes[new_parent.0].children;
+        siblings.insert(index, id);
+        let moved: Vec<NodeId> = siblings[index..].to_vec();
+        for (offset, sibling) in moved.into_iter().enumerate() {
+            self.nodes[sibling.0].sibling_index = Some(index + offset);
+        }
+        self.nodes[id.0].parent = Some(new_parent);
+    }
+
+    /// Append `child` and its subtree as the last child of `parent`, detaching it first
+    pub fn push_child(&mut self, parent: NodeId, child: NodeId) {
+        self.detach(child);
+        let index = self.nodes[parent.0].children.len();
+        self.move_subtree(child, parent, index);
+    }
+
+    /// Insert `child` and its subtree as child `index` of `parent`, detaching it first and
+    /// shifting later children right; panics if `index` is greater than the number of children
+    pub fn insert_child(&mut self, parent: NodeId, index: usize, child: NodeId) {
+        self.move_subtree(child, parent, index);
+    }
+
+    /// Detach and return the child at `index`, shifting later children left; panics if out of
+    /// bounds
+    pub fn remove_child(&mut self, parent: NodeId, index: usize) -> NodeId {
+        let child = self.nodes[parent.0].children[index];
+        self.detach(child);
+        child
+    }
+
+    /// Replace the child at `index` with `child`, returning the detached old child; panics if
+    /// out of bounds
+    pub fn replace_child(&mut self, parent: NodeId, index: usize, child: NodeId) -> NodeId {
+        let old = self.nodes[parent.0].children[index];
+        if old != child {
+            // detaching `child` first may shift `old` if they are siblings
+            self.detach(child);
+            let index = self.nodes[old.0].sibling_index.unwrap_or(index);
+            self.detach(old);
+            self.move_subtree(child, parent, index);
+        }
+        old
+    }
+
+    /// Keep only the children of `parent` that match the predicate, detaching the others
+    pub fn retain_children<F>(&mut self, parent: NodeId, mut predicate: F)
+    where
+        F: FnMut(PairRef) -> bool,
+    {
+        let removed: Vec<NodeId> = self.nodes[parent.0]
+            .children
+            .iter()
+            .copied()
+            .filter(|&id| !predicate(self.get(id)))
+            .collect();
+        for id in removed {
+            self.detach(id);
+        }
+    }
+
+    /// Convert a node and its subtree back into an owned MutablePair tree
+    pub fn to_mutable_pair(&self, id: NodeId) -> MutablePair {
+        let node = &self.nodes[id.0];
+        let mut pair = MutablePair::with_children(
+            node.rule_name.clone(),
+            node.content.clone(),
+            node.start,
+            node.end,
+            node.children
+                .iter()
+                .map(|&child| self.to_mutable_pair(child))
+                .collect(),
+        );
+        pair.start_position = node.start_position;
+        pair.end_position = node.end_position;
+        pair
+    }
+}
+
+/// A borrowed handle to a node in a `TreeArena`, mirroring the MutablePair accessors
+#[derive(Debug, Clone, Copy)]
+pub struct PairRef<'a> {
+    arena: &'a TreeArena,
+    id: NodeId,
+}
+
+impl<'a> PairRef<'a> {
+    fn data(self) -> &'a NodeData {
+        &self.arena.nodes[self.id.0]
+    }
+
+    /// Get the id of this node
+    pub fn id(self) -> NodeId {
+        self.id
+    }
+
+    /// Get the arena this node belongs to
+    pub fn arena(self) -> &'a TreeArena {
+        self.arena
+    }
+
+    /// Get the rule name
+    pub fn rule_name(self) -> &'a str {
+        &self.data().rule_name
+    }
+
+    /// Get the content as a string slice
+    pub fn as_str(self) -> &'a str {
+        &self.data().content
+    }
+
+    /// Get the start position
+    pub fn start_pos(self) -> usize {
+        self.data().start
+    }
+
+    /// Get the end position
+    pub fn end_pos(self) -> usize {
+        self.data().end
+    }
+
+    /// Get the line and column of the start position
+    pub fn start_line_column(self) -> LineColumn {
+        self.data().start_position
+    }
+
+    /// Get the line and column of the end position
+    pub fn end_line_column(self) -> LineColumn {
+        self.data().end_position
+    }
+
+    /// Check if this node has children
+    pub fn has_children(self) -> bool {
+        !self.data().children.is_empty()
+    }
+
+    /// Get an iterator over the children
+    pub fn children(self) -> impl ExactSizeIterator<Item = PairRef<'a>> {
+        let arena = self.arena;
+        self.data()
+            .children
+            .iter()
+            .map(move |&id| PairRef { arena, id })
+    }
+
+    /// Get the parent node, if this is not a root or detached
+    pub fn parent(self) -> Option<PairRef<'a>> {
+        self.data().parent.map(|id| self.arena.get(id))
+    }
+
+    /// Get the following sibling, if any; a detached node has none
+    pub fn next_sibling(self) -> Option<PairRef<'a>> {
+        self.sibling_at(self.data().sibling_index? + 1)
+    }
+
+    /// Get the preceding sibling, if any; a detached node has none
+    pub fn prev_sibling(self) -> Option<PairRef<'a>> {
+        let index = self.data().sibling_index?.checked_sub(1)?;
+        self.sibling_at(index)
+    }
+
+    fn sibling_at(self, index: usize) -> Option<PairRef<'a>> {
+        let siblings = match self.data().parent {
+            Some(parent_id) => &self.arena.nodes[parent_id.0].children,
+            None => &self.arena.roots,
+        };
+        siblings.get(index).map(|&id| self.arena.get(id))
+    }
+
+    /// The span of the input covered by the current children, or `None` without children
+    pub fn children_span(self) -> Option<(usize, usize)> {
+        PairNode::children_span(self)
+    }
+
+    /// Traverse this node and its descendants depth-first, calling the visitor's `enter` before
+    /// and `exit` after each node's children
+    ///
+    /// Returns true if the visitor stopped the traversal.
+    pub fn accept(self, visitor: &mut impl NodeVisitor<'a, PairRef<'a>>) -> bool {
+        self.accept_node(visitor)
+    }
+
+    /// Iterate over all descendants depth-first, parents before their children (self excluded)
+    pub fn iter_descendants(self) -> DescendantNodes<'a, PairRef<'a>> {
+        self.descendant_nodes()
+    }
+
+    /// Find the first descendant, depth-first, with the given rule name
+    pub fn find_first(self, rule_name: &str) -> Option<PairRef<'a>> {
+        self.find_first_node(rule_name)
+    }
+
+    /// Find all descendants with the given rule name, in depth-first order
+    pub fn find_all(self, rule_name: &str) -> Vec<PairRef<'a>> {
+        self.find_all_nodes(rule_name)
+    }
+
+    /// Find the first descendant, depth-first, that matches the predicate
+    pub fn find_where<F>(self, predicate: F) -> Option<PairRef<'a>>
+    where
+        F: FnMut(PairRef<'a>) -> bool,
+    {
+        self.find_node_where(predicate)
+    }
+
+    /// The typed values of a data name in this subtree, in document order, as
+    /// `MutablePair::typed_values` gives them
+    pub fn typed_values(self, data_name: &str) -> Vec<StarValue> {
+        self.typed_node_values(data_name)
+    }
+
+    /// Convert this node and its subtree into an owned MutablePair tree
+    pub fn to_mutable_pair(self) -> MutablePair {
+        self.arena.to_mutable_pair(self.id)
+    }
+}
+
+impl<'a> PairNode<'a> for PairRef<'a> {
+    fn rule_name(self) -> &'a str {
+        PairRef::rule_name(self)
+    }
+
+    fn as_str(self) -> &'a str {
+        PairRef::as_str(self)
+    }
+
+    fn start_pos(self) -> usize {
+        PairRef::start_pos(self)
+    }
+
+    fn end_pos(self) -> usize {
+        PairRef::end_pos(self)
+    }
+
+    fn start_line_column(self) -> LineColumn {
+        PairRef::start_line_column(self)
+    }
+
+    fn end_line_column(self) -> LineColumn {
+        PairRef::end_line_column(self)
+    }
+
+    fn child_count(self) -> usize {
+        self.data().children.len()
+    }
+
+    fn child(self, index: usize) -> Option<Self> {
+        self.data()
+            .children
+            .get(index)
+            .map(|&id| self.arena.get(id))
+    }
+}
+
+impl std::fmt::Display for PairRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data = self.data();
+        write!(
+            f,
+            "{}({}..{}, {:?})",
+            data.rule_name, data.start, data.end, data.content
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> MutablePair {
+        MutablePair::with_children(
+            "data",
+            "_tag a b",
+            0,
+            8,
+            vec![
+                MutablePair::new("data_name", "_tag", 0, 4),
+                MutablePair::new("string", "a", 5, 6),
+                MutablePair::new("string", "b", 7, 8),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let arena = TreeArena::from_mutable_pair(sample_tree());
+
+        assert_eq!(arena.len(), 4);
+        assert_eq!(arena.root().unwrap().to_mutable_pair(), sample_tree());
+    }
+
+    #[test]
+    fn test_navigation() {
+        let arena = TreeArena::from_mutable_pair(sample_tree());
+        let root = arena.root().unwrap();
+        let children: Vec<PairRef> = root.children().collect();
+
+        assert_eq!(children[1].parent().unwrap().id(), root.id());
+        assert_eq!(children[1].next_sibling().unwrap().as_str(), "b");
+        assert_eq!(children[1].prev_sibling().unwrap().as_str(), "_tag");
+        assert!(children[0].prev_sibling().is_none());
+        assert!(children[2].next_sibling().is_none());
+        assert!(root.parent().is_none());
+    }
+
+    #[test]
+    fn test_move_subtree() {
+        let mut arena = TreeArena::from_mutable_pair(sample_tree());
+        let root = arena.roots()[0];
+        let last = arena.node(root).children()[2];
+
+        arena.move_subtree(last, root, 1);
+
+        let root_ref = arena.get(root);
+        let contents: Vec<&str> = root_ref.children().map(|child| child.as_str()).collect();
+        assert_eq!(contents, vec!["_tag", "b", "a"]);
+        assert_eq!(arena.get(last).prev_sibling().unwrap().as_str(), "_tag");
+        assert_eq!(arena.get(last).next_sibling().unwrap().as_str(), "a");
+    }
+
+    #[test]
+    fn test_detached_node_has_no_siblings() {
+        let mut arena = TreeArena::from_mutable_pair(sample_tree());
+        arena.add_node(None, "data", "_other c", 9, 17);
+        let root = arena.roots()[0];
+        let middle = arena.node(root).children()[1];
+        let last = arena.node(root).children()[2];
+
+        arena.detach(middle);
+
+        let detached = arena.get(middle);
+        assert!(arena.node(middle).is_detached());
+        assert!(detached.parent().is_none());
+        assert!(detached.next_sibling().is_none());
+        assert!(detached.prev_sibling().is_none());
+        // the siblings left behind close the gap, and the roots are untouched
+        assert_eq!(arena.get(last).prev_sibling().unwrap().as_str(), "_tag");
+        assert_eq!(arena.get(root).next_sibling().unwrap().as_str(), "_other c");
+
+        // a detached root has no siblings either, and reattaching restores them
+        let other = arena.roots()[1];
+        arena.detach(other);
+        assert!(arena.get(other).prev_sibling().is_none());
+        assert!(arena.get(root).next_sibling().is_none());
+        arena.move_subtree(middle, root, 0);
+        assert!(!arena.node(middle).is_detached());
+        assert_eq!(arena.get(middle).next_sibling().unwrap().as_str(), "_tag");
+    }
+
+    #[test]
+    fn test_pest_nodes_share_the_input() {
+        use crate::parsers::ascii::{AsciiParser, Rule};
+        use pest::Parser;
+
+        let input = "data_a\n_tag  'x y'\nloop_ _b 1 2\n";
+        let pair = AsciiParser::parse(Rule::star_file, input)
+            .unwrap()
+            .next()
+            .unwrap();
+        let arena = TreeArena::from_pest_pair(&pair);
+
+        let root = &arena.node(arena.roots()[0]).content;
+        assert_eq!(root.as_str(), input);
+        assert!((0..arena.len()).all(|index| arena.node(NodeId(index)).content.shares_source(root)));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot move a node into its own subtree")]
+    fn test_move_into_own_subtree() {
+        let mut arena = TreeArena::from_mutable_pair(sample_tree());
+        let root = arena.roots()[0];
+        let child = arena.node(root).children()[0];
+
+        arena.move_subtree(root, child, 0);
+    }
+
+    #[test]
+    fn test_searches_match_mutable_pair() {
+        let pair = crate::parse_default("data_x _a 1 loop_ _b 2 3 stop_\n").unwrap();
+        let arena = TreeArena::from_mutable_pair(pair.clone());
+        let root = arena.root().unwrap();
+
+        let rules = |nodes: Vec<PairRef>| -> Vec<String> {
+            nodes.iter().map(|node| node.to_string()).collect()
+        };
+        let pair_rules = |pairs: Vec<&MutablePair>| -> Vec<String> {
+            pairs.iter().map(|pair| pair.to_string()).collect()
+        };
+        assert_eq!(
+            rules(root.iter_descendants().collect()),
+            pair_rules(pair.iter_descendants().collect())
+        );
+        assert_eq!(
+            rules(root.find_all("data")),
+            pair_rules(pair.find_all("data"))
+        );
+        assert_eq!(
+            root.find_where(|node| node.as_str() == "3")
+                .unwrap()
+                .to_string(),
+            pair.find_where(|pair| pair.as_str() == "3")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(root.typed_values("_b"), pair.typed_values("_b"));
+        assert_eq!(root.children_span(), pair.children_span());
+    }
+
+    #[test]
+    fn test_accept_stops_and_skips() {
+        use crate::mutable_pair::VisitControl;
+
+        struct Names(Vec<String>);
+        impl<'a> NodeVisitor<'a, PairRef<'a>> for Names {
+            fn enter(&mut self, node: PairRef<'a>) -> VisitControl {
+                self.0.push(node.as_str().to_string());
+                match node.as_str() {
+                    "a" => VisitControl::Stop,
+                    _ => VisitControl::SkipChildren,
+                }
+            }
+        }
+
+        let arena = TreeArena::from_mutable_pair(sample_tree());
+        let mut names = Names(Vec::new());
+        assert!(!arena.root().unwrap().accept(&mut names));
+        assert_eq!(names.0, ["_tag a b"]);
+
+        let child = arena.root().unwrap().children().nth(1).unwrap();
+        let mut names = Names(Vec::new());
+        assert!(child.accept(&mut names));
+    }
+
+    #[test]
+    fn test_child_edits_match_mutable_pair() {
+        let mut pair = sample_tree();
+        let mut arena = TreeArena::from_mutable_pair(sample_tree());
+        let root = arena.roots()[0];
+        let extra = arena.add_node(None, "string", "c", 9, 10);
+        arena.detach(extra);
+
+        let removed = pair.remove_child(0);
+        let removed_id = arena.remove_child(root, 0);
+        assert_eq!(arena.to_mutable_pair(removed_id), removed);
+        assert!(arena.node(removed_id).is_detached());
+
+        pair.push_child(removed);
+        arena.push_child(root, removed_id);
+        assert_eq!(arena.to_mutable_pair(root), pair);
+
+        // replacing with a node that is already an earlier sibling moves it
+        let moved = pair.children()[0].clone();
+        let old = pair.replace_child(2, moved);
+        pair.remove_child(0);
+        let first = arena.node(root).children()[0];
+        let old_id = arena.replace_child(root, 2, first);
+        assert_eq!(arena.to_mutable_pair(old_id), old);
+        assert_eq!(arena.to_mutable_pair(root), pair);
+
+        pair.insert_child(1, MutablePair::new("string", "c", 9, 10));
+        arena.insert_child(root, 1, extra);
+        assert_eq!(arena.to_mutable_pair(root), pair);
+
+        pair.retain_children(|child| child.as_str() != "c");
+        arena.retain_children(root, |child| child.as_str() != "c");
+        assert_eq!(arena.to_mutable_pair(root), pair);
+        assert!(arena.node(extra).is_detached());
+    }
+}
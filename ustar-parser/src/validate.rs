@@ -0,0 +1,473 @@
+//! Validation - restrictions checked on a parsed tree.
+//!
+//! Some dialects are stricter than the grammar: NEF and NMR-STAR keep all data in save frames,
+//! CIF limits the length of lines and names, and strict mode keeps quotes and other delimiters
+//! out of block and frame codes. These rules are checked after parsing so that one
+//! grammar serves every dialect, and the first violation in the input is reported as an error
+//! at its position.
+//!
+//! STAR semantics the grammar can't express are checked by `validate_tree`: data names must be
+//! unique within a data block or save frame, save frame names unique within a data block and
+//! frame codes must name a save frame of their data block.
+
+use crate::config::{
+    get_allow_data_outside_saveframes, get_allow_empty_loops, get_max_line_length,
+    get_max_name_length, get_require_stop_keyword, get_strict,
+};
+use crate::line_column_index::LineColumn;
+use crate::mutable_pair::{MutablePair, VisitControl, Visitor};
+use crate::parsers::ascii::Rule;
+use crate::{EncodingMode, ErrorCode, ParserConfig, UstarError};
+use pest::error::{Error, ErrorVariant};
+use pest::Position;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The kinds of semantic problem found by `validate_tree` and `dictionary::validate_against`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    /// A data name defined twice in the same data block or save frame
+    DuplicateTag,
+    /// Two save frames with the same name in one data block
+    DuplicateSaveframe,
+    /// A frame code `$name` with no save frame `save_name` in its data block
+    DanglingFrameCode,
+    /// A data name the dictionary doesn't define
+    UnknownTag,
+    /// A value that doesn't match the type of its item in the dictionary
+    TypeMismatch,
+    /// A value that isn't one of the enumerated values of its item in the dictionary
+    EnumerationViolation,
+    /// A mandatory item of a category missing from a data block or save frame using the category
+    MissingMandatoryItem,
+}
+
+/// A semantic problem in a parsed tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// What is wrong
+    pub kind: ValidationIssueKind,
+    /// The offending data name, save frame name or frame code as written at `position`
+    pub name: String,
+    /// Where the offending occurrence starts
+    pub position: LineColumn,
+    /// Where the first occurrence starts for duplicates, `None` for other issues
+    pub first_position: Option<LineColumn>,
+    /// What was expected and found for a value checked by `dictionary::validate_against`, or
+    /// the category of a missing mandatory item
+    pub detail: Option<String>,
+}
+
+impl ValidationIssueKind {
+    /// The code of the error reported for the issue, as `parse` does with `ConfigKey::Validate` set
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ValidationIssueKind::DuplicateTag => ErrorCode::E0016DuplicateDataName,
+            ValidationIssueKind::DuplicateSaveframe => ErrorCode::E0017DuplicateSaveFrame,
+            ValidationIssueKind::DanglingFrameCode => ErrorCode::E0018DanglingFrameCode,
+            ValidationIssueKind::UnknownTag => ErrorCode::E0023UnknownDataName,
+            ValidationIssueKind::TypeMismatch => ErrorCode::E0024TypeMismatch,
+            ValidationIssueKind::EnumerationViolation => ErrorCode::E0025EnumerationViolation,
+            ValidationIssueKind::MissingMandatoryItem => ErrorCode::E0026MissingMandatoryItem,
+        }
+    }
+}
+
+impl ValidationIssue {
+    /// Describe the issue without its position
+    pub fn message(&self) -> String {
+        let detail = self.detail.as_deref().unwrap_or_default();
+        let first = self
+            .first_position
+            .map(|first| format!(", first defined at l{}:c{}", first.line, first.column))
+            .unwrap_or_default();
+        match self.kind {
+            ValidationIssueKind::DuplicateTag => {
+                format!("duplicate data name {}{}", self.name, first)
+            }
+            ValidationIssueKind::DuplicateSaveframe => {
+                format!("duplicate save frame name {}{}", self.name, first)
+            }
+            ValidationIssueKind::DanglingFrameCode => format!(
+                "frame code {} does not name a save frame in its data block",
+                self.name
+            ),
+            ValidationIssueKind::UnknownTag => {
+                format!("data name {} is not defined in the dictionary", self.name)
+            }
+            ValidationIssueKind::TypeMismatch => {
+                format!("{} has a value of the wrong type, {}", self.name, detail)
+            }
+            ValidationIssueKind::EnumerationViolation => {
+                format!(
+                    "{} has a value outside its enumeration, {}",
+                    self.name, detail
+                )
+            }
+            ValidationIssueKind::MissingMandatoryItem => format!(
+                "mandatory item {} of category {} is missing",
+                self.name, detail
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "l{}:c{} {}",
+            self.position.line,
+            self.position.column,
+            self.message()
+        )
+    }
+}
+
+/// Check the STAR semantics of a parsed tree, or of a single data or global block
+///
+/// Data names and save frame names are compared case-insensitively. A data name may appear once
+/// in a data block and again in each of its save frames, which are separate scopes.
+///
+/// # Returns
+/// * `Vec<ValidationIssue>` - Every issue found, in the order of their positions in the input
+pub fn validate_tree(tree: &MutablePair) -> Vec<ValidationIssue> {
+    find_issues(tree)
+        .into_iter()
+        .map(|(_, issue)| issue)
+        .collect()
+}
+
+/// Check the semantics of the tree parsed from `input`, reporting the first issue as an error
+pub(crate) fn check_semantics(
+    tree: &MutablePair,
+    input: &str,
+    encoding: EncodingMode,
+) -> Result<(), Box<UstarError>> {
+    match find_issues(tree).into_iter().next() {
+        None => Ok(()),
+        Some((offset, issue)) => Err(error_at(
+            input,
+            offset,
+            issue.kind.code(),
+            issue.message(),
+            encoding,
+        )),
+    }
+}
+
+/// The issues in `tree` with the byte offsets where they start, sorted by offset
+fn find_issues(tree: &MutablePair) -> Vec<(usize, ValidationIssue)> {
+    let mut issues = Vec::new();
+    let is_block = |pair: &&MutablePair| matches!(pair.rule_name(), "data_block" | "global_block");
+    // blocks don't nest so every block is checked once
+    for block in std::iter::once(tree)
+        .chain(tree.iter_descendants())
+        .filter(is_block)
+    {
+        check_block(block, &mut issues);
+    }
+    issues.sort_by_key(|(offset, _)| *offset);
+    issues
+}
+
+/// First occurrences of names in one scope, keyed by their lower case form
+type Scope<'a> = HashMap<String, &'a MutablePair>;
+
+/// Find the duplicate and dangling names of one data or global block
+fn check_block(block: &MutablePair, issues: &mut Vec<(usize, ValidationIssue)>) {
+    let mut block_tags = Scope::new();
+    let mut frames = Scope::new();
+
+    for child in block.children() {
+        match child.rule_name() {
+            "data" => check_tags(child, &mut block_tags, issues),
+            "save_frame" => {
+                let Some(heading) = child.find_first("save_heading") else {
+                    continue;
+                };
+                let name = &heading.as_str()["save_".len()..];
+                if let Some(duplicate) = first_occurrence(&mut frames, name, heading) {
+                    issues.push(issue(
+                        ValidationIssueKind::DuplicateSaveframe,
+                        name,
+                        heading,
+                        Some(duplicate),
+                    ));
+                }
+
+                let mut frame_tags = Scope::new();
+                for data in child.children().iter().filter(|c| c.rule_name() == "data") {
+                    check_tags(data, &mut frame_tags, issues);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for frame_code in block.find_all("frame_code") {
+        let name = &frame_code.as_str()["$".len()..];
+        if !frames.contains_key(&name.to_lowercase()) {
+            issues.push(issue(
+                ValidationIssueKind::DanglingFrameCode,
+                frame_code.as_str(),
+                frame_code,
+                None,
+            ));
+        }
+    }
+}
+
+/// Record the data names of a data item or loop in `scope`, reporting any seen before
+fn check_tags<'a>(
+    data: &'a MutablePair,
+    scope: &mut Scope<'a>,
+    issues: &mut Vec<(usize, ValidationIssue)>,
+) {
+    for data_name in data.find_all("data_name") {
+        if let Some(duplicate) = first_occurrence(scope, data_name.as_str(), data_name) {
+            issues.push(issue(
+                ValidationIssueKind::DuplicateTag,
+                data_name.as_str(),
+                data_name,
+                Some(duplicate),
+            ));
+        }
+    }
+}
+
+/// Record `pair` as the first occurrence of `name`, or return the first occurrence if there was one
+fn first_occurrence<'a>(
+    scope: &mut Scope<'a>,
+    name: &str,
+    pair: &'a MutablePair,
+) -> Option<&'a MutablePair> {
+    match scope.entry(name.to_lowercase()) {
+        Entry::Occupied(entry) => Some(*entry.get()),
+        Entry::Vacant(entry) => {
+            entry.insert(pair);
+            None
+        }
+    }
+}
+
+/// An issue of `kind` for `name` at `pair`, keyed by the byte offset of `pair`
+fn issue(
+    kind: ValidationIssueKind,
+    name: &str,
+    pair: &MutablePair,
+    first: Option<&MutablePair>,
+) -> (usize, ValidationIssue) {
+    (
+        pair.start_pos(),
+        ValidationIssue {
+            kind,
+            name: name.to_string(),
+            position: pair.start_position,
+            first_position: first.map(|first| first.start_position),
+            detail: None,
+        },
+    )
+}
+
+/// An error with `code` and `message` at byte `offset` of `input`
+pub(crate) fn error_at(
+    input: &str,
+    offset: usize,
+    code: ErrorCode,
+    message: String,
+    encoding: EncodingMode,
+) -> Box<UstarError> {
+    let position = Position::new(input, offset).expect("issue inside input");
+    let error = Error::<Rule>::new_from_pos(ErrorVariant::CustomError { message }, position);
+    Box::new(UstarError::from_pest_error(error, encoding, input).with_code(code))
+}
+
+/// The restrictions of a configuration that `check_dialect` checks
+struct Restrictions {
+    allow_empty_loops: bool,
+    allow_data_outside_saveframes: bool,
+    require_stop_keyword: bool,
+    max_name_length: Option<usize>,
+    strict: bool,
+}
+
+/// Check if `config` has a dialect restriction or asks for validation, so parsing with it
+/// needs a tree to check
+pub(crate) fn needs_tree_checks(config: &ParserConfig) -> bool {
+    !get_allow_empty_loops(config)
+        || !get_allow_data_outside_saveframes(config)
+        || get_require_stop_keyword(config)
+        || get_max_name_length(config).is_some()
+        || get_max_line_length(config).is_some()
+        || get_strict(config)
+        || crate::config::get_validate(config)
+}
+
+/// Check `tree`, parsed from `input`, against the dialect restrictions of `config`; the tree can
+/// be the whole `star_file` or one block of it, whose lines are the only ones checked for length
+///
+/// # Returns
+/// * `Result<(), UstarError>` - Ok if nothing is violated, otherwise an error at the first violation in the input
+pub(crate) fn check_dialect(
+    tree: &MutablePair,
+    input: &str,
+    config: &ParserConfig,
+    encoding: EncodingMode,
+) -> Result<(), Box<UstarError>> {
+    let mut checker = TreeChecker {
+        restrictions: Restrictions {
+            allow_empty_loops: get_allow_empty_loops(config),
+            allow_data_outside_saveframes: get_allow_data_outside_saveframes(config),
+            require_stop_keyword: get_require_stop_keyword(config),
+            max_name_length: get_max_name_length(config),
+            strict: get_strict(config),
+        },
+        save_frame_depth: 0,
+        violation: None,
+    };
+    tree.accept(&mut checker);
+
+    let lines_start = input[..tree.start_pos()]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    let long_line = get_max_line_length(config)
+        .and_then(|max| find_long_line(&input[lines_start..tree.end_pos()], max))
+        .map(|(offset, message)| (lines_start + offset, ErrorCode::E0015LineTooLong, message));
+
+    // both searches find their first violation, report whichever comes first in the input
+    let first = match (checker.violation, long_line) {
+        (Some(node), Some(line)) => Some(if line.0 < node.0 { line } else { node }),
+        (node, line) => node.or(line),
+    };
+    match first {
+        None => Ok(()),
+        Some((offset, code, message)) => Err(error_at(input, offset, code, message, encoding)),
+    }
+}
+
+/// Visitor stopping at the first node that breaks a restriction
+struct TreeChecker {
+    restrictions: Restrictions,
+    save_frame_depth: usize,
+    violation: Option<(usize, ErrorCode, String)>,
+}
+
+impl TreeChecker {
+    fn check(&self, pair: &MutablePair) -> Option<(ErrorCode, String)> {
+        let restrictions = &self.restrictions;
+        match pair.rule_name() {
+            "data" if !restrictions.allow_data_outside_saveframes && self.save_frame_depth == 0 => {
+                Some((
+                    ErrorCode::E0011DataOutsideSaveFrame,
+                    "data outside a save frame is not allowed".to_string(),
+                ))
+            }
+            "data_loop" => {
+                let values = pair.find_first("data_loop_values")?;
+                let empty = values
+                    .children()
+                    .iter()
+                    .all(|child| matches!(child.rule_name(), "stop_keyword" | "comment"));
+                if !restrictions.allow_empty_loops && empty {
+                    Some((
+                        ErrorCode::E0012EmptyLoop,
+                        "loop has no values and empty loops are not allowed".to_string(),
+                    ))
+                } else if restrictions.require_stop_keyword
+                    && values
+                        .children()
+                        .iter()
+                        .rfind(|child| child.rule_name() != "comment")
+                        .map(|child| child.rule_name())
+                        != Some("stop_keyword")
+                {
+                    Some((
+                        ErrorCode::E0013MissingStopKeyword,
+                        "loop is not terminated by stop_".to_string(),
+                    ))
+                } else {
+                    None
+                }
+            }
+            "data_name" => self.check_name_length(pair.as_str(), "data name"),
+            "data_heading" => self.check_name_length(&pair.as_str()["data_".len()..], "block code"),
+            "save_heading" => self.check_name_length(&pair.as_str()["save_".len()..], "frame code"),
+            _ => None,
+        }
+    }
+
+    fn check_name_length(&self, name: &str, kind: &str) -> Option<(ErrorCode, String)> {
+        let max = self.restrictions.max_name_length?;
+        (name.chars().count() > max).then(|| {
+            (
+                ErrorCode::E0014NameTooLong,
+                format!("{} is longer than the maximum of {} characters", kind, max),
+            )
+        })
+    }
+}
+
+/// Characters strict mode doesn't allow in a block or frame code: quotes and `#` start values
+/// and comments, `$` starts frame codes and brackets delimit CIF 2.0 lists and tables
+const HEADING_DELIMITERS: &[char] = &['\'', '"', '#', '$', '[', ']', '{', '}'];
+
+/// The offset of the first character of the block or frame code of `heading` that strict mode
+/// doesn't allow, with the error for it
+fn check_heading_characters(heading: &MutablePair) -> Option<(usize, ErrorCode, String)> {
+    let (keyword, kind) = match heading.rule_name() {
+        "data_heading" => ("data_", "block code"),
+        "save_heading" => ("save_", "frame code"),
+        _ => return None,
+    };
+    let (index, character) = heading.as_str()[keyword.len()..]
+        .char_indices()
+        .find(|(_, character)| HEADING_DELIMITERS.contains(character))?;
+    Some((
+        heading.start_pos() + keyword.len() + index,
+        ErrorCode::E0028InvalidHeadingCharacter,
+        format!("character {:?} is not allowed in a {}", character, kind),
+    ))
+}
+
+impl Visitor for TreeChecker {
+    fn enter(&mut self, pair: &MutablePair) -> VisitControl {
+        if let Some((code, message)) = self.check(pair) {
+            self.violation = Some((pair.start_pos(), code, message));
+            return VisitControl::Stop;
+        }
+        if self.restrictions.strict {
+            if let Some(violation) = check_heading_characters(pair) {
+                self.violation = Some(violation);
+                return VisitControl::Stop;
+            }
+        }
+        if pair.rule_name() == "save_frame" {
+            self.save_frame_depth += 1;
+        }
+        VisitControl::Continue
+    }
+
+    fn exit(&mut self, pair: &MutablePair) {
+        if pair.rule_name() == "save_frame" {
+            self.save_frame_depth -= 1;
+        }
+    }
+}
+
+/// Find the first line of `input` longer than `max` characters, pointing at its first extra character
+fn find_long_line(input: &str, max: usize) -> Option<(usize, String)> {
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\n', '\r']);
+        if let Some((extra, _)) = text.char_indices().nth(max) {
+            return Some((
+                line_start + extra,
+                format!("line is longer than the maximum of {} characters", max),
+            ));
+        }
+        line_start += line.len();
+    }
+    None
+}
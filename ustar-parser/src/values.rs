@@ -0,0 +1,296 @@
+//! Typed values - interpret value tokens as numbers, nulls, frame codes or text.
+//!
+//! STAR stores every value as text, but numbers are written in a common notation: an optional
+//! sign, digits with an optional decimal point, an optional exponent and an optional standard
+//! uncertainty in the last digits, as in `1.007825031898(14)`. The unquoted values `.` and `?`
+//! mark a value that is inapplicable or unknown, and `$name` refers to a save frame.
+//!
+//! A quoted value is always text, so `'1'` and `'.'` are the strings "1" and ".".
+
+use crate::mutable_pair::PairNode;
+use std::fmt;
+
+/// A value of a STAR data item interpreted by its notation
+#[derive(Debug, Clone, PartialEq)]
+pub enum StarValue {
+    /// A number without decimal point, exponent or uncertainty that fits an `i64`
+    Int(i64),
+    /// Any other number that is finite as an `f64`, numbers too large for one are `Text`
+    Float(f64),
+    /// A number with a standard uncertainty, `1.23(4)` is 1.23 with an uncertainty of 0.04
+    FloatWithUncertainty { value: f64, uncertainty: f64 },
+    /// A value that is not a number, with any quotes removed
+    Text(String),
+    /// The unquoted value `.`, inapplicable or not given
+    Null,
+    /// The unquoted value `?`, unknown
+    Unknown,
+    /// A save frame reference `$name`, holding the name without the `$`
+    FrameCode(String),
+}
+
+impl StarValue {
+    /// Interpret a value token as written in a STAR file
+    ///
+    /// Quoted strings and semicolon text fields become `Text` of their content. Numbers written
+    /// with a Fortran style exponent missing its `E`, as in `2.0152720-01`, are read as floats
+    /// when the mantissa has a decimal point and the exponent has at most three digits, so
+    /// ranges like `1901-1906` remain text.
+    pub fn parse(token: &str) -> StarValue {
+        if let Some(content) = unquote(token) {
+            return StarValue::Text(content.to_string());
+        }
+        match token {
+            "." => StarValue::Null,
+            "?" => StarValue::Unknown,
+            _ => {
+                if let Some(name) = token.strip_prefix('$') {
+                    StarValue::FrameCode(name.to_string())
+                } else {
+                    parse_number(token).unwrap_or_else(|| StarValue::Text(token.to_string()))
+                }
+            }
+        }
+    }
+
+    /// Interpret a value node of a parse tree from either backend, decomposed or not
+    ///
+    /// CIF2 lists and tables have no typed form and are returned as `Text` of their source.
+    pub fn from_pair<'a, N: PairNode<'a>>(pair: N) -> StarValue {
+        match pair.rule_name() {
+            "string" | "non_quoted_string" | "container_non_quoted_string" | "frame_code" => {
+                StarValue::parse(pair.as_str())
+            }
+            "list_value" | "table_value" => StarValue::Text(pair.as_str().to_string()),
+            _ => match pair.find_first_node("string") {
+                // decomposed quoted strings hold their content in a string child
+                Some(content) => StarValue::Text(content.as_str().to_string()),
+                None => {
+                    StarValue::Text(unquote(pair.as_str()).unwrap_or(pair.as_str()).to_string())
+                }
+            },
+        }
+    }
+
+    /// The value as an `f64` if it is a number, dropping any uncertainty
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            StarValue::Int(value) => Some(*value as f64),
+            StarValue::Float(value) | StarValue::FloatWithUncertainty { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value as an `i64` if it is an integer
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            StarValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The text of a `Text` value or the name of a `FrameCode`
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            StarValue::Text(text) | StarValue::FrameCode(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Check if the value is `.` or `?`
+    pub fn is_null_or_unknown(&self) -> bool {
+        matches!(self, StarValue::Null | StarValue::Unknown)
+    }
+}
+
+/// Writes the value as a STAR token that `StarValue::parse` reads back as the same value
+impl fmt::Display for StarValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarValue::Int(value) => write!(f, "{}", value),
+            // Debug keeps a decimal point or exponent so the value is read back as a float
+            StarValue::Float(value) => write!(f, "{:?}", value),
+            StarValue::FloatWithUncertainty { value, uncertainty } => {
+                f.write_str(&format_uncertain(*value, *uncertainty))
+            }
+            StarValue::Text(text) => write_text(f, text),
+            StarValue::Null => f.write_str("."),
+            StarValue::Unknown => f.write_str("?"),
+            StarValue::FrameCode(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+/// Check if `text` written without quotes would be read as a keyword, such as `stop_` or
+/// `data_x`, or as a data name rather than as a value
+///
+/// The grammar never reads such text as an unquoted value, it ends a loop or item there
+/// instead, so writers quote it.
+pub fn collides_with_keyword(text: &str) -> bool {
+    text.starts_with('_')
+        || ["data_", "save_", "loop_", "stop_", "global_"]
+            .iter()
+            .any(|keyword| {
+                text.get(..keyword.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+            })
+}
+
+/// The text of a value node without any quotes, as reported by the walker
+pub(crate) fn value_text<'a, N: PairNode<'a>>(pair: N) -> &'a str {
+    match pair.rule_name() {
+        "string"
+        | "non_quoted_string"
+        | "container_non_quoted_string"
+        | "frame_code"
+        | "list_value"
+        | "table_value" => pair.as_str(),
+        _ => match pair.find_first_node("string") {
+            Some(content) => content.as_str(),
+            None => unquote(pair.as_str()).unwrap_or(pair.as_str()),
+        },
+    }
+}
+
+/// The content of a quoted string or semicolon text field, `None` for an unquoted token
+fn unquote(token: &str) -> Option<&str> {
+    for delimiter in ["'''", "\"\"\"", "'", "\"", "\r\n;", "\n;"] {
+        if token.len() >= 2 * delimiter.len()
+            && token.starts_with(delimiter)
+            && token.ends_with(delimiter)
+        {
+            return Some(&token[delimiter.len()..token.len() - delimiter.len()]);
+        }
+    }
+    None
+}
+
+/// Parse the number notation `[+-]digits[.digits][exponent][(uncertainty)]`
+pub(crate) fn parse_number(token: &str) -> Option<StarValue> {
+    let (number, uncertainty) = match token.strip_suffix(')') {
+        Some(rest) => {
+            let (number, digits) = rest.split_once('(')?;
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            (number, Some(digits))
+        }
+        None => (token, None),
+    };
+
+    let unsigned = number.strip_prefix(['+', '-']).unwrap_or(number);
+    let sign = &number[..number.len() - unsigned.len()];
+    let integer_len = leading_digits(unsigned);
+    let (integer, rest) = unsigned.split_at(integer_len);
+    let (fraction, rest) = match rest.strip_prefix('.') {
+        Some(after_point) => {
+            let fraction_len = leading_digits(after_point);
+            (
+                Some(&after_point[..fraction_len]),
+                &after_point[fraction_len..],
+            )
+        }
+        None => (None, rest),
+    };
+    if integer.is_empty() && fraction.is_none_or(str::is_empty) {
+        return None;
+    }
+
+    let exponent = if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        Some(parse_exponent(exponent)?)
+    } else if fraction.is_some() && rest.starts_with(['+', '-']) && rest.len() <= 4 {
+        // a Fortran style exponent without its E
+        Some(parse_exponent(rest)?)
+    } else if rest.is_empty() {
+        None
+    } else {
+        return None;
+    };
+
+    if fraction.is_none() && exponent.is_none() && uncertainty.is_none() {
+        if let Ok(value) = number.parse::<i64>() {
+            return Some(StarValue::Int(value));
+        }
+    }
+
+    let fraction = fraction.unwrap_or("");
+    let exponent = exponent.unwrap_or(0);
+    // padding with zeros makes forms like `.5` and `5.` valid for str::parse
+    let value = format!("{}0{}.{}0e{}", sign, integer, fraction, exponent)
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())?;
+    match uncertainty {
+        None => Some(StarValue::Float(value)),
+        Some(digits) => {
+            // the uncertainty applies to the last digit of the number as written
+            let scale = exponent - fraction.len() as i64;
+            let uncertainty = format!("{}e{}", digits, scale)
+                .parse::<f64>()
+                .ok()
+                .filter(|uncertainty| uncertainty.is_finite())?;
+            Some(StarValue::FloatWithUncertainty { value, uncertainty })
+        }
+    }
+}
+
+fn leading_digits(text: &str) -> usize {
+    text.bytes().take_while(u8::is_ascii_digit).count()
+}
+
+/// Parse `[+-]digits` as an exponent
+fn parse_exponent(text: &str) -> Option<i64> {
+    let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if digits.is_empty() || leading_digits(digits) != digits.len() {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Write a number with an uncertainty in its last digits
+///
+/// The last digit is placed at the largest power of ten at which both numbers are written
+/// exactly, so the token reads back as the same value and uncertainty.
+fn format_uncertain(value: f64, uncertainty: f64) -> String {
+    let leading = |number: f64| match number.abs() {
+        number if number.is_finite() && number > 0.0 => number.log10().floor() as i32,
+        _ => 0,
+    };
+    let highest = leading(uncertainty);
+    // 17 significant digits reproduce any f64, the lowest scale is used if none is exact
+    let lowest = highest.min(leading(value)) - 17;
+
+    let mut token = String::new();
+    for scale in (lowest..=highest).rev() {
+        let digits = (uncertainty / 10f64.powi(scale)).round() as u64;
+        token = if scale > 0 {
+            format!("{:.0}E{}({})", value / 10f64.powi(scale), scale, digits)
+        } else {
+            format!("{:.*}({})", (-scale) as usize, value, digits)
+        };
+        let written = StarValue::parse(&token);
+        if written == (StarValue::FloatWithUncertainty { value, uncertainty }) {
+            break;
+        }
+    }
+    token
+}
+
+/// Write text bare if it reads back as the same text, otherwise in the first quoting that fits
+fn write_text(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    let bare = !text.is_empty()
+        && !collides_with_keyword(text)
+        && !text.starts_with(['$', '\'', '"', '#', '[', ']', '{', '}', ';'])
+        && !text.contains(char::is_whitespace)
+        && matches!(StarValue::parse(text), StarValue::Text(_));
+
+    if bare {
+        f.write_str(text)
+    } else if !text.contains('\n') && !text.contains("' ") && !text.ends_with('\'') {
+        write!(f, "'{}'", text)
+    } else if !text.contains('\n') && !text.contains("\" ") && !text.ends_with('"') {
+        write!(f, "\"{}\"", text)
+    } else {
+        write!(f, "\n;{}\n;", text)
+    }
+}
@@ -0,0 +1,51 @@
+//! JavaScript bindings - parsing and checking STAR text in a browser or other WebAssembly host
+//!
+//! Build for `wasm32-unknown-unknown` without the default features, as terminal detection and
+//! backtraces for miette reports aren't available there, for example with
+//! `wasm-pack build ustar-parser -- --no-default-features --features extended-errors,wasm`.
+//!
+//! Options are an object of configuration keys and values, as read by
+//! `ConfigFile::from_json_str`, such as `{"encoding": "unicode", "cif_version": "cif2"}`; `null`
+//! or `undefined` gives the default configuration. Errors are objects with the keys of
+//! `ErrorFormatMode::Json`: `code`, `message`, `line`, `column`, `span` and so on.
+
+use crate::config::ConfigFile;
+use crate::{default_config, ParserConfig, UstarError};
+use wasm_bindgen::prelude::*;
+
+/// Parse `input` with `options` to the JSON of its tree, as `MutablePair::to_json` writes it
+///
+/// Throws the parse error as an object, or an `Error` if the options can't be read.
+#[wasm_bindgen]
+pub fn parse_to_json(input: &str, options: JsValue) -> Result<String, JsValue> {
+    let config = config_from(&options)?;
+    let tree = crate::parse(input, &config).map_err(|error| error_value(&error))?;
+    tree.to_json()
+        .map_err(|error| js_sys::Error::new(&error.to_string()).into())
+}
+
+/// Check `input` parses with the default configuration, `null` if it does and the error as an
+/// object if it doesn't
+#[wasm_bindgen]
+pub fn check(input: &str) -> JsValue {
+    match crate::parse(input, &default_config()) {
+        Ok(_) => JsValue::NULL,
+        Err(error) => error_value(&error),
+    }
+}
+
+/// The configuration given by a JavaScript options object (private)
+fn config_from(options: &JsValue) -> Result<ParserConfig, JsValue> {
+    if options.is_null() || options.is_undefined() {
+        return Ok(default_config());
+    }
+    let json: String = js_sys::JSON::stringify(options)?.into();
+    ParserConfig::from_json_str(&json)
+        .map_err(|error| js_sys::Error::new(&error.to_string()).into())
+}
+
+/// An error as a JavaScript object with its position and message (private)
+fn error_value(error: &UstarError) -> JsValue {
+    let json = error.core().format_json();
+    js_sys::JSON::parse(&json).unwrap_or_else(|_| JsValue::from_str(&json))
+}
@@ -0,0 +1,184 @@
+#![cfg(feature = "arrow")]
+
+use arrow::array::{Array, Float64Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use indoc::indoc;
+use ustar::arrow_tables::{loops_to_arrow, ArrowTableError};
+use ustar::line_column_index::LineColumn;
+use ustar::loop_table::LoopTable;
+use ustar::parse_default;
+
+const SHIFTS: &str = indoc! {"
+    data_shifts
+    save_nef_chemical_shift_list_1
+       _nef_chemical_shift_list.sf_category   nef_chemical_shift_list
+       loop_
+          _nef_chemical_shift.sequence_code
+          _nef_chemical_shift.residue_name
+          _nef_chemical_shift.atom_name
+          _nef_chemical_shift.value
+          _nef_chemical_shift.value_uncertainty
+          _nef_chemical_shift.element
+          1  MET  CA  55.2    0.1  .
+          2  PRO  CA  63      .    .
+          3  'GLY'  HA2 4.12(2) ?    .
+       stop_
+       loop_
+          _nef_peak.index
+          _nef_peak.height
+          1  1.0e5
+       stop_
+    save_
+"};
+
+fn shift_table(tree: &ustar::mutable_pair::MutablePair) -> LoopTable<'_> {
+    LoopTable::from_pair(tree.find_first("data_loop").unwrap()).unwrap()
+}
+
+#[test]
+fn test_chemical_shift_loop_to_record_batch() {
+    let tree = parse_default(SHIFTS).unwrap();
+    let batch = shift_table(&tree).to_arrow(None).unwrap();
+
+    let schema = batch.schema();
+    let types: Vec<(&str, &DataType)> = schema
+        .fields()
+        .iter()
+        .map(|field| (field.name().as_str(), field.data_type()))
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            ("_nef_chemical_shift.sequence_code", &DataType::Int64),
+            ("_nef_chemical_shift.residue_name", &DataType::Utf8),
+            ("_nef_chemical_shift.atom_name", &DataType::Utf8),
+            ("_nef_chemical_shift.value", &DataType::Float64),
+            ("_nef_chemical_shift.value_uncertainty", &DataType::Float64),
+            ("_nef_chemical_shift.element", &DataType::Null),
+        ]
+    );
+    assert_eq!(batch.num_rows(), 3);
+
+    let sequence = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    assert_eq!(sequence.values(), &[1, 2, 3]);
+    let residues = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(residues.value(2), "GLY");
+    let values = batch
+        .column(3)
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .unwrap();
+    assert_eq!(values.values(), &[55.2, 63.0, 4.12]);
+    let uncertainties = batch
+        .column(4)
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .unwrap();
+    assert_eq!(uncertainties.value(0), 0.1);
+    assert!(uncertainties.is_null(1) && uncertainties.is_null(2));
+    assert_eq!(batch.column(5).len(), 3);
+}
+
+#[test]
+fn test_schema_hint_overrides_inferred_types() {
+    let tree = parse_default(SHIFTS).unwrap();
+    let hint = Schema::new(vec![
+        Field::new("_NEF_CHEMICAL_SHIFT.SEQUENCE_CODE", DataType::Int64, true),
+        Field::new("_nef_chemical_shift.sequence_code", DataType::Utf8, false),
+        Field::new("_nef_chemical_shift.element", DataType::Utf8, true),
+    ]);
+    let batch = shift_table(&tree).to_arrow(Some(&hint)).unwrap();
+
+    let schema = batch.schema();
+    assert_eq!(schema.field(0).data_type(), &DataType::Utf8);
+    assert!(!schema.field(0).is_nullable());
+    assert_eq!(schema.field(5).data_type(), &DataType::Utf8);
+    let elements = batch
+        .column(5)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(elements.null_count(), 3);
+}
+
+#[test]
+fn test_value_not_of_hinted_type_fails_at_its_position() {
+    let tree = parse_default(SHIFTS).unwrap();
+    let hint = Schema::new(vec![Field::new(
+        "_nef_chemical_shift.value",
+        DataType::Int64,
+        true,
+    )]);
+    let error = shift_table(&tree).to_arrow(Some(&hint)).unwrap_err();
+
+    let ArrowTableError::Value { position, .. } = &error else {
+        panic!("expected a value error, got {:?}", error);
+    };
+    assert_eq!(
+        *position,
+        LineColumn {
+            line: 11,
+            column: 19
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "l11:c19 _nef_chemical_shift.value has the value 55.2 which isn't Int64"
+    );
+
+    let hint = Schema::new(vec![Field::new(
+        "_nef_chemical_shift.value",
+        DataType::Boolean,
+        true,
+    )]);
+    assert!(matches!(
+        shift_table(&tree).to_arrow(Some(&hint)),
+        Err(ArrowTableError::UnsupportedType { .. })
+    ));
+}
+
+#[test]
+fn test_loops_to_arrow_selects_by_prefix() {
+    let tree = parse_default(SHIFTS).unwrap();
+
+    let batches = loops_to_arrow(&tree, "_NEF_PEAK").unwrap();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].schema().field(1).data_type(), &DataType::Float64);
+    assert_eq!(loops_to_arrow(&tree, "").unwrap().len(), 2);
+    assert!(loops_to_arrow(&tree, "_atom_site").unwrap().is_empty());
+}
+
+#[test]
+fn test_nested_loops_are_rejected() {
+    let input = indoc! {"
+        data_nested
+        loop_
+           _outer.id
+           loop_
+              _inner.value
+              _inner.error
+        1  a 0.1 stop_
+        2  stop_
+    "};
+    let tree = parse_default(input).unwrap();
+
+    let error = loops_to_arrow(&tree, "").unwrap_err();
+    assert!(matches!(
+        error,
+        ArrowTableError::NestedLoop {
+            position: LineColumn { line: 2, column: 1 }
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "l2:c1 nested loops can't be converted to a record batch"
+    );
+}
@@ -0,0 +1,232 @@
+use ustar::line_column_index::LineColumnIndex;
+use ustar::mutable_pair::MutablePair;
+use ustar::{
+    default_config, iter_blocks, parse, ColumnUnit, ConfigKey, ConfigValue, EncodingMode,
+    ErrorFormatMode,
+};
+
+fn heading(block: &MutablePair) -> &str {
+    block.children[0].as_str()
+}
+
+/// A synthetic file with `count` data blocks and a global block in the middle
+fn synthetic_file(count: usize) -> String {
+    let mut input = String::new();
+    for index in 0..count {
+        if index == count / 2 {
+            input.push_str("global_\n_global.index  middle\n\n");
+        }
+        input.push_str(&format!(
+            "data_block_{index}\n_entry.id  {index}\nloop_\n_atom.id\n_atom.name\n1 'N'\n2 'CA'\n\n"
+        ));
+    }
+    input
+}
+
+#[test]
+fn test_blocks_are_yielded_in_document_order() {
+    let count = 1000;
+    let input = synthetic_file(count);
+
+    let mut headings = Vec::new();
+    let mut largest_block = 0;
+    for block in iter_blocks(&input, &default_config()) {
+        let block = block.expect("every synthetic block is valid");
+        largest_block = largest_block.max(block.end - block.start);
+        headings.push(heading(&block).to_string());
+        // the block is dropped here, before the next one is parsed
+    }
+
+    let mut expected: Vec<String> = (0..count)
+        .map(|index| format!("data_block_{index}"))
+        .collect();
+    expected.insert(count / 2, "global_".to_string());
+    assert_eq!(headings, expected);
+
+    // each tree only ever covers a single block, never the whole file
+    assert!(largest_block < 100);
+}
+
+#[test]
+fn test_blocks_match_parse() {
+    let input = indoc::indoc! {"
+        # keywords in strings and text fields don't start blocks
+        data_first
+        _entry.title  'see data_second'
+        _entry.note   \"global_ settings\"
+        _entry.details
+        ;
+        data_not_a_block
+        ;
+        save_frame
+        _item.name  '''data_triple'''
+        save_
+
+        global_
+        _global.value  1
+
+        data_last
+        loop_
+        _atom.id
+        1
+        2
+    "};
+    let config = default_config();
+
+    let blocks: Vec<MutablePair> = iter_blocks(input, &config)
+        .map(|block| block.expect("blocks are valid"))
+        .collect();
+
+    let tree = parse(input, &config).unwrap();
+    let expected: Vec<&MutablePair> = tree
+        .children
+        .iter()
+        .filter(|child| child.rule_name() != "EOI")
+        .collect();
+    // equality includes the stored line and columns
+    assert_eq!(blocks.iter().collect::<Vec<_>>(), expected);
+    assert_eq!(
+        blocks.iter().map(heading).collect::<Vec<_>>(),
+        vec!["data_first", "global_", "data_last"]
+    );
+}
+
+#[test]
+fn test_invalid_block_does_not_stop_iteration() {
+    let input = indoc::indoc! {"
+        data_first
+        _entry.id  1
+
+        data_broken
+        _entry.id
+
+        data_last
+        _entry.id  3
+    "};
+
+    let results: Vec<_> = iter_blocks(input, &default_config()).collect();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(heading(results[0].as_ref().unwrap()), "data_first");
+    let error = results[1].as_ref().unwrap_err();
+    assert!(error
+        .format_error(ErrorFormatMode::Basic, 0)
+        .starts_with("Parse error at l7:c1"));
+    assert_eq!(heading(results[2].as_ref().unwrap()), "data_last");
+}
+
+#[test]
+fn test_empty_input_yields_no_blocks() {
+    assert_eq!(iter_blocks("", &default_config()).count(), 0);
+    assert_eq!(
+        iter_blocks("  # only a comment\n\n", &default_config()).count(),
+        0
+    );
+}
+
+#[test]
+fn test_blocks_store_line_columns_in_the_whole_input() {
+    let input = synthetic_file(20);
+    let line_index = LineColumnIndex::new(&input);
+
+    for block in iter_blocks(&input, &default_config()) {
+        let block = block.unwrap();
+        for pair in std::iter::once(&block).chain(block.iter_descendants()) {
+            assert_eq!(
+                pair.start_line_column(),
+                line_index.offset_to_line_col(pair.start)
+            );
+            assert_eq!(
+                pair.end_line_column(),
+                line_index.offset_to_line_col(pair.end)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_blocks_match_parse_in_each_column_unit() {
+    let input = "data_α\n_name 'ά' _next 1\n\ndata_β\nloop_\n_x\n'α' 'β'\n";
+    for unit in [ColumnUnit::Bytes, ColumnUnit::Chars, ColumnUnit::Graphemes] {
+        let mut config = default_config();
+        config.insert(
+            ConfigKey::Encoding,
+            ConfigValue::Encoding(EncodingMode::Unicode),
+        );
+        config.insert(ConfigKey::ColumnUnit, ConfigValue::ColumnUnit(unit));
+
+        let blocks: Vec<MutablePair> = iter_blocks(input, &config)
+            .map(|block| block.expect("blocks are valid"))
+            .collect();
+        let tree = parse(input, &config).unwrap();
+
+        assert_eq!(blocks[..], tree.children[..2], "{:?}", unit);
+    }
+}
+
+#[test]
+fn test_blocks_share_only_their_own_text() {
+    let input = synthetic_file(3);
+
+    let blocks: Vec<MutablePair> = iter_blocks(&input, &default_config())
+        .map(|block| block.unwrap())
+        .collect();
+
+    // a block's nodes share one copy of the block, which no other block holds on to
+    for block in &blocks {
+        assert!(block
+            .iter_descendants()
+            .all(|pair| pair.content.shares_source(&block.content)));
+    }
+    assert!(!blocks[0].content.shares_source(&blocks[1].content));
+}
+
+#[test]
+fn test_blocks_are_checked_as_parse_checks_them() {
+    let input = indoc::indoc! {"
+        data_first
+        _entry.id  1
+
+        data_duplicate
+        _entry.id  2
+        _entry.id  3
+
+        data_last
+        loop_
+        _atom.id
+        stop_
+    "};
+    let mut config = default_config();
+    config.insert(ConfigKey::Validate, ConfigValue::Bool(true));
+    config.insert(ConfigKey::AllowEmptyLoops, ConfigValue::Bool(false));
+
+    let results: Vec<_> = iter_blocks(input, &config).collect();
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(heading(results[0].as_ref().unwrap()), "data_first");
+    let duplicate = results[1].as_ref().unwrap_err();
+    assert_eq!(
+        duplicate.format_error(ErrorFormatMode::Basic, 0),
+        "Parse error at l6:c1 because duplicate data name _entry.id, first defined at l5:c1\n"
+    );
+    let empty_loop = results[2].as_ref().unwrap_err();
+    assert_eq!(empty_loop.code(), ustar::ErrorCode::E0012EmptyLoop);
+}
+
+#[test]
+fn test_blocks_are_checked_for_long_lines() {
+    let input = "data_first\n_entry.id  1\n\ndata_second _entry.title  'a long title'\n";
+    let mut config = default_config();
+    config.insert(ConfigKey::MaxLineLength, ConfigValue::Usize(20));
+
+    let results: Vec<_> = iter_blocks(input, &config).collect();
+
+    assert!(results[0].is_ok());
+    let error = results[1].as_ref().unwrap_err();
+    assert_eq!(
+        error.format_error(ErrorFormatMode::Basic, 0),
+        parse(input, &config)
+            .unwrap_err()
+            .format_error(ErrorFormatMode::Basic, 0)
+    );
+}
@@ -0,0 +1,261 @@
+use ustar::{
+    default_config, get_cif_version, get_column_unit, get_context_lines, get_encoding,
+    get_max_line_length, get_validate, CifVersion, ColumnUnit, ConfigError, ConfigKey, ConfigValue,
+    Dialect, DialectPreset, EncodingMode, ErrorFormatMode, ParserConfig, ParserConfigBuilder,
+};
+
+/// Whether `value` is the kind of value documented for `key`
+fn value_fits_key(key: &ConfigKey, value: &ConfigValue) -> bool {
+    match key {
+        ConfigKey::DecomposedStrings
+        | ConfigKey::UnescapeQuotes
+        | ConfigKey::AutoDetectBom
+        | ConfigKey::AllowEmptyLoops
+        | ConfigKey::AllowDataOutsideSaveframes
+        | ConfigKey::RequireStopKeyword
+        | ConfigKey::Validate
+        | ConfigKey::Strict
+        | ConfigKey::AttachComments
+        | ConfigKey::Prelex => value.as_bool().is_some(),
+        ConfigKey::Encoding => value.as_encoding().is_some(),
+        ConfigKey::ErrorFormat => value.as_error_format().is_some(),
+        ConfigKey::ContextLines
+        | ConfigKey::MaxLineLength
+        | ConfigKey::MaxNameLength
+        | ConfigKey::MaxNestingDepth
+        | ConfigKey::MaxInputBytes => value.as_usize().is_some(),
+        ConfigKey::CifVersion => value.as_cif_version().is_some(),
+        ConfigKey::ColumnUnit => value.as_column_unit().is_some(),
+    }
+}
+
+#[test]
+fn test_builder_sets_every_key_with_its_value_type() {
+    let config = ParserConfigBuilder::new()
+        .decomposed_strings(false)
+        .unescape_quotes(true)
+        .encoding(EncodingMode::Unicode)
+        .auto_detect_bom(true)
+        .error_format(ErrorFormatMode::Json)
+        .context_lines(5)
+        .cif_version(CifVersion::Cif2)
+        .allow_empty_loops(false)
+        .allow_data_outside_saveframes(false)
+        .require_stop_keyword(true)
+        .max_line_length(2048)
+        .max_name_length(75)
+        .validate(true)
+        .strict(true)
+        .attach_comments(true)
+        .column_unit(ColumnUnit::Chars)
+        .max_nesting_depth(16)
+        .max_input_bytes(1 << 20)
+        .prelex(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(config.len(), 19);
+    for (key, value) in &config {
+        assert!(value_fits_key(key, value), "{:?} set to {:?}", key, value);
+    }
+    assert_eq!(get_encoding(&config), EncodingMode::Unicode);
+    assert_eq!(get_context_lines(&config), 5);
+    assert_eq!(get_cif_version(&config), CifVersion::Cif2);
+    assert_eq!(get_max_line_length(&config), Some(2048));
+    assert!(get_validate(&config));
+    assert_eq!(get_column_unit(&config), ColumnUnit::Chars);
+}
+
+#[test]
+fn test_default_config_is_the_unmodified_builder() {
+    assert_eq!(
+        default_config(),
+        ParserConfigBuilder::new().build().unwrap()
+    );
+    for (key, value) in &default_config() {
+        assert!(value_fits_key(key, value), "{:?} set to {:?}", key, value);
+    }
+}
+
+#[test]
+fn test_build_rejects_inconsistent_options() {
+    assert_eq!(
+        ParserConfigBuilder::new().max_line_length(0).build(),
+        Err(ConfigError::ZeroLimit(ConfigKey::MaxLineLength))
+    );
+    assert_eq!(
+        ParserConfigBuilder::new().max_name_length(0).build(),
+        Err(ConfigError::ZeroLimit(ConfigKey::MaxNameLength))
+    );
+    assert_eq!(
+        ParserConfigBuilder::new().max_nesting_depth(0).build(),
+        Err(ConfigError::ZeroLimit(ConfigKey::MaxNestingDepth))
+    );
+
+    let error = ParserConfigBuilder::new()
+        .cif_version(CifVersion::Cif2)
+        .encoding(EncodingMode::ExtendedAscii)
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error,
+        ConfigError::Cif2Encoding(EncodingMode::ExtendedAscii)
+    );
+    assert_eq!(
+        error.to_string(),
+        "CIF 2.0 input is UTF-8 and can't use encoding ExtendedAscii"
+    );
+}
+
+#[test]
+fn test_named_presets_match_their_dialects() {
+    assert_eq!(ParserConfig::nef(), ParserConfig::preset(Dialect::Nef));
+    assert_eq!(ParserConfig::mmcif(), ParserConfig::preset(Dialect::Cif1));
+    assert_eq!(
+        ParserConfig::nmrstar(),
+        ParserConfig::preset(Dialect::NmrStar)
+    );
+
+    for dialect in [
+        Dialect::Star2012,
+        Dialect::Cif1,
+        Dialect::Cif2,
+        Dialect::NmrStar,
+        Dialect::Nef,
+    ] {
+        for (key, value) in &ParserConfig::preset(dialect) {
+            assert!(value_fits_key(key, value), "{:?} set to {:?}", key, value);
+        }
+    }
+}
+
+/// A configuration with every key set away from its default
+#[cfg(feature = "serde")]
+fn full_config() -> ParserConfig {
+    ParserConfigBuilder::new()
+        .decomposed_strings(false)
+        .unescape_quotes(true)
+        .encoding(EncodingMode::Unicode)
+        .auto_detect_bom(true)
+        .error_format(ErrorFormatMode::Basic)
+        .context_lines(5)
+        .cif_version(CifVersion::Cif2)
+        .allow_empty_loops(false)
+        .allow_data_outside_saveframes(false)
+        .require_stop_keyword(true)
+        .max_line_length(2048)
+        .max_name_length(75)
+        .validate(true)
+        .strict(true)
+        .attach_comments(true)
+        .column_unit(ColumnUnit::Graphemes)
+        .max_nesting_depth(16)
+        .max_input_bytes(1 << 20)
+        .prelex(true)
+        .build()
+        .unwrap()
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_full_config_round_trips_through_toml_and_json() {
+    use ustar::ConfigFile;
+
+    let config = full_config();
+
+    let toml = config.to_toml_string();
+    assert!(toml.contains("encoding = \"unicode\"\n"), "{}", toml);
+    assert!(toml.contains("context_lines = 5\n"), "{}", toml);
+    assert_eq!(ParserConfig::from_toml_str(&toml).unwrap(), config);
+
+    let json = config.to_json_string();
+    assert!(json.contains("\"column_unit\": \"graphemes\""), "{}", json);
+    assert_eq!(ParserConfig::from_json_str(&json).unwrap(), config);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_text_keeps_defaults_for_missing_keys() {
+    use ustar::ConfigFile;
+
+    let config = ParserConfig::from_toml_str("encoding = \"unicode\"\n").unwrap();
+    let mut expected = default_config();
+    expected.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::Unicode),
+    );
+    assert_eq!(config, expected);
+
+    assert_eq!(ParserConfig::from_json_str("{}").unwrap(), default_config());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_text_rejects_unknown_keys_and_bad_values() {
+    use ustar::ConfigFile;
+
+    let error = ParserConfig::from_toml_str("contxt_lines = 5\n").unwrap_err();
+    assert_eq!(error, ConfigError::UnknownKey("contxt_lines".to_string()));
+    assert_eq!(
+        error.to_string(),
+        "unknown configuration key 'contxt_lines'"
+    );
+
+    let error = ParserConfig::from_toml_str("encoding = \"utf8\"\n").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid value 'utf8' for encoding, expected one of ascii, extended_ascii, unicode"
+    );
+
+    let error = ParserConfig::from_json_str("{\"context_lines\": -1}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid value -1 for context_lines, expected a non-negative integer"
+    );
+
+    let error = ParserConfig::from_json_str("{\"validate\": \"yes\"}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid value 'yes' for validate, expected true or false"
+    );
+
+    let error =
+        ParserConfig::from_toml_str("cif_version = \"cif2\"\nencoding = \"extended_ascii\"\n")
+            .unwrap_err();
+    assert_eq!(
+        error,
+        ConfigError::Cif2Encoding(EncodingMode::ExtendedAscii)
+    );
+
+    assert!(matches!(
+        ParserConfig::from_toml_str("context_lines = ").unwrap_err(),
+        ConfigError::Unreadable(_)
+    ));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_from_env_reads_prefixed_variables() {
+    use ustar::ConfigFile;
+
+    // the prefix is unique to this test so other tests' environments don't interfere
+    std::env::set_var("USTAR_CONFIG_TEST_CONTEXT_LINES", "7");
+    std::env::set_var("USTAR_CONFIG_TEST_DECOMPOSED_STRINGS", "false");
+    std::env::set_var("USTAR_CONFIG_TEST_COLUMN_UNIT", "chars");
+    let config = ParserConfig::from_env("USTAR_CONFIG_TEST").unwrap();
+    std::env::set_var("USTAR_CONFIG_TEST_CONTXT_LINES", "7");
+    let error = ParserConfig::from_env("USTAR_CONFIG_TEST").unwrap_err();
+    for name in [
+        "CONTEXT_LINES",
+        "DECOMPOSED_STRINGS",
+        "COLUMN_UNIT",
+        "CONTXT_LINES",
+    ] {
+        std::env::remove_var(format!("USTAR_CONFIG_TEST_{}", name));
+    }
+
+    assert_eq!(get_context_lines(&config), 7);
+    assert!(!config[&ConfigKey::DecomposedStrings].as_bool().unwrap());
+    assert_eq!(get_column_unit(&config), ColumnUnit::Chars);
+    assert_eq!(error, ConfigError::UnknownKey("contxt_lines".to_string()));
+}
@@ -0,0 +1,202 @@
+use indoc::indoc;
+use ustar::diagnostics::{Diagnostic, DiagnosticCode, Severity};
+use ustar::line_column_index::LineColumn;
+use ustar::{
+    default_config, parse_with_diagnostics, ColumnUnit, ConfigKey, ConfigValue, EncodingMode,
+};
+
+fn diagnostics(input: &str) -> Vec<Diagnostic> {
+    let (result, diagnostics) = parse_with_diagnostics(input, &default_config());
+    result.expect("input should parse");
+    diagnostics
+}
+
+fn codes(input: &str) -> Vec<(DiagnosticCode, LineColumn)> {
+    diagnostics(input)
+        .into_iter()
+        .map(|diagnostic| (diagnostic.code, diagnostic.start))
+        .collect()
+}
+
+#[test]
+fn test_clean_input_has_no_diagnostics() {
+    let input = indoc! {"
+        data_test
+        _entry.id  'stop_'
+        _entry.title  \"data\"
+        save_first
+        loop_
+        _row.id
+        1
+        stop_
+        save_
+    "};
+
+    assert_eq!(diagnostics(input), vec![]);
+}
+
+#[test]
+fn test_mixed_line_endings() {
+    let input = "data_test\r\n_entry.id  1\n_entry.name  x\r\n";
+
+    assert_eq!(
+        diagnostics(input),
+        vec![Diagnostic {
+            severity: Severity::Warning,
+            code: DiagnosticCode::MixedLineEndings,
+            message: "line ends with LF but earlier lines end with CR LF".to_string(),
+            start: LineColumn::new(2, 13),
+            end: LineColumn::new(3, 1),
+        }]
+    );
+    assert_eq!(codes("data_test\r\n_entry.id  1\r\n"), vec![]);
+}
+
+#[test]
+fn test_tab_in_quoted_string() {
+    let input = "data_test\n_entry.a  'one\ttwo'\n_entry.b  \"\tthree\"\n_entry.c  four\n";
+
+    assert_eq!(
+        codes(input),
+        vec![
+            (DiagnosticCode::TabInQuotedString, LineColumn::new(2, 15)),
+            (DiagnosticCode::TabInQuotedString, LineColumn::new(3, 12)),
+        ]
+    );
+}
+
+#[test]
+fn test_loop_terminated_by_save() {
+    let input = indoc! {"
+        data_test
+        loop_
+        _row.id
+        1
+        save_first
+        loop_
+        _item.id
+        2
+        save_
+        save_second
+        loop_
+        _item.id
+        3
+        stop_
+        save_
+    "};
+
+    assert_eq!(
+        codes(input),
+        vec![
+            (DiagnosticCode::LoopTerminatedBySave, LineColumn::new(5, 1)),
+            (DiagnosticCode::LoopTerminatedBySave, LineColumn::new(9, 1)),
+        ]
+    );
+}
+
+#[test]
+fn test_reserved_word_values() {
+    let input = indoc! {"
+        data_test
+        _entry.state  stop
+        _entry.kind  'loop'
+        loop_
+        _row.id
+        _row.word
+        1 Global
+        2 saved
+    "};
+
+    let found = diagnostics(input);
+    assert_eq!(
+        found
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "l2:c15 warning[W004] unquoted value stop looks like the reserved word stop_",
+            "l7:c3 warning[W004] unquoted value Global looks like the reserved word global_",
+        ]
+    );
+    assert_eq!(found[0].end, LineColumn::new(2, 19));
+}
+
+#[test]
+fn test_keyword_ending_loop_row() {
+    // global_ meant as a value ends the loop after 1 and starts a global block
+    let input = indoc! {"
+        data_test
+        loop_
+        _row.id
+        _row.state
+        1 global_
+        _entry.id  2
+    "};
+
+    assert_eq!(
+        diagnostics(input)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<_>>(),
+        vec!["l5:c3 warning[W005] global_ ends the loop part way through a row, quote it if it is a value"]
+    );
+
+    let input = indoc! {"
+        data_test
+        loop_
+        _row.id
+        _row.name
+        1 # the name follows
+        _x
+        2
+    "};
+    assert_eq!(
+        codes(input),
+        vec![(DiagnosticCode::KeywordEndsLoopRow, LineColumn::new(6, 1))]
+    );
+
+    // complete rows, quoted values and stop_ are read as intended
+    for input in [
+        "data_test\nloop_ _row.id _row.state\n1 x\nglobal_ _entry.id 2\n",
+        "data_test\nloop_ _row.id _row.state\n1 'global_' stop_\n",
+        "data_test\nloop_ _row.id _row.state\n1 x 2 stop_\n_entry.id 2\n",
+    ] {
+        assert_eq!(codes(input), vec![], "{input}");
+    }
+}
+
+#[test]
+fn test_diagnostics_of_input_that_fails_to_parse() {
+    let input = "data_test\n_entry.id\r\n";
+
+    let (result, diagnostics) = parse_with_diagnostics(input, &default_config());
+
+    assert!(result.is_err());
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect::<Vec<_>>(),
+        vec![DiagnosticCode::MixedLineEndings]
+    );
+}
+
+#[test]
+fn test_diagnostic_columns_follow_the_column_unit() {
+    let input = "data_test\n_entry.a  'αβ\tx'\n";
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::Unicode),
+    );
+
+    let (_, diagnostics) = parse_with_diagnostics(input, &config);
+    assert_eq!(diagnostics[0].start, LineColumn::new(2, 16));
+
+    config.insert(
+        ConfigKey::ColumnUnit,
+        ConfigValue::ColumnUnit(ColumnUnit::Chars),
+    );
+    let (_, diagnostics) = parse_with_diagnostics(input, &config);
+    assert_eq!(diagnostics[0].start, LineColumn::new(2, 14));
+}
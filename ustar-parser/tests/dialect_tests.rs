@@ -0,0 +1,274 @@
+use indoc::indoc;
+use std::path::Path;
+use ustar::{
+    default_config, parse, CifVersion, ConfigKey, ConfigValue, Dialect, DialectPreset,
+    EncodingMode, ErrorCode, ErrorFormatMode, ParserConfig,
+};
+use ustar_test_utils::{ensure_test_data_available, TestDataPolicy};
+
+/// The basic error message for `input` parsed with `dialect`, or `None` if it is accepted
+fn parse_error(input: &str, dialect: Dialect) -> Option<String> {
+    parse(input, &ParserConfig::preset(dialect))
+        .err()
+        .map(|error| error.format_error(ErrorFormatMode::Basic, 0))
+}
+
+const EMPTY_LOOP: &str = indoc! {"
+    data_test
+    save_frame
+    _frame.category  test
+    loop_
+    _row.id
+    _row.value
+    stop_
+    save_
+"};
+
+const DATA_OUTSIDE_SAVEFRAME: &str = indoc! {"
+    data_test
+    _entry.id  1
+    save_frame
+    _frame.category  test
+    save_
+"};
+
+const LOOP_WITHOUT_STOP: &str = indoc! {"
+    data_test
+    save_frame
+    _frame.category  test
+    loop_
+    _row.id
+    1
+    save_
+"};
+
+const NMR_STAR: &str = indoc! {"
+    data_test
+    save_frame
+    _frame.category  test
+    loop_
+    _row.id
+    1
+    stop_
+    save_
+"};
+
+#[test]
+fn test_star2012_accepts_everything_the_grammar_does() {
+    for input in [
+        EMPTY_LOOP,
+        DATA_OUTSIDE_SAVEFRAME,
+        LOOP_WITHOUT_STOP,
+        NMR_STAR,
+    ] {
+        assert_eq!(parse_error(input, Dialect::Star2012), None);
+    }
+    assert_eq!(
+        parse_error("data_test\n_entry.title  'café'\n", Dialect::Star2012),
+        None
+    );
+}
+
+#[test]
+fn test_cif1_rejects_empty_loops_and_long_names_and_lines() {
+    assert_eq!(parse_error(DATA_OUTSIDE_SAVEFRAME, Dialect::Cif1), None);
+    assert_eq!(parse_error(LOOP_WITHOUT_STOP, Dialect::Cif1), None);
+
+    let error = parse_error(EMPTY_LOOP, Dialect::Cif1).unwrap();
+    assert!(error.starts_with("Parse error at l4:c1"), "{error}");
+    assert!(error.contains("empty loops are not allowed"), "{error}");
+
+    let name = format!("_{}", "a".repeat(74));
+    assert_eq!(
+        parse_error(&format!("data_test\n{name}  1\n"), Dialect::Cif1),
+        None
+    );
+    let error = parse_error(&format!("data_test\n{name}a  1\n"), Dialect::Cif1).unwrap();
+    assert!(error.starts_with("Parse error at l2:c1"), "{error}");
+    assert!(
+        error.contains("data name is longer than the maximum of 75"),
+        "{error}"
+    );
+
+    let error = parse_error(
+        &format!("data_{}\n_entry.id  1\n", "b".repeat(76)),
+        Dialect::Cif1,
+    )
+    .unwrap();
+    assert!(error.contains("block code is longer"), "{error}");
+
+    // the error points at the first character past the limit
+    let long_line = format!("data_test\n_entry.title  {}\n", "c".repeat(2048));
+    let error = parse_error(&long_line, Dialect::Cif1).unwrap();
+    assert!(error.starts_with("Parse error at l2:c2049"), "{error}");
+    assert!(
+        error.contains("line is longer than the maximum of 2048"),
+        "{error}"
+    );
+
+    // CIF 1.1 is ASCII only
+    assert!(parse_error("data_test\n_entry.title  'café'\n", Dialect::Cif1).is_some());
+}
+
+#[test]
+fn test_cif2_allows_long_names_and_lists_but_not_empty_loops() {
+    let input = format!("data_test\n_{}  [1 2 {{'a':3}}]\n", "a".repeat(100));
+    assert_eq!(parse_error(&input, Dialect::Cif2), None);
+    assert_eq!(parse_error(DATA_OUTSIDE_SAVEFRAME, Dialect::Cif2), None);
+
+    assert!(parse_error(EMPTY_LOOP, Dialect::Cif2)
+        .unwrap()
+        .starts_with("Parse error at l4:c1"));
+
+    let long_line = format!("data_test\n_entry.title  {}\n", "é".repeat(2049));
+    let error = parse_error(&long_line, Dialect::Cif2).unwrap();
+    assert!(error.contains("line is longer"), "{error}");
+}
+
+#[test]
+fn test_nmr_star_requires_saveframes_and_stop() {
+    assert_eq!(parse_error(NMR_STAR, Dialect::NmrStar), None);
+
+    let error = parse_error(DATA_OUTSIDE_SAVEFRAME, Dialect::NmrStar).unwrap();
+    assert!(error.starts_with("Parse error at l2:c1"), "{error}");
+    assert!(error.contains("data outside a save frame"), "{error}");
+
+    let error = parse_error(LOOP_WITHOUT_STOP, Dialect::NmrStar).unwrap();
+    assert!(error.starts_with("Parse error at l4:c1"), "{error}");
+    assert!(error.contains("not terminated by stop_"), "{error}");
+
+    let error = parse_error(EMPTY_LOOP, Dialect::NmrStar).unwrap();
+    assert!(error.contains("empty loops are not allowed"), "{error}");
+}
+
+#[test]
+fn test_nef_allows_empty_loops() {
+    assert_eq!(parse_error(NMR_STAR, Dialect::Nef), None);
+    assert_eq!(parse_error(EMPTY_LOOP, Dialect::Nef), None);
+
+    assert!(parse_error(DATA_OUTSIDE_SAVEFRAME, Dialect::Nef)
+        .unwrap()
+        .contains("data outside a save frame"));
+    assert!(parse_error(LOOP_WITHOUT_STOP, Dialect::Nef)
+        .unwrap()
+        .contains("not terminated by stop_"));
+}
+
+#[test]
+fn test_strict_mode_rejects_delimiters_in_heading_codes() {
+    let mut strict = default_config();
+    strict.insert(ConfigKey::Strict, ConfigValue::Bool(true));
+    for (input, position, message) in [
+        (
+            "data_\"ABC\n_entry.id  1\n",
+            "l1:c6",
+            "character '\"' is not allowed in a block code",
+        ),
+        (
+            "data_test\n_entry.id  1\nsave_'ABC\n_frame.id  1\nsave_\n",
+            "l3:c6",
+            "character '\\'' is not allowed in a frame code",
+        ),
+        (
+            "data_test#1\n_entry.id  1\n",
+            "l1:c10",
+            "character '#' is not allowed in a block code",
+        ),
+    ] {
+        // the grammar takes everything up to the next blank as the code
+        assert!(parse(input, &default_config()).is_ok(), "{input}");
+
+        let error = parse(input, &strict).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::E0028InvalidHeadingCharacter);
+        let error = error.format_error(ErrorFormatMode::Basic, 0);
+        assert!(
+            error.starts_with(&format!("Parse error at {position}")),
+            "{error}"
+        );
+        assert!(error.contains(message), "{error}");
+    }
+
+    // quotes in values are still delimiters
+    assert!(parse("data_test\n_entry.id  'A\"B'\n", &strict).is_ok());
+}
+
+#[test]
+fn test_data_heading_without_block_code_is_reported_at_the_heading() {
+    for (input, position) in [
+        ("data_\n_entry.id  1\n", "l1:c1"),
+        ("data_ok\n_entry.id  1\ndata_\n_entry.id  2\n", "l3:c1"),
+    ] {
+        let error = parse(input, &default_config()).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::E0027EmptyBlockCode);
+        let error = error.format_error(ErrorFormatMode::Basic, 0);
+        assert!(
+            error.starts_with(&format!("Parse error at {position}")),
+            "{error}"
+        );
+        assert!(error.contains("data_ heading has no block code"), "{error}");
+    }
+}
+
+/// Small files of the downloaded corpora, parsing every file of them takes minutes
+const DIALECT_EXAMPLES: &[(&str, &[&str], Dialect)] = &[
+    (
+        "tests/test_data/nef_examples",
+        &["CCPN_Commented_Example.nef", "XplorNIH-simple.nef"],
+        Dialect::Nef,
+    ),
+    (
+        "tests/test_data/bmrb_stars",
+        &["bmr51928_3.str", "bmr1544_3.str"],
+        Dialect::NmrStar,
+    ),
+];
+
+#[test]
+fn test_example_files_parse_with_their_dialect() {
+    for &(directory, files, dialect) in DIALECT_EXAMPLES {
+        let directory = Path::new(directory);
+        ensure_test_data_available(directory, TestDataPolicy::DownloadIfMissing)
+            .expect("Failed to verify test data integrity for dialect examples");
+
+        for file in files {
+            let path = directory.join(file);
+            let input = std::fs::read_to_string(&path).unwrap();
+            if let Some(error) = parse_error(&input, dialect) {
+                panic!("{}: {}", path.display(), error);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_presets_set_grammar_keys() {
+    let cif2 = ParserConfig::preset(Dialect::Cif2);
+    assert_eq!(
+        cif2.get(&ConfigKey::CifVersion),
+        Some(&ConfigValue::CifVersion(CifVersion::Cif2))
+    );
+    assert_eq!(
+        cif2.get(&ConfigKey::Encoding),
+        Some(&ConfigValue::Encoding(EncodingMode::Unicode))
+    );
+    assert_eq!(
+        ParserConfig::preset(Dialect::Cif1).get(&ConfigKey::Encoding),
+        Some(&ConfigValue::Encoding(EncodingMode::Ascii))
+    );
+}
+
+#[test]
+fn test_restrictions_can_be_set_individually() {
+    let mut config = default_config();
+    assert!(parse(EMPTY_LOOP, &config).is_ok());
+
+    config.insert(ConfigKey::AllowEmptyLoops, ConfigValue::Bool(false));
+    assert!(parse(EMPTY_LOOP, &config).is_err());
+
+    let mut config = default_config();
+    config.insert(ConfigKey::MaxLineLength, ConfigValue::Usize(10));
+    let error = parse(DATA_OUTSIDE_SAVEFRAME, &config).unwrap_err();
+    assert!(error
+        .format_error(ErrorFormatMode::Basic, 0)
+        .starts_with("Parse error at l2:c11"));
+}
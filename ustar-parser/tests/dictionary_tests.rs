@@ -0,0 +1,267 @@
+use indoc::indoc;
+use std::fs;
+use ustar::dictionary::{validate_against, Dictionary, Primitive, ValueKind};
+use ustar::line_column_index::LineColumn;
+use ustar::validate::{ValidationIssue, ValidationIssueKind};
+use ustar::{parse_default, ErrorCode};
+
+const NEF_DICTIONARY: &str = "tests/test_data/dicts/mmcif_nef.dic";
+const NEF_EXAMPLE: &str = "tests/test_data/nef_examples/1pqx.nef";
+
+/// A small DDL2 dictionary with one category of four items
+const DICTIONARY: &str = indoc! {"
+    data_test.dic
+    loop_
+    _item_type_list.code
+    _item_type_list.primitive_code
+    _item_type_list.construct
+    code   uchar '[A-Za-z0-9]*'
+    int    numb  '[+-]?[0-9]+'
+    float  numb  '-?[0-9.]+'
+    yyyy-mm-dd char '[0-9-]+'
+    stop_
+
+    save_sample
+    _category.id              sample
+    _category.mandatory_code  yes
+    save_
+
+    save__sample.id
+    _item.name            '_sample.id'
+    _item.category_id     sample
+    _item.mandatory_code  yes
+    _item_type.code       int
+    save_
+
+    save__sample.mass
+    _item.name            '_sample.mass'
+    _item.category_id     sample
+    _item.mandatory_code  no
+    _item_type.code       float
+    save_
+
+    save__sample.state
+    _item.name            '_sample.state'
+    _item.category_id     sample
+    _item.mandatory_code  no
+    _item_type.code       code
+    loop_
+    _item_enumeration.value
+    solid
+    liquid
+    stop_
+    save_
+
+    save__sample.date
+    _item.name            '_sample.date'
+    _item.category_id     sample
+    _item.mandatory_code  no
+    _item_type.code       yyyy-mm-dd
+    save_
+"};
+
+fn issues(input: &str) -> Vec<ValidationIssue> {
+    let dictionary = Dictionary::parse(DICTIONARY).unwrap();
+    validate_against(&dictionary, &parse_default(input).unwrap())
+}
+
+fn kinds(issues: &[ValidationIssue]) -> Vec<ValidationIssueKind> {
+    issues.iter().map(|issue| issue.kind).collect()
+}
+
+#[test]
+fn test_dictionary_definitions() {
+    let dictionary = Dictionary::parse(DICTIONARY).unwrap();
+
+    assert!(dictionary.category("SAMPLE").unwrap().mandatory);
+    let state = dictionary.item("_Sample.State").unwrap();
+    assert_eq!(state.category_id, "sample");
+    assert!(!state.mandatory);
+    assert_eq!(state.enumeration, vec!["solid", "liquid"]);
+    let state_type = dictionary.item_type(state).unwrap();
+    assert_eq!(
+        (state_type.primitive, state_type.kind),
+        (Primitive::Uchar, ValueKind::Text)
+    );
+    assert_eq!(dictionary.types["yyyy-mm-dd"].kind, ValueKind::Date);
+    let mandatory: Vec<_> = dictionary
+        .mandatory_items("sample")
+        .map(|item| item.name.as_str())
+        .collect();
+    assert_eq!(mandatory, vec!["_sample.id"]);
+}
+
+#[test]
+fn test_valid_values() {
+    let input = indoc! {"
+        data_ok
+        _sample.id     1
+        _sample.mass   1.5e3
+        _sample.state  SOLID
+        _sample.date   2024-02-29:12:30
+        save_frame
+        loop_
+        _sample.id
+        _sample.mass
+        _sample.date
+        2 .  ?
+        3 '7' 1999-1-2
+        stop_
+        save_
+    "};
+
+    assert_eq!(issues(input), vec![]);
+}
+
+#[test]
+fn test_unknown_tag() {
+    let input = indoc! {"
+        data_test
+        _sample.id      1
+        _sample.colour  red
+    "};
+
+    assert_eq!(
+        issues(input),
+        vec![ValidationIssue {
+            kind: ValidationIssueKind::UnknownTag,
+            name: "_sample.colour".to_string(),
+            position: LineColumn::new(3, 1),
+            first_position: None,
+            detail: None,
+        }]
+    );
+}
+
+#[test]
+fn test_type_mismatches() {
+    let input = indoc! {"
+        data_test
+        loop_
+        _sample.id
+        _sample.mass
+        _sample.date
+        1.0  heavy  2024-02-29
+        2    3      29/02/2024
+        stop_
+    "};
+
+    let found = issues(input);
+    let mismatches: Vec<_> = found
+        .iter()
+        .map(|issue| (issue.kind, issue.name.as_str(), issue.position))
+        .collect();
+    assert_eq!(
+        mismatches,
+        vec![
+            (
+                ValidationIssueKind::TypeMismatch,
+                "_sample.id",
+                LineColumn::new(6, 1)
+            ),
+            (
+                ValidationIssueKind::TypeMismatch,
+                "_sample.mass",
+                LineColumn::new(6, 6)
+            ),
+            (
+                ValidationIssueKind::TypeMismatch,
+                "_sample.date",
+                LineColumn::new(7, 13)
+            ),
+        ]
+    );
+    assert_eq!(
+        found[1].to_string(),
+        "l6:c6 _sample.mass has a value of the wrong type, expected a number of type float but found heavy"
+    );
+}
+
+#[test]
+fn test_enumeration_violation() {
+    let input = indoc! {"
+        data_test
+        _sample.id     1
+        _sample.state  gas
+    "};
+
+    let found = issues(input);
+    assert_eq!(
+        kinds(&found),
+        vec![ValidationIssueKind::EnumerationViolation]
+    );
+    assert_eq!(found[0].position, LineColumn::new(3, 16));
+    assert_eq!(
+        found[0].message(),
+        "_sample.state has a value outside its enumeration, expected one of solid, liquid but found gas"
+    );
+    assert_eq!(found[0].kind.code(), ErrorCode::E0025EnumerationViolation);
+}
+
+#[test]
+fn test_missing_mandatory_item_in_each_scope() {
+    let input = indoc! {"
+        data_test
+        _sample.mass  1.0
+        save_frame
+        _sample.id    1
+        save_
+        save_other
+        loop_
+        _sample.state
+        solid
+        stop_
+        save_
+    "};
+
+    let found = issues(input);
+    let missing: Vec<_> = found
+        .iter()
+        .map(|issue| (issue.kind, issue.name.as_str(), issue.position))
+        .collect();
+    assert_eq!(
+        missing,
+        vec![
+            (
+                ValidationIssueKind::MissingMandatoryItem,
+                "_sample.id",
+                LineColumn::new(2, 1)
+            ),
+            (
+                ValidationIssueKind::MissingMandatoryItem,
+                "_sample.id",
+                LineColumn::new(8, 1)
+            ),
+        ]
+    );
+    assert_eq!(
+        found[0].message(),
+        "mandatory item _sample.id of category sample is missing"
+    );
+}
+
+#[test]
+fn test_nef_example_against_the_nef_dictionary() {
+    let dictionary = Dictionary::parse(&fs::read_to_string(NEF_DICTIONARY).unwrap()).unwrap();
+    let example = fs::read_to_string(NEF_EXAMPLE).unwrap();
+
+    // the example predates the element and isotope number columns of chemical shifts
+    let found = validate_against(&dictionary, &parse_default(&example).unwrap());
+    assert_eq!(
+        kinds(&found),
+        vec![ValidationIssueKind::MissingMandatoryItem; 2]
+    );
+    assert_eq!(found[0].name, "_nef_chemical_shift.element");
+
+    // a letter O for a zero makes a chemical shift text
+    let broken = example.replacen("170.978", "17O.978", 1);
+    let broken_issues = validate_against(&dictionary, &parse_default(&broken).unwrap());
+    let mismatches: Vec<_> = broken_issues
+        .iter()
+        .filter(|issue| issue.kind == ValidationIssueKind::TypeMismatch)
+        .collect();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].name, "_nef_chemical_shift.value");
+    assert_eq!(mismatches[0].position, LineColumn::new(141, 50));
+    assert_eq!(broken_issues.len(), found.len() + 1);
+}
@@ -0,0 +1,212 @@
+use indoc::indoc;
+use ustar::diff::{diff_documents, DiffEntry, DiffKind, DiffOptions};
+use ustar::line_column_index::LineColumn;
+use ustar::parse_default;
+
+mod snapshot_utils;
+
+ustar_test_utils::snapshot_orphan_check!("diff_tests");
+
+const BEFORE: &str = indoc! {"
+    data_nef_test
+    save_nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_category      nef_nmr_meta_data
+       _nef_nmr_meta_data.format_version   1.0
+       _nef_nmr_meta_data.program_name     'CcpNmr Analysis'
+       _nef_nmr_meta_data.coordinate_file_name  .
+    save_
+    save_nef_molecular_system
+       _nef_molecular_system.sf_category   nef_molecular_system
+       loop_
+          _nef_sequence.index
+          _nef_sequence.chain_code
+          _nef_sequence.sequence_code
+          _nef_sequence.residue_name
+          1  A  1  MET
+          2  A  2  PRO
+          3  A  3  GLY
+       stop_
+    save_
+    save_nef_chemical_shift_list_1
+       _nef_chemical_shift_list.sf_category  nef_chemical_shift_list
+       loop_
+          _nef_chemical_shift.chain_code _nef_chemical_shift.sequence_code
+          _nef_chemical_shift.atom_name _nef_chemical_shift.value
+          A 1 H 8.10
+          A 2 N 120.3
+          A 3 HA 4.20
+       stop_
+    save_
+"};
+
+/// BEFORE reformatted, with the shift list rows reordered, a changed item, residue and shift,
+/// an added row and item and a removed item and save frame
+const AFTER: &str = indoc! {"
+    # written by another program
+    data_NEF_TEST
+
+    save_nef_chemical_shift_list_1
+        _nef_chemical_shift_list.sf_category  nef_chemical_shift_list
+        loop_
+            _nef_chemical_shift.chain_code
+            _nef_chemical_shift.sequence_code
+            _nef_chemical_shift.atom_name
+            _nef_chemical_shift.value
+            A 3 HA 4.25
+            A 1 H 8.10
+            A 2 N 120.3
+            A 4 H 7.95
+        stop_
+    save_
+
+    save_nef_nmr_meta_data
+        _nef_nmr_meta_data.sf_category  nef_nmr_meta_data
+        _nef_nmr_meta_data.format_version  1.1
+        _nef_nmr_meta_data.program_name  \"CcpNmr Analysis\"
+        _nef_nmr_meta_data.program_version  3.1
+    save_
+
+    save_nef_molecular_system
+        _nef_molecular_system.sf_category  nef_molecular_system
+        loop_
+            _nef_sequence.index _nef_sequence.chain_code
+            _nef_sequence.sequence_code _nef_sequence.residue_name
+            1 A 1 MET
+            2 A 2 ALA
+            3 A 3 GLY
+            4 A 4 HIS
+        stop_
+    save_
+
+    save_nef_distance_restraint_list_1
+        _nef_distance_restraint_list.sf_category  nef_distance_restraint_list
+    save_
+"};
+
+fn diff(a: &str, b: &str, options: DiffOptions) -> Vec<DiffEntry> {
+    diff_documents(
+        &parse_default(a).unwrap(),
+        &parse_default(b).unwrap(),
+        options,
+    )
+}
+
+fn report(entries: &[DiffEntry]) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
+#[test]
+fn test_nef_variants_with_key_columns() {
+    let options = DiffOptions::default().with_key_columns(&[
+        "_nef_chemical_shift.chain_code",
+        "_nef_chemical_shift.sequence_code",
+        "_nef_chemical_shift.atom_name",
+    ]);
+    let entries = diff(BEFORE, AFTER, options);
+    snapshot_utils::assert_snapshot_gz("diff_tests__nef_variants", &report(&entries));
+
+    assert_eq!(
+        entries[0],
+        DiffEntry {
+            kind: DiffKind::Changed,
+            path: "data_nef_test/save_nef_nmr_meta_data/_nef_nmr_meta_data.format_version"
+                .to_string(),
+            old: Some("1.0".to_string()),
+            new: Some("1.1".to_string()),
+            old_position: Some(LineColumn::new(4, 4)),
+            new_position: Some(LineColumn::new(20, 5)),
+        }
+    );
+    // only the quotes of the program name differ
+    assert!(!entries
+        .iter()
+        .any(|entry| entry.path.ends_with("program_name")));
+}
+
+#[test]
+fn test_rows_are_matched_by_position_without_key_columns() {
+    let entries = diff(BEFORE, AFTER, DiffOptions::default());
+    let shifts: Vec<String> = entries
+        .iter()
+        .filter(|entry| entry.path.contains("_nef_chemical_shift."))
+        .map(|entry| entry.to_string())
+        .collect();
+    assert_eq!(
+        shifts,
+        [
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.sequence_code[1] 1 -> 3 (l25:c9, l11:c11)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.atom_name[1] H -> HA (l25:c11, l11:c13)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.value[1] 8.10 -> 4.25 (l25:c13, l11:c16)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.sequence_code[2] 2 -> 1 (l26:c9, l12:c11)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.atom_name[2] N -> H (l26:c11, l12:c13)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.value[2] 120.3 -> 8.10 (l26:c13, l12:c15)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.sequence_code[3] 3 -> 2 (l27:c9, l13:c11)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.atom_name[3] HA -> N (l27:c11, l13:c13)",
+            "~ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.value[3] 4.20 -> 120.3 (l27:c14, l13:c15)",
+            "+ data_nef_test/save_nef_chemical_shift_list_1/_nef_chemical_shift.chain_code[4] A 4 H 7.95 (l14:c9)",
+        ]
+    );
+}
+
+#[test]
+fn test_identical_documents_have_no_differences() {
+    assert!(diff(BEFORE, BEFORE, DiffOptions::default()).is_empty());
+
+    let reformatted = BEFORE
+        .replace("   ", "\t")
+        .replace("'CcpNmr Analysis'", "\"CcpNmr Analysis\"");
+    assert!(diff(BEFORE, &reformatted, DiffOptions::default()).is_empty());
+}
+
+#[test]
+fn test_columns_loops_and_blocks() {
+    let a = indoc! {"
+        data_a
+        loop_
+            _atom.id _atom.name
+            1 CA
+        stop_
+        loop_
+            _bond.id
+            1 2
+        stop_
+        data_b
+        _entry.id b
+    "};
+    let b = indoc! {"
+        data_a
+        loop_
+            _atom.id _atom.type
+            1 C
+        stop_
+        data_c
+        _entry.id c
+    "};
+    assert_eq!(
+        report(&diff(a, b, DiffOptions::default())),
+        indoc! {"
+            - data_a/_atom.name (l2:c1)
+            + data_a/_atom.type (l2:c1)
+            - data_a/_bond.id loop_ _bond.id (l6:c1)
+            - data_b (l10:c1)
+            + data_c (l6:c1)
+        "}
+    );
+
+    // save frames diff on their own
+    let a = parse_default(BEFORE).unwrap();
+    let b = parse_default(AFTER).unwrap();
+    let entries = diff_documents(
+        a.find_all("save_frame")[0],
+        b.find_all("save_frame")[1],
+        DiffOptions::default(),
+    );
+    assert_eq!(
+        report(&entries),
+        indoc! {"
+            ~ _nef_nmr_meta_data.format_version 1.0 -> 1.1 (l4:c4, l20:c5)
+            - _nef_nmr_meta_data.coordinate_file_name . (l6:c4)
+            + _nef_nmr_meta_data.program_version 3.1 (l22:c5)
+        "}
+    );
+}
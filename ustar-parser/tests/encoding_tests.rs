@@ -1,5 +1,16 @@
 use std::collections::HashMap;
-use ustar::{default_config, parse, ConfigKey, ConfigValue, EncodingMode, ParserConfig};
+use std::io::Write;
+use ustar::{
+    default_config, parse, parse_bytes, read_star_file, ConfigKey, ConfigValue, EncodingMode,
+    ErrorCode, ParserConfig,
+};
+
+/// Write `bytes` to a temporary file for `read_star_file`
+fn star_file_with(bytes: &[u8]) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(bytes).unwrap();
+    file
+}
 
 #[test]
 fn test_ascii_mode_basic() {
@@ -64,3 +75,222 @@ fn test_parse_default_convenience() {
     let result = ustar::parse_default(input);
     assert!(result.is_ok(), "parse_default should work for basic input");
 }
+
+#[test]
+fn test_read_star_file_decodes_utf16_and_keeps_utf8_bom() {
+    let text = "data_test\n_item αβ\n";
+    let utf16_le: Vec<u8> = "\u{FEFF}data_test\n_item αβ\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let utf16_be: Vec<u8> = "\u{FEFF}data_test\n_item αβ\n"
+        .encode_utf16()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    let utf8_bom = format!("\u{FEFF}{}", text);
+    let mut config = default_config();
+    config.insert(ConfigKey::AutoDetectBom, ConfigValue::Bool(true));
+
+    for bytes in [utf16_le, utf16_be, utf8_bom.clone().into_bytes()] {
+        let file = star_file_with(&bytes);
+        let read = read_star_file(file.path(), &config).unwrap();
+        assert_eq!(read, utf8_bom);
+        assert!(parse(&read, &config).is_ok());
+    }
+}
+
+#[test]
+fn test_read_star_file_reads_latin1_as_extended_ascii() {
+    let file = star_file_with(b"data_test\n_item caf\xe9\n");
+
+    let error = read_star_file(file.path(), &default_config()).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::E0006InvalidCharacter);
+    assert_eq!((error.core().line, error.core().col), (2, 10));
+
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::ExtendedAscii),
+    );
+    let read = read_star_file(file.path(), &config).unwrap();
+    assert_eq!(read, "data_test\n_item café\n");
+}
+
+#[test]
+fn test_read_star_file_reports_unreadable_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let missing = directory.path().join("missing.cif");
+
+    let error = read_star_file(&missing, &default_config()).unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::E0019UnreadableFile);
+    assert_eq!(
+        error.core().source_name.as_deref(),
+        Some(missing.display().to_string().as_str())
+    );
+}
+
+/// A COD-style CIF with `é` and `°` as the single Latin-1 bytes 0xE9 and 0xB0
+const COD_LATIN1: &[u8] = b"data_1000000\n\
+_publ_author_name 'Andr\xe9, G.'\n\
+_cell_measurement_temperature 293\n\
+_diffrn_ambient_temperature_details '20 \xb0C'\n\
+_publ_section_title\n\
+;\n\
+Structure of \x93caf\xe9\x94 crystals\n\
+;\n";
+
+fn extended_ascii_config() -> ParserConfig {
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::ExtendedAscii),
+    );
+    config
+}
+
+#[test]
+fn test_parse_bytes_reads_latin1_and_windows_1252() {
+    let tree = parse_bytes(COD_LATIN1, &extended_ascii_config()).unwrap();
+
+    let text = "data_1000000\n\
+                _publ_author_name 'André, G.'\n\
+                _cell_measurement_temperature 293\n\
+                _diffrn_ambient_temperature_details '20 °C'\n\
+                _publ_section_title\n\
+                ;\n\
+                Structure of \u{201C}café\u{201D} crystals\n\
+                ;\n";
+    assert_eq!(tree, parse(text, &extended_ascii_config()).unwrap());
+    let values: Vec<&str> = tree
+        .find_all("string")
+        .iter()
+        .map(|value| value.as_str())
+        .collect();
+    assert!(values.contains(&"André, G."), "{:?}", values);
+    assert!(values.contains(&"20 °C"), "{:?}", values);
+}
+
+#[test]
+fn test_parse_bytes_reports_errors_at_original_bytes() {
+    // the unterminated quote follows two single byte characters that are two bytes in UTF-8
+    let bytes = b"data_test\n_name caf\xe9\n_unit \xb0 'C\n";
+    let quote = bytes.iter().position(|&byte| byte == b'\'').unwrap();
+
+    let error = parse_bytes(bytes, &extended_ascii_config()).unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::E0002UnterminatedQuote);
+    assert_eq!(error.core().offset, quote);
+    assert_eq!((error.core().line, error.core().col), (3, 9));
+}
+
+#[test]
+fn test_parse_bytes_needs_extended_ascii_for_bytes_that_are_not_utf8() {
+    let text = "data_test\n_item value\n";
+    assert_eq!(
+        parse_bytes(text.as_bytes(), &default_config()).unwrap(),
+        parse(text, &default_config()).unwrap()
+    );
+
+    let error = parse_bytes(COD_LATIN1, &default_config()).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::E0006InvalidCharacter);
+    assert_eq!(
+        error.core().message,
+        "byte 36 of the input is not valid UTF-8"
+    );
+}
+
+/// `text` with a byte order mark in UTF-8, UTF-16LE and UTF-16BE
+fn with_each_bom(text: &str) -> [Vec<u8>; 3] {
+    let with_bom = format!("\u{FEFF}{}", text);
+    [
+        with_bom.clone().into_bytes(),
+        with_bom.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        with_bom.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+    ]
+}
+
+#[test]
+fn test_parse_bytes_gives_one_tree_for_each_bom() {
+    let mut config = default_config();
+    config.insert(ConfigKey::AutoDetectBom, ConfigValue::Bool(true));
+    let [utf8, utf16_le, utf16_be] = with_each_bom("data_test\n_item αβ\nloop_ _a 1 'é' stop_\n");
+
+    let tree = parse_bytes(&utf8, &config).unwrap();
+    assert_eq!(parse_bytes(&utf16_le, &config).unwrap(), tree);
+    assert_eq!(parse_bytes(&utf16_be, &config).unwrap(), tree);
+}
+
+#[test]
+fn test_parse_bytes_notes_the_utf16_encoding_in_errors() {
+    let mut config = default_config();
+    config.insert(ConfigKey::AutoDetectBom, ConfigValue::Bool(true));
+    let [utf8, utf16_le, utf16_be] = with_each_bom("data_test\n_item 'αβ\n");
+
+    let expected = parse_bytes(&utf8, &config).unwrap_err();
+    for (bytes, name) in [(utf16_le, "UTF-16LE"), (utf16_be, "UTF-16BE")] {
+        let error = parse_bytes(&bytes, &config).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::E0002UnterminatedQuote);
+        assert_eq!(error.core().offset, expected.core().offset);
+        assert_eq!(
+            error.core().message,
+            format!("{} (input decoded from {})", expected.core().message, name)
+        );
+    }
+}
+
+#[test]
+fn test_parse_bytes_only_decodes_utf16_when_detecting_boms() {
+    let [_, utf16_le, _] = with_each_bom("data_test\n_item value\n");
+
+    let error = parse_bytes(&utf16_le, &default_config()).unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::E0006InvalidCharacter);
+    assert_eq!(error.core().offset, 0);
+}
+
+#[test]
+fn test_parse_file_decodes_as_parse_bytes_does() {
+    let mut detect_boms = default_config();
+    detect_boms.insert(ConfigKey::AutoDetectBom, ConfigValue::Bool(true));
+    let [_, utf16_le, _] = with_each_bom("data_test\n_item value\n");
+    let latin1 = b"data_test\n_entry.title 'caf\xe9 \xe9t\xe9'\n_entry.id\n".to_vec();
+
+    for (bytes, config) in [
+        (utf16_le.clone(), detect_boms),
+        (utf16_le, default_config()),
+        (latin1.clone(), extended_ascii_config()),
+        (latin1, default_config()),
+    ] {
+        let file = star_file_with(&bytes);
+        let name = file.path().display().to_string();
+        let from_file = ustar::parse_file(file.path(), &config);
+        match parse_bytes(&bytes, &config) {
+            Ok(tree) => assert_eq!(from_file.unwrap(), tree),
+            Err(expected) => {
+                let error = from_file.unwrap_err();
+                assert_eq!(error.core().source_name.as_deref(), Some(name.as_str()));
+                assert_eq!(error.code(), expected.code());
+                assert_eq!(error.core().offset, expected.core().offset);
+                assert_eq!(error.core().message, expected.core().message);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_extended_ascii_reads_double_quotes_as_ascii_does() {
+    // a " only starts a quoted string, an unclosed one isn't read as an unquoted value
+    for input in ["data_test\n_a \"x\n", "data_test\n_a \"x\"y\n"] {
+        assert!(parse(input, &default_config()).is_err(), "{:?}", input);
+        assert!(
+            parse(input, &extended_ascii_config()).is_err(),
+            "{:?}",
+            input
+        );
+    }
+
+    let input = "data_test\n_a 'it\"s'\n_b \"x'y\"\n_c x\"y\n";
+    let ascii = parse(input, &default_config()).unwrap();
+    assert_eq!(parse(input, &extended_ascii_config()).unwrap(), ascii);
+}
@@ -1,7 +1,16 @@
-use ustar::{default_config, parse, ErrorFormatMode};
+use pest::Parser;
+use ustar::mutable_pair::MutablePair;
+use ustar::parsers::ascii::{AsciiParser, Rule};
+use ustar::{
+    default_config, format_parse_error, parse, parse_file, parse_with_recovery, ColumnUnit,
+    ConfigKey, ConfigValue, Dialect, DialectPreset, EncodingMode, ErrorCode, ErrorFormatMode,
+    ParserConfig, UstarError,
+};
 
 mod snapshot_utils;
 
+ustar_test_utils::snapshot_orphan_check!("error_handling_tests");
+
 /// Test data with various parsing errors for snapshot testing
 const ERROR_CASES: &[(&str, &str)] = &[
     (
@@ -96,3 +105,380 @@ fn test_ascii_error_format_snapshots() {
 fn test_fancy_error_format_snapshots() {
     test_error_format_mode(ErrorFormatMode::Fancy, 3, "fancy_error");
 }
+
+/// Bad inputs with the code of their error, parsed with the default configuration unless a
+/// dialect is given
+const CODED_ERROR_CASES: &[(&str, &str, Option<Dialect>, ErrorCode)] = &[
+    (
+        "unterminated_quote",
+        "data_test\n_entry.id  1\n_entry.title  'never closed\n_entry.author  x\n",
+        None,
+        ErrorCode::E0002UnterminatedQuote,
+    ),
+    (
+        "runaway_semicolon_string",
+        "data_test\n_entry.details\n;\nthe text field\nis never closed\n",
+        None,
+        ErrorCode::E0003RunawaySemicolonString,
+    ),
+    (
+        "unexpected_end_of_input",
+        "data_test\n_entry.id  1\n_entry.title\n",
+        None,
+        ErrorCode::E0004UnexpectedEndOfInput,
+    ),
+    (
+        "missing_value",
+        "data_test\n_entry.id\n_entry.title  'a title'\n",
+        None,
+        ErrorCode::E0005MissingValue,
+    ),
+    (
+        "invalid_character",
+        "data_test\n_entry.title  café\n",
+        None,
+        ErrorCode::E0006InvalidCharacter,
+    ),
+    (
+        "unexpected_token",
+        "_entry.id  1\n",
+        None,
+        ErrorCode::E0001UnexpectedToken,
+    ),
+    (
+        "empty_block_code",
+        "data_\n_entry.id  1\n",
+        None,
+        ErrorCode::E0027EmptyBlockCode,
+    ),
+    (
+        "empty_loop",
+        "data_test\nloop_\n_row.id\n_row.value\nstop_\n",
+        Some(Dialect::Cif1),
+        ErrorCode::E0012EmptyLoop,
+    ),
+];
+
+#[test]
+fn test_error_codes_and_json_format() {
+    for (case_name, input, dialect, code) in CODED_ERROR_CASES {
+        let config = match dialect {
+            Some(dialect) => ParserConfig::preset(*dialect),
+            None => default_config(),
+        };
+
+        let error = parse(input, &config).unwrap_err();
+        assert_eq!(error.code(), *code, "{}", case_name);
+
+        let json = error.format_error(ErrorFormatMode::Json, 0);
+        #[cfg(feature = "serde")]
+        serde_json::from_str::<serde_json::Value>(&json).expect("JSON error output should parse");
+        snapshot_utils::assert_snapshot_gz(
+            &format!("error_handling_tests__json_error_{}", case_name),
+            &json,
+        );
+    }
+}
+
+#[test]
+fn test_validation_errors_have_codes() {
+    let mut config = default_config();
+    config.insert(ConfigKey::Validate, ConfigValue::Bool(true));
+    let input = "data_test\n_entry.id  1\n_entry.frame  $missing\n";
+
+    let error = parse(input, &config).unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::E0018DanglingFrameCode);
+    assert!(error
+        .format_error(ErrorFormatMode::Json, 0)
+        .starts_with("{\"code\":\"E0018\",\"message\":\"frame code $missing"));
+}
+
+#[test]
+fn test_runaway_semicolon_string_is_reported_at_opener() {
+    let input = indoc::indoc! {"
+        data_test
+        _entry.id       1
+        _entry.details
+        ;
+        the text field
+        is never closed
+        _entry.author   'Smith, J.'
+    "};
+
+    let error = parse(input, &default_config()).unwrap_err();
+
+    assert!(matches!(*error, UstarError::RunawaySemicolonString { .. }));
+    assert_eq!(error.code(), ErrorCode::E0003RunawaySemicolonString);
+    assert_eq!((error.core().line, error.core().col), (4, 1));
+    assert_eq!(
+        error.format_error(ErrorFormatMode::Basic, 0),
+        "Parse error at l4:c1 because semicolon-delimited string opened here is never closed\n"
+    );
+    #[cfg(feature = "extended-errors")]
+    snapshot_utils::assert_snapshot_gz(
+        "error_handling_tests__fancy_error_runaway_semicolon_string",
+        &error.format_error(ErrorFormatMode::Fancy, 3),
+    );
+}
+
+#[test]
+#[cfg(feature = "extended-errors")]
+fn test_fancy_error_labels_data_name_without_value() {
+    let input = "data_test\n_test _test\n";
+    let multi_label = *parse(input, &default_config()).unwrap_err();
+    assert_eq!(multi_label.core().data_name_span, Some((10, 5)));
+
+    let mut single_label = multi_label.clone();
+    if let UstarError::ParseError { data_name_span, .. } = &mut single_label {
+        *data_name_span = None;
+    }
+
+    snapshot_utils::assert_snapshot_gz(
+        "error_handling_tests__fancy_error_single_label",
+        &single_label.format_error(ErrorFormatMode::Fancy, 1),
+    );
+    snapshot_utils::assert_snapshot_gz(
+        "error_handling_tests__fancy_error_multi_label",
+        &multi_label.format_error(ErrorFormatMode::Fancy, 1),
+    );
+}
+
+#[test]
+fn test_parse_file_names_the_file_in_errors() {
+    let mut file = tempfile::Builder::new().suffix(".cif").tempfile().unwrap();
+    std::io::Write::write_all(
+        &mut file,
+        b"data_test\n_entry.id\n_entry.title  'a title'\n",
+    )
+    .unwrap();
+    let name = file.path().display().to_string();
+
+    let error = parse_file(file.path(), &default_config()).unwrap_err();
+
+    assert_eq!(error.core().source_name.as_deref(), Some(name.as_str()));
+    assert!(error
+        .format_error(ErrorFormatMode::Basic, 0)
+        .starts_with(&format!("Parse error in {} at l3:c1 because", name)));
+    assert!(error
+        .format_error(ErrorFormatMode::Ascii, 0)
+        .contains(&format!(" --> {}:3:1\n", name)));
+    assert!(error
+        .format_error(ErrorFormatMode::Json, 0)
+        .contains(&format!("\"source\":\"{}\"", name)));
+    assert!(error
+        .format_error(ErrorFormatMode::Fancy, 1)
+        .contains(&format!("{}:3:1", name)));
+}
+
+/// Input with three independent errors separated by valid blocks, a save frame and a loop
+const RECOVERY_INPUT: &str = indoc::indoc! {"
+    data_first
+    _a.x  1
+    _a.y  \"unclosed
+    _a.z  3
+
+    data_second
+    _b.x  1
+    _b.y
+    save_frame_one
+    _c.x  'ok'
+    save_
+    loop_
+    _d.x
+    _d.y
+    1 2
+    3 4
+
+    data_third
+    _e.x  2
+    _e.y  'bad'value
+    _e.z  3
+"};
+
+fn error_lines(errors: &[UstarError]) -> Vec<String> {
+    errors
+        .iter()
+        .map(|error| {
+            let basic = error.format_error(ErrorFormatMode::Basic, 0);
+            basic.split(':').next().unwrap().to_string()
+        })
+        .collect()
+}
+
+fn rule_names(pair: &MutablePair) -> Vec<&str> {
+    pair.children
+        .iter()
+        .map(|child| child.rule_name())
+        .collect()
+}
+
+#[test]
+fn test_recovery_reports_each_failed_region() {
+    let (_, errors) = parse_with_recovery(RECOVERY_INPUT, &default_config());
+
+    assert_eq!(
+        error_lines(&errors),
+        vec![
+            "Parse error at l3",
+            "Parse error at l9",
+            "Parse error at l20"
+        ]
+    );
+}
+
+#[test]
+fn test_recovery_keeps_everything_that_parsed() {
+    let (tree, _) = parse_with_recovery(RECOVERY_INPUT, &default_config());
+    let tree = tree.expect("valid blocks should be recovered");
+
+    assert_eq!(tree.rule_name(), "star_file");
+    assert_eq!(
+        rule_names(&tree),
+        vec!["data_block", "data_block", "data_block", "EOI"]
+    );
+
+    let second = &tree.children[1];
+    assert_eq!(
+        rule_names(second),
+        vec!["data_heading", "data", "save_frame", "data"]
+    );
+    assert_eq!(
+        rule_names(&second.children[2]),
+        vec!["save_heading", "data", "save_keyword"]
+    );
+    assert_eq!(second.children[3].as_str(), "loop_\n_d.x\n_d.y\n1 2\n3 4");
+
+    let third = &tree.children[2];
+    assert_eq!(third.children[1].as_str(), "_e.x  2");
+}
+
+#[test]
+fn test_recovery_of_valid_input_matches_parse() {
+    let input = indoc::indoc! {"
+        data_test
+        _entry.id  1ABC
+        save_frame
+        _item.name 'value'
+        save_
+    "};
+    let config = default_config();
+
+    let (tree, errors) = parse_with_recovery(input, &config);
+
+    assert!(errors.is_empty());
+    assert_eq!(tree, Some(parse(input, &config).unwrap()));
+}
+
+#[test]
+fn test_recovery_reports_unterminated_save_frame() {
+    let input = indoc::indoc! {"
+        data_test
+        save_first
+        _item.name 'value'
+        save_second
+        _item.name 'other'
+        save_
+    "};
+
+    let (tree, errors) = parse_with_recovery(input, &default_config());
+
+    assert_eq!(error_lines(&errors), vec!["Parse error at l2"]);
+    let block = &tree.unwrap().children[0];
+    assert_eq!(
+        rule_names(block),
+        vec!["data_heading", "save_frame", "save_frame"]
+    );
+}
+
+#[test]
+fn test_recovery_without_blocks_returns_no_tree() {
+    let (tree, errors) = parse_with_recovery("_item.name value\n", &default_config());
+
+    assert!(tree.is_none());
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_error_column_counts_in_column_unit() {
+    // 'α' takes two bytes, the combining acute accent after it two more but no grapheme
+    let input = "data_test\n_a 'α\u{301}' 'bad'value\n";
+    let error = |unit: ColumnUnit| {
+        let mut config = default_config();
+        config.insert(
+            ConfigKey::Encoding,
+            ConfigValue::Encoding(EncodingMode::Unicode),
+        );
+        config.insert(ConfigKey::ColumnUnit, ConfigValue::ColumnUnit(unit));
+        parse(input, &config).unwrap_err()
+    };
+    let column = |unit: ColumnUnit| {
+        let basic = error(unit).format_error(ErrorFormatMode::Basic, 0);
+        basic.split(' ').nth(3).unwrap().to_string()
+    };
+
+    assert_eq!(column(ColumnUnit::Bytes), "l2:c11");
+    assert_eq!(column(ColumnUnit::Chars), "l2:c9");
+    assert_eq!(column(ColumnUnit::Graphemes), "l2:c8");
+
+    // the caret stays under the error whatever the unit
+    let caret = |unit: ColumnUnit| {
+        let ascii = error(unit).format_error(ErrorFormatMode::Ascii, 0);
+        ascii.lines().last().unwrap().to_string()
+    };
+    assert_eq!(caret(ColumnUnit::Bytes), "  |         ^---");
+    assert_eq!(caret(ColumnUnit::Chars), caret(ColumnUnit::Bytes));
+    assert_eq!(caret(ColumnUnit::Graphemes), caret(ColumnUnit::Bytes));
+}
+
+#[test]
+fn test_pest_errors_are_formatted_without_copying_them() {
+    for input in [
+        "data_test\n_entry.id\n",
+        "data_test\n_entry.id 1\n\tloop_ _a.b\n",
+        "data_test\r\n_entry.id 'open\r\n",
+        "_entry.id 1\n",
+    ] {
+        let pest_error = AsciiParser::parse(Rule::star_file, input).unwrap_err();
+
+        // the pest rendering is rebuilt on display, matching pest's own
+        let error = UstarError::from_pest_error(&pest_error, EncodingMode::Ascii, input);
+        assert_eq!(error.core().pest_display(), pest_error.to_string());
+        assert_eq!(
+            format_parse_error(
+                &pest_error,
+                input,
+                EncodingMode::Ascii,
+                ErrorFormatMode::Ascii,
+                1
+            ),
+            UstarError::from_pest_error(pest_error, EncodingMode::Ascii, input)
+                .format_error(ErrorFormatMode::Ascii, 1)
+        );
+    }
+
+    // named sources render as pest renders a path
+    let input = "data_test\n_entry.id\n";
+    let pest_error = AsciiParser::parse(Rule::star_file, input).unwrap_err();
+    let error = UstarError::from_pest_error(&pest_error, EncodingMode::Ascii, input)
+        .with_source_name("entry.str");
+    assert_eq!(
+        error.core().pest_display(),
+        pest_error.with_path("entry.str").to_string()
+    );
+}
+
+#[test]
+fn test_errors_are_small_enough_to_return_unboxed() {
+    // the details are boxed, so clippy's large error limit of 128 bytes is never near
+    assert!(std::mem::size_of::<UstarError>() <= 64);
+
+    let error = *parse("data_test\n_entry.id\n", &default_config()).unwrap_err();
+    assert_eq!(&*error.core().src, "data_test\n_entry.id\n");
+
+    // the fancy errors label the input held by the details, not a copy of it
+    #[cfg(feature = "extended-errors")]
+    if let UstarError::ParseError { src, core, .. } = &error {
+        assert!(std::sync::Arc::ptr_eq(src, &core.src));
+    }
+}
@@ -0,0 +1,275 @@
+use proptest::prelude::*;
+use ustar::line_column_index::LineColumn;
+use ustar::sas_handlers::{
+    DataBlock, Document, DocumentBuilderHandler, Item, Loop, LoopRow, Saveframe, StarWriterHandler,
+};
+use ustar::sas_interface::{SASContentHandler, ValueDelimiter, WalkControl};
+use ustar::sas_walker::StarWalker;
+use ustar::{parse, walk, EncodingMode, ErrorFormatMode, ParserConfig, ParserConfigBuilder};
+use ustar_test_utils::{star_document, Delimiter, StarContent, StarDocument, StarLoop, StarValue};
+
+fn config(encoding: EncodingMode) -> ParserConfig {
+    ParserConfigBuilder::new()
+        .encoding(encoding)
+        .error_format(ErrorFormatMode::Basic)
+        .build()
+        .unwrap()
+}
+
+/// The `Document` a walk of the generated document should collect
+fn expected_document(generated: &StarDocument) -> Document {
+    fn to_loop(star_loop: &StarLoop) -> Loop {
+        Loop {
+            tags: vec![star_loop.tags.clone()],
+            rows: star_loop
+                .rows
+                .iter()
+                .map(|row| LoopRow {
+                    values: row.iter().map(|value| value.text.clone()).collect(),
+                    rows: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn fill(contents: &[StarContent], items: &mut Vec<Item>, loops: &mut Vec<Loop>) {
+        for content in contents {
+            match content {
+                StarContent::Item(item) => items.push(Item {
+                    tag: item.tag.clone(),
+                    value: item.value.text.clone(),
+                }),
+                StarContent::Loop(star_loop) => loops.push(to_loop(star_loop)),
+                StarContent::Saveframe(_) => {}
+            }
+        }
+    }
+
+    let mut document = Document::default();
+    for block in &generated.blocks {
+        let mut data_block = DataBlock {
+            name: block.name.clone().unwrap_or_default(),
+            ..DataBlock::default()
+        };
+        fill(
+            &block.contents,
+            &mut data_block.items,
+            &mut data_block.loops,
+        );
+        for content in &block.contents {
+            if let StarContent::Saveframe(frame) = content {
+                let mut saveframe = Saveframe {
+                    name: frame.name.clone(),
+                    ..Saveframe::default()
+                };
+                fill(&frame.contents, &mut saveframe.items, &mut saveframe.loops);
+                data_block.saveframes.push(saveframe);
+            }
+        }
+        match block.name {
+            Some(_) => document.data_blocks.push(data_block),
+            None => document.global_blocks.push(data_block),
+        }
+    }
+    document
+}
+
+/// The values of the generated document in order, with the delimiter a walk should report
+fn expected_values(generated: &StarDocument) -> Vec<(String, ValueDelimiter)> {
+    fn delimiter(value: &StarValue) -> ValueDelimiter {
+        match value.delimiter {
+            Delimiter::None => ValueDelimiter::None,
+            Delimiter::Single => ValueDelimiter::Single,
+            Delimiter::Double => ValueDelimiter::Double,
+            Delimiter::TripleSingle => ValueDelimiter::TripleSingle,
+            Delimiter::TripleDouble => ValueDelimiter::TripleDouble,
+            Delimiter::Semicolon => ValueDelimiter::Semicolon,
+        }
+    }
+
+    fn collect(contents: &[StarContent], values: &mut Vec<(String, ValueDelimiter)>) {
+        for content in contents {
+            match content {
+                StarContent::Item(item) => {
+                    values.push((item.value.text.clone(), delimiter(&item.value)))
+                }
+                StarContent::Loop(star_loop) => values.extend(
+                    star_loop
+                        .rows
+                        .iter()
+                        .flatten()
+                        .map(|value| (value.text.clone(), delimiter(value))),
+                ),
+                StarContent::Saveframe(frame) => collect(&frame.contents, values),
+            }
+        }
+    }
+
+    let mut values = Vec::new();
+    for block in &generated.blocks {
+        collect(&block.contents, &mut values);
+    }
+    values
+}
+
+/// Records the events of a walk without positions or comments, which writing doesn't keep
+#[derive(Default)]
+struct EventRecorder {
+    events: Vec<String>,
+    values: Vec<(String, ValueDelimiter)>,
+}
+
+impl EventRecorder {
+    fn record(&mut self, event: String) -> WalkControl {
+        self.events.push(event);
+        WalkControl::Continue
+    }
+}
+
+impl SASContentHandler for EventRecorder {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.record("start_stream".to_string())
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_stream".to_string())
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_global".to_string())
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_global".to_string())
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_data({})", name))
+    }
+
+    fn end_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_data({})", name))
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_saveframe({})", name))
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_saveframe({})", name))
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_loop".to_string())
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_loop".to_string())
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        level: usize,
+    ) -> WalkControl {
+        self.record(format!("loop_definition({}: {})", level, tags.join(", ")))
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("start_loop_row({})", row_index))
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("end_loop_row({})", row_index))
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.values.push((value.to_string(), delimiter));
+        self.record(format!(
+            "data({}, {:?}, {:?}, {})",
+            tag, value, delimiter, loop_level
+        ))
+    }
+}
+
+/// The events of walking `input` straight from the parser
+fn walked_events(input: &str, config: &ParserConfig) -> EventRecorder {
+    let mut recorder = EventRecorder::default();
+    walk(input, config, &mut recorder)
+        .unwrap_or_else(|e| panic!("Failed to walk: {}\n{}", e, input));
+    recorder
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn prop_generated_documents_parse_to_their_model(generated in star_document()) {
+        let input = generated.to_star();
+        let expected = expected_document(&generated);
+
+        for encoding in [EncodingMode::Ascii, EncodingMode::ExtendedAscii, EncodingMode::Unicode] {
+            let config = config(encoding);
+            parse(&input, &config).unwrap_or_else(|e| panic!("Failed to parse: {}\n{}", e, input));
+
+            let mut builder = DocumentBuilderHandler::new();
+            walk(&input, &config, &mut builder).unwrap();
+            prop_assert_eq!(&builder.into_document(), &expected, "for input\n{}", input);
+        }
+    }
+
+    #[test]
+    fn prop_generated_values_keep_their_delimiters(generated in star_document()) {
+        let input = generated.to_star();
+        let recorder = walked_events(&input, &config(EncodingMode::Ascii));
+
+        prop_assert_eq!(recorder.values.len(), generated.value_count());
+        prop_assert_eq!(recorder.values, expected_values(&generated), "for input\n{}", input);
+    }
+
+    #[test]
+    fn prop_generated_event_streams_are_stable(generated in star_document()) {
+        let input = generated.to_star();
+        let config = config(EncodingMode::Ascii);
+        let events = walked_events(&input, &config).events;
+
+        // walking the tree gives the events walking the parser does
+        let tree = parse(&input, &config).unwrap();
+        let mut from_tree = EventRecorder::default();
+        StarWalker::from_input(&mut from_tree, &input).walk_star_tree_buffered(&tree);
+        prop_assert_eq!(&from_tree.events, &events, "tree walk of\n{}", input);
+
+        // and so does walking the text written from those events
+        let mut writer = StarWriterHandler::new(Vec::new());
+        walk(&input, &config, &mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        prop_assert_eq!(
+            &walked_events(&written, &config).events,
+            &events,
+            "written as\n{}\nfrom\n{}",
+            written,
+            input
+        );
+
+        // the pre-lexer doesn't change the tree
+        let prelexed = ParserConfigBuilder::new()
+            .error_format(ErrorFormatMode::Basic)
+            .prelex(true)
+            .build()
+            .unwrap();
+        prop_assert_eq!(parse(&input, &prelexed).unwrap(), tree);
+    }
+}
@@ -0,0 +1,61 @@
+//! Heap usage of parsed trees, measured with a counting global allocator
+//!
+//! This file holds a single test so no other test allocates while it measures.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ustar::mutable_pair::MutablePair;
+use ustar::parse_default;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_tree_heap_does_not_grow_with_nested_text() {
+    let input = fs::read_to_string("tests/test_data/dicts/mmcif_pdbx_vrpt.dic").unwrap();
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let tree = parse_default(&input).unwrap();
+    let retained = ALLOCATED.load(Ordering::Relaxed) - before;
+
+    let nodes = 1 + tree.iter_descendants().count();
+
+    // one copy of the input plus exactly sized child vectors, however deeply text is nested,
+    // and a little fixed overhead
+    let bound = input.len() + nodes * std::mem::size_of::<MutablePair>() + 4096;
+    assert!(
+        retained <= bound,
+        "retained {} bytes, expected at most {}",
+        retained,
+        bound
+    );
+}
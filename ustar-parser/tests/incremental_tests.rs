@@ -0,0 +1,246 @@
+use indoc::indoc;
+use proptest::prelude::*;
+use ustar::incremental::{IncrementalParser, ReparseReport};
+use ustar::{
+    default_config, parse, ColumnUnit, ConfigKey, ConfigValue, EncodingMode, ParserConfig,
+};
+
+const INPUT: &str = indoc! {"
+    # three blocks
+    data_first
+    _entry.id  first
+    _entry.title  'the first block'
+
+    data_second
+    loop_
+      _atom.id _atom.name
+      1 CA
+      2 CB
+    stop_
+    _entry.details
+    ;
+    a text field
+    ;
+
+    data_third
+    save_frame
+      _frame.value  3
+    save_
+"};
+
+/// Check the incremental tree is the tree a full parse of the edited text builds
+fn assert_matches_parse(parser: &IncrementalParser, config: &ParserConfig) {
+    match (parser.tree(), parse(parser.text(), config)) {
+        (Ok(tree), Ok(expected)) => {
+            assert_eq!(tree, expected, "after editing to\n{}", parser.text())
+        }
+        (Err(_), Err(_)) => {}
+        (tree, expected) => panic!(
+            "incremental {:?} but parse {:?} for\n{}",
+            tree.map(|_| "parsed"),
+            expected.map(|_| "parsed"),
+            parser.text()
+        ),
+    }
+}
+
+/// Replace the first `old` in the text of `parser` with `new`
+fn edit(parser: &mut IncrementalParser, old: &str, new: &str) -> Result<ReparseReport, String> {
+    let start = parser.text().find(old).expect("the text to edit");
+    parser
+        .apply_edit(start..start + old.len(), new)
+        .map_err(|error| error.core().message.clone())
+}
+
+fn report(changed: std::ops::Range<usize>, replaced: usize) -> Result<ReparseReport, String> {
+    Ok(ReparseReport {
+        changed,
+        replaced,
+        full_reparse: false,
+    })
+}
+
+#[test]
+fn test_edits_reparse_only_the_blocks_they_touch() {
+    let config = default_config();
+    let mut parser = IncrementalParser::new(INPUT, &config);
+    assert_eq!(parser.blocks().count(), 3);
+    assert_matches_parse(&parser, &config);
+
+    // a value in the second block
+    assert_eq!(edit(&mut parser, "2 CB", "2 CG"), report(1..2, 1));
+    assert_matches_parse(&parser, &config);
+
+    // lines added to the first block move the blocks after it
+    assert_eq!(
+        edit(
+            &mut parser,
+            "_entry.id  first",
+            "_entry.id  first\n_entry.new  1\n"
+        ),
+        report(0..1, 1)
+    );
+    assert_matches_parse(&parser, &config);
+
+    // a value of the last block, to the end of the text
+    assert_eq!(
+        edit(&mut parser, "3\nsave_\n", "4\nsave_\n\n"),
+        report(2..3, 1)
+    );
+    assert_matches_parse(&parser, &config);
+
+    // text deleted from the second block, moving the third back
+    assert_eq!(edit(&mut parser, "  1 CA\n", ""), report(1..2, 1));
+    assert_matches_parse(&parser, &config);
+}
+
+#[test]
+fn test_headings_added_and_removed_split_and_merge_blocks() {
+    let config = default_config();
+    let mut parser = IncrementalParser::new(INPUT, &config);
+
+    // a heading inserted into the second block splits it
+    assert_eq!(
+        edit(&mut parser, "stop_\n", "stop_\ndata_split\n"),
+        report(1..3, 1)
+    );
+    assert_eq!(parser.blocks().count(), 4);
+    assert_matches_parse(&parser, &config);
+
+    // and removing it merges them again
+    assert_eq!(edit(&mut parser, "data_split\n", ""), report(1..2, 2));
+    assert_eq!(parser.blocks().count(), 3);
+    assert_matches_parse(&parser, &config);
+
+    // a heading edited away merges its block with the one before, as values the block can't
+    // hold
+    assert!(edit(&mut parser, "data_third", "dat_third").is_err());
+    assert_eq!(parser.blocks().count(), 2);
+    assert!(parser.blocks().nth(1).unwrap().is_err());
+    assert_matches_parse(&parser, &config);
+    assert_eq!(
+        edit(&mut parser, "dat_third", "data_third"),
+        report(1..3, 1)
+    );
+    assert_matches_parse(&parser, &config);
+}
+
+#[test]
+fn test_strings_run_on_by_an_edit_widen_the_reparse() {
+    let config = default_config();
+    let mut parser = IncrementalParser::new(INPUT, &config);
+
+    // an unclosed text field swallows the headings after it
+    assert!(edit(
+        &mut parser,
+        "_entry.title  'the first block'\n",
+        "_entry.title\n;\n"
+    )
+    .is_err());
+    assert_eq!(parser.blocks().count(), 1);
+    assert_matches_parse(&parser, &config);
+
+    // closing it gives back the blocks
+    assert_eq!(
+        edit(&mut parser, "_entry.title\n;\n", "_entry.title\n;\n;\n"),
+        report(0..3, 1)
+    );
+    assert_matches_parse(&parser, &config);
+}
+
+#[test]
+fn test_blocks_sharing_a_line_with_an_edit_are_reparsed() {
+    let config = default_config();
+    let input = "data_a _x 1 data_b _y 2\ndata_c _z 3\n";
+    let mut parser = IncrementalParser::new(input, &config);
+
+    // the columns of data_b change, those of data_c on the next line don't
+    assert_eq!(edit(&mut parser, "_x 1", "_x 100"), report(0..2, 2));
+    assert_matches_parse(&parser, &config);
+}
+
+#[test]
+fn test_errors_are_kept_until_an_edit_fixes_them() {
+    let config = default_config();
+    let mut parser = IncrementalParser::new(INPUT, &config);
+
+    assert!(edit(&mut parser, "2 CB", "2 'CB").is_err());
+    assert!(parser.tree().is_err());
+    assert_matches_parse(&parser, &config);
+
+    // an edit elsewhere leaves the error, moved with the text
+    assert_eq!(
+        edit(&mut parser, "_frame.value", "_frame.count"),
+        report(2..3, 1)
+    );
+    assert!(parser.tree().is_err());
+    assert_eq!(
+        edit(&mut parser, "_entry.id  first", "_entry.id\n first"),
+        report(0..1, 1)
+    );
+    assert_matches_parse(&parser, &config);
+
+    assert_eq!(edit(&mut parser, "2 'CB", "2 CB"), report(1..2, 1));
+    assert_matches_parse(&parser, &config);
+    assert!(parser.tree().is_ok());
+}
+
+#[test]
+fn test_edits_at_the_start_reparse_everything() {
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::Unicode),
+    );
+    config.insert(
+        ConfigKey::ColumnUnit,
+        ConfigValue::ColumnUnit(ColumnUnit::Chars),
+    );
+    let mut parser = IncrementalParser::new("data_a\n_x 'α'\ndata_b _y 'β' _z 1\n", &config);
+
+    // a byte order mark added to the start changes the offsets of every block
+    let full = parser.apply_edit(0..0, "\u{FEFF}").unwrap();
+    assert!(full.full_reparse);
+    assert_eq!(full.changed, 0..2);
+    assert_matches_parse(&parser, &config);
+
+    // columns of characters after a multi byte edit
+    assert_eq!(edit(&mut parser, "'β'", "'βββ'"), report(1..2, 1));
+    assert_matches_parse(&parser, &config);
+    assert_eq!(edit(&mut parser, "'α'", "'αα'\n"), report(0..1, 1));
+    assert_matches_parse(&parser, &config);
+}
+
+/// Snippets edits insert, chosen to add and remove headings, strings and values
+const SNIPPETS: &[&str] = &[
+    "",
+    " ",
+    "\n",
+    "data_new\n",
+    "global_\n",
+    "_item.value 1\n",
+    "'",
+    "\n;\n",
+    "loop_ _a.b 1 2 stop_\n",
+    "# comment\n",
+    "x",
+];
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn test_generated_edit_sequences_match_full_parses(
+        edits in prop::collection::vec((any::<prop::sample::Index>(), 0usize..12, 0..SNIPPETS.len()), 1..12)
+    ) {
+        let config = default_config();
+        let mut parser = IncrementalParser::new(INPUT, &config);
+        for (position, length, snippet) in edits {
+            let text = parser.text();
+            let start = position.index(text.len() + 1);
+            let end = (start + length).min(text.len());
+            let _ = parser.apply_edit(start..end, SNIPPETS[snippet]);
+            assert_matches_parse(&parser, &config);
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use ustar::{ConfigKey, ConfigValue, EncodingMode, ErrorFormatMode, ParserConfig};
-use ustar_test_utils::ensure_test_data_available;
+use ustar_test_utils::{ensure_test_data_available, TestDataPolicy};
 
 struct TestResult {
     files_tested: usize,
@@ -121,7 +121,8 @@ fn test_directory_files(
 #[test]
 fn test_bmrb_star_files_can_be_parsed() {
     let dir = Path::new("tests/test_data/bmrb_stars");
-    ensure_test_data_available(dir).expect("Failed to verify test data integrity for BMRB stars");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for BMRB stars");
 
     let result = test_directory_files(dir, "str", EncodingMode::Unicode, &[], &[]);
     result.assert_success("BMRB STAR", Some(EncodingMode::Unicode));
@@ -130,7 +131,8 @@ fn test_bmrb_star_files_can_be_parsed() {
 #[test]
 fn test_cod_cif_files_can_be_parsed() {
     let dir = Path::new("tests/test_data/cod_cifs");
-    ensure_test_data_available(dir).expect("Failed to verify test data integrity for COD CIFs");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for COD CIFs");
 
     let result = test_directory_files(dir, "cif", EncodingMode::Unicode, &[], &[]);
     result.assert_success("COD CIF", Some(EncodingMode::Unicode));
@@ -139,7 +141,8 @@ fn test_cod_cif_files_can_be_parsed() {
 #[test]
 fn test_nef_examples_can_be_parsed() {
     let dir = Path::new("tests/test_data/nef_examples");
-    ensure_test_data_available(dir).expect("Failed to verify test data integrity for NEF examples");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for NEF examples");
 
     let result = test_directory_files(dir, "nef", EncodingMode::Unicode, &[], &[]);
     result.assert_success("NEF examples", Some(EncodingMode::Unicode));
@@ -149,11 +152,10 @@ fn test_nef_examples_can_be_parsed() {
 fn test_nef_specification_files_can_be_parsed() {
     let nef_dir = Path::new("tests/test_data/nef_spec");
 
-    ensure_test_data_available(nef_dir)
+    ensure_test_data_available(nef_dir, TestDataPolicy::DownloadIfMissing)
         .expect("Failed to verify test data integrity for NEF specification files");
 
-    let known_failures = vec!["CCPN_XPLOR_test1.nef"];
-    let result = test_directory_files(nef_dir, "nef", EncodingMode::Ascii, &[], &known_failures);
+    let result = test_directory_files(nef_dir, "nef", EncodingMode::Ascii, &[], &[]);
 
     result.assert_success("NEF specification", Some(EncodingMode::Ascii));
 }
@@ -164,17 +166,10 @@ fn test_nef_specification_files_can_be_parsed() {
 fn test_nef_site_files_can_be_parsed(#[case] encoding_mode: EncodingMode) {
     let nef_dir = Path::new("tests/test_data/nef_spec");
 
-    ensure_test_data_available(nef_dir)
+    ensure_test_data_available(nef_dir, TestDataPolicy::DownloadIfMissing)
         .expect("Failed to verify test data integrity for NEF site files");
 
-    // Files known to have parsing failures due to quote handling issues
-    let known_failures = vec![
-        "CCPN_H1GI_clean.nef",
-        "CCPN_H1GI_clean_extended.nef",
-        "CCPN_Sec5Part3.nef",
-    ];
-
-    let result = test_directory_files(nef_dir, "nef", encoding_mode, &[], &known_failures);
+    let result = test_directory_files(nef_dir, "nef", encoding_mode, &[], &[]);
     result.assert_success("NEF site", Some(encoding_mode));
 }
 
@@ -210,7 +205,8 @@ fn test_mmcif_dictionaries_can_be_parsed(#[case] encoding_mode: EncodingMode) {
 #[test]
 fn test_pdb_mmcif_files_can_be_parsed() {
     let dir = Path::new("tests/test_data/pdb_mmcifs");
-    ensure_test_data_available(dir).expect("Failed to verify test data integrity for PDB mmCIFs");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for PDB mmCIFs");
 
     let result = test_directory_files(dir, "cif", EncodingMode::Unicode, &[], &[]);
     result.assert_success("PDB mmCIF", Some(EncodingMode::Unicode));
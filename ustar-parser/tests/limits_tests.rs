@@ -0,0 +1,189 @@
+use proptest::prelude::*;
+use ustar::sas_handlers::DocumentBuilderHandler;
+use ustar::{
+    default_config, parse, parse_with_recovery, walk, CifVersion, ConfigKey, ConfigValue,
+    ErrorCode, ParserConfig, ParserConfigBuilder, UstarError, DEFAULT_MAX_NESTING_DEPTH,
+};
+
+/// A data block with a loop holding `depth` nested loops and a row of values
+fn nested_loops(depth: usize) -> String {
+    let mut input = String::from("data_test\nloop_ _top\n");
+    for level in 0..depth {
+        input.push_str(&format!("loop_ _level_{}\n", level));
+    }
+    input.push_str("_last\n1 2\n");
+    input
+}
+
+/// A CIF 2.0 data block with a value of `depth` nested lists
+fn nested_lists(depth: usize) -> String {
+    format!(
+        "data_test\n_list {}{}\n",
+        "[".repeat(depth),
+        "]".repeat(depth)
+    )
+}
+
+fn cif2_config() -> ParserConfig {
+    ParserConfigBuilder::new()
+        .cif_version(CifVersion::Cif2)
+        .build()
+        .unwrap()
+}
+
+/// Parse on a thread with the default 2 MiB stack, so unbounded recursion would overflow it
+fn parse_on_small_stack(input: String, config: ParserConfig) -> Result<(), Box<UstarError>> {
+    std::thread::spawn(move || parse(&input, &config).map(|_| ()))
+        .join()
+        .expect("parsing should not panic")
+}
+
+#[test]
+fn test_nesting_at_the_default_limit_parses() {
+    // the outer loop is one level, so the nested loops reach the limit
+    let input = nested_loops(DEFAULT_MAX_NESTING_DEPTH - 1);
+    assert!(parse_on_small_stack(input, default_config()).is_ok());
+
+    let input = nested_lists(DEFAULT_MAX_NESTING_DEPTH);
+    assert!(parse_on_small_stack(input, cif2_config()).is_ok());
+}
+
+#[test]
+fn test_deeply_nested_loops_fail_at_the_first_loop_over_the_limit() {
+    let input = nested_loops(10_000);
+    let first_over = input
+        .match_indices("loop_")
+        .nth(DEFAULT_MAX_NESTING_DEPTH)
+        .unwrap()
+        .0;
+
+    let error = parse_on_small_stack(input, default_config()).unwrap_err();
+
+    assert!(matches!(*error, UstarError::LimitExceeded { .. }));
+    assert_eq!(error.code(), ErrorCode::E0021NestingTooDeep);
+    assert_eq!(error.core().offset, first_over);
+    assert_eq!(
+        (error.core().line, error.core().col),
+        (DEFAULT_MAX_NESTING_DEPTH + 2, 1)
+    );
+    assert_eq!(
+        error.core().message,
+        "loop nested deeper than the maximum of 128"
+    );
+}
+
+#[test]
+fn test_deeply_nested_lists_fail_gracefully() {
+    let error = parse_on_small_stack(nested_lists(100_000), cif2_config()).unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::E0021NestingTooDeep);
+    assert_eq!(error.core().offset, "data_test\n_list ".len() + 128);
+}
+
+#[test]
+fn test_configured_nesting_depth() {
+    let mut config = default_config();
+    config.insert(ConfigKey::MaxNestingDepth, ConfigValue::Usize(3));
+
+    assert!(parse(&nested_loops(2), &config).is_ok());
+    let error = parse(&nested_loops(3), &config).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::E0021NestingTooDeep);
+
+    // brackets are ordinary characters outside CIF 2.0
+    assert!(parse(&nested_lists(4), &config).is_ok());
+}
+
+#[test]
+fn test_nesting_ignores_keywords_in_strings_comments_and_text_fields() {
+    let input = "data_test\n\
+                 loop_ _a # loop_ loop_\n\
+                 _b\n\
+                 'loop_ loop_' \"loop_\"\n\
+                 ;\n\
+                 loop_ loop_\n\
+                 ;\n\
+                 x\n";
+    let mut config = default_config();
+    config.insert(ConfigKey::MaxNestingDepth, ConfigValue::Usize(1));
+
+    assert!(parse(input, &config).is_ok());
+}
+
+#[test]
+fn test_nesting_check_handles_other_ascii_whitespace() {
+    // form feeds and vertical tabs aren't blanks in STAR, the grammar reports them
+    for input in ["data_test\n_a 1\n\x0c", "data_test\n\x0b_a 1\n", "\x0c"] {
+        for config in [default_config(), cif2_config()] {
+            assert!(parse(input, &config).is_err(), "for {:?}", input);
+        }
+    }
+}
+
+#[test]
+fn test_input_over_the_size_limit_is_rejected_before_parsing() {
+    let input = "data_test\n_item value\n";
+    let mut config = default_config();
+    config.insert(ConfigKey::MaxInputBytes, ConfigValue::Usize(input.len()));
+    assert!(parse(input, &config).is_ok());
+
+    config.insert(ConfigKey::MaxInputBytes, ConfigValue::Usize(12));
+    let error = parse(input, &config).unwrap_err();
+
+    assert!(matches!(*error, UstarError::LimitExceeded { .. }));
+    assert_eq!(error.code(), ErrorCode::E0020InputTooLarge);
+    assert_eq!((error.core().line, error.core().col), (2, 3));
+    assert_eq!(
+        error.core().message,
+        "input of 22 bytes is larger than the maximum of 12 bytes"
+    );
+}
+
+#[test]
+fn test_walk_and_recovery_stop_at_limits() {
+    let input = nested_loops(10_000);
+
+    let walked = std::thread::spawn({
+        let input = input.clone();
+        move || {
+            let mut handler = DocumentBuilderHandler::new();
+            walk(&input, &default_config(), &mut handler).map(|_| ())
+        }
+    })
+    .join()
+    .unwrap();
+    assert_eq!(walked.unwrap_err().code(), ErrorCode::E0021NestingTooDeep);
+
+    let (tree, errors) = std::thread::spawn(move || parse_with_recovery(&input, &default_config()))
+        .join()
+        .unwrap();
+    assert!(tree.is_none());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code(), ErrorCode::E0021NestingTooDeep);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_generated_nesting_fails_gracefully(
+        depth in 1usize..2_000,
+        max_depth in 1usize..64,
+        lists in any::<bool>(),
+    ) {
+        let mut config = if lists { cif2_config() } else { default_config() };
+        config.insert(ConfigKey::MaxNestingDepth, ConfigValue::Usize(max_depth));
+        let (input, levels) = if lists {
+            (nested_lists(depth), depth)
+        } else {
+            (nested_loops(depth), depth + 1)
+        };
+
+        let result = parse_on_small_stack(input, config);
+
+        if levels > max_depth {
+            prop_assert_eq!(result.unwrap_err().code(), ErrorCode::E0021NestingTooDeep);
+        } else {
+            prop_assert!(result.is_ok());
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use ustar::line_column_index::{LineColumn, LineColumnIndex};
+use ustar::line_column_index::{count_columns, LineColumn, LineColumnIndex};
+use ustar::ColumnUnit;
 
 #[test]
 fn test_simple_input() {
@@ -39,3 +40,95 @@ fn test_empty_lines() {
     assert_eq!(index.offset_to_line_col(2), LineColumn::new(2, 1)); // empty line
     assert_eq!(index.offset_to_line_col(3), LineColumn::new(3, 1)); // 'b'
 }
+
+#[test]
+fn test_crlf_line_endings() {
+    let input = "ab\r\ncd\r\n";
+    let index = LineColumnIndex::new(input);
+
+    assert_eq!(index.line_count(), 3);
+    assert_eq!(index.position(2), LineColumn::new(1, 3)); // '\r'
+    assert_eq!(index.position(4), LineColumn::new(2, 1)); // 'c'
+    assert_eq!(index.position(8), LineColumn::new(3, 1)); // end of input
+    assert_eq!(index.line_text(1), Some("ab"));
+    assert_eq!(index.line_text(2), Some("cd"));
+    assert_eq!(index.line_text(3), Some(""));
+}
+
+#[test]
+fn test_final_line_without_newline() {
+    let input = "first\nlast";
+    let index = LineColumnIndex::new(input);
+
+    assert_eq!(index.line_count(), 2);
+    assert_eq!(index.line_text(2), Some("last"));
+    assert_eq!(index.position(9), LineColumn::new(2, 4)); // 't'
+    assert_eq!(index.position(10), LineColumn::new(2, 5)); // end of input
+    assert_eq!(index.line_text(0), None);
+    assert_eq!(index.line_text(3), None);
+}
+
+#[test]
+fn test_multibyte_characters_count_one_column() {
+    // 'é' is two bytes and '€' three, offsets are in bytes
+    let input = "_é '€x' y\nz";
+    let index = LineColumnIndex::new(input);
+
+    assert_eq!(index.offset_to_line_col(4), LineColumn::new(1, 5)); // quote as a byte column
+    assert_eq!(index.position(4), LineColumn::new(1, 4)); // quote
+    assert_eq!(index.position(5), LineColumn::new(1, 5)); // '€'
+    assert_eq!(index.position(6), LineColumn::new(1, 5)); // inside '€'
+    assert_eq!(index.position(8), LineColumn::new(1, 6)); // 'x'
+    assert_eq!(index.position(13), LineColumn::new(2, 1)); // 'z'
+    assert_eq!(index.line_text(1), Some("_é '€x' y"));
+}
+
+#[test]
+fn test_tab_width() {
+    let input = "\ta\tb\n  \tc";
+    let index = LineColumnIndex::new(input);
+    assert_eq!(index.position(1), LineColumn::new(1, 2)); // 'a'
+    assert_eq!(index.position(3), LineColumn::new(1, 4)); // 'b'
+
+    let index = index.with_tab_width(4);
+    assert_eq!(index.position(1), LineColumn::new(1, 5)); // 'a'
+    assert_eq!(index.position(2), LineColumn::new(1, 6)); // second tab
+    assert_eq!(index.position(3), LineColumn::new(1, 9)); // 'b'
+    assert_eq!(index.position(8), LineColumn::new(2, 5)); // 'c' after two spaces and a tab
+    assert_eq!(index.offset_to_line_col(8), LineColumn::new(2, 4));
+}
+
+#[test]
+fn test_column_units() {
+    // 'α' is two bytes, the combining acute accent after it two more and joins its grapheme
+    let input = "_a 'α\u{301}' b\nc";
+    let bytes = LineColumnIndex::new(input);
+    let chars = LineColumnIndex::new(input).with_column_unit(ColumnUnit::Chars);
+    let graphemes = LineColumnIndex::new(input).with_column_unit(ColumnUnit::Graphemes);
+
+    assert_eq!(bytes.offset_to_line_col(10), LineColumn::new(1, 11)); // 'b'
+    assert_eq!(chars.offset_to_line_col(10), LineColumn::new(1, 9));
+    assert_eq!(graphemes.offset_to_line_col(10), LineColumn::new(1, 8));
+
+    assert_eq!(chars.offset_to_line_col(5), LineColumn::new(1, 5)); // inside 'α'
+    assert_eq!(chars.offset_to_line_col(6), LineColumn::new(1, 6)); // accent
+    assert_eq!(graphemes.offset_to_line_col(6), LineColumn::new(1, 5)); // accent joins 'α'
+    assert_eq!(graphemes.offset_to_line_col(12), LineColumn::new(2, 1)); // 'c'
+
+    assert_eq!(chars.position(10), LineColumn::new(1, 9));
+    assert_eq!(graphemes.position(10), LineColumn::new(1, 8));
+
+    let line = bytes.line_text(1).unwrap();
+    assert_eq!(count_columns(line, ColumnUnit::Bytes), 11);
+    assert_eq!(count_columns(line, ColumnUnit::Chars), 9);
+    assert_eq!(count_columns(line, ColumnUnit::Graphemes), 8);
+    // a zero width joiner sequence and an emoji with a skin tone are one grapheme each
+    assert_eq!(
+        count_columns("\u{1F469}\u{200D}\u{1F4BB}", ColumnUnit::Graphemes),
+        1
+    );
+    assert_eq!(
+        count_columns("\u{1F44B}\u{1F3FD}", ColumnUnit::Graphemes),
+        1
+    );
+}
@@ -0,0 +1,197 @@
+use indoc::indoc;
+use ustar::line_column_index::LineColumn;
+use ustar::loop_table::{LoopTable, LoopTableError};
+use ustar::parse_default;
+
+#[test]
+fn test_atomic_mass_loop_rows_and_columns() {
+    let input = indoc! {"
+        data_masses
+        loop_
+            _atom_name
+            _atomic_mass_ratio
+            1H   1.007825031898(14)
+            # a comment between rows
+            2H   1.0070508889220(75)
+            3H   1.005349760440(27)
+            3He  '1.005343107322(20)'
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+    let table = LoopTable::from_pair(&tree).unwrap();
+
+    assert_eq!(table.columns(), ["_atom_name", "_atomic_mass_ratio"]);
+    assert_eq!(table.position(), LineColumn { line: 2, column: 1 });
+    assert_eq!(table.row_count(), 4);
+    assert_eq!(
+        table.rows().collect::<Vec<_>>(),
+        vec![
+            ["1H", "1.007825031898(14)"],
+            ["2H", "1.0070508889220(75)"],
+            ["3H", "1.005349760440(27)"],
+            ["3He", "1.005343107322(20)"],
+        ]
+    );
+    assert_eq!(
+        table.column("_ATOM_NAME").unwrap().collect::<Vec<_>>(),
+        vec!["1H", "2H", "3H", "3He"]
+    );
+    assert!(table.column("_missing").is_none());
+    assert!(table.nested(0).is_none());
+    assert_eq!(
+        table.value_position(3, 1),
+        Some(LineColumn {
+            line: 9,
+            column: 10
+        })
+    );
+    assert_eq!(table.value_position(4, 0), None);
+    assert_eq!(table.value(3, 0), Some("3He"));
+    assert_eq!(table.value(4, 0), None);
+    assert_eq!(table.value_position(0, 2), None);
+    assert_eq!(
+        table.value_node(3, 1).map(|node| node.as_str()),
+        Some("'1.005343107322(20)'")
+    );
+    assert!(table.value_node(4, 1).is_none());
+}
+
+#[test]
+fn test_nested_atomic_loop_sub_tables() {
+    let input = indoc! {r#"
+        data_basis
+        loop_
+            _atomic_name
+            loop_
+                _level_scheme
+                _level_energy
+                loop_
+                    _function_exponent
+                    _function_coefficient
+        hydrogen
+            "(2)->[2] " -0.485813
+                1.3324838E+01    1.0
+                2.0152720-01     1.0 stop_
+            "(2)->[2]"  -0.485813
+                1.3326990E+01    1.0
+                2.0154600E-01    1.0 stop_
+            "(2)->[1]"  -0.485813
+                1.3324800E-01    2.7440850-01
+                2.0152870E-01    8.2122540-01 stop_
+            "(3)->[2]"  -0.496979
+                4.5018000+00    1.5628500E-01
+                6.8144400E-01   9.0469100E-01
+                1.5139800E-01   1.0000000E+01 stop_
+        stop_
+        helium
+            "(1)->[1]"  -0.9
+                1.0    1.0 stop_
+        stop_
+    "#};
+    let tree = parse_default(input).unwrap();
+    let table = LoopTable::from_pair(&tree).unwrap();
+
+    assert_eq!(table.columns(), ["_atomic_name"]);
+    assert_eq!(
+        table.column("_atomic_name").unwrap().collect::<Vec<_>>(),
+        vec!["hydrogen", "helium"]
+    );
+
+    let levels = table.nested(0).unwrap();
+    assert_eq!(levels.columns(), ["_level_scheme", "_level_energy"]);
+    assert_eq!(levels.position(), LineColumn { line: 4, column: 5 });
+    assert_eq!(
+        levels.column("_level_scheme").unwrap().collect::<Vec<_>>(),
+        vec!["(2)->[2] ", "(2)->[2]", "(2)->[1]", "(3)->[2]"]
+    );
+
+    let functions = levels.nested(3).unwrap();
+    assert_eq!(
+        functions.columns(),
+        ["_function_exponent", "_function_coefficient"]
+    );
+    assert_eq!(
+        functions.rows().collect::<Vec<_>>(),
+        vec![
+            ["4.5018000+00", "1.5628500E-01"],
+            ["6.8144400E-01", "9.0469100E-01"],
+            ["1.5139800E-01", "1.0000000E+01"],
+        ]
+    );
+    assert!(levels.nested(4).is_none());
+    assert!(table.is_nested() && levels.is_nested() && !functions.is_nested());
+
+    let helium = table.nested(1).unwrap();
+    assert_eq!(helium.row_count(), 1);
+    assert_eq!(helium.nested(0).unwrap().row_count(), 1);
+}
+
+#[test]
+fn test_ragged_loop_is_an_error() {
+    let input = indoc! {"
+        data_ragged
+        loop_
+            _atom_name
+            _atomic_mass_ratio
+            1H   1.007825031898(14)
+            2H   1.0070508889220(75)
+            3H
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+    let error = LoopTable::from_pair(&tree).unwrap_err();
+
+    assert_eq!(
+        error,
+        LoopTableError::RaggedLoop {
+            position: LineColumn { line: 7, column: 5 },
+            level: 1,
+            columns: 2,
+            values: 5,
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "l7:c5 loop level 1 has 5 values, which is not a multiple of its 2 columns"
+    );
+}
+
+#[test]
+fn test_ragged_nested_loop_is_an_error() {
+    let input = indoc! {"
+        data_ragged
+        loop_
+            _outer
+            loop_
+                _inner_a
+                _inner_b
+        x
+            1 2
+            3 stop_
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+
+    assert!(matches!(
+        LoopTable::from_pair(&tree),
+        Err(LoopTableError::RaggedLoop {
+            position: LineColumn { line: 9, column: 5 },
+            level: 2,
+            columns: 2,
+            values: 3,
+        })
+    ));
+}
+
+#[test]
+fn test_not_a_loop() {
+    let tree = parse_default("data_items\n_tag value\n").unwrap();
+
+    assert_eq!(
+        LoopTable::from_pair(&tree).unwrap_err(),
+        LoopTableError::NotALoop {
+            rule: "star_file".to_string(),
+            position: LineColumn { line: 1, column: 1 },
+        }
+    );
+}
@@ -0,0 +1,249 @@
+use indoc::indoc;
+use ustar::line_column_index::LineColumn;
+use ustar::merge::{merge, MergeError, MergePolicy, Resolution};
+use ustar::mutable_pair::MutablePair;
+use ustar::parse_default;
+use ustar::sas_handlers::StarWriterHandler;
+use ustar::sas_walker::StarWalker;
+
+const BASE: &str = indoc! {"
+    data_nef_test
+    save_nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_category nef_nmr_meta_data
+       _nef_nmr_meta_data.format_name nmr_exchange_format
+       _nef_nmr_meta_data.program_name base
+    save_
+    save_nef_molecular_system
+       _nef_molecular_system.sf_category nef_molecular_system
+       loop_
+          _nef_sequence.index _nef_sequence.chain_code _nef_sequence.residue_name
+          1 A ALA
+          2 A GLY
+       stop_
+    save_
+"};
+
+const OVERLAY: &str = indoc! {"
+    data_NEF_TEST
+    save_nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_category nef_nmr_meta_data
+
+       _nef_nmr_meta_data.program_name overlay
+       _nef_nmr_meta_data.program_version 1.0
+    save_
+    save_nef_distance_restraint_list_noes
+       _nef_distance_restraint_list.sf_category nef_distance_restraint_list
+       loop_
+          _nef_distance_restraint.index _nef_distance_restraint.upper_limit
+          1 5.0
+       stop_
+    save_
+"};
+
+/// The text written for the SAS events of walking `tree`
+fn events(tree: &MutablePair) -> String {
+    let mut writer = StarWriterHandler::new(Vec::new());
+    StarWalker::from_input(&mut writer, "").walk_star_tree_buffered(tree);
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+fn merge_text(base: &str, overlay: &str, policy: MergePolicy) -> Result<String, MergeError> {
+    let (base, overlay) = (
+        parse_default(base).unwrap(),
+        parse_default(overlay).unwrap(),
+    );
+    merge(&base, &overlay, policy).map(|merged| events(&merged))
+}
+
+#[test]
+fn test_save_frames_are_united_and_items_merged() {
+    let policy = MergePolicy::default()
+        .with_tag("_nef_nmr_meta_data.sf_category", Resolution::KeepBase)
+        .with_tag("_nef_nmr_meta_data.program_name", Resolution::TakeOverlay);
+    let merged = merge_text(BASE, OVERLAY, policy).unwrap();
+
+    assert_eq!(
+        merged,
+        indoc! {"
+            data_nef_test
+            save_nef_nmr_meta_data
+                _nef_nmr_meta_data.sf_category  nef_nmr_meta_data
+                _nef_nmr_meta_data.format_name  nmr_exchange_format
+                _nef_nmr_meta_data.program_name  overlay
+                _nef_nmr_meta_data.program_version  1.0
+            save_
+            save_nef_molecular_system
+                _nef_molecular_system.sf_category  nef_molecular_system
+                loop_
+                    _nef_sequence.index
+                    _nef_sequence.chain_code
+                    _nef_sequence.residue_name
+                    1 A ALA
+                    2 A GLY
+                stop_
+            save_
+            save_nef_distance_restraint_list_noes
+                _nef_distance_restraint_list.sf_category  nef_distance_restraint_list
+                loop_
+                    _nef_distance_restraint.index
+                    _nef_distance_restraint.upper_limit
+                    1 5.0
+                stop_
+            save_
+        "}
+    );
+}
+
+#[test]
+fn test_whole_save_frames_are_kept_or_taken() {
+    let keep = MergePolicy::default().with_frame("nef_nmr_meta_data", Resolution::KeepBase);
+    let merged = merge_text(BASE, OVERLAY, keep).unwrap();
+    assert!(merged.contains("program_name  base\n"));
+    assert!(!merged.contains("program_version"));
+    assert!(merged.contains("save_nef_distance_restraint_list_noes\n"));
+
+    let take = MergePolicy::default().with_frame("save_NEF_NMR_META_DATA", Resolution::TakeOverlay);
+    let merged = merge_text(BASE, OVERLAY, take).unwrap();
+    assert!(merged.contains("program_name  overlay\n"));
+    assert!(!merged.contains("format_name"));
+}
+
+#[test]
+fn test_conflicts_report_both_positions() {
+    let error = merge_text(BASE, OVERLAY, MergePolicy::default()).unwrap_err();
+    assert_eq!(
+        error,
+        MergeError::Conflict {
+            name: "_nef_nmr_meta_data.sf_category".to_string(),
+            base: LineColumn::new(3, 4),
+            overlay: LineColumn::new(3, 4),
+        }
+    );
+
+    let policy = MergePolicy::new(Resolution::KeepBase)
+        .with_tag("_nef_nmr_meta_data.program_name", Resolution::Error);
+    let error = merge_text(BASE, OVERLAY, policy).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "l5:c4 _nef_nmr_meta_data.program_name in the overlay is already defined at l5:c4 in \
+         the base"
+    );
+
+    let policy =
+        MergePolicy::new(Resolution::KeepBase).with_frame("nef_nmr_meta_data", Resolution::Error);
+    let error = merge_text(BASE, OVERLAY, policy).unwrap_err();
+    assert_eq!(
+        error,
+        MergeError::Conflict {
+            name: "save_nef_nmr_meta_data".to_string(),
+            base: LineColumn::new(2, 1),
+            overlay: LineColumn::new(2, 1),
+        }
+    );
+}
+
+#[test]
+fn test_merged_nodes_keep_the_positions_of_their_file() {
+    let (base, overlay) = (
+        parse_default(BASE).unwrap(),
+        parse_default(OVERLAY).unwrap(),
+    );
+    let merged = merge(&base, &overlay, MergePolicy::new(Resolution::KeepBase)).unwrap();
+
+    let position = |tag: &str| {
+        merged
+            .find_where(|node| node.rule_name() == "data_name" && node.as_str() == tag)
+            .map(|node| node.start_position)
+    };
+    assert_eq!(
+        position("_nef_nmr_meta_data.program_name"),
+        Some(LineColumn::new(5, 4))
+    );
+    assert_eq!(
+        position("_nef_nmr_meta_data.program_version"),
+        Some(LineColumn::new(6, 4))
+    );
+    assert!(merged
+        .iter_descendants()
+        .all(|node| node.start == 0 && node.end == 0));
+}
+
+const SHIFTS: &str = indoc! {"
+    data_shifts
+    save_nef_chemical_shift_list_1
+       loop_
+          _nef_chemical_shift.atom_name _nef_chemical_shift.value
+          H 8.1
+          N 120.3
+       stop_
+    save_
+"};
+
+const MORE_SHIFTS: &str = indoc! {"
+    data_shifts
+    save_nef_chemical_shift_list_1
+       loop_
+          _nef_chemical_shift.value _nef_chemical_shift.atom_name
+          4.2 HA
+       stop_
+    save_
+"};
+
+#[test]
+fn test_loops_with_the_same_tags_are_concatenated_or_replaced() {
+    let concatenated = merge_text(
+        SHIFTS,
+        MORE_SHIFTS,
+        MergePolicy::default().with_concatenated_loops(),
+    )
+    .unwrap();
+    assert!(concatenated.contains("        H 8.1\n        N 120.3\n        HA 4.2\n    stop_\n"));
+
+    let replaced = merge_text(
+        SHIFTS,
+        MORE_SHIFTS,
+        MergePolicy::new(Resolution::TakeOverlay),
+    )
+    .unwrap();
+    assert!(replaced.contains("_nef_chemical_shift.atom_name\n        4.2 HA\n    stop_\n"));
+
+    // a resolution given for a column takes precedence over concatenation
+    let kept = merge_text(
+        SHIFTS,
+        MORE_SHIFTS,
+        MergePolicy::default()
+            .with_concatenated_loops()
+            .with_tag("_nef_chemical_shift.value", Resolution::KeepBase),
+    )
+    .unwrap();
+    assert_eq!(
+        kept,
+        merge_text(SHIFTS, "", MergePolicy::default()).unwrap()
+    );
+}
+
+#[test]
+fn test_new_blocks_are_added_and_roots_must_match() {
+    let merged = merge_text(SHIFTS, BASE, MergePolicy::default()).unwrap();
+    let shifts = merged.find("data_shifts").unwrap();
+    let nef = merged.find("data_nef_test").unwrap();
+    assert!(shifts < nef);
+
+    let base = parse_default(BASE).unwrap();
+    let frame = base.find_first("save_frame").unwrap();
+    assert_eq!(
+        merge(&base, frame, MergePolicy::default()).unwrap_err(),
+        MergeError::Mismatch {
+            base: "star_file".to_string(),
+            overlay: "save_frame".to_string(),
+        }
+    );
+
+    // save frames can be merged on their own
+    let molecular_system = &base.find_all("save_frame")[1];
+    let merged = merge(frame, molecular_system, MergePolicy::default()).unwrap();
+    assert_eq!(
+        merged.find_all("data").len(),
+        frame.find_all("data").len() + molecular_system.find_all("data").len()
+    );
+}
@@ -0,0 +1,88 @@
+//! Peak heap of parsing a large file mapped into memory against reading it first, measured with
+//! a counting global allocator
+//!
+//! This file holds a single test so no other test allocates while it measures.
+#![cfg(feature = "mmap")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ustar::{default_config, parse_file, parse_mmap};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The result of `operation` and the most heap in use above what was allocated before it ran
+fn peak_heap<T>(operation: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = operation();
+    (result, PEAK.load(Ordering::Relaxed) - before)
+}
+
+#[test]
+fn test_mapped_file_parses_with_one_copy_of_the_text() {
+    // about 50MB in 500 blocks of long text fields, so the text outweighs the tree
+    let line = format!("{}\n", "x".repeat(79));
+    let mut file = tempfile::Builder::new().suffix(".cif").tempfile().unwrap();
+    for block in 0..500 {
+        write!(
+            file,
+            "data_block_{}\n_entry.id  {}\n_entry.text\n;\n",
+            block, block
+        )
+        .unwrap();
+        for _ in 0..1250 {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.write_all(b";\n").unwrap();
+    }
+    file.flush().unwrap();
+    let size = file.as_file().metadata().unwrap().len() as usize;
+    assert!(size > 50_000_000);
+
+    let config = default_config();
+    let (read, read_peak) = peak_heap(|| parse_file(file.path(), &config).unwrap());
+    let (mapped, mapped_peak) = peak_heap(|| parse_mmap(file.path(), &config).unwrap());
+    assert_eq!(mapped, read);
+
+    // reading the file holds it in a string alongside the copy the tree shares
+    assert!(
+        mapped_peak + size * 9 / 10 <= read_peak,
+        "parse_mmap peaked at {} bytes, parse_file at {} bytes, for a {} byte file",
+        mapped_peak,
+        read_peak,
+        size
+    );
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "mmap")]
+
+use std::io::Write;
+use ustar::mmap::MappedStarFile;
+use ustar::{
+    default_config, parse_file, parse_mmap, ConfigKey, ConfigValue, EncodingMode, ErrorCode,
+    ErrorFormatMode,
+};
+
+fn star_file_with(bytes: &[u8]) -> tempfile::NamedTempFile {
+    let mut file = tempfile::Builder::new().suffix(".cif").tempfile().unwrap();
+    file.write_all(bytes).unwrap();
+    file
+}
+
+/// A document of `blocks` data blocks, each with items, a text field and a loop
+fn generated_document(blocks: usize) -> String {
+    let mut document = String::new();
+    for block in 0..blocks {
+        document.push_str(&format!("data_block_{}\n", block));
+        document.push_str(&format!("_entry.id  entry_{}\n", block));
+        document.push_str("_entry.title  'a quoted title'\n");
+        document.push_str("_entry.details\n;\nfirst line of a text field\nsecond line\n;\n");
+        document.push_str("loop_\n  _atom.id\n  _atom.name\n  _atom.x\n");
+        for row in 0..20 {
+            document.push_str(&format!("  {} CA {}.{:03}\n", row, row * 3, block % 1000));
+        }
+        document.push_str("stop_\n");
+    }
+    document
+}
+
+#[test]
+fn test_parse_mmap_matches_parse_file() {
+    let config = default_config();
+    let file = star_file_with(generated_document(200).as_bytes());
+
+    let mapped = parse_mmap(file.path(), &config).unwrap();
+    assert_eq!(mapped, parse_file(file.path(), &config).unwrap());
+
+    // the blocks of the mapped file are those of the whole parse
+    let mapped_file = MappedStarFile::open(file.path(), &config).unwrap();
+    let blocks: Vec<_> = mapped_file
+        .blocks(&config)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(blocks.len(), 200);
+    assert_eq!(blocks[..], mapped.children[..200]);
+}
+
+#[test]
+fn test_errors_are_positioned_in_the_file_and_name_it() {
+    let config = default_config();
+    let file = star_file_with(b"data_test\n_entry.id 1\n_entry.title\n");
+    let name = file.path().display().to_string();
+
+    let error = parse_mmap(file.path(), &config).unwrap_err();
+    assert_eq!(error.core().source_name.as_deref(), Some(name.as_str()));
+    assert_eq!((error.core().line, error.core().col), (4, 1));
+    assert_eq!(error.core().offset, 35);
+    assert_eq!(
+        error.format_error(ErrorFormatMode::Basic, 0),
+        parse_file(file.path(), &config)
+            .unwrap_err()
+            .format_error(ErrorFormatMode::Basic, 0)
+    );
+
+    // a legacy 8-bit file is decoded, but its errors are still at bytes of the file
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::ExtendedAscii),
+    );
+    let file = star_file_with(b"data_test\n_entry.title 'caf\xe9 \xe9t\xe9'\n_entry.id\n");
+    let error = parse_mmap(file.path(), &config).unwrap_err();
+    assert_eq!(error.core().offset, 44);
+
+    // the text of a file that isn't UTF-8 is an error at its first invalid byte
+    let mapped = MappedStarFile::open(file.path(), &default_config()).unwrap();
+    let error = mapped.text(&default_config()).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::E0006InvalidCharacter);
+    assert_eq!(error.core().offset, 27);
+}
+
+#[test]
+fn test_empty_and_missing_files() {
+    let config = default_config();
+    let file = star_file_with(b"");
+    assert_eq!(
+        parse_mmap(file.path(), &config).unwrap(),
+        parse_file(file.path(), &config).unwrap()
+    );
+    let mapped = MappedStarFile::open(file.path(), &config).unwrap();
+    assert!(mapped.bytes().is_empty());
+    assert_eq!(mapped.path(), file.path());
+
+    let directory = tempfile::tempdir().unwrap();
+    let missing = directory.path().join("missing.cif");
+    let error = parse_mmap(&missing, &config).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::E0019UnreadableFile);
+    assert_eq!(
+        error.core().source_name,
+        Some(missing.display().to_string())
+    );
+}
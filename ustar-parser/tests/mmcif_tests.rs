@@ -0,0 +1,137 @@
+use indoc::indoc;
+use std::fs;
+use std::path::Path;
+use ustar::line_column_index::LineColumn;
+use ustar::loop_table::LoopTableError;
+use ustar::mmcif::{atom_sites, MmcifBlock};
+use ustar::parse_default;
+use ustar_test_utils::{ensure_test_data_available, TestDataPolicy};
+
+const EXCERPT: &str = indoc! {"
+    data_1ABC
+    _entry.id   1ABC
+    _cell.entry_id   1ABC
+    _cell.length_a   50.840
+    _cell.length_b   42.770
+    _cell_measurement_temperature 293(2)
+    loop_
+    _atom_site.group_PDB
+    _atom_site.id
+    _atom_site.type_symbol
+    _atom_site.Cartn_x
+    _atom_site.Cartn_y
+    _atom_site.Cartn_z
+    _atom_site.occupancy
+    ATOM   1 N  27.340 24.430 2.614   1.00
+    ATOM   2 C  26.266 25.413 2.842(3) .
+    HETATM 3 ?  ?      25.413 'O'     0.50
+"};
+
+fn read_1ubq() -> String {
+    let dir = Path::new("tests/test_data/mmcif");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for mmCIF files");
+    fs::read_to_string(dir.join("1UBQ.cif")).unwrap()
+}
+
+#[test]
+fn test_categories_group_items_and_loops() {
+    let tree = parse_default(EXCERPT).unwrap();
+    let block = MmcifBlock::from_block(&tree.children()[0]).unwrap();
+
+    assert_eq!(block.name(), "1ABC");
+    let names: Vec<&str> = block.categories().map(|category| category.name()).collect();
+    assert_eq!(names, vec!["entry", "cell", "", "atom_site"]);
+
+    let cell = block.category("_CELL").unwrap();
+    assert!(!cell.is_loop());
+    assert_eq!(cell.items(), &["entry_id", "length_a", "length_b"]);
+    assert_eq!(cell.row_count(), 1);
+    assert_eq!(cell.value("Length_A"), Some("50.840"));
+    assert_eq!(cell.value_at(1, "length_a"), None);
+    assert_eq!(cell.position(), LineColumn { line: 3, column: 1 });
+    assert_eq!(
+        block
+            .category("")
+            .unwrap()
+            .value("cell_measurement_temperature"),
+        Some("293(2)")
+    );
+
+    let atom_site = block.category("atom_site").unwrap();
+    assert!(atom_site.is_loop());
+    assert_eq!(atom_site.row_count(), 3);
+    assert_eq!(atom_site.value_at(2, "group_PDB"), Some("HETATM"));
+    assert_eq!(atom_site.column("id"), Some(vec!["1", "2", "3"]));
+    assert_eq!(atom_site.column("B_iso_or_equiv"), None);
+    assert_eq!(atom_site.table().unwrap().columns().len(), 7);
+    assert!(block.category("refine").is_none());
+}
+
+#[test]
+fn test_atom_sites_of_excerpt() {
+    let atoms = atom_sites(&parse_default(EXCERPT).unwrap()).unwrap();
+
+    assert_eq!(atoms.len(), 3);
+    assert_eq!(&atoms.x[..2], &[27.340, 26.266]);
+    assert!(atoms.x[2].is_nan());
+    assert_eq!(&atoms.z[..2], &[2.614, 2.842]);
+    assert!(atoms.z[2].is_nan());
+    assert_eq!(atoms.y, vec![24.430, 25.413, 25.413]);
+    assert_eq!(
+        atoms.element,
+        vec![Some("N".to_string()), Some("C".to_string()), None]
+    );
+    assert_eq!((atoms.occupancy[0], atoms.occupancy[2]), (1.0, 0.5));
+    assert!(atoms.occupancy[1].is_nan());
+    // a column missing from the loop reads as NaN
+    assert_eq!(atoms.b_factor.len(), 3);
+    assert!(atoms.b_factor.iter().all(|b| b.is_nan()));
+}
+
+#[test]
+fn test_atom_sites_without_loop_is_empty() {
+    let tree = parse_default("data_empty\n_entry.id empty\n").unwrap();
+
+    assert!(atom_sites(&tree).unwrap().is_empty());
+}
+
+#[test]
+fn test_ragged_atom_site_loop_fails() {
+    let tree =
+        parse_default("data_bad\nloop_\n_atom_site.id\n_atom_site.Cartn_x\n1 2.0 2\n").unwrap();
+
+    assert!(matches!(
+        atom_sites(&tree),
+        Err(LoopTableError::RaggedLoop { .. })
+    ));
+    assert!(MmcifBlock::from_block(&tree.children()[0]).is_err());
+}
+
+#[test]
+fn test_1ubq_categories_and_atom_sites() {
+    let tree = parse_default(&read_1ubq()).unwrap();
+    let block = MmcifBlock::from_block(&tree.children()[0]).unwrap();
+
+    assert_eq!(block.name(), "1UBQ");
+    assert_eq!(block.category("entry").unwrap().value("id"), Some("1UBQ"));
+    assert_eq!(
+        block.category("cell").unwrap().value("length_a"),
+        Some("50.840")
+    );
+    assert_eq!(block.category("atom_site").unwrap().row_count(), 660);
+
+    let atoms = atom_sites(&tree).unwrap();
+    assert_eq!(atoms.len(), 660);
+    assert_eq!(
+        (atoms.x[0], atoms.y[0], atoms.z[0]),
+        (27.340, 24.430, 2.614)
+    );
+    assert_eq!(atoms.element[0].as_deref(), Some("N"));
+    assert_eq!((atoms.occupancy[0], atoms.b_factor[0]), (1.0, 9.67));
+    assert_eq!(
+        (atoms.x[659], atoms.occupancy[659], atoms.b_factor[659]),
+        (37.667, 0.5, 33.32)
+    );
+    assert_eq!(atoms.element[659].as_deref(), Some("O"));
+}
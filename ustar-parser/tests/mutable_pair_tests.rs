@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fs;
+use ustar::line_column_index::LineColumnIndex;
+use ustar::mutable_pair::{MutablePair, VisitControl, Visitor};
+use ustar::{parse_default, parse_with_recovery};
+
+const COMPREHENSIVE_EXAMPLE: &str = "tests/test_data/comprehensive_example.star";
+
+fn comprehensive_example() -> (String, MutablePair) {
+    let input = fs::read_to_string(COMPREHENSIVE_EXAMPLE).unwrap();
+    let tree = parse_default(&input).unwrap();
+    (input, tree)
+}
+
+fn count_nodes(pair: &MutablePair) -> usize {
+    pair.children()
+        .iter()
+        .map(|child| 1 + count_nodes(child))
+        .sum()
+}
+
+#[test]
+fn test_find_all_data_names_in_document_order() {
+    let (input, tree) = comprehensive_example();
+
+    // every tag in the example starts a line
+    let expected: Vec<&str> = input
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with('_'))
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect();
+
+    let names: Vec<&str> = tree
+        .find_all("data_name")
+        .iter()
+        .map(|pair| pair.as_str())
+        .collect();
+
+    assert_eq!(names.len(), 60);
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn test_iter_descendants_is_depth_first() {
+    let (_, tree) = comprehensive_example();
+
+    let descendants: Vec<&MutablePair> = tree.iter_descendants().collect();
+
+    assert_eq!(descendants.len(), count_nodes(&tree));
+    assert_eq!(descendants[0].rule_name(), "data_block");
+    assert_eq!(descendants[1].rule_name(), "data_heading");
+    assert_eq!(descendants[1].as_str(), "data_comprehensive_example");
+    assert!(descendants
+        .windows(2)
+        .all(|pair| pair[0].start <= pair[1].start));
+    assert_eq!(descendants.last().unwrap().rule_name(), "EOI");
+}
+
+#[test]
+fn test_find_first_returns_a_reference_into_the_tree() {
+    let (_, tree) = comprehensive_example();
+
+    let heading = tree.find_first("save_heading").unwrap();
+    assert_eq!(heading.as_str(), "save_frame_example_1");
+
+    let first_block = &tree.children()[0];
+    let frame = first_block
+        .children()
+        .iter()
+        .find(|child| child.rule_name() == "save_frame")
+        .unwrap();
+    assert!(std::ptr::eq(heading, &frame.children()[0]));
+
+    assert!(tree.find_first("no_such_rule").is_none());
+    assert!(tree.find_all("no_such_rule").is_empty());
+}
+
+#[test]
+fn test_find_where_matches_predicate() {
+    let (_, tree) = comprehensive_example();
+
+    let author = tree
+        .find_where(|pair| !pair.has_children() && pair.as_str() == "Gary Thompson")
+        .unwrap();
+    assert_eq!(author.rule_name(), "string");
+
+    let global_version = tree
+        .find_where(|pair| {
+            pair.rule_name() == "data" && pair.children()[0].as_str() == "_global_version"
+        })
+        .unwrap();
+    assert_eq!(global_version.children()[1].as_str(), "2.1");
+
+    // searches start below the node itself
+    assert!(tree
+        .find_where(|pair| pair.rule_name() == "star_file")
+        .is_none());
+}
+
+fn child_rule_names(pair: &MutablePair) -> Vec<&str> {
+    pair.children()
+        .iter()
+        .map(|child| child.rule_name())
+        .collect()
+}
+
+#[test]
+fn test_remove_and_retain_children_preserve_sibling_offsets() {
+    let (_, mut tree) = comprehensive_example();
+    let original = tree.clone();
+    let block = &mut tree.children_mut()[0];
+    let (block_start, block_end) = (block.start, block.end);
+
+    let frame_index = block
+        .children()
+        .iter()
+        .position(|child| child.rule_name() == "save_frame")
+        .unwrap();
+    let removed = block.remove_child(frame_index);
+    assert_eq!(removed.children()[0].as_str(), "save_frame_example_1");
+
+    block.retain_children(|child| child.rule_name() != "save_frame");
+    assert!(!child_rule_names(block).contains(&"save_frame"));
+
+    // the remaining children are untouched, the block still describes its original span
+    let original_block = &original.children()[0];
+    let kept: Vec<&MutablePair> = original_block
+        .children()
+        .iter()
+        .filter(|child| child.rule_name() != "save_frame")
+        .collect();
+    assert_eq!(block.children().iter().collect::<Vec<_>>(), kept);
+    assert_eq!((block.start, block.end), (block_start, block_end));
+
+    // the span of the current children no longer reaches the removed frames
+    let last_frame_end = original_block.children().last().unwrap().end;
+    let (_, children_end) = block.children_span().unwrap();
+    assert!(children_end < last_frame_end);
+}
+
+#[test]
+fn test_insert_replace_and_push_children() {
+    let (_, mut tree) = comprehensive_example();
+    let block = &mut tree.children_mut()[0];
+    let child_count = block.children().len();
+    let second = block.children()[1].clone();
+
+    // rename the first tag
+    let item = &mut block.children_mut()[1];
+    let name = &item.children()[0];
+    let renamed = MutablePair::new("data_name", "_renamed_value", name.start, name.end);
+    let old_name = item.replace_child(0, renamed);
+    assert_eq!(old_name.as_str(), "_simple_text_value");
+    assert_eq!(item.children()[0].as_str(), "_renamed_value");
+    assert_eq!(item.children()[1], second.children()[1]);
+
+    let inserted = MutablePair::with_children(
+        "data",
+        "_inserted 1",
+        0,
+        0,
+        vec![
+            MutablePair::new("data_name", "_inserted", 0, 0),
+            MutablePair::new("string", "1", 0, 0),
+        ],
+    );
+    block.insert_child(1, inserted.clone());
+    block.push_child(inserted);
+
+    assert_eq!(block.children().len(), child_count + 2);
+    assert_eq!(block.children()[0].rule_name(), "data_heading");
+    assert_eq!(block.children()[1].children()[0].as_str(), "_inserted");
+    assert_eq!(block.children()[2].children()[0].as_str(), "_renamed_value");
+    assert_eq!(block.children()[2].start, second.start);
+    assert_eq!(block.children().last().unwrap().as_str(), "_inserted 1");
+    assert_eq!(block.children_span().unwrap().0, 0);
+}
+
+/// Every node's stored line and columns agree with a lookup of its offsets in the input
+fn assert_positions_match_index(input: &str, tree: &MutablePair) {
+    let line_index = LineColumnIndex::new(input);
+    for pair in std::iter::once(tree).chain(tree.iter_descendants()) {
+        assert_eq!(
+            pair.start_line_column(),
+            line_index.offset_to_line_col(pair.start),
+            "start of {}",
+            pair
+        );
+        assert_eq!(
+            pair.end_line_column(),
+            line_index.offset_to_line_col(pair.end),
+            "end of {}",
+            pair
+        );
+    }
+}
+
+#[test]
+fn test_parsed_nodes_store_line_columns() {
+    let (input, tree) = comprehensive_example();
+
+    assert_positions_match_index(&input, &tree);
+
+    let tag = tree.find_first("data_name").unwrap();
+    assert_eq!(tag.as_str(), "_simple_text_value");
+    assert_eq!(tag.start_line_column().line, 8);
+    assert_eq!(tag.start_line_column().column, 1);
+}
+
+#[test]
+fn test_recovered_nodes_store_line_columns() {
+    let input = fs::read_to_string(COMPREHENSIVE_EXAMPLE)
+        .unwrap()
+        .replace("_numeric_value         42.5", "_numeric_value  'unclosed");
+
+    let (tree, errors) = parse_with_recovery(&input, &ustar::default_config());
+
+    assert_eq!(errors.len(), 1);
+    assert_positions_match_index(&input, &tree.unwrap());
+}
+
+#[test]
+fn test_constructed_nodes_have_undefined_line_columns() {
+    let pair = MutablePair::new("data_name", "_tag", 0, 4);
+
+    assert!(!pair.start_line_column().is_defined());
+    assert!(!pair.end_line_column().is_defined());
+}
+
+#[test]
+fn test_parsed_content_shares_one_copy_of_the_input() {
+    let (input, tree) = comprehensive_example();
+
+    // including the delimiters and strings split out by the default string decomposition
+    assert!(tree
+        .iter_descendants()
+        .all(|pair| pair.content.shares_source(&tree.content)));
+    let quote = tree.find_first("SINGLE_QUOTE").unwrap();
+    assert_eq!(quote.content, "'");
+    assert_eq!(&input[quote.start..quote.end], "'");
+}
+
+/// Counts the nodes of each rule
+#[derive(Default)]
+struct RuleCounter {
+    counts: HashMap<String, usize>,
+}
+
+impl Visitor for RuleCounter {
+    fn enter(&mut self, pair: &MutablePair) -> VisitControl {
+        *self.counts.entry(pair.rule_name().to_string()).or_default() += 1;
+        VisitControl::Continue
+    }
+}
+
+/// Records the deepest level reached, skipping the children of nodes at the depth limit
+struct DepthLimiter {
+    limit: usize,
+    depth: usize,
+    deepest: usize,
+    exits: usize,
+}
+
+impl Visitor for DepthLimiter {
+    fn enter(&mut self, _pair: &MutablePair) -> VisitControl {
+        self.deepest = self.deepest.max(self.depth);
+        self.depth += 1;
+        if self.depth > self.limit {
+            VisitControl::SkipChildren
+        } else {
+            VisitControl::Continue
+        }
+    }
+
+    fn exit(&mut self, _pair: &MutablePair) {
+        self.depth -= 1;
+        self.exits += 1;
+    }
+}
+
+/// Counts loops nested more than two deep, e.g. "count all loops deeper than 2"
+#[derive(Default)]
+struct DeepLoopCounter {
+    loop_depth: usize,
+    deep_loops: usize,
+}
+
+impl Visitor for DeepLoopCounter {
+    fn enter(&mut self, pair: &MutablePair) -> VisitControl {
+        if pair.rule_name() == "loop_keyword" {
+            self.loop_depth += 1;
+            if self.loop_depth > 2 {
+                self.deep_loops += 1;
+            }
+        }
+        VisitControl::Continue
+    }
+
+    fn exit(&mut self, pair: &MutablePair) {
+        if matches!(pair.rule_name(), "data_loop" | "nested_loop") {
+            self.loop_depth -= 1;
+        }
+    }
+}
+
+/// Stops at the first save frame, recording the rules entered and exited
+#[derive(Default)]
+struct StopAtSaveFrame {
+    events: Vec<String>,
+}
+
+impl Visitor for StopAtSaveFrame {
+    fn enter(&mut self, pair: &MutablePair) -> VisitControl {
+        if pair.rule_name() == "save_frame" {
+            return VisitControl::Stop;
+        }
+        self.events.push(format!("enter {}", pair.rule_name()));
+        VisitControl::Continue
+    }
+
+    fn exit(&mut self, pair: &MutablePair) {
+        self.events.push(format!("exit {}", pair.rule_name()));
+    }
+}
+
+#[test]
+fn test_rule_counter_visitor() {
+    let (_, tree) = comprehensive_example();
+    let mut counter = RuleCounter::default();
+
+    let stopped = tree.accept(&mut counter);
+
+    assert!(!stopped);
+    assert_eq!(counter.counts["star_file"], 1);
+    assert_eq!(counter.counts["data_block"], 2);
+    assert_eq!(
+        counter.counts["data_name"],
+        tree.find_all("data_name").len()
+    );
+    assert_eq!(
+        counter.counts.values().sum::<usize>(),
+        tree.iter_descendants().count() + 1
+    );
+}
+
+#[test]
+fn test_depth_limiter_visitor_skips_children() {
+    let (_, tree) = comprehensive_example();
+    let mut limiter = DepthLimiter {
+        limit: 2,
+        depth: 0,
+        deepest: 0,
+        exits: 0,
+    };
+
+    tree.accept(&mut limiter);
+
+    // the root, its blocks and their direct children are entered, and every entered node exited
+    let expected = 1
+        + tree.children().len()
+        + tree
+            .children()
+            .iter()
+            .map(|child| child.children().len())
+            .sum::<usize>();
+    assert_eq!(limiter.deepest, 2);
+    assert_eq!(limiter.exits, expected);
+    assert_eq!(limiter.depth, 0);
+}
+
+#[test]
+fn test_visitor_counts_deeply_nested_loops() {
+    let input = indoc::indoc! {"
+        data_loops
+        loop_
+          _a
+          loop_
+            _b
+            loop_
+              _c
+              _d
+        1 2 3 4 stop_ stop_
+        loop_
+          _e
+        5
+    "};
+    let tree = parse_default(input).unwrap();
+    let mut counter = DeepLoopCounter::default();
+
+    tree.accept(&mut counter);
+
+    assert_eq!(counter.deep_loops, 1);
+    assert_eq!(counter.loop_depth, 0);
+}
+
+#[test]
+fn test_visitor_stop_ends_traversal() {
+    let (_, tree) = comprehensive_example();
+    let mut visitor = StopAtSaveFrame::default();
+
+    let stopped = tree.accept(&mut visitor);
+
+    assert!(stopped);
+    assert_eq!(visitor.events[0], "enter star_file");
+    assert!(!visitor
+        .events
+        .iter()
+        .any(|event| event.starts_with("exit data_block")));
+    assert!(!visitor.events.iter().any(|event| event == "exit star_file"));
+}
@@ -0,0 +1,282 @@
+#![cfg(feature = "nef")]
+
+use indoc::indoc;
+use rstest::rstest;
+use std::fs;
+use ustar::line_column_index::LineColumn;
+use ustar::loop_table::LoopTableError;
+use ustar::nef::{NefError, NefFile};
+use ustar::parse_default;
+use ustar::validate::ValidationIssueKind;
+
+mod snapshot_utils;
+
+ustar_test_utils::snapshot_orphan_check!("nef_tests");
+
+const METADATA: &str = indoc! {"
+    data_test
+    save_nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_category      nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_framecode     nef_nmr_meta_data
+       _nef_nmr_meta_data.format_name      nmr_exchange_format
+       _nef_nmr_meta_data.format_version   1.1
+       _nef_nmr_meta_data.program_name     test
+       _nef_nmr_meta_data.program_version  1.0
+       _nef_nmr_meta_data.creation_date    2024-01-01T00:00:00
+    save_
+"};
+
+const MOLECULAR_SYSTEM: &str = indoc! {"
+    save_nef_molecular_system
+       _nef_molecular_system.sf_category   nef_molecular_system
+       _nef_molecular_system.sf_framecode  nef_molecular_system
+       loop_
+          _nef_sequence.index
+          _nef_sequence.chain_code
+          _nef_sequence.sequence_code
+          _nef_sequence.residue_name
+          _nef_sequence.cis_peptide
+          1  A  1  MET  .
+          2  A  2  PRO  true
+       stop_
+    save_
+"};
+
+fn read(input: &str) -> Result<NefFile, NefError> {
+    NefFile::from_tree(&parse_default(input).unwrap())
+}
+
+#[rstest]
+#[case("Commented_Example_v1_1.nef")]
+#[case("Commented_Example.nef")]
+fn test_nef_spec_examples(#[case] name: &str) {
+    let input = fs::read_to_string(format!("tests/test_data/nef_examples/{}", name)).unwrap();
+    let nef = read(&input).unwrap();
+
+    let extracted = format!(
+        "{:#?}\n{:#?}\n{:#?}\n",
+        nef.metadata(),
+        nef.molecular_system(),
+        nef.chemical_shift_lists()
+    );
+    snapshot_utils::assert_snapshot_gz(
+        &format!("nef_tests__{}", name.trim_end_matches(".nef")),
+        &extracted,
+    );
+}
+
+#[test]
+fn test_ccpn_example_reports_missing_loop_tags() {
+    let input =
+        fs::read_to_string("tests/test_data/nef_examples/CCPN_Commented_Example.nef").unwrap();
+    let Err(NefError::Invalid(issues)) = read(&input) else {
+        panic!("expected violations");
+    };
+
+    let found: Vec<(&str, LineColumn)> = issues
+        .iter()
+        .map(|issue| (issue.name.as_str(), issue.position))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                "_nef_sequence.index",
+                LineColumn {
+                    line: 31,
+                    column: 7
+                }
+            ),
+            (
+                "_nef_sequence.residue_name",
+                LineColumn {
+                    line: 31,
+                    column: 7
+                }
+            ),
+            (
+                "_nef_chemical_shift.residue_name",
+                LineColumn {
+                    line: 118,
+                    column: 7
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_typed_fields() {
+    let input = format!(
+        "{}{}{}",
+        METADATA,
+        MOLECULAR_SYSTEM,
+        indoc! {"
+            save_nef_chemical_shift_list_1
+               _nef_chemical_shift_list.sf_category   nef_chemical_shift_list
+               _nef_chemical_shift_list.sf_framecode  nef_chemical_shift_list_1
+               loop_
+                  _nef_chemical_shift.chain_code
+                  _nef_chemical_shift.sequence_code
+                  _nef_chemical_shift.residue_name
+                  _nef_chemical_shift.atom_name
+                  _nef_chemical_shift.value
+                  _nef_chemical_shift.value_uncertainty
+                  _nef_chemical_shift.isotope_number
+                  A  2  PRO  CA  63.2  0.1  13
+                  A  2  .    HA  4.4   .    .
+               stop_
+            save_
+        "}
+    );
+    let nef = read(&input).unwrap();
+
+    assert_eq!(nef.metadata().program_name, "test");
+    assert_eq!(nef.metadata().uuid, None);
+    let residues = nef.molecular_system();
+    assert_eq!(residues.len(), 2);
+    assert_eq!(
+        (residues[1].index, residues[1].residue_name.as_str()),
+        (2, "PRO")
+    );
+    assert_eq!(
+        (residues[0].cis_peptide, residues[1].cis_peptide),
+        (None, Some(true))
+    );
+
+    let lists = nef.chemical_shift_lists();
+    assert_eq!(lists.len(), 1);
+    assert_eq!(lists[0].framecode, "nef_chemical_shift_list_1");
+    let [ca, ha] = lists[0].shifts.as_slice() else {
+        panic!("expected two shifts");
+    };
+    assert_eq!(
+        (ca.value, ca.uncertainty, ca.isotope_number),
+        (63.2, Some(0.1), Some(13))
+    );
+    assert_eq!(ca.element, None);
+    assert_eq!(
+        (ha.residue_name.as_deref(), ha.value, ha.uncertainty),
+        (None, 4.4, None)
+    );
+}
+
+#[test]
+fn test_restraint_only_file_has_no_shift_lists() {
+    let nef = read(&format!("{}{}", METADATA, MOLECULAR_SYSTEM)).unwrap();
+
+    assert!(nef.chemical_shift_lists().is_empty());
+}
+
+#[test]
+fn test_violations_are_reported_with_positions() {
+    let input = format!(
+        "{}{}",
+        METADATA.replace("   _nef_nmr_meta_data.program_version  1.0\n", ""),
+        indoc! {"
+            save_nef_molecular_system
+               _nef_molecular_system.sf_category   nef_molecular_system
+               loop_
+                  _nef_sequence.index
+                  _nef_sequence.chain_code
+                  _nef_sequence.sequence_code
+                  one  A  1
+               stop_
+            save_
+            save_nef_chemical_shift_list_1
+               _nef_chemical_shift_list.sf_category   nef_chemical_shift_list
+               _nef_chemical_shift_list.sf_framecode  nef_chemical_shift_list_1
+               loop_
+                  _nef_chemical_shift.chain_code
+                  _nef_chemical_shift.sequence_code
+                  _nef_chemical_shift.residue_name
+                  _nef_chemical_shift.atom_name
+                  _nef_chemical_shift.value
+                  A  1  MET  CA  ?
+               stop_
+            save_
+        "}
+    );
+    let Err(NefError::Invalid(issues)) = read(&input) else {
+        panic!("expected violations");
+    };
+
+    let found: Vec<(ValidationIssueKind, &str, LineColumn)> = issues
+        .iter()
+        .map(|issue| (issue.kind, issue.name.as_str(), issue.position))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                ValidationIssueKind::MissingMandatoryItem,
+                "_nef_nmr_meta_data.program_version",
+                LineColumn { line: 2, column: 1 }
+            ),
+            (
+                ValidationIssueKind::MissingMandatoryItem,
+                "_nef_sequence.residue_name",
+                LineColumn {
+                    line: 12,
+                    column: 4
+                }
+            ),
+            (
+                ValidationIssueKind::TypeMismatch,
+                "_nef_sequence.index",
+                LineColumn {
+                    line: 16,
+                    column: 7
+                }
+            ),
+            (
+                ValidationIssueKind::TypeMismatch,
+                "_nef_chemical_shift.value",
+                LineColumn {
+                    line: 28,
+                    column: 22
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        issues[2].to_string(),
+        "l16:c7 _nef_sequence.index has a value of the wrong type, expected an integer but found one"
+    );
+}
+
+#[test]
+fn test_missing_mandatory_frames() {
+    let Err(NefError::Invalid(issues)) = read("data_empty\n_tag value\n") else {
+        panic!("expected violations");
+    };
+
+    let names: Vec<&str> = issues.iter().map(|issue| issue.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["save_nef_nmr_meta_data", "save_nef_molecular_system"]
+    );
+}
+
+#[test]
+fn test_only_ragged_loops_that_are_read_fail() {
+    let ragged_unread = format!(
+        "{}{}",
+        METADATA.replace(
+            "save_\n",
+            "   loop_\n      _nef_program_script.program_name\n      _nef_program_script.script_name\n      a b c\n   stop_\nsave_\n"
+        ),
+        MOLECULAR_SYSTEM
+    );
+    assert!(read(&ragged_unread).is_ok());
+
+    let ragged_read = format!(
+        "{}{}",
+        METADATA,
+        MOLECULAR_SYSTEM.replace("2  A  2  PRO  true", "2  A  2  PRO")
+    );
+    assert!(matches!(
+        read(&ragged_read),
+        Err(NefError::Loop(LoopTableError::RaggedLoop { .. }))
+    ));
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "rayon")]
+
+use std::fs;
+use ustar::{
+    default_config, parse, parse_parallel, CifVersion, ColumnUnit, ConfigKey, ConfigValue,
+    EncodingMode, ErrorFormatMode,
+};
+
+fn read(name: &str) -> String {
+    fs::read_to_string(format!("tests/test_data/{}", name)).unwrap()
+}
+
+#[test]
+fn test_parallel_matches_parse() {
+    // several dictionaries concatenated give a large file with one data block each
+    let bundle: String = [
+        "mmcif_ddl.dic",
+        "mmcif_sym.dic",
+        "mmcif_biosync.dic",
+        "mmcif_sas.dic",
+    ]
+    .iter()
+    .map(|name| read(&format!("dicts/{}", name)))
+    .collect::<Vec<_>>()
+    .join("\n");
+    let config = default_config();
+
+    for input in [
+        read("comprehensive_example.star"),
+        read("comprehensive_example_crlf.star"),
+        bundle,
+        String::new(),
+    ] {
+        // equality includes offsets and line and columns in the whole input
+        assert_eq!(
+            parse_parallel(&input, &config).unwrap(),
+            parse(&input, &config).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_parallel_matches_parse_for_cif2() {
+    let input = read("cif2_lists_tables.cif");
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::CifVersion,
+        ConfigValue::CifVersion(CifVersion::Cif2),
+    );
+
+    assert_eq!(
+        parse_parallel(&input, &config).unwrap(),
+        parse(&input, &config).unwrap()
+    );
+}
+
+#[test]
+fn test_parallel_matches_parse_in_each_column_unit() {
+    let input = "data_α\n_name 'ά' _next 1\n\ndata_β\nloop_\n_x\n'α' 'β'\n";
+    for unit in [ColumnUnit::Chars, ColumnUnit::Graphemes] {
+        let mut config = default_config();
+        config.insert(
+            ConfigKey::Encoding,
+            ConfigValue::Encoding(EncodingMode::Unicode),
+        );
+        config.insert(ConfigKey::ColumnUnit, ConfigValue::ColumnUnit(unit));
+
+        assert_eq!(
+            parse_parallel(input, &config).unwrap(),
+            parse(input, &config).unwrap(),
+            "{:?}",
+            unit
+        );
+    }
+}
+
+#[test]
+fn test_parallel_preserves_block_order() {
+    let input: String = (0..500)
+        .map(|index| format!("data_block_{index}\n_entry.id  {index}\n\n"))
+        .collect();
+
+    let tree = parse_parallel(&input, &default_config()).unwrap();
+
+    let headings: Vec<&str> = tree
+        .find_all("data_heading")
+        .iter()
+        .map(|heading| heading.as_str())
+        .collect();
+    let expected: Vec<String> = (0..500)
+        .map(|index| format!("data_block_{index}"))
+        .collect();
+    assert_eq!(headings, expected);
+    assert_eq!(tree.children().last().unwrap().rule_name(), "EOI");
+}
+
+#[test]
+fn test_parallel_reports_first_error_in_the_whole_input() {
+    let input = indoc::indoc! {"
+        data_first
+        _entry.id  1
+
+        data_broken
+        _entry.id
+
+        data_also_broken
+        loop_
+
+        data_last
+        _entry.id  4
+    "};
+
+    let error = parse_parallel(input, &default_config()).unwrap_err();
+
+    assert!(error
+        .format_error(ErrorFormatMode::Basic, 0)
+        .starts_with("Parse error at l7:c1"));
+}
@@ -3,10 +3,10 @@ use std::path::Path;
 
 // loop1 - missing value in row 2 - currently ustar doesn't count values
 // loop2 - loop with no rows - curently ustar doesn't count this as an error
-// loop3 - loop with no headers - an error in ustar
-// loop4 - loop with no body - an error in ustar
-// loop5 - missing closing triple quote -  triple quotes not supported by ustar
-// warning.cif - contains triple quotes - not supported by ustar
+// loop3 - loop with no headers - an error in ustar, leaving out stop_ is allowed but tags aren't
+// loop4 - loop with no headers or body - an error in ustar whether or not stop_ is required
+// loop5 - missing closing triple quote - an error in ustar
+// warning.cif / warning.str - """ string with no closing triple quote - an error in ustar
 
 static KNOWN_FAILURES: &[&str] = &[
     "loop3.str",
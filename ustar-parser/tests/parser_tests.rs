@@ -6,6 +6,8 @@ use ustar::parsers::ascii::{AsciiParser, Rule as AsciiRule};
 
 mod snapshot_utils;
 
+ustar_test_utils::snapshot_orphan_check!("parser_tests");
+
 // data_name
 #[test]
 fn data_name() {
@@ -153,6 +155,8 @@ fn data() {
             AsciiRule::non_quoted_string,
             AsciiRule::double_quote_string,
             AsciiRule::single_quote_string,
+            AsciiRule::triple_single_quote_string,
+            AsciiRule::triple_double_quote_string,
             AsciiRule::frame_code,
             AsciiRule::semi_colon_string
         ],
@@ -168,6 +172,8 @@ fn data() {
             AsciiRule::non_quoted_string,
             AsciiRule::double_quote_string,
             AsciiRule::single_quote_string,
+            AsciiRule::triple_single_quote_string,
+            AsciiRule::triple_double_quote_string,
             AsciiRule::frame_code,
             AsciiRule::semi_colon_string
         ],
@@ -270,6 +276,199 @@ fn single_quoted_string() {
     }
 }
 
+// triple quoted strings (CIF2)
+#[test]
+fn triple_quoted_string() {
+    parses_to! {
+        parser: AsciiParser,
+        input:  "''''''",
+        rule:   AsciiRule::triple_single_quote_string,
+        tokens: [
+            triple_single_quote_string(0, 6)
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input:  "'''it's \"quoted\"'''",
+        rule:   AsciiRule::triple_single_quote_string,
+        tokens: [
+            triple_single_quote_string(0, 19)
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input:  "'''first\nsecond'''",
+        rule:   AsciiRule::triple_single_quote_string,
+        tokens: [
+            triple_single_quote_string(0, 18)
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input:  "\"\"\"say \"hi\" 'now'\"\"\"",
+        rule:   AsciiRule::triple_double_quote_string,
+        tokens: [
+            triple_double_quote_string(0, 20)
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input:  "\"\"\"first\r\nsecond\"\"\"",
+        rule:   AsciiRule::triple_double_quote_string,
+        tokens: [
+            triple_double_quote_string(0, 19)
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input:  "_test '''a value'''",
+        rule:   AsciiRule::data,
+        tokens: [
+            data(0, 19, [
+                data_name(0, 5),
+                triple_single_quote_string(6, 19)
+            ])
+        ]
+    }
+
+    fails_with! {
+        parser: AsciiParser,
+        input: "'''unterminated\nvalue''",
+        rule: AsciiRule::triple_single_quote_string,
+        positives: vec![AsciiRule::triple_single_quote_string],
+        negatives: vec![],
+        pos: 0
+    }
+
+    fails_with! {
+        parser: AsciiParser,
+        input: "\"\"\"unterminated\"\"",
+        rule: AsciiRule::triple_double_quote_string,
+        positives: vec![AsciiRule::triple_double_quote_string],
+        negatives: vec![],
+        pos: 0
+    }
+}
+
+#[test]
+fn triple_quoted_strings_file() {
+    let file_path = "tests/test_data/triple_quoted_strings.star";
+    let test_string = std::fs::read_to_string(file_path).unwrap();
+    let pairs = AsciiParser::parse(AsciiRule::star_file, &test_string).unwrap();
+    let star_file_pair = pairs.into_iter().next().unwrap();
+    snapshot_utils::assert_snapshot_gz(
+        "parser_tests__triple_quoted_strings",
+        &ustar_test_utils::format_pest_pair(&star_file_pair),
+    );
+}
+
+// CIF2 list and table values
+#[test]
+fn cif2_list_and_table_values() {
+    parses_to! {
+        parser: AsciiParser,
+        input: "data_x _a [1 'b c']",
+        rule: AsciiRule::cif2_star_file,
+        tokens: [
+            cif2_star_file(0, 19, [
+                data_block(0, 19, [
+                    data_heading(0, 6),
+                    data(7, 19, [
+                        data_name(7, 9),
+                        list_value(10, 19, [
+                            container_non_quoted_string(11, 12),
+                            container_single_quote_string(13, 18)
+                        ])
+                    ])
+                ]),
+                EOI(19, 19)
+            ])
+        ]
+    }
+
+    parses_to! {
+        parser: AsciiParser,
+        input: "data_x _a {'k':[1] \"v\": {}}",
+        rule: AsciiRule::cif2_star_file,
+        tokens: [
+            cif2_star_file(0, 27, [
+                data_block(0, 27, [
+                    data_heading(0, 6),
+                    data(7, 27, [
+                        data_name(7, 9),
+                        table_value(10, 27, [
+                            table_entry(11, 18, [
+                                table_key(11, 14, [
+                                    container_single_quote_string(11, 14)
+                                ]),
+                                list_value(15, 18, [
+                                    container_non_quoted_string(16, 17)
+                                ])
+                            ]),
+                            table_entry(19, 26, [
+                                table_key(19, 22, [
+                                    container_double_quote_string(19, 22)
+                                ]),
+                                table_value(24, 26)
+                            ])
+                        ])
+                    ])
+                ]),
+                EOI(27, 27)
+            ])
+        ]
+    }
+
+    // whitespace is not allowed between a table key and its colon
+    fails_with! {
+        parser: AsciiParser,
+        input: "{'k' :1}",
+        rule: AsciiRule::table_value,
+        positives: vec![AsciiRule::table_key],
+        negatives: vec![],
+        pos: 1
+    }
+}
+
+#[test]
+fn cif2_values_need_cif2_star_file() {
+    // without the CIF2 entry point brackets are just part of a non quoted string
+    parses_to! {
+        parser: AsciiParser,
+        input: "data_x _a [1]",
+        rule: AsciiRule::star_file,
+        tokens: [
+            star_file(0, 13, [
+                data_block(0, 13, [
+                    data_heading(0, 6),
+                    data(7, 13, [
+                        data_name(7, 9),
+                        non_quoted_string(10, 13)
+                    ])
+                ]),
+                EOI(13, 13)
+            ])
+        ]
+    }
+}
+
+#[test]
+fn cif2_lists_tables_file() {
+    let file_path = "tests/test_data/cif2_lists_tables.cif";
+    let test_string = std::fs::read_to_string(file_path).unwrap();
+    let pairs = AsciiParser::parse(AsciiRule::cif2_star_file, &test_string).unwrap();
+    let cif2_file_pair = pairs.into_iter().next().unwrap();
+    snapshot_utils::assert_snapshot_gz(
+        "parser_tests__cif2_lists_tables",
+        &ustar_test_utils::format_pest_pair(&cif2_file_pair),
+    );
+}
+
 // double_quoted_string
 #[test]
 fn double_quoted_string() {
@@ -467,6 +666,8 @@ fn basic_data_block() {
             AsciiRule::non_quoted_string,
             AsciiRule::double_quote_string,
             AsciiRule::single_quote_string,
+            AsciiRule::triple_single_quote_string,
+            AsciiRule::triple_double_quote_string,
             AsciiRule::frame_code,
             AsciiRule::semi_colon_string
         ],
@@ -749,6 +950,35 @@ fn data_loop() {
     }
 }
 
+#[test]
+fn data_loop_definition_starts_with_a_data_name() {
+    parses_to! {
+        parser: AsciiParser,
+        input:  "_a loop_ _b _c",
+        rule:   AsciiRule::data_loop_definition,
+        tokens: [
+            data_loop_definition(0, 14, [
+                data_name(0, 2),
+                nested_loop(3, 14, [
+                    loop_keyword(3, 8),
+                    data_name(9, 11),
+                    data_name(12, 14)
+                ])
+            ])
+        ]
+    }
+
+    // the outermost level would have no tags
+    fails_with! {
+        parser: AsciiParser,
+        input: "loop_ _b _c",
+        rule: AsciiRule::data_loop_definition,
+        positives: vec![AsciiRule::data_name],
+        negatives: vec![],
+        pos: 0
+    }
+}
+
 #[test]
 fn data_block_with_save_frame() {
     // data_frame with save_frames from
@@ -1436,6 +1666,77 @@ fn comment_before_semicolon_string() {
     assert_eq!(star_file.as_rule(), AsciiRule::star_file);
 }
 
+#[rstest]
+#[case::between_loop_rows("comment_between_loop_rows", 2)]
+#[case::after_loop_keyword("comment_after_loop_keyword", 2)]
+#[case::before_stop("comment_before_stop", 2)]
+fn comments_inside_loops_are_kept(#[case] name: &str, #[case] expected_comments: usize) {
+    // Comments inside a loop are kept as comment nodes so the walker can report them,
+    // comments elsewhere are still skipped as whitespace
+    let file_path = format!("tests/test_data/{}.star", name);
+    let test_string = std::fs::read_to_string(&file_path).unwrap();
+    let pairs = AsciiParser::parse(AsciiRule::star_file, &test_string).unwrap();
+    let star_file_pair = pairs.into_iter().next().unwrap();
+
+    let comments = star_file_pair
+        .clone()
+        .into_inner()
+        .flatten()
+        .filter(|pair| pair.as_rule() == AsciiRule::comment)
+        .count();
+    assert_eq!(comments, expected_comments);
+
+    snapshot_utils::assert_snapshot_gz(
+        &format!("parser_tests__{}", name),
+        &ustar_test_utils::format_pest_pair(&star_file_pair),
+    );
+}
+
+#[rstest]
+#[case::after_save_frame("data_test\nsave_frame\n_tag value\nsave_\n# trailing comment\n")]
+#[case::after_data_item("data_test\n_tag value\n# trailing comment\n")]
+#[case::no_final_newline("data_test\nsave_frame\n_tag value\nsave_\n\n\n# End of data_test")]
+#[case::several_lines("data_test\n_tag value\n\n# one\n   # two\n\n")]
+fn trailing_comments_before_eoi(#[case] input: &str) {
+    // e.g. CCPN_XPLOR_test1.nef ends with comment lines after the final save_
+    use ustar::parsers::extended::{ExtendedParser, Rule as ExtendedRule};
+    use ustar::parsers::unicode::{Rule as UnicodeRule, UnicodeParser};
+
+    let ascii = AsciiParser::parse(AsciiRule::star_file, input).unwrap();
+    assert_eq!(ascii.as_str(), input);
+    let extended = ExtendedParser::parse(ExtendedRule::star_file, input).unwrap();
+    assert_eq!(extended.as_str(), input);
+    let unicode = UnicodeParser::parse(UnicodeRule::star_file, input).unwrap();
+    assert_eq!(unicode.as_str(), input);
+}
+
+#[rstest]
+#[case::data_name("data_test\nloop_ _a _b 1 2 3 4\n_next 5\n", "loop_ _a _b 1 2 3 4")]
+#[case::save_end("data_test\nsave_frame\nloop_ _a 1 2\nsave_\n", "loop_ _a 1 2")]
+#[case::loop_keyword("data_test\nloop_ _a 1 2\nloop_ _b 3\n", "loop_ _a 1 2")]
+#[case::data_heading("data_test\nloop_ _a 1 2\ndata_next\n_b 3\n", "loop_ _a 1 2")]
+#[case::global_heading("data_test\nloop_ _a 1 2\nglobal_\n_b 3\n", "loop_ _a 1 2")]
+#[case::eoi("data_test\nloop_ _a 1 2 # last\n", "loop_ _a 1 2")]
+#[case::nested(
+    "data_test\nloop_ _a loop_ _b _c stop_ 1 2 3 4 5\n_next 6\n",
+    "loop_ _a loop_ _b _c stop_ 1 2 3 4 5"
+)]
+#[case::nested_inner_stop(
+    "data_test\nloop_ _a loop_ _b _c stop_ 1 2 3 stop_ 4 5 6\nsave_x\n_d 1\nsave_\n",
+    "loop_ _a loop_ _b _c stop_ 1 2 3 stop_ 4 5 6"
+)]
+fn loops_end_without_stop(#[case] input: &str, #[case] expected_loop: &str) {
+    // NMR-STAR and NEF files often leave out the final stop_, the next token ends the loop
+    let pairs = AsciiParser::parse(AsciiRule::star_file, input).unwrap();
+    let data_loop = pairs
+        .flatten()
+        .find(|pair| pair.as_rule() == AsciiRule::data_loop)
+        .unwrap();
+    assert_eq!(data_loop.as_str(), expected_loop);
+    let values = data_loop.into_inner().last().unwrap();
+    assert_eq!(values.as_rule(), AsciiRule::data_loop_values);
+}
+
 #[test]
 fn single_quote_string_closed_with_two_quotes() {
     // Test that a single-quoted string ending with '' (two quotes before space/EOI)
@@ -1693,6 +1994,11 @@ generate_quote_tests! {
     multiple_quotes_at_start: ("'''Hello world'", "'''Hello world'", r#""""Hello world""#, r#""""Hello world""#),
     complex_with_quotes_at_start: ("'''''data''more''text'", "'''''data''more''text'", r#"""""data""more""text""#, r#"""""data""more""text""#),
     quotes_at_both_ends: ("'''Hello world'''", "'''Hello world'''", r#""""Hello world""""#, r#""""Hello world""""#),
+    // minimal reproducers for values from the CCPN NEF specification files
+    nef_interior_space_and_slash: ("'15N HSQC/HMQC'", "'15N HSQC/HMQC'", r#""15N HSQC/HMQC""#, r#""15N HSQC/HMQC""#),
+    nef_padded_with_spaces: ("' -,-  '", "' -,-  '", r#"" -,-  ""#, r#"" -,-  ""#),
+    nef_braces_and_slashes: ("' {*}(Hb*/Hg*),Cb  '", "' {*}(Hb*/Hg*),Cb  '", r#"" {*}(Hb*/Hg*),Cb  ""#, r#"" {*}(Hb*/Hg*),Cb  ""#),
+    nef_hash_and_colon: ("'Orig. #: 210, ARIA2 REJECT'", "'Orig. #: 210, ARIA2 REJECT'", r#""Orig. #: 210, ARIA2 REJECT""#, r#""Orig. #: 210, ARIA2 REJECT""#),
 }
 
 // Test cases that should fail
@@ -1763,6 +2069,10 @@ fn test_invalid_double_quote_with_space_after_double_quote(
 #[case::double_quote_followed_by_space("\"test\" ", "\"test\"")]
 #[case::single_quote_followed_by_newline("'test'\n", "'test'")]
 #[case::double_quote_followed_by_newline("\"test\"\n", "\"test\"")]
+#[case::nef_interior_space_then_newline("'15N HSQC/HMQC'\n", "'15N HSQC/HMQC'")]
+#[case::nef_padded_then_next_value("' -,-  ' ' -,-  '", "' -,-  '")]
+#[case::single_quote_before_double_quote("'it'\"s' ", "'it'\"s'")]
+#[case::double_quote_before_single_quote("\"it\"'s\" ", "\"it\"'s\"")]
 fn test_quote_termination(#[case] input: &str, #[case] expected: &str) {
     // Test both single and double quotes
     let rule = if input.starts_with('\'') {
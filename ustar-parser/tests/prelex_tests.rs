@@ -0,0 +1,274 @@
+use indoc::indoc;
+use proptest::prelude::*;
+use rstest::rstest;
+use std::fs;
+use std::path::Path;
+use ustar::{
+    parse, walk, ConfigKey, ConfigValue, EncodingMode, ErrorFormatMode, ParserConfig,
+    ParserConfigBuilder,
+};
+use ustar_test_utils::{ensure_test_data_available, TestDataPolicy};
+
+fn config_with_prelex(encoding: EncodingMode, prelex: bool) -> ParserConfig {
+    ParserConfigBuilder::new()
+        .encoding(encoding)
+        .error_format(ErrorFormatMode::Basic)
+        .prelex(prelex)
+        .build()
+        .unwrap()
+}
+
+/// Check that parsing `input` with the pre-lexer gives the tree, or the error, parsing it as
+/// written gives
+fn assert_prelex_matches(input: &str, encoding: EncodingMode) {
+    let plain = parse(input, &config_with_prelex(encoding, false));
+    let prelexed = parse(input, &config_with_prelex(encoding, true));
+    match (prelexed, plain) {
+        (Ok(tree), Ok(expected)) => assert_eq!(tree, expected, "for input\n{}", input),
+        (Err(error), Err(expected)) => {
+            assert_eq!(
+                error.to_string(),
+                expected.to_string(),
+                "for input\n{}",
+                input
+            )
+        }
+        (tree, expected) => panic!(
+            "prelexed {:?} but parsed {:?} for input\n{}",
+            tree.map(|_| "tree"),
+            expected.map(|_| "tree"),
+            input
+        ),
+    }
+}
+
+#[rstest]
+#[case::loop_comments(indoc! {"
+    # a comment before the block
+    data_test   # after the heading
+    loop_   # after the keyword
+      _atom.id    # after a name
+      _atom.name
+      # between the names and values
+      1   CA   # after a row
+      2   CB
+      # before stop
+    stop_
+    # after the loop
+"})]
+#[case::nested_loop_without_stop(indoc! {"
+    data_test
+    loop_
+      _a
+      loop_   _b   _c   # trailing the nested loop
+
+      1 2 3   4 5 6
+"})]
+#[case::comment_before_text_field(indoc! {"
+    data_test
+    _item # a comment before a text field
+    ;
+    text   with   spaces
+    ;
+    loop_ _a
+    # a comment in a loop before a text field
+    ;
+    first value
+    ;
+    stop_
+"})]
+#[case::indented_semicolons("data_test\n   _a\n   ;   _b\n   ;\n_c\n;\ntext\n;\n")]
+#[case::quote_before_other_quote("data_test\n_a   'it'\"s'\n_b   \"it\"'s\"\n")]
+#[case::quoted_strings(indoc! {"
+    data_test
+    _a   'it''s   quoted'
+    _b   \"a  \"\"b\"\"  c\"
+    _c   'O'Brien   and   co'
+    _d   '''triple   quoted
+    over   lines'''
+    _e   \"mixed'quote\"
+    _f   C1'   # an atom name
+    _g   ''
+"})]
+#[case::frame_codes_and_keywords(indoc! {"
+    data_test
+    save_frame_one
+      _frame.link   $frame_two
+      _frame.hash   a#b   # not a comment inside a value
+    save_
+    save_frame_two
+      _frame.value   1
+    save_
+"})]
+#[case::crlf("data_test\r\n  _a   1   # c\r\n  loop_ _b _c\r\n  # inside\r\n  1 2\r\n;\r\ntext\r\n;\r\n  stop_\r\n")]
+#[case::tabs_and_blank_lines("data_test\n\n\n\t_a\t\t1\n\n\tloop_\t_b\n\t\t2\t\t3\n")]
+#[case::global_block("global_\n   _a   1\ndata_test\n   _b   2\n")]
+#[case::comment_at_end("data_test\n_a 1\n# no final newline")]
+#[case::unclosed_quote("data_test\n_a 'unclosed   quote\n")]
+#[case::syntax_error("data_test\n\n\n   _a   \n   _b   2\n")]
+#[case::empty("")]
+#[case::only_comments("# one\n   # two\n")]
+fn test_prelex_gives_the_tree_of_the_input(#[case] input: &str) {
+    for encoding in [EncodingMode::Ascii, EncodingMode::Unicode] {
+        assert_prelex_matches(input, encoding);
+    }
+}
+
+#[test]
+fn test_prelex_keeps_the_positions_of_loop_comments() {
+    let input = "data_test\nloop_ _a\n   # first\n1\n      # second\n2\nstop_\n";
+    let tree = parse(input, &config_with_prelex(EncodingMode::Ascii, true)).unwrap();
+
+    let comments = tree.find_all("comment");
+    let texts: Vec<&str> = comments.iter().map(|comment| comment.as_str()).collect();
+    assert_eq!(texts, ["# first", "# second"]);
+    assert_eq!(comments[1].start, input.find("# second").unwrap());
+    assert_eq!(comments[1].start_position.line, 5);
+    assert_eq!(comments[1].start_position.column, 7);
+}
+
+#[test]
+fn test_prelex_errors_are_those_of_the_input() {
+    let input = "data_test\n   _a   1\n   _b\n\n   # comment\n";
+    let error = parse(input, &config_with_prelex(EncodingMode::Ascii, true)).unwrap_err();
+    let expected = parse(input, &config_with_prelex(EncodingMode::Ascii, false)).unwrap_err();
+
+    assert_eq!(error.core().offset, expected.core().offset);
+    assert_eq!(error.to_string(), expected.to_string());
+}
+
+#[test]
+fn test_prelex_falls_back_for_input_it_cannot_lex() {
+    // non-ASCII blanks, frame codes running into quotes and quotes followed by the other quote
+    for input in [
+        "data_test\n_a\u{2003}1\n",
+        "data_test\nsave_a\n_b $c'd  e'\nsave_\n",
+        "data_test\nloop_ _a 'b'\"c\" stop_\n",
+        "data_test\n_a ';b\n",
+    ] {
+        assert_prelex_matches(input, EncodingMode::Unicode);
+    }
+}
+
+#[test]
+fn test_prelex_config_key_is_named() {
+    assert_eq!(ConfigKey::Prelex.name(), "prelex");
+    assert_eq!(ConfigKey::from_name("prelex"), Some(ConfigKey::Prelex));
+
+    let mut config = config_with_prelex(EncodingMode::Ascii, false);
+    config.insert(ConfigKey::Prelex, ConfigValue::Bool(true));
+    assert!(ustar::get_prelex(&config));
+}
+
+#[test]
+fn test_walk_is_unaffected_by_prelex() {
+    use ustar::sas_handlers::DocumentBuilderHandler;
+
+    let input = "data_test\nloop_ _a   # c\n 1   2\nstop_\n";
+    let mut plain = DocumentBuilderHandler::new();
+    walk(
+        input,
+        &config_with_prelex(EncodingMode::Ascii, false),
+        &mut plain,
+    )
+    .unwrap();
+    let mut prelexed = DocumentBuilderHandler::new();
+    walk(
+        input,
+        &config_with_prelex(EncodingMode::Ascii, true),
+        &mut prelexed,
+    )
+    .unwrap();
+    assert_eq!(prelexed.into_document(), plain.into_document());
+}
+
+/// Every file of `dir` with `extension` parses to the same tree with and without the pre-lexer
+fn assert_prelex_matches_files(dir: &Path, extension: &str, encoding: EncodingMode) {
+    let mut files = 0;
+    for entry in fs::read_dir(dir).unwrap_or_else(|e| panic!("Failed to read {:?}: {}", dir, e)) {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|s| s.to_str()) != Some(extension) {
+            continue;
+        }
+        let content = String::from_utf8_lossy(&fs::read(&path).unwrap()).to_string();
+        assert_prelex_matches(&content, encoding);
+        files += 1;
+    }
+    assert!(files > 0, "no .{} files in {:?}", extension, dir);
+}
+
+#[rstest]
+#[case::examples("tests/test_data", "star")]
+#[case::sas_files("tests/test_data/sas_test_files", "str")]
+#[case::dictionary("tests/test_data", "dic")]
+fn test_prelex_matches_committed_files(#[case] dir: &str, #[case] extension: &str) {
+    assert_prelex_matches_files(Path::new(dir), extension, EncodingMode::Unicode);
+}
+
+#[rstest]
+#[case::bmrb("tests/test_data/bmrb_stars", "str")]
+#[case::cod("tests/test_data/cod_cifs", "cif")]
+#[case::dicts("tests/test_data/dicts", "dic")]
+#[case::nef("tests/test_data/nef_examples", "nef")]
+fn test_prelex_matches_downloaded_files(#[case] dir: &str, #[case] extension: &str) {
+    let dir = Path::new(dir);
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity");
+    assert_prelex_matches_files(dir, extension, EncodingMode::Unicode);
+}
+
+/// Tokens and the text between them, chosen to meet the cases the pre-lexer treats specially
+fn token() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(vec![
+        "data_a",
+        "save_f",
+        "save_",
+        "loop_",
+        "stop_",
+        "global_",
+        "_tag",
+        "_t.x",
+        "value",
+        "1.5",
+        "C1'",
+        "a#b",
+        "$f",
+        "'q'",
+        "'it''s'",
+        "\"d q\"",
+        "'a'\"b\"",
+        "'''t\nq'''",
+        "''",
+        "#c",
+        "# c c",
+        ";",
+        "\n;text\n;",
+        "\r\n;x\r\n;",
+        " ",
+        "  ",
+        "\t",
+        "\n",
+        "\r\n",
+        "\n\n",
+    ])
+}
+
+proptest! {
+    #[test]
+    fn prop_prelex_gives_the_tree_of_the_input(tokens in prop::collection::vec(token(), 0..40)) {
+        let input = format!("data_test\n{}", tokens.concat());
+        assert_prelex_matches(&input, EncodingMode::Ascii);
+    }
+
+    #[test]
+    fn prop_prelex_gives_the_tree_of_loops(
+        rows in prop::collection::vec(prop::collection::vec(token(), 1..4), 0..10)
+    ) {
+        let mut input = String::from("data_test\nloop_ _a _b\n");
+        for row in rows {
+            input.push_str(&row.join(" "));
+            input.push('\n');
+        }
+        assert_prelex_matches(&input, EncodingMode::Ascii);
+    }
+}
@@ -0,0 +1,125 @@
+use std::ops::ControlFlow;
+use ustar::sas_handlers::DocumentBuilderHandler;
+use ustar::{
+    default_config, parse, parse_with_progress, walk_with_progress, ConfigKey, ConfigValue,
+    ErrorCode, ParseProgress, UstarError,
+};
+
+const THREE_BLOCKS: &str = "data_first\n_a 1\n\ndata_second\n_b 2\n\nglobal_\n_c 3\n";
+
+/// Offset of the second block, where a parse cancelled after the first block stops
+fn second_block() -> usize {
+    THREE_BLOCKS.find("data_second").unwrap()
+}
+
+#[test]
+fn test_progress_is_reported_after_each_block() {
+    let mut reports = Vec::new();
+    let tree = parse_with_progress(THREE_BLOCKS, &default_config(), &mut |progress| {
+        reports.push(progress);
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+
+    assert_eq!(tree, parse(THREE_BLOCKS, &default_config()).unwrap());
+    let total_bytes = THREE_BLOCKS.len();
+    assert_eq!(
+        reports,
+        vec![
+            ParseProgress {
+                bytes_consumed: second_block(),
+                total_bytes,
+                blocks_completed: 1
+            },
+            ParseProgress {
+                bytes_consumed: THREE_BLOCKS.find("global_").unwrap(),
+                total_bytes,
+                blocks_completed: 2
+            },
+            ParseProgress {
+                bytes_consumed: total_bytes,
+                total_bytes,
+                blocks_completed: 3
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_cancel_after_the_first_block() {
+    let mut calls = 0;
+    let error = parse_with_progress(THREE_BLOCKS, &default_config(), &mut |_| {
+        calls += 1;
+        ControlFlow::Break(())
+    })
+    .unwrap_err();
+
+    assert_eq!(calls, 1);
+    assert!(matches!(*error, UstarError::Cancelled { .. }));
+    assert_eq!(error.code(), ErrorCode::E0022Cancelled);
+    assert_eq!(error.core().offset, second_block());
+    assert_eq!((error.core().line, error.core().col), (4, 1));
+    assert_eq!(error.core().message, "parsing cancelled after 1 block");
+}
+
+#[test]
+fn test_parse_errors_are_reported_before_cancelling() {
+    let input = "data_first\nloop_ _a\n\ndata_second\n_b 2\n";
+    let mut calls = 0;
+    let error = parse_with_progress(input, &default_config(), &mut |_| {
+        calls += 1;
+        ControlFlow::Continue(())
+    })
+    .unwrap_err();
+
+    assert_eq!(calls, 0);
+    assert_eq!(
+        error.code(),
+        parse(input, &default_config()).unwrap_err().code()
+    );
+}
+
+#[test]
+fn test_walk_cancelled_after_the_first_block() {
+    let mut handler = DocumentBuilderHandler::new();
+    let mut reports = Vec::new();
+    let error = walk_with_progress(
+        THREE_BLOCKS,
+        &default_config(),
+        &mut handler,
+        &mut |report| {
+            reports.push(report);
+            ControlFlow::Break(())
+        },
+    )
+    .unwrap_err();
+
+    assert!(matches!(*error, UstarError::Cancelled { .. }));
+    assert_eq!(error.code(), ErrorCode::E0022Cancelled);
+    assert_eq!(error.core().offset, reports[0].bytes_consumed);
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].blocks_completed, 1);
+    // the walk reports the end of the block's last value, before the whitespace after it
+    assert_eq!(reports[0].bytes_consumed, "data_first\n_a 1".len());
+
+    let document = handler.into_document();
+    assert_eq!(document.data_blocks.len(), 1);
+    assert_eq!(document.data_blocks[0].name, "first");
+}
+
+#[test]
+fn test_walk_reports_every_block_with_tree_checks() {
+    // a dialect restriction makes the walk go through a checked tree
+    let mut config = default_config();
+    config.insert(ConfigKey::RequireStopKeyword, ConfigValue::Bool(true));
+    let mut handler = DocumentBuilderHandler::new();
+    let mut blocks = Vec::new();
+    let stopped = walk_with_progress(THREE_BLOCKS, &config, &mut handler, &mut |report| {
+        blocks.push(report.blocks_completed);
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+
+    assert!(!stopped);
+    assert_eq!(blocks, vec![1, 2, 3]);
+}
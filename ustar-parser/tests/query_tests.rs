@@ -0,0 +1,218 @@
+use indoc::indoc;
+use ustar::mutable_pair::MutablePair;
+use ustar::query::{parse_query, QueryError};
+use ustar::{default_config, parse_arena, parse_default};
+
+const INPUT: &str = indoc! {"
+    global_
+    _version 1.1
+    data_experiment
+    _title 'A test'
+    save_fragment_1
+        _molecular_weight 1024.5
+        loop_
+            _atom_site.id _atom_site.Cartn_x
+            1 1.5
+            2 2.5
+            3 3.5
+        stop_
+    save_
+    save_fragment_2
+        _molecular_weight 2048.0
+    save_
+    data_control
+    loop_
+        _atom_site.id _atom_site.Cartn_x
+        1 -1.0
+    stop_
+"};
+
+/// The text of the nodes `query` selects in the tree of `INPUT`
+fn select(query: &str) -> Vec<String> {
+    let tree = parse_default(INPUT).unwrap();
+    parse_query(query)
+        .unwrap()
+        .select(&tree)
+        .into_iter()
+        .map(|node: &MutablePair| node.as_str().to_string())
+        .collect()
+}
+
+#[test]
+fn test_block_frame_and_tag_segments() {
+    assert_eq!(
+        select("data_experiment/save_fragment_1/_molecular_weight"),
+        ["_molecular_weight 1024.5"]
+    );
+    assert_eq!(
+        select("/DATA_EXPERIMENT/save_fragment_2/_Molecular_Weight"),
+        ["_molecular_weight 2048.0"]
+    );
+    assert_eq!(select("data_experiment/_title"), ["_title 'A test'"]);
+    assert_eq!(select("global_/_version"), ["_version 1.1"]);
+    assert_eq!(
+        select("data_experiment/save_fragment_1/_atom_site.Cartn_x"),
+        ["1.5", "2.5", "3.5"]
+    );
+    assert!(select("data_experiment/_molecular_weight").is_empty());
+    assert!(select("data_missing").is_empty());
+
+    let frames = select("data_experiment/save_fragment_1");
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].starts_with("save_fragment_1\n"));
+}
+
+#[test]
+fn test_wildcards() {
+    assert_eq!(
+        select("data_experiment/save_*/_molecular_weight"),
+        ["_molecular_weight 1024.5", "_molecular_weight 2048.0"]
+    );
+    assert_eq!(
+        select("data_*/save_fragment_1/_atom_site.*"),
+        ["1", "2", "3", "1.5", "2.5", "3.5"]
+    );
+    // _version, _title, the two save frames and the id and Cartn_x of the control loop
+    assert_eq!(select("*/*").len(), 6);
+    assert_eq!(
+        select("data_experiment/save_*2/*"),
+        ["_molecular_weight 2048.0"]
+    );
+}
+
+#[test]
+fn test_descendant_operator() {
+    assert_eq!(
+        select("//_atom_site.Cartn_x"),
+        ["1.5", "2.5", "3.5", "-1.0"]
+    );
+    assert_eq!(select("data_control//_atom_site.Cartn_x"), ["-1.0"]);
+    assert_eq!(
+        select("//_molecular_weight"),
+        ["_molecular_weight 1024.5", "_molecular_weight 2048.0"]
+    );
+    assert_eq!(select("//save_fragment_2/_molecular_weight").len(), 1);
+}
+
+#[test]
+fn test_row_index_predicate() {
+    assert_eq!(select("//_atom_site.Cartn_x[3]"), ["3.5"]);
+    assert_eq!(select("//_atom_site.Cartn_x[1]"), ["1.5", "-1.0"]);
+    assert!(select("//_atom_site.Cartn_x[4]").is_empty());
+    assert_eq!(select("//_molecular_weight[1]").len(), 2);
+    assert!(select("//_molecular_weight[2]").is_empty());
+
+    let second = select("data_experiment/save_*[2]");
+    assert_eq!(second.len(), 1);
+    assert!(second[0].starts_with("save_fragment_2\n"));
+}
+
+#[test]
+fn test_nested_loop_columns() {
+    let input = indoc! {"
+        data_nested
+        loop_
+            _outer.id _outer.name
+            loop_
+                _inner.id _inner.value
+        1 a
+            11 x 12 y
+        stop_
+        2 b
+            21 z
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+    let values = |query: &str| -> Vec<&str> {
+        parse_query(query)
+            .unwrap()
+            .select(&tree)
+            .into_iter()
+            .map(|node| node.as_str())
+            .collect()
+    };
+    assert_eq!(values("//_inner.value"), ["x", "y", "z"]);
+    assert_eq!(values("//_inner.id[3]"), ["21"]);
+    assert_eq!(values("//_outer.name"), ["a", "b"]);
+}
+
+#[test]
+fn test_arena_and_mutable_pair_select_the_same_nodes() {
+    let tree = parse_default(INPUT).unwrap();
+    let arena = parse_arena(INPUT, &default_config()).unwrap();
+    let root = arena.root().unwrap();
+
+    for query in [
+        "data_experiment/save_fragment_1/_molecular_weight",
+        "data_experiment/save_fragment_1/_atom_site.Cartn_x",
+        "//_atom_site.Cartn_x",
+        "//_atom_site.id[2]",
+        "data_experiment/save_*",
+        "data_experiment/*[2]",
+        "global_/_version",
+        "//*",
+        "data_missing",
+    ] {
+        let query = parse_query(query).unwrap();
+        let from_tree: Vec<_> = query
+            .select(&tree)
+            .into_iter()
+            .map(|node| {
+                (
+                    node.rule_name(),
+                    node.as_str(),
+                    node.start_pos(),
+                    node.end_pos(),
+                )
+            })
+            .collect();
+        let from_arena: Vec<_> = query
+            .select(root)
+            .into_iter()
+            .map(|node| {
+                (
+                    node.rule_name(),
+                    node.as_str(),
+                    node.start_pos(),
+                    node.end_pos(),
+                )
+            })
+            .collect();
+        assert_eq!(from_arena, from_tree, "{:?}", query);
+    }
+}
+
+#[test]
+fn test_invalid_queries() {
+    assert_eq!(parse_query(""), Err(QueryError::Empty));
+    assert_eq!(
+        parse_query("data_a///_tag"),
+        Err(QueryError::Segment {
+            segment: "///_tag".to_string(),
+            column: 7,
+        })
+    );
+    assert_eq!(
+        parse_query("data_a/").unwrap_err().to_string(),
+        "c7 '/' isn't a data_, global_, save_ or data name segment or *"
+    );
+    assert!(matches!(
+        parse_query("data_/_tag"),
+        Err(QueryError::Segment { column: 1, .. })
+    ));
+    assert!(matches!(
+        parse_query("data_a/tag"),
+        Err(QueryError::Segment { column: 8, .. })
+    ));
+    assert_eq!(
+        parse_query("//_tag[0]"),
+        Err(QueryError::Index {
+            predicate: "[0]".to_string(),
+            column: 7,
+        })
+    );
+    assert_eq!(
+        parse_query("//_tag[x]").unwrap_err().to_string(),
+        "c7 [x] isn't a row number counting from 1"
+    );
+}
@@ -0,0 +1,253 @@
+use indoc::indoc;
+use ustar::parse_default;
+use ustar::sas_handlers::{CsvLoopHandler, Document, DocumentBuilderHandler, Item, Loop, LoopRow};
+use ustar::sas_walker::StarWalker;
+
+mod snapshot_utils;
+
+ustar_test_utils::snapshot_orphan_check!("sas_handlers_tests");
+
+fn build_document(input: &str) -> Document {
+    let tree = parse_default(input).expect("Failed to parse");
+    let mut handler = DocumentBuilderHandler::new();
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    handler.into_document()
+}
+
+fn row(values: &[&str], rows: Vec<LoopRow>) -> LoopRow {
+    LoopRow {
+        values: values.iter().map(|value| value.to_string()).collect(),
+        rows,
+    }
+}
+
+fn tags(levels: &[&[&str]]) -> Vec<Vec<String>> {
+    levels
+        .iter()
+        .map(|level| level.iter().map(|tag| tag.to_string()).collect())
+        .collect()
+}
+
+#[test]
+fn test_items_saveframes_and_unquoted_values() {
+    let input = indoc! {"
+        data_test
+        _entry.id  1
+        _entry.title  'a title'
+        save_first
+        _frame.text
+        ;
+        line one
+        ;
+        _frame.link  $other
+        save_
+    "};
+
+    let document = build_document(input);
+    let block = document.data_block("TEST").unwrap();
+    assert_eq!(block.item("_entry.title"), Some("a title"));
+    assert_eq!(
+        block.items[0],
+        Item {
+            tag: "_entry.id".to_string(),
+            value: "1".to_string(),
+        }
+    );
+
+    let frame = block.saveframe("first").unwrap();
+    assert_eq!(frame.item("_frame.text"), Some("\nline one"));
+    assert_eq!(frame.item("_frame.link"), Some("$other"));
+    assert!(block.item("_frame.link").is_none());
+}
+
+#[test]
+fn test_nested_loop_rows() {
+    let input = indoc! {"
+        data_test
+        loop_
+            _outer
+            loop_
+                _inner
+                _label
+            stop_
+            a
+                1 x
+                2 y
+            stop_
+            b
+                3 z
+            stop_
+        stop_
+    "};
+
+    let document = build_document(input);
+    assert_eq!(
+        document.data_blocks[0].loops,
+        vec![Loop {
+            tags: tags(&[&["_outer"], &["_inner", "_label"]]),
+            rows: vec![
+                row(
+                    &["a"],
+                    vec![row(&["1", "x"], vec![]), row(&["2", "y"], vec![])]
+                ),
+                row(&["b"], vec![row(&["3", "z"], vec![])]),
+            ],
+        }]
+    );
+}
+
+#[test]
+fn test_empty_loop_has_tags_but_no_rows() {
+    let input = indoc! {"
+        data_test
+        save_frame
+        loop_
+            _row.id
+            _row.value
+        stop_
+        save_
+    "};
+
+    let document = build_document(input);
+    let frame = &document.data_blocks[0].saveframes[0];
+    assert_eq!(
+        frame.loops,
+        vec![Loop {
+            tags: tags(&[&["_row.id", "_row.value"]]),
+            rows: vec![],
+        }]
+    );
+    assert!(frame.items.is_empty());
+}
+
+#[test]
+fn test_global_blocks_are_kept_apart() {
+    let input = indoc! {"
+        global_
+        _version  2
+        data_test
+        _entry.id  1
+    "};
+
+    let document = build_document(input);
+    assert_eq!(document.global_blocks.len(), 1);
+    assert_eq!(document.global_blocks[0].name, "");
+    assert_eq!(document.global_blocks[0].item("_version"), Some("2"));
+    assert_eq!(document.data_blocks.len(), 1);
+    assert_eq!(document.data_blocks[0].item("_version"), None);
+}
+
+fn extract_loops(input: &str, prefixes: &[&str], separator: char) -> (String, usize) {
+    let tree = parse_default(input).expect("Failed to parse");
+    let mut handler = CsvLoopHandler::new(Vec::new(), prefixes).with_separator(separator);
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    let loops_written = handler.loops_written();
+    let written = String::from_utf8(handler.into_inner().unwrap()).unwrap();
+    (written, loops_written)
+}
+
+#[test]
+fn test_csv_quoting_and_nulls() {
+    let input = indoc! {"
+        data_test
+        _other.id  1
+        loop_
+            _atom_site.id
+            _atom_site.label
+            _atom_site.occupancy
+            1  'C, alpha'  .
+            2  'say \"hi\"'  ?
+            3  '.'  1.0
+        stop_
+        loop_
+            _bond.id
+            1
+        stop_
+    "};
+
+    let (csv, loops_written) = extract_loops(input, &["_ATOM_SITE"], ',');
+    assert_eq!(loops_written, 1);
+    assert_eq!(
+        csv,
+        indoc! {r#"
+            _atom_site.id,_atom_site.label,_atom_site.occupancy
+            1,"C, alpha",
+            2,"say ""hi""",
+            3,.,1.0
+        "#}
+    );
+
+    let (tsv, _) = extract_loops(input, &["_atom_site"], '\t');
+    assert_eq!(tsv.lines().nth(1), Some("1\tC, alpha\t"));
+}
+
+#[test]
+fn test_csv_flattens_nested_loops() {
+    let input = indoc! {"
+        data_test
+        loop_
+            _outer
+            loop_
+                _inner
+                _label
+            stop_
+            a
+                1 x
+                2 y
+            stop_
+            b
+            stop_
+        stop_
+    "};
+
+    let (csv, _) = extract_loops(input, &["_outer"], ',');
+    assert_eq!(
+        csv,
+        indoc! {"
+            _outer,_inner,_label
+            a,1,x
+            a,2,y
+            b,,
+        "}
+    );
+}
+
+#[test]
+fn test_csv_writes_each_matching_loop_with_its_header() {
+    let input = indoc! {"
+        data_test
+        loop_
+            _a.id
+            1
+            2
+        stop_
+        save_frame
+        loop_
+            _a.name
+        stop_
+        save_
+        loop_
+            _b.id
+            3
+        stop_
+    "};
+
+    let (csv, loops_written) = extract_loops(input, &["_a."], ',');
+    assert_eq!(loops_written, 2);
+    assert_eq!(csv, "_a.id\n1\n2\n\n_a.name\n");
+
+    let (csv, loops_written) = extract_loops(input, &["_c."], ',');
+    assert_eq!((csv.as_str(), loops_written), ("", 0));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_comprehensive_example_document_json() {
+    let input = std::fs::read_to_string("tests/test_data/comprehensive_example.star").unwrap();
+    let document = build_document(&input);
+
+    snapshot_utils::assert_snapshot_gz(
+        "sas_handlers_tests__comprehensive_example_document_json",
+        &serde_json::to_string_pretty(&document).unwrap(),
+    );
+}
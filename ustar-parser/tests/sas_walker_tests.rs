@@ -1,20 +1,33 @@
+use indoc::indoc;
 use std::collections::HashMap;
 use std::fs;
+use std::io::Cursor;
 use std::path::Path;
 use ustar::line_column_index::LineColumn;
-use ustar::parse_default;
-use ustar::sas_interface::{SASContentHandler, EMPTY_LOOP_DELIMITER};
-use ustar::sas_walker::StarWalker;
+use ustar::sas_handlers::StarWriterHandler;
+use ustar::sas_interface::{
+    CommentKind, FilterHandler, SASContentHandler, StreamCheckpoint, TeeHandler, ValueDelimiter,
+    WalkControl,
+};
+use ustar::sas_walker::{resume_from, ResumeError, StarWalker};
+use ustar::{
+    default_config, parse, parse_arena, parse_default, CifVersion, ColumnUnit, ConfigKey,
+    ConfigValue, EncodingMode,
+};
+
+use ustar_test_utils::{ensure_test_data_available, TestDataPolicy};
 
 mod snapshot_utils;
 
+ustar_test_utils::snapshot_orphan_check!("sas_walker_tests");
+
 // Files that are known to fail parsing (or have special handling needs)
 static KNOWN_PARSE_FAILURES: &[&str] = &[
-    "loop3.str",   // loop with no header we should fail this
-    "loop4.str",   // loop with no body, should we parse this
-    "loop5.str",   // has triple quoted strings
-    "warning.cif", // has triple quoted strings again and tests an error state...
-    "warning.str", // uses triple quoted strings and tests an error state [runaway string]P
+    "loop3.str",   // loop with no header, an error even when stop_ isn't required
+    "loop4.str",   // loop with no header or body, an error even when stop_ isn't required
+    "loop5.str",   // triple quoted string with no closing triple quote
+    "warning.cif", // tests an error state: """ string with no closing triple quote [EOF in value]
+    "warning.str", // tests an error state: """ string with no closing triple quote [runaway string]
 ];
 
 // Test input constants for early termination tests
@@ -42,6 +55,24 @@ data_test
     _after value
 ";
 
+const NESTED_LOOP_INPUT: &str = "
+data_test
+    loop_
+        _outer
+        loop_
+            _inner
+            _label
+        stop_
+        a
+            1 x
+            2 y
+        stop_
+        b
+            3 z
+        stop_
+    stop_
+";
+
 const GLOBAL_INPUT: &str = "
 global_
     _global_setting 'test_value'
@@ -89,6 +120,62 @@ data_application
     _app_name 'web_app'
 ";
 
+// loops ended without stop_, by a data name, loop_, save_, data_ heading and the end of input
+const IMPLICIT_STOP_INPUT: &str = "
+data_first
+    loop_
+        _a
+        _b
+        1 2
+        3 4
+    _after_loop 5
+    loop_
+        _c
+        6 7
+    loop_
+        _d
+        8
+    save_frame
+        loop_
+            _e
+            9 10
+    save_
+    loop_
+        _f
+        11
+
+data_second
+    loop_
+        _g
+        12
+    # comments between a loop and the end of the input are skipped
+";
+
+// nested loops ended without their final stop_, after an inner stop_ and without one
+const NESTED_IMPLICIT_STOP_INPUT: &str = "
+data_nested
+    loop_
+        _outer
+        loop_
+            _inner_a
+            _inner_b
+        stop_
+        o1
+            i1 i2
+            i3 i4
+        stop_
+        o2
+            i5 i6
+    _after_outer x
+    loop_
+        _outer_2
+        loop_
+            _inner_c
+            _inner_d
+        stop_
+        p1 j1 j2 j3 j4
+";
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 enum ElementToStopOn {
     StartStream(usize),
@@ -98,6 +185,9 @@ enum ElementToStopOn {
     EndSaveframe(usize),
     StartLoop(usize),
     EndLoop(usize),
+    LoopDefinition(usize),
+    StartLoopRow(usize),
+    EndLoopRow(usize),
     Data(usize),
 }
 
@@ -111,6 +201,9 @@ impl ElementToStopOn {
             ElementToStopOn::EndSaveframe(n) => *n,
             ElementToStopOn::StartLoop(n) => *n,
             ElementToStopOn::EndLoop(n) => *n,
+            ElementToStopOn::LoopDefinition(n) => *n,
+            ElementToStopOn::StartLoopRow(n) => *n,
+            ElementToStopOn::EndLoopRow(n) => *n,
             ElementToStopOn::Data(n) => *n,
         }
     }
@@ -124,6 +217,9 @@ impl ElementToStopOn {
             ElementToStopOn::EndSaveframe(_) => ElementType::EndSaveframe,
             ElementToStopOn::StartLoop(_) => ElementType::StartLoop,
             ElementToStopOn::EndLoop(_) => ElementType::EndLoop,
+            ElementToStopOn::LoopDefinition(_) => ElementType::LoopDefinition,
+            ElementToStopOn::StartLoopRow(_) => ElementType::StartLoopRow,
+            ElementToStopOn::EndLoopRow(_) => ElementType::EndLoopRow,
             ElementToStopOn::Data(_) => ElementType::Data,
         }
     }
@@ -138,6 +234,9 @@ enum ElementType {
     EndSaveframe,
     StartLoop,
     EndLoop,
+    LoopDefinition,
+    StartLoopRow,
+    EndLoopRow,
     Data,
 }
 
@@ -156,72 +255,93 @@ impl ParameterizedHandler {
         }
     }
 
-    fn increment_and_check(&mut self, element_type: ElementType) -> bool {
+    fn increment_and_check(&mut self, element_type: ElementType) -> WalkControl {
         let count = self.element_counts.entry(element_type).or_insert(0);
         *count += 1;
 
-        if self.stop_on.element_type() == element_type {
-            *count >= self.stop_on.get_count()
+        if self.stop_on.element_type() == element_type && *count >= self.stop_on.get_count() {
+            WalkControl::Stop
         } else {
-            false
+            WalkControl::Continue
         }
     }
 }
 
 impl SASContentHandler for ParameterizedHandler {
-    fn start_stream(&mut self, _name: Option<&str>) -> bool {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
         self.events.push("start_stream".to_string());
         self.increment_and_check(ElementType::StartStream)
     }
 
-    fn end_stream(&mut self, _position: LineColumn) -> bool {
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
         self.events.push("end_stream".to_string());
-        false
+        WalkControl::Continue
     }
 
-    fn start_global(&mut self, _position: LineColumn) -> bool {
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
         self.events.push("start_global".to_string());
-        false
+        WalkControl::Continue
     }
 
-    fn end_global(&mut self, _position: LineColumn) -> bool {
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
         self.events.push("end_global".to_string());
-        false
+        WalkControl::Continue
     }
 
-    fn start_data(&mut self, _position: LineColumn, name: &str) -> bool {
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
         self.events.push(format!("start_data({})", name));
         self.increment_and_check(ElementType::StartData)
     }
 
-    fn end_data(&mut self, _position: LineColumn, name: &str) -> bool {
+    fn end_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
         self.events.push(format!("end_data({})", name));
         self.increment_and_check(ElementType::EndData)
     }
 
-    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> bool {
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
         self.events.push(format!("start_saveframe({})", name));
         self.increment_and_check(ElementType::StartSaveframe)
     }
 
-    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> bool {
+    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
         self.events.push(format!("end_saveframe({})", name));
         self.increment_and_check(ElementType::EndSaveframe)
     }
 
-    fn start_loop(&mut self, _position: LineColumn) -> bool {
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
         self.events.push("start_loop".to_string());
         self.increment_and_check(ElementType::StartLoop)
     }
 
-    fn end_loop(&mut self, _position: LineColumn) -> bool {
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
         self.events.push("end_loop".to_string());
         self.increment_and_check(ElementType::EndLoop)
     }
 
-    fn comment(&mut self, _position: LineColumn, text: &str) -> bool {
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        _level: usize,
+    ) -> WalkControl {
+        self.events
+            .push(format!("loop_definition({})", tags.join(", ")));
+        self.increment_and_check(ElementType::LoopDefinition)
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.events.push(format!("start_loop_row({})", row_index));
+        self.increment_and_check(ElementType::StartLoopRow)
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.events.push(format!("end_loop_row({})", row_index));
+        self.increment_and_check(ElementType::EndLoopRow)
+    }
+
+    fn comment(&mut self, _position: LineColumn, text: &str) -> WalkControl {
         self.events.push(format!("comment({})", text));
-        false
+        WalkControl::Continue
     }
 
     fn data(
@@ -230,9 +350,9 @@ impl SASContentHandler for ParameterizedHandler {
         _tag_position: LineColumn,
         value: &str,
         _value_position: LineColumn,
-        _delimiter: &str,
+        _delimiter: ValueDelimiter,
         _loop_level: usize,
-    ) -> bool {
+    ) -> WalkControl {
         self.events.push(format!("data({}, {})", tag, value));
         self.increment_and_check(ElementType::Data)
     }
@@ -258,66 +378,117 @@ struct ComprehensiveTestHandler {
 }
 
 impl SASContentHandler for ComprehensiveTestHandler {
-    fn start_stream(&mut self, _name: Option<&str>) -> bool {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
         self.output.push("<start_stream>".to_string());
-        false
+        WalkControl::Continue
     }
 
-    fn end_stream(&mut self, _position: LineColumn) -> bool {
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
         self.output.push("<end_stream>".to_string());
-        false
+        WalkControl::Continue
     }
 
-    fn start_global(&mut self, position: LineColumn) -> bool {
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
         self.output
             .push(format!("<start global> [{}]", position.line));
-        false
+        WalkControl::Continue
     }
 
-    fn end_global(&mut self, position: LineColumn) -> bool {
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
         self.output
             .push(format!("<end global> [{}]", position.line));
-        false
+        WalkControl::Continue
     }
 
-    fn start_data(&mut self, position: LineColumn, name: &str) -> bool {
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
         self.output
             .push(format!("<start data> [{}] {}", position.line, name));
-        false
+        WalkControl::Continue
     }
 
-    fn end_data(&mut self, position: LineColumn, name: &str) -> bool {
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
         self.output
             .push(format!("<end data> [{}] {}", position.line, name));
-        false
+        WalkControl::Continue
     }
 
-    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> bool {
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
         self.output
             .push(format!("<start saveframe> [{}] {}", position.line, name));
-        false
+        WalkControl::Continue
     }
 
-    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> bool {
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
         self.output
             .push(format!("<end saveframe> [{}] {}", position.line, name));
-        false
+        WalkControl::Continue
     }
 
-    fn start_loop(&mut self, position: LineColumn) -> bool {
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
         self.output
             .push(format!("<start_loop> [{}]", position.line));
-        false
+        WalkControl::Continue
     }
 
-    fn end_loop(&mut self, position: LineColumn) -> bool {
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
         self.output.push(format!("<end_loop> [{}]", position.line));
-        false
+        WalkControl::Continue
+    }
+
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.output.push(format!(
+            "<loop_definition> [{}] level: {} tags: {}",
+            position.line,
+            nesting_level,
+            tags.join(", ")
+        ));
+        WalkControl::Continue
+    }
+
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.output.push(format!(
+            "<start_loop_row> [{}] {}",
+            position.line, row_index
+        ));
+        WalkControl::Continue
+    }
+
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.output
+            .push(format!("<end_loop_row> [{}] {}", position.line, row_index));
+        WalkControl::Continue
     }
 
-    fn comment(&mut self, position: LineColumn, text: &str) -> bool {
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
         self.output.push(format!("# [{}] {}", position.line, text));
-        false
+        WalkControl::Continue
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        self.output.push(format!(
+            "# [{}:{}] {:?} {:?}",
+            position.line, position.column, kind, text
+        ));
+        WalkControl::Continue
+    }
+
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        self.output.push("<element_comments>".to_string());
+        for (position, text) in comments {
+            self.output
+                .push(format!("    # [{}] {}", position.line, text));
+        }
+        WalkControl::Continue
     }
 
     fn data(
@@ -326,18 +497,18 @@ impl SASContentHandler for ComprehensiveTestHandler {
         tag_position: LineColumn,
         value: &str,
         value_position: LineColumn,
-        delimiter: &str,
+        delimiter: ValueDelimiter,
         loop_level: usize,
-    ) -> bool {
+    ) -> WalkControl {
         match delimiter {
-            EMPTY_LOOP_DELIMITER => {
+            ValueDelimiter::EmptyLoop => {
                 // Empty loop - no value position, special format
                 self.output.push(format!(
                     "<data> [t:{}:{}] {} loop_level: {} [empty-loop]",
                     tag_position.line, tag_position.column, tag, loop_level
                 ));
             }
-            ";" => {
+            ValueDelimiter::Semicolon => {
                 // Semicolon-bounded string - multi-line
                 self.output.push(format!(
                     "<data> [t:{}:{},v:{}:{}] {} delimiter: ; loop_level: {} [multi-line] value: {}",
@@ -350,7 +521,7 @@ impl SASContentHandler for ComprehensiveTestHandler {
                     value
                 ));
             }
-            "" => {
+            ValueDelimiter::None => {
                 // No delimiter (non-quoted string, frame code)
                 self.output.push(format!(
                     "<data> [t:{}:{},v:{}:{}] {} delimiter: none loop_level: {} value: {}",
@@ -364,7 +535,7 @@ impl SASContentHandler for ComprehensiveTestHandler {
                 ));
             }
             _ => {
-                // Quote delimiters: "'", "\""
+                // Quote, triple quote, list and table delimiters"
                 self.output.push(format!(
                     "<data> [t:{}:{},v:{}:{}] {} delimiter: {} loop_level: {} value: {}",
                     tag_position.line,
@@ -378,7 +549,7 @@ impl SASContentHandler for ComprehensiveTestHandler {
                 ));
             }
         }
-        false
+        WalkControl::Continue
     }
 }
 
@@ -424,6 +595,560 @@ fn test_multiline_and_frame_codes() {
     snapshot_utils::assert_snapshot_gz("sas_walker_tests__multiline_and_frame_codes", &output);
 }
 
+#[test]
+fn test_triple_quoted_strings_walker_output() {
+    let input = fs::read_to_string("tests/test_data/triple_quoted_strings.star")
+        .expect("Failed to read triple quoted strings test file");
+
+    let tree = parse_default(&input).expect("Failed to parse triple quoted strings");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, &input);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__triple_quoted_strings_walker_output",
+        &output,
+    );
+}
+
+/// CIF2 lists and tables are emitted as one data() call per value, with the
+/// text between the outer brackets as the value and "[" or "{" as the delimiter
+#[test]
+fn test_cif2_lists_tables_walker_output() {
+    let input = fs::read_to_string("tests/test_data/cif2_lists_tables.cif")
+        .expect("Failed to read CIF2 lists and tables test file");
+
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::CifVersion,
+        ConfigValue::CifVersion(CifVersion::Cif2),
+    );
+    let tree = parse(&input, &config).expect("Failed to parse CIF2 lists and tables");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, &input);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__cif2_lists_tables_walker_output",
+        &output,
+    );
+}
+
+fn assert_loop_comment_walker_output(name: &str) {
+    let input = fs::read_to_string(format!("tests/test_data/{}.star", name))
+        .expect("Failed to read loop comment test file");
+
+    let tree = parse_default(&input).expect("Failed to parse loop comment test file");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, &input);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz(
+        &format!("sas_walker_tests__{}_walker_output", name),
+        &output,
+    );
+}
+
+#[test]
+fn test_comment_between_loop_rows_walker_output() {
+    assert_loop_comment_walker_output("comment_between_loop_rows");
+}
+
+#[test]
+fn test_comment_after_loop_keyword_walker_output() {
+    assert_loop_comment_walker_output("comment_after_loop_keyword");
+}
+
+#[test]
+fn test_comment_before_stop_walker_output() {
+    assert_loop_comment_walker_output("comment_before_stop");
+}
+
+const COMMENT_KINDS_FILE: &str = "tests/test_data/comment_kinds.star";
+
+#[test]
+fn test_comment_kinds_walker_output() {
+    let input = fs::read_to_string(COMMENT_KINDS_FILE).unwrap();
+
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__comment_kinds_walker_output",
+        &attached_comments_output(&input, false).join("\n"),
+    );
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__attached_comment_kinds_walker_output",
+        &attached_comments_output(&input, true).join("\n"),
+    );
+}
+
+#[test]
+fn test_comment_kinds_and_text() {
+    let input = fs::read_to_string(COMMENT_KINDS_FILE).unwrap();
+    let comments = |input: &str| -> Vec<String> {
+        attached_comments_output(input, false)
+            .into_iter()
+            .filter(|line| line.starts_with('#'))
+            .collect()
+    };
+
+    // every comment is reported in document order, the text is everything after the # and
+    // the position is that of the #
+    assert_eq!(
+        comments(&input),
+        vec![
+            r#"# [1:1] FullLine " full line comment before the block""#,
+            r#"# [2:22] Inline " inline after the heading""#,
+            r#"# [3:14] Inline " inline after a value""#,
+            r#"# [4:5] FullLine " indented full line comment""#,
+            r#"# [5:37] Inline "inline without a space""#,
+            r#"# [6:7] Inline " inline after loop_""#,
+            r#"# [9:1] FullLine " full line between the loop header and its values""#,
+            r#"# [10:5] Inline " inline after a row""#,
+            r#"# [11:1] FullLine " full line between rows""#,
+            r#"# [13:7] Inline " inline after stop_""#,
+        ]
+    );
+    assert_eq!(
+        comments("data_test\r\nloop_ _a\r\n  #first\r\n1 #second\r\nstop_\r\n"),
+        vec![r#"# [3:3] FullLine "first""#, r#"# [4:3] Inline "second""#,]
+    );
+}
+
+/// Records events like ComprehensiveTestHandler plus checkpoints, optionally stopping at one
+struct CheckpointHandler {
+    inner: ComprehensiveTestHandler,
+    checkpoints: Vec<StreamCheckpoint>,
+    stop_at_checkpoint: Option<usize>,
+}
+
+impl CheckpointHandler {
+    fn new(stop_at_checkpoint: Option<usize>) -> Self {
+        CheckpointHandler {
+            inner: ComprehensiveTestHandler { output: Vec::new() },
+            checkpoints: Vec::new(),
+            stop_at_checkpoint,
+        }
+    }
+}
+
+impl SASContentHandler for CheckpointHandler {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        self.inner.start_stream(name)
+    }
+
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
+        self.inner.end_stream(position)
+    }
+
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
+        self.inner.start_global(position)
+    }
+
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
+        self.inner.end_global(position)
+    }
+
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.inner.start_data(position, name)
+    }
+
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.inner.end_data(position, name)
+    }
+
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.inner.start_saveframe(position, name)
+    }
+
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.inner.end_saveframe(position, name)
+    }
+
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.inner.start_loop(position)
+    }
+
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.inner.end_loop(position)
+    }
+
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.inner.loop_definition(position, tags, nesting_level)
+    }
+
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.inner.start_loop_row(position, row_index)
+    }
+
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.inner.end_loop_row(position, row_index)
+    }
+
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        self.inner.comment(position, text)
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        self.inner.comment_with_kind(position, text, kind)
+    }
+
+    fn checkpoint(&mut self, checkpoint: &StreamCheckpoint) -> WalkControl {
+        self.inner.output.push(format!(
+            "<checkpoint> [{}:{}] @{} {}",
+            checkpoint.line,
+            checkpoint.column,
+            checkpoint.byte_offset,
+            checkpoint.path.join("/")
+        ));
+        self.checkpoints.push(checkpoint.clone());
+        if self.stop_at_checkpoint == Some(self.checkpoints.len() - 1) {
+            WalkControl::Stop
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.inner.data(
+            tag,
+            tag_position,
+            value,
+            value_position,
+            delimiter,
+            loop_level,
+        )
+    }
+}
+
+const CHECKPOINT_FILE: &str = "tests/test_data/multi_frame_checkpoint.star";
+
+fn walk_with_checkpoints(input: &str, stop_at_checkpoint: Option<usize>) -> CheckpointHandler {
+    let tree = parse_default(input).expect("Failed to parse checkpoint test file");
+    let mut handler = CheckpointHandler::new(stop_at_checkpoint);
+    let mut walker = StarWalker::from_input(&mut handler, input);
+    walker.walk_star_tree_buffered(&tree);
+    handler
+}
+
+#[test]
+fn test_checkpoints_at_data_block_and_saveframe_headings() {
+    let input = fs::read_to_string(CHECKPOINT_FILE).expect("Failed to read checkpoint test file");
+    let handler = walk_with_checkpoints(&input, None);
+
+    let paths: Vec<String> = handler
+        .checkpoints
+        .iter()
+        .map(|checkpoint| checkpoint.path.join("/"))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "first_entry",
+            "first_entry/entry_information",
+            "first_entry/assembly",
+            "first_entry/entity_1",
+            "second_entry",
+            "second_entry/sample_conditions",
+        ]
+    );
+
+    for checkpoint in &handler.checkpoints {
+        let keyword = if checkpoint.path.len() == 1 {
+            "data_"
+        } else {
+            "save_"
+        };
+        assert!(input[checkpoint.byte_offset..].starts_with(keyword));
+        let line = input.lines().nth(checkpoint.line - 1).unwrap();
+        assert_eq!(line.find(keyword), Some(checkpoint.column - 1));
+    }
+}
+
+#[test]
+fn test_resume_from_each_checkpoint_matches_uninterrupted_walk() {
+    let input = fs::read_to_string(CHECKPOINT_FILE).expect("Failed to read checkpoint test file");
+    let uninterrupted = walk_with_checkpoints(&input, None);
+
+    for (index, checkpoint) in uninterrupted.checkpoints.iter().enumerate() {
+        // the interrupted walk stops as soon as the checkpoint has been delivered
+        let interrupted = walk_with_checkpoints(&input, Some(index));
+        assert_eq!(interrupted.checkpoints.last(), Some(checkpoint));
+
+        let mut resumed = CheckpointHandler::new(None);
+        let mut reader = Cursor::new(input.as_bytes());
+        let stopped = resume_from(&mut reader, checkpoint, &mut resumed, &default_config())
+            .expect("Failed to resume from checkpoint");
+        assert!(!stopped);
+
+        let mut events = interrupted.inner.output.clone();
+        events.extend(resumed.inner.output);
+        assert_eq!(
+            events, uninterrupted.inner.output,
+            "Resuming from checkpoint {} ({:?}) changed the event stream",
+            index, checkpoint.path
+        );
+    }
+}
+
+#[test]
+fn test_resume_from_changed_input_reports_heading_mismatch() {
+    let input = fs::read_to_string(CHECKPOINT_FILE).expect("Failed to read checkpoint test file");
+    let checkpoints = walk_with_checkpoints(&input, None).checkpoints;
+    let checkpoint = &checkpoints[2];
+
+    let changed = input.replace("save_assembly", "save_assembly_v2");
+    let mut handler = CheckpointHandler::new(None);
+    let result = resume_from(
+        &mut Cursor::new(changed.as_bytes()),
+        checkpoint,
+        &mut handler,
+        &default_config(),
+    );
+
+    match result {
+        Err(ResumeError::HeadingMismatch {
+            expected, found, ..
+        }) => {
+            assert_eq!(expected, "save_assembly");
+            assert_eq!(found, "save_assembly_v2");
+        }
+        other => panic!("Expected a heading mismatch, got {:?}", other.map(|_| ())),
+    }
+    assert!(handler.inner.output.is_empty());
+}
+
+#[test]
+fn test_resume_from_keeps_comments_between_blocks() {
+    let input = indoc! {"
+        data_first
+        _entry.id  1  # inline
+        # after the first block
+
+        data_second # on the heading line
+        save_frame
+        # inside the frame
+        _frame.id  2
+        save_
+        # before the last block
+        data_third
+        _entry.id  3
+        # at the end
+    "};
+    for attach in [false, true] {
+        let mut config = default_config();
+        config.insert(ConfigKey::AttachComments, ConfigValue::Bool(attach));
+        let walk = |stop_at_checkpoint| {
+            let tree = parse(input, &config).unwrap();
+            let mut handler = CheckpointHandler::new(stop_at_checkpoint);
+            StarWalker::from_input(&mut handler, input)
+                .with_config(&config)
+                .walk_star_tree_buffered(&tree);
+            handler
+        };
+        let uninterrupted = walk(None);
+
+        for (index, checkpoint) in uninterrupted.checkpoints.iter().enumerate() {
+            let mut events = walk(Some(index)).inner.output;
+            let mut resumed = CheckpointHandler::new(None);
+            resume_from(
+                &mut Cursor::new(input.as_bytes()),
+                checkpoint,
+                &mut resumed,
+                &config,
+            )
+            .expect("Failed to resume from checkpoint");
+            events.extend(resumed.inner.output);
+            assert_eq!(
+                events, uninterrupted.inner.output,
+                "attach {} checkpoint {:?}",
+                attach, checkpoint.path
+            );
+        }
+    }
+}
+
+/// A reader that fails when asked for bytes past `limit`
+struct LimitedReader {
+    inner: Cursor<Vec<u8>>,
+    limit: u64,
+}
+
+impl std::io::Read for LimitedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.limit.saturating_sub(self.inner.position());
+        if left == 0 {
+            return Err(std::io::Error::other("read past the limit"));
+        }
+        let len = buf.len().min(left as usize);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl std::io::Seek for LimitedReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_resume_from_reads_the_stream_a_block_at_a_time() {
+    let input: String = (0..50_000)
+        .map(|index| format!("data_entry_{index}\n_entry.id  {index}\n\n"))
+        .collect();
+    let checkpoint = StreamCheckpoint {
+        byte_offset: input.find("data_entry_10\n").unwrap(),
+        line: 31,
+        column: 1,
+        path: vec!["entry_10".to_string()],
+    };
+
+    // the walk stops a few blocks in, long before the bytes it was allowed to read run out
+    let mut handler = CheckpointHandler::new(Some(3));
+    let mut reader = LimitedReader {
+        inner: Cursor::new(input.clone().into_bytes()),
+        limit: checkpoint.byte_offset as u64 + 256 * 1024,
+    };
+    assert!(input.len() > 4 * 256 * 1024);
+    let stopped = resume_from(&mut reader, &checkpoint, &mut handler, &default_config())
+        .expect("Resuming reads no further than the blocks walked");
+
+    assert!(stopped);
+    let last = handler.checkpoints.last().unwrap();
+    assert_eq!(last.path, vec!["entry_14"]);
+    assert_eq!(last.line, 43);
+    assert!(input[last.byte_offset..].starts_with("data_entry_14\n"));
+}
+
+#[test]
+fn test_resume_from_rejects_bytes_that_are_not_utf8() {
+    let mut bytes = b"data_first\n_entry.id  1\n\ndata_second\n_entry.name  '".to_vec();
+    let invalid = bytes.len();
+    bytes.extend(b"caf\xe9'\n");
+    let checkpoint = StreamCheckpoint {
+        byte_offset: 0,
+        line: 1,
+        column: 1,
+        path: vec!["first".to_string()],
+    };
+
+    let mut handler = CheckpointHandler::new(None);
+    let result = resume_from(
+        &mut Cursor::new(bytes),
+        &checkpoint,
+        &mut handler,
+        &default_config(),
+    );
+
+    match result {
+        Err(ResumeError::InvalidUtf8 { byte_offset }) => assert_eq!(byte_offset, invalid + 3),
+        other => panic!("Expected invalid UTF-8, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_resume_from_reads_latin1_blocks_in_extended_ascii_mode() {
+    let mut bytes = b"data_first\n_entry.name  'caf\xe9'\n\n".to_vec();
+    let second = bytes.len();
+    bytes.extend(b"data_second\n_entry.name  'na\xefve'\n");
+    let checkpoint = StreamCheckpoint {
+        byte_offset: 0,
+        line: 1,
+        column: 1,
+        path: vec!["first".to_string()],
+    };
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::ExtendedAscii),
+    );
+
+    let mut handler = CheckpointHandler::new(None);
+    resume_from(&mut Cursor::new(bytes), &checkpoint, &mut handler, &config)
+        .expect("Latin-1 blocks are read in extended ASCII mode");
+
+    let values: Vec<&String> = handler
+        .inner
+        .output
+        .iter()
+        .filter(|event| event.contains("café") || event.contains("naïve"))
+        .collect();
+    assert_eq!(values.len(), 2, "{:#?}", handler.inner.output);
+    // checkpoints after a Latin-1 block are byte offsets in the stream
+    assert_eq!(handler.checkpoints[0].byte_offset, second);
+    assert_eq!(handler.checkpoints[0].line, 4);
+}
+
+#[test]
+fn test_quoted_heading_codes_are_cleaned_in_events_and_kept_in_checkpoints() {
+    let input = indoc! {r#"
+        data_"ABC
+        _entry.id  1
+        save_'frame'
+        _frame.category  test
+        save_
+    "#};
+    let uninterrupted = walk_with_checkpoints(input, None);
+
+    let headings: Vec<&str> = uninterrupted
+        .inner
+        .output
+        .iter()
+        .map(String::as_str)
+        .filter(|event| event.contains(" data>") || event.contains(" saveframe>"))
+        .collect();
+    assert_eq!(
+        headings,
+        vec![
+            "<start data> [1] ABC",
+            "<start saveframe> [3] frame",
+            "<end saveframe> [5] frame",
+            "<end data> [5] ABC",
+        ]
+    );
+
+    let paths: Vec<String> = uninterrupted
+        .checkpoints
+        .iter()
+        .map(|checkpoint| checkpoint.path.join("/"))
+        .collect();
+    assert_eq!(paths, vec![r#""ABC"#, r#""ABC/'frame'"#]);
+
+    // resuming finds the headings by the codes as written
+    let checkpoint = &uninterrupted.checkpoints[1];
+    let mut resumed = CheckpointHandler::new(None);
+    resume_from(
+        &mut Cursor::new(input.as_bytes()),
+        checkpoint,
+        &mut resumed,
+        &default_config(),
+    )
+    .expect("Failed to resume from checkpoint");
+    assert_eq!(resumed.inner.output[0], "<start saveframe> [3] frame");
+}
+
 #[test]
 fn test_saveframe_walker_output() {
     let input = "data_test\nsave_frame1\n_tag value\nsave_";
@@ -684,42 +1409,341 @@ fn test_early_termination_all_methods() {
             "start_stream",
             "start_data(test)",
             "start_loop",
+            "loop_definition(_tag1, _tag2)",
+            "start_loop_row(0)",
             "data(_tag1, value1)",
             "data(_tag2, value2)",
+            "end_loop_row(0)",
             "end_loop",
         ],
     );
 
-    // 7. data after N - should stop after N data items
+    // 7. loop_definition - should stop after the loop's tags, before any of its values
     test_early_termination(
-        ElementToStopOn::Data(2),
-        BASIC_INPUT,
+        ElementToStopOn::LoopDefinition(1),
+        LOOP_INPUT,
         &[
             "start_stream",
             "start_data(test)",
-            "data(_item1, value1)",
-            "data(_item2, value2)",
+            "start_loop",
+            "loop_definition(_tag1, _tag2)",
         ],
     );
 
-    // 8. Test stopping after 1st data item (demonstrating default of 1)
+    // 8. start_loop_row after N - should stop at the start of the Nth row, before its values
     test_early_termination(
-        ElementToStopOn::Data(1),
-        BASIC_INPUT,
-        &["start_stream", "start_data(test)", "data(_item1, value1)"],
+        ElementToStopOn::StartLoopRow(2),
+        GLOBAL_WITH_LOOP_INPUT,
+        &[
+            "start_stream",
+            "start_global",
+            "data(_global_setting, test_value)",
+            "start_loop",
+            "loop_definition(_config_key, _config_value)",
+            "start_loop_row(0)",
+            "data(_config_key, database_host)",
+            "data(_config_value, localhost)",
+            "end_loop_row(0)",
+            "start_loop_row(1)",
+        ],
     );
-}
 
-// ============================================================================
-// Snapshot tests for SAS test files
-// ============================================================================
+    // 9. end_loop_row after N - nested rows end before the outer row that holds them
+    test_early_termination(
+        ElementToStopOn::EndLoopRow(3),
+        NESTED_LOOP_INPUT,
+        &[
+            "start_stream",
+            "start_data(test)",
+            "start_loop",
+            "loop_definition(_outer)",
+            "loop_definition(_inner, _label)",
+            "start_loop_row(0)",
+            "data(_outer, a)",
+            "start_loop_row(0)",
+            "data(_inner, 1)",
+            "data(_label, x)",
+            "end_loop_row(0)",
+            "start_loop_row(1)",
+            "data(_inner, 2)",
+            "data(_label, y)",
+            "end_loop_row(1)",
+            "end_loop_row(0)",
+        ],
+    );
 
-/// Generate a snapshot test for each file in the sas_test_files directory
-/// that can be successfully parsed.
-/// Uses check_snapshot_gz to collect all failures before panicking,
-/// so all .snap.new files are generated in a single test run.
-#[test]
-fn test_sas_test_files_walker_output() {
+    // 10. end_loop_row for every row - outer row indices continue after nested rows
+    test_early_termination(
+        ElementToStopOn::EndLoopRow(5),
+        NESTED_LOOP_INPUT,
+        &[
+            "start_stream",
+            "start_data(test)",
+            "start_loop",
+            "loop_definition(_outer)",
+            "loop_definition(_inner, _label)",
+            "start_loop_row(0)",
+            "data(_outer, a)",
+            "start_loop_row(0)",
+            "data(_inner, 1)",
+            "data(_label, x)",
+            "end_loop_row(0)",
+            "start_loop_row(1)",
+            "data(_inner, 2)",
+            "data(_label, y)",
+            "end_loop_row(1)",
+            "end_loop_row(0)",
+            "start_loop_row(1)",
+            "data(_outer, b)",
+            "start_loop_row(0)",
+            "data(_inner, 3)",
+            "data(_label, z)",
+            "end_loop_row(0)",
+            "end_loop_row(1)",
+        ],
+    );
+
+    // 11. data after N - should stop after N data items
+    test_early_termination(
+        ElementToStopOn::Data(2),
+        BASIC_INPUT,
+        &[
+            "start_stream",
+            "start_data(test)",
+            "data(_item1, value1)",
+            "data(_item2, value2)",
+        ],
+    );
+
+    // 12. Test stopping after 1st data item (demonstrating default of 1)
+    test_early_termination(
+        ElementToStopOn::Data(1),
+        BASIC_INPUT,
+        &["start_stream", "start_data(test)", "data(_item1, value1)"],
+    );
+}
+
+const THREE_SAVEFRAMES_INPUT: &str = "
+data_test
+    save_first
+        _item.id 1
+    save_
+    save_second
+        _item.id 2
+        loop_
+            _row.a
+            _row.b
+            x y
+        stop_
+    save_
+    save_third
+        _item.id 3
+        loop_
+            _row.a
+            _row.b
+            z w
+        stop_
+    save_
+    _after value
+";
+
+/// Records events like ParameterizedHandler, skipping the data blocks and save frames named
+/// `skip` and the loops with a tag `skip`
+struct SkippingHandler {
+    skip: &'static str,
+    events: Vec<String>,
+}
+
+impl SkippingHandler {
+    fn walk(input: &str, skip: &'static str) -> Vec<String> {
+        let tree = parse_default(input).expect("Failed to parse");
+        let mut handler = SkippingHandler {
+            skip,
+            events: Vec::new(),
+        };
+        StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+        handler.events
+    }
+
+    fn push(&mut self, event: String) -> WalkControl {
+        self.events.push(event);
+        WalkControl::Continue
+    }
+
+    fn skip_if(&self, skip: bool) -> WalkControl {
+        if skip {
+            WalkControl::SkipSubtree
+        } else {
+            WalkControl::Continue
+        }
+    }
+}
+
+impl SASContentHandler for SkippingHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.push("start_stream".to_string())
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.push("end_stream".to_string())
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.push("start_global".to_string())
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.push("end_global".to_string())
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.push(format!("start_data({})", name));
+        self.skip_if(name == self.skip)
+    }
+
+    fn end_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.push(format!("end_data({})", name))
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.push(format!("start_saveframe({})", name));
+        self.skip_if(name == self.skip)
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.push(format!("end_saveframe({})", name))
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.push("start_loop".to_string())
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.push("end_loop".to_string())
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        _level: usize,
+    ) -> WalkControl {
+        self.push(format!("loop_definition({})", tags.join(", ")));
+        self.skip_if(tags.contains(&self.skip))
+    }
+
+    fn comment(&mut self, _position: LineColumn, text: &str) -> WalkControl {
+        self.push(format!("comment({})", text))
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.push(format!("data({}, {})", tag, value))
+    }
+}
+
+#[test]
+fn test_skip_subtree_skips_one_saveframe() {
+    let events = SkippingHandler::walk(THREE_SAVEFRAMES_INPUT, "second");
+
+    assert_eq!(
+        events,
+        [
+            "start_stream",
+            "start_data(test)",
+            "start_saveframe(first)",
+            "data(_item.id, 1)",
+            "end_saveframe(first)",
+            "start_saveframe(second)",
+            "end_saveframe(second)",
+            "start_saveframe(third)",
+            "data(_item.id, 3)",
+            "start_loop",
+            "loop_definition(_row.a, _row.b)",
+            "data(_row.a, z)",
+            "data(_row.b, w)",
+            "end_loop",
+            "end_saveframe(third)",
+            "data(_after, value)",
+            "end_data(test)",
+            "end_stream",
+        ]
+    );
+}
+
+#[test]
+fn test_skip_subtree_from_loop_definition_skips_loop_values() {
+    let events = SkippingHandler::walk(THREE_SAVEFRAMES_INPUT, "_row.a");
+
+    let loops: Vec<&str> = events
+        .iter()
+        .map(String::as_str)
+        .skip_while(|event| *event != "start_saveframe(second)")
+        .collect();
+    assert_eq!(
+        loops,
+        [
+            "start_saveframe(second)",
+            "data(_item.id, 2)",
+            "start_loop",
+            "loop_definition(_row.a, _row.b)",
+            "end_loop",
+            "end_saveframe(second)",
+            "start_saveframe(third)",
+            "data(_item.id, 3)",
+            "start_loop",
+            "loop_definition(_row.a, _row.b)",
+            "end_loop",
+            "end_saveframe(third)",
+            "data(_after, value)",
+            "end_data(test)",
+            "end_stream",
+        ]
+    );
+}
+
+#[test]
+fn test_skip_subtree_from_start_data_skips_block() {
+    let input = "
+data_first
+    _item.id 1
+
+data_second
+    _item.id 2
+";
+    let events = SkippingHandler::walk(input, "first");
+
+    assert_eq!(
+        events,
+        [
+            "start_stream",
+            "start_data(first)",
+            "end_data(first)",
+            "start_data(second)",
+            "data(_item.id, 2)",
+            "end_data(second)",
+            "end_stream",
+        ]
+    );
+}
+
+// ============================================================================
+// Snapshot tests for SAS test files
+// ============================================================================
+
+/// Generate a snapshot test for each file in the sas_test_files directory
+/// that can be successfully parsed.
+/// Uses check_snapshot_gz to collect all failures before panicking,
+/// so all .snap.new files are generated in a single test run.
+#[test]
+fn test_sas_test_files_walker_output() {
     let dir = Path::new("tests/test_data/sas_test_files");
     assert!(
         dir.exists() && dir.is_dir(),
@@ -778,43 +1802,1807 @@ fn test_sas_test_files_walker_output() {
     }
 }
 
+/// Walk every parseable file in sas_test_files with both the MutablePair and
+/// TreeArena backends and check that they produce identical SAS event streams.
 #[test]
-fn test_global_block_walker_output() {
-    let tree = parse_default(GLOBAL_INPUT).expect("Failed to parse global input");
-    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
-    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_INPUT);
+fn test_sas_test_files_arena_equivalence() {
+    let dir = Path::new("tests/test_data/sas_test_files");
+    let mut files_checked = 0;
 
-    walker.walk_star_tree_buffered(&tree);
+    for entry in fs::read_dir(dir).expect("read_dir failed") {
+        let path = entry.expect("entry failed").path();
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let is_star = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("str" | "cif" | "dic")
+        );
+        if !is_star || KNOWN_PARSE_FAILURES.contains(&filename.as_str()) {
+            continue;
+        }
 
-    let output = handler.output.join("\n");
-    snapshot_utils::assert_snapshot_gz("sas_walker_tests__global_block_walker_output", &output);
+        let data = fs::read(&path).unwrap_or_else(|_| panic!("Failed to read file {:?}", path));
+        let content = String::from_utf8_lossy(&data).to_string();
+
+        let tree =
+            parse_default(&content).unwrap_or_else(|e| panic!("Failed to parse {:?}: {}", path, e));
+        let mut pair_handler = ComprehensiveTestHandler { output: Vec::new() };
+        StarWalker::from_input(&mut pair_handler, &content).walk_star_tree_buffered(&tree);
+
+        let arena = parse_arena(&content, &default_config())
+            .unwrap_or_else(|e| panic!("Failed to parse {:?}: {}", path, e));
+        let mut arena_handler = ComprehensiveTestHandler { output: Vec::new() };
+        StarWalker::from_input(&mut arena_handler, &content).walk_node(arena.root().unwrap());
+
+        assert_eq!(
+            pair_handler.output, arena_handler.output,
+            "SAS event streams differ between backends for {}",
+            filename
+        );
+        files_checked += 1;
+    }
+
+    assert!(files_checked > 0, "No files found in {:?}", dir);
 }
 
 #[test]
-fn test_global_with_loop_walker_output() {
-    let tree =
-        parse_default(GLOBAL_WITH_LOOP_INPUT).expect("Failed to parse global with loop input");
-    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
-    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_WITH_LOOP_INPUT);
+fn test_parse_arena_matches_parse() {
+    let input = indoc! {r#"
+        data_test
+        _plain   value
+        _quoted  'it''s'
+        _double  "x"
+        _text
+        ;
+        a text field
+        ;
+        loop_ _a _b
+        1 'é' 2 """three"""
+        stop_
+    "#};
+    let mut unicode = default_config();
+    unicode.insert(
+        ConfigKey::Encoding,
+        ConfigValue::Encoding(EncodingMode::Unicode),
+    );
+    let mut configs = vec![unicode.clone()];
+    for (key, value) in [
+        (ConfigKey::DecomposedStrings, ConfigValue::Bool(false)),
+        (ConfigKey::UnescapeQuotes, ConfigValue::Bool(true)),
+        (
+            ConfigKey::ColumnUnit,
+            ConfigValue::ColumnUnit(ColumnUnit::Chars),
+        ),
+        (
+            ConfigKey::CifVersion,
+            ConfigValue::CifVersion(CifVersion::Cif2),
+        ),
+        (ConfigKey::AllowEmptyLoops, ConfigValue::Bool(false)),
+    ] {
+        let mut config = unicode.clone();
+        config.insert(key, value);
+        configs.push(config);
+    }
 
-    walker.walk_star_tree_buffered(&tree);
+    for config in &configs {
+        let tree = parse(input, config).unwrap();
+        let arena = parse_arena(input, config).unwrap();
+        assert_eq!(arena.roots().len(), 1);
+        assert_eq!(
+            arena.root().unwrap().to_mutable_pair(),
+            tree,
+            "{:?}",
+            config
+        );
+    }
+    // errors are those of parse, in the default ASCII mode é is one
+    let error = parse_arena(input, &default_config()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        parse(input, &default_config()).unwrap_err().to_string()
+    );
+}
 
-    let output = handler.output.join("\n");
-    snapshot_utils::assert_snapshot_gz("sas_walker_tests__global_with_loop_walker_output", &output);
+/// Walk `content` parsed with `config` both as a tree and straight from the pest pairs, with
+/// and without decomposed strings, and check all the walks give identical SAS event streams
+fn assert_pairs_walk_matches_tree(content: &str, config: &ustar::ParserConfig, name: &str) {
+    let tree = parse(content, config).unwrap_or_else(|e| panic!("Failed to parse {}: {}", name, e));
+    let mut tree_handler = ComprehensiveTestHandler { output: Vec::new() };
+    StarWalker::from_input(&mut tree_handler, content).walk_star_tree_buffered(&tree);
+
+    for decomposed in [true, false] {
+        let mut config = config.clone();
+        config.insert(ConfigKey::DecomposedStrings, ConfigValue::Bool(decomposed));
+
+        let mut pairs_handler = ComprehensiveTestHandler { output: Vec::new() };
+        let stopped = ustar::walk(content, &config, &mut pairs_handler)
+            .unwrap_or_else(|e| panic!("Failed to walk {}: {}", name, e));
+
+        assert!(!stopped);
+        assert_eq!(
+            tree_handler.output, pairs_handler.output,
+            "SAS event streams differ between the tree and the pairs of {} (decomposed: {})",
+            name, decomposed
+        );
+    }
 }
 
+/// Walk every parseable file in sas_test_files from its pest pairs and check the SAS event
+/// streams are identical to walking its MutablePair tree
 #[test]
-fn test_global_with_nested_walker_output() {
-    let tree =
-        parse_default(GLOBAL_WITH_NESTED_INPUT).expect("Failed to parse global with nested input");
+fn test_sas_test_files_pairs_equivalence() {
+    let dir = Path::new("tests/test_data/sas_test_files");
+    let mut files_checked = 0;
+
+    for entry in fs::read_dir(dir).expect("read_dir failed") {
+        let path = entry.expect("entry failed").path();
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let is_star = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("str" | "cif" | "dic")
+        );
+        if !is_star || KNOWN_PARSE_FAILURES.contains(&filename.as_str()) {
+            continue;
+        }
+
+        let data = fs::read(&path).unwrap_or_else(|_| panic!("Failed to read file {:?}", path));
+        let content = String::from_utf8_lossy(&data).to_string();
+        assert_pairs_walk_matches_tree(&content, &default_config(), &filename);
+        files_checked += 1;
+    }
+
+    assert!(files_checked > 0, "No files found in {:?}", dir);
+}
+
+#[test]
+fn test_pairs_walk_matches_tree_for_crlf_cif2_and_inline_inputs() {
+    for filename in [
+        "comprehensive_example.star",
+        "comprehensive_example_crlf.star",
+        "semicolon_test_crlf.star",
+        "multi_frame_checkpoint.star",
+    ] {
+        let content = fs::read_to_string(Path::new("tests/test_data").join(filename)).unwrap();
+        assert_pairs_walk_matches_tree(&content, &default_config(), filename);
+    }
+
+    let mut cif2_config = default_config();
+    cif2_config.insert(
+        ConfigKey::CifVersion,
+        ConfigValue::CifVersion(CifVersion::Cif2),
+    );
+    let content = fs::read_to_string("tests/test_data/cif2_lists_tables.cif").unwrap();
+    assert_pairs_walk_matches_tree(&content, &cif2_config, "cif2_lists_tables.cif");
+
+    for (name, input) in [
+        ("NESTED_LOOP_INPUT", NESTED_LOOP_INPUT),
+        ("GLOBAL_WITH_NESTED_INPUT", GLOBAL_WITH_NESTED_INPUT),
+        ("THREE_SAVEFRAMES_INPUT", THREE_SAVEFRAMES_INPUT),
+    ] {
+        assert_pairs_walk_matches_tree(input, &default_config(), name);
+    }
+}
+
+#[test]
+fn test_walk_reports_parse_errors_and_dialect_violations() {
     let mut handler = ComprehensiveTestHandler { output: Vec::new() };
-    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_WITH_NESTED_INPUT);
+    assert!(ustar::walk("data_test _item", &default_config(), &mut handler).is_err());
 
-    walker.walk_star_tree_buffered(&tree);
+    // checking a dialect restriction needs the tree, the walk is still the same
+    let mut config = default_config();
+    config.insert(
+        ConfigKey::AllowDataOutsideSaveframes,
+        ConfigValue::Bool(false),
+    );
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    assert!(ustar::walk(BASIC_INPUT, &config, &mut handler).is_err());
+    assert_pairs_walk_matches_tree(
+        SAVEFRAME_INPUT.replace("_after value", "").as_str(),
+        &config,
+        "SAVEFRAME_INPUT",
+    );
+}
 
-    let output = handler.output.join("\n");
-    snapshot_utils::assert_snapshot_gz(
-        "sas_walker_tests__global_with_nested_walker_output",
-        &output,
+/// Records events without positions or comments, the parts a StarWriterHandler preserves
+struct EventRecorder(Vec<String>);
+
+impl EventRecorder {
+    fn record(&mut self, event: String) -> WalkControl {
+        self.0.push(event);
+        WalkControl::Continue
+    }
+}
+
+impl SASContentHandler for EventRecorder {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.record("start_stream".to_string())
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_stream".to_string())
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_global".to_string())
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_global".to_string())
+    }
+
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_data({})", name))
+    }
+
+    fn end_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_data({})", name))
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_saveframe({})", name))
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_saveframe({})", name))
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_loop".to_string())
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_loop".to_string())
+    }
+
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        level: usize,
+    ) -> WalkControl {
+        self.record(format!("loop_definition({}: {})", level, tags.join(", ")))
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("start_loop_row({})", row_index))
+    }
+
+    fn end_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("end_loop_row({})", row_index))
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.record(format!(
+            "data({}, {:?}, {:?}, {})",
+            tag, value, delimiter, loop_level
+        ))
+    }
+}
+
+fn recorded_events(input: &str) -> Vec<String> {
+    let tree = parse_default(input).unwrap_or_else(|e| panic!("Failed to parse: {}\n{}", e, input));
+    let mut recorder = EventRecorder(Vec::new());
+    StarWalker::from_input(&mut recorder, input).walk_star_tree_buffered(&tree);
+    recorder.0
+}
+
+#[test]
+fn test_value_delimiters() {
+    let input = indoc! {r#"
+        data_test
+        _none  value
+        _single  'a b'
+        _double  "a b"
+        _triple_single  '''a b'''
+        _triple_double  """a b"""
+        _semicolon
+        ;a b
+        ;
+        loop_
+            _empty
+        stop_
+    "#};
+
+    let delimiters: Vec<String> = recorded_events(input)
+        .into_iter()
+        .filter(|event| event.starts_with("data("))
+        .collect();
+    assert_eq!(
+        delimiters,
+        vec![
+            r#"data(_none, "value", None, 0)"#,
+            r#"data(_single, "a b", Single, 0)"#,
+            r#"data(_double, "a b", Double, 0)"#,
+            r#"data(_triple_single, "a b", TripleSingle, 0)"#,
+            r#"data(_triple_double, "a b", TripleDouble, 0)"#,
+            r#"data(_semicolon, "a b", Semicolon, 0)"#,
+            r#"data(_empty, "", EmptyLoop, 1)"#,
+        ]
+    );
+    assert_eq!(ValueDelimiter::TripleDouble.as_str(), r#"""""#);
+    assert_eq!(ValueDelimiter::EmptyLoop.to_string(), "EMPTY_LOOP");
+    assert!(ValueDelimiter::Semicolon.is_quoted() && !ValueDelimiter::None.is_quoted());
+}
+
+/// With `ConfigKey::UnescapeQuotes` set the walker reports quoted values with their doubled
+/// quotes collapsed, with strings decomposed or not
+#[test]
+fn test_unescape_quotes_reports_logical_values() {
+    let input = indoc! {r#"
+        data_test
+        _dense  'He said''Hello''to''me'
+        _double  "text""more""data"""
+        _lone  'x'y'
+        loop_
+            _row.text
+            'test'''''
+            ''''Hello world'
+        stop_
+    "#};
+    let values = |unescape: bool, decomposed: bool| {
+        let mut config = default_config();
+        config.insert(ConfigKey::UnescapeQuotes, ConfigValue::Bool(unescape));
+        config.insert(ConfigKey::DecomposedStrings, ConfigValue::Bool(decomposed));
+        let mut recorder = EventRecorder(Vec::new());
+        ustar::walk(input, &config, &mut recorder).expect("Failed to walk");
+        recorder
+            .0
+            .into_iter()
+            .filter(|event| event.starts_with("data("))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        values(false, true),
+        [
+            r#"data(_dense, "He said''Hello''to''me", Single, 0)"#,
+            r#"data(_double, "text\"\"more\"\"data\"\"", Double, 0)"#,
+            r#"data(_lone, "x'y", Single, 0)"#,
+            r#"data(_row.text, "test''''", Single, 1)"#,
+            r#"data(_row.text, "'''Hello world", Single, 1)"#,
+        ]
+    );
+    let unescaped = [
+        r#"data(_dense, "He said'Hello'to'me", Single, 0)"#,
+        r#"data(_double, "text\"more\"data\"", Double, 0)"#,
+        r#"data(_lone, "x'y", Single, 0)"#,
+        r#"data(_row.text, "test''", Single, 1)"#,
+        r#"data(_row.text, "''Hello world", Single, 1)"#,
+    ];
+    assert_eq!(values(true, true), unescaped);
+    assert_eq!(values(true, false), unescaped);
+}
+
+/// Write every parseable file in sas_test_files with a StarWriterHandler and check that walking
+/// the written text gives the same events as walking the original
+#[test]
+fn test_sas_test_files_star_writer_round_trip() {
+    let dir = Path::new("tests/test_data/sas_test_files");
+    let mut files_checked = 0;
+
+    for entry in fs::read_dir(dir).expect("read_dir failed") {
+        let path = entry.expect("entry failed").path();
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let is_star = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("str" | "cif" | "dic")
+        );
+        if !is_star || KNOWN_PARSE_FAILURES.contains(&filename.as_str()) {
+            continue;
+        }
+
+        let data = fs::read(&path).unwrap_or_else(|_| panic!("Failed to read file {:?}", path));
+        let content = String::from_utf8_lossy(&data).to_string();
+
+        let tree =
+            parse_default(&content).unwrap_or_else(|e| panic!("Failed to parse {:?}: {}", path, e));
+        let mut writer = StarWriterHandler::new(Vec::new());
+        StarWalker::from_input(&mut writer, &content).walk_star_tree_buffered(&tree);
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            recorded_events(&content),
+            recorded_events(&written),
+            "SAS event streams differ after writing {}",
+            filename
+        );
+        files_checked += 1;
+    }
+
+    assert!(files_checked > 0, "No files found in {:?}", dir);
+}
+
+#[test]
+fn test_star_writer_output() {
+    let mut writer = StarWriterHandler::new(Vec::new()).with_indent("  ");
+    let tree = parse_default(GLOBAL_WITH_NESTED_INPUT).unwrap();
+    StarWalker::from_input(&mut writer, GLOBAL_WITH_NESTED_INPUT).walk_star_tree_buffered(&tree);
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        written,
+        indoc! {"
+            global_
+            _global_setting  'production'
+            loop_
+              _server_name
+              _server_type
+              loop_
+                _port
+                _protocol
+              stop_
+              'web-server' 'nginx'
+                '80' 'http'
+                '443' 'https'
+                stop_
+              'db-server' 'postgresql'
+                '5432' 'tcp'
+                stop_
+            stop_
+
+            data_application
+            _app_name  'web_app'
+        "}
+    );
+    assert_eq!(
+        recorded_events(&written),
+        recorded_events(GLOBAL_WITH_NESTED_INPUT)
+    );
+}
+
+#[test]
+fn test_star_writer_keeps_short_rows_ended_by_stop() {
+    let input = "data_test\nloop_ _a _b _c\n1 2 3 4 5\nstop_\n6 7 8 9\nstop_\n";
+    for aligned in [false, true] {
+        let mut writer = StarWriterHandler::new(Vec::new());
+        if aligned {
+            writer = writer.with_aligned_loops();
+        }
+        ustar::walk(input, &default_config(), &mut writer).expect("Failed to walk");
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            written.matches("stop_").count(),
+            2,
+            "written as\n{}",
+            written
+        );
+        assert_eq!(recorded_events(&written), recorded_events(input));
+    }
+}
+
+#[test]
+fn test_star_writer_quotes_values_that_read_as_keywords() {
+    let mut writer = StarWriterHandler::new(Vec::new());
+    writer.start_stream(None);
+    writer.start_data(LineColumn::new(1, 1), "test");
+    writer.data(
+        "_entry.state",
+        LineColumn::new(2, 1),
+        "stop_",
+        LineColumn::new(2, 15),
+        ValueDelimiter::None,
+        0,
+    );
+    writer.start_loop(LineColumn::new(3, 1));
+    writer.loop_definition(LineColumn::new(3, 7), &["_row.id", "_row.word"], 0);
+    for (row, values) in [
+        ["1", "global_"],
+        ["2", "_x"],
+        ["3", "Data_x"],
+        ["4", "save"],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        writer.start_loop_row(LineColumn::new(4, 1), row);
+        for value in values {
+            let position = LineColumn::new(4, 1);
+            writer.data("", position, value, position, ValueDelimiter::None, 1);
+        }
+        writer.end_loop_row(LineColumn::new(4, 1), row);
+    }
+    writer.end_loop(LineColumn::new(5, 1));
+    writer.end_data(LineColumn::new(5, 1), "test");
+    writer.end_stream(LineColumn::new(5, 1));
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        written,
+        indoc! {"
+            data_test
+            _entry.state  'stop_'
+            loop_
+            _row.id
+            _row.word
+                1 'global_'
+                2 '_x'
+                3 'Data_x'
+                4 save
+            stop_
+        "}
+    );
+    let values: Vec<String> = recorded_events(&written)
+        .into_iter()
+        .filter(|event| event.starts_with("data("))
+        .collect();
+    assert_eq!(
+        values,
+        vec![
+            r#"data(_entry.state, "stop_", Single, 0)"#,
+            r#"data(_row.id, "1", None, 1)"#,
+            r#"data(_row.word, "global_", Single, 1)"#,
+            r#"data(_row.id, "2", None, 1)"#,
+            r#"data(_row.word, "_x", Single, 1)"#,
+            r#"data(_row.id, "3", None, 1)"#,
+            r#"data(_row.word, "Data_x", Single, 1)"#,
+            r#"data(_row.id, "4", None, 1)"#,
+            r#"data(_row.word, "save", None, 1)"#,
+        ]
+    );
+}
+
+#[test]
+fn test_star_writer_nests_loop_definitions() {
+    // a nested level of one tag is only a definition with the level inside it as a field
+    let input = "data_test\nloop_ _a loop_ _b loop_ _c _d\n1 2 3 4 stop_ stop_\n";
+    let mut writer = StarWriterHandler::new(Vec::new()).with_indent("  ");
+    ustar::walk(input, &default_config(), &mut writer).expect("Failed to walk");
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        written,
+        indoc! {"
+            data_test
+            loop_
+              _a
+              loop_
+                _b
+                loop_
+                  _c
+                  _d
+                stop_
+              stop_
+              1
+                2
+                  3 4
+                  stop_
+                stop_
+            stop_
+        "}
+    );
+    assert_eq!(recorded_events(&written), recorded_events(input));
+}
+
+#[test]
+fn test_global_block_walker_output() {
+    let tree = parse_default(GLOBAL_INPUT).expect("Failed to parse global input");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_INPUT);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz("sas_walker_tests__global_block_walker_output", &output);
+}
+
+#[test]
+fn test_global_with_loop_walker_output() {
+    let tree =
+        parse_default(GLOBAL_WITH_LOOP_INPUT).expect("Failed to parse global with loop input");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_WITH_LOOP_INPUT);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz("sas_walker_tests__global_with_loop_walker_output", &output);
+}
+
+#[test]
+fn test_global_with_nested_walker_output() {
+    let tree =
+        parse_default(GLOBAL_WITH_NESTED_INPUT).expect("Failed to parse global with nested input");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    let mut walker = StarWalker::from_input(&mut handler, GLOBAL_WITH_NESTED_INPUT);
+
+    walker.walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__global_with_nested_walker_output",
+        &output,
+    );
+}
+
+#[test]
+fn test_implicit_stop_walker_output() {
+    let tree = parse_default(IMPLICIT_STOP_INPUT).expect("Failed to parse implicit stop input");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    StarWalker::from_input(&mut handler, IMPLICIT_STOP_INPUT).walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz("sas_walker_tests__implicit_stop_walker_output", &output);
+}
+
+#[test]
+fn test_nested_implicit_stop_walker_output() {
+    let tree = parse_default(NESTED_IMPLICIT_STOP_INPUT)
+        .expect("Failed to parse nested implicit stop input");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    StarWalker::from_input(&mut handler, NESTED_IMPLICIT_STOP_INPUT).walk_star_tree_buffered(&tree);
+
+    let output = handler.output.join("\n");
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__nested_implicit_stop_walker_output",
+        &output,
+    );
+}
+
+#[test]
+fn test_implicit_stop_walks_match_pairs_and_are_rejected_when_stop_is_required() {
+    for (name, input) in [
+        ("IMPLICIT_STOP_INPUT", IMPLICIT_STOP_INPUT),
+        ("NESTED_IMPLICIT_STOP_INPUT", NESTED_IMPLICIT_STOP_INPUT),
+    ] {
+        assert_pairs_walk_matches_tree(input, &default_config(), name);
+
+        let mut config = default_config();
+        config.insert(ConfigKey::RequireStopKeyword, ConfigValue::Bool(true));
+        let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+        let error = ustar::walk(input, &config, &mut handler).unwrap_err();
+        assert_eq!(error.code(), ustar::ErrorCode::E0013MissingStopKeyword);
+    }
+}
+
+/// Records the position of each end_loop
+struct EndLoopPositions(Vec<LineColumn>);
+
+impl SASContentHandler for EndLoopPositions {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.0.push(position);
+        WalkControl::Continue
+    }
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        WalkControl::Continue
+    }
+}
+
+#[test]
+fn test_implicit_stop_end_loop_is_at_the_terminating_token() {
+    let input = "data_a\nloop_ _x 1 2\n  _next 3\nloop_ _y 4 stop_\nloop_ _z 5 # end\n";
+    let mut handler = EndLoopPositions(Vec::new());
+    ustar::walk(input, &default_config(), &mut handler).expect("Failed to walk");
+    assert_eq!(
+        handler.0,
+        vec![
+            LineColumn::new(3, 3),  // _next
+            LineColumn::new(4, 17), // the end of stop_
+            LineColumn::new(6, 1),  // the end of the input
+        ]
+    );
+}
+
+/// The start_loop and end_loop events of `events`, with the deepest level of a loop definition
+/// and of a looped value
+fn loop_accounting(events: &[String]) -> (usize, usize, usize, usize) {
+    let mut accounting = (0, 0, 0, 0);
+    for event in events {
+        if event == "start_loop" {
+            accounting.0 += 1;
+        } else if event == "end_loop" {
+            accounting.1 += 1;
+        } else if let Some(definition) = event.strip_prefix("loop_definition(") {
+            let level = definition.split(':').next().unwrap().parse().unwrap();
+            accounting.2 = accounting.2.max(level);
+        } else if event.starts_with("data(") {
+            let level = event.trim_end_matches(')').rsplit(", ").next().unwrap();
+            accounting.3 = accounting.3.max(level.parse().unwrap());
+        }
+    }
+    accounting
+}
+
+#[test]
+fn test_global_block_loop_levels_match_data_block() {
+    let three_levels = indoc! {"
+        global_
+        loop_
+            _a
+            loop_
+                _b
+                loop_
+                    _c _d
+                stop_
+            stop_
+            a1
+                b1
+                    c1 d1
+                    c2 d2
+                stop_
+                b2
+                    c3 d3
+                stop_
+            stop_
+            a2
+                b3
+                    c4 d4
+                stop_
+            stop_
+        stop_
+        _after 1
+
+        data_next
+        _item 2
+    "};
+
+    for (input, depth) in [(GLOBAL_WITH_NESTED_INPUT, 2), (three_levels, 3)] {
+        let events = recorded_events(input);
+        let global_end = events.iter().position(|e| e == "end_global").unwrap();
+        let (start_loops, end_loops, definition_depth, value_depth) =
+            loop_accounting(&events[..global_end]);
+        assert_eq!(start_loops, 1, "{:#?}", events);
+        assert_eq!(end_loops, start_loops, "{:#?}", events);
+        assert_eq!(definition_depth, depth, "{:#?}", events);
+        assert_eq!(value_depth, depth, "{:#?}", events);
+
+        // a global block brackets the same events as a data block with the same contents
+        let as_data = input.replacen("global_", "data_global", 1);
+        let expected: Vec<String> = recorded_events(&as_data)
+            .into_iter()
+            .map(|event| match event.as_str() {
+                "start_data(global)" => "start_global".to_string(),
+                "end_data(global)" => "end_global".to_string(),
+                _ => event,
+            })
+            .collect();
+        assert_eq!(events, expected);
+
+        let mut recorder = EventRecorder(Vec::new());
+        ustar::walk(input, &default_config(), &mut recorder).expect("Failed to walk");
+        assert_eq!(recorder.0, events);
+    }
+}
+
+#[cfg(feature = "serde")]
+fn json_output(input: &str) -> String {
+    use ustar::sas_handlers::JsonHandler;
+
+    let tree = parse_default(input).expect("Failed to parse");
+    let mut handler = JsonHandler::buffered();
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    serde_json::to_string_pretty(&handler.into_value().unwrap()).unwrap()
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_saveframe_json_output() {
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__saveframe_json_output",
+        &json_output(SAVEFRAME_INPUT),
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_loop_json_output() {
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__loop_json_output",
+        &json_output(LOOP_INPUT),
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_nested_loop_json_output() {
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__nested_loop_json_output",
+        &json_output(NESTED_LOOP_INPUT),
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_global_with_loop_json_output() {
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__global_with_loop_json_output",
+        &json_output(GLOBAL_WITH_LOOP_INPUT),
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_streaming_nulls_and_empty_loops() {
+    use ustar::sas_handlers::JsonHandler;
+
+    let input = indoc! {"
+        data_test
+        _entry.id  .
+        _entry.date  ?
+        _entry.quoted  '?'
+        loop_
+            _row.id
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+    let mut handler = JsonHandler::new(Cursor::new(Vec::new())).with_unknown(serde_json::json!(-1));
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    let written = handler.into_inner().unwrap().into_inner();
+
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        r#"{"data_test":{"_entry.id":null,"_entry.date":-1,"_entry.quoted":"?","loop_1":[]}}"#
+            .to_string()
+            + "\n"
+    );
+}
+
+/// Counts callbacks by name, stopping the walk at the callback named `stop_at`
+#[derive(Default)]
+struct CountingHandler {
+    counts: HashMap<&'static str, usize>,
+    stop_at: Option<&'static str>,
+}
+
+impl CountingHandler {
+    fn count(&mut self, event: &'static str) -> WalkControl {
+        *self.counts.entry(event).or_insert(0) += 1;
+        if self.stop_at == Some(event) {
+            WalkControl::Stop
+        } else {
+            WalkControl::Continue
+        }
+    }
+
+    fn get(&self, event: &str) -> usize {
+        self.counts.get(event).copied().unwrap_or(0)
+    }
+}
+
+impl SASContentHandler for CountingHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.count("start_stream")
+    }
+
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_stream")
+    }
+
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("start_global")
+    }
+
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_global")
+    }
+
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("start_data")
+    }
+
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("end_data")
+    }
+
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("start_saveframe")
+    }
+
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("end_saveframe")
+    }
+
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("start_loop")
+    }
+
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_loop")
+    }
+
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        self.count("start_loop_row")
+    }
+
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        self.count("comment")
+    }
+
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.count("data")
+    }
+}
+
+fn comprehensive_output(input: &str) -> Vec<String> {
+    let tree = parse_default(input).expect("Failed to parse");
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    handler.output
+}
+
+/// The stream events and the lines of `output` from the start to the end of the `kind` (data or
+/// saveframe) called `name`
+fn stream_with_lines_of(output: &[String], kind: &str, name: &str) -> Vec<String> {
+    let find = |event: String| {
+        output
+            .iter()
+            .position(|line| line.starts_with(&event) && line.ends_with(&format!(" {}", name)))
+            .unwrap()
+    };
+    let start = find(format!("<start {}>", kind));
+    let end = find(format!("<end {}>", kind));
+    let mut lines = vec!["<start_stream>".to_string()];
+    lines.extend_from_slice(&output[start..=end]);
+    lines.push("<end_stream>".to_string());
+    lines
+}
+
+#[test]
+fn test_tee_handler_feeds_both_handlers() {
+    let tree = parse_default(THREE_SAVEFRAMES_INPUT).unwrap();
+    let mut tee = TeeHandler::new(
+        ComprehensiveTestHandler { output: Vec::new() },
+        CountingHandler::default(),
+    );
+    StarWalker::from_input(&mut tee, THREE_SAVEFRAMES_INPUT).walk_star_tree_buffered(&tree);
+    let (comprehensive, counting) = tee.into_inner();
+
+    assert_eq!(
+        comprehensive.output,
+        comprehensive_output(THREE_SAVEFRAMES_INPUT)
+    );
+    assert_eq!(counting.get("start_saveframe"), 3);
+    assert_eq!(counting.get("end_saveframe"), 3);
+    assert_eq!(counting.get("start_loop"), 2);
+    assert_eq!(counting.get("start_loop_row"), 2);
+    assert_eq!(counting.get("data"), 8);
+    assert_eq!(counting.get("end_stream"), 1);
+}
+
+#[test]
+fn test_tee_handler_skips_only_for_the_handler_that_asked() {
+    let tree = parse_default(THREE_SAVEFRAMES_INPUT).unwrap();
+    let skipping = SkippingHandler {
+        skip: "second",
+        events: Vec::new(),
+    };
+    let mut tee = TeeHandler::new(skipping, ComprehensiveTestHandler { output: Vec::new() });
+    StarWalker::from_input(&mut tee, THREE_SAVEFRAMES_INPUT).walk_star_tree_buffered(&tree);
+    let (skipping, comprehensive) = tee.into_inner();
+
+    assert_eq!(
+        skipping.events,
+        SkippingHandler::walk(THREE_SAVEFRAMES_INPUT, "second")
+    );
+    assert_eq!(
+        comprehensive.output,
+        comprehensive_output(THREE_SAVEFRAMES_INPUT)
+    );
+
+    // a loop skipped from loop_definition is skipped up to its end_loop
+    let skipping = SkippingHandler {
+        skip: "_row.a",
+        events: Vec::new(),
+    };
+    let mut tee = TeeHandler::new(CountingHandler::default(), skipping);
+    StarWalker::from_input(&mut tee, THREE_SAVEFRAMES_INPUT).walk_star_tree_buffered(&tree);
+    let (counting, skipping) = tee.into_inner();
+
+    assert_eq!(
+        skipping.events,
+        SkippingHandler::walk(THREE_SAVEFRAMES_INPUT, "_row.a")
+    );
+    assert_eq!(counting.get("data"), 8);
+}
+
+#[test]
+fn test_tee_handler_stops_when_either_handler_stops() {
+    let tree = parse_default(THREE_SAVEFRAMES_INPUT).unwrap();
+    let counting = CountingHandler {
+        stop_at: Some("start_loop"),
+        ..Default::default()
+    };
+    let mut tee = TeeHandler::new(ComprehensiveTestHandler { output: Vec::new() }, counting);
+    StarWalker::from_input(&mut tee, THREE_SAVEFRAMES_INPUT).walk_star_tree_buffered(&tree);
+    let (comprehensive, counting) = tee.into_inner();
+
+    assert_eq!(counting.get("start_loop"), 1);
+    assert_eq!(counting.get("end_stream"), 0);
+    assert!(comprehensive
+        .output
+        .last()
+        .unwrap()
+        .starts_with("<start_loop>"));
+}
+
+#[test]
+fn test_filter_handler_forwards_matching_saveframe() {
+    let tree = parse_default(THREE_SAVEFRAMES_INPUT).unwrap();
+    let tee = TeeHandler::new(
+        ComprehensiveTestHandler { output: Vec::new() },
+        CountingHandler::default(),
+    );
+    let mut filter = FilterHandler::new(tee, |name: &str| name == "second");
+    StarWalker::from_input(&mut filter, THREE_SAVEFRAMES_INPUT).walk_star_tree_buffered(&tree);
+    let (comprehensive, counting) = filter.into_inner().into_inner();
+
+    assert_eq!(
+        comprehensive.output,
+        stream_with_lines_of(
+            &comprehensive_output(THREE_SAVEFRAMES_INPUT),
+            "saveframe",
+            "second"
+        )
+    );
+    assert_eq!(counting.get("start_data"), 0);
+    assert_eq!(counting.get("start_saveframe"), 1);
+    assert_eq!(counting.get("data"), 3);
+}
+
+#[test]
+fn test_filter_handler_forwards_matching_data_block() {
+    let tree = parse_default(GLOBAL_WITH_LOOP_INPUT).unwrap();
+    let tee = TeeHandler::new(
+        ComprehensiveTestHandler { output: Vec::new() },
+        CountingHandler::default(),
+    );
+    let mut filter = FilterHandler::new(tee, |name: &str| name.starts_with("exp"));
+    StarWalker::from_input(&mut filter, GLOBAL_WITH_LOOP_INPUT).walk_star_tree_buffered(&tree);
+    let (comprehensive, counting) = filter.into_inner().into_inner();
+
+    assert_eq!(
+        comprehensive.output,
+        stream_with_lines_of(
+            &comprehensive_output(GLOBAL_WITH_LOOP_INPUT),
+            "data",
+            "experiment"
+        )
+    );
+    assert_eq!(counting.get("start_global"), 0);
+    assert_eq!(counting.get("start_loop"), 0);
+    assert_eq!(counting.get("data"), 1);
+}
+
+const COMMENT_FILE: &str = "tests/test_data/comment_association.star";
+
+/// The ComprehensiveTestHandler output of walking `input` with comments attached or not
+fn attached_comments_output(input: &str, attach: bool) -> Vec<String> {
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(attach));
+    let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+    ustar::walk(input, &config, &mut handler).expect("Failed to walk");
+    handler.output
+}
+
+#[test]
+fn test_attached_comments_walker_output() {
+    let input = fs::read_to_string(COMMENT_FILE).unwrap();
+
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__attached_comments_walker_output",
+        &attached_comments_output(&input, true).join("\n"),
+    );
+    snapshot_utils::assert_snapshot_gz(
+        "sas_walker_tests__unattached_comments_walker_output",
+        &attached_comments_output(&input, false).join("\n"),
+    );
+}
+
+/// `attached_comments_output` lines without their positions or comment kinds, which laying a
+/// file out changes
+fn without_positions(output: Vec<String>) -> Vec<String> {
+    output
+        .into_iter()
+        .map(|line| match line.find(" [") {
+            Some(start) => {
+                let end = start + line[start..].find(']').unwrap();
+                format!("{}{}", &line[..start], &line[end + 1..])
+            }
+            None => line,
+        })
+        .map(|line| {
+            line.replacen("# FullLine ", "# ", 1)
+                .replacen("# Inline ", "# ", 1)
+        })
+        .collect()
+}
+
+/// Write the comment file and every parseable file in sas_test_files laid out with comments,
+/// aligned loops and blank lines, and check walking the text gives the same events, comments
+/// included
+#[test]
+fn test_star_writer_layout_round_trip() {
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+    let mut paths = vec![Path::new(COMMENT_FILE).to_path_buf()];
+    for entry in fs::read_dir("tests/test_data/sas_test_files").expect("read_dir failed") {
+        let path = entry.expect("entry failed").path();
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let is_star = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("str" | "cif" | "dic")
+        );
+        if is_star && !KNOWN_PARSE_FAILURES.contains(&filename.as_str()) {
+            paths.push(path);
+        }
+    }
+
+    for path in paths {
+        let content = String::from_utf8_lossy(&fs::read(&path).unwrap()).to_string();
+        let mut writer = StarWriterHandler::new(Vec::new())
+            .with_comments()
+            .with_aligned_loops()
+            .with_blank_lines();
+        ustar::walk(&content, &config, &mut writer).expect("Failed to walk");
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            without_positions(attached_comments_output(&content, true)),
+            without_positions(attached_comments_output(&written, true)),
+            "SAS event streams differ after laying out {:?}:\n{}",
+            path,
+            written
+        );
+    }
+}
+
+#[test]
+fn test_star_writer_layout_output() {
+    let input = indoc! {"
+        # header
+        data_test
+        _entry.id   1
+           _entry.title 'a title'
+        loop_
+        _atom.id _atom.name
+        1 CA   # first
+        22 CB
+        333 N
+        stop_
+        _entry.after yes
+        save_frame
+        # inside
+        _frame.id 7
+        save_
+    "};
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+    let mut writer = StarWriterHandler::new(Vec::new())
+        .with_indent("  ")
+        .with_comments()
+        .with_aligned_loops()
+        .with_blank_lines();
+    ustar::walk(input, &config, &mut writer).unwrap();
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        written,
+        indoc! {"
+            # header
+            data_test
+            _entry.id  1
+            _entry.title  'a title'
+
+            loop_
+              _atom.id
+              _atom.name
+              1   CA # first
+              22  CB
+              333 N
+            stop_
+
+            _entry.after  yes
+
+            save_frame
+              # inside
+              _frame.id  7
+            save_
+        "}
+    );
+}
+
+#[test]
+fn test_star_writer_pynmrstar_style_output() {
+    let input = indoc! {"
+        data_tiny
+        # comments are dropped
+        save_entry
+        _Entry.Sf_category entry_information
+        _Entry.ID 1
+        _Entry.Title
+        ;
+        A title
+        ;
+        loop_
+        _Author.Ordinal _Author.Name
+        1 'Jane Doe' 22 Smith
+        333
+        ;
+        Long name
+        ;
+        stop_
+        save_
+    "};
+    let mut writer = StarWriterHandler::new(Vec::new()).with_pynmrstar_style();
+    ustar::walk(input, &default_config(), &mut writer).unwrap();
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        written,
+        indoc! {"
+            data_tiny
+
+            save_entry
+               _Entry.Sf_category   entry_information
+               _Entry.ID            1
+               _Entry.Title
+            ;
+            A title
+            ;
+
+               loop_
+                  _Author.Ordinal
+                  _Author.Name
+
+                  1     'Jane Doe'
+                  22    Smith
+                  333  \x20
+            ;
+            Long name
+            ;
+               stop_
+            save_
+
+        "}
+    );
+    assert_eq!(
+        without_positions(attached_comments_output(&written, false)),
+        without_positions(attached_comments_output(input, false))
+            .into_iter()
+            .filter(|line| !line.trim_start().starts_with("# "))
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Entries BMRB wrote with pynmrstar 3, which lays out text fields in loop rows and rows
+/// starting with a text field as well as the usual items and loops
+static PYNMRSTAR_ENTRIES: &[&str] = &["bmr51928_3.str", "bmr52127_3.str", "bmr52810_3.str"];
+
+/// `text` without its comment lines, and the blank line pynmrstar writes before each block of
+/// them, which is what pynmrstar writes when it doesn't add its standard comments
+fn without_pynmrstar_comments(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_text_field = false;
+    let mut in_comments = false;
+    for line in text.split('\n') {
+        if line.starts_with(';') {
+            in_text_field = !in_text_field;
+        }
+        let trimmed = line.trim_start_matches([' ', '\t']);
+        let is_comment = trimmed.starts_with('#') || (trimmed.is_empty() && !line.is_empty());
+        if !in_text_field && !line.starts_with(';') && is_comment {
+            if !in_comments && lines.last() == Some(&"") {
+                lines.pop();
+            }
+            in_comments = true;
+            continue;
+        }
+        in_comments = false;
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn test_star_writer_pynmrstar_style_matches_bmrb_entries() {
+    let dir = Path::new("tests/test_data/bmrb_stars");
+    ensure_test_data_available(dir, TestDataPolicy::DownloadIfMissing)
+        .expect("Failed to verify test data integrity for BMRB stars");
+
+    for name in PYNMRSTAR_ENTRIES {
+        let content = fs::read_to_string(dir.join(name)).unwrap();
+        let mut writer = StarWriterHandler::new(Vec::new()).with_pynmrstar_style();
+        ustar::walk(&content, &default_config(), &mut writer).expect("Failed to walk");
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert!(
+            written == without_pynmrstar_comments(&content),
+            "{} laid out in pynmrstar style differs from the original",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_attached_comments_skip_hashes_in_values() {
+    let input = indoc! {"
+        data_test
+        _quoted  'a # b'
+        _text
+        ;
+        # a line of the text field
+        ;
+        # a comment
+        _after  a#b
+    "};
+
+    let comments: Vec<String> = attached_comments_output(input, true)
+        .into_iter()
+        .filter(|line| line.trim_start().starts_with('#'))
+        .collect();
+    assert_eq!(comments, vec!["    # [7] # a comment"]);
+}
+
+#[test]
+fn test_attached_comments_precede_their_element() {
+    let input = fs::read_to_string(COMMENT_FILE).unwrap();
+    let output = attached_comments_output(&input, true);
+    let following = |comment: &str| {
+        let index = output
+            .iter()
+            .position(|line| line.ends_with(comment))
+            .unwrap_or_else(|| panic!("{} not found in {:#?}", comment, output));
+        let next = output[index + 1..]
+            .iter()
+            .find(|line| !line.starts_with("    #"))
+            .unwrap();
+        (output[index - 1].as_str(), next.as_str())
+    };
+
+    let (before, after) = following("# spread over two lines");
+    assert_eq!(before, "    # [1] # Header comment describing the file");
+    assert!(after.starts_with("<start data>"), "{}", after);
+
+    // an inline comment belongs to the value before it, it's delivered on its own after it
+    let (before, after) = following("Inline \" inline comment after a value\"");
+    assert!(before.contains("_entry.id"), "{}", before);
+    assert!(after.contains("_entry.title"), "{}", after);
+    let (_, after) = following("# documents the save frame below");
+    assert!(after.starts_with("<start saveframe>"), "{}", after);
+    let (_, after) = following("# documents the loop");
+    assert!(after.starts_with("<start_loop>"), "{}", after);
+
+    // comments with nothing after them in their scope are delivered on their own, as the text
+    // after their # with its kind
+    for comment in [
+        "trailing comment in the loop",
+        "trailing comment in the save frame",
+        "trailing comment in the data block",
+    ] {
+        let standalone = format!("FullLine \" {}\"", comment);
+        let (before, _) = following(&standalone);
+        assert!(!before.starts_with("<element_comments>"), "{}", comment);
+        assert!(output
+            .iter()
+            .any(|line| line.starts_with("# [") && line.ends_with(&standalone)));
+    }
+}
+
+/// Records attached comments with their kinds and standalone comments in the order delivered
+#[derive(Default)]
+struct CommentKindHandler {
+    events: Vec<String>,
+}
+
+impl SASContentHandler for CommentKindHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.events.push(format!("data_{}", name));
+        WalkControl::Continue
+    }
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.events.push("loop_".to_string());
+        WalkControl::Continue
+    }
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn comment(&mut self, _position: LineColumn, text: &str) -> WalkControl {
+        panic!(
+            "comment called with {:?}, comment_with_kind is overridden",
+            text
+        );
+    }
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        self.events
+            .push(format!("{:?} [{}] {:?}", kind, position.line, text));
+        WalkControl::Continue
+    }
+    fn element_comments_with_kind(
+        &mut self,
+        comments: &[(LineColumn, &str, CommentKind)],
+    ) -> WalkControl {
+        for &(position, text, kind) in comments {
+            self.events.push(format!(
+                "attached {:?} [{}] {:?}",
+                kind, position.line, text
+            ));
+        }
+        WalkControl::Continue
+    }
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.events.push(format!("{} {}", tag, value));
+        WalkControl::Continue
+    }
+}
+
+#[test]
+fn test_attached_comments_carry_their_kind_and_inline_comments_stay_put() {
+    let input = indoc! {"
+        # about the block
+        data_x
+        _x 1 # inline
+        loop_
+        _a
+        # about the row
+        1 # after the row
+        stop_
+    "};
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+    let mut handler = CommentKindHandler::default();
+    ustar::walk(input, &config, &mut handler).unwrap();
+
+    assert_eq!(
+        handler.events,
+        [
+            "attached FullLine [1] \" about the block\"",
+            "data_x",
+            "_x 1",
+            "Inline [3] \" inline\"",
+            "loop_",
+            "attached FullLine [6] \" about the row\"",
+            "_a 1",
+            "Inline [7] \" after the row\"",
+        ]
+    );
+}
+
+#[test]
+fn test_attached_comments_default_to_comment_events() {
+    let input = fs::read_to_string(COMMENT_FILE).unwrap();
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+
+    // CountingHandler leaves element_comments to its default, each comment goes to comment
+    let mut attached = CountingHandler::default();
+    ustar::walk(&input, &config, &mut attached).unwrap();
+    let mut standalone = CountingHandler::default();
+    ustar::walk(&input, &default_config(), &mut standalone).unwrap();
+
+    assert_eq!(attached.get("comment"), 11);
+    attached.counts.remove("comment");
+    standalone.counts.remove("comment");
+    assert_eq!(attached.counts, standalone.counts);
+}
+
+#[test]
+fn test_comment_count_is_the_same_attached_or_not() {
+    // the header, inline and trailing comments are all reported whether or not they're attached
+    let input = fs::read_to_string(COMMENT_FILE).unwrap();
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+
+    let mut attached = CountingHandler::default();
+    ustar::walk(&input, &config, &mut attached).unwrap();
+    let mut standalone = CountingHandler::default();
+    ustar::walk(&input, &default_config(), &mut standalone).unwrap();
+
+    assert_eq!(standalone.get("comment"), attached.get("comment"));
+    assert_eq!(standalone.get("comment"), input.matches('#').count());
+}
+
+#[test]
+fn test_filter_handler_forwards_comments_attached_to_a_match() {
+    let input = fs::read_to_string(COMMENT_FILE).unwrap();
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+
+    let mut filter = FilterHandler::new(ComprehensiveTestHandler { output: Vec::new() }, |name| {
+        name == "frame_one"
+    });
+    ustar::walk(&input, &config, &mut filter).unwrap();
+    let output = filter.into_inner().output;
+
+    assert_eq!(output[1], "<element_comments>");
+    assert_eq!(output[2], "    # [9] # documents the save frame below");
+    assert!(output[3].starts_with("<start saveframe>"));
+    assert!(!output.iter().any(|line| line.contains("inline comment")));
+}
+
+#[test]
+fn test_walker_positions_count_in_column_unit() {
+    // 'α' takes two bytes, the combining acute accent after it two more but no grapheme
+    let input = "data_test\n_a 'α\u{301}' _b x\n";
+    for (unit, expected) in [
+        (ColumnUnit::Bytes, "[t:2:11,v:2:14]"),
+        (ColumnUnit::Chars, "[t:2:9,v:2:12]"),
+        (ColumnUnit::Graphemes, "[t:2:8,v:2:11]"),
+    ] {
+        let mut config = default_config();
+        config.insert(
+            ConfigKey::Encoding,
+            ConfigValue::Encoding(EncodingMode::Unicode),
+        );
+        config.insert(ConfigKey::ColumnUnit, ConfigValue::ColumnUnit(unit));
+
+        let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+        ustar::walk(input, &config, &mut handler).unwrap();
+        let walked = handler.output;
+
+        let tree = parse(input, &config).unwrap();
+        let mut handler = ComprehensiveTestHandler { output: Vec::new() };
+        StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+
+        assert_eq!(walked, handler.output, "{:?}", unit);
+        let data = walked.iter().find(|line| line.contains(" _b ")).unwrap();
+        assert!(data.contains(expected), "{:?}: {}", unit, data);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_pretty_output() {
+    use ustar::sas_handlers::JsonHandler;
+
+    let input = indoc! {"
+        data_test
+        _entry.id  1
+        loop_
+            _row.id
+            _row.name
+            1 a
+        stop_
+        loop_
+            _empty.id
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+    let mut handler = JsonHandler::new(Cursor::new(Vec::new())).with_indent("  ");
+    StarWalker::from_input(&mut handler, input).walk_star_tree_buffered(&tree);
+    let written = handler.into_inner().unwrap().into_inner();
+
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        indoc! {r#"
+            {
+              "data_test": {
+                "_entry.id": "1",
+                "loop_1": [
+                  {
+                    "_row.id": "1",
+                    "_row.name": "a"
+                  }
+                ],
+                "loop_2": []
+              }
+            }
+        "#}
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_reader_writes_star() {
+    use ustar::json_reader::walk_json;
+
+    let json = r#"{
+        "global_": {
+            "_setting": "on",
+            "loop_1": [
+                {"_outer.id": "1", "loop_": [{"_inner.id": "a", "_inner.n": 1}]},
+                {"_outer.id": "2", "loop_": [{"_inner.id": "b", "_inner.n": 2}]}
+            ]
+        },
+        "data_test": {
+            "_entry.id": null,
+            "_entry.date": "?",
+            "_entry.title": "two words",
+            "_entry.dot": ".",
+            "_entry.count": 3,
+            "save_frame": {
+                "loop_1": [{"_row.text": "it's \"quoted\""}]
+            },
+            "loop_2": []
+        }
+    }"#;
+    let mut writer = StarWriterHandler::new(Vec::new());
+    assert!(!walk_json(json, &mut writer).unwrap());
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    // reading the written STAR gives the events and delimiters of the JSON
+    let mut recorder = EventRecorder(Vec::new());
+    walk_json(json, &mut recorder).unwrap();
+    assert_eq!(recorded_events(&written), recorder.0);
+    assert_eq!(
+        written,
+        indoc! {r#"
+            global_
+            _setting  on
+            loop_
+                _outer.id
+                loop_
+                    _inner.id
+                    _inner.n
+                stop_
+                1
+                    a 1
+                    stop_
+                2
+                    b 2
+                    stop_
+            stop_
+
+            data_test
+            _entry.id  .
+            _entry.date  ?
+            _entry.title  'two words'
+            _entry.dot  '.'
+            _entry.count  3
+            save_frame
+                loop_
+                    _row.text
+            ;it's "quoted"
+            ;
+                stop_
+            save_
+        "#}
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_reader_rejects_other_shapes() {
+    use ustar::json_reader::walk_json;
+
+    let mut writer = StarWriterHandler::new(Vec::new());
+    let error = walk_json(r#"{"test": {}}"#, &mut writer).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected a block key starting with data_ or global_, found test"
+    );
+    assert!(walk_json("[1, 2]", &mut writer).is_err());
+    assert!(walk_json("{", &mut writer).is_err());
+}
+
+#[test]
+fn test_stats_handler_counts() {
+    use ustar::sas_handlers::StatsHandler;
+
+    let input = indoc! {"
+        # header
+        data_test
+        _entry.id  1
+        _entry.title  'a title'
+        save_frame
+            loop_
+                _outer.id
+                loop_
+                    _inner.a
+                    _inner.b
+                stop_
+                1
+                    x
+        ;text
+        ;
+                    stop_
+            stop_
+        save_
+        loop_
+            _empty.id
+        stop_
+    "};
+    let mut config = default_config();
+    config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+    let mut handler = StatsHandler::new();
+    ustar::walk(input, &config, &mut handler).unwrap();
+    let stats = handler.into_stats();
+
+    assert_eq!(
+        (stats.data_blocks, stats.global_blocks, stats.saveframes),
+        (1, 0, 1)
+    );
+    assert_eq!((stats.loops, stats.nested_loops), (2, 1));
+    assert_eq!((stats.items, stats.loop_values), (2, 3));
+    assert_eq!(stats.comments, 1);
+    assert_eq!(stats.max_depth, 4);
+    assert_eq!(
+        &stats.delimiters[..4],
+        &[
+            (ValueDelimiter::None, 3),
+            (ValueDelimiter::Single, 1),
+            (ValueDelimiter::Double, 0),
+            (ValueDelimiter::Semicolon, 1),
+        ]
     );
 }
@@ -0,0 +1,76 @@
+#![cfg(feature = "serde")]
+
+use std::fs;
+use ustar::line_column_index::LineColumn;
+use ustar::mutable_pair::MutablePair;
+use ustar::{parse_default, ErrorFormatMode, UstarError};
+
+mod snapshot_utils;
+
+ustar_test_utils::snapshot_orphan_check!("serde_tests");
+
+fn parse_file(name: &str) -> MutablePair {
+    let input = fs::read_to_string(format!("tests/test_data/{}", name)).unwrap();
+    parse_default(&input).unwrap()
+}
+
+/// Line and column positions aren't serialized, so they are undefined after a round trip
+fn clear_positions(pair: &mut MutablePair) {
+    pair.start_position = LineColumn::undefined();
+    pair.end_position = LineColumn::undefined();
+    for child in &mut pair.children {
+        clear_positions(child);
+    }
+}
+
+#[test]
+fn test_mutable_pair_json_round_trip() {
+    let tree = parse_file("comprehensive_example.star");
+
+    let json = tree.to_json().unwrap();
+    let restored = MutablePair::from_json(&json).unwrap();
+
+    let mut expected = tree.clone();
+    clear_positions(&mut expected);
+    assert_eq!(restored, expected);
+}
+
+#[test]
+fn test_mutable_pair_json_shape() {
+    let tree = parse_file("tiny.star");
+
+    let value: serde_json::Value = serde_json::from_str(&tree.to_json().unwrap()).unwrap();
+    let keys: Vec<&String> = value.as_object().unwrap().keys().collect();
+    assert_eq!(keys, vec!["children", "end", "rule", "start", "text"]);
+    assert_eq!(value["rule"], "star_file");
+
+    snapshot_utils::assert_snapshot_gz(
+        "serde_tests__tiny_star_json",
+        &serde_json::to_string_pretty(&tree).unwrap(),
+    );
+}
+
+#[test]
+fn test_ustar_error_json_round_trip() {
+    let error = parse_default("data_test\n_entry.id\n").unwrap_err();
+
+    let json = serde_json::to_string(&error).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["ParseError"]["line"], 3);
+    assert_eq!(value["ParseError"]["encoding"], "Ascii");
+
+    let restored: UstarError = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.to_string(), error.to_string());
+    for mode in [ErrorFormatMode::Basic, ErrorFormatMode::Ascii] {
+        assert_eq!(restored.format_error(mode, 2), error.format_error(mode, 2));
+    }
+}
+
+#[test]
+fn test_line_column_json_round_trip() {
+    let position = LineColumn::new(12, 7);
+
+    let json = serde_json::to_string(&position).unwrap();
+    assert_eq!(json, r#"{"line":12,"column":7}"#);
+    assert_eq!(serde_json::from_str::<LineColumn>(&json).unwrap(), position);
+}
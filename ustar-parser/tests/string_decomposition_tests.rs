@@ -1,8 +1,14 @@
 use std::fs;
-use ustar::{parse_default, string_decomposer::decompose_strings};
+use ustar::mutable_pair::MutablePair;
+use ustar::{
+    default_config, parse, parse_default, string_decomposer::decompose_strings, ConfigKey,
+    ConfigValue,
+};
 
 mod snapshot_utils;
 
+ustar_test_utils::snapshot_orphan_check!("string_decomposition_tests");
+
 #[test]
 fn test_semicolon_string_decomposition_lf_vs_crlf() {
     // Test LF version
@@ -29,3 +35,37 @@ fn test_semicolon_string_decomposition_lf_vs_crlf() {
         &format!("{:#?}", crlf_mutable),
     );
 }
+
+#[test]
+fn test_unescape_quotes_keeps_raw_offsets() {
+    let input = "data_test\n_dense 'He said''Hello''to''me'\n_double \"x\"\"y\"\n_plain 'it''s'\n";
+    let mut config = default_config();
+    config.insert(ConfigKey::UnescapeQuotes, ConfigValue::Bool(true));
+    let tree = parse(input, &config).expect("Failed to parse");
+
+    let contents: Vec<_> = tree
+        .find_all("string")
+        .into_iter()
+        .map(|content| (content.as_str(), &input[content.start..content.end]))
+        .collect();
+    assert_eq!(
+        contents,
+        [
+            ("He said'Hello'to'me", "He said''Hello''to''me"),
+            ("x\"y", "x\"\"y"),
+            ("it's", "it''s"),
+        ]
+    );
+
+    // the logical value of the whole string is the same with or without the option
+    let escaped = parse_default(input).expect("Failed to parse");
+    let values = |tree: &MutablePair| -> Vec<String> {
+        ["single_quote_string", "double_quote_string"]
+            .iter()
+            .flat_map(|rule| tree.find_all(rule))
+            .map(|string| string.logical_value().into_owned())
+            .collect()
+    };
+    assert_eq!(values(&tree), values(&escaped));
+    assert_eq!(values(&tree), ["He said'Hello'to'me", "it's", "x\"y"]);
+}
@@ -0,0 +1,190 @@
+use std::fs;
+use ustar::parse_default;
+use ustar::tag_index::{CaseSensitivity, TagIndex, TagValue};
+
+const BLOCK: &str = "comprehensive_example";
+
+fn comprehensive_example() -> String {
+    fs::read_to_string("tests/test_data/comprehensive_example.star").unwrap()
+}
+
+#[test]
+fn test_items_outside_and_inside_save_frames() {
+    let input = comprehensive_example();
+    let tree = parse_default(&input).unwrap();
+    let index = TagIndex::build(&tree).unwrap();
+
+    let value = |frame, tag| {
+        index
+            .get(BLOCK, frame, tag)
+            .and_then(|value| value.as_str())
+    };
+    assert_eq!(value(None, "_numeric_value"), Some("42.5"));
+    assert_eq!(
+        value(None, "_single_quoted"),
+        Some("Hello \"world\" with spaces")
+    );
+    assert_eq!(value(None, "_frame_code_simple"), Some("$frame1"));
+    assert_eq!(
+        value(Some("frame_example_1"), "_temperature"),
+        Some("298.15")
+    );
+    assert_eq!(
+        value(Some("frame_example_2"), "_methodology"),
+        Some("X-ray crystallography")
+    );
+    assert_eq!(
+        index
+            .get("second_example", None, "_mixed_case_VALUE")
+            .unwrap()
+            .values(),
+        vec!["MixedCaseValue"]
+    );
+    assert_eq!(
+        index.get("", None, "_global_format").unwrap().as_str(),
+        Some("STAR")
+    );
+}
+
+#[test]
+fn test_loop_columns() {
+    let input = comprehensive_example();
+    let tree = parse_default(&input).unwrap();
+    let index = TagIndex::build(&tree).unwrap();
+
+    let labels = index.get(BLOCK, None, "_atom_site_label").unwrap();
+    assert!(matches!(labels, TagValue::Column { .. }));
+    assert_eq!(labels.as_str(), None);
+    assert_eq!(labels.values(), vec!["C1", "N1", "O1", "H1"]);
+
+    assert_eq!(
+        index
+            .get(BLOCK, None, "_struct_conf_type_id")
+            .unwrap()
+            .values(),
+        vec!["HELX_P1", "STRN_S1"]
+    );
+    assert_eq!(
+        index
+            .get(BLOCK, None, "_struct_conf_atom_site_auth_seq_id")
+            .unwrap()
+            .values(),
+        vec!["123", "124", "125", "456", "457"]
+    );
+    assert_eq!(
+        index
+            .get(BLOCK, Some("frame_example_1"), "_bond_length")
+            .unwrap()
+            .values(),
+        vec!["1.54", "1.52", "1.47"]
+    );
+    assert_eq!(
+        index.get("", None, "_software_version").unwrap().values(),
+        vec!["1.0", "2024.1"]
+    );
+}
+
+#[test]
+fn test_blocks_frames_and_tags() {
+    let input = comprehensive_example();
+    let tree = parse_default(&input).unwrap();
+    let index = TagIndex::build(&tree).unwrap();
+
+    assert_eq!(
+        index.blocks().collect::<Vec<_>>(),
+        vec![BLOCK, "", "second_example"]
+    );
+    assert_eq!(
+        index.frames(BLOCK).collect::<Vec<_>>(),
+        vec!["frame_example_1", "frame_example_2"]
+    );
+    assert_eq!(index.frames("second_example").count(), 0);
+    assert_eq!(
+        index
+            .tags(BLOCK, Some("frame_example_2"))
+            .collect::<Vec<_>>(),
+        vec!["_another_category", "_methodology", "_experimental_details"]
+    );
+    let block_tags: Vec<&str> = index.tags(BLOCK, None).collect();
+    assert_eq!(block_tags.first(), Some(&"_simple_text_value"));
+    assert!(block_tags.contains(&"_struct_conf_atom_site_label"));
+    assert!(!block_tags.contains(&"_temperature"));
+}
+
+#[test]
+fn test_misses() {
+    let input = comprehensive_example();
+    let tree = parse_default(&input).unwrap();
+    let index = TagIndex::build(&tree).unwrap();
+
+    assert!(index.get(BLOCK, None, "_missing").is_none());
+    assert!(index.get("missing_block", None, "_numeric_value").is_none());
+    // names in save frames aren't visible in the block, or in other save frames
+    assert!(index.get(BLOCK, None, "_temperature").is_none());
+    assert!(index
+        .get(BLOCK, Some("frame_example_2"), "_temperature")
+        .is_none());
+    assert!(index
+        .get(BLOCK, Some("missing_frame"), "_temperature")
+        .is_none());
+    assert_eq!(index.frames("missing_block").count(), 0);
+    assert_eq!(index.tags(BLOCK, Some("missing_frame")).count(), 0);
+}
+
+#[test]
+fn test_case_sensitivity() {
+    let input = comprehensive_example();
+    let tree = parse_default(&input).unwrap();
+
+    let insensitive = TagIndex::build(&tree).unwrap();
+    assert_eq!(
+        insensitive
+            .get(
+                "COMPREHENSIVE_Example",
+                Some("FRAME_EXAMPLE_1"),
+                "_Temperature"
+            )
+            .and_then(|value| value.as_str()),
+        Some("298.15")
+    );
+    assert_eq!(
+        insensitive
+            .get("second_example", None, "_mixed_case_value")
+            .and_then(|value| value.as_str()),
+        Some("MixedCaseValue")
+    );
+
+    let sensitive = TagIndex::build_with(&tree, CaseSensitivity::Sensitive).unwrap();
+    assert!(sensitive
+        .get(
+            "COMPREHENSIVE_Example",
+            Some("frame_example_1"),
+            "_temperature"
+        )
+        .is_none());
+    assert!(sensitive
+        .get("second_example", None, "_mixed_case_value")
+        .is_none());
+    assert!(sensitive
+        .get("second_example", None, "_mixed_case_VALUE")
+        .is_some());
+}
+
+#[test]
+fn test_first_definition_of_a_duplicate_is_indexed() {
+    let tree = parse_default("data_dup\n_tag first\n_TAG second\n").unwrap();
+    let index = TagIndex::build(&tree).unwrap();
+
+    assert_eq!(
+        index.get("dup", None, "_tag").unwrap().as_str(),
+        Some("first")
+    );
+    assert_eq!(index.tags("dup", None).collect::<Vec<_>>(), vec!["_tag"]);
+}
+
+#[test]
+fn test_ragged_loop_fails_the_build() {
+    let tree = parse_default("data_ragged\nloop_\n_a\n_b\n1 2 3\nstop_\n").unwrap();
+
+    assert!(TagIndex::build(&tree).is_err());
+}
@@ -1,6 +1,6 @@
 //! Test the automatic test data download functionality
 
-use ustar_test_utils::ensure_test_data_available;
+use ustar_test_utils::{ensure_test_data_available, TestDataError, TestDataPolicy};
 
 #[test]
 fn test_ensure_data_available_with_missing_files() {
@@ -9,13 +9,13 @@ fn test_ensure_data_available_with_missing_files() {
     // This test checks if the download system works
     // If no-large-tests is enabled, it should fail
     // If no-large-tests is disabled (default), it should download and succeed
-    let result = ensure_test_data_available(test_data_dir);
+    let result = ensure_test_data_available(test_data_dir, TestDataPolicy::DownloadIfMissing);
 
     #[cfg(feature = "no-large-tests")]
     {
-        // With no-large-tests, should fail
+        // With no-large-tests, should fail without trying to download
         assert!(
-            result.is_err(),
+            matches!(result, Err(TestDataError::OfflineMissing { .. })),
             "Should fail when no-large-tests is enabled and files are missing"
         );
         println!("✅ no-large-tests correctly prevented download");
@@ -43,6 +43,11 @@ fn test_ensure_data_available_with_missing_files() {
                 }
                 assert!(found_files > 0, "No expected files were downloaded");
             }
+            Err(TestDataError::OfflineMissing { .. })
+                if std::env::var("USTAR_OFFLINE").is_ok_and(|value| value == "1") =>
+            {
+                println!("USTAR_OFFLINE=1 is set, so the download wasn't attempted");
+            }
             Err(e) => {
                 panic!("Download failed when it should have succeeded: {}", e);
             }
@@ -0,0 +1,73 @@
+use std::fs;
+use std::thread;
+use ustar::line_column_index::LineColumn;
+use ustar::mutable_pair::MutablePair;
+use ustar::shared_text::SharedText;
+use ustar::{default_config, parse, ParserConfig, UstarError};
+
+/// Compiles only if `T` can be sent to and shared between threads
+fn assert_send_sync<T: Send + Sync>() {}
+
+/// Compiles only if `T` can also be kept for as long as the program runs, as boxed errors are
+fn assert_send_sync_static<T: Send + Sync + 'static>() {}
+
+#[test]
+fn test_trees_and_errors_are_send_and_sync() {
+    assert_send_sync::<MutablePair>();
+    assert_send_sync::<SharedText>();
+    assert_send_sync::<LineColumn>();
+    assert_send_sync::<ParserConfig>();
+    assert_send_sync_static::<UstarError>();
+    assert_send_sync_static::<Box<dyn std::error::Error + Send + Sync>>();
+}
+
+#[test]
+fn test_tree_parsed_on_one_thread_is_read_on_another() {
+    let input = fs::read_to_string("tests/test_data/comprehensive_example.star").unwrap();
+    let tree = parse(&input, &default_config()).unwrap();
+    let expected = tree.find_all("data_name").len();
+
+    let counted = thread::spawn(move || tree.find_all("data_name").len())
+        .join()
+        .unwrap();
+    assert_eq!(counted, expected);
+}
+
+#[test]
+fn test_error_is_returned_from_a_thread() {
+    let error = thread::spawn(|| parse("data_test\n_a\n", &default_config()).unwrap_err())
+        .join()
+        .unwrap();
+    let boxed: Box<dyn std::error::Error + Send + Sync> = Box::new(error);
+    assert!(!boxed.to_string().is_empty());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_saveframes_are_processed_in_parallel() {
+    use rayon::prelude::*;
+
+    let input = fs::read_to_string("tests/test_data/comprehensive_example.star").unwrap();
+    let tree = parse(&input, &default_config()).unwrap();
+    let frames = tree.find_all("save_frame");
+    assert!(frames.len() > 1, "expected several saveframes");
+
+    // each frame's heading and its data names, with string nodes sharing the one input
+    let summarise = |frame: &&MutablePair| {
+        let heading = frame
+            .find_first("save_heading")
+            .unwrap()
+            .as_str()
+            .to_string();
+        let names: Vec<String> = frame
+            .find_all("data_name")
+            .iter()
+            .map(|name| name.as_str().to_string())
+            .collect();
+        (heading, names)
+    };
+    let sequential: Vec<_> = frames.iter().map(summarise).collect();
+    let parallel: Vec<_> = frames.par_iter().map(summarise).collect();
+
+    assert_eq!(parallel, sequential);
+}
@@ -0,0 +1,190 @@
+#![cfg(feature = "trace")]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use ustar::sas_handlers::DocumentBuilderHandler;
+use ustar::{parse, walk, ParserConfig, ParserConfigBuilder};
+
+/// A span seen by `Recorder`, with the span it was opened in and the fields it was given
+#[derive(Debug, Clone)]
+struct SpanRecord {
+    name: &'static str,
+    parent: Option<usize>,
+    fields: HashMap<String, String>,
+}
+
+impl Visit for SpanRecord {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// A subscriber recording every span in the order opened, with its parent from the spans
+/// entered when it was opened
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<SpanRecord>>>,
+    entered: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Recorder {
+    /// The recorded spans as `parent/child` paths of their names
+    fn paths(&self) -> Vec<String> {
+        let spans = self.spans.lock().unwrap();
+        (0..spans.len())
+            .map(|mut index| {
+                let mut names = vec![spans[index].name];
+                while let Some(parent) = spans[index].parent {
+                    names.push(spans[parent].name);
+                    index = parent;
+                }
+                names.reverse();
+                names.join("/")
+            })
+            .collect()
+    }
+
+    /// The fields of the recorded spans named `name`
+    fn fields(&self, name: &str) -> Vec<HashMap<String, String>> {
+        let spans = self.spans.lock().unwrap();
+        spans
+            .iter()
+            .filter(|span| span.name == name)
+            .map(|span| span.fields.clone())
+            .collect()
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut span = SpanRecord {
+            name: attributes.metadata().name(),
+            parent: self.entered.lock().unwrap().last().copied(),
+            fields: HashMap::new(),
+        };
+        attributes.record(&mut span);
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span);
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, id: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        values.record(&mut spans[id.into_u64() as usize - 1]);
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, id: &Id) {
+        self.entered
+            .lock()
+            .unwrap()
+            .push(id.into_u64() as usize - 1);
+    }
+
+    fn exit(&self, _id: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+/// Run `f` with a `Recorder` as the subscriber and return it
+fn record(f: impl FnOnce()) -> Recorder {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), f);
+    recorder
+}
+
+const INPUT: &str = "data_first\n_a 'one'\ndata_second\nloop_ _b _c\n1 2\n3 4\nstop_\n";
+
+fn config(prelex: bool) -> ParserConfig {
+    ParserConfigBuilder::new()
+        .decomposed_strings(true)
+        .prelex(prelex)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_parse_spans_each_stage() {
+    let recorder = record(|| {
+        parse(INPUT, &config(false)).unwrap();
+    });
+
+    assert_eq!(
+        recorder.paths(),
+        [
+            "parse",
+            "parse/pest",
+            "parse/process_pairs",
+            "parse/process_pairs/block",
+            "parse/process_pairs/block",
+            "parse/decompose_strings",
+        ]
+    );
+
+    let parse = &recorder.fields("parse")[0];
+    assert_eq!(parse["bytes"], INPUT.len().to_string());
+    let pest = &recorder.fields("pest")[0];
+    assert_eq!(pest["encoding"], "\"ascii\"");
+    assert_eq!(pest["compacted"], "false");
+    assert_eq!(recorder.fields("process_pairs")[0]["roots"], "1");
+
+    let blocks = recorder.fields("block");
+    assert_eq!(blocks[0]["rule"], "\"data_block\"");
+    assert_eq!(blocks[0]["start"], "0");
+    // blocks end at their last token
+    let second = INPUT.find("data_second").unwrap();
+    assert_eq!(
+        blocks[0]["end"],
+        INPUT[..second].trim_end().len().to_string()
+    );
+    assert_eq!(blocks[1]["start"], second.to_string());
+    assert_eq!(blocks[1]["end"], INPUT.trim_end().len().to_string());
+    assert_eq!(blocks[1]["children"], "2");
+}
+
+#[test]
+fn test_prelexed_parse_spans_the_lexical_scan() {
+    let recorder = record(|| {
+        parse(INPUT, &config(true)).unwrap();
+    });
+
+    assert_eq!(
+        recorder.paths(),
+        [
+            "parse",
+            "parse/prelex",
+            "parse/pest",
+            "parse/process_pairs",
+            "parse/process_pairs/block",
+            "parse/process_pairs/block",
+            "parse/decompose_strings",
+        ]
+    );
+    assert_eq!(recorder.fields("pest")[0]["compacted"], "true");
+    // block offsets are those of the input, not the compacted text
+    let blocks = recorder.fields("block");
+    assert_eq!(blocks[1]["end"], INPUT.trim_end().len().to_string());
+}
+
+#[test]
+fn test_walk_spans_the_parse() {
+    let recorder = record(|| {
+        let mut handler = DocumentBuilderHandler::new();
+        walk(INPUT, &config(false), &mut handler).unwrap();
+    });
+
+    assert_eq!(recorder.paths(), ["walk", "walk/pest"]);
+    assert_eq!(recorder.fields("walk")[0]["stopped"], "false");
+}
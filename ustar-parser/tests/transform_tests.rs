@@ -0,0 +1,227 @@
+use indoc::indoc;
+use std::collections::HashMap;
+use ustar::line_column_index::LineColumn;
+use ustar::mutable_pair::MutablePair;
+use ustar::parse_default;
+use ustar::sas_handlers::StarWriterHandler;
+use ustar::sas_walker::StarWalker;
+use ustar::tag_index::CaseSensitivity;
+use ustar::transform::{rename_tags, AppliedRename, RenameOptions, RenameReport};
+
+const INPUT: &str = indoc! {"
+    data_nef_test
+    # the old names of the tags
+    save_nef_nmr_meta_data
+       _nef_nmr_meta_data.sf_category nef_nmr_meta_data
+       _nef_nmr_meta_data.program_name  'old program'
+    save_
+    save_nef_chemical_shift_list_1
+       _nef_chemical_shift_list.sf_category nef_chemical_shift_list
+       loop_
+          _nef_chemical_shift.atom_name   _nef_chemical_shift.value
+          H 8.1   # amide
+          N 120.3
+       stop_
+    save_
+    data_nested
+    loop_
+        _outer.id _outer.name
+        loop_
+            _inner.id _inner.value
+    1 a
+        11 x 12 y
+    stop_
+    2 b
+        21 z
+    stop_
+"};
+
+fn mapping(renames: &[(&str, &str)]) -> HashMap<String, String> {
+    renames
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect()
+}
+
+fn rename(renames: &[(&str, &str)], options: RenameOptions) -> (MutablePair, RenameReport) {
+    let mut tree = parse_default(INPUT).unwrap();
+    let report = rename_tags(&mut tree, &mapping(renames), options);
+    (tree, report)
+}
+
+/// The text written for the SAS events of walking `tree`
+fn events(tree: &MutablePair) -> String {
+    let mut writer = StarWriterHandler::new(Vec::new());
+    StarWalker::from_input(&mut writer, "").walk_star_tree_buffered(tree);
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+#[test]
+fn test_items_and_loop_names_are_renamed_and_reported() {
+    let (tree, report) = rename(
+        &[
+            (
+                "_nef_nmr_meta_data.program_name",
+                "_nef_nmr_meta_data.software_name",
+            ),
+            ("_NEF_CHEMICAL_SHIFT.VALUE", "_nef_chemical_shift.shift"),
+            ("_inner.value", "_inner.label"),
+            ("_missing.tag", "_found.tag"),
+            ("_also_missing", "_still_missing"),
+        ],
+        RenameOptions::default(),
+    );
+
+    assert_eq!(
+        report.applied,
+        [
+            AppliedRename {
+                from: "_nef_nmr_meta_data.program_name".to_string(),
+                to: "_nef_nmr_meta_data.software_name".to_string(),
+                position: LineColumn::new(5, 4),
+                start: 120,
+                end: 151,
+            },
+            AppliedRename {
+                from: "_nef_chemical_shift.value".to_string(),
+                to: "_nef_chemical_shift.shift".to_string(),
+                position: LineColumn::new(10, 39),
+                start: 315,
+                end: 340,
+            },
+            AppliedRename {
+                from: "_inner.value".to_string(),
+                to: "_inner.label".to_string(),
+                position: LineColumn::new(19, 19),
+                start: 464,
+                end: 476,
+            },
+        ]
+    );
+    for applied in &report.applied {
+        assert_eq!(&INPUT[applied.start..applied.end], applied.from);
+    }
+    assert_eq!(report.unmatched, ["_also_missing", "_missing.tag"]);
+
+    let names: Vec<&str> = tree
+        .find_all("data_name")
+        .into_iter()
+        .map(|name| name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "_nef_nmr_meta_data.sf_category",
+            "_nef_nmr_meta_data.software_name",
+            "_nef_chemical_shift_list.sf_category",
+            "_nef_chemical_shift.atom_name",
+            "_nef_chemical_shift.shift",
+            "_outer.id",
+            "_outer.name",
+            "_inner.id",
+            "_inner.label",
+        ]
+    );
+}
+
+#[test]
+fn test_case_sensitive_names_must_match_exactly() {
+    let options = RenameOptions {
+        case: CaseSensitivity::Sensitive,
+        ..RenameOptions::default()
+    };
+    let (_, report) = rename(
+        &[
+            ("_Outer.id", "_outer.index"),
+            ("_outer.name", "_outer.label"),
+        ],
+        options,
+    );
+    assert_eq!(report.applied.len(), 1);
+    assert_eq!(report.applied[0].from, "_outer.name");
+    assert_eq!(report.unmatched, ["_Outer.id"]);
+}
+
+#[test]
+fn test_categories_are_renamed_with_their_sf_category() {
+    let options = RenameOptions {
+        rename_categories: true,
+        ..RenameOptions::default()
+    };
+    let (tree, report) = rename(
+        &[
+            ("_nef_nmr_meta_data", "_nef_metadata"),
+            ("_nef_chemical_shift", "_nef_shift"),
+            ("_outer.id", "_outer.index"),
+        ],
+        options,
+    );
+    assert!(report.unmatched.is_empty());
+
+    let written = events(&tree);
+    assert!(written.contains(indoc! {"
+        save_nef_nmr_meta_data
+            _nef_metadata.sf_category  nef_metadata
+            _nef_metadata.program_name  'old program'
+        save_
+    "}));
+    // _nef_chemical_shift_list is another category
+    assert!(written.contains("_nef_chemical_shift_list.sf_category  nef_chemical_shift_list\n"));
+    assert!(written.contains("_nef_shift.atom_name\n        _nef_shift.value\n"));
+    assert!(written.contains("_outer.index\n"));
+
+    let applied: Vec<(&str, &str)> = report
+        .applied
+        .iter()
+        .map(|applied| (applied.from.as_str(), applied.to.as_str()))
+        .collect();
+    assert_eq!(
+        &applied[..3],
+        [
+            (
+                "_nef_nmr_meta_data.sf_category",
+                "_nef_metadata.sf_category"
+            ),
+            ("nef_nmr_meta_data", "nef_metadata"),
+            (
+                "_nef_nmr_meta_data.program_name",
+                "_nef_metadata.program_name"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_untouched_content_round_trips_byte_for_byte() {
+    let renames = [
+        (
+            "_nef_nmr_meta_data.program_name",
+            "_nef_nmr_meta_data.software_name",
+        ),
+        ("_nef_chemical_shift.value", "_nef_chemical_shift.shift"),
+        ("_inner.value", "_inner.label"),
+    ];
+    let (tree, report) = rename(&renames, RenameOptions::default());
+
+    let mut expected = INPUT.to_string();
+    for (from, to) in renames {
+        expected = expected.replace(from, to);
+    }
+    let renamed = report.apply_to(INPUT);
+    assert_eq!(renamed, expected);
+
+    // the renamed text parses to the renamed tree, and writes as it does
+    let reparsed = parse_default(&renamed).unwrap();
+    assert_eq!(events(&reparsed), events(&tree));
+
+    let mut written = events(&parse_default(INPUT).unwrap());
+    for (from, to) in renames {
+        written = written.replace(from, to);
+    }
+    assert_eq!(events(&tree), written);
+
+    // with nothing to rename the input comes back unchanged
+    let (_, report) = rename(&[], RenameOptions::default());
+    assert_eq!(report, RenameReport::default());
+    assert_eq!(report.apply_to(INPUT), INPUT);
+}
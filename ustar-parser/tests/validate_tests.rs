@@ -0,0 +1,227 @@
+use indoc::indoc;
+use ustar::line_column_index::LineColumn;
+use ustar::validate::{validate_tree, ValidationIssue, ValidationIssueKind};
+use ustar::{default_config, parse, parse_default, ConfigKey, ConfigValue, ErrorFormatMode};
+
+fn issues(input: &str) -> Vec<ValidationIssue> {
+    validate_tree(&parse_default(input).unwrap())
+}
+
+#[test]
+fn test_valid_file_has_no_issues() {
+    let input = indoc! {"
+        data_test
+        _entry.id  1
+        _entry.frame  $first
+        save_first
+        _entry.id  2
+        loop_
+        _row.id
+        _row.value
+        1 a
+        stop_
+        save_
+        save_second
+        _entry.id  3
+        _frame.link  $FIRST
+        save_
+    "};
+
+    assert_eq!(issues(input), vec![]);
+}
+
+#[test]
+fn test_duplicate_tags_outside_loops() {
+    let input = indoc! {"
+        data_test
+        _entry.id  1
+        _entry.title  first
+        _ENTRY.ID  2
+    "};
+
+    assert_eq!(
+        issues(input),
+        vec![ValidationIssue {
+            kind: ValidationIssueKind::DuplicateTag,
+            name: "_ENTRY.ID".to_string(),
+            position: LineColumn::new(4, 1),
+            first_position: Some(LineColumn::new(2, 1)),
+            detail: None,
+        }]
+    );
+}
+
+#[test]
+fn test_duplicate_tags_inside_loops() {
+    let input = indoc! {"
+        data_test
+        loop_
+        _row.id
+        _row.value
+        _row.id
+        1 a 2
+        stop_
+    "};
+
+    let found = issues(input);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].kind, ValidationIssueKind::DuplicateTag);
+    assert_eq!(found[0].position, LineColumn::new(5, 1));
+    assert_eq!(found[0].first_position, Some(LineColumn::new(3, 1)));
+}
+
+#[test]
+fn test_duplicate_tags_between_loop_and_item() {
+    let input = indoc! {"
+        data_test
+        _row.id  1
+        loop_
+        _row.id
+        _row.value
+        1 a
+        stop_
+        loop_
+        _row.value
+        b
+        stop_
+    "};
+
+    let found = issues(input);
+    let positions: Vec<_> = found
+        .iter()
+        .map(|issue| (issue.name.as_str(), issue.position, issue.first_position))
+        .collect();
+    assert_eq!(
+        positions,
+        vec![
+            (
+                "_row.id",
+                LineColumn::new(4, 1),
+                Some(LineColumn::new(2, 1))
+            ),
+            (
+                "_row.value",
+                LineColumn::new(9, 1),
+                Some(LineColumn::new(5, 1))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_save_frames_and_blocks_are_separate_tag_scopes() {
+    let input = indoc! {"
+        data_first
+        _entry.id  1
+        save_one
+        _entry.id  2
+        save_
+        save_two
+        _entry.id  3
+        save_
+
+        data_second
+        _entry.id  4
+    "};
+
+    assert_eq!(issues(input), vec![]);
+}
+
+#[test]
+fn test_duplicate_save_frames_are_case_insensitive() {
+    let input = indoc! {"
+        data_test
+        save_Assembly
+        _entry.id  1
+        save_
+        save_ASSEMBLY
+        _entry.id  2
+        save_
+    "};
+
+    assert_eq!(
+        issues(input),
+        vec![ValidationIssue {
+            kind: ValidationIssueKind::DuplicateSaveframe,
+            name: "ASSEMBLY".to_string(),
+            position: LineColumn::new(5, 1),
+            first_position: Some(LineColumn::new(2, 1)),
+            detail: None,
+        }]
+    );
+}
+
+#[test]
+fn test_save_frame_names_may_repeat_in_other_blocks() {
+    let input = indoc! {"
+        data_first
+        save_frame
+        _entry.id  1
+        save_
+
+        data_second
+        save_frame
+        _entry.id  2
+        save_
+    "};
+
+    assert_eq!(issues(input), vec![]);
+}
+
+#[test]
+fn test_dangling_frame_codes() {
+    let input = indoc! {"
+        data_first
+        save_frame
+        _entry.id  1
+        save_
+
+        data_second
+        _entry.link  $frame
+    "};
+
+    assert_eq!(
+        issues(input),
+        vec![ValidationIssue {
+            kind: ValidationIssueKind::DanglingFrameCode,
+            name: "$frame".to_string(),
+            position: LineColumn::new(7, 14),
+            first_position: None,
+            detail: None,
+        }]
+    );
+}
+
+#[test]
+fn test_parse_only_validates_when_enabled() {
+    let input = indoc! {"
+        data_test
+        _entry.id  1
+        _entry.id  2
+    "};
+    assert!(parse(input, &default_config()).is_ok());
+
+    let mut config = default_config();
+    config.insert(ConfigKey::Validate, ConfigValue::Bool(true));
+    let error = parse(input, &config)
+        .unwrap_err()
+        .format_error(ErrorFormatMode::Basic, 0);
+
+    assert!(error.starts_with("Parse error at l3:c1"), "{error}");
+    assert!(
+        error.contains("duplicate data name _entry.id, first defined at l2:c1"),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_nmr_star_entries_validate() {
+    for entry in std::fs::read_dir("tests/test_data/bmrb_stars").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "str") {
+            let input = std::fs::read_to_string(&path).unwrap();
+            let found = issues(&input);
+            assert!(found.is_empty(), "{}: {}", path.display(), found[0]);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use indoc::indoc;
+use proptest::prelude::*;
+use rstest::rstest;
+use ustar::parse_default;
+use ustar::values::StarValue;
+
+fn uncertain(value: f64, uncertainty: f64) -> StarValue {
+    StarValue::FloatWithUncertainty { value, uncertainty }
+}
+
+#[rstest]
+#[case("42", StarValue::Int(42))]
+#[case("-7", StarValue::Int(-7))]
+#[case("+7", StarValue::Int(7))]
+#[case("1.5", StarValue::Float(1.5))]
+#[case(".5", StarValue::Float(0.5))]
+#[case("5.", StarValue::Float(5.0))]
+#[case("1.3324838E+01", StarValue::Float(13.324838))]
+#[case("1e3", StarValue::Float(1000.0))]
+#[case("99999999999999999999", StarValue::Float(1e20))]
+#[case("1.007825031898(14)", uncertain(1.007825031898, 1.4e-11))]
+#[case("123(4)", uncertain(123.0, 4.0))]
+#[case("-0.0214(7)", uncertain(-0.0214, 0.0007))]
+#[case("1.23E-5(4)", uncertain(1.23e-5, 4e-7))]
+#[case("2.0152720-01", StarValue::Float(0.2015272))]
+#[case("1.0+100", StarValue::Float(1e100))]
+#[case(".", StarValue::Null)]
+#[case("?", StarValue::Unknown)]
+#[case("$frame_1", StarValue::FrameCode("frame_1".to_string()))]
+#[case("'1'", StarValue::Text("1".to_string()))]
+#[case("'.'", StarValue::Text(".".to_string()))]
+#[case("\"a b\"", StarValue::Text("a b".to_string()))]
+#[case("\n;line 1\nline 2\n;", StarValue::Text("line 1\nline 2".to_string()))]
+#[case("1901-1906", StarValue::Text("1901-1906".to_string()))]
+#[case("1.2.3", StarValue::Text("1.2.3".to_string()))]
+#[case("1.5(x)", StarValue::Text("1.5(x)".to_string()))]
+#[case("1.5e", StarValue::Text("1.5e".to_string()))]
+#[case("1.0e400", StarValue::Text("1.0e400".to_string()))]
+#[case("-1.0e400(5)", StarValue::Text("-1.0e400(5)".to_string()))]
+#[case("-", StarValue::Text("-".to_string()))]
+#[case("hydrogen", StarValue::Text("hydrogen".to_string()))]
+fn test_parse(#[case] token: &str, #[case] expected: StarValue) {
+    assert_eq!(StarValue::parse(token), expected);
+}
+
+#[rstest]
+#[case(StarValue::Text("1".to_string()), "'1'")]
+#[case(StarValue::Text("a b".to_string()), "'a b'")]
+#[case(StarValue::Text("it's here".to_string()), "'it's here'")]
+#[case(StarValue::Text("don' t".to_string()), "\"don' t\"")]
+#[case(StarValue::Text("_name".to_string()), "'_name'")]
+#[case(StarValue::Text("loop_".to_string()), "'loop_'")]
+#[case(StarValue::Text(String::new()), "''")]
+#[case(StarValue::Text("two\nlines".to_string()), "\n;two\nlines\n;")]
+#[case(StarValue::Text("plain".to_string()), "plain")]
+#[case(uncertain(1.007825031898, 1.4e-11), "1.007825031898(14)")]
+#[case(uncertain(1200.0, 300.0), "12E2(3)")]
+#[case(uncertain(0.0, 1.9e19), "0E18(19)")]
+#[case(StarValue::Float(2.0), "2.0")]
+fn test_display(#[case] value: StarValue, #[case] expected: &str) {
+    assert_eq!(value.to_string(), expected);
+    assert_eq!(StarValue::parse(expected), value);
+}
+
+#[test]
+fn test_typed_values_of_items_and_loop_columns() {
+    let input = indoc! {"
+        data_test
+        _entry.mass  1.007825031898(14)
+        _entry.name  'water'
+        loop_
+        _atom.id
+        _atom.shift
+        # a comment between rows
+        1 4.75
+        2 .
+        3 ?
+        stop_
+    "};
+    let tree = parse_default(input).unwrap();
+
+    assert_eq!(
+        tree.typed_values("_entry.mass"),
+        vec![uncertain(1.007825031898, 1.4e-11)]
+    );
+    assert_eq!(
+        tree.typed_values("_ENTRY.NAME"),
+        vec![StarValue::Text("water".to_string())]
+    );
+    assert_eq!(
+        tree.typed_values("_atom.shift"),
+        vec![StarValue::Float(4.75), StarValue::Null, StarValue::Unknown]
+    );
+    assert_eq!(
+        tree.typed_values("_atom.id"),
+        vec![StarValue::Int(1), StarValue::Int(2), StarValue::Int(3)]
+    );
+    assert_eq!(tree.typed_values("_missing"), vec![]);
+}
+
+#[test]
+fn test_typed_values_of_a_bmrb_entry() {
+    let input = std::fs::read_to_string("tests/test_data/bmrb_stars/bmr10097_3.str").unwrap();
+    let tree = parse_default(&input).unwrap();
+
+    let shifts = tree.typed_values("_Atom_chem_shift.Val");
+    assert!(!shifts.is_empty());
+    assert!(shifts.iter().all(|shift| shift.as_f64().is_some()));
+}
+
+proptest! {
+    #[test]
+    fn test_integers_round_trip(value in any::<i64>()) {
+        let token = StarValue::Int(value).to_string();
+        prop_assert_eq!(StarValue::parse(&token), StarValue::Int(value));
+    }
+
+    #[test]
+    fn test_floats_round_trip(value in any::<f64>().prop_filter("finite", |v| v.is_finite())) {
+        let token = StarValue::Float(value).to_string();
+        prop_assert_eq!(StarValue::parse(&token), StarValue::Float(value));
+    }
+
+    #[test]
+    fn test_written_numbers_round_trip(
+        negative in any::<bool>(),
+        integer in 0u64..1_000_000,
+        fraction in proptest::option::of("[0-9]{1,8}"),
+        exponent in proptest::option::of(-30i32..30),
+        uncertainty in proptest::option::of(1u32..100),
+    ) {
+        let sign = if negative { "-" } else { "" };
+        let fraction = fraction.map(|digits| format!(".{digits}")).unwrap_or_default();
+        let exponent = exponent.map(|e| format!("E{e}")).unwrap_or_default();
+        let uncertainty = uncertainty.map(|u| format!("({u})")).unwrap_or_default();
+        let token = format!("{sign}{integer}{fraction}{exponent}{uncertainty}");
+
+        let value = StarValue::parse(&token);
+        prop_assert!(value.as_f64().is_some(), "{} parsed as {:?}", token, value);
+        prop_assert_eq!(StarValue::parse(&value.to_string()), value);
+    }
+
+    #[test]
+    fn test_numbers_too_large_for_a_float_round_trip_as_text(
+        negative in any::<bool>(),
+        mantissa in "[1-9]\\.[0-9]{0,8}",
+        exponent in 309i32..2000,
+        uncertainty in proptest::option::of(1u32..100),
+    ) {
+        let sign = if negative { "-" } else { "" };
+        let uncertainty = uncertainty.map(|u| format!("({u})")).unwrap_or_default();
+        let token = format!("{sign}{mantissa}e{exponent}{uncertainty}");
+
+        let value = StarValue::parse(&token);
+        prop_assert_eq!(&value, &StarValue::Text(token));
+        prop_assert_eq!(StarValue::parse(&value.to_string()), value);
+    }
+
+    #[test]
+    fn test_fortran_exponents_match_e_notation(
+        mantissa in "[0-9]\\.[0-9]{1,8}",
+        negative_exponent in any::<bool>(),
+        exponent in 0u32..100,
+    ) {
+        let sign = if negative_exponent { "-" } else { "+" };
+        prop_assert_eq!(
+            StarValue::parse(&format!("{mantissa}{sign}{exponent:02}")),
+            StarValue::parse(&format!("{mantissa}E{sign}{exponent:02}"))
+        );
+    }
+
+    #[test]
+    fn test_text_round_trips(text in "[ -~]{0,20}") {
+        let value = StarValue::Text(text);
+        prop_assert_eq!(StarValue::parse(&value.to_string()), value);
+    }
+}
@@ -0,0 +1,53 @@
+//! Run with `wasm-pack test --node ustar-parser -- --no-default-features --features
+//! extended-errors,wasm`
+
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use ustar::wasm::{check, parse_to_json};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const NEF_FRAGMENT: &str = "data_nef_test\n\
+                            save_nef_nmr_meta_data\n\
+                            _nef_nmr_meta_data.sf_category nef_nmr_meta_data\n\
+                            _nef_nmr_meta_data.format_version 1.1\n\
+                            save_\n";
+
+/// The value of `key` in a JavaScript object
+fn get(object: &JsValue, key: &str) -> JsValue {
+    js_sys::Reflect::get(object, &JsValue::from_str(key)).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn test_parse_to_json_gives_the_tree() {
+    let json = parse_to_json(NEF_FRAGMENT, JsValue::UNDEFINED).unwrap();
+    let tree = ustar::mutable_pair::MutablePair::from_json(&json).unwrap();
+
+    assert_eq!(tree.rule_name.as_ref(), "star_file");
+    assert_eq!(tree.find_all("save_heading").len(), 1);
+    assert!(check(NEF_FRAGMENT).is_null());
+}
+
+#[wasm_bindgen_test]
+fn test_failing_parse_gives_the_error_position_and_message() {
+    let input = "data_test\n_item\n";
+
+    let error = check(input);
+    assert_eq!(get(&error, "line").as_f64(), Some(3.0));
+    assert_eq!(get(&error, "column").as_f64(), Some(1.0));
+    assert!(get(&error, "message").as_string().is_some());
+
+    let thrown = parse_to_json(input, JsValue::NULL).unwrap_err();
+    assert_eq!(get(&thrown, "code"), get(&error, "code"));
+}
+
+#[wasm_bindgen_test]
+fn test_options_configure_the_parse() {
+    let options = js_sys::JSON::parse(r#"{"cif_version": "cif2"}"#).unwrap();
+    assert!(parse_to_json("data_test\n_list [1 2]\n", options).is_ok());
+
+    let options = js_sys::JSON::parse(r#"{"encoding": "klingon"}"#).unwrap();
+    assert!(parse_to_json(NEF_FRAGMENT, options)
+        .unwrap_err()
+        .is_instance_of::<js_sys::Error>());
+}
@@ -0,0 +1,344 @@
+//! Python bindings - the `ustar` module, parsing STAR text to dictionaries and walking it with a
+//! Python handler
+//!
+//! Build and install into the current Python environment with
+//! `maturin develop -m ustar-py/Cargo.toml`, then run the tests with `pytest ustar-py/tests`.
+//!
+//! Options are keyword arguments naming configuration keys, as read by
+//! `ConfigFile::from_json_str`, such as `parse(text, encoding="unicode", cif_version="cif2")`.
+//! `parse` releases the GIL while it parses, and converts the JSON `JsonHandler` writes with
+//! Python's `json` module, which is much quicker than building the objects a value at a time
+//! through the C API. `walk` holds the GIL throughout, as every event is a call into Python; the
+//! handler's methods are looked up once before the walk rather than for each event.
+
+use pyo3::call::PyCallArgs;
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use ustar_parser::line_column_index::LineColumn;
+use ustar_parser::sas_handlers::JsonHandler;
+use ustar_parser::sas_interface::{
+    SASContentHandler, StreamCheckpoint, ValueDelimiter, WalkControl,
+};
+use ustar_parser::{default_config, ConfigFile, ParserConfig, UstarError};
+
+create_exception!(
+    ustar,
+    ParseError,
+    PyValueError,
+    "Text that isn't valid STAR, with the `line` and `column` of the error, its `code`, \
+     `message` and byte `offset`"
+);
+
+/// What the walk does after a handler method returns, `None` is `WalkControl.CONTINUE`
+#[pyclass(
+    name = "WalkControl",
+    module = "ustar",
+    eq,
+    eq_int,
+    frozen,
+    from_py_object
+)]
+#[derive(Clone, Copy, PartialEq)]
+enum PyWalkControl {
+    /// Carry on walking
+    #[pyo3(name = "CONTINUE")]
+    Continue,
+    /// From a start method or loop_definition, don't walk the contents of what was just started
+    #[pyo3(name = "SKIP_SUBTREE")]
+    SkipSubtree,
+    /// End the walk
+    #[pyo3(name = "STOP")]
+    Stop,
+}
+
+impl From<PyWalkControl> for WalkControl {
+    fn from(control: PyWalkControl) -> Self {
+        match control {
+            PyWalkControl::Continue => Self::Continue,
+            PyWalkControl::SkipSubtree => Self::SkipSubtree,
+            PyWalkControl::Stop => Self::Stop,
+        }
+    }
+}
+
+/// Parse STAR text to a dict of its blocks, as JSON written by the ustar JsonHandler reads
+///
+/// Each block, `global_` or `data_<name>`, maps its items as `tag: value`, its save frames as
+/// `save_<name>: {...}` and its loops as `loop_1: [...]` and so on, a list of rows keyed by tag.
+/// Values are strings, apart from the unquoted `.` which is `None`. Raises `ParseError` if the
+/// text isn't valid STAR, or `ValueError` for an unknown option.
+#[pyfunction]
+#[pyo3(signature = (text, **options))]
+fn parse<'py>(
+    py: Python<'py>,
+    text: &str,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let config = config_from(py, options)?;
+    let json = py
+        .detach(|| {
+            let mut handler = JsonHandler::buffered();
+            ustar_parser::walk(text, &config, &mut handler).map(|_| handler.into_inner())
+        })
+        .map_err(|error| parse_error(py, &error))??;
+    py.import("json")?
+        .call_method1("loads", (PyBytes::new(py, &json),))
+}
+
+/// Walk STAR text, calling the methods of `handler` named as those of the SAS content handler
+///
+/// Positions are `(line, column)` tuples and the methods called are `start_stream(name)`,
+/// `end_stream(position)`, `start_global(position)`, `end_global(position)`,
+/// `start_data(position, name)`, `end_data(position, name)`, `start_saveframe(position, name)`,
+/// `end_saveframe(position, name)`, `start_loop(position)`, `end_loop(position)`,
+/// `loop_definition(position, tags, nesting_level)`, `start_loop_row(position, row_index)`,
+/// `end_loop_row(position, row_index)`, `comment(position, text)`,
+/// `element_comments(comments)`, `checkpoint(byte_offset, position, path)` and
+/// `data(tag, tag_position, value, value_position, delimiter, loop_level)`, where delimiter is
+/// the opening delimiter as written, "" for none. Methods the handler doesn't have are skipped,
+/// and each returns `None` or a `WalkControl`. Returns whether the handler stopped the walk;
+/// an exception raised by a method stops it and is raised again.
+#[pyfunction]
+#[pyo3(signature = (text, handler, **options))]
+fn walk<'py>(
+    py: Python<'py>,
+    text: &str,
+    handler: &Bound<'py, PyAny>,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<bool> {
+    let config = config_from(py, options)?;
+    let mut handler = PythonHandler::new(handler)?;
+    let walked = ustar_parser::walk(text, &config, &mut handler);
+    if let Some(error) = handler.error {
+        return Err(error);
+    }
+    walked.map_err(|error| parse_error(py, &error))
+}
+
+#[pymodule]
+fn ustar(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_function(wrap_pyfunction!(walk, module)?)?;
+    module.add_class::<PyWalkControl>()?;
+    module.add("ParseError", module.py().get_type::<ParseError>())?;
+    Ok(())
+}
+
+/// The configuration given by keyword options, the default without any (private)
+fn config_from(py: Python<'_>, options: Option<&Bound<'_, PyDict>>) -> PyResult<ParserConfig> {
+    let Some(options) = options.filter(|options| !options.is_empty()) else {
+        return Ok(default_config());
+    };
+    let json: String = py
+        .import("json")?
+        .call_method1("dumps", (options,))?
+        .extract()?;
+    ParserConfig::from_json_str(&json).map_err(|error| PyValueError::new_err(error.to_string()))
+}
+
+/// An error as a `ParseError` with its position, code and message (private)
+fn parse_error(py: Python<'_>, error: &UstarError) -> PyErr {
+    let core = error.core();
+    let exception = ParseError::new_err(core.format_basic().trim_end().to_string());
+    let value = exception.value(py);
+    let attributes = value
+        .setattr("code", error.code().as_str())
+        .and_then(|_| value.setattr("message", &core.message))
+        .and_then(|_| value.setattr("line", core.line))
+        .and_then(|_| value.setattr("column", core.col))
+        .and_then(|_| value.setattr("offset", core.offset));
+    match attributes {
+        Ok(()) => exception,
+        Err(error) => error,
+    }
+}
+
+fn position(position: LineColumn) -> (usize, usize) {
+    (position.line, position.column)
+}
+
+/// Calls the methods of a Python handler for the events of a walk, keeping the first exception
+/// one raises (private)
+struct PythonHandler<'py> {
+    start_stream: Option<Bound<'py, PyAny>>,
+    end_stream: Option<Bound<'py, PyAny>>,
+    start_global: Option<Bound<'py, PyAny>>,
+    end_global: Option<Bound<'py, PyAny>>,
+    start_data: Option<Bound<'py, PyAny>>,
+    end_data: Option<Bound<'py, PyAny>>,
+    start_saveframe: Option<Bound<'py, PyAny>>,
+    end_saveframe: Option<Bound<'py, PyAny>>,
+    start_loop: Option<Bound<'py, PyAny>>,
+    end_loop: Option<Bound<'py, PyAny>>,
+    loop_definition: Option<Bound<'py, PyAny>>,
+    start_loop_row: Option<Bound<'py, PyAny>>,
+    end_loop_row: Option<Bound<'py, PyAny>>,
+    comment: Option<Bound<'py, PyAny>>,
+    element_comments: Option<Bound<'py, PyAny>>,
+    checkpoint: Option<Bound<'py, PyAny>>,
+    data: Option<Bound<'py, PyAny>>,
+    error: Option<PyErr>,
+}
+
+impl<'py> PythonHandler<'py> {
+    fn new(handler: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let method = |name: &str| handler.getattr_opt(name);
+        Ok(PythonHandler {
+            start_stream: method("start_stream")?,
+            end_stream: method("end_stream")?,
+            start_global: method("start_global")?,
+            end_global: method("end_global")?,
+            start_data: method("start_data")?,
+            end_data: method("end_data")?,
+            start_saveframe: method("start_saveframe")?,
+            end_saveframe: method("end_saveframe")?,
+            start_loop: method("start_loop")?,
+            end_loop: method("end_loop")?,
+            loop_definition: method("loop_definition")?,
+            start_loop_row: method("start_loop_row")?,
+            end_loop_row: method("end_loop_row")?,
+            comment: method("comment")?,
+            element_comments: method("element_comments")?,
+            checkpoint: method("checkpoint")?,
+            data: method("data")?,
+            error: None,
+        })
+    }
+}
+
+/// Call `method`, if the handler has it, with `args`, stopping the walk with the exception it
+/// raises or a return value that isn't `None` or a `WalkControl` kept in `error`
+fn call<'py>(
+    error: &mut Option<PyErr>,
+    method: &Option<Bound<'py, PyAny>>,
+    args: impl PyCallArgs<'py>,
+) -> WalkControl {
+    let Some(method) = method else {
+        return WalkControl::Continue;
+    };
+    let control = method.call1(args).and_then(|returned| {
+        if returned.is_none() {
+            Ok(WalkControl::Continue)
+        } else {
+            Ok(returned.extract::<PyWalkControl>()?.into())
+        }
+    });
+    control.unwrap_or_else(|exception| {
+        *error = Some(exception);
+        WalkControl::Stop
+    })
+}
+
+impl SASContentHandler for PythonHandler<'_> {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        call(&mut self.error, &self.start_stream, (name,))
+    }
+
+    fn end_stream(&mut self, at: LineColumn) -> WalkControl {
+        call(&mut self.error, &self.end_stream, (position(at),))
+    }
+
+    fn start_global(&mut self, at: LineColumn) -> WalkControl {
+        call(&mut self.error, &self.start_global, (position(at),))
+    }
+
+    fn end_global(&mut self, at: LineColumn) -> WalkControl {
+        call(&mut self.error, &self.end_global, (position(at),))
+    }
+
+    fn start_data(&mut self, at: LineColumn, name: &str) -> WalkControl {
+        call(&mut self.error, &self.start_data, (position(at), name))
+    }
+
+    fn end_data(&mut self, at: LineColumn, name: &str) -> WalkControl {
+        call(&mut self.error, &self.end_data, (position(at), name))
+    }
+
+    fn start_saveframe(&mut self, at: LineColumn, name: &str) -> WalkControl {
+        call(&mut self.error, &self.start_saveframe, (position(at), name))
+    }
+
+    fn end_saveframe(&mut self, at: LineColumn, name: &str) -> WalkControl {
+        call(&mut self.error, &self.end_saveframe, (position(at), name))
+    }
+
+    fn start_loop(&mut self, at: LineColumn) -> WalkControl {
+        call(&mut self.error, &self.start_loop, (position(at),))
+    }
+
+    fn end_loop(&mut self, at: LineColumn) -> WalkControl {
+        call(&mut self.error, &self.end_loop, (position(at),))
+    }
+
+    fn loop_definition(&mut self, at: LineColumn, tags: &[&str], level: usize) -> WalkControl {
+        let args = (position(at), tags.to_vec(), level);
+        call(&mut self.error, &self.loop_definition, args)
+    }
+
+    fn start_loop_row(&mut self, at: LineColumn, row_index: usize) -> WalkControl {
+        call(
+            &mut self.error,
+            &self.start_loop_row,
+            (position(at), row_index),
+        )
+    }
+
+    fn end_loop_row(&mut self, at: LineColumn, row_index: usize) -> WalkControl {
+        call(
+            &mut self.error,
+            &self.end_loop_row,
+            (position(at), row_index),
+        )
+    }
+
+    fn comment(&mut self, at: LineColumn, text: &str) -> WalkControl {
+        call(&mut self.error, &self.comment, (position(at), text))
+    }
+
+    fn element_comments(&mut self, comments: &[(LineColumn, &str)]) -> WalkControl {
+        if self.element_comments.is_none() {
+            for &(at, text) in comments {
+                if self.comment(at, text) == WalkControl::Stop {
+                    return WalkControl::Stop;
+                }
+            }
+            return WalkControl::Continue;
+        }
+        let comments: Vec<_> = comments
+            .iter()
+            .map(|&(at, text)| (position(at), text))
+            .collect();
+        call(&mut self.error, &self.element_comments, (comments,))
+    }
+
+    fn checkpoint(&mut self, checkpoint: &StreamCheckpoint) -> WalkControl {
+        let args = (
+            checkpoint.byte_offset,
+            (checkpoint.line, checkpoint.column),
+            checkpoint.path.clone(),
+        );
+        call(&mut self.error, &self.checkpoint, args)
+    }
+
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        let args = (
+            tag,
+            position(tag_position),
+            value,
+            position(value_position),
+            delimiter.as_str(),
+            loop_level,
+        );
+        call(&mut self.error, &self.data, args)
+    }
+}
@@ -0,0 +1,140 @@
+"""Tests of the ustar Python bindings, run with `maturin develop -m ustar-py/Cargo.toml` and then
+`pytest ustar-py/tests`"""
+
+from pathlib import Path
+
+import pytest
+
+import ustar
+
+NEF_EXAMPLE = (
+    Path(__file__).resolve().parents[2]
+    / "ustar-parser"
+    / "tests"
+    / "test_data"
+    / "nef_examples"
+    / "1pqx.nef"
+)
+
+
+class EventCollector:
+    """A handler keeping the name and arguments of each event"""
+
+    def __init__(self):
+        self.events = []
+
+    def __getattr__(self, name):
+        if name.startswith("__"):
+            raise AttributeError(name)
+        return lambda *args: self.events.append((name, *args))
+
+
+def test_parse_nef_example():
+    document = ustar.parse(NEF_EXAMPLE.read_text())
+
+    block = document["data_PDBStat_converted_file"]
+    meta_data = block["save_nef_nmr_meta_data"]
+    assert meta_data["_nef_nmr_meta_data.format_version"] == "1.1"
+    assert meta_data["_nef_nmr_meta_data.coordinate_file_name"] is None
+
+    sequence = block["save_nef_molecular_system"]["loop_1"]
+    assert sequence[0]["_nef_sequence.residue_name"] == "MET"
+    assert sequence[0]["_nef_sequence.linking"] == "start"
+    assert list(block)[:2] == ["save_nef_nmr_meta_data", "save_nef_molecular_system"]
+
+
+def test_parse_options():
+    document = ustar.parse("data_test\n_list [1 2]\n", cif_version="cif2")
+    assert document == {"data_test": {"_list": "1 2"}}
+
+    with pytest.raises(ValueError):
+        ustar.parse("data_test\n", encoding="klingon")
+
+
+def test_parse_error_has_its_position():
+    with pytest.raises(ustar.ParseError) as raised:
+        ustar.parse("data_test\n_item\n")
+
+    error = raised.value
+    assert (error.line, error.column) == (3, 1)
+    assert error.code.startswith("E")
+    assert error.message
+    assert isinstance(error, ValueError)
+
+
+def test_walk_calls_the_handler_for_each_event():
+    collector = EventCollector()
+    text = "data_test\nloop_ _a _b\n1 'x'\n# note\nstop_\n"
+
+    assert ustar.walk(text, collector) is False
+
+    names = [event[0] for event in collector.events]
+    assert names[0] == "start_stream"
+    assert names[-1] == "end_stream"
+    assert ("start_data", (1, 1), "test") in collector.events
+    assert ("loop_definition", (2, 1), ["_a", "_b"], 1) in collector.events
+    assert ("data", "_b", (2, 10), "x", (3, 4), "'", 1) in collector.events
+    assert ("comment", (4, 1), "# note") in collector.events
+    assert names.index("start_loop") < names.index("start_loop_row") < names.index("end_loop")
+
+
+def test_walk_nef_example():
+    class SaveFrames:
+        def __init__(self):
+            self.names = []
+            self.values = 0
+
+        def start_saveframe(self, position, name):
+            self.names.append(name)
+
+        def data(self, tag, tag_position, value, value_position, delimiter, loop_level):
+            self.values += 1
+
+    handler = SaveFrames()
+    ustar.walk(NEF_EXAMPLE.read_text(), handler)
+
+    assert handler.names[:2] == ["nef_nmr_meta_data", "nef_molecular_system"]
+    assert len(handler.names) == 5
+    assert handler.values > 1000
+
+
+def test_walk_control_stops_and_skips():
+    text = "data_one\n_a 1\ndata_two\n_b 2\n"
+
+    class StopAtFirstValue:
+        def __init__(self):
+            self.tags = []
+
+        def data(self, tag, *args):
+            self.tags.append(tag)
+            return ustar.WalkControl.STOP
+
+    handler = StopAtFirstValue()
+    assert ustar.walk(text, handler) is True
+    assert handler.tags == ["_a"]
+
+    class SkipFirstBlock:
+        def __init__(self):
+            self.tags = []
+
+        def start_data(self, position, name):
+            return ustar.WalkControl.SKIP_SUBTREE if name == "one" else None
+
+        def data(self, tag, *args):
+            self.tags.append(tag)
+
+    handler = SkipFirstBlock()
+    assert ustar.walk(text, handler) is False
+    assert handler.tags == ["_b"]
+
+
+def test_walk_raises_handler_and_parse_errors():
+    class Failing:
+        def start_data(self, position, name):
+            raise KeyError(name)
+
+    with pytest.raises(KeyError):
+        ustar.walk("data_test\n_a 1\n", Failing())
+
+    with pytest.raises(ustar.ParseError):
+        ustar.walk("data_test\n_a\n", EventCollector())
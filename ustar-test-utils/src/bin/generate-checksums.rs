@@ -0,0 +1,35 @@
+//! Write `checksums.sha256` and `checksums.sha1` files for test data directories.
+//!
+//! Usage: generate-checksums <dir1> [dir2] ...
+
+use std::process::ExitCode;
+use ustar_test_utils::generate_checksums;
+
+fn main() -> ExitCode {
+    let dirs: Vec<String> = std::env::args().skip(1).collect();
+    if dirs.is_empty() || dirs.iter().any(|arg| arg == "-h" || arg == "--help") {
+        eprintln!("Usage: generate-checksums <directory1> [directory2] ...");
+        eprintln!();
+        eprintln!(
+            "Writes checksums.sha256 and checksums.sha1 for the test data files in each directory"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = false;
+    for dir in &dirs {
+        match generate_checksums(dir) {
+            Ok(count) => println!("{}: {} files", dir, count),
+            Err(e) => {
+                eprintln!("Error: {}: {}", dir, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
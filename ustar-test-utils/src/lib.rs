@@ -2,14 +2,27 @@
 //!
 //! This crate provides common testing functionality including
 //! support for gzip-compressed snapshot files, test data management,
-//! and mock HTTP clients for testing download functionality.
+//! mock HTTP clients for testing download functionality and a generator
+//! of valid STAR documents for property tests.
 
 mod mock_http_client;
 mod pest_format;
 mod snapshot_utils;
+mod star_generator;
 mod test_data_download_utils;
 
-pub use mock_http_client::MockHttpClient;
+pub use mock_http_client::{MockHttpClient, MockHttpError, MockResponse};
 pub use pest_format::format_pest_pair;
-pub use snapshot_utils::{assert_snapshot_gz, check_snapshot_gz, read_snapshot, SnapshotMismatch};
-pub use test_data_download_utils::{ensure_test_data_available, verify_test_data_checksums};
+pub use snapshot_utils::{
+    assert_snapshot_gz, assert_snapshot_gz_filtered, check_snapshot_gz, check_snapshot_gz_filtered,
+    check_unused_snapshots, prune_unused_snapshots, read_snapshot, redact_durations, redact_paths,
+    redact_timestamps, report_unused_snapshots, used_snapshots, SnapshotFilter, SnapshotMismatch,
+};
+pub use star_generator::{
+    star_document, star_value, tag, Delimiter, StarBlock, StarContent, StarDocument, StarItem,
+    StarLoop, StarSaveframe, StarValue,
+};
+pub use test_data_download_utils::{
+    ensure_test_data_available, generate_checksums, verify_test_data_checksums, TestDataError,
+    TestDataPolicy,
+};
@@ -1,30 +1,146 @@
 //! Mock HTTP client for testing download functionality
 
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A canned answer to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockResponse {
+    /// A successful response, text or binary such as a gzipped file
+    Body(Vec<u8>),
+    /// An unsuccessful HTTP status, such as 404 or 503
+    Status(u16),
+    /// A failure without a response, such as a timeout or a refused connection
+    Error(String),
+}
+
+impl MockResponse {
+    /// A successful text response
+    pub fn text(text: &str) -> Self {
+        MockResponse::Body(text.as_bytes().to_vec())
+    }
+}
+
+/// Answers the requests for the URLs matching a pattern
+type Handler = Box<dyn Fn(&str) -> MockResponse + Send + Sync>;
+
+/// A handler for the URLs matching a regular expression
+struct PatternResponse {
+    /// The glob or regex as given, whose length ranks overlapping patterns
+    source: String,
+    regex: Regex,
+    handler: Handler,
+}
 
 /// Mock HTTP client for testing with pre-recorded responses
 pub struct MockHttpClient {
-    responses: HashMap<String, String>,
-    binary_responses: HashMap<String, Vec<u8>>,
+    responses: HashMap<String, MockResponse>,
+    patterns: Vec<PatternResponse>,
+    /// Answers for the next requests of a URL, before its usual response
+    sequences: Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    /// URLs requested, in the order of the requests
+    requests: Mutex<Vec<String>>,
+    /// How long each request takes to answer, and how long those for some URLs take
+    latency: Duration,
+    delays: HashMap<String, Duration>,
+    /// Requests being answered now, and the most there have been at once
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// A failed mock request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockHttpError {
+    pub url: String,
+    /// The HTTP status a server would have sent, none for a failure without a response
+    pub status: Option<u16>,
+    pub message: String,
 }
 
+impl fmt::Display for MockHttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(404) => write!(f, "Mock: No response for URL: {}", self.url),
+            Some(status) => write!(f, "Mock: HTTP {} for URL: {}", status, self.url),
+            None => write!(f, "Mock: {} for URL: {}", self.message, self.url),
+        }
+    }
+}
+
+impl std::error::Error for MockHttpError {}
+
 impl MockHttpClient {
     pub fn new() -> Self {
         Self {
             responses: HashMap::new(),
-            binary_responses: HashMap::new(),
+            patterns: Vec::new(),
+            sequences: Mutex::new(HashMap::new()),
+            requests: Mutex::new(Vec::new()),
+            latency: Duration::ZERO,
+            delays: HashMap::new(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
         }
     }
 
     /// Add a text response for a URL
     pub fn with_response(mut self, url: &str, response: &str) -> Self {
-        self.responses.insert(url.to_string(), response.to_string());
+        self.responses
+            .insert(url.to_string(), MockResponse::text(response));
         self
     }
 
     /// Add a binary response for a URL
     pub fn with_binary_response(mut self, url: &str, response: Vec<u8>) -> Self {
-        self.binary_responses.insert(url.to_string(), response);
+        self.responses
+            .insert(url.to_string(), MockResponse::Body(response));
+        self
+    }
+
+    /// Answer the requests for URLs matching a glob, where `*` matches any run of characters
+    /// and `?` any one, with `handler` called with the URL requested
+    ///
+    /// A URL with its own response is answered with that; otherwise the longest matching
+    /// pattern, the most specific, answers it.
+    pub fn with_pattern<F>(self, glob: &str, handler: F) -> Self
+    where
+        F: Fn(&str) -> MockResponse + Send + Sync + 'static,
+    {
+        let mut regex = String::from("^");
+        for c in glob.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        self.add_pattern(glob, Regex::new(&regex).unwrap(), handler)
+    }
+
+    /// Answer the requests for URLs matching a regular expression, as for a glob pattern
+    pub fn with_regex<F>(self, regex: &str, handler: F) -> Self
+    where
+        F: Fn(&str) -> MockResponse + Send + Sync + 'static,
+    {
+        let compiled =
+            Regex::new(regex).unwrap_or_else(|e| panic!("Invalid mock URL regex {}: {}", regex, e));
+        self.add_pattern(regex, compiled, handler)
+    }
+
+    fn add_pattern<F>(mut self, source: &str, regex: Regex, handler: F) -> Self
+    where
+        F: Fn(&str) -> MockResponse + Send + Sync + 'static,
+    {
+        self.patterns.push(PatternResponse {
+            source: source.to_string(),
+            regex,
+            handler: Box::new(handler),
+        });
         self
     }
 
@@ -35,26 +151,124 @@ impl MockHttpClient {
         self.with_response(url, &content)
     }
 
+    /// Answer every request for a URL with an HTTP status, such as 500
+    pub fn with_status(mut self, url: &str, status: u16) -> Self {
+        self.responses
+            .insert(url.to_string(), MockResponse::Status(status));
+        self
+    }
+
+    /// Fail every request for a URL without a response, as a timeout or refused connection
+    pub fn with_error(mut self, url: &str, message: &str) -> Self {
+        self.responses
+            .insert(url.to_string(), MockResponse::Error(message.to_string()));
+        self
+    }
+
+    /// Answer the next requests for a URL with `responses` in turn, then as usual
+    pub fn with_sequence(self, url: &str, responses: Vec<MockResponse>) -> Self {
+        self.sequences
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .extend(responses);
+        self
+    }
+
+    /// Fail the first `count` requests for a URL with an HTTP status, such as 503
+    pub fn with_failures(self, url: &str, count: usize, status: u16) -> Self {
+        self.with_sequence(url, vec![MockResponse::Status(status); count])
+    }
+
+    /// Take `latency` to answer each request, as a slow server would
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Take `delay` to answer the requests for a URL, instead of the latency
+    pub fn with_delay(mut self, url: &str, delay: Duration) -> Self {
+        self.delays.insert(url.to_string(), delay);
+        self
+    }
+
+    /// The URLs requested so far, once for each request, in the order they were requested
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The number of requests made for a URL, failed or not
+    pub fn request_count(&self, url: &str) -> usize {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|requested| *requested == url)
+            .count()
+    }
+
+    /// The most requests that were being answered at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Record a request and answer it after its delay, counting it as in flight while it waits
+    fn answer(&self, url: &str) -> Result<Vec<u8>, MockHttpError> {
+        self.requests.lock().unwrap().push(url.to_string());
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        std::thread::sleep(self.delays.get(url).copied().unwrap_or(self.latency));
+
+        let next = self
+            .sequences
+            .lock()
+            .unwrap()
+            .get_mut(url)
+            .and_then(VecDeque::pop_front);
+        let response = next
+            .or_else(|| self.responses.get(url).cloned())
+            .or_else(|| {
+                self.patterns
+                    .iter()
+                    .filter(|pattern| pattern.regex.is_match(url))
+                    .max_by_key(|pattern| pattern.source.len())
+                    .map(|pattern| (pattern.handler)(url))
+            });
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let error = |status, message: &str| MockHttpError {
+            url: url.to_string(),
+            status,
+            message: message.to_string(),
+        };
+        match response {
+            Some(MockResponse::Body(body)) => Ok(body),
+            Some(MockResponse::Status(status)) => Err(error(Some(status), "HTTP error")),
+            Some(MockResponse::Error(message)) => Err(error(None, &message)),
+            None => Err(error(Some(404), "Not found")),
+        }
+    }
+
+    /// Get a text response for a URL, or why the request failed
+    pub fn request(&self, url: &str) -> Result<String, MockHttpError> {
+        self.answer(url)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Get a binary response for a URL, or why the request failed
+    pub fn request_bytes(&self, url: &str) -> Result<Vec<u8>, MockHttpError> {
+        self.answer(url)
+    }
+
     /// Get a text response for a URL
     pub fn get(&self, url: &str) -> Result<String, String> {
-        self.responses
-            .get(url)
-            .cloned()
-            .ok_or_else(|| format!("Mock: No response for URL: {}", url))
+        self.request(url).map_err(|e| e.to_string())
     }
 
     /// Get binary response for a URL
     pub fn get_bytes(&self, url: &str) -> Result<Vec<u8>, String> {
-        // Try binary responses first
-        if let Some(binary) = self.binary_responses.get(url) {
-            return Ok(binary.clone());
-        }
-
-        // Fall back to text response as bytes
-        self.responses
-            .get(url)
-            .map(|s| s.as_bytes().to_vec())
-            .ok_or_else(|| format!("Mock: No response for URL: {}", url))
+        self.request_bytes(url).map_err(|e| e.to_string())
     }
 }
 
@@ -63,3 +277,184 @@ impl Default for MockHttpClient {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.org/entry.cif";
+
+    #[test]
+    fn test_responses_and_missing_urls() {
+        let client = MockHttpClient::new()
+            .with_response(URL, "data_a")
+            .with_binary_response("https://example.org/entry.gz", vec![0x1f, 0x8b]);
+
+        assert_eq!(client.get(URL).unwrap(), "data_a");
+        assert_eq!(client.get_bytes(URL).unwrap(), b"data_a");
+        assert_eq!(
+            client
+                .request_bytes("https://example.org/entry.gz")
+                .unwrap(),
+            [0x1f, 0x8b]
+        );
+
+        let missing = client.request("https://example.org/missing").unwrap_err();
+        assert_eq!(missing.status, Some(404));
+        assert_eq!(
+            client.get("https://example.org/missing").unwrap_err(),
+            "Mock: No response for URL: https://example.org/missing"
+        );
+    }
+
+    #[test]
+    fn test_statuses_and_errors() {
+        let client = MockHttpClient::new()
+            .with_status(URL, 503)
+            .with_error("https://example.org/slow", "timed out");
+
+        for _ in 0..2 {
+            assert_eq!(client.request(URL).unwrap_err().status, Some(503));
+        }
+        let error = client.request("https://example.org/slow").unwrap_err();
+        assert_eq!(error.status, None);
+        assert_eq!(
+            error.to_string(),
+            "Mock: timed out for URL: https://example.org/slow"
+        );
+    }
+
+    #[test]
+    fn test_sequence_then_usual_response() {
+        let client = MockHttpClient::new()
+            .with_response(URL, "data_final")
+            .with_sequence(
+                URL,
+                vec![
+                    MockResponse::Error("connection reset".to_string()),
+                    MockResponse::Status(502),
+                    MockResponse::text("data_first"),
+                ],
+            );
+
+        assert_eq!(client.request(URL).unwrap_err().status, None);
+        assert_eq!(client.request(URL).unwrap_err().status, Some(502));
+        assert_eq!(client.request(URL).unwrap(), "data_first");
+        assert_eq!(client.request(URL).unwrap(), "data_final");
+        assert_eq!(client.request(URL).unwrap(), "data_final");
+    }
+
+    #[test]
+    fn test_failures_before_success() {
+        let client = MockHttpClient::new()
+            .with_response(URL, "data_a")
+            .with_failures(URL, 2, 500);
+
+        assert!(client.request(URL).is_err());
+        assert!(client.request(URL).is_err());
+        assert_eq!(client.request(URL).unwrap(), "data_a");
+    }
+
+    #[test]
+    fn test_requests_are_recorded_in_order() {
+        let client = MockHttpClient::new().with_response(URL, "data_a");
+        let other = "https://example.org/other";
+
+        client.get(URL).unwrap();
+        client.get(other).unwrap_err();
+        client.get_bytes(URL).unwrap();
+
+        assert_eq!(client.requests(), [URL, other, URL]);
+        assert_eq!(client.request_count(URL), 2);
+        assert_eq!(client.request_count(other), 1);
+        assert_eq!(client.request_count("https://example.org/never"), 0);
+    }
+
+    #[test]
+    fn test_delays() {
+        let slow = "https://example.org/slow";
+        let client = MockHttpClient::new()
+            .with_response(URL, "data_a")
+            .with_response(slow, "data_b")
+            .with_latency(Duration::from_millis(5))
+            .with_delay(slow, Duration::from_millis(40));
+
+        let start = std::time::Instant::now();
+        client.get(URL).unwrap();
+        let fast = start.elapsed();
+        assert!(fast >= Duration::from_millis(5));
+
+        let start = std::time::Instant::now();
+        client.get(slow).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(client.max_in_flight(), 1);
+    }
+
+    #[test]
+    fn test_pattern_precedence() {
+        let client = MockHttpClient::new()
+            .with_response("https://example.org/cif/1000001.cif", "data_exact")
+            .with_pattern("https://example.org/*", |_| MockResponse::Status(403))
+            .with_pattern("https://example.org/cif/*.cif", |url| {
+                let id = url.rsplit('/').next().unwrap().trim_end_matches(".cif");
+                MockResponse::text(&format!("data_{}", id))
+            })
+            .with_regex(r"^https://example\.org/cif/9\d{6}\.cif$", |_| {
+                MockResponse::Status(410)
+            });
+
+        assert_eq!(
+            client.get("https://example.org/cif/1000001.cif").unwrap(),
+            "data_exact"
+        );
+        assert_eq!(
+            client.get("https://example.org/cif/1000002.cif").unwrap(),
+            "data_1000002"
+        );
+        let gone = client.request("https://example.org/cif/9000001.cif");
+        assert_eq!(gone.unwrap_err().status, Some(410));
+        let forbidden = client.request("https://example.org/cif/1000002.txt");
+        assert_eq!(forbidden.unwrap_err().status, Some(403));
+        let missing = client.request("https://example.com/cif/1000002.cif");
+        assert_eq!(missing.unwrap_err().status, Some(404));
+    }
+
+    #[test]
+    fn test_glob_characters_are_literal_except_wildcards() {
+        let client = MockHttpClient::new().with_pattern("https://example.org/list?page=?", |_| {
+            MockResponse::text("ok")
+        });
+
+        assert!(client.get("https://example.org/list?page=2").is_ok());
+        assert!(client.get("https://example.org/listXpage=2").is_ok());
+        assert!(client.get("https://example.org/list?page=12").is_err());
+        assert!(client.get("https://example.org.uk/list?page=2").is_err());
+    }
+
+    #[test]
+    fn test_gzip_body_round_trip() {
+        use flate2::read::GzDecoder;
+        use flate2::write::GzEncoder;
+        use std::io::{Read, Write};
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"data_1abc\n_entry.id 1ABC\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let client = MockHttpClient::new()
+            .with_binary_response(URL, gzipped.clone())
+            .with_pattern("https://example.org/*.gz", move |_| {
+                MockResponse::Body(gzipped.clone())
+            });
+
+        for url in [URL, "https://example.org/2def.cif.gz"] {
+            let body = client.get_bytes(url).unwrap();
+            assert_eq!(body[..2], [0x1f, 0x8b]);
+            let mut text = String::new();
+            GzDecoder::new(body.as_slice())
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text, "data_1abc\n_entry.id 1ABC\n");
+        }
+    }
+}
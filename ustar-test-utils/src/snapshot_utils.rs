@@ -1,5 +1,56 @@
+use regex::Regex;
 use similar::{ChangeTag, TextDiff};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A regex and its replacement, applied to a value before it's compared with its snapshot to
+/// redact text that changes from run to run; the replacement may refer to groups as `${1}`
+pub type SnapshotFilter<'a> = (&'a str, &'a str);
+
+/// Replace absolute paths, Unix or Windows, with `[path]`
+pub fn redact_paths() -> SnapshotFilter<'static> {
+    (
+        r#"(?:[A-Za-z]:\\|/)(?:[^\s"'/\\:]+[/\\])+[^\s"'/\\:,]*"#,
+        "[path]",
+    )
+}
+
+/// Replace durations with a unit, such as `12.5 ms` or `3s`, with `[duration]`
+pub fn redact_durations() -> SnapshotFilter<'static> {
+    (
+        r"\b[0-9]+(?:\.[0-9]+)?(?:e[+-]?[0-9]+)? ?(?:ns|µs|us|ms|s)\b",
+        "[duration]",
+    )
+}
+
+/// Replace ISO 8601 timestamps, such as `2024-05-01T12:30:00.123Z`, with `[timestamp]`
+pub fn redact_timestamps() -> SnapshotFilter<'static> {
+    (
+        r"\b[0-9]{4}-[0-9]{2}-[0-9]{2}[T ][0-9]{2}:[0-9]{2}:[0-9]{2}(?:\.[0-9]+)?(?:Z|[+-][0-9]{2}:?[0-9]{2})?",
+        "[timestamp]",
+    )
+}
+
+/// Apply each filter in turn to `value`
+fn apply_filters(value: &str, filters: &[SnapshotFilter]) -> String {
+    filters
+        .iter()
+        .fold(value.to_string(), |value, (pattern, replacement)| {
+            let regex = Regex::new(pattern)
+                .unwrap_or_else(|e| panic!("Invalid snapshot filter {}: {}", pattern, e));
+            regex.replace_all(&value, *replacement).into_owned()
+        })
+}
+
+/// The snapshot header's description of the filters applied, so a mismatch can be explained
+fn describe_filters(filters: &[SnapshotFilter]) -> String {
+    let mut description = String::from("filters:");
+    for (pattern, replacement) in filters {
+        description.push_str(&format!("\n  {} => {}", pattern, replacement));
+    }
+    description
+}
 
 /// Print message only in verbose mode - controlled by insta settings
 macro_rules! verbose_println {
@@ -82,18 +133,91 @@ pub struct SnapshotMismatch {
     pub snapshot_name: String,
     pub diff_path: std::path::PathBuf,
     pub new_path: std::path::PathBuf,
+    /// Unified diff of the expected snapshot against the actual value, without headers
+    pub diff: String,
 }
 
 impl std::fmt::Display for SnapshotMismatch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "Snapshot mismatch for '{}'\n  Diff: {}\n  New:  {}",
+            "Snapshot mismatch for '{}'\n  Diff: {}\n  New:  {}\n",
             self.snapshot_name,
             self.diff_path.display(),
             self.new_path.display()
-        )
+        )?;
+        if use_color() {
+            write!(f, "{}", colorize_diff(&self.diff))
+        } else {
+            write!(f, "{}", self.diff)
+        }
+    }
+}
+
+/// What to do with a snapshot that doesn't match, chosen with the `USTAR_SNAPSHOT`
+/// environment variable in the same way as insta's `INSTA_UPDATE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SnapshotUpdate {
+    /// Fail, writing `.snap.new`, `.snap.old` and `.snap.diff` files for review (the default)
+    Review,
+    /// Overwrite the `.snap.zst` snapshot with the new value and pass (`USTAR_SNAPSHOT=accept`)
+    Accept,
+    /// Fail, writing only the `.snap.new` file (`USTAR_SNAPSHOT=new`)
+    New,
+}
+
+impl SnapshotUpdate {
+    fn from_env() -> Self {
+        Self::from_value(std::env::var("USTAR_SNAPSHOT").ok().as_deref())
     }
+
+    fn from_value(value: Option<&str>) -> Self {
+        match value {
+            Some("accept") | Some("always") => SnapshotUpdate::Accept,
+            Some("new") => SnapshotUpdate::New,
+            None | Some("") | Some("review") => SnapshotUpdate::Review,
+            Some(other) => panic!(
+                "Unknown USTAR_SNAPSHOT value '{}', expected accept, new or review",
+                other
+            ),
+        }
+    }
+}
+
+/// Lines of context around each change in a mismatch diff, from `USTAR_SNAPSHOT_CONTEXT`
+fn diff_context_lines() -> usize {
+    std::env::var("USTAR_SNAPSHOT_CONTEXT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_DIFF_CONTEXT)
+}
+
+const DEFAULT_DIFF_CONTEXT: usize = 3;
+
+/// Color mismatch diffs written to a terminal unless NO_COLOR is set
+fn use_color() -> bool {
+    use std::io::IsTerminal;
+
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::io::stderr().is_terminal()
+}
+
+/// Color removed lines red, added lines green and hunk separators cyan
+fn colorize_diff(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let color = match line.chars().next() {
+                Some('-') => Some("31"),
+                Some('+') => Some("32"),
+                _ if line == "..." => Some("36"),
+                _ => None,
+            };
+            match color {
+                Some(color) => format!("\x1b[{}m{}\x1b[0m\n", color, line),
+                None => format!("{}\n", line),
+            }
+        })
+        .collect()
 }
 
 /// Check a snapshot without panicking. Returns Ok(()) if the snapshot matches,
@@ -105,18 +229,59 @@ impl std::fmt::Display for SnapshotMismatch {
 ///
 /// Looks for snapshots in the calling package's `tests/snapshots/` directory.
 pub fn check_snapshot_gz(snapshot_name: &str, value: &str) -> Result<(), SnapshotMismatch> {
-    let snapshot_dir = get_snapshot_dir();
+    check_snapshot(snapshot_name, value, None)
+}
+
+/// Check a snapshot as `check_snapshot_gz` does, after applying `filters` to the value.
+/// The filters are listed in the description in the snapshot's header.
+pub fn check_snapshot_gz_filtered(
+    snapshot_name: &str,
+    value: &str,
+    filters: &[SnapshotFilter],
+) -> Result<(), SnapshotMismatch> {
+    check_snapshot(
+        snapshot_name,
+        &apply_filters(value, filters),
+        Some(describe_filters(filters)),
+    )
+}
+
+fn check_snapshot(
+    snapshot_name: &str,
+    value: &str,
+    description: Option<String>,
+) -> Result<(), SnapshotMismatch> {
+    record_used_snapshot(snapshot_name);
+    check_snapshot_in(
+        &get_snapshot_dir(),
+        snapshot_name,
+        value,
+        description,
+        SnapshotUpdate::from_env(),
+    )
+}
+
+fn check_snapshot_in(
+    snapshot_dir: &Path,
+    snapshot_name: &str,
+    value: &str,
+    description: Option<String>,
+    update: SnapshotUpdate,
+) -> Result<(), SnapshotMismatch> {
     let snapshot_path = snapshot_dir.join(format!("{}.snap", snapshot_name));
 
     // Ensure all .snap.zst files are decompressed to .snap files for insta to use
-    if let Err(e) = ensure_snapshots_synchronized(&snapshot_dir) {
+    if let Err(e) = ensure_snapshots_synchronized(snapshot_dir) {
         eprintln!("Warning: Failed to synchronize snapshots: {}", e);
     }
 
     // Use insta's actual comparison logic by catching panics
     let mut settings = insta::Settings::clone_current();
-    settings.set_snapshot_path(&snapshot_dir);
+    settings.set_snapshot_path(snapshot_dir);
     settings.set_prepend_module_to_snapshot(false);
+    if let Some(description) = &description {
+        settings.set_description(description.clone());
+    }
 
     let result = std::panic::catch_unwind(|| {
         settings.bind(|| {
@@ -124,25 +289,251 @@ pub fn check_snapshot_gz(snapshot_name: &str, value: &str) -> Result<(), Snapsho
         });
     });
 
-    match result {
-        Ok(_) => {
-            // Snapshot matches! No need to do anything - compression handled by acceptance script
+    if result.is_ok() {
+        // Snapshot matches! No need to do anything - compression handled by acceptance script
+        return Ok(());
+    }
+
+    let new_path = snapshot_path.with_extension("snap.new");
+    let expected = read_snapshot(&snapshot_path).unwrap_or_default();
+    let diff = create_diff(
+        snapshot_body(&expected),
+        value,
+        snapshot_name,
+        diff_context_lines(),
+    );
+    let serialized = serialize_snapshot(value, description.as_deref());
+
+    match update {
+        SnapshotUpdate::Accept => {
+            if let Err(e) = accept_snapshot(&snapshot_path, &serialized) {
+                panic!("Failed to accept snapshot '{}': {}", snapshot_name, e);
+            }
+            eprintln!("Accepted new snapshot for '{}'", snapshot_name);
             Ok(())
         }
-        Err(_) => {
+        SnapshotUpdate::New => {
+            if let Err(e) = std::fs::write(&new_path, &serialized) {
+                eprintln!("Failed to write .snap.new file: {}", e);
+            }
+            Err(SnapshotMismatch {
+                snapshot_name: snapshot_name.to_string(),
+                diff_path: snapshot_path.with_extension("snap.diff"),
+                new_path,
+                diff,
+            })
+        }
+        SnapshotUpdate::Review => {
             // Snapshot mismatch or doesn't exist - insta has already created .snap.new
+            // unless it's running on CI, so make sure it's there for the acceptance script
+            if !new_path.exists() {
+                if let Err(e) = std::fs::write(&new_path, &serialized) {
+                    eprintln!("Failed to write .snap.new file: {}", e);
+                }
+            }
             // Always create diff and old files after insta runs, regardless of the failure path
             create_review_files(&snapshot_path, snapshot_name);
 
             Err(SnapshotMismatch {
                 snapshot_name: snapshot_name.to_string(),
                 diff_path: snapshot_path.with_extension("snap.diff"),
-                new_path: snapshot_path.with_extension("snap.new"),
+                new_path,
+                diff,
             })
         }
     }
 }
 
+/// Snapshot file content in insta's format: a metadata header followed by the value
+fn serialize_snapshot(value: &str, description: Option<&str>) -> String {
+    let mut snapshot = String::from("---\nsource: ustar-test-utils/src/snapshot_utils.rs\n");
+    if let Some(description) = description {
+        // A JSON string is also a valid YAML scalar
+        snapshot.push_str(&format!(
+            "description: {}\n",
+            serde_json::Value::from(description)
+        ));
+    }
+    snapshot.push_str("expression: value\n---\n");
+    snapshot.push_str(value);
+    snapshot.push('\n');
+    snapshot
+}
+
+/// The value stored in a snapshot file, without its metadata header
+fn snapshot_body(snapshot: &str) -> &str {
+    snapshot
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---\n"))
+        .map_or(snapshot, |(_, body)| body)
+}
+
+/// Overwrite a snapshot's `.snap.zst` and `.snap` files, removing any left over review files
+fn accept_snapshot(
+    snapshot_path: &Path,
+    serialized: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = snapshot_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let compressed = zstd::encode_all(serialized.as_bytes(), 0)?;
+    std::fs::write(snapshot_path.with_extension("snap.zst"), compressed)?;
+    std::fs::write(snapshot_path, serialized)?;
+
+    for extension in ["snap.new", "snap.old", "snap.diff"] {
+        let review_path = snapshot_path.with_extension(extension);
+        if review_path.exists() {
+            std::fs::remove_file(review_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Names of the snapshots checked so far in this test binary
+static USED_SNAPSHOTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Environment variable naming a file that the child run of `check_unused_snapshots`
+/// appends the name of each snapshot it checks to
+const USED_SNAPSHOTS_FILE_VAR: &str = "USTAR_SNAPSHOT_USED_FILE";
+
+/// Remember that a test checked a snapshot, so snapshots no test checks can be reported
+fn record_used_snapshot(snapshot_name: &str) {
+    let mut used = USED_SNAPSHOTS.lock().unwrap_or_else(|e| e.into_inner());
+    if !used.iter().any(|name| name == snapshot_name) {
+        used.push(snapshot_name.to_string());
+    }
+
+    if let Some(used_file) = std::env::var_os(USED_SNAPSHOTS_FILE_VAR) {
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&used_file)
+            .and_then(|mut file| writeln!(file, "{}", snapshot_name));
+        if let Err(e) = written {
+            eprintln!(
+                "Warning: Failed to record snapshot {}: {}",
+                snapshot_name, e
+            );
+        }
+    }
+}
+
+/// The names of the snapshots checked so far in this test binary, in the order first checked
+pub fn used_snapshots() -> Vec<String> {
+    USED_SNAPSHOTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// The snapshot name a snapshot file is stored under, or None if it isn't a snapshot file.
+/// Review files (`.snap.new`, `.snap.old` and `.snap.diff`) aren't snapshot files.
+fn snapshot_file_name(path: &Path) -> Option<&str> {
+    let file_name = path.file_name()?.to_str()?;
+    [".snap.zst", ".snap.gz", ".snap"]
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix))
+}
+
+/// List the snapshot files in `snapshot_dir`, compressed or not, whose names aren't in
+/// `used_names`, sorted by path
+pub fn report_unused_snapshots(snapshot_dir: &Path, used_names: &[String]) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(snapshot_dir) else {
+        return Vec::new();
+    };
+
+    let mut unused: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            snapshot_file_name(path).is_some_and(|name| !used_names.iter().any(|used| used == name))
+        })
+        .collect();
+    unused.sort();
+    unused
+}
+
+/// Delete the snapshot files `report_unused_snapshots` reports, returning their paths
+pub fn prune_unused_snapshots(
+    snapshot_dir: &Path,
+    used_names: &[String],
+) -> std::io::Result<Vec<PathBuf>> {
+    let unused = report_unused_snapshots(snapshot_dir, used_names);
+    for path in &unused {
+        std::fs::remove_file(path)?;
+    }
+    Ok(unused)
+}
+
+/// Fail if the package's snapshot directory holds snapshots named `<prefix>__...` that no
+/// test in this binary checks, when `USTAR_SNAPSHOT_STRICT=1` is set; otherwise do nothing.
+///
+/// Tests can run in any order, so this reruns the current test binary, skipping
+/// `test_name` (the test calling it), and collects the snapshots the rerun checks.
+/// Use `snapshot_orphan_check!` to define the test rather than calling this directly.
+pub fn check_unused_snapshots(prefix: &str, test_name: &str) {
+    let strict = std::env::var("USTAR_SNAPSHOT_STRICT").is_ok_and(|value| value == "1");
+    if !strict || std::env::var_os(USED_SNAPSHOTS_FILE_VAR).is_some() {
+        return;
+    }
+
+    let used_file = tempfile::NamedTempFile::new().expect("Failed to create used snapshots file");
+    let test_binary = std::env::current_exe().expect("Failed to find the test binary");
+    let status = std::process::Command::new(test_binary)
+        .args(["--skip", test_name])
+        .env(USED_SNAPSHOTS_FILE_VAR, used_file.path())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .expect("Failed to rerun the test binary");
+
+    let used_names: Vec<String> = std::fs::read_to_string(used_file.path())
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    let prefix = format!("{}__", prefix);
+    let snapshot_dir = get_snapshot_dir();
+    let orphans: Vec<PathBuf> = report_unused_snapshots(&snapshot_dir, &used_names)
+        .into_iter()
+        .filter(|path| snapshot_file_name(path).is_some_and(|name| name.starts_with(&prefix)))
+        .collect();
+
+    if !orphans.is_empty() {
+        let listing: Vec<String> = orphans
+            .iter()
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+        panic!(
+            "{} snapshot file(s) in {} aren't checked by any test{}:\n{}\n\nDelete them or remove them with prune_unused_snapshots.",
+            orphans.len(),
+            snapshot_dir.display(),
+            if status.success() {
+                ""
+            } else {
+                " (some tests failed, so this may be incomplete)"
+            },
+            listing.join("\n")
+        );
+    }
+}
+
+/// Define a test that fails when `USTAR_SNAPSHOT_STRICT=1` is set and the snapshot directory
+/// holds snapshots named `<prefix>__...` that no test in this file checks, listing them:
+///
+/// ```ignore
+/// ustar_test_utils::snapshot_orphan_check!("ustar_dumper_tests");
+/// ```
+#[macro_export]
+macro_rules! snapshot_orphan_check {
+    ($prefix:expr) => {
+        #[test]
+        fn test_no_unused_snapshots() {
+            $crate::check_unused_snapshots($prefix, "test_no_unused_snapshots");
+        }
+    };
+}
+
 /// Create .snap.diff and .snap.old files for review after a snapshot mismatch
 fn create_review_files(snapshot_path: &std::path::Path, snapshot_name: &str) {
     // With prepend_module_to_snapshot(false), insta uses the snapshot_name directly
@@ -169,7 +560,12 @@ fn create_review_files(snapshot_path: &std::path::Path, snapshot_name: &str) {
             read_snapshot(snapshot_path),
         ) {
             // Don't strip headers - show diff of full files including metadata
-            let diff_content = create_diff(&expected_content, &new_content, snapshot_name);
+            let diff_content = create_diff(
+                &expected_content,
+                &new_content,
+                snapshot_name,
+                diff_context_lines(),
+            );
             if let Err(e) = std::fs::write(&diff_path, &diff_content) {
                 eprintln!("Failed to write diff file: {}", e);
             } else {
@@ -190,24 +586,43 @@ fn create_review_files(snapshot_path: &std::path::Path, snapshot_name: &str) {
 /// "sas_walker_tests__loop_walker_output.snap.zst")
 pub fn assert_snapshot_gz(snapshot_name: &str, value: &str) {
     if let Err(mismatch) = check_snapshot_gz(snapshot_name, value) {
-        panic!(
-            "Snapshot mismatch for '{}':\n\nDiff available at: {}\nNew snapshot at: {}\n\nRun ./scripts/insta-zstd.sh to accept the new snapshot.\n",
-            mismatch.snapshot_name,
-            mismatch.diff_path.display(),
-            mismatch.new_path.display(),
-        );
+        panic_on_mismatch(mismatch);
+    }
+}
+
+/// Assert a snapshot as `assert_snapshot_gz` does, after applying `filters` to the value,
+/// for output with timestamps, absolute paths or timings that change from run to run:
+///
+/// ```ignore
+/// assert_snapshot_gz_filtered(
+///     "binary_integration_tests__report",
+///     &stdout,
+///     &[redact_paths(), redact_durations()],
+/// );
+/// ```
+pub fn assert_snapshot_gz_filtered(snapshot_name: &str, value: &str, filters: &[SnapshotFilter]) {
+    if let Err(mismatch) = check_snapshot_gz_filtered(snapshot_name, value, filters) {
+        panic_on_mismatch(mismatch);
     }
 }
 
-/// Create a unified diff between expected and actual content using the `similar` crate
-fn create_diff(expected: &str, actual: &str, snapshot_name: &str) -> String {
+fn panic_on_mismatch(mismatch: SnapshotMismatch) -> ! {
+    panic!(
+        "{}\nRun ./scripts/insta-zstd.sh to accept the new snapshot, or rerun with USTAR_SNAPSHOT=accept.\n",
+        mismatch
+    );
+}
+
+/// Create a unified diff between expected and actual content using the `similar` crate,
+/// with `context` unchanged lines around each change
+fn create_diff(expected: &str, actual: &str, snapshot_name: &str, context: usize) -> String {
     let diff = TextDiff::from_lines(expected, actual);
     let mut output = String::new();
 
     output.push_str(&format!("--- {}.snap (expected)\n", snapshot_name));
     output.push_str(&format!("+++ {}.snap (actual)\n", snapshot_name));
 
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+    for (idx, group) in diff.grouped_ops(context).iter().enumerate() {
         if idx > 0 {
             output.push_str("...\n");
         }
@@ -268,3 +683,191 @@ fn get_snapshot_dir() -> std::path::PathBuf {
         .join("tests")
         .join("snapshots")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::snapshot_orphan_check!("snapshot_utils");
+
+    #[test]
+    fn test_builtin_filters() {
+        let output = "read /home/user/data/1abc.cif, C:\\data\\2def.cif and ./local.cif\n\
+                      parsed in 12.5 ms (1.2e-3s) at 2024-05-01T12:30:00.123Z for 3 loops";
+        let filtered = apply_filters(
+            output,
+            &[redact_paths(), redact_durations(), redact_timestamps()],
+        );
+        assert_eq!(
+            filtered,
+            "read [path], [path] and ./local.cif\n\
+             parsed in [duration] ([duration]) at [timestamp] for 3 loops"
+        );
+    }
+
+    #[test]
+    fn test_filter_replacements_use_groups() {
+        let filtered = apply_filters(
+            r#"{"parse_time_ms": 0.25, "size": 10}"#,
+            &[(r#"("parse_time_ms": )[0-9.]+"#, "${1}[time]")],
+        );
+        assert_eq!(filtered, r#"{"parse_time_ms": [time], "size": 10}"#);
+    }
+
+    #[test]
+    fn test_filtered_snapshot_ignores_timestamps() {
+        for timestamp in ["2024-05-01T12:30:00Z", "2025-11-17 08:05:59.5+01:00"] {
+            let log = format!("data_1abc\ndownloaded at {}\n", timestamp);
+            assert_snapshot_gz_filtered(
+                "snapshot_utils__filtered_timestamps",
+                &log,
+                &[redact_timestamps()],
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff_shows_changes_with_context() {
+        let expected = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let actual = "a\nb\nc\nd\nE\nf\ng\nh\n";
+
+        let diff = create_diff(expected, actual, "example", 1);
+        assert_eq!(
+            diff,
+            "--- example.snap (expected)\n\
+             +++ example.snap (actual)\n \
+             d\n\
+             -«e»\n\
+             +«E»\n \
+             f\n"
+        );
+
+        let wide_diff = create_diff(expected, actual, "example", 3);
+        assert!(wide_diff.contains(" b\n") && wide_diff.contains(" h\n"));
+        assert!(!wide_diff.contains(" a\n"));
+    }
+
+    #[test]
+    fn test_diff_separates_hunks_and_colorizes() {
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let actual = "one\n2\n3\n4\n5\n6\n7\n8\nnine\n";
+
+        let diff = create_diff(expected, actual, "hunks", 0);
+        assert_eq!(diff.lines().filter(|line| *line == "...").count(), 1);
+
+        let colored = colorize_diff(&diff);
+        assert!(colored.contains("\x1b[31m-«1»\x1b[0m"));
+        assert!(colored.contains("\x1b[32m+«one»\x1b[0m"));
+        assert!(colored.contains("\x1b[36m...\x1b[0m"));
+    }
+
+    #[test]
+    fn test_snapshot_update_modes() {
+        assert_eq!(SnapshotUpdate::from_value(None), SnapshotUpdate::Review);
+        assert_eq!(SnapshotUpdate::from_value(Some("")), SnapshotUpdate::Review);
+        assert_eq!(
+            SnapshotUpdate::from_value(Some("accept")),
+            SnapshotUpdate::Accept
+        );
+        assert_eq!(SnapshotUpdate::from_value(Some("new")), SnapshotUpdate::New);
+    }
+
+    #[test]
+    fn test_snapshot_body_strips_header() {
+        let snapshot = serialize_snapshot("data_1abc\nloop_", Some("filters: none"));
+        assert!(snapshot.contains("description: \"filters: none\"\n"));
+        assert_eq!(snapshot_body(&snapshot), "data_1abc\nloop_\n");
+    }
+
+    #[test]
+    fn test_accept_overwrites_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = "accept_example";
+        std::fs::write(
+            dir.path().join(format!("{}.snap.zst", name)),
+            zstd::encode_all(serialize_snapshot("old value", None).as_bytes(), 0).unwrap(),
+        )
+        .unwrap();
+
+        let mismatch =
+            check_snapshot_in(dir.path(), name, "new value", None, SnapshotUpdate::Review)
+                .unwrap_err();
+        assert!(mismatch.diff.contains("-«old» value"));
+        assert!(mismatch.diff.contains("+«new» value"));
+        assert!(mismatch.new_path.exists());
+
+        check_snapshot_in(dir.path(), name, "new value", None, SnapshotUpdate::Accept)
+            .expect("accept should pass");
+        let accepted = read_snapshot(dir.path().join(format!("{}.snap", name))).unwrap();
+        assert_eq!(snapshot_body(&accepted), "new value\n");
+        assert!(!mismatch.new_path.exists());
+        assert!(!dir.path().join(format!("{}.snap.diff", name)).exists());
+
+        check_snapshot_in(dir.path(), name, "new value", None, SnapshotUpdate::Review)
+            .expect("accepted snapshot should match");
+    }
+
+    #[test]
+    fn test_new_writes_only_new_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = "new_example";
+
+        let mismatch =
+            check_snapshot_in(dir.path(), name, "first value", None, SnapshotUpdate::New)
+                .unwrap_err();
+        assert!(mismatch.diff.contains("+first value"));
+        assert!(mismatch.new_path.exists());
+        assert!(!dir.path().join(format!("{}.snap.zst", name)).exists());
+        assert!(!mismatch.diff_path.exists());
+    }
+
+    #[test]
+    fn test_checked_snapshots_are_recorded() {
+        assert_snapshot_gz_filtered(
+            "snapshot_utils__filtered_timestamps",
+            "data_1abc\ndownloaded at 2024-05-01T12:30:00Z\n",
+            &[redact_timestamps()],
+        );
+        assert!(used_snapshots().contains(&"snapshot_utils__filtered_timestamps".to_string()));
+    }
+
+    #[test]
+    fn test_report_and_prune_unused_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "tests__used.snap",
+            "tests__used.snap.zst",
+            "tests__renamed.snap",
+            "tests__renamed.snap.zst",
+            "tests__legacy.snap.gz",
+            "tests__used.snap.new",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        let used = vec!["tests__used".to_string()];
+
+        let unused = report_unused_snapshots(dir.path(), &used);
+        assert_eq!(
+            unused,
+            vec![
+                dir.path().join("tests__legacy.snap.gz"),
+                dir.path().join("tests__renamed.snap"),
+                dir.path().join("tests__renamed.snap.zst"),
+            ]
+        );
+
+        let pruned = prune_unused_snapshots(dir.path(), &used).unwrap();
+        assert_eq!(pruned, unused);
+        assert!(report_unused_snapshots(dir.path(), &used).is_empty());
+        assert!(dir.path().join("tests__used.snap.zst").exists());
+        assert!(dir.path().join("tests__used.snap.new").exists());
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_report_unused_snapshots_in_missing_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(report_unused_snapshots(&dir.path().join("snapshots"), &[]).is_empty());
+    }
+}
@@ -0,0 +1,382 @@
+//! Structured generation of valid STAR documents for property tests.
+//!
+//! `star_document()` is a proptest strategy producing a `StarDocument`: data and global blocks
+//! holding items, loops and save frames whose values are written with every delimiter STAR has.
+//! The value text is chosen to meet the corners of the quoting rules, such as quotes inside a
+//! value quoted with the same character, `#` inside values and values that look like the start
+//! of a comment or a keyword once a delimiter is removed, while staying a value every parser of
+//! the grammar should read back unchanged.
+//!
+//! `StarDocument::to_star` lays the document out with the separators it was generated with,
+//! mixing blanks, line ends in either style and comments, so the same structure is written in
+//! many ways. The model records the value each token should be read as, letting a test compare
+//! what was parsed with what was written.
+
+use proptest::prelude::*;
+use proptest::sample::select;
+
+/// How a generated value is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    /// A bare value or a frame code
+    None,
+    /// 'single quotes'
+    Single,
+    /// "double quotes"
+    Double,
+    /// '''triple single quotes'''
+    TripleSingle,
+    /// """triple double quotes"""
+    TripleDouble,
+    /// A text field between lines starting with `;`
+    Semicolon,
+}
+
+/// A value and the delimiter it is written with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarValue {
+    /// The value as a parser should report it, without its delimiters
+    pub text: String,
+    pub delimiter: Delimiter,
+}
+
+/// A tag and its value outside a loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarItem {
+    pub tag: String,
+    pub value: StarValue,
+}
+
+/// A loop of one level with complete rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarLoop {
+    pub tags: Vec<String>,
+    /// Rows of one value for each tag, never empty
+    pub rows: Vec<Vec<StarValue>>,
+    /// Whether the loop is closed with `stop_`
+    pub stop: bool,
+}
+
+/// An element of a block or save frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StarContent {
+    Item(StarItem),
+    Loop(StarLoop),
+    /// Only found in data blocks
+    Saveframe(StarSaveframe),
+}
+
+/// A save frame holding items and loops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarSaveframe {
+    /// The frame code without `save_`
+    pub name: String,
+    /// Items and loops, never empty
+    pub contents: Vec<StarContent>,
+}
+
+/// A data block, or a global block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarBlock {
+    /// The block code without `data_`, `None` for a global block
+    pub name: Option<String>,
+    /// Items, loops and, for data blocks, save frames, never empty
+    pub contents: Vec<StarContent>,
+}
+
+/// A generated STAR document and the separators it is laid out with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarDocument {
+    pub blocks: Vec<StarBlock>,
+    /// Text written between tokens, used in turn; each starts with a blank or a line end and
+    /// any comment in it ends with a line end
+    pub separators: Vec<String>,
+}
+
+impl StarDocument {
+    /// The document as STAR text
+    pub fn to_star(&self) -> String {
+        let mut writer = Writer {
+            text: String::new(),
+            separators: &self.separators,
+            next: 0,
+        };
+        for block in &self.blocks {
+            match &block.name {
+                Some(name) => writer.token(&format!("data_{}", name)),
+                None => writer.token("global_"),
+            }
+            writer.contents(&block.contents);
+        }
+        writer.text
+    }
+
+    /// The number of values in the document, items and loop values together
+    pub fn value_count(&self) -> usize {
+        fn count(contents: &[StarContent]) -> usize {
+            contents
+                .iter()
+                .map(|content| match content {
+                    StarContent::Item(_) => 1,
+                    StarContent::Loop(star_loop) => star_loop.rows.iter().map(Vec::len).sum(),
+                    StarContent::Saveframe(frame) => count(&frame.contents),
+                })
+                .sum()
+        }
+        self.blocks.iter().map(|block| count(&block.contents)).sum()
+    }
+}
+
+/// Writes tokens separated by the separators of a document in turn (private)
+struct Writer<'a> {
+    text: String,
+    separators: &'a [String],
+    next: usize,
+}
+
+impl Writer<'_> {
+    fn token(&mut self, token: &str) {
+        if !self.text.is_empty() {
+            let separator = &self.separators[self.next % self.separators.len()];
+            self.next += 1;
+            self.text.push_str(separator);
+        }
+        self.text.push_str(token);
+    }
+
+    fn value(&mut self, value: &StarValue) {
+        let token = match value.delimiter {
+            Delimiter::None => value.text.clone(),
+            Delimiter::Single => format!("'{}'", value.text),
+            Delimiter::Double => format!("\"{}\"", value.text),
+            Delimiter::TripleSingle => format!("'''{}'''", value.text),
+            Delimiter::TripleDouble => format!("\"\"\"{}\"\"\"", value.text),
+            Delimiter::Semicolon => format!("\n;{}\n;", value.text),
+        };
+        self.token(&token);
+    }
+
+    fn contents(&mut self, contents: &[StarContent]) {
+        for content in contents {
+            match content {
+                StarContent::Item(item) => {
+                    self.token(&item.tag);
+                    self.value(&item.value);
+                }
+                StarContent::Loop(star_loop) => {
+                    self.token("loop_");
+                    for tag in &star_loop.tags {
+                        self.token(tag);
+                    }
+                    for value in star_loop.rows.iter().flatten() {
+                        self.value(value);
+                    }
+                    if star_loop.stop {
+                        self.token("stop_");
+                    }
+                }
+                StarContent::Saveframe(frame) => {
+                    self.token(&format!("save_{}", frame.name));
+                    self.contents(&frame.contents);
+                    self.token("save_");
+                }
+            }
+        }
+    }
+}
+
+/// Characters of names and bare values, with those that end or start other tokens
+const NAME_CHARS: &str = "abcxyzABCXYZ0129._-()+*/:'\"#$;[]";
+
+/// Characters of quoted values, with both quotes, blanks, `#` and `;`
+const QUOTED_CHARS: &str = "abcXYZ019 ._-'\"#;$[]";
+
+/// Text of 1 to `max` characters drawn from `chars`
+fn text(chars: &'static str, max: usize) -> impl Strategy<Value = String> {
+    prop::collection::vec(select(chars.chars().collect::<Vec<_>>()), 1..=max)
+        .prop_map(|chars| chars.into_iter().collect())
+}
+
+/// Whether `text` starts with a reserved word, in any case
+fn starts_with_keyword(text: &str) -> bool {
+    ["data_", "loop_", "global_", "save_", "stop_"]
+        .iter()
+        .any(|keyword| {
+            text.get(..keyword.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(keyword))
+        })
+}
+
+/// A data name, `_` and name characters
+pub fn tag() -> impl Strategy<Value = String> {
+    text(NAME_CHARS, 8).prop_map(|name| format!("_{}", name))
+}
+
+/// A block code or frame code, without the quotes and `#` a name may hold
+fn code() -> impl Strategy<Value = String> {
+    text("abcxyzABCXYZ0129._-()+*/:", 8)
+}
+
+/// A value that reads back as written with `delimiter`
+fn value_with(delimiter: Delimiter) -> BoxedStrategy<StarValue> {
+    let value = move |text| StarValue { text, delimiter };
+    match delimiter {
+        // not a tag, frame code, quote, comment, text field or CIF2 container opener
+        Delimiter::None => prop_oneof![
+            4 => text(NAME_CHARS, 8).prop_filter("reserved bare start", |text| {
+                !text.starts_with(['_', '$', '\'', '"', '#', ';', '[', ']'])
+                    && !starts_with_keyword(text)
+            }),
+            1 => text("abcXYZ019._-", 6).prop_map(|code| format!("${}", code)),
+            1 => select(vec![".", "?", "C1'", "O\"x", "a#b", "a;b", "x'y'z"])
+                .prop_map(String::from),
+        ]
+        .prop_map(value)
+        .boxed(),
+        // a quote ends the value only when a blank follows it, and a value opening with two
+        // quotes would open a triple quoted one
+        Delimiter::Single | Delimiter::Double => {
+            let quote = if delimiter == Delimiter::Single {
+                '\''
+            } else {
+                '"'
+            };
+            prop_oneof![
+                4 => prop::collection::vec(select(QUOTED_CHARS.chars().collect::<Vec<_>>()), 0..10)
+                    .prop_map(|chars| chars.into_iter().collect::<String>()),
+                1 => select(vec!["O'Brien", "a''b", "say \"hi\"", "# not a comment", ""])
+                    .prop_map(String::from),
+            ]
+            .prop_filter("closing quote", move |text| {
+                let written = format!("{}{}", text, quote);
+                !text.starts_with(quote)
+                    && !written
+                        .as_bytes()
+                        .windows(2)
+                        .any(|pair| pair[0] == quote as u8 && matches!(pair[1], b' ' | b'\t'))
+            })
+            .prop_map(value)
+            .boxed()
+        }
+        // anything but three of its quote in a row, or its quote at the end
+        Delimiter::TripleSingle | Delimiter::TripleDouble => {
+            let quote = if delimiter == Delimiter::TripleSingle {
+                '\''
+            } else {
+                '"'
+            };
+            prop::collection::vec(
+                prop_oneof![
+                    4 => text(QUOTED_CHARS, 6),
+                    1 => Just("\n".to_string()),
+                ],
+                0..4,
+            )
+            .prop_map(|parts| parts.concat())
+            .prop_filter("closing triple quote", move |text| {
+                !text.contains(&quote.to_string().repeat(3)) && !text.ends_with(quote)
+            })
+            .prop_map(value)
+            .boxed()
+        }
+        // lines of anything, none of them starting with `;`
+        Delimiter::Semicolon => prop::collection::vec(
+            prop_oneof![
+                4 => text(QUOTED_CHARS, 8),
+                1 => select(vec!["data_x loop_ _a", "'''", "  indented", "\"\"\""])
+                    .prop_map(String::from),
+                1 => Just(String::new()),
+            ],
+            1..4,
+        )
+        .prop_map(|lines| lines.join("\n"))
+        .prop_filter("closing semicolon", |text| !text.contains("\n;"))
+        .prop_map(value)
+        .boxed(),
+    }
+}
+
+/// A value written with any delimiter
+pub fn star_value() -> impl Strategy<Value = StarValue> {
+    select(vec![
+        Delimiter::None,
+        Delimiter::Single,
+        Delimiter::Double,
+        Delimiter::TripleSingle,
+        Delimiter::TripleDouble,
+        Delimiter::Semicolon,
+    ])
+    .prop_flat_map(value_with)
+}
+
+/// A loop of 1 to 3 tags and 1 to 4 rows
+fn star_loop() -> impl Strategy<Value = StarLoop> {
+    (1usize..=3, 1usize..=4, any::<bool>()).prop_flat_map(|(columns, rows, stop)| {
+        (
+            prop::collection::vec(tag(), columns),
+            prop::collection::vec(prop::collection::vec(star_value(), columns), rows),
+        )
+            .prop_map(move |(tags, rows)| StarLoop { tags, rows, stop })
+    })
+}
+
+/// Items and loops, as held by save frames and global blocks
+fn data_contents() -> impl Strategy<Value = Vec<StarContent>> {
+    prop::collection::vec(
+        prop_oneof![
+            3 => (tag(), star_value())
+                .prop_map(|(tag, value)| StarContent::Item(StarItem { tag, value })),
+            1 => star_loop().prop_map(StarContent::Loop),
+        ],
+        1..5,
+    )
+}
+
+/// Items, loops and save frames, as held by data blocks
+fn block_contents() -> impl Strategy<Value = Vec<StarContent>> {
+    prop::collection::vec(
+        prop_oneof![
+            3 => data_contents(),
+            1 => (code(), data_contents()).prop_map(|(name, contents)| {
+                vec![StarContent::Saveframe(StarSaveframe { name, contents })]
+            }),
+        ],
+        1..4,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+/// Text between tokens: blanks, line ends in both styles and comments
+fn separator() -> impl Strategy<Value = String> {
+    select(vec![
+        " ",
+        "  ",
+        "\t",
+        "\n",
+        "\r\n",
+        "\n\n  ",
+        " # a comment\n",
+        "\n# 'quoted' \"comment\" ; data_x\n  ",
+        "\t#\n",
+    ])
+    .prop_map(String::from)
+}
+
+/// A valid STAR document of 1 to 3 blocks, a few of them global blocks
+pub fn star_document() -> impl Strategy<Value = StarDocument> {
+    let block = prop_oneof![
+        5 => (code(), block_contents()).prop_map(|(name, contents)| StarBlock {
+            name: Some(name),
+            contents,
+        }),
+        1 => data_contents().prop_map(|contents| StarBlock {
+            name: None,
+            contents,
+        }),
+    ];
+    (
+        prop::collection::vec(block, 1..4),
+        prop::collection::vec(separator(), 1..6),
+    )
+        .prop_map(|(blocks, separators)| StarDocument { blocks, separators })
+}
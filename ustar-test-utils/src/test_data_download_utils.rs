@@ -6,17 +6,23 @@
 //! from the GitHub repository.
 //!
 //! This module provides functionality to:
-//! - Automatically discover test directories with checksums.sha1 files
-//! - Verify test data integrity using SHA-1 checksums  
-//! - Download missing test data files from GitHub when needed
+//! - Automatically discover test directories with checksums.sha256 or checksums.sha1 files
+//! - Verify test data integrity using SHA-256 or SHA-1 checksums
+//! - Download missing test data files from GitHub when needed, from the release tag
+//!   matching the crate version unless `USTAR_TEST_DATA_REF` names another git ref,
+//!   extracting only the directories with missing files
 //! - Ensure test data is available before running tests
-//! - Support for disabling downloads via --features no-large-tests
+//! - Support for disabling downloads via --features no-large-tests, `USTAR_OFFLINE=1`
+//!   or `TestDataPolicy::Offline`
 
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Cursor;
-use std::path::Path;
-use std::sync::OnceLock;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 /// Error type for test data operations
 #[derive(Debug)]
@@ -33,6 +39,13 @@ pub enum TestDataError {
     InvalidChecksumFile(String),
     /// Test data directory not found
     DirectoryNotFound(String),
+    /// Test data files are missing and downloading is disabled
+    OfflineMissing {
+        /// The test data directories with missing files
+        directories: Vec<PathBuf>,
+        /// The missing files
+        files: Vec<String>,
+    },
 }
 
 impl std::fmt::Display for TestDataError {
@@ -56,6 +69,15 @@ impl std::fmt::Display for TestDataError {
             TestDataError::DirectoryNotFound(path) => {
                 write!(f, "Test data directory not found: {}", path)
             }
+            TestDataError::OfflineMissing { directories, files } => {
+                writeln!(
+                    f,
+                    "Missing test data files and downloading is disabled: {}",
+                    files.join(", ")
+                )?;
+                writeln!(f, "To download them manually:")?;
+                write!(f, "{}", manual_download_commands(directories))
+            }
         }
     }
 }
@@ -68,16 +90,100 @@ impl From<std::io::Error> for TestDataError {
     }
 }
 
-/// Calculate SHA-1 hash of a file
-fn calculate_file_sha1<P: AsRef<Path>>(file_path: P) -> Result<String, TestDataError> {
+/// What `ensure_test_data_available` may do about test data files that are missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TestDataPolicy {
+    /// Download missing files from GitHub, then verify all the files. Downloading is
+    /// disabled, as with `Offline`, by `USTAR_OFFLINE=1` or `--features no-large-tests`
+    #[default]
+    DownloadIfMissing,
+    /// Never download: fail with `TestDataError::OfflineMissing` if any files are missing
+    Offline,
+    /// Never download: verify the files that are present and ignore missing ones
+    VerifyOnly,
+}
+
+impl TestDataPolicy {
+    /// The policy to follow once `USTAR_OFFLINE` and the no-large-tests feature are applied
+    fn effective(self) -> Self {
+        let offline = std::env::var("USTAR_OFFLINE").is_ok_and(|value| value == "1");
+        self.with_downloads_disabled(offline || cfg!(feature = "no-large-tests"))
+    }
+
+    fn with_downloads_disabled(self, disabled: bool) -> Self {
+        match self {
+            TestDataPolicy::DownloadIfMissing if disabled => TestDataPolicy::Offline,
+            policy => policy,
+        }
+    }
+}
+
+/// Shell commands that download the given test data directories by hand
+fn manual_download_commands(directories: &[PathBuf]) -> String {
+    let mut commands = format!(
+        "  git clone --depth=1 --branch {} https://github.com/varioustoxins/ustar.git temp_ustar\n",
+        test_data_ref()
+    );
+    for dir in directories {
+        commands.push_str(&format!(
+            "  mkdir -p {0} && cp -r temp_ustar/ustar-parser/tests/test_data/{1}/. {0}/\n",
+            dir.display(),
+            directory_name(dir)
+        ));
+    }
+    commands.push_str("  rm -rf temp_ustar\n");
+    commands
+}
+
+/// A hash algorithm test data checksums can be stored with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumAlgorithm {
+    Sha256,
+    Sha1,
+}
+
+impl ChecksumAlgorithm {
+    /// Algorithms in order of preference
+    const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Sha1];
+
+    /// The name of the checksum file holding checksums made with this algorithm
+    fn file_name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "checksums.sha256",
+            ChecksumAlgorithm::Sha1 => "checksums.sha1",
+        }
+    }
+
+    /// Calculate the hash of a file with this algorithm as lower case hex
+    fn hash_file<P: AsRef<Path>>(self, file_path: P) -> Result<String, TestDataError> {
+        match self {
+            ChecksumAlgorithm::Sha256 => hash_file::<Sha256, _>(file_path),
+            ChecksumAlgorithm::Sha1 => hash_file::<Sha1, _>(file_path),
+        }
+    }
+}
+
+/// Calculate the hash of a file with any `Digest` algorithm as lower case hex
+fn hash_file<D: Digest + std::io::Write, P: AsRef<Path>>(
+    file_path: P,
+) -> Result<String, TestDataError> {
     let mut file = fs::File::open(file_path)?;
-    let mut hasher = Sha1::new();
+    let mut hasher = D::new();
     std::io::copy(&mut file, &mut hasher)?;
     let result = hasher.finalize();
-    Ok(format!("{:x}", result))
+    Ok(result.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The checksum file for a test data directory, preferring `checksums.sha256` over
+/// `checksums.sha1` when both are present
+fn find_checksum_file(test_data_dir: &Path) -> Option<(PathBuf, ChecksumAlgorithm)> {
+    ChecksumAlgorithm::ALL.iter().find_map(|algorithm| {
+        let path = test_data_dir.join(algorithm.file_name());
+        path.exists().then_some((path, *algorithm))
+    })
 }
 
-/// Parse a single line from checksums.sha1 file
+/// Parse a single line from a checksums.sha256 or checksums.sha1 file
 fn parse_checksum_line(line: &str) -> Option<(String, String)> {
     // Expected format: "hash  filename"
     let parts: Vec<&str> = line.splitn(2, "  ").collect();
@@ -90,23 +196,29 @@ fn parse_checksum_line(line: &str) -> Option<(String, String)> {
 
 /// Verify checksums for all files in a test data directory
 ///
-/// Reads the `checksums.sha1` file and verifies each file listed.
-/// Uses the Rust `sha1` crate for reliable cross-platform verification.
+/// Reads the `checksums.sha256` file, or the `checksums.sha1` file if there isn't one,
+/// and verifies each file listed with the matching algorithm.
 pub fn verify_test_data_checksums<P: AsRef<Path>>(test_data_dir: P) -> Result<(), TestDataError> {
-    let test_data_dir = test_data_dir.as_ref();
+    verify_checksums(test_data_dir.as_ref(), false)
+}
 
+/// Verify the checksums of the files in a test data directory, skipping missing files
+/// rather than failing if `skip_missing` is set
+fn verify_checksums(test_data_dir: &Path, skip_missing: bool) -> Result<(), TestDataError> {
     if !test_data_dir.exists() {
         return Err(TestDataError::DirectoryNotFound(
             test_data_dir.display().to_string(),
         ));
     }
 
-    let checksum_file = test_data_dir.join("checksums.sha1");
-    if !checksum_file.exists() {
-        return Err(TestDataError::InvalidChecksumFile(
-            checksum_file.display().to_string(),
-        ));
-    }
+    let (checksum_file, algorithm) = find_checksum_file(test_data_dir).ok_or_else(|| {
+        TestDataError::InvalidChecksumFile(
+            test_data_dir
+                .join(ChecksumAlgorithm::Sha256.file_name())
+                .display()
+                .to_string(),
+        )
+    })?;
 
     let checksum_content = fs::read_to_string(&checksum_file)?;
 
@@ -125,14 +237,16 @@ pub fn verify_test_data_checksums<P: AsRef<Path>>(test_data_dir: P) -> Result<()
         })?;
 
         let file_path = test_data_dir.join(&filename);
-        if !file_path.exists() {
+        if !file_path.exists() && skip_missing {
+            continue;
+        } else if !file_path.exists() {
             return Err(TestDataError::DirectoryNotFound(format!(
                 "Referenced file not found: {}",
                 filename
             )));
         }
 
-        let actual_hash = calculate_file_sha1(&file_path)?;
+        let actual_hash = algorithm.hash_file(&file_path)?;
         if expected_hash != actual_hash {
             return Err(TestDataError::ChecksumMismatch {
                 file: filename,
@@ -145,9 +259,11 @@ pub fn verify_test_data_checksums<P: AsRef<Path>>(test_data_dir: P) -> Result<()
     Ok(())
 }
 
-/// Get the list of expected files from checksums.sha1
+/// Get the list of expected files from the directory's checksum file
 fn get_expected_files<P: AsRef<Path>>(test_data_dir: P) -> Result<Vec<String>, TestDataError> {
-    let checksum_file = test_data_dir.as_ref().join("checksums.sha1");
+    let test_data_dir = test_data_dir.as_ref();
+    let (checksum_file, _) = find_checksum_file(test_data_dir)
+        .ok_or_else(|| TestDataError::InvalidChecksumFile(test_data_dir.display().to_string()))?;
     let content = fs::read_to_string(&checksum_file)?;
 
     let mut files = Vec::new();
@@ -178,7 +294,7 @@ fn get_missing_files<P: AsRef<Path>>(test_data_dir: P) -> Result<Vec<String>, Te
     Ok(missing_files)
 }
 
-/// Discover all test data directories that have checksums.sha1 files
+/// Discover all test data directories that have checksums.sha256 or checksums.sha1 files
 fn discover_test_data_directories<P: AsRef<Path>>(
     base_dir: P,
 ) -> Result<Vec<std::path::PathBuf>, TestDataError> {
@@ -197,11 +313,8 @@ fn discover_test_data_directories<P: AsRef<Path>>(
         let entry = entry.map_err(TestDataError::Io)?;
         let path = entry.path();
 
-        if path.is_dir() {
-            let checksum_file = path.join("checksums.sha1");
-            if checksum_file.exists() {
-                directories.push(path);
-            }
+        if path.is_dir() && find_checksum_file(&path).is_some() {
+            directories.push(path);
         }
     }
 
@@ -211,29 +324,39 @@ fn discover_test_data_directories<P: AsRef<Path>>(
 /// Ensure test data is available and verified
 ///
 /// This function:
-/// 1. Dynamically discovers test data directories with checksums.sha1 files
+/// 1. Dynamically discovers test data directories with checksum files
 /// 2. Checks all discovered directories for missing files
-/// 3. Downloads missing data if needed (unless disabled)
+/// 3. Downloads missing data if `policy` allows it, or reports the missing files
 /// 4. Verifies checksums of all files
 ///
 /// Can be called with either a specific directory or a base directory to scan
-pub fn ensure_test_data_available<P: AsRef<Path>>(path: P) -> Result<(), TestDataError> {
+pub fn ensure_test_data_available<P: AsRef<Path>>(
+    path: P,
+    policy: TestDataPolicy,
+) -> Result<(), TestDataError> {
     let path = path.as_ref();
+    let policy = policy.effective();
 
     // Determine if this is a specific directory or base directory to scan
-    let (_base_dir, specific_dirs) = if path.join("checksums.sha1").exists() {
+    let specific_dirs = if find_checksum_file(path).is_some() {
         // This is a specific test data directory
-        (path.parent().unwrap_or(path), vec![path.to_path_buf()])
+        vec![path.to_path_buf()]
     } else {
         // This is a base directory - discover all test data directories
-        let discovered = discover_test_data_directories(path)?;
-        (path, discovered)
+        discover_test_data_directories(path)?
     };
 
     if specific_dirs.is_empty() {
         return Ok(()); // No test data directories found, nothing to do
     }
 
+    if policy == TestDataPolicy::VerifyOnly {
+        for dir in &specific_dirs {
+            verify_checksums(dir, true)?;
+        }
+        return Ok(());
+    }
+
     // Check all directories for missing files
     let mut all_missing_files = Vec::new();
     let mut dirs_with_missing = Vec::new();
@@ -243,7 +366,7 @@ pub fn ensure_test_data_available<P: AsRef<Path>>(path: P) -> Result<(), TestDat
         if !missing_files.is_empty() {
             println!(
                 "Missing files in {}: {}",
-                dir.file_name().unwrap_or_default().to_string_lossy(),
+                directory_name(dir),
                 missing_files.join(", ")
             );
             all_missing_files.extend(missing_files.iter().cloned());
@@ -253,44 +376,45 @@ pub fn ensure_test_data_available<P: AsRef<Path>>(path: P) -> Result<(), TestDat
 
     // If any files are missing, attempt download
     if !all_missing_files.is_empty() {
-        if !cfg!(feature = "no-large-tests") {
-            println!("Attempting to download test data from GitHub...");
-
-            match download_test_data_from_github() {
-                Ok(_) => {
-                    println!("Test data download completed successfully!");
-
-                    // Re-check all directories that had missing files
-                    for dir in &dirs_with_missing {
-                        let still_missing = get_missing_files(dir)?;
-                        if !still_missing.is_empty() {
-                            return Err(TestDataError::DirectoryNotFound(format!(
-                                "Download completed but still missing files in {}: {}",
-                                dir.display(),
-                                still_missing.join(", ")
-                            )));
-                        }
+        if policy == TestDataPolicy::Offline {
+            return Err(TestDataError::OfflineMissing {
+                directories: dirs_with_missing,
+                files: all_missing_files,
+            });
+        }
+
+        println!(
+            "Attempting to download test data for {} from GitHub...",
+            test_data_ref()
+        );
+
+        match download_test_data_from_github(&dirs_with_missing) {
+            Ok(_) => {
+                println!("Test data download completed successfully!");
+
+                // Re-check all directories that had missing files
+                for dir in &dirs_with_missing {
+                    let still_missing = get_missing_files(dir)?;
+                    if !still_missing.is_empty() {
+                        return Err(TestDataError::DirectoryNotFound(format!(
+                            "Download completed but still missing files in {}: {}",
+                            dir.display(),
+                            still_missing.join(", ")
+                        )));
                     }
                 }
-                Err(e) => {
-                    eprintln!("Error: Failed to download test data: {}", e);
-                    eprintln!("To skip large tests, run: cargo test --features no-large-tests");
-                    eprintln!("To download manually:");
-                    eprintln!("  git clone --depth=1 https://github.com/varioustoxins/ustar.git temp_ustar");
-                    eprintln!("  cp -r temp_ustar/ustar-parser/tests/test_data/* <your-project>/tests/test_data/");
-                    eprintln!("  rm -rf temp_ustar");
-
-                    return Err(TestDataError::DirectoryNotFound(format!(
-                        "Missing test data files and download failed: {}",
-                        e
-                    )));
-                }
             }
-        } else {
-            return Err(TestDataError::DirectoryNotFound(format!(
-                "Missing test data files: {}. Download disabled by --features no-large-tests.",
-                all_missing_files.join(", ")
-            )));
+            Err(e) => {
+                eprintln!("Error: Failed to download test data: {}", e);
+                eprintln!("To skip downloading, set USTAR_OFFLINE=1 or run: cargo test --features no-large-tests");
+                eprintln!("To download manually:");
+                eprint!("{}", manual_download_commands(&dirs_with_missing));
+
+                return Err(TestDataError::DirectoryNotFound(format!(
+                    "Missing test data files and download failed: {}",
+                    e
+                )));
+            }
         }
     }
 
@@ -302,91 +426,312 @@ pub fn ensure_test_data_available<P: AsRef<Path>>(path: P) -> Result<(), TestDat
     Ok(())
 }
 
-static DOWNLOAD_RESULT: OnceLock<Result<(), String>> = OnceLock::new();
+/// Results of the downloads attempted in this process, by test data directory name
+static DOWNLOAD_RESULTS: Mutex<Option<HashMap<String, Result<(), String>>>> = Mutex::new(None);
+
+/// How often to report download progress
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Report extraction progress each time this many more files are extracted from a directory
+const EXTRACTION_PROGRESS_FILES: usize = 50;
 
-/// Download missing test data from GitHub repository
-/// Uses sparse checkout to only download test data directories
-fn download_test_data_from_github() -> Result<(), Box<dyn std::error::Error>> {
-    // Ensure download only happens once per test run using OnceLock
-    let result = DOWNLOAD_RESULT.get_or_init(|| perform_download().map_err(|e| e.to_string()));
+/// A download lock older than this is assumed to be left over from a killed process
+const STALE_LOCK_AGE: Duration = Duration::from_secs(60 * 60);
 
-    // Return the cached result
-    result.clone().map_err(|e| e.into())
+/// Environment variable naming the git ref (tag, branch or commit) to download test data from
+const TEST_DATA_REF_VAR: &str = "USTAR_TEST_DATA_REF";
+
+/// The git ref to download test data from: `USTAR_TEST_DATA_REF` if it's set, otherwise
+/// the release tag for this crate's version, so released crates get the data they were tested with
+fn test_data_ref() -> String {
+    select_test_data_ref(
+        std::env::var(TEST_DATA_REF_VAR).ok().as_deref(),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Choose the git ref to download from, given any override and the crate version
+fn select_test_data_ref(override_ref: Option<&str>, version: &str) -> String {
+    match override_ref.map(str::trim) {
+        Some(git_ref) if !git_ref.is_empty() => git_ref.to_string(),
+        _ => format!("v{}", version),
+    }
+}
+
+/// The URL of the GitHub archive of the repository at a git ref
+fn archive_url(git_ref: &str) -> String {
+    format!(
+        "https://github.com/varioustoxins/ustar/archive/{}.zip",
+        git_ref
+    )
+}
+
+/// Download the missing files in the given test data directories from the GitHub repository.
+/// Each directory is only attempted once per test run, and the result of that attempt reused.
+fn download_test_data_from_github(
+    directories: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut results = DOWNLOAD_RESULTS.lock().unwrap_or_else(|e| e.into_inner());
+    let results = results.get_or_insert_with(HashMap::new);
+
+    let pending: Vec<PathBuf> = directories
+        .iter()
+        .filter(|dir| !results.contains_key(&directory_name(dir)))
+        .cloned()
+        .collect();
+    if !pending.is_empty() {
+        let result = perform_download(&pending).map_err(|e| e.to_string());
+        for dir in &pending {
+            results.insert(directory_name(dir), result.clone());
+        }
+    }
+
+    for dir in directories {
+        if let Some(Err(e)) = results.get(&directory_name(dir)) {
+            return Err(e.clone().into());
+        }
+    }
+    Ok(())
+}
+
+/// The name of a test data directory, as it appears under `tests/test_data/` in the archive
+fn directory_name(dir: &Path) -> String {
+    dir.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
 }
 
 /// Perform the actual test data download from GitHub
-fn perform_download() -> Result<(), Box<dyn std::error::Error>> {
+fn perform_download(directories: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    // Determine current package root directory for target
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    let target_base = Path::new(&manifest_dir).join("tests").join("test_data");
+    fs::create_dir_all(&target_base)?;
+
+    // Another test process may have downloaded the files while we waited for the lock
+    let _lock = DownloadLock::acquire(&target_base)?;
+    let still_missing: Vec<String> = directories
+        .iter()
+        .filter(|dir| get_missing_files(dir).map_or(true, |missing| !missing.is_empty()))
+        .map(|dir| directory_name(dir))
+        .collect();
+    if still_missing.is_empty() {
+        println!("Test data was downloaded by another test process");
+        return Ok(());
+    }
+
     // Use tokio runtime for async operations
     let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(download_github_archive())
+    let zip_bytes = rt.block_on(download_github_archive())?;
+
+    let extracted_files = extract_test_data(Cursor::new(zip_bytes), &target_base, &still_missing)?;
+    println!(
+        "Extracted {} files from {} directories",
+        extracted_files,
+        still_missing.len()
+    );
+    Ok(())
 }
 
-/// Download and extract test data from GitHub ZIP archive
-async fn download_github_archive() -> Result<(), Box<dyn std::error::Error>> {
-    let archive_url = "https://github.com/varioustoxins/ustar/archive/refs/heads/main.zip";
+/// A lock file in the test data directory, held while downloading so concurrent test
+/// processes don't download the same files; it's removed when dropped
+struct DownloadLock {
+    path: PathBuf,
+}
+
+impl DownloadLock {
+    /// Create the lock file, waiting for any other process holding it to finish
+    fn acquire(target_base: &Path) -> std::io::Result<Self> {
+        let path = target_base.join(".download.lock");
+        let mut reported_wait = false;
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(DownloadLock { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if Self::is_stale(&path) {
+                        println!("Removing stale download lock {}", path.display());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if !reported_wait {
+                        println!(
+                            "Waiting for another test process to finish downloading test data ({})...",
+                            path.display()
+                        );
+                        reported_wait = true;
+                    }
+                    std::thread::sleep(Duration::from_millis(500));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
 
-    println!("Downloading repository archive from GitHub...");
+    fn is_stale(path: &Path) -> bool {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_LOCK_AGE)
+    }
+}
+
+impl Drop for DownloadLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Download the GitHub ZIP archive of the repository into memory, reporting progress.
+/// GitHub only serves archives of the whole repository, so unneeded directories are
+/// skipped when extracting rather than when downloading.
+async fn download_github_archive() -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let git_ref = test_data_ref();
+    let archive_url = archive_url(&git_ref);
+
+    println!(
+        "Downloading repository archive for {} from GitHub...",
+        git_ref
+    );
 
     // Download the ZIP archive directly to memory (no temporary files needed)
-    let response = reqwest::get(archive_url).await?;
+    let mut response = reqwest::get(&archive_url).await?;
     if !response.status().is_success() {
-        return Err(format!("Failed to download archive: HTTP {}", response.status()).into());
+        return Err(format!(
+            "Failed to download archive {}: HTTP {} (set {} to a tag, branch or commit to use another ref)",
+            archive_url,
+            response.status(),
+            TEST_DATA_REF_VAR
+        )
+        .into());
+    }
+
+    let total = response.content_length();
+    let mut zip_bytes = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut last_report = Instant::now();
+    while let Some(chunk) = response.chunk().await? {
+        zip_bytes.extend_from_slice(&chunk);
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            println!("{}", download_progress(zip_bytes.len() as u64, total));
+            last_report = Instant::now();
+        }
     }
 
-    let zip_bytes = response.bytes().await?;
     println!(
-        "Downloaded {} bytes, extracting test data...",
-        zip_bytes.len()
+        "{}, extracting test data...",
+        download_progress(zip_bytes.len() as u64, total)
     );
+    Ok(zip_bytes)
+}
 
-    // Extract test data from ZIP
-    let cursor = Cursor::new(zip_bytes);
-    let mut archive = zip::ZipArchive::new(cursor)?;
+/// A progress line for a download, such as `downloaded 12/300 MB`
+fn download_progress(downloaded: u64, total: Option<u64>) -> String {
+    const MB: u64 = 1024 * 1024;
+    match total {
+        Some(total) => format!("downloaded {}/{} MB", downloaded / MB, total.div_ceil(MB)),
+        None => format!("downloaded {} MB", downloaded / MB),
+    }
+}
 
-    // Determine current package root directory for target
-    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
-    let target_base = format!("{}/tests/test_data", manifest_dir);
-
-    // Create target directory if it doesn't exist
-    fs::create_dir_all(&target_base)?;
+/// Extract the files in the named test data directories from a repository ZIP archive into
+/// `target_base`, returning how many files were extracted
+fn extract_test_data<R: Read + Seek>(
+    reader: R,
+    target_base: &Path,
+    directories: &[String],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let wanted: HashSet<&str> = directories.iter().map(String::as_str).collect();
 
     let mut extracted_files = 0;
-    let mut extracted_dirs = std::collections::HashSet::new();
+    let mut files_per_dir: HashMap<String, usize> = HashMap::new();
 
-    // Extract files from the archive
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        let file_path = file.name();
 
-        // Only extract files from ustar-main/ustar-parser/tests/test_data/
-        if let Some(relative_path) = extract_test_data_path(file_path) {
-            let target_path = format!("{}/{}", target_base, relative_path);
+        // Only extract files from the wanted directories under tests/test_data/
+        let Some(relative_path) = extract_test_data_path(file.name()) else {
+            continue;
+        };
+        let Some(dir) = relative_path.split('/').next() else {
+            continue;
+        };
+        if !wanted.contains(dir) {
+            continue;
+        }
 
-            // Track which directories we're extracting
-            if let Some(dir) = relative_path.split('/').next() {
-                if extracted_dirs.insert(dir.to_string()) {
-                    println!("Extracting {}...", dir);
-                }
-            }
+        let count = files_per_dir.entry(dir.to_string()).or_insert(0);
+        if *count == 0 {
+            println!("Extracting {}...", dir);
+        }
 
-            // Create parent directories
-            if let Some(parent) = Path::new(&target_path).parent() {
-                fs::create_dir_all(parent)?;
-            }
+        let target_path = target_base.join(&relative_path);
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut target_file = fs::File::create(&target_path)?;
+        std::io::copy(&mut file, &mut target_file)?;
 
-            // Extract the file
-            let mut target_file = fs::File::create(&target_path)?;
-            std::io::copy(&mut file, &mut target_file)?;
-            extracted_files += 1;
+        *count += 1;
+        extracted_files += 1;
+        if *count % EXTRACTION_PROGRESS_FILES == 0 {
+            println!("extracting {}… {} files", dir, count);
         }
     }
 
-    println!(
-        "Extracted {} files from {} directories",
-        extracted_files,
-        extracted_dirs.len()
-    );
-    Ok(())
+    Ok(extracted_files)
+}
+
+/// Write `checksums.sha256` and `checksums.sha1` files listing the test data files
+/// (`.str`, `.cif`, `.nef`, `.dic` and `.mmcif`) in a directory, returning how many files
+/// were listed. The files are in `shasum` format, sorted like the output of
+/// `scripts/generate-checksums.sh`.
+pub fn generate_checksums<P: AsRef<Path>>(test_data_dir: P) -> Result<usize, TestDataError> {
+    const EXTENSIONS: [&str; 5] = ["str", "cif", "nef", "dic", "mmcif"];
+
+    let test_data_dir = test_data_dir.as_ref();
+    if !test_data_dir.is_dir() {
+        return Err(TestDataError::DirectoryNotFound(
+            test_data_dir.display().to_string(),
+        ));
+    }
+
+    let mut file_names = Vec::new();
+    for entry in fs::read_dir(test_data_dir)? {
+        let path = entry?.path();
+        let is_test_data = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension));
+        if path.is_file() && is_test_data {
+            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
+                file_names.push(file_name.to_string());
+            }
+        }
+    }
+
+    for algorithm in ChecksumAlgorithm::ALL {
+        let mut lines = file_names
+            .iter()
+            .map(|file_name| {
+                let hash = algorithm.hash_file(test_data_dir.join(file_name))?;
+                Ok(format!("{}  {}\n", hash, file_name))
+            })
+            .collect::<Result<Vec<String>, TestDataError>>()?;
+        lines.sort();
+        fs::write(test_data_dir.join(algorithm.file_name()), lines.concat())?;
+    }
+
+    Ok(file_names.len())
 }
 
 /// Extract the test data relative path from a ZIP archive path
@@ -487,6 +832,262 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sha256_verification() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("test.txt"), "Hello, world!").unwrap();
+
+        // The SHA-256 hash of "Hello, world!"
+        let expected_hash = "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3";
+        fs::write(
+            temp_dir.path().join("checksums.sha256"),
+            format!("{}  test.txt\n", expected_hash),
+        )
+        .unwrap();
+        assert!(verify_test_data_checksums(temp_dir.path()).is_ok());
+
+        let wrong_hash = "0".repeat(64);
+        fs::write(
+            temp_dir.path().join("checksums.sha256"),
+            format!("{}  test.txt\n", wrong_hash),
+        )
+        .unwrap();
+        match verify_test_data_checksums(temp_dir.path()).unwrap_err() {
+            TestDataError::ChecksumMismatch { actual, .. } => assert_eq!(actual, expected_hash),
+            error => panic!("Expected ChecksumMismatch error, got {}", error),
+        }
+    }
+
+    #[test]
+    fn test_sha256_preferred_over_sha1() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("test.txt"), "Hello, world!").unwrap();
+
+        // A stale SHA-1 checksum is ignored when there's a SHA-256 checksum file
+        fs::write(
+            temp_dir.path().join("checksums.sha1"),
+            format!("{}  test.txt\n", "0".repeat(40)),
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("checksums.sha256"),
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3  test.txt\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_checksum_file(temp_dir.path()).map(|(_, algorithm)| algorithm),
+            Some(ChecksumAlgorithm::Sha256)
+        );
+        assert!(verify_test_data_checksums(temp_dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_generate_checksums_writes_both_files() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("b.str"), "Hello, world!").unwrap();
+        fs::write(temp_dir.path().join("a.cif"), "data_a").unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), "not test data").unwrap();
+
+        assert_eq!(generate_checksums(temp_dir.path()).unwrap(), 2);
+
+        let sha256 = fs::read_to_string(temp_dir.path().join("checksums.sha256")).unwrap();
+        assert!(sha256
+            .contains("315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3  b.str\n"));
+        let sha1 = fs::read_to_string(temp_dir.path().join("checksums.sha1")).unwrap();
+        assert!(sha1.contains("943a702d06f34599aee1f8da8ef9f7296031d699  b.str\n"));
+        assert!(!sha1.contains("notes.txt"));
+
+        assert!(verify_test_data_checksums(temp_dir.path()).is_ok());
+        fs::remove_file(temp_dir.path().join("checksums.sha256")).unwrap();
+        assert!(verify_test_data_checksums(temp_dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_test_data_ref_selection() {
+        assert_eq!(select_test_data_ref(None, "0.1.4"), "v0.1.4");
+        assert_eq!(select_test_data_ref(Some(""), "0.1.4"), "v0.1.4");
+        assert_eq!(select_test_data_ref(Some("main"), "0.1.4"), "main");
+        assert_eq!(
+            select_test_data_ref(Some(" refs/heads/main "), "0.1.4"),
+            "refs/heads/main"
+        );
+
+        assert_eq!(
+            archive_url("v0.1.4"),
+            "https://github.com/varioustoxins/ustar/archive/v0.1.4.zip"
+        );
+    }
+
+    /// An in-memory repository archive laid out like GitHub's, with two test data directories
+    fn archive_fixture() -> Cursor<Vec<u8>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in [
+            ("ustar-0.1.4/README.md", "readme"),
+            (
+                "ustar-0.1.4/ustar-parser/tests/test_data/nef_examples/a.nef",
+                "data_a",
+            ),
+            (
+                "ustar-0.1.4/ustar-parser/tests/test_data/nef_examples/sub/b.nef",
+                "data_b",
+            ),
+            (
+                "ustar-0.1.4/ustar-parser/tests/test_data/bmrb_stars/c.str",
+                "data_c",
+            ),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer
+            .add_directory(
+                "ustar-0.1.4/ustar-parser/tests/test_data/cod_cifs/",
+                options,
+            )
+            .unwrap();
+        Cursor::new(writer.finish().unwrap().into_inner())
+    }
+
+    #[test]
+    fn test_extract_only_requested_directories() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let extracted = extract_test_data(
+            archive_fixture(),
+            temp_dir.path(),
+            &["nef_examples".to_string(), "cod_cifs".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(extracted, 2);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("nef_examples/a.nef")).unwrap(),
+            "data_a"
+        );
+        assert!(temp_dir.path().join("nef_examples/sub/b.nef").exists());
+        assert!(!temp_dir.path().join("bmrb_stars").exists());
+        assert!(!temp_dir.path().join("README.md").exists());
+    }
+
+    #[test]
+    fn test_download_progress() {
+        let mb = 1024 * 1024;
+        assert_eq!(
+            download_progress(12 * mb + 5, Some(300 * mb)),
+            "downloaded 12/300 MB"
+        );
+        assert_eq!(download_progress(3 * mb, None), "downloaded 3 MB");
+    }
+
+    #[test]
+    fn test_download_lock_is_exclusive_and_released() {
+        let temp_dir = TempDir::new().unwrap();
+        let lock_path = temp_dir.path().join(".download.lock");
+
+        let lock = DownloadLock::acquire(temp_dir.path()).unwrap();
+        assert!(lock_path.exists());
+        assert!(fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .is_err());
+
+        drop(lock);
+        assert!(!lock_path.exists());
+        drop(DownloadLock::acquire(temp_dir.path()).unwrap());
+    }
+
+    /// A test data directory with one good file, one corrupted file and one missing file
+    fn policy_fixture() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("nef_examples");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("good.nef"), "Hello, world!").unwrap();
+        fs::write(
+            dir.join("checksums.sha1"),
+            "943a702d06f34599aee1f8da8ef9f7296031d699  good.nef\n\
+             943a702d06f34599aee1f8da8ef9f7296031d699  missing.nef\n",
+        )
+        .unwrap();
+        temp_dir
+    }
+
+    #[test]
+    fn test_offline_policy_reports_missing_files() {
+        let temp_dir = policy_fixture();
+        let dir = temp_dir.path().join("nef_examples");
+
+        let error =
+            ensure_test_data_available(temp_dir.path(), TestDataPolicy::Offline).unwrap_err();
+        match &error {
+            TestDataError::OfflineMissing { directories, files } => {
+                assert_eq!(directories, &vec![dir.clone()]);
+                assert_eq!(files, &vec!["missing.nef".to_string()]);
+            }
+            error => panic!("Expected OfflineMissing error, got {}", error),
+        }
+
+        let message = error.to_string();
+        assert!(message.contains("missing.nef"));
+        assert!(message.contains(&format!(
+            "cp -r temp_ustar/ustar-parser/tests/test_data/nef_examples/. {}/",
+            dir.display()
+        )));
+    }
+
+    #[test]
+    fn test_offline_policy_verifies_complete_data() {
+        let temp_dir = policy_fixture();
+        let dir = temp_dir.path().join("nef_examples");
+        fs::write(dir.join("missing.nef"), "Hello, world!").unwrap();
+        assert!(ensure_test_data_available(&dir, TestDataPolicy::Offline).is_ok());
+
+        fs::write(dir.join("good.nef"), "corrupted").unwrap();
+        assert!(matches!(
+            ensure_test_data_available(&dir, TestDataPolicy::Offline),
+            Err(TestDataError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_only_policy_skips_missing_files() {
+        let temp_dir = policy_fixture();
+        let dir = temp_dir.path().join("nef_examples");
+        assert!(ensure_test_data_available(&dir, TestDataPolicy::VerifyOnly).is_ok());
+
+        fs::write(dir.join("good.nef"), "corrupted").unwrap();
+        match ensure_test_data_available(&dir, TestDataPolicy::VerifyOnly).unwrap_err() {
+            TestDataError::ChecksumMismatch { file, .. } => assert_eq!(file, "good.nef"),
+            error => panic!("Expected ChecksumMismatch error, got {}", error),
+        }
+    }
+
+    #[test]
+    fn test_download_policy_with_complete_data_does_not_download() {
+        let temp_dir = policy_fixture();
+        let dir = temp_dir.path().join("nef_examples");
+        fs::write(dir.join("missing.nef"), "Hello, world!").unwrap();
+        assert!(ensure_test_data_available(&dir, TestDataPolicy::DownloadIfMissing).is_ok());
+    }
+
+    #[test]
+    fn test_disabling_downloads_makes_download_policy_offline() {
+        assert_eq!(
+            TestDataPolicy::DownloadIfMissing.with_downloads_disabled(true),
+            TestDataPolicy::Offline
+        );
+        assert_eq!(
+            TestDataPolicy::DownloadIfMissing.with_downloads_disabled(false),
+            TestDataPolicy::DownloadIfMissing
+        );
+        assert_eq!(
+            TestDataPolicy::VerifyOnly.with_downloads_disabled(true),
+            TestDataPolicy::VerifyOnly
+        );
+    }
+
     #[test]
     fn test_parse_checksum_line() {
         assert_eq!(
@@ -3,9 +3,9 @@ use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use ustar_tools::downloader_common::{
-    CommonDownloaderCli, DataSource, DownloadError, DownloaderConfig, GenericDownloader,
-    HttpClient, ReqwestClient,
+    CommonDownloaderCli, DataSource, DownloadError, GenericDownloader, HttpClient, ReqwestClient,
 };
 
 /// BMRB-specific data source implementation
@@ -15,10 +15,10 @@ pub struct BmrbDataSource {
 }
 
 impl BmrbDataSource {
-    pub fn new(verbose: bool) -> Self {
+    pub fn new(verbose: bool, timeout: Duration) -> Self {
         Self {
             verbose,
-            http_client: Arc::new(ReqwestClient),
+            http_client: Arc::new(ReqwestClient::new().timeout(timeout)),
         }
     }
 
@@ -29,6 +29,14 @@ impl BmrbDataSource {
             http_client: client,
         }
     }
+
+    /// URL of an entry's file
+    fn url_for(&self, entry_id: &str) -> String {
+        format!(
+            "https://bmrb.io/ftp/pub/bmrb/entry_directories/{}/{}.str",
+            entry_id, entry_id
+        )
+    }
 }
 
 impl DataSource for BmrbDataSource {
@@ -72,10 +80,7 @@ impl DataSource for BmrbDataSource {
         entry_id: &str,
         output_path: &PathBuf,
     ) -> Result<PathBuf, DownloadError> {
-        let url = format!(
-            "https://bmrb.io/ftp/pub/bmrb/entry_directories/{}/{}.str",
-            entry_id, entry_id
-        );
+        let url = self.url_for(entry_id);
 
         if self.verbose {
             println!(
@@ -104,6 +109,10 @@ impl DataSource for BmrbDataSource {
 
         Ok(output_path.clone())
     }
+
+    fn entry_url(&self, entry_id: &str) -> Option<String> {
+        Some(self.url_for(entry_id))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -122,6 +131,14 @@ impl Default for Cli {
                 verbose: false,
                 list: false,
                 seed: 42,
+                retries: 3,
+                timeout: 60,
+                concurrency: 4,
+                max_requests_per_second: None,
+                validate: false,
+                ids: Vec::new(),
+                ids_file: None,
+                force: false,
             },
         }
     }
@@ -130,12 +147,9 @@ impl Default for Cli {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
-    let config = DownloaderConfig::new()
-        .output_dir(&cli.common.output_dir)
-        .verbose(cli.common.verbose)
-        .file_extension("str");
+    let config = cli.common.config().file_extension("str");
 
-    let data_source = BmrbDataSource::new(cli.common.verbose);
+    let data_source = BmrbDataSource::new(cli.common.verbose, config.request_timeout);
     let downloader = GenericDownloader::new(config, data_source);
 
     if cli.common.list {
@@ -143,19 +157,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    if cli.common.verbose {
-        println!(
-            "[VERBOSE] Downloading {} unique random BMRB files to {}...",
-            cli.common.count, cli.common.output_dir
-        );
+    let ids = cli.common.requested_ids()?;
+    let report = if ids.is_empty() {
+        if cli.common.verbose {
+            println!(
+                "[VERBOSE] Downloading {} unique random BMRB files to {}...",
+                cli.common.count, cli.common.output_dir
+            );
+        } else {
+            println!(
+                "Downloading {} unique random BMRB files to {}...",
+                cli.common.count, cli.common.output_dir
+            );
+        }
+
+        downloader.download_batch(cli.common.count, cli.common.seed)?
     } else {
         println!(
-            "Downloading {} unique random BMRB files to {}...",
-            cli.common.count, cli.common.output_dir
+            "Downloading {} BMRB entries by id to {}...",
+            ids.len(),
+            cli.common.output_dir
         );
-    }
-
-    let batch = downloader.download_unique_random_batch(cli.common.count, cli.common.seed)?;
+        downloader.download_ids(&ids)?
+    };
+    let batch = report.downloaded;
 
     if cli.common.verbose {
         println!("[VERBOSE] Downloaded {} files:", batch.len());
@@ -168,5 +193,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if !report.failed.is_empty() {
+        eprintln!("Failed to download {} entries:", report.failed.len());
+        for (id, e) in &report.failed {
+            eprintln!("  {}: {}", id, e);
+        }
+    }
+
+    if !report.not_found.is_empty() {
+        eprintln!(
+            "Not found, use --force to download anyway: {}",
+            report.not_found.join(" ")
+        );
+    }
+
     Ok(())
 }
@@ -1,12 +1,13 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use ustar_tools::downloader_common::{
-    CommonDownloaderCli, DataSource, DownloadError, DownloaderConfig, GenericDownloader,
-    HttpClient, ReqwestClient,
+    CommonDownloaderCli, DataSource, DownloadError, GenericDownloader, HttpClient, ReqwestClient,
 };
+use ustar_tools::pdbe_source::PdbeSource;
 
 /// PDB-specific data source implementation
 pub struct PdbDataSource {
@@ -17,12 +18,12 @@ pub struct PdbDataSource {
 }
 
 impl PdbDataSource {
-    pub fn new(compressed: bool, verbose: bool) -> Self {
+    pub fn new(compressed: bool, verbose: bool, timeout: Duration) -> Self {
         Self {
             base_url: "https://files.rcsb.org/download".to_string(),
             compressed,
             verbose,
-            http_client: Arc::new(ReqwestClient),
+            http_client: Arc::new(ReqwestClient::new().timeout(timeout)),
         }
     }
 
@@ -66,11 +67,8 @@ impl DataSource for PdbDataSource {
     ) -> Result<PathBuf, DownloadError> {
         let pdb_id = pdb_id.to_lowercase();
 
-        let (extension, url) = if self.compressed {
-            ("cif.gz", format!("{}/{}.cif.gz", self.base_url, pdb_id))
-        } else {
-            ("cif", format!("{}/{}.cif", self.base_url, pdb_id))
-        };
+        let extension = self.extension();
+        let url = self.url_for(&pdb_id);
 
         if self.verbose {
             println!(
@@ -95,9 +93,26 @@ impl DataSource for PdbDataSource {
             }
         }
     }
+
+    fn entry_url(&self, pdb_id: &str) -> Option<String> {
+        Some(self.url_for(&pdb_id.to_lowercase()))
+    }
 }
 
 impl PdbDataSource {
+    fn extension(&self) -> &'static str {
+        if self.compressed {
+            "cif.gz"
+        } else {
+            "cif"
+        }
+    }
+
+    /// URL of an entry's file, given its lowercase id
+    fn url_for(&self, pdb_id: &str) -> String {
+        format!("{}/{}.{}", self.base_url, pdb_id, self.extension())
+    }
+
     fn save_content(
         &self,
         content: &[u8],
@@ -131,6 +146,18 @@ struct Cli {
     /// Download compressed .cif.gz files
     #[arg(long)]
     compressed: bool,
+    /// Archive to download from
+    #[arg(long, value_enum, default_value_t = Mirror::Rcsb)]
+    mirror: Mirror,
+}
+
+/// Archives for --mirror
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Mirror {
+    /// RCSB PDB in the US
+    Rcsb,
+    /// PDBe at the EBI, downloading gzipped files
+    Pdbe,
 }
 
 impl Default for Cli {
@@ -142,8 +169,17 @@ impl Default for Cli {
                 verbose: false,
                 list: false,
                 seed: 42,
+                retries: 3,
+                timeout: 60,
+                concurrency: 4,
+                max_requests_per_second: None,
+                validate: false,
+                ids: Vec::new(),
+                ids_file: None,
+                force: false,
             },
             compressed: false,
+            mirror: Mirror::Rcsb,
         }
     }
 }
@@ -157,32 +193,56 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "cif".to_string()
     };
 
-    let config = DownloaderConfig::new()
-        .output_dir(&cli.common.output_dir)
-        .verbose(cli.common.verbose)
-        .file_extension(file_extension);
+    let config = cli.common.config().file_extension(file_extension);
 
-    let data_source = PdbDataSource::new(cli.compressed, cli.common.verbose);
-    let downloader = GenericDownloader::new(config, data_source);
+    match cli.mirror {
+        Mirror::Rcsb => {
+            let data_source =
+                PdbDataSource::new(cli.compressed, cli.common.verbose, config.request_timeout);
+            run(&cli, GenericDownloader::new(config, data_source))
+        }
+        Mirror::Pdbe => {
+            let data_source = PdbeSource::new(cli.common.verbose, config.request_timeout)
+                .compressed(cli.compressed);
+            run(&cli, GenericDownloader::new(config, data_source))
+        }
+    }
+}
 
+/// List or download entries with the downloader for the chosen mirror
+fn run<T: DataSource + Sync>(
+    cli: &Cli,
+    downloader: GenericDownloader<T>,
+) -> Result<(), Box<dyn std::error::Error>> {
     if cli.common.list {
         downloader.list_files()?;
         return Ok(());
     }
 
-    if cli.common.verbose {
-        println!(
-            "[VERBOSE] Downloading {} unique random mmCIF files to {}...",
-            cli.common.count, cli.common.output_dir
-        );
+    let ids = cli.common.requested_ids()?;
+    let report = if ids.is_empty() {
+        if cli.common.verbose {
+            println!(
+                "[VERBOSE] Downloading {} unique random mmCIF files to {}...",
+                cli.common.count, cli.common.output_dir
+            );
+        } else {
+            println!(
+                "Downloading {} unique random mmCIF files to {}...",
+                cli.common.count, cli.common.output_dir
+            );
+        }
+
+        downloader.download_batch(cli.common.count, cli.common.seed)?
     } else {
         println!(
-            "Downloading {} unique random mmCIF files to {}...",
-            cli.common.count, cli.common.output_dir
+            "Downloading {} PDB entries by id to {}...",
+            ids.len(),
+            cli.common.output_dir
         );
-    }
-
-    let batch = downloader.download_unique_random_batch(cli.common.count, cli.common.seed)?;
+        downloader.download_ids(&ids)?
+    };
+    let batch = report.downloaded;
 
     if cli.common.verbose {
         println!("[VERBOSE] Downloaded {} files:", batch.len());
@@ -195,5 +255,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if !report.failed.is_empty() {
+        eprintln!("Failed to download {} entries:", report.failed.len());
+        for (id, e) in &report.failed {
+            eprintln!("  {}: {}", id, e);
+        }
+    }
+
+    if !report.not_found.is_empty() {
+        eprintln!(
+            "Not found, use --force to download anyway: {}",
+            report.not_found.join(" ")
+        );
+    }
+
     Ok(())
 }
@@ -1,90 +1,143 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use std::collections::HashSet;
 use std::fs;
 use ustar_parser::line_column_index::LineColumn;
-use ustar_parser::sas_interface::SASContentHandler;
+use ustar_parser::mutable_pair::MutablePair;
+use ustar_parser::sas_interface::{
+    CommentKind, FilterHandler, SASContentHandler, ValueDelimiter, WalkControl,
+};
 use ustar_parser::sas_walker::StarWalker;
 use ustar_parser::{default_config, get_context_lines, get_error_format, parse};
 
+/// The kinds of SAS event, as named by --only, the JSON "event" key and the counts
+const EVENT_KINDS: [&str; 15] = [
+    "start_stream",
+    "end_stream",
+    "start_global",
+    "end_global",
+    "start_data",
+    "end_data",
+    "start_saveframe",
+    "end_saveframe",
+    "start_loop",
+    "end_loop",
+    "loop_definition",
+    "start_loop_row",
+    "end_loop_row",
+    "comment",
+    "data",
+];
+
 struct DemoHandler {
     depth: usize,
 }
 
 impl SASContentHandler for DemoHandler {
-    fn start_stream(&mut self, name: Option<&str>) -> bool {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
         match name {
             Some(n) => println!("<start_stream> {}", n),
             None => println!("<start_stream>"),
         }
-        false
+        WalkControl::Continue
     }
 
-    fn end_stream(&mut self, position: LineColumn) -> bool {
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
         println!("<end_stream> [{}:{}]", position.line, position.column);
-        false
+        WalkControl::Continue
     }
 
-    fn start_global(&mut self, position: LineColumn) -> bool {
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         println!("{}<start global> [{}]", indent, position.line);
         self.depth += 1;
-        false
+        WalkControl::Continue
     }
 
-    fn end_global(&mut self, position: LineColumn) -> bool {
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
         if self.depth > 0 {
             self.depth -= 1;
         }
         let indent = "    ".repeat(self.depth);
         println!("{}<end global> [{}]", indent, position.line);
-        false
+        WalkControl::Continue
     }
 
-    fn start_data(&mut self, position: LineColumn, name: &str) -> bool {
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         println!("{}<start data> [{}] {}", indent, position.line, name);
         self.depth += 1;
-        false
+        WalkControl::Continue
     }
-    fn end_data(&mut self, position: LineColumn, name: &str) -> bool {
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
         if self.depth > 0 {
             self.depth -= 1;
         }
         let indent = "    ".repeat(self.depth);
         println!("{}<end data> [{}] {}", indent, position.line, name);
-        false
+        WalkControl::Continue
     }
-    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> bool {
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         println!("{}<start saveframe> [{}] {}", indent, position.line, name);
         self.depth += 1;
-        false
+        WalkControl::Continue
     }
-    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> bool {
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
         if self.depth > 0 {
             self.depth -= 1;
         }
         let indent = "    ".repeat(self.depth);
         println!("{}<end saveframe> [{}] {}", indent, position.line, name);
-        false
+        WalkControl::Continue
     }
-    fn start_loop(&mut self, position: LineColumn) -> bool {
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         println!("{}<start_loop> [{}]", indent, position.line);
         self.depth += 1;
-        false
+        WalkControl::Continue
     }
-    fn end_loop(&mut self, position: LineColumn) -> bool {
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
         if self.depth > 0 {
             self.depth -= 1;
         }
         let indent = "    ".repeat(self.depth);
         println!("{}<end_loop> [{}]", indent, position.line);
-        false
+        WalkControl::Continue
     }
-    fn comment(&mut self, position: LineColumn, text: &str) -> bool {
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        let indent = "    ".repeat(self.depth);
+        println!(
+            "{}<loop_definition> [{}] level: {} tags: {}",
+            indent,
+            position.line,
+            nesting_level,
+            tags.join(", ")
+        );
+        WalkControl::Continue
+    }
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        let indent = "    ".repeat(self.depth);
+        println!(
+            "{}<start_loop_row> [{}] {}",
+            indent, position.line, row_index
+        );
+        WalkControl::Continue
+    }
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        let indent = "    ".repeat(self.depth);
+        println!("{}<end_loop_row> [{}] {}", indent, position.line, row_index);
+        WalkControl::Continue
+    }
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         println!("{}# [{}] {}", indent, position.line, text);
-        false
+        WalkControl::Continue
     }
     fn data(
         &mut self,
@@ -92,18 +145,18 @@ impl SASContentHandler for DemoHandler {
         tag_position: LineColumn,
         value: &str,
         value_position: LineColumn,
-        delimiter: &str,
+        delimiter: ValueDelimiter,
         loop_level: usize,
-    ) -> bool {
+    ) -> WalkControl {
         let indent = "    ".repeat(self.depth);
         let tag_prefix = format!("{}<data> ", indent);
         let value_indent = " ".repeat(tag_prefix.len());
 
         match delimiter {
-            "\n" => {
+            ValueDelimiter::Semicolon => {
                 // Print line numbers right after <data>, then tag name
                 println!(
-                    "{}<data> [t:{}:{},v:{}:{}] {} delimiter: {:?} loop_level: {} value:",
+                    "{}<data> [t:{}:{},v:{}:{}] {} delimiter: {} loop_level: {} value [multiline]:",
                     indent,
                     tag_position.line,
                     tag_position.column,
@@ -121,15 +174,368 @@ impl SASContentHandler for DemoHandler {
             _ => {
                 // Print line numbers right after <data>, then tag name
                 println!(
-                    "{}<data> [t:{}:{},v:{}:{}] {} delimiter: {} loop_level: {} value [multiline]: {}",
-                    indent, tag_position.line, tag_position.column, value_position.line, value_position.column, tag, delimiter, loop_level, value
+                    "{}<data> [t:{}:{},v:{}:{}] {} delimiter: {} loop_level: {} value: {}",
+                    indent,
+                    tag_position.line,
+                    tag_position.column,
+                    value_position.line,
+                    value_position.column,
+                    tag,
+                    delimiter,
+                    loop_level,
+                    value
                 );
             }
         }
-        false
+        WalkControl::Continue
+    }
+}
+
+/// Passes on only the events of the kinds chosen with --only, and stops the walk once
+/// --max-events of them have been passed on
+struct EventSelector<H> {
+    handler: H,
+    only: Option<HashSet<String>>,
+    max_events: Option<usize>,
+    events: usize,
+}
+
+impl<H: SASContentHandler> EventSelector<H> {
+    fn new(handler: H, only: &[String], max_events: Option<usize>) -> Self {
+        EventSelector {
+            handler,
+            only: (!only.is_empty()).then(|| only.iter().cloned().collect()),
+            max_events,
+            events: 0,
+        }
+    }
+
+    fn into_inner(self) -> H {
+        self.handler
+    }
+
+    fn forward(&mut self, kind: &str, callback: impl FnOnce(&mut H) -> WalkControl) -> WalkControl {
+        if self.only.as_ref().is_some_and(|only| !only.contains(kind)) {
+            return WalkControl::Continue;
+        }
+        if self.max_events.is_some_and(|max| self.events >= max) {
+            return WalkControl::Stop;
+        }
+        self.events += 1;
+        callback(&mut self.handler)
+    }
+}
+
+impl<H: SASContentHandler> SASContentHandler for EventSelector<H> {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        self.forward("start_stream", |h| h.start_stream(name))
+    }
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
+        self.forward("end_stream", |h| h.end_stream(position))
+    }
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
+        self.forward("start_global", |h| h.start_global(position))
+    }
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
+        self.forward("end_global", |h| h.end_global(position))
+    }
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.forward("start_data", |h| h.start_data(position, name))
+    }
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.forward("end_data", |h| h.end_data(position, name))
+    }
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.forward("start_saveframe", |h| h.start_saveframe(position, name))
+    }
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.forward("end_saveframe", |h| h.end_saveframe(position, name))
+    }
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.forward("start_loop", |h| h.start_loop(position))
+    }
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.forward("end_loop", |h| h.end_loop(position))
+    }
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.forward("loop_definition", |h| {
+            h.loop_definition(position, tags, nesting_level)
+        })
+    }
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.forward("start_loop_row", |h| h.start_loop_row(position, row_index))
+    }
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.forward("end_loop_row", |h| h.end_loop_row(position, row_index))
+    }
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        self.forward("comment", |h| h.comment(position, text))
+    }
+
+    fn comment_with_kind(
+        &mut self,
+        position: LineColumn,
+        text: &str,
+        kind: CommentKind,
+    ) -> WalkControl {
+        self.forward("comment", |h| h.comment_with_kind(position, text, kind))
+    }
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.forward("data", |h| {
+            h.data(
+                tag,
+                tag_position,
+                value,
+                value_position,
+                delimiter,
+                loop_level,
+            )
+        })
+    }
+}
+
+/// Prints each event as a JSON object on a line of its own
+struct JsonEventsHandler;
+
+impl JsonEventsHandler {
+    fn print(&self, event: serde_json::Value) -> WalkControl {
+        println!("{}", event);
+        WalkControl::Continue
+    }
+}
+
+fn position_json(position: LineColumn) -> serde_json::Value {
+    json!({ "line": position.line, "column": position.column })
+}
+
+impl SASContentHandler for JsonEventsHandler {
+    fn start_stream(&mut self, name: Option<&str>) -> WalkControl {
+        self.print(json!({ "event": "start_stream", "name": name }))
+    }
+    fn end_stream(&mut self, position: LineColumn) -> WalkControl {
+        self.print(json!({ "event": "end_stream", "position": position_json(position) }))
+    }
+    fn start_global(&mut self, position: LineColumn) -> WalkControl {
+        self.print(json!({ "event": "start_global", "position": position_json(position) }))
+    }
+    fn end_global(&mut self, position: LineColumn) -> WalkControl {
+        self.print(json!({ "event": "end_global", "position": position_json(position) }))
+    }
+    fn start_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.print(json!({
+            "event": "start_data", "position": position_json(position), "name": name
+        }))
+    }
+    fn end_data(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.print(json!({
+            "event": "end_data", "position": position_json(position), "name": name
+        }))
+    }
+    fn start_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.print(json!({
+            "event": "start_saveframe", "position": position_json(position), "name": name
+        }))
+    }
+    fn end_saveframe(&mut self, position: LineColumn, name: &str) -> WalkControl {
+        self.print(json!({
+            "event": "end_saveframe", "position": position_json(position), "name": name
+        }))
+    }
+    fn start_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.print(json!({ "event": "start_loop", "position": position_json(position) }))
+    }
+    fn end_loop(&mut self, position: LineColumn) -> WalkControl {
+        self.print(json!({ "event": "end_loop", "position": position_json(position) }))
+    }
+    fn loop_definition(
+        &mut self,
+        position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.print(json!({
+            "event": "loop_definition",
+            "position": position_json(position),
+            "tags": tags,
+            "nesting_level": nesting_level,
+        }))
+    }
+    fn start_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.print(json!({
+            "event": "start_loop_row", "position": position_json(position), "row_index": row_index
+        }))
+    }
+    fn end_loop_row(&mut self, position: LineColumn, row_index: usize) -> WalkControl {
+        self.print(json!({
+            "event": "end_loop_row", "position": position_json(position), "row_index": row_index
+        }))
+    }
+    fn comment(&mut self, position: LineColumn, text: &str) -> WalkControl {
+        self.print(json!({
+            "event": "comment", "position": position_json(position), "text": text
+        }))
+    }
+    fn data(
+        &mut self,
+        tag: &str,
+        tag_position: LineColumn,
+        value: &str,
+        value_position: LineColumn,
+        delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.print(json!({
+            "event": "data",
+            "tag": tag,
+            "tag_position": position_json(tag_position),
+            "value": value,
+            "value_position": position_json(value_position),
+            "delimiter": delimiter.as_str(),
+            "loop_level": loop_level,
+        }))
+    }
+}
+
+/// Counts the events of each kind, in the order of `EVENT_KINDS`
+struct CountsHandler {
+    counts: [usize; EVENT_KINDS.len()],
+}
+
+impl CountsHandler {
+    fn new() -> Self {
+        CountsHandler {
+            counts: [0; EVENT_KINDS.len()],
+        }
+    }
+
+    fn count(&mut self, kind: &str) -> WalkControl {
+        if let Some(index) = EVENT_KINDS.iter().position(|k| *k == kind) {
+            self.counts[index] += 1;
+        }
+        WalkControl::Continue
+    }
+
+    fn print(&self) {
+        let width = EVENT_KINDS.iter().map(|kind| kind.len()).max().unwrap_or(0);
+        for (kind, count) in EVENT_KINDS.iter().zip(self.counts) {
+            println!("{:<width$}  {}", kind, count, width = width);
+        }
+        println!(
+            "{:<width$}  {}",
+            "total",
+            self.counts.iter().sum::<usize>(),
+            width = width
+        );
+    }
+}
+
+impl SASContentHandler for CountsHandler {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.count("start_stream")
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_stream")
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("start_global")
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_global")
+    }
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("start_data")
+    }
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("end_data")
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("start_saveframe")
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        self.count("end_saveframe")
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("start_loop")
+    }
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.count("end_loop")
+    }
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        _tags: &[&str],
+        _nesting_level: usize,
+    ) -> WalkControl {
+        self.count("loop_definition")
+    }
+    fn start_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        self.count("start_loop_row")
+    }
+    fn end_loop_row(&mut self, _position: LineColumn, _row_index: usize) -> WalkControl {
+        self.count("end_loop_row")
+    }
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        self.count("comment")
+    }
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.count("data")
+    }
+}
+
+/// Walk `tree` with `handler` behind the --only, --max-events and --saveframe selections,
+/// returning the handler
+fn walk_selected<H: SASContentHandler>(
+    handler: H,
+    cli: &Cli,
+    input: &str,
+    tree: &MutablePair,
+) -> H {
+    let mut selector = EventSelector::new(handler, &cli.only, cli.max_events);
+    match &cli.saveframe {
+        Some(frame) => {
+            let mut filter = FilterHandler::new(selector, |name: &str| name == frame);
+            StarWalker::from_input(&mut filter, input).walk_star_tree_buffered(tree);
+            filter.into_inner().into_inner()
+        }
+        None => {
+            StarWalker::from_input(&mut selector, input).walk_star_tree_buffered(tree);
+            selector.into_inner()
+        }
     }
 }
 
+/// Formats for --format
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// An indented line for each event
+    Events,
+    /// A JSON object for each event, one per line
+    Json,
+    /// The number of events of each kind
+    Counts,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Demonstrate SAS (SAX-like API for STAR) event streaming", long_about = None)]
 struct Cli {
@@ -140,6 +546,23 @@ struct Cli {
     /// Show verbose output with line numbers and positions
     #[arg(short, long, help = "Enable verbose output")]
     verbose: bool,
+
+    /// Format of the events; verbose output goes to stderr for json and counts
+    #[arg(long, value_enum, default_value_t = OutputFormat::Events)]
+    format: OutputFormat,
+
+    /// Show only these kinds of event, comma separated
+    #[arg(long, value_name = "KINDS", value_delimiter = ',', value_parser = EVENT_KINDS)]
+    only: Vec<String>,
+
+    /// Show only the events inside the save frames (or data blocks) with this name; the stream
+    /// start and end are always shown
+    #[arg(long, value_name = "NAME")]
+    saveframe: Option<String>,
+
+    /// Stop after showing this many events
+    #[arg(long, value_name = "N")]
+    max_events: Option<usize>,
 }
 
 fn main() {
@@ -147,6 +570,7 @@ fn main() {
 
     let filename = cli
         .file
+        .clone()
         .unwrap_or_else(|| "examples/comprehensive_example.star".to_string());
 
     let input = fs::read_to_string(&filename).unwrap_or_else(|_| {
@@ -155,11 +579,19 @@ fn main() {
         std::process::exit(1);
     });
 
-    if cli.verbose {
-        println!("Processing STAR file: {}", filename);
-        println!("File size: {} bytes", input.len());
-        println!("Starting SAS event stream...\n");
-    }
+    // Keep the JSON and counts on stdout parseable
+    let verbose = |message: &str| {
+        if !cli.verbose {
+        } else if cli.format == OutputFormat::Events {
+            println!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    };
+
+    verbose(&format!("Processing STAR file: {}", filename));
+    verbose(&format!("File size: {} bytes", input.len()));
+    verbose("Starting SAS event stream...\n");
 
     let config = default_config();
     let tree = parse(&input, &config).unwrap_or_else(|e| {
@@ -170,11 +602,15 @@ fn main() {
         std::process::exit(1);
     });
 
-    let mut handler = DemoHandler { depth: 0 };
-    let mut walker = StarWalker::from_input(&mut handler, &input);
-    walker.walk_star_tree_buffered(&tree);
-
-    if cli.verbose {
-        println!("\nSAS event streaming completed successfully.");
+    match cli.format {
+        OutputFormat::Events => {
+            walk_selected(DemoHandler { depth: 0 }, &cli, &input, &tree);
+        }
+        OutputFormat::Json => {
+            walk_selected(JsonEventsHandler, &cli, &input, &tree);
+        }
+        OutputFormat::Counts => walk_selected(CountsHandler::new(), &cli, &input, &tree).print(),
     }
+
+    verbose("\nSAS event streaming completed successfully.");
 }
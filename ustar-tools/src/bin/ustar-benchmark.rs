@@ -1,10 +1,20 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use pest::Parser as PestParser;
+use serde::Serialize;
+#[cfg(feature = "alloc-tracking")]
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::fs;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use ustar_parser::mutable_pair::MutablePair;
+use ustar_parser::line_column_index::{LineColumn, LineColumnIndex};
+use ustar_parser::mutable_pair::{MutablePair, PairNode};
 use ustar_parser::parsers::ascii::{AsciiParser, Rule};
+use ustar_parser::sas_interface::{SASContentHandler, ValueDelimiter, WalkControl};
+use ustar_parser::sas_walker::StarWalker;
+use ustar_parser::string_decomposer::decompose_strings;
+use ustar_parser::tree_arena::TreeArena;
+use ustar_parser::ErrorFormatMode;
 
 #[derive(Parser)]
 #[command(name = "ustar-benchmark")]
@@ -29,8 +39,199 @@ struct Args {
     /// Include MutablePair conversion benchmark
     #[arg(short = 'm', long)]
     mutable_pair: bool,
+
+    /// Compare MutablePair and TreeArena construction and traversal
+    #[arg(short = 'a', long)]
+    arena: bool,
+
+    /// Compare line/column positions stored in the tree with looking them up in the input
+    #[arg(short = 'l', long)]
+    line_columns: bool,
+
+    /// Report the heap retained by a MutablePair tree (needs the alloc-tracking feature)
+    #[arg(short = 'H', long)]
+    heap: bool,
+
+    /// Report the peak heap, allocations and heap per input byte of each phase: pest parse,
+    /// MutablePair conversion, string decomposition and SAS walk (needs the alloc-tracking
+    /// feature)
+    #[arg(short = 'M', long)]
+    memory: bool,
+
+    /// Compare parse with parse_parallel, which parses data blocks on separate threads
+    #[arg(short = 'P', long)]
+    parallel: bool,
+
+    /// Compare walking a SAS handler over a parsed tree with walking it straight from the parse
+    #[arg(short = 'W', long)]
+    walk: bool,
+
+    /// Compare the time and peak heap of parse_file, which reads the file into memory, with
+    /// parse_mmap, which parses it where it is mapped into memory (needs the alloc-tracking
+    /// feature)
+    #[arg(long)]
+    mmap: bool,
+
+    /// Format of the results; json and csv write only the parse results, for other tools
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        conflicts_with_all = ["verbose", "mutable_pair", "arena", "line_columns", "heap", "parallel", "walk", "mmap"]
+    )]
+    format: OutputFormat,
+}
+
+/// Formats for --format
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// A report with the timing distribution and baseline comparison
+    Text,
+    /// A JSON object of the parse results
+    Json,
+    /// A header line and a line of the parse results
+    Csv,
+}
+
+/// The results of timing the parse of a file, as written by --format json and csv
+#[derive(Serialize)]
+struct BenchmarkResults {
+    file: String,
+    size_bytes: usize,
+    iterations: usize,
+    warmup: usize,
+    total_ms: f64,
+    average_ms: f64,
+    median_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    average_bytes_per_sec: f64,
+    peak_bytes_per_sec: f64,
+    ns_per_byte: f64,
+    baseline_ns_per_byte: f64,
+    baseline_ratio: f64,
+    /// The heap use of each phase, with --memory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Vec<PhaseMemory>>,
+}
+
+/// The heap used by one phase of turning input into SAS events, measured by
+/// `CountingAllocator`
+#[derive(Serialize)]
+struct PhaseMemory {
+    phase: &'static str,
+    /// The most heap in use above what was allocated before the phase
+    peak_bytes: usize,
+    /// Allocations and reallocations made by the phase
+    allocations: usize,
+    /// The peak heap over the size of the input
+    bytes_per_input_byte: f64,
+}
+
+impl BenchmarkResults {
+    /// The CSV header and line of the results, in the order of the JSON keys
+    fn to_csv(&self) -> String {
+        let values = serde_json::to_value(self).expect("the results serialize");
+        let fields = [
+            "file",
+            "size_bytes",
+            "iterations",
+            "warmup",
+            "total_ms",
+            "average_ms",
+            "median_ms",
+            "min_ms",
+            "max_ms",
+            "p95_ms",
+            "p99_ms",
+            "average_bytes_per_sec",
+            "peak_bytes_per_sec",
+            "ns_per_byte",
+            "baseline_ns_per_byte",
+            "baseline_ratio",
+        ];
+        let mut header: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+        let mut line: Vec<String> = fields
+            .iter()
+            .map(|field| match &values[field] {
+                serde_json::Value::String(text) => csv_field(text),
+                value => value.to_string(),
+            })
+            .collect();
+        // a column for each measure of each phase, such as pest_parse_peak_bytes
+        for phase in self.memory.iter().flatten() {
+            let prefix = phase.phase.replace(' ', "_");
+            header.push(format!("{}_peak_bytes", prefix));
+            header.push(format!("{}_allocations", prefix));
+            header.push(format!("{}_bytes_per_input_byte", prefix));
+            line.push(phase.peak_bytes.to_string());
+            line.push(phase.allocations.to_string());
+            line.push(phase.bytes_per_input_byte.to_string());
+        }
+        format!("{}\n{}\n", header.join(","), line.join(","))
+    }
+}
+
+/// `text` as a CSV field, quoted when it holds a comma, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
 }
 
+/// Bytes currently allocated on the heap, maintained by `CountingAllocator`
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// The most bytes allocated at once since it was last reset, maintained by `CountingAllocator`
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocations and reallocations made, maintained by `CountingAllocator`
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting live bytes and allocations for the heap benchmarks
+///
+/// Installed with the alloc-tracking feature, off by default so timings carry no counting
+/// overhead; without it the heap benchmarks are unavailable.
+#[cfg(feature = "alloc-tracking")]
+struct CountingAllocator;
+
+#[cfg(feature = "alloc-tracking")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "alloc-tracking")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
 fn main() {
     let args = Args::parse();
 
@@ -50,143 +251,100 @@ fn main() {
 
     let file_size = content.len();
 
+    if (args.heap || args.memory || args.mmap) && !cfg!(feature = "alloc-tracking") {
+        eprintln!(
+            "Error: --heap, --memory and --mmap need ustar-benchmark built with the alloc-tracking feature (cargo run --features alloc-tracking --bin ustar-benchmark)"
+        );
+        std::process::exit(1);
+    }
+
     // Establish baseline performance using simple_star_file.star
     let baseline_per_byte = establish_baseline();
 
-    println!("STAR File Parsing Benchmark");
-    println!("==========================");
-    println!("File: {}", args.file_path);
-    println!(
-        "Size: {} bytes ({:.2} KB)",
-        file_size,
-        file_size as f64 / 1024.0
-    );
-    println!("Iterations: {}", args.iterations);
-    println!("Warmup cycles: {}", args.warmup);
-    println!(
-        "Baseline: {:.2} ns/byte (from comprehensive_example.star)",
-        baseline_per_byte
-    );
-    println!();
+    let text = args.format == OutputFormat::Text;
+    if text {
+        println!("STAR File Parsing Benchmark");
+        println!("==========================");
+        println!("File: {}", args.file_path);
+        println!(
+            "Size: {} bytes ({:.2} KB)",
+            file_size,
+            file_size as f64 / 1024.0
+        );
+        println!("Iterations: {}", args.iterations);
+        println!("Warmup cycles: {}", args.warmup);
+        println!(
+            "Baseline: {:.2} ns/byte (from comprehensive_example.star)",
+            baseline_per_byte
+        );
+        println!();
 
-    // Warmup parse to ensure the file is valid
-    print!("Validating file... ");
+        // Warmup parse to ensure the file is valid
+        print!("Validating file... ");
+    }
     match AsciiParser::parse(Rule::star_file, &content) {
-        Ok(_) => println!("✓ Valid STAR file"),
+        Ok(_) if text => println!("✓ Valid STAR file"),
+        Ok(_) => {}
         Err(e) => {
             eprintln!("✗ Parse error: {}", e);
             std::process::exit(1);
         }
     }
 
-    // Warmup phase
-    if args.warmup > 0 {
-        println!();
-        println!("Running warmup ({} cycles)...", args.warmup);
-        for i in 0..args.warmup {
-            if let Err(e) = AsciiParser::parse(Rule::star_file, &content) {
-                eprintln!("Parse error during warmup iteration {}: {}", i + 1, e);
-                std::process::exit(1);
-            }
-            if args.verbose && (i + 1) % (args.warmup / 5).max(1) == 0 {
-                println!("  Warmup {}/{}", i + 1, args.warmup);
-            }
-        }
+    let (mut results, parse_times) = run_benchmark(&args, &content, baseline_per_byte, text);
+    if args.memory {
+        results.memory = Some(measure_phases(&content));
     }
 
-    println!();
-    println!("Running benchmark...");
-
-    let mut parse_times: Vec<Duration> = Vec::with_capacity(args.iterations);
-    let mut total_duration = Duration::new(0, 0);
-
-    for i in 0..args.iterations {
-        let start_time = Instant::now();
-
-        match AsciiParser::parse(Rule::star_file, &content) {
-            Ok(_) => {
-                let elapsed = start_time.elapsed();
-                parse_times.push(elapsed);
-                total_duration += elapsed;
-
-                if args.verbose && (i + 1) % (args.iterations / 10).max(1) == 0 {
-                    println!(
-                        "  Iteration {}/{}: {:.3}ms",
-                        i + 1,
-                        args.iterations,
-                        elapsed.as_secs_f64() * 1000.0
-                    );
-                }
-            }
-            Err(e) => {
-                eprintln!("Parse error on iteration {}: {}", i + 1, e);
-                std::process::exit(1);
-            }
+    // json and csv write only the results, so they can be read by other tools
+    match args.format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&results).expect("the results serialize")
+            );
+            return;
+        }
+        OutputFormat::Csv => {
+            print!("{}", results.to_csv());
+            return;
         }
     }
 
-    // Calculate statistics
-    parse_times.sort();
-
-    let total_ms = total_duration.as_secs_f64() * 1000.0;
-    let avg_ms = total_ms / args.iterations as f64;
-    let min_ms = parse_times[0].as_secs_f64() * 1000.0;
-    let max_ms = parse_times[args.iterations - 1].as_secs_f64() * 1000.0;
-    let median_ms = if args.iterations % 2 == 0 {
-        (parse_times[args.iterations / 2 - 1].as_secs_f64()
-            + parse_times[args.iterations / 2].as_secs_f64())
-            * 500.0
-    } else {
-        parse_times[args.iterations / 2].as_secs_f64() * 1000.0
-    };
-
-    // Calculate percentiles
-    let p95_idx = ((args.iterations as f64) * 0.95) as usize;
-    let p99_idx = ((args.iterations as f64) * 0.99) as usize;
-    let p95_ms = parse_times[p95_idx.min(args.iterations - 1)].as_secs_f64() * 1000.0;
-    let p99_ms = parse_times[p99_idx.min(args.iterations - 1)].as_secs_f64() * 1000.0;
-
-    // Calculate throughput
-    let avg_throughput_bytes_per_sec = (file_size as f64) / (avg_ms / 1000.0);
-    let peak_throughput_bytes_per_sec = (file_size as f64) / (min_ms / 1000.0);
-
     println!();
     println!("Benchmark Results");
     println!("=================");
-    println!("Total time:     {:.3}ms", total_ms);
-    println!("Average time:   {:.3}ms", avg_ms);
-    println!("Median time:    {:.3}ms", median_ms);
-    println!("Min time:       {:.3}ms", min_ms);
-    println!("Max time:       {:.3}ms", max_ms);
-    println!("95th percentile: {:.3}ms", p95_ms);
-    println!("99th percentile: {:.3}ms", p99_ms);
+    println!("Total time:     {:.3}ms", results.total_ms);
+    println!("Average time:   {:.3}ms", results.average_ms);
+    println!("Median time:    {:.3}ms", results.median_ms);
+    println!("Min time:       {:.3}ms", results.min_ms);
+    println!("Max time:       {:.3}ms", results.max_ms);
+    println!("95th percentile: {:.3}ms", results.p95_ms);
+    println!("99th percentile: {:.3}ms", results.p99_ms);
     println!();
     println!("Throughput");
     println!("==========");
     println!(
         "Average:        {}",
-        format_throughput(avg_throughput_bytes_per_sec)
+        format_throughput(results.average_bytes_per_sec)
     );
     println!(
         "Peak (min time): {}",
-        format_throughput(peak_throughput_bytes_per_sec)
+        format_throughput(results.peak_bytes_per_sec)
     );
     println!();
-    println!(
-        "Performance per byte: {:.2} ns/byte",
-        (avg_ms * 1_000_000.0) / file_size as f64
-    );
+    println!("Performance per byte: {:.2} ns/byte", results.ns_per_byte);
 
     // Performance classification based on baseline deviation
-    let actual_per_byte = (avg_ms * 1_000_000.0) / file_size as f64;
-    let performance_ratio = actual_per_byte / baseline_per_byte;
+    let performance_ratio = results.baseline_ratio;
     let deviation_percent = (performance_ratio - 1.0) * 100.0;
 
     println!();
     println!("Baseline Comparison");
     println!("==================");
     println!("Expected (baseline): {:.2} ns/byte", baseline_per_byte);
-    println!("Actual:              {:.2} ns/byte", actual_per_byte);
+    println!("Actual:              {:.2} ns/byte", results.ns_per_byte);
     println!("Performance ratio:   {:.2}x baseline", performance_ratio);
     println!("Deviation:           {:+.1}%", deviation_percent);
 
@@ -228,9 +386,176 @@ fn main() {
             args.iterations,
             args.warmup,
             args.verbose,
-            total_ms,
+            results.total_ms,
         );
     }
+
+    // Report the heap of each phase (if requested)
+    if let Some(phases) = &results.memory {
+        println!();
+        println!("==============================================");
+        println!("Heap Use by Phase");
+        println!("==============================================");
+        print_phase_memory(phases);
+    }
+
+    // Compare tree backends (if requested)
+    if args.arena {
+        println!();
+        println!("==============================================");
+        println!("MutablePair vs TreeArena Benchmark");
+        println!("==============================================");
+        benchmark_tree_backends(&content, args.iterations, args.warmup);
+    }
+
+    // Compare line/column lookups (if requested)
+    if args.line_columns {
+        println!();
+        println!("==============================================");
+        println!("Line/Column Position Benchmark");
+        println!("==============================================");
+        benchmark_line_columns(&content, args.iterations, args.warmup);
+    }
+
+    // Measure the heap held by the tree (if requested)
+    if args.heap {
+        println!();
+        println!("==============================================");
+        println!("MutablePair Heap Usage");
+        println!("==============================================");
+        benchmark_heap(&content);
+    }
+
+    // Compare sequential and parallel parsing (if requested)
+    if args.parallel {
+        println!();
+        println!("==============================================");
+        println!("Parallel Block Parsing Benchmark");
+        println!("==============================================");
+        benchmark_parallel(&content, args.iterations, args.warmup);
+    }
+
+    // Compare walking a tree with walking the parse (if requested)
+    if args.walk {
+        println!();
+        println!("==============================================");
+        println!("SAS Walk Benchmark");
+        println!("==============================================");
+        benchmark_walk(&content, args.iterations, args.warmup);
+    }
+
+    // Compare reading the file with mapping it (if requested)
+    if args.mmap {
+        println!();
+        println!("==============================================");
+        println!("Memory-Mapped File Benchmark");
+        println!("==============================================");
+        benchmark_mmap(Path::new(&args.file_path), args.iterations, args.warmup);
+    }
+}
+
+/// Warm up, then time `args.iterations` parses of `content`, returning the results and the
+/// sorted times; progress is printed with the text format
+fn run_benchmark(
+    args: &Args,
+    content: &str,
+    baseline_per_byte: f64,
+    text: bool,
+) -> (BenchmarkResults, Vec<Duration>) {
+    // Warmup phase
+    if args.warmup > 0 {
+        if text {
+            println!();
+            println!("Running warmup ({} cycles)...", args.warmup);
+        }
+        for i in 0..args.warmup {
+            if let Err(e) = AsciiParser::parse(Rule::star_file, content) {
+                eprintln!("Parse error during warmup iteration {}: {}", i + 1, e);
+                std::process::exit(1);
+            }
+            if args.verbose && (i + 1) % (args.warmup / 5).max(1) == 0 {
+                println!("  Warmup {}/{}", i + 1, args.warmup);
+            }
+        }
+    }
+
+    if text {
+        println!();
+        println!("Running benchmark...");
+    }
+
+    let mut parse_times: Vec<Duration> = Vec::with_capacity(args.iterations);
+    let mut total_duration = Duration::new(0, 0);
+
+    for i in 0..args.iterations {
+        let start_time = Instant::now();
+
+        match AsciiParser::parse(Rule::star_file, content) {
+            Ok(_) => {
+                let elapsed = start_time.elapsed();
+                parse_times.push(elapsed);
+                total_duration += elapsed;
+
+                if args.verbose && (i + 1) % (args.iterations / 10).max(1) == 0 {
+                    println!(
+                        "  Iteration {}/{}: {:.3}ms",
+                        i + 1,
+                        args.iterations,
+                        elapsed.as_secs_f64() * 1000.0
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("Parse error on iteration {}: {}", i + 1, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Calculate statistics
+    parse_times.sort();
+
+    let total_ms = total_duration.as_secs_f64() * 1000.0;
+    let avg_ms = total_ms / args.iterations as f64;
+    let min_ms = parse_times[0].as_secs_f64() * 1000.0;
+    let max_ms = parse_times[args.iterations - 1].as_secs_f64() * 1000.0;
+    let median_ms = if args.iterations % 2 == 0 {
+        (parse_times[args.iterations / 2 - 1].as_secs_f64()
+            + parse_times[args.iterations / 2].as_secs_f64())
+            * 500.0
+    } else {
+        parse_times[args.iterations / 2].as_secs_f64() * 1000.0
+    };
+
+    // Calculate percentiles
+    let p95_idx = ((args.iterations as f64) * 0.95) as usize;
+    let p99_idx = ((args.iterations as f64) * 0.99) as usize;
+    let p95_ms = parse_times[p95_idx.min(args.iterations - 1)].as_secs_f64() * 1000.0;
+    let p99_ms = parse_times[p99_idx.min(args.iterations - 1)].as_secs_f64() * 1000.0;
+
+    // Calculate throughput
+    let file_size = content.len();
+    let ns_per_byte = (avg_ms * 1_000_000.0) / file_size as f64;
+    let results = BenchmarkResults {
+        file: args.file_path.clone(),
+        size_bytes: file_size,
+        iterations: args.iterations,
+        warmup: args.warmup,
+        total_ms,
+        average_ms: avg_ms,
+        median_ms,
+        min_ms,
+        max_ms,
+        p95_ms,
+        p99_ms,
+        average_bytes_per_sec: (file_size as f64) / (avg_ms / 1000.0),
+        peak_bytes_per_sec: (file_size as f64) / (min_ms / 1000.0),
+        ns_per_byte,
+        baseline_ns_per_byte: baseline_per_byte,
+        baseline_ratio: ns_per_byte / baseline_per_byte,
+        memory: None,
+    };
+    (results, parse_times)
 }
 
 fn create_timing_histogram(times: &[Duration]) -> Vec<(String, usize)> {
@@ -463,3 +788,452 @@ fn benchmark_mutable_pair_conversion(
         }
     }
 }
+
+/// Count nodes with a full depth-first traversal over either tree backend
+fn count_nodes<'a, N: PairNode<'a>>(node: N) -> usize {
+    1 + node.child_nodes().map(count_nodes).sum::<usize>()
+}
+
+/// Time `operation` over `iterations` runs after `warmup` runs, returning the average in ms
+fn average_ms<F: FnMut() -> usize>(iterations: usize, warmup: usize, mut operation: F) -> f64 {
+    for _ in 0..warmup {
+        std::hint::black_box(operation());
+    }
+
+    let mut total_duration = Duration::new(0, 0);
+    for _ in 0..iterations {
+        let start_time = Instant::now();
+        std::hint::black_box(operation());
+        total_duration += start_time.elapsed();
+    }
+
+    total_duration.as_secs_f64() * 1000.0 / iterations as f64
+}
+
+fn benchmark_tree_backends(content: &str, iterations: usize, warmup: usize) {
+    println!("Testing construction and traversal of MutablePair and TreeArena trees...");
+    println!();
+
+    let pair = match AsciiParser::parse(Rule::star_file, content) {
+        Ok(mut pairs) => pairs.next().expect("star_file pair"),
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mutable_build_ms = average_ms(iterations, warmup, || {
+        MutablePair::from_pest_pair(&pair).children().len()
+    });
+    let arena_build_ms = average_ms(iterations, warmup, || {
+        TreeArena::from_pest_pair(&pair).len()
+    });
+
+    let mutable_pair = MutablePair::from_pest_pair(&pair);
+    let arena = TreeArena::from_pest_pair(&pair);
+    let arena_root = arena.root().expect("arena root");
+
+    let mutable_walk_ms = average_ms(iterations, warmup, || count_nodes(&mutable_pair));
+    let arena_walk_ms = average_ms(iterations, warmup, || count_nodes(arena_root));
+
+    println!("Nodes:          {}", arena.len());
+    println!();
+    println!("{:<14} {:>14} {:>14}", "", "MutablePair", "TreeArena");
+    println!(
+        "{:<14} {:>12.3}ms {:>12.3}ms",
+        "Construction", mutable_build_ms, arena_build_ms
+    );
+    println!(
+        "{:<14} {:>12.3}ms {:>12.3}ms",
+        "Traversal", mutable_walk_ms, arena_walk_ms
+    );
+    println!();
+    println!(
+        "Arena/MutablePair ratio: construction {:.2}x, traversal {:.2}x",
+        arena_build_ms / mutable_build_ms,
+        arena_walk_ms / mutable_walk_ms
+    );
+}
+
+/// Line and column of an offset found by scanning the input from the start, as consumers did
+/// before positions were stored in the tree
+fn rescan_line_column(content: &str, offset: usize) -> LineColumn {
+    let before = &content.as_bytes()[..offset];
+    let line_start = before
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let line = before.iter().filter(|&&byte| byte == b'\n').count() + 1;
+    LineColumn::new(line, offset - line_start + 1)
+}
+
+fn benchmark_line_columns(content: &str, iterations: usize, warmup: usize) {
+    println!("Testing line/column lookup for the start and end of every node...");
+    println!();
+
+    let pair = match AsciiParser::parse(Rule::star_file, content) {
+        Ok(mut pairs) => pairs.next().expect("star_file pair"),
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let tree = MutablePair::from_pest_pair(&pair);
+    let nodes: Vec<&MutablePair> = tree.iter_descendants().collect();
+
+    let stored_ms = average_ms(iterations, warmup, || {
+        nodes
+            .iter()
+            .map(|node| node.start_line_column().line + node.end_line_column().line)
+            .sum()
+    });
+
+    let indexed_ms = average_ms(iterations, warmup, || {
+        let line_index = LineColumnIndex::new(content);
+        nodes
+            .iter()
+            .map(|node| {
+                line_index.offset_to_line_col(node.start).line
+                    + line_index.offset_to_line_col(node.end).line
+            })
+            .sum()
+    });
+
+    // rescanning is quadratic, so time an evenly spread sample of nodes and scale it up
+    let step = (nodes.len() / 1000).max(1);
+    let sample: Vec<&MutablePair> = nodes.iter().step_by(step).copied().collect();
+    let sample_ms = average_ms(1, 0, || {
+        sample
+            .iter()
+            .map(|node| {
+                rescan_line_column(content, node.start).line
+                    + rescan_line_column(content, node.end).line
+            })
+            .sum()
+    });
+    let rescan_ms = sample_ms * nodes.len() as f64 / sample.len().max(1) as f64;
+
+    println!("Nodes:          {}", nodes.len());
+    println!();
+    println!(
+        "{:<24} {:>14}",
+        "Stored in tree",
+        format!("{:.3}ms", stored_ms)
+    );
+    println!(
+        "{:<24} {:>14}",
+        "LineColumnIndex lookup",
+        format!("{:.3}ms", indexed_ms)
+    );
+    println!(
+        "{:<24} {:>14}",
+        "Rescan input (estimated)",
+        format!("{:.3}ms", rescan_ms)
+    );
+    println!();
+    println!(
+        "Speedup of stored positions: {:.1}x over index lookup, {:.1}x over rescanning",
+        indexed_ms / stored_ms,
+        rescan_ms / stored_ms
+    );
+}
+
+fn benchmark_heap(content: &str) {
+    println!("Measuring the heap retained by a MutablePair tree...");
+    println!();
+
+    let pair = match AsciiParser::parse(Rule::star_file, content) {
+        Ok(mut pairs) => pairs.next().expect("star_file pair"),
+        Err(e) => {
+            eprintln!("Parse error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let tree = MutablePair::from_pest_pair(&pair);
+    let retained = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+    let nodes = count_nodes(&tree);
+
+    println!("Nodes:          {}", nodes);
+    println!(
+        "Heap retained:  {} bytes ({:.2} MB)",
+        retained,
+        retained as f64 / (1024.0 * 1024.0)
+    );
+    println!(
+        "Per node:       {:.1} bytes",
+        retained as f64 / nodes as f64
+    );
+    println!(
+        "Input ratio:    {:.1}x the input size",
+        retained as f64 / content.len() as f64
+    );
+}
+
+fn benchmark_parallel(content: &str, iterations: usize, warmup: usize) {
+    println!("Testing parse against parse_parallel with the default configuration...");
+    println!();
+
+    let config = ustar_parser::default_config();
+    let tree = match ustar_parser::parse_parallel(content, &config) {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("Parse error: {}", e.format_error(ErrorFormatMode::Basic, 0));
+            std::process::exit(1);
+        }
+    };
+    let blocks = tree.children().len() - 1;
+
+    let sequential_ms = average_ms(iterations, warmup, || {
+        ustar_parser::parse(content, &config).map_or(0, |tree| tree.children().len())
+    });
+    let parallel_ms = average_ms(iterations, warmup, || {
+        ustar_parser::parse_parallel(content, &config).map_or(0, |tree| tree.children().len())
+    });
+
+    println!("Blocks:         {}", blocks);
+    println!(
+        "Threads:        {}",
+        std::thread::available_parallelism().map_or(1, |threads| threads.get())
+    );
+    println!();
+    println!("{:<24} {:>14}", "parse", format!("{:.3}ms", sequential_ms));
+    println!(
+        "{:<24} {:>14}",
+        "parse_parallel",
+        format!("{:.3}ms", parallel_ms)
+    );
+    println!();
+    println!("Speedup: {:.2}x", sequential_ms / parallel_ms);
+}
+
+fn benchmark_mmap(path: &Path, iterations: usize, warmup: usize) {
+    println!("Testing parse_file, reading the file, against parse_mmap, mapping it...");
+    println!();
+
+    let config = ustar_parser::default_config();
+    let parse_file =
+        || ustar_parser::parse_file(path, &config).map_or(0, |tree| tree.children().len());
+    let parse_mmap =
+        || ustar_parser::parse_mmap(path, &config).map_or(0, |tree| tree.children().len());
+    if let Err(e) = ustar_parser::parse_mmap(path, &config) {
+        eprintln!("Parse error: {}", e.format_error(ErrorFormatMode::Basic, 0));
+        std::process::exit(1);
+    }
+
+    // the mapped pages are in the page cache rather than the heap
+    let file_peak = peak_heap(parse_file);
+    let mmap_peak = peak_heap(parse_mmap);
+    let file_ms = average_ms(iterations, warmup, parse_file);
+    let mmap_ms = average_ms(iterations, warmup, parse_mmap);
+
+    let megabytes = |bytes: usize| format!("{:.2} MB", bytes as f64 / (1024.0 * 1024.0));
+    println!("{:<24} {:>14} {:>14}", "", "Time", "Peak heap");
+    println!(
+        "{:<24} {:>14} {:>14}",
+        "parse_file",
+        format!("{:.3}ms", file_ms),
+        megabytes(file_peak)
+    );
+    println!(
+        "{:<24} {:>14} {:>14}",
+        "parse_mmap",
+        format!("{:.3}ms", mmap_ms),
+        megabytes(mmap_peak)
+    );
+    println!();
+    println!(
+        "Heap saved: {} ({:.0}% of parse_file)",
+        megabytes(file_peak.saturating_sub(mmap_peak)),
+        100.0 * file_peak.saturating_sub(mmap_peak) as f64 / file_peak as f64
+    );
+}
+
+/// A SAS handler that only counts data items, so a walk costs little beyond the traversal
+#[derive(Default)]
+struct ItemCounter {
+    items: usize,
+}
+
+impl SASContentHandler for ItemCounter {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_data(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, _name: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn data(
+        &mut self,
+        _tag: &str,
+        _tag_position: LineColumn,
+        _value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        _loop_level: usize,
+    ) -> WalkControl {
+        self.items += 1;
+        WalkControl::Continue
+    }
+}
+
+/// Walk `content` through parse and the tree it builds, counting data items
+fn walk_tree(content: &str, config: &ustar_parser::ParserConfig) -> usize {
+    let mut counter = ItemCounter::default();
+    if let Ok(tree) = ustar_parser::parse(content, config) {
+        StarWalker::from_input(&mut counter, content).walk_star_tree_buffered(&tree);
+    }
+    counter.items
+}
+
+/// Walk `content` straight from the parse, counting data items
+fn walk_pairs(content: &str, config: &ustar_parser::ParserConfig) -> usize {
+    let mut counter = ItemCounter::default();
+    let _ = ustar_parser::walk(content, config, &mut counter);
+    counter.items
+}
+
+/// The result of `operation` with the most heap in use above what was allocated before it ran
+/// and the allocations it made
+fn measure_heap<T, F: FnOnce() -> T>(operation: F) -> (T, usize, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = std::hint::black_box(operation());
+    (
+        result,
+        PEAK.load(Ordering::Relaxed).saturating_sub(before),
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+    )
+}
+
+/// Measure the heap of each phase of turning `content` into SAS events, each phase keeping
+/// what the ones before it built
+fn measure_phases(content: &str) -> Vec<PhaseMemory> {
+    let phase = |phase, peak_bytes, allocations| PhaseMemory {
+        phase,
+        peak_bytes,
+        allocations,
+        bytes_per_input_byte: peak_bytes as f64 / content.len() as f64,
+    };
+
+    let (pair, parse_peak, parse_allocations) = measure_heap(|| {
+        AsciiParser::parse(Rule::star_file, content)
+            .expect("the file was validated")
+            .next()
+            .expect("star_file pair")
+    });
+    let (mut tree, tree_peak, tree_allocations) =
+        measure_heap(|| MutablePair::from_pest_pair(&pair));
+    let ((), decompose_peak, decompose_allocations) = measure_heap(|| decompose_strings(&mut tree));
+    let (_, walk_peak, walk_allocations) = measure_heap(|| {
+        let mut counter = ItemCounter::default();
+        StarWalker::from_input(&mut counter, content).walk_star_tree_buffered(&tree);
+        counter.items
+    });
+
+    vec![
+        phase("pest parse", parse_peak, parse_allocations),
+        phase("mutable pair", tree_peak, tree_allocations),
+        phase("decompose strings", decompose_peak, decompose_allocations),
+        phase("sas walk", walk_peak, walk_allocations),
+    ]
+}
+
+fn print_phase_memory(phases: &[PhaseMemory]) {
+    println!(
+        "{:<18} {:>14} {:>12} {:>16}",
+        "", "Peak heap", "Allocations", "Heap/input byte"
+    );
+    for phase in phases {
+        println!(
+            "{:<18} {:>11.2} MB {:>12} {:>16.2}",
+            phase.phase,
+            phase.peak_bytes as f64 / (1024.0 * 1024.0),
+            phase.allocations,
+            phase.bytes_per_input_byte
+        );
+    }
+}
+
+/// The most heap in use above what was allocated before `operation` ran
+fn peak_heap<F: FnOnce() -> usize>(operation: F) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    std::hint::black_box(operation());
+    PEAK.load(Ordering::Relaxed).saturating_sub(before)
+}
+
+fn benchmark_walk(content: &str, iterations: usize, warmup: usize) {
+    println!("Testing a SAS walk over a parsed tree against a walk straight from the parse...");
+    println!();
+
+    let config = ustar_parser::default_config();
+    let mut counter = ItemCounter::default();
+    if let Err(e) = ustar_parser::walk(content, &config, &mut counter) {
+        eprintln!("Parse error: {}", e.format_error(ErrorFormatMode::Basic, 0));
+        std::process::exit(1);
+    }
+
+    let tree_ms = average_ms(iterations, warmup, || walk_tree(content, &config));
+    let pairs_ms = average_ms(iterations, warmup, || walk_pairs(content, &config));
+    let tree_peak = peak_heap(|| walk_tree(content, &config));
+    let pairs_peak = peak_heap(|| walk_pairs(content, &config));
+
+    let megabytes = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    let throughput = |ms: f64| format_throughput(content.len() as f64 / (ms / 1000.0));
+
+    println!("Data items:     {}", counter.items);
+    println!();
+    println!(
+        "{:<14} {:>14} {:>16} {:>14}",
+        "", "Time", "Throughput", "Peak heap"
+    );
+    println!(
+        "{:<14} {:>12.3}ms {:>16} {:>11.2} MB",
+        "parse + walk",
+        tree_ms,
+        throughput(tree_ms),
+        megabytes(tree_peak)
+    );
+    println!(
+        "{:<14} {:>12.3}ms {:>16} {:>11.2} MB",
+        "walk",
+        pairs_ms,
+        throughput(pairs_ms),
+        megabytes(pairs_peak)
+    );
+    println!();
+    println!(
+        "Walk/tree ratio: time {:.2}x, peak heap {:.2}x",
+        pairs_ms / tree_ms,
+        pairs_peak as f64 / tree_peak.max(1) as f64
+    );
+}
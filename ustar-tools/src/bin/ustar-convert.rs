@@ -0,0 +1,288 @@
+use clap::{Parser, ValueEnum};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use ustar_parser::json_reader::walk_json;
+use ustar_parser::mutable_pair::MutablePair;
+use ustar_parser::sas_handlers::{JsonHandler, StarWriterHandler};
+use ustar_parser::sas_walker::StarWalker;
+use ustar_parser::transform::{rename_tags, RenameOptions, RenameReport};
+use ustar_parser::{
+    default_config, parse, read_star_file, walk, ConfigKey, ConfigValue, EncodingMode,
+    ErrorFormatMode, ParserConfig,
+};
+
+#[derive(Parser)]
+#[command(name = "ustar-convert")]
+#[command(
+    about = "Convert STAR, CIF and NEF files to JSON and JSON back to STAR, renaming data names"
+)]
+#[command(version = "0.1.0")]
+struct Args {
+    /// Input file, STAR to convert to JSON or STAR, or JSON to convert to STAR
+    #[arg(value_name = "FILE")]
+    input: PathBuf,
+    /// Format to write, by default STAR for a .json input and JSON for anything else
+    #[arg(long, value_enum)]
+    to: Option<OutputFormat>,
+    /// Write JSON with one entry per line, indented by two spaces
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pretty: bool,
+    /// Write to FILE instead of stdout
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Character set of STAR input, ASCII by default
+    #[arg(long, value_enum)]
+    encoding: Option<Encoding>,
+    /// Layout of STAR written from JSON, STAR written from STAR keeps the layout of the input
+    #[arg(long, value_enum, default_value_t = StarStyle::Plain)]
+    style: StarStyle,
+    /// Rename the data names of STAR input as a CSV file of old and new names says, one pair
+    /// per line
+    #[arg(long, value_name = "FILE")]
+    rename_map: Option<PathBuf>,
+    /// Read names of --rename-map without a '.' as categories, renaming all their data names
+    /// and sf_category values
+    #[arg(long, action = clap::ArgAction::SetTrue, requires = "rename_map")]
+    rename_categories: bool,
+}
+
+/// Formats for --to
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// JSON in the shape of the JSON handler: blocks, save frames and loops as nested objects
+    Json,
+    /// STAR, one loop row per line
+    Star,
+}
+
+/// Layouts for --style
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StarStyle {
+    /// Four space indents and one loop row per line
+    Plain,
+    /// NMR-STAR as pynmrstar writes it, with aligned items and loop columns
+    Pynmrstar,
+}
+
+/// Character sets for --encoding
+#[derive(Clone, Copy, ValueEnum)]
+enum Encoding {
+    /// 7-bit ASCII
+    Ascii,
+    /// Latin-1 and Windows-1252
+    Extended,
+    /// UTF-8
+    Unicode,
+}
+
+impl Encoding {
+    fn mode(self) -> EncodingMode {
+        match self {
+            Encoding::Ascii => EncodingMode::Ascii,
+            Encoding::Extended => EncodingMode::ExtendedAscii,
+            Encoding::Unicode => EncodingMode::Unicode,
+        }
+    }
+}
+
+/// Read the old and new names of a --rename-map file
+///
+/// Each line holds an old and a new data name separated by a comma, optionally quoted with
+/// `"`. Blank lines are skipped, as is a first line whose old name doesn't start with `_`,
+/// taking it to be a header.
+fn read_rename_map(path: &Path) -> Result<HashMap<String, String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut mapping = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|field| field.trim().trim_matches('"'))
+            .collect();
+        if index == 0 && !fields[0].starts_with('_') {
+            continue;
+        }
+        let [from, to] = fields[..] else {
+            return Err(format!(
+                "{}:{} expected an old and a new data name, found {}",
+                path.display(),
+                index + 1,
+                line
+            ));
+        };
+        mapping.insert(from.to_string(), to.to_string());
+    }
+    Ok(mapping)
+}
+
+/// The parser configuration for STAR input
+fn star_config(args: &Args) -> ParserConfig {
+    let mut config = default_config();
+    if let Some(encoding) = args.encoding {
+        config.insert(ConfigKey::Encoding, ConfigValue::Encoding(encoding.mode()));
+    }
+    config
+}
+
+/// Split a UTF-8 byte order mark from the start of `input`, reading the rest as UTF-8 as
+/// `parse` does, so the offsets of its tree refer to the text after the mark
+fn split_bom(input: String, config: &mut ParserConfig) -> (&'static str, String) {
+    match input.strip_prefix('\u{FEFF}') {
+        Some(text) => {
+            config.insert(
+                ConfigKey::Encoding,
+                ConfigValue::Encoding(EncodingMode::Unicode),
+            );
+            ("\u{FEFF}", text.to_string())
+        }
+        None => ("", input),
+    }
+}
+
+/// Parse `input`, read from `path`, and rename its data names as `mapping` says, warning of
+/// the old names it doesn't have
+fn parse_renamed(
+    path: &Path,
+    input: &str,
+    config: &ParserConfig,
+    mapping: &HashMap<String, String>,
+    args: &Args,
+) -> Result<(MutablePair, RenameReport), String> {
+    let mut tree = parse(input, config).map_err(|e| {
+        let e = e.with_source_name(&path.display().to_string());
+        e.format_error(ErrorFormatMode::Basic, 0)
+    })?;
+    let options = RenameOptions {
+        rename_categories: args.rename_categories,
+        ..RenameOptions::default()
+    };
+    let report = rename_tags(&mut tree, mapping, options);
+    for name in &report.unmatched {
+        eprintln!(
+            "Warning: {} of --rename-map isn't in {}",
+            name,
+            path.display()
+        );
+    }
+    Ok((tree, report))
+}
+
+/// Convert the STAR file at `path` to JSON, renaming its data names as `mapping` says
+fn star_to_json(
+    path: &Path,
+    args: &Args,
+    mapping: Option<&HashMap<String, String>>,
+    writer: Box<dyn Write>,
+) -> Result<(), String> {
+    let config = star_config(args);
+    let input = read_star_file(path, &config).map_err(|e| e.to_string())?;
+
+    let mut handler = JsonHandler::new(writer);
+    if args.pretty {
+        handler = handler.with_indent("  ");
+    }
+    match mapping {
+        Some(mapping) => {
+            let mut config = config;
+            let (_, input) = split_bom(input, &mut config);
+            let (tree, _) = parse_renamed(path, &input, &config, mapping, args)?;
+            StarWalker::from_input(&mut handler, &input)
+                .with_config(&config)
+                .walk_star_tree_buffered(&tree);
+        }
+        None => {
+            walk(&input, &config, &mut handler).map_err(|e| {
+                let e = e.with_source_name(&path.display().to_string());
+                e.format_error(ErrorFormatMode::Basic, 0)
+            })?;
+        }
+    }
+    handler.into_inner().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Write the STAR file at `path` again with its data names renamed as `mapping` says, keeping
+/// everything else as it is
+fn star_to_star(
+    path: &Path,
+    args: &Args,
+    mapping: Option<&HashMap<String, String>>,
+    mut writer: Box<dyn Write>,
+) -> Result<(), String> {
+    let mut config = star_config(args);
+    let input = read_star_file(path, &config).map_err(|e| e.to_string())?;
+    let renamed = match mapping {
+        Some(mapping) => {
+            let (bom, input) = split_bom(input, &mut config);
+            let (_, report) = parse_renamed(path, &input, &config, mapping, args)?;
+            format!("{}{}", bom, report.apply_to(&input))
+        }
+        None => input,
+    };
+    writer
+        .write_all(renamed.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Convert the JSON file at `path` to STAR
+fn json_to_star(path: &Path, style: StarStyle, writer: Box<dyn Write>) -> Result<(), String> {
+    let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut handler = StarWriterHandler::new(writer);
+    if style == StarStyle::Pynmrstar {
+        handler = handler.with_pynmrstar_style();
+    }
+    walk_json(&json, &mut handler).map_err(|e| format!("invalid JSON: {}", e))?;
+    handler.into_inner().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    let is_json = args
+        .input
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let to = args.to.unwrap_or(if is_json {
+        OutputFormat::Star
+    } else {
+        OutputFormat::Json
+    });
+
+    if is_json && args.rename_map.is_some() {
+        eprintln!("Error: --rename-map renames the data names of STAR input, not JSON");
+        std::process::exit(1);
+    }
+    let mapping = match args.rename_map.as_deref().map(read_rename_map).transpose() {
+        Ok(mapping) => mapping,
+        Err(message) => {
+            eprintln!("Error reading --rename-map {}", message);
+            std::process::exit(1);
+        }
+    };
+
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Error creating {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let result = match to {
+        OutputFormat::Json => star_to_json(&args.input, &args, mapping.as_ref(), writer),
+        OutputFormat::Star if is_json => json_to_star(&args.input, args.style, writer),
+        OutputFormat::Star => star_to_star(&args.input, &args, mapping.as_ref(), writer),
+    };
+    if let Err(message) = result {
+        eprintln!("Error converting {}: {}", args.input.display(), message);
+        std::process::exit(1);
+    }
+}
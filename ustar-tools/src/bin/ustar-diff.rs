@@ -0,0 +1,107 @@
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use ustar_parser::diff::{diff_documents, DiffEntry, DiffOptions};
+use ustar_parser::line_column_index::LineColumn;
+use ustar_parser::mutable_pair::MutablePair;
+use ustar_parser::{default_config, parse, read_star_file, ErrorFormatMode};
+
+#[derive(Parser)]
+#[command(name = "ustar-diff")]
+#[command(about = "Compare two STAR files by blocks, save frames, data items and loop rows")]
+#[command(version = "0.1.0")]
+struct Args {
+    /// The first file, whose values are reported as old
+    #[arg(value_name = "A")]
+    a: PathBuf,
+    /// The second file, whose values are reported as new
+    #[arg(value_name = "B")]
+    b: PathBuf,
+    /// Match the rows of loops with these data names, separated by commas, by their values
+    /// instead of by position; may be repeated
+    #[arg(long, value_name = "TAGS")]
+    key: Vec<String>,
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+}
+
+/// Formats for --format
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    /// A line per difference: + added, - removed or ~ changed, the path, values and positions
+    Text,
+    /// A JSON object with a list of the differences
+    Json,
+}
+
+fn read_tree(path: &Path) -> Result<MutablePair, String> {
+    let config = default_config();
+    let input = read_star_file(path, &config).map_err(|e| e.to_string())?;
+    parse(&input, &config).map_err(|e| {
+        let e = e.with_source_name(&path.display().to_string());
+        e.format_error(ErrorFormatMode::Basic, 0)
+    })
+}
+
+fn position_json(position: Option<LineColumn>) -> serde_json::Value {
+    match position {
+        Some(position) => json!({"line": position.line, "column": position.column}),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn print_json(args: &Args, entries: &[DiffEntry]) {
+    let differences: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "kind": entry.kind.as_str(),
+                "path": entry.path,
+                "old": entry.old,
+                "new": entry.new,
+                "old_position": position_json(entry.old_position),
+                "new_position": position_json(entry.new_position),
+            })
+        })
+        .collect();
+    let report = json!({
+        "a": args.a.display().to_string(),
+        "b": args.b.display().to_string(),
+        "differences": differences,
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("a JSON value serializes")
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+    let (a, b) = match (read_tree(&args.a), read_tree(&args.b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(message), _) | (_, Err(message)) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let mut options = DiffOptions::default();
+    for key in &args.key {
+        let columns: Vec<&str> = key.split(',').map(str::trim).collect();
+        options = options.with_key_columns(&columns);
+    }
+    let entries = diff_documents(&a, &b, options);
+
+    match args.format {
+        ReportFormat::Text => {
+            for entry in &entries {
+                println!("{}", entry);
+            }
+        }
+        ReportFormat::Json => print_json(&args, &entries),
+    }
+    if !entries.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -1,12 +1,25 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use serde::Serialize;
+use std::collections::HashSet;
 use std::fs;
-use std::io::{self, Read};
-use std::path::PathBuf;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tabled::{settings::Style, Table, Tabled};
 use text_trees::{FormatCharacters, StringTreeNode, TreeFormatting};
-use ustar_parser::line_column_index::LineColumnIndex;
+use ustar_parser::line_column_index::LineColumn;
 use ustar_parser::mutable_pair::MutablePair;
-use ustar_parser::{default_config, get_context_lines, get_error_format, parse};
+use ustar_parser::query::{parse_query, Query};
+use ustar_parser::sas_handlers::{CsvLoopHandler, StarStats, StarWriterHandler, StatsHandler};
+use ustar_parser::sas_interface::ValueDelimiter;
+use ustar_parser::sas_walker::StarWalker;
+use ustar_parser::{
+    default_config, get_context_lines, get_error_format, parse, parse_with_diagnostics,
+    read_star_file, walk, ConfigFile, ConfigKey, ConfigValue, ErrorFormatMode, ParserConfig,
+    UstarError,
+};
+use ustar_tools::color::{paint, ColorChoice};
 use ustar_tools::dump_extractors::{DumpExtractor, MutablePairExtractor};
 
 #[derive(Parser)]
@@ -14,12 +27,212 @@ use ustar_tools::dump_extractors::{DumpExtractor, MutablePairExtractor};
 #[command(about = "A STAR format parser with detailed parse tree visualization")]
 #[command(version = "0.1.0")]
 struct Args {
-    /// Input file to parse (use '-' or omit for stdin)
+    /// Input files to parse (use '-' or omit for stdin)
     #[arg(value_name = "FILE")]
-    input: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+    /// Only show the files that fail to parse
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "extract_loop")]
+    quiet_success: bool,
     /// Display rule names as a tree with ASCII connecting lines
     #[arg(long, action = clap::ArgAction::SetTrue)]
     tree: bool,
+    /// Only show symbols of the rule NAME, may be repeated; like the other filters it keeps
+    /// the numbers symbols have in the full dump
+    #[arg(long, value_name = "NAME")]
+    rule: Vec<String>,
+    /// Only show the data block data_NAME and its contents
+    #[arg(long, value_name = "NAME")]
+    block: Option<String>,
+    /// Only show save frames save_NAME and their contents
+    #[arg(long, value_name = "NAME")]
+    saveframe: Option<String>,
+    /// Only show symbols at most N levels below the root
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+    /// Only show the blocks, save frames, items and loop values the STAR path QUERY selects,
+    /// such as data_NAME/save_NAME/_tag or //_tag[3], and their contents
+    #[arg(long, value_name = "QUERY", value_parser = parse_select, conflicts_with_all = ["extract_loop", "stats", "reformat"])]
+    select: Option<Query>,
+    /// Write the rows of loops with a tag starting with PREFIX instead of the parse tree,
+    /// may be repeated
+    #[arg(long, value_name = "PREFIX")]
+    extract_loop: Vec<String>,
+    /// Format of the parse tree, table by default, or of the extracted loops, csv by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Write the extracted loops to FILE instead of stdout
+    #[arg(long, value_name = "FILE", requires = "extract_loop")]
+    output: Option<PathBuf>,
+    /// Print counts of blocks, save frames, loops, values and comments instead of the parse tree
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["tree", "extract_loop"])]
+    stats: bool,
+    /// Write the input again as neatly laid out STAR, keeping its comments, instead of the
+    /// parse tree
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["tree", "extract_loop", "stats", "format"])]
+    reformat: bool,
+    /// Layout --reformat writes
+    #[arg(long, value_enum, default_value_t = ReformatStyle::Neat, requires = "reformat")]
+    style: ReformatStyle,
+    /// Whether --reformat pads the values of loops so their columns line up
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Set, default_value_t = true, requires = "reformat")]
+    align_loops: bool,
+    /// Replace each input file with its reformatted text, keeping the original as FILE.bak
+    #[arg(long, action = clap::ArgAction::SetTrue, requires = "reformat")]
+    in_place: bool,
+    /// When to color the content column
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Show whitespace and line breaks in the content column without the ·, →, ␊ and ␍ markers
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_whitespace_markers: bool,
+    /// Print warnings about the input, such as mixed line endings, to stderr
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    warnings: bool,
+    /// Read parser options from a TOML or JSON (.json) file instead of the defaults
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+/// Formats for --format
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// The parse tree as a table with a row per symbol
+    Table,
+    /// The parse tree as a JSON object with an entry per symbol
+    Json,
+    /// The parse tree as a line of JSON per symbol, written as the tree is read
+    Ndjson,
+    /// Extracted loops as comma separated values
+    Csv,
+    /// Extracted loops as tab separated values
+    Tsv,
+}
+
+impl Format {
+    fn is_loop_format(self) -> bool {
+        matches!(self, Format::Csv | Format::Tsv)
+    }
+
+    fn separator(self) -> char {
+        match self {
+            Format::Tsv => '\t',
+            _ => ',',
+        }
+    }
+}
+
+/// Layouts for --style
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReformatStyle {
+    /// Four space indents and blank lines around save frames and loops, keeping comments
+    Neat,
+    /// NMR-STAR as pynmrstar writes it, without comments
+    Pynmrstar,
+}
+
+/// Parse the STAR path of --select
+fn parse_select(query: &str) -> Result<Query, String> {
+    parse_query(query).map_err(|e| e.to_string())
+}
+
+/// The symbols selected by --rule, --block, --saveframe, --select and --max-depth
+///
+/// Symbols keep the index and level they have in the full dump so a filtered dump can be
+/// matched against it. The subtrees of other blocks and save frames, and those below the
+/// maximum depth, are skipped without being visited.
+struct SymbolFilter<'t> {
+    rules: Vec<String>,
+    block: Option<String>,
+    saveframe: Option<String>,
+    /// The rule and offsets of the nodes of the tree --select selects, which identify them
+    /// in the copies of the tree the table is built from
+    selected: Option<HashSet<(&'t str, usize, usize)>>,
+    max_depth: Option<usize>,
+}
+
+/// Whether a symbol is inside the selected data block and save frame, and a node --select
+/// selects
+#[derive(Clone, Copy, Default)]
+struct Scope {
+    in_block: bool,
+    in_saveframe: bool,
+    in_selection: bool,
+}
+
+impl<'t> SymbolFilter<'t> {
+    fn from_args(args: &Args, tree: &'t MutablePair) -> SymbolFilter<'t> {
+        SymbolFilter {
+            rules: args.rule.clone(),
+            block: args.block.clone(),
+            saveframe: args.saveframe.clone(),
+            selected: args.select.as_ref().map(|query| {
+                query
+                    .select(tree)
+                    .into_iter()
+                    .map(|node| (node.rule_name(), node.start, node.end))
+                    .collect()
+            }),
+            max_depth: args.max_depth,
+        }
+    }
+
+    /// The scope of `pair`, or None if it is a block or save frame other than the selected one
+    fn enter(&self, pair: &MutablePair, scope: Scope) -> Option<Scope> {
+        let key = (pair.rule_name(), pair.start, pair.end);
+        let scope = match &self.selected {
+            Some(selected) if selected.contains(&key) => Scope {
+                in_selection: true,
+                ..scope
+            },
+            _ => scope,
+        };
+        match pair.rule_name() {
+            "data_block" | "global_block" => match &self.block {
+                Some(block) => heading_name(pair, "data_")
+                    .filter(|name| {
+                        pair.rule_name() == "data_block" && name.eq_ignore_ascii_case(block)
+                    })
+                    .map(|_| Scope {
+                        in_block: true,
+                        ..scope
+                    }),
+                None => Some(scope),
+            },
+            "save_frame" => match &self.saveframe {
+                Some(saveframe) => heading_name(pair, "save_")
+                    .filter(|name| name.eq_ignore_ascii_case(saveframe))
+                    .map(|_| Scope {
+                        in_saveframe: true,
+                        ..scope
+                    }),
+                None => Some(scope),
+            },
+            _ => Some(scope),
+        }
+    }
+
+    /// Whether to show `pair`, a symbol in `scope`
+    fn shows(&self, pair: &MutablePair, scope: Scope) -> bool {
+        (self.block.is_none() || scope.in_block)
+            && (self.saveframe.is_none() || scope.in_saveframe)
+            && (self.selected.is_none() || scope.in_selection)
+            && (self.rules.is_empty() || self.rules.iter().any(|rule| rule == pair.rule_name()))
+    }
+
+    /// Whether to visit the children of a symbol at `level`
+    fn descends(&self, level: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| level < max_depth)
+    }
+}
+
+/// The name of a block or save frame from its heading, without `keyword`
+fn heading_name<'a>(pair: &'a MutablePair, keyword: &str) -> Option<&'a str> {
+    pair.children().first()?.as_str().get(keyword.len()..)
+}
+
+/// The number of symbols in the subtree of `pair`, including itself
+fn subtree_size(pair: &MutablePair) -> usize {
+    1 + pair.children().iter().map(subtree_size).sum::<usize>()
 }
 
 /// Structure to hold information about a parsed symbol for table display
@@ -49,17 +262,16 @@ fn display_rule(rule: &String) -> String {
     rule.clone()
 }
 
-/// Generate tree visualization from MutablePair using text_trees
+/// Generate tree visualization from any tree `extractor` reads using text_trees
 /// Returns a vector of strings representing the tree structure
-fn generate_tree_lines(root: &MutablePair) -> Vec<String> {
-    fn build_tree_node(pair: &MutablePair) -> StringTreeNode {
-        let extractor = MutablePairExtractor::new();
-        let rule_name = extractor.extract_rule_name(pair);
+fn generate_tree_lines<E: DumpExtractor>(extractor: &E, root: &E::Node<'_>) -> Vec<String> {
+    fn build_tree_node<E: DumpExtractor>(extractor: &E, pair: &E::Node<'_>) -> StringTreeNode {
+        let rule_name = extractor.rule_name(pair);
 
         if extractor.has_children(pair) {
             let children: Vec<StringTreeNode> = extractor
-                .get_children(pair)
-                .map(|child| build_tree_node(&child))
+                .children(pair)
+                .map(|child| build_tree_node(extractor, &child))
                 .collect();
 
             StringTreeNode::with_child_nodes(rule_name, children.into_iter())
@@ -68,7 +280,7 @@ fn generate_tree_lines(root: &MutablePair) -> Vec<String> {
         }
     }
 
-    let tree_node = build_tree_node(root);
+    let tree_node = build_tree_node(extractor, root);
 
     // Use box characters, top-down orientation, anchor below with your specified settings
     let formatting = TreeFormatting::dir_tree(FormatCharacters::box_chars());
@@ -86,32 +298,41 @@ fn display_line_col(line_col: &String) -> String {
     line_col.clone()
 }
 
-/// Apply ANSI coloring to whitespace and special characters
-fn apply_content_coloring(content_part: &str) -> String {
+/// How the content column shows whitespace, see --color and --no-whitespace-markers
+#[derive(Clone, Copy)]
+struct ContentStyle {
+    color: bool,
+    markers: bool,
+}
+
+/// Mark whitespace and line breaks with visible placeholders and grey them and the ellipsis
+/// of shortened content, as `style` asks
+fn apply_content_coloring(content_part: &str, style: ContentStyle) -> String {
+    let grey = |text: &str| paint(text, "38;5;250", style.color); // Very light grey
     let mut result = String::new();
     let mut chars = content_part.chars().peekable();
 
     while let Some(ch) = chars.next() {
         match ch {
-            ' ' => result.push_str("\x1b[38;5;250m·\x1b[0m"), // Very light grey dot for space
-            '\t' => result.push_str("\x1b[38;5;250m→\x1b[0m"), // Very light grey arrow for tab
+            ' ' if style.markers => result.push_str(&grey("·")), // Dot for space
+            '\t' if style.markers => result.push_str(&grey("→")), // Arrow for tab
             '.' if chars.peek() == Some(&'.') => {
                 // Handle ellipsis
                 chars.next(); // consume second dot
                 if chars.peek() == Some(&'.') {
                     chars.next(); // consume third dot
-                    result.push_str("\x1b[38;5;250m...\x1b[0m"); // Very light grey ellipsis
+                    result.push_str(&grey("..."));
                 } else {
                     result.push_str("..");
                 }
             }
             _ => {
                 // Handle escape sequences
-                if ch == '\\' && chars.peek().is_some() {
+                if ch == '\\' && style.markers && chars.peek().is_some() {
                     let next_ch = chars.next().unwrap();
                     match next_ch {
-                        'n' => result.push_str("\x1b[38;5;250m␊\x1b[0m"), // Very light grey newline symbol
-                        'r' => result.push_str("\x1b[38;5;250m␍\x1b[0m"), // Very light grey carriage return symbol
+                        'n' => result.push_str(&grey("␊")), // Newline symbol
+                        'r' => result.push_str(&grey("␍")), // Carriage return symbol
                         _ => {
                             result.push('\\');
                             result.push(next_ch);
@@ -127,113 +348,131 @@ fn apply_content_coloring(content_part: &str) -> String {
     result
 }
 
-/// Collect symbol information from MutablePair into a vector for table display
+/// Collect symbol information from MutablePair into a vector for table display, skipping
+/// the symbols `filter` doesn't select
 fn collect_symbol_info_from_mutable(
     pair: &MutablePair,
-    line_index: &LineColumnIndex,
     symbol_counter: &mut usize,
     indent_level: usize,
     symbols: &mut Vec<SymbolInfo>,
     tree_lines: Option<&[String]>,
+    filter: &SymbolFilter,
+    scope: Scope,
 ) {
     let extractor = MutablePairExtractor::new();
 
+    // Skipped subtrees are still counted so symbols keep their numbers in the full dump
+    let Some(scope) = filter.enter(pair, scope) else {
+        *symbol_counter += subtree_size(pair);
+        return;
+    };
     *symbol_counter += 1;
     let current_symbol = *symbol_counter;
 
-    // Get tree line if available, otherwise use basic rule name
-    let rule_name = if let Some(lines) = tree_lines {
-        // Use the tree line for this symbol index
-        if let Some(line) = lines.get(current_symbol - 1) {
-            line.clone()
+    if filter.shows(pair, scope) {
+        // Get tree line if available, otherwise use basic rule name
+        let rule_name = if let Some(lines) = tree_lines {
+            // Use the tree line for this symbol index
+            if let Some(line) = lines.get(current_symbol - 1) {
+                line.clone()
+            } else {
+                extractor.rule_name(pair)
+            }
         } else {
-            extractor.extract_rule_name(pair)
-        }
-    } else {
-        extractor.extract_rule_name(pair)
-    };
+            extractor.rule_name(pair)
+        };
 
-    let start_pos = extractor.extract_start(pair);
-    let end_pos = extractor.extract_end(pair);
-    let content = extractor.extract_str(pair);
-
-    // Calculate line and column positions using fast index
-    let start_line_col = line_index.offset_to_line_col(start_pos);
-    let end_line_col = line_index.offset_to_line_col(end_pos);
-
-    // Check if this has children (non-terminal)
-    let has_children = extractor.has_children(pair);
-
-    // Format content display: apply 30...30 rule to ALL symbols and replace newlines
-    let normalized_content = content.replace('\n', "\\n").replace('\r', "\\r");
-    let display_content = if normalized_content.len() > 65 {
-        // For long content, show first 30 ... last 30 chars
-        let first_30: String = normalized_content.chars().take(30).collect();
-        let last_30: String = normalized_content
-            .chars()
-            .rev()
-            .take(30)
-            .collect::<String>()
-            .chars()
-            .rev()
-            .collect();
-        format!("{}...{}", first_30, last_30)
-    } else {
-        normalized_content
-    };
+        let start_pos = extractor.start(pair);
+        let end_pos = extractor.end(pair);
+        let content = extractor.text(pair);
 
-    // Create symbol info
-    let symbol_info = SymbolInfo {
-        symbol_number: current_symbol,
-        level: indent_level,
-        rule_name,
-        positions: format!("{}-{}", start_pos, end_pos),
-        line_col: format!(
-            "{}:{}-{}:{}",
-            start_line_col.line, start_line_col.column, end_line_col.line, end_line_col.column
-        ),
-        content: display_content,
-    };
+        // Line and column positions are stored in the tree by the parser
+        let start_line_col = pair.start_line_column();
+        let end_line_col = pair.end_line_column();
 
-    symbols.push(symbol_info);
+        // Format content display: apply 30...30 rule to ALL symbols and replace newlines
+        let normalized_content = content.replace('\n', "\\n").replace('\r', "\\r");
+        let display_content = if normalized_content.len() > 65 {
+            // For long content, show first 30 ... last 30 chars
+            let first_30: String = normalized_content.chars().take(30).collect();
+            let last_30: String = normalized_content
+                .chars()
+                .rev()
+                .take(30)
+                .collect::<String>()
+                .chars()
+                .rev()
+                .collect();
+            format!("{}...{}", first_30, last_30)
+        } else {
+            normalized_content
+        };
+
+        // Create symbol info
+        let symbol_info = SymbolInfo {
+            symbol_number: current_symbol,
+            level: indent_level,
+            rule_name,
+            positions: format!("{}-{}", start_pos, end_pos),
+            line_col: format!(
+                "{}:{}-{}:{}",
+                start_line_col.line, start_line_col.column, end_line_col.line, end_line_col.column
+            ),
+            content: display_content,
+        };
+
+        symbols.push(symbol_info);
+    }
 
     // Recursively collect child pairs
-    if has_children {
-        for child in extractor.get_children(pair) {
-            collect_symbol_info_from_mutable(
-                &child,
-                line_index,
-                symbol_counter,
-                indent_level + 1,
-                symbols,
-                tree_lines,
-            );
-        }
+    if !extractor.has_children(pair) {
+        return;
+    }
+    if !filter.descends(indent_level) {
+        *symbol_counter += subtree_size(pair) - 1;
+        return;
+    }
+    for child in extractor.children(pair) {
+        collect_symbol_info_from_mutable(
+            &child,
+            symbol_counter,
+            indent_level + 1,
+            symbols,
+            tree_lines,
+            filter,
+            scope,
+        );
     }
 }
 
 /// Display parse tree as a formatted table using tabled for alignment (no headers/borders)
-/// Returns the number of symbols parsed
-fn display_parse_tree(mutable_pair: &MutablePair, input: &str, use_tree: bool) -> usize {
+/// Returns the number of symbols shown
+fn display_parse_tree(
+    mutable_pair: &MutablePair,
+    use_tree: bool,
+    filter: &SymbolFilter,
+    style: ContentStyle,
+) -> usize {
     let mut symbol_counter = 0;
     let mut symbols = Vec::new();
 
-    // Create fast line/column index for O(log n) lookups
-    let line_index = LineColumnIndex::new(input);
-
     let tree_lines = if use_tree {
-        Some(generate_tree_lines(mutable_pair))
+        Some(generate_tree_lines(
+            &MutablePairExtractor::new(),
+            mutable_pair,
+        ))
     } else {
         None
     };
 
     collect_symbol_info_from_mutable(
         mutable_pair,
-        &line_index,
         &mut symbol_counter,
         0,
         &mut symbols,
         tree_lines.as_deref(),
+        filter,
+        Scope::default(),
     );
 
     // Pre-compute the maximum width of the first part of line:col for alignment
@@ -333,7 +572,7 @@ fn display_parse_tree(mutable_pair: &MutablePair, input: &str, use_tree: bool) -
                 let content_trimmed = content_part.trim_end();
 
                 // Apply light grey coloring to special characters
-                let colored_content = apply_content_coloring(content_trimmed);
+                let colored_content = apply_content_coloring(content_trimmed, style);
 
                 println!("{}{}", prefix, colored_content);
             } else {
@@ -342,69 +581,526 @@ fn display_parse_tree(mutable_pair: &MutablePair, input: &str, use_tree: bool) -
         }
     }
 
-    symbol_counter
+    symbols.len()
+}
+
+/// A symbol of the parse tree as written by --format json and ndjson
+#[derive(Serialize)]
+struct SymbolRecord<'a> {
+    index: usize,
+    level: usize,
+    rule: &'a str,
+    start: usize,
+    end: usize,
+    line_col: LineColRange,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct LineColRange {
+    start: LineColumn,
+    end: LineColumn,
+}
+
+/// The parse tree as written by --format json
+#[derive(Serialize)]
+struct TreeRecord<'a> {
+    source: &'a str,
+    lines: usize,
+    symbols: Vec<SymbolRecord<'a>>,
+}
+
+/// Call `visit` with each symbol of the tree `filter` selects in document order, numbering
+/// them from 1 as in the full dump
+fn visit_symbols<'a>(
+    pair: &'a MutablePair,
+    level: usize,
+    index: &mut usize,
+    filter: &SymbolFilter,
+    scope: Scope,
+    visit: &mut impl FnMut(SymbolRecord<'a>) -> io::Result<()>,
+) -> io::Result<()> {
+    let Some(scope) = filter.enter(pair, scope) else {
+        *index += subtree_size(pair);
+        return Ok(());
+    };
+    *index += 1;
+    if filter.shows(pair, scope) {
+        visit(SymbolRecord {
+            index: *index,
+            level,
+            rule: pair.rule_name(),
+            start: pair.start_pos(),
+            end: pair.end_pos(),
+            line_col: LineColRange {
+                start: pair.start_line_column(),
+                end: pair.end_line_column(),
+            },
+            content: pair.as_str(),
+        })?;
+    }
+    if !filter.descends(level) {
+        *index += subtree_size(pair) - 1;
+        return Ok(());
+    }
+    for child in pair.children() {
+        visit_symbols(child, level + 1, index, filter, scope, visit)?;
+    }
+    Ok(())
+}
+
+/// Write the parse tree as JSON, content is written in full and without coloring
+fn write_json_tree(
+    tree: &MutablePair,
+    filter: &SymbolFilter,
+    source: &str,
+    lines: usize,
+) -> io::Result<()> {
+    let mut symbols = Vec::new();
+    visit_symbols(tree, 0, &mut 0, filter, Scope::default(), &mut |symbol| {
+        symbols.push(symbol);
+        Ok(())
+    })?;
+    let record = TreeRecord {
+        source,
+        lines,
+        symbols,
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    serde_json::to_writer_pretty(&mut out, &record)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+/// Write the parse tree as a line of JSON per symbol
+fn write_ndjson_tree(tree: &MutablePair, filter: &SymbolFilter) -> io::Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    visit_symbols(tree, 0, &mut 0, filter, Scope::default(), &mut |symbol| {
+        serde_json::to_writer(&mut out, &symbol)?;
+        writeln!(out)
+    })?;
+    out.flush()
+}
+
+/// The statistics written by --stats
+#[derive(Serialize)]
+struct StatsRecord<'a> {
+    source: &'a str,
+    file_size: usize,
+    parse_time_ms: f64,
+    max_depth: usize,
+    data_blocks: usize,
+    global_blocks: usize,
+    saveframes: usize,
+    loops: usize,
+    nested_loops: usize,
+    items: usize,
+    loop_values: usize,
+    comments: usize,
+    delimiters: DelimiterCounts<'a>,
+}
+
+impl<'a> StatsRecord<'a> {
+    fn new(source: &'a str, input: &str, parse_time: Duration, stats: &'a StarStats) -> Self {
+        StatsRecord {
+            source,
+            file_size: input.len(),
+            parse_time_ms: parse_time.as_secs_f64() * 1000.0,
+            max_depth: stats.max_depth,
+            data_blocks: stats.data_blocks,
+            global_blocks: stats.global_blocks,
+            saveframes: stats.saveframes,
+            loops: stats.loops,
+            nested_loops: stats.nested_loops,
+            items: stats.items,
+            loop_values: stats.loop_values,
+            comments: stats.comments,
+            delimiters: DelimiterCounts(&stats.delimiters),
+        }
+    }
+}
+
+/// The number of values with each delimiter, written as an object in the order of the delimiters
+struct DelimiterCounts<'a>(&'a [(ValueDelimiter, usize)]);
+
+impl Serialize for DelimiterCounts<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.0
+                .iter()
+                .map(|(delimiter, count)| (delimiter_name(*delimiter), count)),
+        )
+    }
+}
+
+fn delimiter_name(delimiter: ValueDelimiter) -> &'static str {
+    match delimiter {
+        ValueDelimiter::None => "unquoted",
+        ValueDelimiter::Single => "single_quoted",
+        ValueDelimiter::Double => "double_quoted",
+        ValueDelimiter::Semicolon => "text_field",
+        ValueDelimiter::TripleSingle => "triple_single_quoted",
+        ValueDelimiter::TripleDouble => "triple_double_quoted",
+        ValueDelimiter::List => "list",
+        ValueDelimiter::Table => "table",
+        ValueDelimiter::EmptyLoop => "empty_loop",
+    }
+}
+
+/// Print the statistics as a table of names and values
+fn print_stats(stats: &StatsRecord) {
+    let mut rows = vec![
+        (
+            "file size".to_string(),
+            format!("{} bytes", stats.file_size),
+        ),
+        (
+            "parse time".to_string(),
+            format!("{:.3} ms", stats.parse_time_ms),
+        ),
+        ("max depth".to_string(), stats.max_depth.to_string()),
+        ("data blocks".to_string(), stats.data_blocks.to_string()),
+        ("global blocks".to_string(), stats.global_blocks.to_string()),
+        ("save frames".to_string(), stats.saveframes.to_string()),
+        ("loops".to_string(), stats.loops.to_string()),
+        ("nested loops".to_string(), stats.nested_loops.to_string()),
+        ("items".to_string(), stats.items.to_string()),
+        ("loop values".to_string(), stats.loop_values.to_string()),
+        ("comments".to_string(), stats.comments.to_string()),
+    ];
+    rows.extend(stats.delimiters.0.iter().map(|(delimiter, count)| {
+        (
+            format!("values {}", delimiter_name(*delimiter).replace('_', " ")),
+            count.to_string(),
+        )
+    }));
+
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    println!("source: {}", stats.source);
+    println!();
+    for (name, value) in rows {
+        println!("{:<width$}  {}", name, value, width = width);
+    }
+}
+
+/// Write the loops matching the --extract-loop prefixes, returning the number of loops written
+fn extract_loops(args: &Args, input_text: &str, tree: &MutablePair) -> io::Result<usize> {
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let prefixes: Vec<&str> = args.extract_loop.iter().map(String::as_str).collect();
+    let format = args.format.unwrap_or(Format::Csv);
+    let mut handler = CsvLoopHandler::new(writer, &prefixes).with_separator(format.separator());
+
+    StarWalker::from_input(&mut handler, input_text).walk_star_tree_buffered(tree);
+
+    let loops_written = handler.loops_written();
+    handler.into_inner()?;
+    Ok(loops_written)
 }
 
 fn main() {
     let args = Args::parse();
+    if let Some(format) = args.format {
+        let message = if args.stats && !matches!(format, Format::Table | Format::Json) {
+            Some("--stats writes a table or json")
+        } else if format.is_loop_format() == args.extract_loop.is_empty() {
+            Some(if args.extract_loop.is_empty() {
+                "--format csv and tsv require --extract-loop"
+            } else {
+                "--extract-loop writes loops as csv or tsv"
+            })
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            Args::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+    }
+    let config = match &args.config {
+        Some(path) => match ParserConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error in config file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => default_config(),
+    };
 
-    // Determine input source
-    let (input_text, source_info) = match &args.input {
+    let inputs: Vec<Option<&Path>> = if args.inputs.is_empty() {
+        vec![None]
+    } else {
+        args.inputs
+            .iter()
+            .map(|path| (path.to_string_lossy() != "-").then_some(path.as_path()))
+            .collect()
+    };
+    if args.in_place && inputs.contains(&None) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--in-place rewrites files, it can't read stdin",
+            )
+            .exit();
+    }
+    if args.output.is_some() && inputs.len() > 1 {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--output takes a single input file",
+            )
+            .exit();
+    }
+
+    // JSON output names its source, other output gets a header when there are several files
+    let headers = inputs.len() > 1 && !matches!(args.format, Some(Format::Json | Format::Ndjson));
+    let mut failures = Vec::new();
+    let mut shown = 0;
+    for input in &inputs {
+        let header = headers.then_some(shown > 0);
+        match dump_input(&args, &config, *input, header) {
+            Ok(true) => shown += 1,
+            Ok(false) => {}
+            Err(failure) => failures.push(failure),
+        }
+    }
+
+    if failures.is_empty() {
+        return;
+    }
+    if inputs.len() > 1 {
+        eprintln!();
+        eprintln!("{} of {} files failed:", failures.len(), inputs.len());
+        for failure in &failures {
+            eprintln!("  {}", failure);
+        }
+    }
+    std::process::exit(1);
+}
+
+/// Read the text of `input`, a file or stdin if None, and the name to report it by
+fn read_input(input: Option<&Path>, config: &ParserConfig) -> Result<(String, String), String> {
+    match input {
         None => {
-            // Read from stdin
             let mut buffer = String::new();
             match io::stdin().read_to_string(&mut buffer) {
-                Ok(_) => (buffer, "-".to_string()),
+                Ok(_) => Ok((buffer, "-".to_string())),
                 Err(e) => {
                     eprintln!("Error reading from stdin: {}", e);
-                    std::process::exit(1);
+                    Err(format!("-: {}", e))
                 }
             }
         }
-        Some(path) if path.to_string_lossy() == "-" => {
-            // Explicitly requested stdin with "-"
-            let mut buffer = String::new();
-            match io::stdin().read_to_string(&mut buffer) {
-                Ok(_) => (buffer, "-".to_string()),
-                Err(e) => {
-                    eprintln!("Error reading from stdin: {}", e);
-                    std::process::exit(1);
-                }
+        Some(path) => match read_star_file(path, config) {
+            Ok(content) => Ok((content, path.display().to_string())),
+            Err(e) => {
+                eprintln!("{}", e.format_error(ErrorFormatMode::Basic, 0));
+                Err(format!("{}: {}", path.display(), e.core().message))
             }
-        }
-        Some(path) => {
-            // Read from file
-            match fs::read_to_string(path) {
-                Ok(content) => (content, path.display().to_string()),
-                Err(e) => {
-                    eprintln!("Error reading file {}: {}", path.display(), e);
-                    std::process::exit(1);
-                }
+        },
+    }
+}
+
+/// Dump one input as the arguments ask, returning whether anything was shown or a line
+/// summarising why it failed, once the failure itself has been reported
+///
+/// `header` is None for no header, otherwise whether to put a blank line before it.
+fn dump_input(
+    args: &Args,
+    config: &ParserConfig,
+    input: Option<&Path>,
+    header: Option<bool>,
+) -> Result<bool, String> {
+    let (input_text, source_info) = read_input(input, config)?;
+    let print_header = || {
+        if let Some(separate) = header {
+            if separate {
+                println!();
             }
+            println!("==> {} <==", source_info);
         }
     };
 
+    if args.stats {
+        // attached comments are passed on to the handler, so all comments are counted
+        let mut stats_config = config.clone();
+        stats_config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+        let started = Instant::now();
+        let mut handler = StatsHandler::new();
+        if let Err(e) = walk(&input_text, &stats_config, &mut handler) {
+            return Err(report_syntax_error(*e, input, &source_info, config));
+        }
+        if args.quiet_success {
+            return Ok(false);
+        }
+        let stats = StatsRecord::new(
+            &source_info,
+            &input_text,
+            started.elapsed(),
+            handler.stats(),
+        );
+        print_header();
+        if args.format == Some(Format::Json) {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&stats).expect("statistics serialize")
+            );
+        } else {
+            print_stats(&stats);
+        }
+        return Ok(true);
+    }
+
+    if args.reformat {
+        return reformat(args, config, input, &input_text, &source_info, print_header);
+    }
+
     // Parse the input using the new error formatting system
-    let config = default_config();
-    match parse(&input_text, &config) {
-        Ok(mutable_result) => {
-            println!("source: {}", source_info);
-            println!();
-            let symbol_count = display_parse_tree(&mutable_result, &input_text, args.tree);
-            let line_count = input_text.lines().count();
-            println!();
-            println!("lines: {} symbols: {}", line_count, symbol_count);
+    let (result, diagnostics) = if args.warnings {
+        parse_with_diagnostics(&input_text, config)
+    } else {
+        (parse(&input_text, config), Vec::new())
+    };
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {}", source_info, diagnostic);
+    }
+    let mutable_result = match result {
+        Ok(mutable_result) => mutable_result,
+        Err(e) => return Err(report_syntax_error(*e, input, &source_info, config)),
+    };
+    if args.quiet_success {
+        return Ok(false);
+    }
+
+    print_header();
+    if !args.extract_loop.is_empty() {
+        match extract_loops(args, &input_text, &mutable_result) {
+            Ok(0) => {
+                let message = format!(
+                    "No loop in {} has a tag starting with {}",
+                    source_info,
+                    args.extract_loop.join(" or ")
+                );
+                eprintln!("{}", message);
+                return Err(message);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error writing loops from {}: {}", source_info, e);
+                return Err(format!("{}: {}", source_info, e));
+            }
         }
-        Err(e) => {
-            eprintln!("Syntax error in {}", source_info);
+    } else if matches!(args.format, Some(Format::Json | Format::Ndjson)) {
+        let filter = SymbolFilter::from_args(args, &mutable_result);
+        let written = if args.format == Some(Format::Json) {
+            write_json_tree(
+                &mutable_result,
+                &filter,
+                &source_info,
+                input_text.lines().count(),
+            )
+        } else {
+            write_ndjson_tree(&mutable_result, &filter)
+        };
+        if let Err(e) = written {
+            eprintln!("Error writing the parse tree of {}: {}", source_info, e);
+            return Err(format!("{}: {}", source_info, e));
+        }
+    } else {
+        println!("source: {}", source_info);
+        println!();
+        let filter = SymbolFilter::from_args(args, &mutable_result);
+        let style = ContentStyle {
+            color: args.color.enabled(),
+            markers: !args.no_whitespace_markers,
+        };
+        let symbol_count = display_parse_tree(&mutable_result, args.tree, &filter, style);
+        let line_count = input_text.lines().count();
+        println!();
+        println!("lines: {} symbols: {}", line_count, symbol_count);
+    }
+    Ok(true)
+}
 
-            eprintln!();
+/// Write `input_text` laid out afresh with its comments, to stdout or back over `input` with
+/// --in-place, returning whether anything was shown as `dump_input` does
+fn reformat(
+    args: &Args,
+    config: &ParserConfig,
+    input: Option<&Path>,
+    input_text: &str,
+    source_info: &str,
+    print_header: impl Fn(),
+) -> Result<bool, String> {
+    // comments outside loops are only reported when attached to the element after them
+    let mut reformat_config = config.clone();
+    reformat_config.insert(ConfigKey::AttachComments, ConfigValue::Bool(true));
+    let mut handler = StarWriterHandler::new(Vec::new());
+    handler = match args.style {
+        ReformatStyle::Neat => handler.with_comments().with_blank_lines(),
+        ReformatStyle::Pynmrstar => handler.with_pynmrstar_style(),
+    };
+    if args.align_loops {
+        handler = handler.with_aligned_loops();
+    }
+    if let Err(e) = walk(input_text, &reformat_config, &mut handler) {
+        return Err(report_syntax_error(*e, input, source_info, config));
+    }
+    let written = handler
+        .into_inner()
+        .expect("writing to memory doesn't fail");
+    let text = String::from_utf8(written).expect("the text written is from a str");
 
-            // Then show the detailed error formatting
-            let error_format = get_error_format(&config);
-            let context_lines = get_context_lines(&config);
-            eprintln!("{}", e.format_error(error_format, context_lines));
-            std::process::exit(1);
+    match input {
+        Some(path) if args.in_place => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            if let Err(e) = fs::copy(path, &backup).and_then(|_| fs::write(path, &text)) {
+                eprintln!("Error rewriting {}: {}", source_info, e);
+                return Err(format!("{}: {}", source_info, e));
+            }
+            Ok(false)
+        }
+        _ if args.quiet_success => Ok(false),
+        _ => {
+            print_header();
+            print!("{}", text);
+            Ok(true)
         }
     }
 }
+
+/// Print a parse error in the format the config asks for, returning a line summarising it
+fn report_syntax_error(
+    e: UstarError,
+    input: Option<&Path>,
+    source_info: &str,
+    config: &ParserConfig,
+) -> String {
+    eprintln!("Syntax error in {}", source_info);
+
+    eprintln!();
+
+    // Then show the detailed error formatting, naming the file it is in
+    let e = match input {
+        Some(_) => e.with_source_name(source_info),
+        None => e,
+    };
+    let error_format = get_error_format(config);
+    let context_lines = get_context_lines(config);
+    eprintln!("{}", e.format_error(error_format, context_lines));
+    let core = e.core();
+    format!(
+        "{}: l{}:c{} {} {}",
+        source_info,
+        core.line,
+        core.col,
+        core.code.as_str(),
+        core.message
+    )
+}
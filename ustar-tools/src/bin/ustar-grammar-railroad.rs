@@ -49,6 +49,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Grammar file loaded, {} bytes", grammar_content.len());
     }
 
+    // pest_railroad predates PUSH_LITERAL, PUSH of the same string draws the same diagram
+    let grammar_content = grammar_content.replace("PUSH_LITERAL(", "PUSH(");
+
     // Generate railroad diagram
     match generate_diagram(&grammar_content) {
         Ok((diagram, warnings)) => {
@@ -1,7 +1,7 @@
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, ValueEnum};
 
 #[derive(Debug)]
-struct ParseResult {
+struct ParseResult<'a, R: RuleType> {
     last_position: usize,
     #[allow(dead_code)]
     total_bytes: usize,
@@ -11,22 +11,54 @@ struct ParseResult {
     total_lines: usize,
     parsed_lines: usize,
     remaining_lines: usize,
-    truncated_content: String,
-    unparsed_content: String,
+    unparsed_content: &'a str,
+    pairs: Pairs<'a, R>,
 }
-use pest::Parser;
+use pest::iterators::{Pair, Pairs};
+use pest::{Parser, RuleType};
+use rayon::prelude::*;
+use serde_json::json;
 use std::fs;
-use std::path::PathBuf;
-use ustar_parser::parsers::ascii::{AsciiParser, Rule};
-use ustar_parser::{default_config, parse, ConfigKey, ConfigValue, ErrorFormatMode};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tabled::{settings::Style, Table, Tabled};
+use ustar_parser::parsers::ascii::AsciiParser;
+use ustar_parser::parsers::extended::ExtendedParser;
+use ustar_parser::parsers::unicode::UnicodeParser;
+use ustar_parser::{
+    default_config, get_auto_detect_bom, get_cif_version, get_context_lines, get_encoding,
+    get_error_format, parse, read_star_file, CifVersion, ConfigFile, ConfigKey, ConfigValue,
+    EncodingMode, ErrorFormatMode, ParserConfig, UstarError,
+};
+use ustar_tools::color::{paint, ColorChoice};
+use ustar_tools::dump_extractors::{visit_nodes, DumpExtractor, PestPairExtractor};
+use ustar_tools::report_bundle;
 
 #[derive(ClapParser, Debug)]
 #[command(name = "ustar-parse-debugger")]
 #[command(about = "Debug STAR file parsing by finding the last parseable position")]
 struct Args {
     /// Input file to debug
-    #[arg(value_name = "FILE")]
-    input: PathBuf,
+    #[arg(value_name = "FILE", required_unless_present = "batch")]
+    input: Option<PathBuf>,
+
+    /// Debug every file in DIR and its subdirectories, printing a summary table; exits with 1
+    /// if any file fails
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["input", "report_bundle"])]
+    batch: Option<PathBuf>,
+
+    /// Extensions of the files debugged with --batch
+    #[arg(
+        long,
+        value_name = "EXT",
+        value_delimiter = ',',
+        default_value = "cif,dic,mmcif,nef,star,str"
+    )]
+    ext: Vec<String>,
+
+    /// Write the report of each file that fails with --batch to DIR
+    #[arg(long, value_name = "DIR", requires = "batch")]
+    report_dir: Option<PathBuf>,
 
     /// Show full parse tree for the successful portion
     #[arg(short, long)]
@@ -35,235 +67,790 @@ struct Args {
     /// Show visible whitespace characters (spaces, tabs, CR, LF)
     #[arg(short, long)]
     whitespace: bool,
+
+    /// When to color the whitespace characters shown with --whitespace
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Write a zip bundle for bug reports (minimized snippet, error, versions and config)
+    #[arg(long, value_name = "ZIP")]
+    report_bundle: Option<PathBuf>,
+
+    /// Include the gzipped original input in the report bundle
+    #[arg(long, requires = "report_bundle")]
+    include_input: bool,
+
+    /// Read parser options from a TOML or JSON (.json) file instead of the defaults
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Character set of the input, overriding the config, ASCII by default
+    #[arg(long, value_enum)]
+    encoding: Option<Encoding>,
+
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+}
+
+/// Character sets for --encoding
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Encoding {
+    /// 7-bit ASCII
+    Ascii,
+    /// Latin-1 and Windows-1252
+    Extended,
+    /// UTF-8
+    Unicode,
+}
+
+impl Encoding {
+    fn mode(self) -> EncodingMode {
+        match self {
+            Encoding::Ascii => EncodingMode::Ascii,
+            Encoding::Extended => EncodingMode::ExtendedAscii,
+            Encoding::Unicode => EncodingMode::Unicode,
+        }
+    }
+}
+
+/// Formats for --format
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// Prose with the parse tree of the part that parses
+    Text,
+    /// A JSON object with the last good position, counts, unparsed excerpt and error
+    Json,
+}
+
+/// The name of an encoding in the JSON report, as given to --encoding
+fn encoding_name(encoding: EncodingMode) -> &'static str {
+    match encoding {
+        EncodingMode::Ascii => "ascii",
+        EncodingMode::ExtendedAscii => "extended",
+        EncodingMode::Unicode => "unicode",
+    }
+}
+
+/// A pest parser's parse function, such as `AsciiParser::parse`
+type ParseFn<'a, R> = fn(R, &'a str) -> Result<Pairs<'a, R>, pest::error::Error<R>>;
+
+/// The pest parser and entry rule `parse` chose for the input, used to probe it
+#[derive(Clone, Copy)]
+struct Grammar<'a, R: RuleType> {
+    encoding: EncodingMode,
+    parse: ParseFn<'a, R>,
+    rule: R,
+}
+
+impl<'a, R: RuleType> Grammar<'a, R> {
+    fn parse(&self, input: &'a str) -> Result<Pairs<'a, R>, pest::error::Error<R>> {
+        (self.parse)(self.rule, input)
+    }
+}
+
+/// Whether a file parses, for the batch summary
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Parses,
+    Fails,
+    /// The file couldn't be read as text in the encoding
+    Unreadable,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Parses => "ok",
+            Outcome::Fails => "failed",
+            Outcome::Unreadable => "unreadable",
+        }
+    }
+}
+
+/// What debugging a file found, a row of the batch summary
+struct Summary {
+    size: usize,
+    outcome: Outcome,
+    failure_line: Option<usize>,
+    last_good_line: Option<usize>,
 }
 
 fn main() {
     let args = Args::parse();
 
+    let mut config = match &args.config {
+        Some(path) => match ParserConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error in config file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let mut config = default_config();
+            // Configure for fancy error display with 10 lines of context
+            config.insert(
+                ConfigKey::ErrorFormat,
+                ConfigValue::ErrorFormat(ErrorFormatMode::Fancy),
+            );
+            config.insert(ConfigKey::ContextLines, ConfigValue::Usize(10));
+            config
+        }
+    };
+    if let Some(encoding) = args.encoding {
+        config.insert(ConfigKey::Encoding, ConfigValue::Encoding(encoding.mode()));
+    }
+
+    if let Some(dir) = &args.batch {
+        if !run_batch(dir, &args, &config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let path = args
+        .input
+        .as_ref()
+        .expect("clap requires FILE without --batch");
+
     // Read the input file
-    let content = match fs::read_to_string(&args.input) {
+    let content = match read_star_file(path, &config) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("Error reading file {:?}: {}", args.input, e);
+            eprintln!("{}", e.format_error(ErrorFormatMode::Basic, 0));
             std::process::exit(1);
         }
     };
 
-    println!("uSTAR Parse Debugger\n");
-
-    let mut config = default_config();
-    // Configure for fancy error display with 10 lines of context
-    config.insert(
-        ConfigKey::ErrorFormat,
-        ConfigValue::ErrorFormat(ErrorFormatMode::Fancy),
+    let summary = debug_content(
+        path,
+        &content,
+        &config,
+        &args,
+        args.color.enabled(),
+        &mut io::stdout().lock(),
     );
-    config.insert(ConfigKey::ContextLines, ConfigValue::Usize(10));
+    match summary {
+        Ok(summary) if summary.outcome == Outcome::Parses => std::process::exit(0),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error writing report: {}", e);
+            std::process::exit(1);
+        }
+    }
 
-    let stored_result = match parse(&content, &config) {
-        Ok(_) => {
-            println!("✓ File parses successfully!");
-            std::process::exit(0);
+    if let Some(bundle_path) = &args.report_bundle {
+        match report_bundle::create(bundle_path, path, &content, &config, args.include_input) {
+            Ok(entries) => {
+                let note = format!(
+                    "Report bundle written to {} ({})",
+                    bundle_path.display(),
+                    entries.join(", ")
+                );
+                // keep stdout a single JSON document
+                match args.format {
+                    ReportFormat::Text => println!("\n{}", note),
+                    ReportFormat::Json => eprintln!("{}", note),
+                }
+            }
+            Err(e) => {
+                eprintln!("Error writing report bundle {:?}: {}", bundle_path, e);
+                std::process::exit(1);
+            }
         }
-        Err(e) => {
-            let result = {
-                // Now use direct pest parsing for detailed debugging
-                match AsciiParser::parse(Rule::star_file, &content) {
-                    Ok(_) => {
-                        println!(
-                            "Unexpected: direct pest parser succeeded where new system failed"
-                        );
-                        std::process::exit(0);
-                    }
-                    Err(pest_error) => {
-                        // Extract error position for debugging analysis
-                        let error_pos = match pest_error.location {
-                            pest::error::InputLocation::Pos(pos) => pos,
-                            pest::error::InputLocation::Span((start, _)) => start,
-                        };
-
-                        // Extract line and column
-                        let (error_line, _error_col) = match pest_error.line_col {
-                            pest::error::LineColLocation::Pos((line, col)) => (line, col),
-                            pest::error::LineColLocation::Span((line, col), _) => (line, col),
-                        };
-
-                        println!("\n=== Attempting to find last parseable position ===\n");
-
-                        // Now try to find the last good parse position by trimming back token by token
-                        let parse_result = find_last_good_parse(&content, error_pos);
-
-                        if let Some(result) = parse_result {
-                            display_parse_debug_info(
-                                &content,
-                                error_line,
-                                &result,
-                                args.full_tree,
-                                !args.whitespace,
-                            );
-                            Some(result)
-                        } else {
-                            println!("     Could not find a successful parse point.");
-                            println!("     The file may have fundamental syntax errors near the beginning.");
-                            None
-                        }
+    }
+}
+
+/// A row of the batch summary table
+#[derive(Tabled)]
+struct SummaryRow {
+    file: String,
+    size: usize,
+    outcome: &'static str,
+    #[tabled(rename = "failure line")]
+    failure_line: String,
+    #[tabled(rename = "last good line")]
+    last_good_line: String,
+}
+
+/// Debug the files in `dir` in parallel, print the summary and write the reports of the files
+/// that fail, returning whether every file parses
+fn run_batch(dir: &Path, args: &Args, config: &ParserConfig) -> bool {
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(dir, &args.ext, &mut files) {
+        eprintln!("Error reading directory {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+
+    // the files are independent, so each thread debugs its own into a report in memory
+    let color = args.color == ColorChoice::Always;
+    let results: Vec<(PathBuf, Summary, Vec<u8>)> = files
+        .into_par_iter()
+        .map(|path| {
+            let mut report = Vec::new();
+            let summary = match read_star_file(&path, config) {
+                Ok(content) => debug_content(&path, &content, config, args, color, &mut report)
+                    .expect("writing to memory succeeds"),
+                Err(e) => {
+                    report = format!("{}\n", e.format_error(ErrorFormatMode::Basic, 0)).into();
+                    Summary {
+                        size: fs::metadata(&path).map_or(0, |metadata| metadata.len() as usize),
+                        outcome: Outcome::Unreadable,
+                        failure_line: Some(e.core().line).filter(|&line| line > 0),
+                        last_good_line: None,
                     }
                 }
             };
+            (path, summary, report)
+        })
+        .collect();
 
-            println!("\n=== Error Message ===\n");
+    if let Some(report_dir) = &args.report_dir {
+        if let Err(e) = write_reports(dir, report_dir, args.format, &results) {
+            eprintln!("Error writing reports to {}: {}", report_dir.display(), e);
+            std::process::exit(1);
+        }
+    }
 
-            println!("{}\n", e.format_error(ErrorFormatMode::Fancy, 10));
+    let failed = results
+        .iter()
+        .filter(|(_, summary, _)| summary.outcome != Outcome::Parses)
+        .count();
+    match args.format {
+        ReportFormat::Text => {
+            let rows: Vec<_> = results
+                .iter()
+                .map(|(path, summary, _)| SummaryRow {
+                    file: relative_name(dir, path),
+                    size: summary.size,
+                    outcome: summary.outcome.name(),
+                    failure_line: summary.failure_line.map_or("-".into(), |l| l.to_string()),
+                    last_good_line: summary.last_good_line.map_or("-".into(), |l| l.to_string()),
+                })
+                .collect();
+            let mut table = Table::new(&rows);
+            table.with(Style::empty());
+            println!("{}", table);
+            println!();
+            println!(
+                "files: {} passed: {} failed: {}",
+                results.len(),
+                results.len() - failed,
+                failed
+            );
+        }
+        ReportFormat::Json => {
+            let files: Vec<_> = results
+                .iter()
+                .map(|(path, summary, _)| {
+                    json!({
+                        "file": relative_name(dir, path),
+                        "size": summary.size,
+                        "outcome": summary.outcome.name(),
+                        "failure_line": summary.failure_line,
+                        "last_good_line": summary.last_good_line,
+                    })
+                })
+                .collect();
+            let summary = json!({
+                "files": files,
+                "passed": results.len() - failed,
+                "failed": failed,
+            });
+            write_json(&mut io::stdout().lock(), &summary).expect("writing to stdout succeeds");
+        }
+    }
+    failed == 0
+}
 
-            result
+/// Add the files of `dir` and its subdirectories with one of `extensions` to `files`, sorted
+/// by path
+fn collect_files(dir: &Path, extensions: &[String], files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, extensions, files)?;
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extensions
+                    .iter()
+                    .any(|wanted| wanted.eq_ignore_ascii_case(extension))
+            })
+        {
+            files.push(path);
         }
+    }
+    Ok(())
+}
+
+/// The path of a batch file relative to the batch directory
+fn relative_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+/// Write the report of each file that didn't parse to `report_dir`, at its path relative to the
+/// batch directory with .txt or .json added
+fn write_reports(
+    dir: &Path,
+    report_dir: &Path,
+    format: ReportFormat,
+    results: &[(PathBuf, Summary, Vec<u8>)],
+) -> io::Result<()> {
+    let extension = match format {
+        ReportFormat::Text => "txt",
+        ReportFormat::Json => "json",
     };
-    println!("✗ Parse failed\n");
-    if let Some(result) = stored_result {
-        println!("Last parsed position: {} bytes", result.last_position);
-        println!("Bytes parsed: {} bytes", result.parsed_bytes);
-        println!("Bytes remaining: {} bytes", result.remaining_bytes);
-        println!("Lines parsed: {} lines", result.parsed_lines);
-        println!("Lines remaining: {} lines\n", result.remaining_lines);
-    } else {
-        println!("No successful parse position could be determined.\n");
+    for (path, summary, report) in results {
+        if summary.outcome == Outcome::Parses {
+            continue;
+        }
+        let mut target = report_dir.join(path.strip_prefix(dir).unwrap_or(path));
+        target.as_mut_os_string().push(format!(".{}", extension));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, report)?;
     }
-    println!("File: {}", args.input.display());
-    println!("Size: {} bytes", content.len());
+    Ok(())
 }
 
-fn find_last_good_parse(content: &str, error_pos: usize) -> Option<ParseResult> {
-    // Start from the error position and work backwards
-    let mut current_pos = error_pos.min(content.len());
+fn write_json(out: &mut dyn Write, report: &serde_json::Value) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(report).expect("a JSON value serializes")
+    )
+}
 
-    // Try to find whitespace/newline boundaries working backwards
-    let mut attempts = 0;
-    let max_attempts = 10000; // Limit attempts to avoid infinite loops
+/// Parse the content of the file at `path` and, when it fails, search for the last good parse,
+/// writing the report in the format of `args` to `out`
+fn debug_content(
+    path: &Path,
+    content: &str,
+    config: &ParserConfig,
+    args: &Args,
+    color: bool,
+    out: &mut dyn Write,
+) -> io::Result<Summary> {
+    // Probe with the parser and entry rule parse chooses, so the positions match its error
+    let (encoding, input) = if get_auto_detect_bom(config) && content.starts_with('\u{FEFF}') {
+        (EncodingMode::Unicode, &content[3..])
+    } else {
+        (get_encoding(config), content)
+    };
+    let cif2 = get_cif_version(config) == CifVersion::Cif2;
 
-    while current_pos > 0 && attempts < max_attempts {
-        attempts += 1;
+    if args.format == ReportFormat::Text {
+        writeln!(out, "uSTAR Parse Debugger\n")?;
+    }
 
-        // Find the previous whitespace boundary (space, tab, or newline)
-        let mut found_boundary = false;
-        while current_pos > 0 {
-            current_pos -= 1;
-            let ch = content.as_bytes()[current_pos] as char;
-            if ch == ' ' || ch == '\t' || ch == '\n' || ch == '\r' {
-                found_boundary = true;
-                break;
+    let error = match parse(content, config) {
+        Ok(_) => {
+            match args.format {
+                ReportFormat::Text => writeln!(out, "✓ File parses successfully!")?,
+                ReportFormat::Json => write_json(
+                    out,
+                    &json!({
+                        "file": path.display().to_string(),
+                        "size": content.len(),
+                        "encoding": encoding_name(encoding),
+                        "parses": true,
+                    }),
+                )?,
             }
+            return Ok(Summary {
+                size: content.len(),
+                outcome: Outcome::Parses,
+                failure_line: None,
+                last_good_line: None,
+            });
         }
+        Err(e) => e.with_source_name(path.display().to_string()),
+    };
+
+    let report = Report {
+        args,
+        config,
+        path,
+        color,
+        error: &error,
+    };
+    macro_rules! debug_with {
+        ($module:ident, $parser:ident) => {{
+            use ustar_parser::parsers::$module::Rule;
+            let grammar = Grammar {
+                encoding,
+                parse: $parser::parse,
+                rule: if cif2 {
+                    Rule::cif2_star_file
+                } else {
+                    Rule::star_file
+                },
+            };
+            debug_failure(&report, input, grammar, out)?
+        }};
+    }
+
+    let last_good_line = match encoding {
+        EncodingMode::Ascii => debug_with!(ascii, AsciiParser),
+        EncodingMode::ExtendedAscii => debug_with!(extended, ExtendedParser),
+        EncodingMode::Unicode => debug_with!(unicode, UnicodeParser),
+    };
+    Ok(Summary {
+        size: content.len(),
+        outcome: Outcome::Fails,
+        failure_line: Some(error.core().line),
+        last_good_line,
+    })
+}
+
+/// What a report of a failed parse is about and how to write it
+struct Report<'r> {
+    args: &'r Args,
+    config: &'r ParserConfig,
+    path: &'r Path,
+    color: bool,
+    error: &'r UstarError,
+}
 
-        if !found_boundary {
-            break;
+/// Search back from the error in `input` for the last good parse and report it with the error,
+/// returning the line the good parse ends on
+fn debug_failure<'a, R: RuleType>(
+    report: &Report,
+    input: &'a str,
+    grammar: Grammar<'a, R>,
+    out: &mut dyn Write,
+) -> io::Result<Option<usize>> {
+    // Locate the error with the probe parser itself
+    let search = match grammar.parse(input) {
+        Ok(_) => None,
+        Err(pest_error) => {
+            let error_pos = match pest_error.location {
+                pest::error::InputLocation::Pos(pos) => pos,
+                pest::error::InputLocation::Span((start, _)) => start,
+            };
+            let error_line = match pest_error.line_col {
+                pest::error::LineColLocation::Pos((line, _)) => line,
+                pest::error::LineColLocation::Span((line, _), _) => line,
+            };
+            Some((error_line, find_last_good_parse(input, error_pos, grammar)))
         }
+    };
+    let last_good_line = search
+        .as_ref()
+        .and_then(|(_, result)| result.as_ref())
+        .map(|result| result.parsed_lines);
+
+    match report.args.format {
+        ReportFormat::Text => write_text_report(report, input, search, out)?,
+        ReportFormat::Json => write_json(out, &json_report(report, input, grammar, search))?,
+    }
+    Ok(last_good_line)
+}
+
+fn write_text_report<R: RuleType>(
+    report: &Report,
+    input: &str,
+    search: Option<(usize, Option<ParseResult<R>>)>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let Some((error_line, result)) = search else {
+        return writeln!(
+            out,
+            "Unexpected: direct pest parser succeeded where new system failed"
+        );
+    };
+
+    writeln!(
+        out,
+        "\n=== Attempting to find last parseable position ===\n"
+    )?;
+    if let Some(result) = &result {
+        display_parse_debug_info(report, input, error_line, result, out)?;
+    } else {
+        writeln!(out, "     Could not find a successful parse point.")?;
+        writeln!(
+            out,
+            "     The file may have fundamental syntax errors near the beginning."
+        )?;
+    }
+
+    writeln!(out, "\n=== Error Message ===\n")?;
+    writeln!(
+        out,
+        "{}\n",
+        report.error.format_error(
+            get_error_format(report.config),
+            get_context_lines(report.config)
+        )
+    )?;
+
+    writeln!(out, "✗ Parse failed\n")?;
+    if let Some(result) = result {
+        writeln!(out, "Last parsed position: {} bytes", result.last_position)?;
+        writeln!(out, "Bytes parsed: {} bytes", result.parsed_bytes)?;
+        writeln!(out, "Bytes remaining: {} bytes", result.remaining_bytes)?;
+        writeln!(out, "Lines parsed: {} lines", result.parsed_lines)?;
+        writeln!(out, "Lines remaining: {} lines\n", result.remaining_lines)?;
+    } else {
+        writeln!(out, "No successful parse position could be determined.\n")?;
+    }
+    writeln!(out, "File: {}", report.path.display())?;
+    writeln!(out, "Size: {} bytes", input.len())
+}
 
-        // Skip any additional whitespace
-        while current_pos > 0 {
-            let ch = content.as_bytes()[current_pos.saturating_sub(1)] as char;
-            if ch != ' ' && ch != '\t' && ch != '\n' && ch != '\r' {
-                break;
+/// The JSON report of a failed parse: where the last good parse ends, how much of the input it
+/// covers, the unparsed lines up to the error and the error itself
+fn json_report<R: RuleType>(
+    report: &Report,
+    input: &str,
+    grammar: Grammar<'_, R>,
+    search: Option<(usize, Option<ParseResult<R>>)>,
+) -> serde_json::Value {
+    let core = report.error.core();
+    let mut value = json!({
+        "file": report.path.display().to_string(),
+        "size": input.len(),
+        "encoding": encoding_name(grammar.encoding),
+        "parses": false,
+        "last_good": null,
+        "parsed": null,
+        "remaining": null,
+        "unparsed_excerpt": [],
+        "error": {
+            "code": core.code.as_str(),
+            "line": core.line,
+            "column": core.col,
+            "message": core.message,
+            "formatted": report
+                .error
+                .format_error(ErrorFormatMode::Basic, get_context_lines(report.config)),
+        },
+    });
+
+    if let Some((error_line, Some(result))) = search {
+        let excerpt: Vec<_> = split_lines_with_endings(result.unparsed_content)
+            .into_iter()
+            .take(error_line.saturating_sub(result.parsed_lines))
+            .enumerate()
+            .map(|(i, line)| {
+                json!({
+                    "line": result.parsed_lines + i + 1,
+                    "text": line.trim_end_matches(['\r', '\n']),
+                })
+            })
+            .collect();
+        value["last_good"] = json!({
+            "byte": result.last_position,
+            "line": result.parsed_lines,
+        });
+        value["parsed"] = json!({
+            "bytes": result.parsed_bytes,
+            "lines": result.parsed_lines,
+        });
+        value["remaining"] = json!({
+            "bytes": result.remaining_bytes,
+            "lines": result.remaining_lines,
+        });
+        value["unparsed_excerpt"] = json!(excerpt);
+    }
+    value
+}
+
+/// Bytes of input the search for the last good parse may reparse in all, a hundred parses of
+/// a 10MB file
+const REPARSE_BUDGET: usize = 1 << 30;
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Whether a prefix ending with `token` might parse; one ending with a data name or a keyword
+/// opening a data block, save frame or loop can't
+fn may_end_parse(token: &[u8]) -> bool {
+    let starts_with = |keyword: &[u8]| {
+        token
+            .get(..keyword.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(keyword))
+    };
+    !(token.starts_with(b"_")
+        || starts_with(b"data_")
+        || starts_with(b"global_")
+        || starts_with(b"loop_")
+        || (starts_with(b"save_") && token.len() > 5))
+}
+
+/// The offsets before `error_pos` where a token a parse may end with ends, in order
+fn token_ends(content: &str, error_pos: usize) -> Vec<usize> {
+    let bytes = &content.as_bytes()[..error_pos.min(content.len())];
+    let mut ends = Vec::new();
+    let mut start = 0;
+    for (offset, &byte) in bytes.iter().enumerate() {
+        if is_whitespace(byte) {
+            if offset > start && may_end_parse(&bytes[start..offset]) {
+                ends.push(offset);
             }
-            current_pos -= 1;
+            start = offset + 1;
         }
+    }
+    ends
+}
 
-        if current_pos == 0 {
-            break;
+/// Parses the prefixes of the content that end at token ends, within the reparse budget
+struct Prober<'a, R: RuleType> {
+    content: &'a str,
+    grammar: Grammar<'a, R>,
+    ends: Vec<usize>,
+    budget: usize,
+    spent: bool, // A probe was refused for lack of budget
+}
+
+impl<'a, R: RuleType> Prober<'a, R> {
+    /// The parse of the prefix ending at the token end `back` places before the error,
+    /// counting from 0
+    fn probe(&mut self, back: usize) -> Option<(usize, Pairs<'a, R>)> {
+        let end = self.ends[self.ends.len() - 1 - back];
+        if end > self.budget {
+            self.spent = true;
+            return None;
         }
+        self.budget -= end;
+        self.grammar
+            .parse(&self.content[..end])
+            .ok()
+            .map(|pairs| (end, pairs))
+    }
+}
+
+/// Find the longest prefix ending at a token end before `error_pos` that parses
+///
+/// Each probe reparses a prefix, so rather than trying every token end back from the error the
+/// search doubles its distance from the error until a prefix parses, then bisects between that
+/// and the nearest failure. The result is the last good parse as long as the prefixes between
+/// the two go from parsing to failing just once, which leaving out the ends of tags and
+/// keywords makes true outside quoted values.
+fn find_last_good_parse<'a, R: RuleType>(
+    content: &'a str,
+    error_pos: usize,
+    grammar: Grammar<'a, R>,
+) -> Option<ParseResult<'a, R>> {
+    let mut prober = Prober {
+        content,
+        grammar,
+        ends: token_ends(content, error_pos),
+        budget: REPARSE_BUDGET,
+        spent: false,
+    };
+    let count = prober.ends.len();
+    if count == 0 {
+        return None;
+    }
 
-        // Try to parse up to this position
-        let truncated = &content[..current_pos];
-
-        match AsciiParser::parse(Rule::star_file, truncated) {
-            Ok(_) => {
-                // Found a successful parse! Calculate and return parse statistics
-                let lines_before: Vec<&str> = truncated.lines().collect();
-                let parse_line = lines_before.len();
-                let total_bytes = content.len();
-                let parsed_bytes = current_pos;
-                let remaining_bytes = total_bytes - parsed_bytes;
-                let total_lines = content.lines().count();
-                let parsed_lines = parse_line;
-                let remaining_lines = total_lines - parsed_lines;
-                let unparsed_content = content[current_pos..].to_string();
-
-                return Some(ParseResult {
-                    last_position: current_pos,
-                    total_bytes,
-                    parsed_bytes,
-                    remaining_bytes,
-                    total_lines,
-                    parsed_lines,
-                    remaining_lines,
-                    truncated_content: truncated.to_string(),
-                    unparsed_content,
-                });
+    let mut back = 0;
+    let mut step = 1;
+    let mut failed = None;
+    let mut found = loop {
+        match prober.probe(back) {
+            Some(parse) => break (back, parse),
+            None if prober.spent || back + 1 >= count => return None,
+            None => {
+                failed = Some(back);
+                back = (back + step).min(count - 1);
+                step *= 2;
             }
-            Err(_) => {
-                // Still failing, continue trimming
-                continue;
+        }
+    };
+    if let Some(mut failed) = failed {
+        while found.0 - failed > 1 && !prober.spent {
+            let middle = failed + (found.0 - failed) / 2;
+            match prober.probe(middle) {
+                Some(parse) => found = (middle, parse),
+                None => failed = middle,
             }
         }
     }
 
-    None
+    // Found a successful parse! Calculate and return parse statistics
+    let (current_pos, pairs) = found.1;
+    let truncated = &content[..current_pos];
+    let total_bytes = content.len();
+    let parsed_bytes = current_pos;
+    let remaining_bytes = total_bytes - parsed_bytes;
+    let total_lines = content.lines().count();
+    let parsed_lines = truncated.lines().count();
+    let remaining_lines = total_lines - parsed_lines;
+
+    Some(ParseResult {
+        last_position: current_pos,
+        total_bytes,
+        parsed_bytes,
+        remaining_bytes,
+        total_lines,
+        parsed_lines,
+        remaining_lines,
+        unparsed_content: &content[current_pos..],
+        pairs,
+    })
 }
 
-fn display_parse_debug_info(
+fn display_parse_debug_info<R: RuleType>(
+    report: &Report,
     content: &str,
     error_line: usize,
-    result: &ParseResult,
-    show_full_tree: bool,
-    no_visible_whitespace: bool,
-) {
-    println!(
+    result: &ParseResult<R>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    writeln!(
+        out,
         "   ✓ Found successful parse at byte position: {}\n",
         result.last_position
-    );
+    )?;
 
-    // Show the parse tree by re-parsing the truncated content
-    match AsciiParser::parse(Rule::star_file, &result.truncated_content) {
-        Ok(pairs) => {
-            if show_full_tree {
-                println!("=== Full Parse Tree ===\n");
-                for pair in pairs {
-                    println!("{:#?}", pair);
-                }
-            } else {
-                println!("=== Successful Parse Tree ===\n");
-                for pair in pairs {
-                    print_tree_summary(&pair, 0, content);
-                }
-            }
+    // Show the parse tree of the truncated content kept from the search
+    if report.args.full_tree {
+        writeln!(out, "=== Full Parse Tree ===\n")?;
+        for pair in result.pairs.clone() {
+            writeln!(out, "{:#?}", pair)?;
         }
-        Err(_) => {
-            println!("Error: Could not re-parse truncated content for tree display");
+    } else {
+        writeln!(out, "=== Successful Parse Tree ===\n")?;
+        let line_ends = line_feed_offsets(content);
+        for pair in result.pairs.clone() {
+            print_tree_summary(&pair, 0, &line_ends, out)?;
         }
     }
 
-    println!();
-    println!("=== Unparsed Content ===\n");
+    writeln!(out)?;
+    writeln!(out, "=== Unparsed Content ===\n")?;
 
-    let remaining_lines: Vec<&str> = split_lines_with_endings(&result.unparsed_content);
+    let remaining_lines: Vec<&str> = split_lines_with_endings(result.unparsed_content);
 
     if remaining_lines.is_empty() {
-        println!("    (No remaining content - file ends cleanly)");
+        writeln!(out, "    (No remaining content - file ends cleanly)")?;
     } else {
         // Calculate how many lines from unparsed start to error
         let lines_to_error = error_line - result.parsed_lines;
         let total_lines_to_show = lines_to_error;
 
-        println!(
+        writeln!(
+            out,
             "    Showing unparsed content ({} lines):\n",
             total_lines_to_show
-        );
+        )?;
 
         for (i, line) in remaining_lines.iter().take(total_lines_to_show).enumerate() {
             let line_num = result.parsed_lines + i + 1;
-            let visible_line = make_whitespace_visible(line, no_visible_whitespace);
-            println!("  {:4}: {}", line_num, visible_line);
+            let visible_line = make_whitespace_visible(line, !report.args.whitespace, report.color);
+            writeln!(out, "  {:4}: {}", line_num, visible_line)?;
         }
-        println!();
+        writeln!(out)?;
     }
+    Ok(())
 }
 
 fn split_lines_with_endings(content: &str) -> Vec<&str> {
@@ -300,7 +887,7 @@ fn split_lines_with_endings(content: &str) -> Vec<&str> {
     lines
 }
 
-fn make_whitespace_visible(line: &str, no_visible_whitespace: bool) -> String {
+fn make_whitespace_visible(line: &str, no_visible_whitespace: bool, color: bool) -> String {
     if no_visible_whitespace {
         // Strip line endings and return the line
         return line.trim_end_matches(&['\r', '\n'][..]).to_string();
@@ -309,73 +896,93 @@ fn make_whitespace_visible(line: &str, no_visible_whitespace: bool) -> String {
     let mut result = String::new();
     for ch in line.chars() {
         match ch {
-            ' ' => result.push_str("\x1b[90m·\x1b[0m"), // Grey middle dot for space
-            '\t' => result.push_str("\x1b[90m→\x1b[0m"), // Grey arrow for tab
-            '\r' => result.push_str("\x1b[90m␍\x1b[0m"), // Grey CR symbol
-            '\n' => result.push_str("\x1b[90m␊\x1b[0m"), // Grey LF symbol
+            ' ' => result.push_str(&paint("·", "90", color)), // Grey middle dot for space
+            '\t' => result.push_str(&paint("→", "90", color)), // Grey arrow for tab
+            '\r' => result.push_str(&paint("␍", "90", color)), // Grey CR symbol
+            '\n' => result.push_str(&paint("␊", "90", color)), // Grey LF symbol
             _ => result.push(ch),
         }
     }
     result
 }
 
-fn calculate_max_depth(pair: &pest::iterators::Pair<Rule>, current_depth: usize) -> usize {
+fn calculate_max_depth<E: DumpExtractor>(
+    extractor: &E,
+    pair: &E::Node<'_>,
+    current_depth: usize,
+) -> usize {
     let mut max = current_depth;
-    for inner in pair.clone().into_inner() {
-        let child_max = calculate_max_depth(&inner, current_depth + 1);
-        max = max.max(child_max);
-    }
+    visit_nodes(extractor, pair, current_depth, &mut |_, depth| {
+        max = max.max(depth)
+    });
     max
 }
 
-fn byte_to_line(content: &str, byte_pos: usize) -> usize {
-    content[..byte_pos.min(content.len())]
-        .chars()
-        .filter(|&c| c == '\n')
-        .count()
-        + 1
+/// The offsets of the line feeds in `content`, for finding the lines of offsets
+fn line_feed_offsets(content: &str) -> Vec<usize> {
+    content
+        .bytes()
+        .enumerate()
+        .filter(|&(_, byte)| byte == b'\n')
+        .map(|(offset, _)| offset)
+        .collect()
 }
 
-fn find_max_line(pair: &pest::iterators::Pair<Rule>, content: &str) -> usize {
-    let span = pair.as_span();
-    let mut max_line = byte_to_line(content, span.end());
-
-    for inner in pair.clone().into_inner() {
-        let child_max = find_max_line(&inner, content);
-        max_line = max_line.max(child_max);
-    }
+fn byte_to_line(line_ends: &[usize], byte_pos: usize) -> usize {
+    line_ends.partition_point(|&end| end < byte_pos) + 1
+}
 
+fn find_max_line<E: DumpExtractor>(
+    extractor: &E,
+    pair: &E::Node<'_>,
+    line_ends: &[usize],
+) -> usize {
+    let mut max_line = 0;
+    visit_nodes(extractor, pair, 0, &mut |node, _| {
+        max_line = max_line.max(byte_to_line(line_ends, extractor.end(node)))
+    });
     max_line
 }
 
-fn print_tree_summary(pair: &pest::iterators::Pair<Rule>, depth: usize, content: &str) {
-    print_tree_summary_with_indent(pair, depth, content, 4);
+fn print_tree_summary<R: RuleType>(
+    pair: &Pair<R>,
+    depth: usize,
+    line_ends: &[usize],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    print_tree_summary_with_indent(
+        &PestPairExtractor::<R>::new(),
+        pair,
+        depth,
+        line_ends,
+        4,
+        out,
+    )
 }
 
-fn print_tree_summary_with_indent(
-    pair: &pest::iterators::Pair<Rule>,
+fn print_tree_summary_with_indent<E: DumpExtractor>(
+    extractor: &E,
+    pair: &E::Node<'_>,
     depth: usize,
-    content: &str,
+    line_ends: &[usize],
     indent_spaces: usize,
-) {
+    out: &mut dyn Write,
+) -> io::Result<()> {
     // First pass: calculate max depth
-    let max_depth = calculate_max_depth(pair, depth);
+    let max_depth = calculate_max_depth(extractor, pair, depth);
 
     // Second pass: calculate max line number
-    let max_line = find_max_line(pair, content);
+    let max_line = find_max_line(extractor, pair, line_ends);
 
     // Third pass: build tree structure strings and calculate max width
     let mut tree_lines = Vec::new();
     let mut text_lines = Vec::new();
-    build_tree_lines(
-        pair,
-        depth,
-        max_depth,
-        content,
-        max_line,
-        &mut tree_lines,
-        &mut text_lines,
-    );
+    visit_nodes(extractor, pair, depth, &mut |node, depth| {
+        let (tree_line, text_line) =
+            build_tree_line(extractor, node, depth, max_depth, line_ends, max_line);
+        tree_lines.push(tree_line);
+        text_lines.push(text_line);
+    });
 
     // Find the maximum width of tree structure
     let max_tree_width = tree_lines.iter().map(|s| s.len()).max().unwrap_or(0);
@@ -383,31 +990,32 @@ fn print_tree_summary_with_indent(
     // Fourth pass: print with aligned text and custom indentation
     let indent = " ".repeat(indent_spaces);
     for (tree_line, text_line) in tree_lines.iter().zip(text_lines.iter()) {
-        println!(
+        writeln!(
+            out,
             "{}{:<width$}  \"{}\"",
             indent,
             tree_line,
             text_line,
             width = max_tree_width
-        );
+        )?;
     }
+    Ok(())
 }
 
-fn build_tree_lines(
-    pair: &pest::iterators::Pair<Rule>,
+/// The tree structure and text of one node of the tree summary
+fn build_tree_line<E: DumpExtractor>(
+    extractor: &E,
+    pair: &E::Node<'_>,
     depth: usize,
     max_depth: usize,
-    content: &str,
+    line_ends: &[usize],
     max_line: usize,
-    tree_lines: &mut Vec<String>,
-    text_lines: &mut Vec<String>,
-) {
-    let rule = pair.as_rule();
-    let span = pair.as_span();
+) -> (String, String) {
+    let (start, end) = (extractor.start(pair), extractor.end(pair));
 
     // Calculate line numbers for start and end positions
-    let start_line = byte_to_line(content, span.start());
-    let end_line = byte_to_line(content, span.end());
+    let start_line = byte_to_line(line_ends, start);
+    let end_line = byte_to_line(line_ends, end);
 
     // Calculate the width needed for line numbers and depth
     let line_width = max_line.to_string().len();
@@ -416,13 +1024,13 @@ fn build_tree_lines(
     // Build the tree structure line
     let indent = "  ".repeat(depth);
     let tree_line = format!(
-        "{:>line_width$} | {:>depth_width$} | {}{:?} ({}-{}) lines {}-{}",
+        "{:>line_width$} | {:>depth_width$} | {}{} ({}-{}) lines {}-{}",
         start_line,
         depth,
         indent,
-        rule,
-        span.start(),
-        span.end(),
+        extractor.rule_name(pair),
+        start,
+        end,
         start_line,
         end_line,
         line_width = line_width,
@@ -430,7 +1038,7 @@ fn build_tree_lines(
     );
 
     // Get the token text and format it
-    let token_text = span.as_str();
+    let token_text = extractor.text(pair);
     let formatted_text = if token_text.len() > 60 {
         let first_30: String = token_text.chars().take(30).collect();
         let last_30: String = token_text
@@ -452,18 +1060,5 @@ fn build_tree_lines(
         .replace('\r', "\\r")
         .replace('\t', "\\t");
 
-    tree_lines.push(tree_line);
-    text_lines.push(display_text);
-
-    for inner in pair.clone().into_inner() {
-        build_tree_lines(
-            &inner,
-            depth + 1,
-            max_depth,
-            content,
-            max_line,
-            tree_lines,
-            text_lines,
-        );
-    }
+    (tree_line, display_text)
 }
@@ -0,0 +1,279 @@
+use clap::{Parser, ValueEnum};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ustar_parser::dictionary::{validate_against, Dictionary};
+use ustar_parser::validate::validate_tree;
+use ustar_parser::{
+    default_config, parse, read_star_file, ConfigFile, Dialect, DialectPreset, ErrorCode,
+    ParserConfig, UstarError,
+};
+
+/// Extensions of the files checked in directories with --recursive
+const STAR_EXTENSIONS: [&str; 6] = ["cif", "dic", "mmcif", "nef", "star", "str"];
+
+#[derive(Parser)]
+#[command(name = "ustar-validate")]
+#[command(about = "Check STAR files for syntax errors, dialect restrictions and STAR semantics")]
+#[command(version = "0.1.0")]
+struct Args {
+    /// Files to check, or directories with --recursive
+    #[arg(value_name = "FILE", required = true)]
+    inputs: Vec<PathBuf>,
+    /// Check the STAR files in directories and their subdirectories, by extension
+    #[arg(long, short, action = clap::ArgAction::SetTrue)]
+    recursive: bool,
+    /// Apply the restrictions of a dialect, such as no empty loops
+    #[arg(long, value_enum, conflicts_with = "config")]
+    dialect: Option<DialectName>,
+    /// Read parser options from a TOML or JSON (.json) file instead of the defaults
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Also check data names, types and mandatory items against a DDL2 dictionary
+    #[arg(long, value_name = "FILE")]
+    dictionary: Option<PathBuf>,
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+}
+
+/// Dialects for --dialect
+#[derive(Clone, Copy, ValueEnum)]
+enum DialectName {
+    /// STAR 2012 without further restrictions
+    Star2012,
+    /// CIF 1.1
+    Cif1,
+    /// CIF 2.0
+    Cif2,
+    /// NMR-STAR
+    NmrStar,
+    /// NMR Exchange Format
+    Nef,
+}
+
+impl DialectName {
+    fn dialect(self) -> Dialect {
+        match self {
+            DialectName::Star2012 => Dialect::Star2012,
+            DialectName::Cif1 => Dialect::Cif1,
+            DialectName::Cif2 => Dialect::Cif2,
+            DialectName::NmrStar => Dialect::NmrStar,
+            DialectName::Nef => Dialect::Nef,
+        }
+    }
+}
+
+/// Formats for --format
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    /// A line per file followed by its issues
+    Text,
+    /// A JSON object with the issues of every file
+    Json,
+}
+
+/// A problem found in a file
+struct Issue {
+    code: ErrorCode,
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl Issue {
+    fn from_error(error: &UstarError) -> Issue {
+        let core = error.core();
+        Issue {
+            code: core.code,
+            line: core.line,
+            column: core.col,
+            message: core.message.clone(),
+        }
+    }
+}
+
+/// Read, parse and check one file, returning its issues in the order of their positions
+fn check_file(path: &Path, config: &ParserConfig, dictionary: Option<&Dictionary>) -> Vec<Issue> {
+    let input = match read_star_file(path, config) {
+        Ok(input) => input,
+        Err(e) => return vec![Issue::from_error(&e)],
+    };
+    // a syntax error or broken dialect restriction stops the parse, semantic issues don't
+    let tree = match parse(&input, config) {
+        Ok(tree) => tree,
+        Err(e) => return vec![Issue::from_error(&e)],
+    };
+
+    let mut found = validate_tree(&tree);
+    if let Some(dictionary) = dictionary {
+        found.extend(validate_against(dictionary, &tree));
+        found.sort_by_key(|issue| (issue.position.line, issue.position.column));
+    }
+    found
+        .into_iter()
+        .map(|issue| Issue {
+            code: issue.kind.code(),
+            line: issue.position.line,
+            column: issue.position.column,
+            message: issue.message(),
+        })
+        .collect()
+}
+
+/// The files to check for the command line inputs, directories are read with --recursive
+fn collect_files(inputs: &[PathBuf], recursive: bool) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+        } else if recursive {
+            collect_directory(input, &mut files)
+                .map_err(|e| format!("Error reading directory {}: {}", input.display(), e))?;
+        } else {
+            return Err(format!(
+                "{} is a directory, use --recursive to check the files in it",
+                input.display()
+            ));
+        }
+    }
+    Ok(files)
+}
+
+/// Add the STAR files of `dir` and its subdirectories to `files`, sorted by path
+fn collect_directory(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_directory(&path, files)?;
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| STAR_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn print_text(results: &[(PathBuf, Vec<Issue>)]) {
+    for (path, issues) in results {
+        if issues.is_empty() {
+            println!("{}: ok", path.display());
+            continue;
+        }
+        println!("{}: {} issue(s)", path.display(), issues.len());
+        for issue in issues {
+            println!(
+                "  l{}:c{} {} {}",
+                issue.line,
+                issue.column,
+                issue.code.as_str(),
+                issue.message
+            );
+        }
+    }
+    let failed = results
+        .iter()
+        .filter(|(_, issues)| !issues.is_empty())
+        .count();
+    println!();
+    println!(
+        "files: {} passed: {} failed: {}",
+        results.len(),
+        results.len() - failed,
+        failed
+    );
+}
+
+fn print_json(results: &[(PathBuf, Vec<Issue>)]) {
+    let files: Vec<_> = results
+        .iter()
+        .map(|(path, issues)| {
+            let issues: Vec<_> = issues
+                .iter()
+                .map(|issue| {
+                    json!({
+                        "code": issue.code.as_str(),
+                        "line": issue.line,
+                        "column": issue.column,
+                        "message": issue.message,
+                    })
+                })
+                .collect();
+            json!({
+                "path": path.display().to_string(),
+                "valid": issues.is_empty(),
+                "issues": issues,
+            })
+        })
+        .collect();
+    let failed = results
+        .iter()
+        .filter(|(_, issues)| !issues.is_empty())
+        .count();
+    let report = json!({
+        "files": files,
+        "passed": results.len() - failed,
+        "failed": failed,
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("a JSON value serializes")
+    );
+}
+
+fn main() {
+    let args = Args::parse();
+    let config = match (&args.config, args.dialect) {
+        (Some(path), _) => match ParserConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error in config file {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        (None, Some(dialect)) => ParserConfig::preset(dialect.dialect()),
+        (None, None) => default_config(),
+    };
+    let dictionary = args.dictionary.as_ref().map(|path| {
+        match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Dictionary::parse(&text).map_err(|e| e.to_string()))
+        {
+            Ok(dictionary) => dictionary,
+            Err(e) => {
+                eprintln!("Error in dictionary {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        }
+    });
+
+    let files = match collect_files(&args.inputs, args.recursive) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let results: Vec<(PathBuf, Vec<Issue>)> = files
+        .into_iter()
+        .map(|path| {
+            let issues = check_file(&path, &config, dictionary.as_ref());
+            (path, issues)
+        })
+        .collect();
+
+    match args.format {
+        ReportFormat::Text => print_text(&results),
+        ReportFormat::Json => print_json(&results),
+    }
+    if results.iter().any(|(_, issues)| !issues.is_empty()) {
+        std::process::exit(1);
+    }
+}
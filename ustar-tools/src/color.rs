@@ -0,0 +1,43 @@
+//! Choosing whether the output of the binaries is colored.
+//!
+//! Binaries take a `--color` option of `ColorChoice`. The default, `auto`, colors output
+//! written to a terminal unless the `NO_COLOR` environment variable is set to a non-empty value
+//! (see https://no-color.org), so output piped into files or other tools is plain text.
+
+use clap::ValueEnum;
+use std::io::IsTerminal;
+
+/// When to color output, for the `--color` option
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color output to a terminal unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// Always color output
+    Always,
+    /// Never color output
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output written to stdout should be colored
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+/// `text` wrapped in the ANSI escape `code`, or unchanged when `color` is false
+pub fn paint(text: &str, code: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,126 @@
+//! Manifest of downloaded files, kept as `manifest.json` in a downloader's output directory.
+//!
+//! Each entry records where a file came from, its size and SHA-1, and when it was downloaded,
+//! so a later run can skip entries it already has after checking they haven't changed, and an
+//! interrupted batch can be resumed.
+
+use crate::downloader_common::DownloadError;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the manifest in the output directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A downloaded file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: String,
+    /// Where the file was downloaded from, when the data source says
+    pub url: Option<String>,
+    /// Path of the file, relative to the output directory
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha1: String,
+    /// When the file was downloaded, in seconds since the Unix epoch
+    pub downloaded_at: u64,
+}
+
+/// How a file on disk compares with its manifest entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    Unchanged,
+    Missing,
+    /// The file's size or checksum differs from the manifest
+    Changed,
+}
+
+impl ManifestEntry {
+    /// Record the file downloaded for `id` at `path`, which is in or below `output_dir`
+    pub fn for_file(
+        id: &str,
+        url: Option<String>,
+        output_dir: &Path,
+        path: &Path,
+    ) -> std::io::Result<ManifestEntry> {
+        let downloaded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Ok(ManifestEntry {
+            id: id.to_string(),
+            url,
+            path: path.strip_prefix(output_dir).unwrap_or(path).to_path_buf(),
+            size: fs::metadata(path)?.len(),
+            sha1: file_sha1(path)?,
+            downloaded_at,
+        })
+    }
+
+    /// Check the file against its size and checksum
+    pub fn state(&self, output_dir: &Path) -> EntryState {
+        let path = output_dir.join(&self.path);
+        match fs::metadata(&path) {
+            Err(_) => EntryState::Missing,
+            Ok(metadata) if metadata.len() != self.size => EntryState::Changed,
+            Ok(_) => match file_sha1(&path) {
+                Ok(sha1) if sha1 == self.sha1 => EntryState::Unchanged,
+                Ok(_) => EntryState::Changed,
+                Err(_) => EntryState::Missing,
+            },
+        }
+    }
+}
+
+/// The files downloaded to an output directory, by entry id
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Read the manifest of `output_dir`, or an empty one if there isn't one yet
+    pub fn load(output_dir: &Path) -> Result<Manifest, DownloadError> {
+        match fs::read_to_string(output_dir.join(MANIFEST_FILE)) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the manifest to `output_dir`, replacing the old one only once it is complete
+    pub fn save(&self, output_dir: &Path) -> Result<(), DownloadError> {
+        fs::create_dir_all(output_dir)?;
+        let temporary = output_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temporary, output_dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, entry: ManifestEntry) {
+        self.entries.insert(entry.id.clone(), entry);
+    }
+}
+
+/// Entries of a manifest whose files are no longer as they were downloaded
+#[derive(Debug, Default)]
+pub struct ManifestCheck {
+    pub missing: Vec<ManifestEntry>,
+    pub mismatched: Vec<ManifestEntry>,
+}
+
+impl ManifestCheck {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// SHA-1 of a file's contents, as lowercase hex
+pub fn file_sha1(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -1,10 +1,16 @@
 // Common downloader traits and utilities for STAR/CIF file downloads
 
+use crate::download_manifest::{EntryState, Manifest, ManifestCheck, ManifestEntry};
 use rand::seq::SliceRandom;
-use rand::SeedableRng;
-use std::collections::HashSet;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ustar_parser::{default_config, parse, read_star_file, ConfigKey, ConfigValue, EncodingMode};
 
 /// HTTP client trait for dependency injection and testing
 pub trait HttpClient: Send + Sync {
@@ -20,6 +26,39 @@ pub enum DownloadError {
     NoEntriesFound,
     DownloadFailed(String),
     JsonError(serde_json::Error),
+    /// The server answered with an unsuccessful HTTP status
+    HttpStatus {
+        status: u16,
+        url: String,
+    },
+    /// The request failed without a response, such as a refused connection or a timeout
+    Connection {
+        message: String,
+        url: String,
+    },
+    /// The downloaded file doesn't parse as STAR, with a summary of the parse error
+    InvalidContent(String),
+}
+
+impl DownloadError {
+    /// Whether trying again might succeed: network failures, timeouts and server errors are
+    /// transient, while missing entries (404), bad requests and local IO errors are not
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::RequestError(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.is_request()
+                    || e.is_body()
+                    || e.status().is_some_and(|status| status.is_server_error())
+            }
+            DownloadError::HttpStatus { status, .. } => {
+                *status >= 500 || *status == 408 || *status == 429
+            }
+            DownloadError::Connection { .. } => true,
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for DownloadError {
@@ -30,6 +69,11 @@ impl std::fmt::Display for DownloadError {
             DownloadError::NoEntriesFound => write!(f, "No entries found"),
             DownloadError::DownloadFailed(msg) => write!(f, "Download failed: {}", msg),
             DownloadError::JsonError(e) => write!(f, "JSON error: {}", e),
+            DownloadError::HttpStatus { status, url } => write!(f, "HTTP {}: {}", status, url),
+            DownloadError::Connection { message, url } => {
+                write!(f, "Connection error: {}: {}", message, url)
+            }
+            DownloadError::InvalidContent(summary) => write!(f, "Invalid content: {}", summary),
         }
     }
 }
@@ -61,55 +105,87 @@ impl From<String> for DownloadError {
 }
 
 /// Production HTTP client using reqwest
-pub struct ReqwestClient;
+#[derive(Default)]
+pub struct ReqwestClient {
+    timeout: Option<Duration>,
+}
+
+impl ReqwestClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on a request that hasn't completed within `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send a GET request, failing on an unsuccessful status
+    async fn send(&self, url: &str) -> Result<reqwest::Response, DownloadError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let response = builder.build()?.get(url).send().await?;
+        #[cfg(feature = "trace")]
+        tracing::debug!(status = response.status().as_u16(), "response");
+        if !response.status().is_success() {
+            return Err(DownloadError::HttpStatus {
+                status: response.status().as_u16(),
+                url: url.to_string(),
+            });
+        }
+        Ok(response)
+    }
+}
+
+/// A failed mock request as the error the real client would have returned
+#[cfg(test)]
+fn mock_error(error: ustar_test_utils::MockHttpError) -> DownloadError {
+    match error.status {
+        Some(status) => DownloadError::HttpStatus {
+            status,
+            url: error.url,
+        },
+        None => DownloadError::Connection {
+            message: error.message,
+            url: error.url,
+        },
+    }
+}
 
 /// Implementation of HttpClient for MockHttpClient from test utils
 #[cfg(test)]
 impl HttpClient for ustar_test_utils::MockHttpClient {
     fn get(&self, url: &str) -> Result<String, DownloadError> {
-        self.get(url).map_err(DownloadError::DownloadFailed)
+        self.request(url).map_err(mock_error)
     }
 
     fn get_bytes(&self, url: &str) -> Result<Vec<u8>, DownloadError> {
-        self.get_bytes(url).map_err(DownloadError::DownloadFailed)
+        self.request_bytes(url).map_err(mock_error)
     }
 }
 
 impl HttpClient for ReqwestClient {
     fn get(&self, url: &str) -> Result<String, DownloadError> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("http_request", url).entered();
         let rt = tokio::runtime::Runtime::new().map_err(|e| {
             DownloadError::DownloadFailed(format!("Failed to create runtime: {}", e))
         })?;
 
-        rt.block_on(async {
-            let response = reqwest::get(url).await?;
-            if !response.status().is_success() {
-                return Err(DownloadError::DownloadFailed(format!(
-                    "HTTP {}: {}",
-                    response.status(),
-                    url
-                )));
-            }
-            Ok(response.text().await?)
-        })
+        rt.block_on(async { Ok(self.send(url).await?.text().await?) })
     }
 
     fn get_bytes(&self, url: &str) -> Result<Vec<u8>, DownloadError> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("http_request", url).entered();
         let rt = tokio::runtime::Runtime::new().map_err(|e| {
             DownloadError::DownloadFailed(format!("Failed to create runtime: {}", e))
         })?;
 
-        rt.block_on(async {
-            let response = reqwest::get(url).await?;
-            if !response.status().is_success() {
-                return Err(DownloadError::DownloadFailed(format!(
-                    "HTTP {}: {}",
-                    response.status(),
-                    url
-                )));
-            }
-            Ok(response.bytes().await?.to_vec())
-        })
+        rt.block_on(async { Ok(self.send(url).await?.bytes().await?.to_vec()) })
     }
 }
 
@@ -131,13 +207,95 @@ pub struct CommonDownloaderCli {
     /// Random number seed for reproducible shuffling
     #[arg(long, default_value_t = 42)]
     pub seed: u64,
+    /// Times to retry a request that failed with a network or server error
+    #[arg(long, default_value_t = 3, value_name = "COUNT")]
+    pub retries: u32,
+    /// Seconds to wait for a request before giving up on it
+    #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+    pub timeout: u64,
+    /// Number of files to download at the same time
+    #[arg(long, short = 'j', default_value_t = 4, value_name = "COUNT")]
+    pub concurrency: usize,
+    /// Most requests to start each second
+    #[arg(long, value_name = "RATE")]
+    pub max_requests_per_second: Option<f64>,
+    /// Parse each downloaded file, moving those that don't parse to a rejected subdirectory
+    #[arg(long)]
+    pub validate: bool,
+    /// Download this entry instead of a random batch, may be repeated
+    #[arg(long = "id", value_name = "ID")]
+    pub ids: Vec<String>,
+    /// Download the entries listed in FILE, one id per line, instead of a random batch
+    #[arg(long, value_name = "FILE")]
+    pub ids_file: Option<PathBuf>,
+    /// Download the ids given without checking they are in the list of available entries
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl CommonDownloaderCli {
+    /// A downloader config with the options given on the command line
+    pub fn config(&self) -> DownloaderConfig {
+        let config = DownloaderConfig::new()
+            .output_dir(&self.output_dir)
+            .verbose(self.verbose)
+            .max_retries(self.retries)
+            .request_timeout(Duration::from_secs(self.timeout))
+            .concurrency(self.concurrency)
+            .validate_parse(self.validate)
+            .force(self.force);
+        match self.max_requests_per_second {
+            Some(rate) => config.max_requests_per_second(rate),
+            None => config,
+        }
+    }
+
+    /// The ids given with --id and in the --ids-file, skipping blank lines and # comments
+    pub fn requested_ids(&self) -> std::io::Result<Vec<String>> {
+        let mut ids = self.ids.clone();
+        if let Some(path) = &self.ids_file {
+            ids.extend(
+                fs::read_to_string(path)?
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        Ok(ids)
+    }
 }
 
+/// Subdirectory of the output directory that files failing validation are moved to
+pub const REJECTED_DIRECTORY: &str = "rejected";
+
 /// Configuration for a downloader
 pub struct DownloaderConfig {
     pub output_dir: PathBuf,
     pub verbose: bool,
     pub file_extension: String,
+    /// Retries after the first attempt of a request that failed with a retryable error
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each retry after it
+    pub initial_backoff: Duration,
+    /// Longest wait between retries
+    pub max_backoff: Duration,
+    /// Time allowed for each request, for data sources that build their own client
+    pub request_timeout: Duration,
+    /// Entries downloaded at the same time
+    pub concurrency: usize,
+    /// Most requests started each second, across all downloads from the data source's host
+    pub max_requests_per_second: Option<f64>,
+    /// Skip entries already downloaded: those in the manifest whose files still match their
+    /// checksums, and files that are in the output directory without being in the manifest
+    pub skip_existing: bool,
+    /// Parse each downloaded file and reject those that fail, moving them to
+    /// [`REJECTED_DIRECTORY`] in the output directory
+    pub validate_parse: bool,
+    /// Encoding to parse downloaded files in, the parser's default when not set
+    pub validate_encoding: Option<EncodingMode>,
+    /// Download ids given by name without checking the data source has them
+    pub force: bool,
 }
 
 impl DownloaderConfig {
@@ -146,6 +304,16 @@ impl DownloaderConfig {
             output_dir: PathBuf::from("."),
             verbose: true,
             file_extension: "cif".to_string(),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(60),
+            concurrency: 1,
+            max_requests_per_second: None,
+            skip_existing: true,
+            validate_parse: false,
+            validate_encoding: None,
+            force: false,
         }
     }
 
@@ -159,10 +327,86 @@ impl DownloaderConfig {
         self
     }
 
+    /// Extension of the files written, which may be double such as cif.gz, with or without a
+    /// leading dot
     pub fn file_extension<S: Into<String>>(mut self, ext: S) -> Self {
-        self.file_extension = ext.into();
+        let ext = ext.into();
+        self.file_extension = ext.strip_prefix('.').unwrap_or(&ext).to_string();
+        self
+    }
+
+    /// The name of the file an entry is written to
+    pub fn file_name(&self, entry_id: &str) -> String {
+        format!("{}.{}", entry_id, self.file_extension)
+    }
+
+    /// The entry a file in the output directory was written for, if it has the extension
+    pub fn entry_id<'n>(&self, file_name: &'n str) -> Option<&'n str> {
+        file_name
+            .strip_suffix(self.file_extension.as_str())?
+            .strip_suffix('.')
+            .filter(|entry_id| !entry_id.is_empty())
+    }
+
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn max_requests_per_second(mut self, rate: f64) -> Self {
+        self.max_requests_per_second = Some(rate);
+        self
+    }
+
+    pub fn skip_existing(mut self, skip: bool) -> Self {
+        self.skip_existing = skip;
+        self
+    }
+
+    pub fn validate_parse(mut self, validate: bool) -> Self {
+        self.validate_parse = validate;
+        self
+    }
+
+    pub fn validate_encoding(mut self, encoding: EncodingMode) -> Self {
+        self.validate_encoding = Some(encoding);
         self
     }
+
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// The wait before retry number `retry`, counting from 0: the initial backoff doubled for
+    /// each earlier retry, capped at the maximum, then jittered down by up to half
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        exponential.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
 }
 
 /// Trait for different data source strategies
@@ -173,19 +417,67 @@ pub trait DataSource {
         entry_id: &str,
         output_path: &PathBuf,
     ) -> Result<PathBuf, DownloadError>;
+
+    /// The URL an entry is downloaded from, recorded in the manifest
+    fn entry_url(&self, _entry_id: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Spaces out the starts of requests so no more than a given number start each second
+struct RateLimiter {
+    interval: Duration,
+    next_start: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next free start time and claim it
+    fn wait(&self) {
+        let now = Instant::now();
+        let start = {
+            let mut next_start = self.next_start.lock().unwrap();
+            let start = (*next_start).max(now);
+            *next_start = start + self.interval;
+            start
+        };
+        std::thread::sleep(start - now);
+    }
+}
+
+/// The outcome of a batch download, entry by entry
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Entries downloaded, with their paths, in the order the seed chose them
+    pub downloaded: Vec<(String, PathBuf)>,
+    /// Entries skipped as they were already downloaded
+    pub existing: Vec<String>,
+    /// Entries that couldn't be downloaded, after any retries
+    pub failed: Vec<(String, DownloadError)>,
+    /// Ids asked for that the data source doesn't have
+    pub not_found: Vec<String>,
 }
 
 /// Common downloader implementation
 pub struct GenericDownloader<T: DataSource> {
     config: DownloaderConfig,
     data_source: T,
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl<T: DataSource> GenericDownloader<T> {
     pub fn new(config: DownloaderConfig, data_source: T) -> Self {
+        let rate_limiter = config.max_requests_per_second.map(RateLimiter::new);
         Self {
             config,
             data_source,
+            rate_limiter,
         }
     }
 
@@ -194,8 +486,26 @@ impl<T: DataSource> GenericDownloader<T> {
         &self,
         count: usize,
         seed: u64,
-    ) -> Result<Vec<(String, PathBuf)>, DownloadError> {
-        let mut entries = self.data_source.get_available_entries()?;
+    ) -> Result<Vec<(String, PathBuf)>, DownloadError>
+    where
+        T: Sync,
+    {
+        Ok(self.download_batch(count, seed)?.downloaded)
+    }
+
+    /// Download `count` unique random entries not already in output_dir, up to `concurrency`
+    /// at a time, reporting what happened to each entry tried
+    ///
+    /// The entries are tried in the order the seed shuffles them into, a wave at a time with as
+    /// many entries as are still needed, so the same entries are downloaded whatever the
+    /// concurrency. Only fetching the list of entries can fail the whole batch.
+    pub fn download_batch(&self, count: usize, seed: u64) -> Result<BatchReport, DownloadError>
+    where
+        T: Sync,
+    {
+        let mut entries = self.with_retries("the list of entries", || {
+            self.data_source.get_available_entries()
+        })?;
 
         if entries.is_empty() {
             return Err(DownloadError::NoEntriesFound);
@@ -205,57 +515,274 @@ impl<T: DataSource> GenericDownloader<T> {
         let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
         entries.shuffle(&mut rng);
 
-        let mut results: Vec<(String, PathBuf)> = Vec::new();
+        self.download_entries(entries, count)
+    }
+
+    /// Download the entries with the given ids, with the same file names as a batch, and
+    /// without drawing replacements for any that fail
+    ///
+    /// Unless the config forces them, the ids are first looked up, ignoring case, in the
+    /// entries the data source has; those it doesn't have are reported as not found.
+    pub fn download_ids(&self, ids: &[String]) -> Result<BatchReport, DownloadError>
+    where
+        T: Sync,
+    {
+        let mut not_found = Vec::new();
+        let wanted = if self.config.force {
+            ids.to_vec()
+        } else {
+            let available: HashMap<String, String> = self
+                .with_retries("the list of entries", || {
+                    self.data_source.get_available_entries()
+                })?
+                .into_iter()
+                .map(|entry_id| (entry_id.to_lowercase(), entry_id))
+                .collect();
+            let mut wanted = Vec::new();
+            for id in ids {
+                match available.get(&id.to_lowercase()) {
+                    Some(entry_id) => wanted.push(entry_id.clone()),
+                    None => {
+                        if self.config.verbose {
+                            eprintln!("Not found: {}", id);
+                        }
+                        not_found.push(id.clone());
+                    }
+                }
+            }
+            wanted
+        };
+
+        let count = wanted.len();
+        let mut report = self.download_entries(wanted, count)?;
+        report.not_found = not_found;
+        Ok(report)
+    }
+
+    /// Download entries in order until `count` have been downloaded or none are left, a wave
+    /// of as many as are still needed at a time
+    fn download_entries(
+        &self,
+        entries: Vec<String>,
+        count: usize,
+    ) -> Result<BatchReport, DownloadError>
+    where
+        T: Sync,
+    {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.config.concurrency)
+            .build()
+            .map_err(|e| {
+                DownloadError::DownloadFailed(format!("Failed to start download threads: {}", e))
+            })?;
+
+        let output_dir = &self.config.output_dir;
+        let manifest = Mutex::new(Manifest::load(output_dir)?);
+        let mut report = BatchReport::default();
         let mut tried = HashSet::new();
+        let mut candidates = entries.into_iter();
+
+        while report.downloaded.len() < count {
+            let mut wave = Vec::new();
+            for entry_id in candidates.by_ref() {
+                if !tried.insert(entry_id.clone()) {
+                    continue;
+                }
 
-        for entry_id in entries.into_iter() {
-            if results.len() >= count {
+                let filepath = self
+                    .config
+                    .output_dir
+                    .join(self.config.file_name(&entry_id));
+
+                if self.config.skip_existing {
+                    let state = manifest
+                        .lock()
+                        .unwrap()
+                        .entries
+                        .get(&entry_id)
+                        .map(|entry| entry.state(output_dir));
+                    match state {
+                        Some(EntryState::Unchanged) | None if filepath.exists() => {
+                            if self.config.verbose {
+                                println!("Already exists, skipping: {}", filepath.display());
+                            }
+                            report.existing.push(entry_id);
+                            continue;
+                        }
+                        Some(EntryState::Changed) if self.config.verbose => {
+                            println!("Changed since downloaded, fetching again: {}", entry_id);
+                        }
+                        _ => {}
+                    }
+                }
+
+                wave.push((entry_id, filepath));
+                if report.downloaded.len() + wave.len() == count {
+                    break;
+                }
+            }
+            if wave.is_empty() {
                 break;
             }
 
-            if !tried.insert(entry_id.clone()) {
-                continue;
+            let results: Vec<_> = pool.install(|| {
+                wave.into_par_iter()
+                    .map(|(entry_id, filepath)| {
+                        let result = self
+                            .with_retries(&entry_id, || {
+                                self.data_source.download_entry(&entry_id, &filepath)
+                            })
+                            .and_then(|path| {
+                                if self.config.validate_parse {
+                                    self.validate_download(&path)?;
+                                }
+                                self.record_download(&manifest, &entry_id, &path)?;
+                                Ok(path)
+                            });
+                        (entry_id, result)
+                    })
+                    .collect()
+            });
+            for (entry_id, result) in results {
+                match result {
+                    Ok(path) => report.downloaded.push((entry_id, path)),
+                    Err(e) => {
+                        if self.config.verbose {
+                            eprintln!("Failed to download {}: {}", entry_id, e);
+                        }
+                        report.failed.push((entry_id, e));
+                    }
+                }
             }
+        }
+
+        Ok(report)
+    }
 
-            let filename = format!("{}.{}", entry_id, self.config.file_extension);
-            let filepath = self.config.output_dir.join(&filename);
+    /// Parse a downloaded file, moving it to the rejected directory if it doesn't parse
+    fn validate_download(&self, path: &Path) -> Result<(), DownloadError> {
+        let mut config = default_config();
+        if let Some(encoding) = self.config.validate_encoding {
+            config.insert(ConfigKey::Encoding, ConfigValue::Encoding(encoding));
+        }
 
-            if filepath.exists() {
-                if self.config.verbose {
-                    println!("Already exists, skipping: {}", filepath.display());
-                }
-                continue;
+        let parsed = if path.extension().is_some_and(|extension| extension == "gz") {
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(fs::File::open(path)?)
+                .read_to_string(&mut text)
+                .map(|_| parse(&text, &config).map(|_| ()))
+        } else {
+            Ok(read_star_file(path, &config).and_then(|input| parse(&input, &config).map(|_| ())))
+        };
+        let summary = match parsed {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => {
+                let core = e.core();
+                format!("line {} column {}: {}", core.line, core.col, core.message)
             }
+            Err(e) => format!("not gzip compressed text: {}", e),
+        };
 
-            match self.data_source.download_entry(&entry_id, &filepath) {
-                Ok(path) => results.push((entry_id, path)),
-                Err(e) => {
+        let rejected = self.config.output_dir.join(REJECTED_DIRECTORY);
+        fs::create_dir_all(&rejected)?;
+        if let Some(name) = path.file_name() {
+            fs::rename(path, rejected.join(name))?;
+        }
+        Err(DownloadError::InvalidContent(summary))
+    }
+
+    /// Add a downloaded file to the manifest and save it, so an interrupted batch can resume
+    fn record_download(
+        &self,
+        manifest: &Mutex<Manifest>,
+        entry_id: &str,
+        path: &std::path::Path,
+    ) -> Result<(), DownloadError> {
+        let entry = ManifestEntry::for_file(
+            entry_id,
+            self.data_source.entry_url(entry_id),
+            &self.config.output_dir,
+            path,
+        )?;
+        let mut manifest = manifest.lock().unwrap();
+        manifest.insert(entry);
+        manifest.save(&self.config.output_dir)
+    }
+
+    /// Check the files in the manifest of the output directory against their sizes and
+    /// checksums, returning the entries whose files are missing or have changed
+    pub fn verify_manifest(&self) -> Result<ManifestCheck, DownloadError> {
+        let manifest = Manifest::load(&self.config.output_dir)?;
+        let mut check = ManifestCheck::default();
+        for entry in manifest.entries.into_values() {
+            match entry.state(&self.config.output_dir) {
+                EntryState::Unchanged => {}
+                EntryState::Missing => check.missing.push(entry),
+                EntryState::Changed => check.mismatched.push(entry),
+            }
+        }
+        Ok(check)
+    }
+
+    /// Run `request`, retrying it with backoff while it fails with a retryable error, and
+    /// keeping to the request rate limit
+    fn with_retries<R>(
+        &self,
+        what: &str,
+        mut request: impl FnMut() -> Result<R, DownloadError>,
+    ) -> Result<R, DownloadError> {
+        #[cfg(feature = "trace")]
+        let _span = tracing::debug_span!("fetch", what).entered();
+        let mut retry = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.wait();
+            }
+            match request() {
+                Err(e) if e.is_retryable() && retry < self.config.max_retries => {
+                    let backoff = self.config.backoff(retry);
+                    retry += 1;
+                    #[cfg(feature = "trace")]
+                    tracing::warn!(
+                        error = %e,
+                        retry,
+                        max_retries = self.config.max_retries,
+                        backoff_secs = backoff.as_secs_f64(),
+                        "retrying"
+                    );
                     if self.config.verbose {
-                        eprintln!("Failed to download {}: {}", entry_id, e);
+                        eprintln!(
+                            "Fetching {} failed: {}, retry {} of {} in {:.1}s",
+                            what,
+                            e,
+                            retry,
+                            self.config.max_retries,
+                            backoff.as_secs_f64()
+                        );
                     }
+                    std::thread::sleep(backoff);
                 }
+                result => return result,
             }
         }
-
-        Ok(results)
     }
 
     /// List available files and show which are downloaded
     pub fn list_files(&self) -> Result<(), DownloadError> {
-        let entries = self.data_source.get_available_entries()?;
+        let entries = self.with_retries("the list of entries", || {
+            self.data_source.get_available_entries()
+        })?;
 
         // Build set of already downloaded files
         let mut downloaded = HashSet::new();
         if let Ok(dir_entries) = fs::read_dir(&self.config.output_dir) {
             for entry in dir_entries.flatten() {
-                if let Some(name) = entry.file_name().to_str() {
-                    if name.ends_with(&self.config.file_extension) {
-                        if let Some(stem) =
-                            name.strip_suffix(&format!(".{}", self.config.file_extension))
-                        {
-                            downloaded.insert(stem.to_lowercase());
-                        }
-                    }
+                if let Some(entry_id) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| self.config.entry_id(name))
+                {
+                    downloaded.insert(entry_id.to_lowercase());
                 }
             }
         }
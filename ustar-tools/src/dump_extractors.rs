@@ -3,72 +3,83 @@
 //! This provides a uniform interface for extracting dump information
 //! from both real `Pair<Rule>` objects and mutable structures.
 
-use ustar_parser::mutable_pair::MutablePair;
-use ustar_parser::Rule;
-use ustar_parser::{Pair, Pairs};
+use std::marker::PhantomData;
+use ustar_parser::mutable_pair::{MutablePair, VisitControl, Visitor};
+use ustar_parser::tree_arena::PairRef;
+use ustar_parser::{Pair, Pairs, Rule, RuleType};
 
 /// A trait that defines how to extract information needed for dumping.
-/// The extractor is stateless - methods take references to the object being extracted.
-pub trait DumpExtractor<T: ?Sized> {
-    /// The type of child item returned by get_children
-    type Child;
-    /// The type of iterator returned by get_children
-    type ChildIter: Iterator<Item = Self::Child>;
+/// The extractor is stateless - methods take references to the node being extracted.
+pub trait DumpExtractor {
+    /// The type of tree node, which may borrow the input or tree for `'n`
+    type Node<'n>;
+    /// The type of iterator returned by children
+    type Children<'n>: Iterator<Item = Self::Node<'n>>;
 
     /// Extract the rule name from a node as a string
-    fn extract_rule_name(&self, node: &T) -> String;
-
-    /// Extract the string content from a node
-    fn extract_str<'a>(&self, node: &'a T) -> &'a str;
+    fn rule_name(&self, node: &Self::Node<'_>) -> String;
 
     /// Extract the starting position
-    fn extract_start(&self, node: &T) -> usize;
+    fn start(&self, node: &Self::Node<'_>) -> usize;
 
     /// Extract the ending position
-    fn extract_end(&self, node: &T) -> usize;
+    fn end(&self, node: &Self::Node<'_>) -> usize;
+
+    /// Extract the string content from a node
+    fn text<'a>(&self, node: &'a Self::Node<'_>) -> &'a str;
 
     /// Check if node has children (efficient check without iteration)
-    fn has_children(&self, node: &T) -> bool;
+    fn has_children(&self, node: &Self::Node<'_>) -> bool;
 
     /// Get an iterator over children
-    fn get_children(&self, node: &T) -> Self::ChildIter;
+    fn children<'n>(&self, node: &Self::Node<'n>) -> Self::Children<'n>;
 }
 
-/// Extractor for `Pair<Rule>` objects - stateless!
-#[derive(Default)]
-pub struct PairExtractor;
+/// Extractor for pest `Pair<R>` objects of any of the ascii, extended or unicode grammars - stateless!
+pub struct PestPairExtractor<R> {
+    rule: PhantomData<R>,
+}
 
-impl PairExtractor {
+impl<R: RuleType> PestPairExtractor<R> {
     pub fn new() -> Self {
-        PairExtractor
+        PestPairExtractor { rule: PhantomData }
     }
 }
 
-impl<'i> DumpExtractor<Pair<'i, Rule>> for PairExtractor {
-    type Child = Pair<'i, Rule>;
-    type ChildIter = Pairs<'i, Rule>;
-
-    fn extract_rule_name(&self, node: &Pair<'i, Rule>) -> String {
-        format!("{:?}", node.as_rule())
+impl<R: RuleType> Default for PestPairExtractor<R> {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn extract_str<'a>(&self, node: &'a Pair<'i, Rule>) -> &'a str {
-        node.as_str()
+/// Extractor for `Pair<Rule>` objects of the default grammar
+pub type PairExtractor = PestPairExtractor<Rule>;
+
+impl<R: RuleType> DumpExtractor for PestPairExtractor<R> {
+    type Node<'n> = Pair<'n, R>;
+    type Children<'n> = Pairs<'n, R>;
+
+    fn rule_name(&self, node: &Pair<'_, R>) -> String {
+        format!("{:?}", node.as_rule())
     }
 
-    fn extract_start(&self, node: &Pair<'i, Rule>) -> usize {
+    fn start(&self, node: &Pair<'_, R>) -> usize {
         node.as_span().start()
     }
 
-    fn extract_end(&self, node: &Pair<'i, Rule>) -> usize {
+    fn end(&self, node: &Pair<'_, R>) -> usize {
         node.as_span().end()
     }
 
-    fn has_children(&self, node: &Pair<'i, Rule>) -> bool {
+    fn text<'a>(&self, node: &'a Pair<'_, R>) -> &'a str {
+        node.as_str()
+    }
+
+    fn has_children(&self, node: &Pair<'_, R>) -> bool {
         node.clone().into_inner().peek().is_some()
     }
 
-    fn get_children(&self, node: &Pair<'i, Rule>) -> Self::ChildIter {
+    fn children<'n>(&self, node: &Self::Node<'n>) -> Self::Children<'n> {
         node.clone().into_inner()
     }
 }
@@ -83,40 +94,92 @@ impl MutablePairExtractor {
     }
 }
 
-impl DumpExtractor<MutablePair> for MutablePairExtractor {
-    type Child = MutablePair; // Return owned values for consistency with Pair
-    type ChildIter = std::vec::IntoIter<MutablePair>;
+impl DumpExtractor for MutablePairExtractor {
+    type Node<'n> = MutablePair; // Return owned values for consistency with Pair
+    type Children<'n> = std::vec::IntoIter<MutablePair>;
 
-    fn extract_rule_name(&self, node: &MutablePair) -> String {
+    fn rule_name(&self, node: &MutablePair) -> String {
         node.rule_name().to_owned()
     }
 
-    fn extract_str<'a>(&self, node: &'a MutablePair) -> &'a str {
+    fn start(&self, node: &MutablePair) -> usize {
+        node.start_pos()
+    }
+
+    fn end(&self, node: &MutablePair) -> usize {
+        node.end_pos()
+    }
+
+    fn text<'a>(&self, node: &'a MutablePair) -> &'a str {
         node.as_str()
     }
 
-    fn extract_start(&self, node: &MutablePair) -> usize {
+    fn has_children(&self, node: &MutablePair) -> bool {
+        node.has_children()
+    }
+
+    fn children<'n>(&self, node: &MutablePair) -> Self::Children<'n> {
+        node.children().to_vec().into_iter()
+    }
+}
+
+/// Extractor for arena-backed `PairRef` handles - stateless!
+#[derive(Default)]
+pub struct ArenaExtractor;
+
+impl ArenaExtractor {
+    pub fn new() -> Self {
+        ArenaExtractor
+    }
+}
+
+impl DumpExtractor for ArenaExtractor {
+    type Node<'n> = PairRef<'n>; // Handles are Copy so no cloning of subtrees is needed
+    type Children<'n> = std::vec::IntoIter<PairRef<'n>>;
+
+    fn rule_name(&self, node: &PairRef<'_>) -> String {
+        node.rule_name().to_owned()
+    }
+
+    fn start(&self, node: &PairRef<'_>) -> usize {
         node.start_pos()
     }
 
-    fn extract_end(&self, node: &MutablePair) -> usize {
+    fn end(&self, node: &PairRef<'_>) -> usize {
         node.end_pos()
     }
 
-    fn has_children(&self, node: &MutablePair) -> bool {
+    fn text<'a>(&self, node: &'a PairRef<'_>) -> &'a str {
+        node.as_str()
+    }
+
+    fn has_children(&self, node: &PairRef<'_>) -> bool {
         node.has_children()
     }
 
-    fn get_children(&self, node: &MutablePair) -> Self::ChildIter {
-        node.children().to_vec().into_iter()
+    fn children<'n>(&self, node: &Self::Node<'n>) -> Self::Children<'n> {
+        node.children().collect::<Vec<_>>().into_iter()
     }
 }
 
-/// Dump a `Pair<Rule>` recursively
-pub fn dump_pair(pair: &Pair<Rule>, level: usize) {
-    let extractor = PairExtractor::new();
+/// Visit `node` and its descendants depth first, calling `visit` with each and its depth,
+/// counting `node` as at `depth`
+pub fn visit_nodes<'n, E: DumpExtractor>(
+    extractor: &E,
+    node: &E::Node<'n>,
+    depth: usize,
+    visit: &mut impl FnMut(&E::Node<'n>, usize),
+) {
+    visit(node, depth);
+    for child in extractor.children(node) {
+        visit_nodes(extractor, &child, depth + 1, visit);
+    }
+}
+
+/// Print the dump line of one node at `level`
+fn print_node<E: DumpExtractor>(extractor: &E, node: &E::Node<'_>, level: usize) {
     let indent = "  ".repeat(level);
-    let symbol = if extractor.has_children(pair) {
+    let symbol = if extractor.has_children(node) {
         ">"
     } else {
         "-"
@@ -126,38 +189,125 @@ pub fn dump_pair(pair: &Pair<Rule>, level: usize) {
         "{}{} {} {}..{} {:?}",
         indent,
         symbol,
-        extractor.extract_rule_name(pair),
-        extractor.extract_start(pair),
-        extractor.extract_end(pair),
-        extractor.extract_str(pair)
+        extractor.rule_name(node),
+        extractor.start(node),
+        extractor.end(node),
+        extractor.text(node)
     );
+}
 
-    for child in extractor.get_children(pair) {
-        dump_pair(&child, level + 1);
-    }
+/// Dump a `Pair<Rule>` recursively
+pub fn dump_pair(pair: &Pair<Rule>, level: usize) {
+    let extractor = PairExtractor::new();
+    visit_nodes(&extractor, pair, level, &mut |node, level| {
+        print_node(&extractor, node, level)
+    });
 }
 
 /// Dump a MutablePair recursively
 pub fn dump_mutable_pair(pair: &MutablePair, level: usize) {
-    let extractor = MutablePairExtractor::new();
-    let indent = "  ".repeat(level);
-    let symbol = if extractor.has_children(pair) {
-        ">"
-    } else {
-        "-"
-    };
+    pair.accept(&mut MutablePairDumper {
+        extractor: MutablePairExtractor::new(),
+        level,
+    });
+}
 
-    println!(
-        "{}{} {} {}..{} {:?}",
-        indent,
-        symbol,
-        extractor.extract_rule_name(pair),
-        extractor.extract_start(pair),
-        extractor.extract_end(pair),
-        extractor.extract_str(pair)
-    );
+/// Visitor printing each node of a mutable tree indented by its depth
+struct MutablePairDumper {
+    extractor: MutablePairExtractor,
+    level: usize,
+}
+
+impl Visitor for MutablePairDumper {
+    fn enter(&mut self, pair: &MutablePair) -> VisitControl {
+        print_node(&self.extractor, pair, self.level);
+        self.level += 1;
+        VisitControl::Continue
+    }
+
+    fn exit(&mut self, _pair: &MutablePair) {
+        self.level -= 1;
+    }
+}
+
+/// Dump an arena-backed tree node recursively
+pub fn dump_arena_pair(pair: PairRef, level: usize) {
+    let extractor = ArenaExtractor::new();
+    visit_nodes(&extractor, &pair, level, &mut |node, level| {
+        print_node(&extractor, node, level)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pest::Parser;
+    use ustar_parser::parsers::{ascii, extended, unicode};
+    use ustar_parser::tree_arena::TreeArena;
+
+    const INPUT: &str =
+        "data_test\n_tag value\nloop_\n_a _b\n1 'two'\n3\n;four\n;\nsave_frame\n_c c\nsave_\n";
+
+    /// The rule name, offsets, text and depth of every node below `node`, in order
+    fn node_sequence<'n, E: DumpExtractor>(
+        extractor: &E,
+        node: &E::Node<'n>,
+    ) -> Vec<(String, usize, usize, String, usize)> {
+        let mut sequence = Vec::new();
+        visit_nodes(extractor, node, 0, &mut |node, depth| {
+            sequence.push((
+                extractor.rule_name(node),
+                extractor.start(node),
+                extractor.end(node),
+                extractor.text(node).to_string(),
+                depth,
+            ));
+        });
+        sequence
+    }
+
+    /// Check the pest, mutable and arena extractors agree over the tree of `pair`
+    fn assert_extractors_agree<R: RuleType>(pair: Pair<'_, R>) {
+        let pest_sequence = node_sequence(&PestPairExtractor::<R>::new(), &pair);
+        let mutable_sequence = node_sequence(
+            &MutablePairExtractor::new(),
+            &MutablePair::from_pest_pair(&pair),
+        );
+        let arena = TreeArena::from_pest_pair(&pair);
+        let arena_sequence = node_sequence(&ArenaExtractor::new(), &arena.root().unwrap());
+
+        assert!(pest_sequence.len() > 1);
+        assert_eq!(pest_sequence, mutable_sequence);
+        assert_eq!(pest_sequence, arena_sequence);
+    }
+
+    #[test]
+    fn test_ascii_extractors_agree() {
+        let mut pairs = ascii::AsciiParser::parse(ascii::Rule::star_file, INPUT).unwrap();
+        assert_extractors_agree(pairs.next().unwrap());
+    }
+
+    #[test]
+    fn test_extended_extractors_agree() {
+        let mut pairs = extended::ExtendedParser::parse(extended::Rule::star_file, INPUT).unwrap();
+        assert_extractors_agree(pairs.next().unwrap());
+    }
+
+    #[test]
+    fn test_unicode_extractors_agree() {
+        let mut pairs = unicode::UnicodeParser::parse(unicode::Rule::star_file, INPUT).unwrap();
+        assert_extractors_agree(pairs.next().unwrap());
+    }
 
-    for child in extractor.get_children(pair) {
-        dump_mutable_pair(&child, level + 1);
+    #[test]
+    fn test_has_children_matches_children() {
+        let mut pairs = ascii::AsciiParser::parse(ascii::Rule::star_file, INPUT).unwrap();
+        let extractor = PestPairExtractor::<ascii::Rule>::new();
+        visit_nodes(&extractor, &pairs.next().unwrap(), 0, &mut |node, _| {
+            assert_eq!(
+                extractor.has_children(node),
+                extractor.children(node).next().is_some()
+            );
+        });
     }
 }
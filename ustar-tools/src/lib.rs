@@ -1,6 +1,12 @@
 // CLI utilities
+pub mod color;
+pub mod download_manifest;
 pub mod downloader_common;
 pub mod dump_extractors;
+pub mod pdbe_source;
+pub mod report_bundle;
 
 // Re-export common types from the core parser for convenience
-pub use ustar_parser::{mutable_pair, parse, parse_default, ParserConfig, UstarError};
+pub use ustar_parser::{
+    mutable_pair, parse, parse_arena, parse_default, tree_arena, ParserConfig, UstarError,
+};
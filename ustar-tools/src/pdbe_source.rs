@@ -0,0 +1,139 @@
+//! PDBe data source - mmCIF entries from the EBI mirror of the PDB archive.
+//!
+//! Entries are listed with the PDBe search API and downloaded gzipped from the EBI copy of the
+//! wwPDB archive, then decompressed before they are written unless compressed files are asked
+//! for.
+
+use crate::downloader_common::{DataSource, DownloadError, HttpClient, ReqwestClient};
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Search API query returning the id of every released entry
+pub const PDBE_ENTRIES_URL: &str = "https://www.ebi.ac.uk/pdbe/search/pdb/select?q=*:*&fl=pdb_id&group=true&group.field=pdb_id&group.main=true&rows=1000000&wt=json";
+
+/// Directory of the gzipped mmCIF files of all entries
+pub const PDBE_MMCIF_URL: &str =
+    "https://ftp.ebi.ac.uk/pub/databases/pdb/data/structures/all/mmCIF";
+
+/// The two bytes starting every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Downloads PDB entries from PDBe
+pub struct PdbeSource {
+    compressed: bool,
+    verbose: bool,
+    http_client: Arc<dyn HttpClient>,
+}
+
+impl PdbeSource {
+    pub fn new(verbose: bool, timeout: Duration) -> Self {
+        Self::with_client(verbose, Arc::new(ReqwestClient::new().timeout(timeout)))
+    }
+
+    pub fn with_client(verbose: bool, client: Arc<dyn HttpClient>) -> Self {
+        Self {
+            compressed: false,
+            verbose,
+            http_client: client,
+        }
+    }
+
+    /// Write the .cif.gz files as downloaded instead of decompressing them
+    pub fn compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    /// URL of an entry's gzipped mmCIF file
+    fn url_for(&self, entry_id: &str) -> String {
+        format!("{}/{}.cif.gz", PDBE_MMCIF_URL, entry_id.to_lowercase())
+    }
+
+    /// The extension of the files written, cif.gz when kept compressed
+    pub fn file_extension(&self) -> &'static str {
+        if self.compressed {
+            "cif.gz"
+        } else {
+            "cif"
+        }
+    }
+}
+
+impl DataSource for PdbeSource {
+    fn get_available_entries(&self) -> Result<Vec<String>, DownloadError> {
+        if self.verbose {
+            println!("Fetching the list of PDBe entries...");
+        }
+
+        let response: serde_json::Value =
+            serde_json::from_str(&self.http_client.get(PDBE_ENTRIES_URL)?)?;
+        let docs = response["response"]["docs"].as_array().ok_or_else(|| {
+            DownloadError::DownloadFailed("PDBe search response has no docs".to_string())
+        })?;
+        let mut entries: Vec<String> = docs
+            .iter()
+            .filter_map(|doc| doc["pdb_id"].as_str())
+            .map(str::to_lowercase)
+            .collect();
+        entries.sort_unstable();
+        entries.dedup();
+
+        if self.verbose {
+            println!("Found {} PDBe entries", entries.len());
+        }
+
+        if entries.is_empty() {
+            return Err(DownloadError::NoEntriesFound);
+        }
+
+        Ok(entries)
+    }
+
+    fn download_entry(
+        &self,
+        entry_id: &str,
+        output_path: &PathBuf,
+    ) -> Result<PathBuf, DownloadError> {
+        let url = self.url_for(entry_id);
+        if self.verbose {
+            println!(
+                "[VERBOSE] Downloading PDBe entry {} from {}...",
+                entry_id, url
+            );
+        }
+
+        let bytes = self.http_client.get_bytes(&url)?;
+        // a client or proxy may already have decoded the gzip transfer
+        let is_gzip = bytes.starts_with(&GZIP_MAGIC);
+        let content = if self.compressed || !is_gzip {
+            bytes
+        } else {
+            let mut content = Vec::new();
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut content)?;
+            content
+        };
+
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(output_path, &content)?;
+
+        if self.verbose {
+            println!(
+                "Successfully saved {} ({} bytes)",
+                output_path.display(),
+                content.len()
+            );
+        }
+
+        Ok(output_path.clone())
+    }
+
+    fn entry_url(&self, entry_id: &str) -> Option<String> {
+        Some(self.url_for(entry_id))
+    }
+}
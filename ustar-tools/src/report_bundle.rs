@@ -0,0 +1,218 @@
+//! Self-contained error report bundles for filing parser bugs.
+//!
+//! A bundle is a zip archive holding everything needed to reproduce a parse failure: a minimized
+//! snippet that still fails, the error in the parser's JSON error format, the tool and parser
+//! versions and the parser's capabilities, the exact configuration used as a JSON configuration
+//! file and, optionally, the gzipped original input.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use ustar_parser::{
+    parse, CifVersion, ColumnUnit, ConfigFile, EncodingMode, ErrorFormatMode, ParserConfig,
+    UstarError,
+};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Name of the minimized failing snippet inside the bundle
+pub const SNIPPET_ENTRY: &str = "snippet.star";
+/// Name of the JSON error report inside the bundle
+pub const ERROR_ENTRY: &str = "error.json";
+/// Name of the version and capabilities JSON inside the bundle
+pub const VERSION_ENTRY: &str = "version.json";
+/// Name of the parser configuration JSON inside the bundle
+pub const CONFIG_ENTRY: &str = "config.json";
+/// Directory holding the gzipped original input when it is included
+pub const INPUT_DIRECTORY: &str = "input/";
+
+/// Error types for report bundle creation
+#[derive(Debug)]
+pub enum ReportBundleError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    Json(serde_json::Error),
+    /// The input parsed successfully so there is no failure to report
+    NoParseError,
+}
+
+impl std::fmt::Display for ReportBundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportBundleError::Io(e) => write!(f, "IO error: {}", e),
+            ReportBundleError::Zip(e) => write!(f, "Zip error: {}", e),
+            ReportBundleError::Json(e) => write!(f, "JSON error: {}", e),
+            ReportBundleError::NoParseError => {
+                write!(f, "Input parses successfully, there is nothing to report")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReportBundleError {}
+
+impl From<std::io::Error> for ReportBundleError {
+    fn from(err: std::io::Error) -> Self {
+        ReportBundleError::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for ReportBundleError {
+    fn from(err: zip::result::ZipError) -> Self {
+        ReportBundleError::Zip(err)
+    }
+}
+
+impl From<serde_json::Error> for ReportBundleError {
+    fn from(err: serde_json::Error) -> Self {
+        ReportBundleError::Json(err)
+    }
+}
+
+/// Write a report bundle for `content` (read from `input_path`) to the zip file `output`.
+///
+/// Returns the names of the entries written, in archive order.
+pub fn create(
+    output: &Path,
+    input_path: &Path,
+    content: &str,
+    config: &ParserConfig,
+    include_input: bool,
+) -> Result<Vec<String>, ReportBundleError> {
+    let error = match parse(content, config) {
+        Ok(_) => return Err(ReportBundleError::NoParseError),
+        Err(error) => error,
+    };
+
+    let snippet = minimize(content, config);
+
+    let mut entries = vec![
+        (SNIPPET_ENTRY.to_string(), snippet.into_bytes()),
+        (
+            ERROR_ENTRY.to_string(),
+            serde_json::to_vec_pretty(&error_report(*error, input_path)?)?,
+        ),
+        (
+            VERSION_ENTRY.to_string(),
+            serde_json::to_vec_pretty(&version_report())?,
+        ),
+        (
+            CONFIG_ENTRY.to_string(),
+            config.to_json_string().into_bytes(),
+        ),
+    ];
+
+    if include_input {
+        let file_name = input_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "input".to_string());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes())?;
+        entries.push((
+            format!("{}{}.gz", INPUT_DIRECTORY, file_name),
+            encoder.finish()?,
+        ));
+    }
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let mut names = Vec::new();
+    for (name, data) in entries {
+        // the gzipped input is already compressed, everything else is small text
+        let method = if name.ends_with(".gz") {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        zip.start_file(
+            name.as_str(),
+            SimpleFileOptions::default().compression_method(method),
+        )?;
+        zip.write_all(&data)?;
+        names.push(name);
+    }
+    zip.finish()?;
+
+    Ok(names)
+}
+
+/// Reduce a failing input to a smaller one that fails with the same error message.
+///
+/// Whole lines are removed, first in large chunks and then one at a time, keeping any removal
+/// after which the parse still fails with the original message. Input that parses is returned
+/// unchanged.
+pub fn minimize(content: &str, config: &ParserConfig) -> String {
+    let Some(target) = failure_message(content, config) else {
+        return content.to_string();
+    };
+    let fails_the_same =
+        |lines: &[&str]| failure_message(&lines.concat(), config).as_ref() == Some(&target);
+
+    let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut chunk = (lines.len() / 2).max(1);
+    loop {
+        let mut removed_any = false;
+        let mut start = 0;
+        while start < lines.len() {
+            let end = (start + chunk).min(lines.len());
+            let candidate: Vec<&str> = lines[..start]
+                .iter()
+                .chain(&lines[end..])
+                .copied()
+                .collect();
+            if fails_the_same(&candidate) {
+                lines = candidate;
+                removed_any = true;
+            } else {
+                start = end;
+            }
+        }
+
+        if !removed_any {
+            if chunk == 1 {
+                break;
+            }
+            chunk = (chunk / 2).max(1);
+        }
+    }
+
+    lines.concat()
+}
+
+fn failure_message(content: &str, config: &ParserConfig) -> Option<String> {
+    match parse(content, config) {
+        Ok(_) => None,
+        Err(error) => Some(error.core().message.clone()),
+    }
+}
+
+/// The error in the parser's JSON error format, naming the input file
+fn error_report(error: UstarError, input_path: &Path) -> serde_json::Result<Value> {
+    let error = error.with_source_name(input_path.display().to_string());
+    serde_json::from_str(&error.format_error(ErrorFormatMode::Json, 0))
+}
+
+/// The debug names of every variant in `modes`
+fn names<T: std::fmt::Debug>(modes: &[T]) -> Vec<String> {
+    modes.iter().map(|mode| format!("{:?}", mode)).collect()
+}
+
+fn version_report() -> Value {
+    json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "parser_version": ustar_parser::VERSION,
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "command": std::env::args().collect::<Vec<_>>(),
+        "capabilities": {
+            "encodings": names(&EncodingMode::ALL),
+            "cif_versions": names(&CifVersion::ALL),
+            "error_formats": names(&ErrorFormatMode::ALL),
+            "column_units": names(&ColumnUnit::ALL),
+        },
+    })
+}
@@ -2,8 +2,12 @@
 // Note: ustar-dumper is already tested comprehensively in ustar_dumper_tests.rs
 
 use std::process::Command;
+use ustar_parser::line_column_index::LineColumn;
+use ustar_parser::sas_interface::{SASContentHandler, ValueDelimiter, WalkControl};
 use ustar_test_utils::assert_snapshot_gz;
 
+ustar_test_utils::snapshot_orphan_check!("binary_integration_tests");
+
 // Simple smoke tests to verify the binaries execute without errors
 
 /// Helper function to test download functionality using mocked data sources
@@ -67,6 +71,7 @@ fn test_binaries_help_commands() {
     println!("Building binaries...");
     let build_output = Command::new("cargo")
         .args(&["build", "--bins"])
+        .args(BUILD_FEATURES)
         .output()
         .expect("Failed to build binaries");
 
@@ -85,6 +90,9 @@ fn test_binaries_help_commands() {
         "download-pdbs",
         "ustar-grammar-railroad",
         "sas-demo",
+        "ustar-validate",
+        "ustar-convert",
+        "ustar-diff",
     ];
 
     // Find the target directory
@@ -164,6 +172,82 @@ fn test_sas_demo_basic_execution() {
     );
 }
 
+/// Build sas-demo and run it over comprehensive_example.star with `args`, returning stdout
+fn run_sas_demo(args: &[&str]) -> String {
+    let build_output = Command::new("cargo")
+        .args(["build", "--bin", "sas-demo"])
+        .output()
+        .expect("Failed to build sas-demo");
+    assert!(
+        build_output.status.success(),
+        "Failed to build sas-demo: {}",
+        String::from_utf8_lossy(&build_output.stderr)
+    );
+
+    let output = Command::new("../target/debug/sas-demo")
+        .arg("../ustar-parser/tests/test_data/comprehensive_example.star")
+        .args(args)
+        .output()
+        .expect("Failed to run sas-demo");
+    assert!(
+        output.status.success(),
+        "sas-demo {:?} should execute successfully: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).expect("Failed to parse stdout")
+}
+
+#[test]
+fn test_sas_demo_json_format() {
+    let stdout = run_sas_demo(&["--format", "json"]);
+
+    for line in stdout.lines() {
+        let event: serde_json::Value =
+            serde_json::from_str(line).expect("each line should be a JSON object");
+        assert!(event["event"].is_string(), "missing event kind in {}", line);
+    }
+
+    assert_snapshot_gz(
+        "binary_integration_tests__sas_demo_comprehensive_example_json",
+        &stdout,
+    );
+}
+
+#[test]
+fn test_sas_demo_counts_format() {
+    let stdout = run_sas_demo(&["--format", "counts"]);
+
+    assert_snapshot_gz(
+        "binary_integration_tests__sas_demo_comprehensive_example_counts",
+        &stdout,
+    );
+}
+
+#[test]
+fn test_sas_demo_event_selection() {
+    let stdout = run_sas_demo(&[
+        "--format",
+        "json",
+        "--only",
+        "start_saveframe,data",
+        "--saveframe",
+        "frame_example_2",
+        "--max-events",
+        "3",
+    ]);
+
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("each line should be a JSON object"))
+        .collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["event"], "start_saveframe");
+    assert_eq!(events[0]["name"], "frame_example_2");
+    assert!(events[1..].iter().all(|event| event["event"] == "data"));
+}
+
 #[test]
 fn test_download_pdbs_basic_functionality() {
     test_download_functionality_with_mocks("pdb", "cif", |temp_dir| {
@@ -400,6 +484,207 @@ fn test_download_cod_cifs_basic_functionality() {
     });
 }
 
+/// A data source over a mock client that keeps the HTTP status of failed requests, so the
+/// downloader can tell failures worth retrying from the rest
+struct RetryingMockSource {
+    http_client: std::sync::Arc<ustar_test_utils::MockHttpClient>,
+}
+
+const RETRY_LIST_URL: &str = "https://example.org/entries.txt";
+
+fn retry_entry_url(entry_id: &str) -> String {
+    format!("https://example.org/entries/{}.str", entry_id)
+}
+
+/// A mock client listing two entries, with any failures already added to `client`
+fn retry_client(
+    client: ustar_test_utils::MockHttpClient,
+) -> std::sync::Arc<ustar_test_utils::MockHttpClient> {
+    std::sync::Arc::new(
+        client
+            .with_response(RETRY_LIST_URL, "1000\n2000")
+            .with_response(&retry_entry_url("1000"), "data_1000\n_Entry.ID 1000\n")
+            .with_response(&retry_entry_url("2000"), "data_2000\n_Entry.ID 2000\n"),
+    )
+}
+
+impl ustar_tools::downloader_common::DataSource for RetryingMockSource {
+    fn get_available_entries(
+        &self,
+    ) -> Result<Vec<String>, ustar_tools::downloader_common::DownloadError> {
+        let text = self
+            .http_client
+            .request(RETRY_LIST_URL)
+            .map_err(status_error)?;
+        Ok(text.lines().map(str::to_string).collect())
+    }
+
+    fn download_entry(
+        &self,
+        entry_id: &str,
+        output_path: &std::path::PathBuf,
+    ) -> Result<std::path::PathBuf, ustar_tools::downloader_common::DownloadError> {
+        let content = self
+            .http_client
+            .request(&retry_entry_url(entry_id))
+            .map_err(status_error)?;
+        std::fs::write(output_path, content)?;
+        Ok(output_path.clone())
+    }
+}
+
+fn status_error(
+    error: ustar_test_utils::MockHttpError,
+) -> ustar_tools::downloader_common::DownloadError {
+    use ustar_tools::downloader_common::DownloadError;
+    match error.status {
+        Some(status) => DownloadError::HttpStatus {
+            status,
+            url: error.url,
+        },
+        None => DownloadError::Connection {
+            message: error.message,
+            url: error.url,
+        },
+    }
+}
+
+/// A quiet downloader config with millisecond backoffs so retries don't slow the tests
+fn retry_config(
+    output_dir: &std::path::Path,
+    max_retries: u32,
+) -> ustar_tools::downloader_common::DownloaderConfig {
+    ustar_tools::downloader_common::DownloaderConfig::new()
+        .output_dir(output_dir)
+        .verbose(false)
+        .file_extension("str")
+        .max_retries(max_retries)
+        .initial_backoff(std::time::Duration::from_millis(1))
+        .max_backoff(std::time::Duration::from_millis(4))
+}
+
+#[test]
+fn test_downloader_retries_transient_failures() {
+    use ustar_test_utils::MockHttpClient;
+    use ustar_tools::downloader_common::GenericDownloader;
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let client = retry_client(
+        MockHttpClient::new()
+            .with_failures(RETRY_LIST_URL, 1, 502)
+            .with_failures(&retry_entry_url("1000"), 2, 503)
+            .with_failures(&retry_entry_url("2000"), 3, 429),
+    );
+    let downloader = GenericDownloader::new(
+        retry_config(temp_dir.path(), 3),
+        RetryingMockSource {
+            http_client: client.clone(),
+        },
+    );
+
+    let mut batch = downloader
+        .download_unique_random_batch(2, 42)
+        .expect("Retries should outlast the failures");
+    batch.sort();
+    let ids: Vec<_> = batch.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["1000", "2000"]);
+    assert!(batch.iter().all(|(_, path)| path.exists()));
+
+    assert_eq!(client.request_count(RETRY_LIST_URL), 2);
+    assert_eq!(client.request_count(&retry_entry_url("1000")), 3);
+    assert_eq!(client.request_count(&retry_entry_url("2000")), 4);
+}
+
+#[test]
+fn test_downloader_gives_up_after_max_retries() {
+    use ustar_test_utils::MockHttpClient;
+    use ustar_tools::downloader_common::GenericDownloader;
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let client =
+        retry_client(MockHttpClient::new().with_failures(&retry_entry_url("1000"), 5, 500));
+    let downloader = GenericDownloader::new(
+        retry_config(temp_dir.path(), 2),
+        RetryingMockSource {
+            http_client: client.clone(),
+        },
+    );
+
+    let batch = downloader.download_unique_random_batch(2, 42).unwrap();
+    let ids: Vec<_> = batch.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, ["2000"], "the failing entry should be skipped");
+
+    assert_eq!(client.request_count(&retry_entry_url("1000")), 3);
+}
+
+#[test]
+fn test_downloader_does_not_retry_fatal_errors() {
+    use ustar_test_utils::MockHttpClient;
+    use ustar_tools::downloader_common::{DownloadError, GenericDownloader};
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let client = retry_client(MockHttpClient::new().with_failures(RETRY_LIST_URL, 1, 404));
+    let downloader = GenericDownloader::new(
+        retry_config(temp_dir.path(), 3),
+        RetryingMockSource {
+            http_client: client.clone(),
+        },
+    );
+
+    match downloader.download_unique_random_batch(2, 42) {
+        Err(DownloadError::HttpStatus { status: 404, .. }) => {}
+        other => panic!("Expected the 404 to be returned, got {:?}", other),
+    }
+    assert_eq!(client.request_count(RETRY_LIST_URL), 1);
+
+    // an entry that isn't there is a 404 too, and is tried once
+    let client = std::sync::Arc::new(
+        MockHttpClient::new()
+            .with_response(RETRY_LIST_URL, "1000\n3000")
+            .with_response(&retry_entry_url("1000"), "data_1000\n"),
+    );
+    let downloader = GenericDownloader::new(
+        retry_config(temp_dir.path(), 3),
+        RetryingMockSource {
+            http_client: client.clone(),
+        },
+    );
+    let batch = downloader.download_unique_random_batch(2, 42).unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(client.request_count(&retry_entry_url("3000")), 1);
+}
+
+#[test]
+fn test_downloader_backoff_grows_and_is_capped() {
+    use std::time::Duration;
+    use ustar_tools::downloader_common::DownloaderConfig;
+
+    let config = DownloaderConfig::new()
+        .initial_backoff(Duration::from_millis(100))
+        .max_backoff(Duration::from_millis(1000));
+    for (retry, full) in [
+        (0, 100),
+        (1, 200),
+        (2, 400),
+        (3, 800),
+        (4, 1000),
+        (30, 1000),
+    ] {
+        let full = Duration::from_millis(full);
+        for _ in 0..20 {
+            let backoff = config.backoff(retry);
+            assert!(
+                backoff >= full / 2 && backoff <= full,
+                "retry {} waited {:?}, expected between {:?} and {:?}",
+                retry,
+                backoff,
+                full / 2,
+                full
+            );
+        }
+    }
+}
+
 #[test]
 fn test_ustar_parse_debugger_invalid_file() {
     use std::path::Path;
@@ -444,6 +729,238 @@ fn test_ustar_parse_debugger_invalid_file() {
     );
 }
 
+#[test]
+fn test_ustar_parse_debugger_json_report() {
+    use std::path::Path;
+
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-parse-debugger"])
+        .output()
+        .expect("Failed to build ustar-parse-debugger");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-parse-debugger: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    let binary_path = Path::new("../target/debug/ustar-parse-debugger");
+    let output = Command::new(binary_path)
+        .args(&["--format", "json", "tests/test_data/invalid_syntax.star"])
+        .output()
+        .expect("Failed to run ustar-parse-debugger");
+    assert!(output.status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout should be a single JSON document");
+    let keys: Vec<&str> = report
+        .as_object()
+        .expect("the report should be an object")
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            "encoding",
+            "error",
+            "file",
+            "last_good",
+            "parsed",
+            "parses",
+            "remaining",
+            "size",
+            "unparsed_excerpt"
+        ]
+    );
+    assert_eq!(report["file"], "tests/test_data/invalid_syntax.star");
+    assert_eq!(report["encoding"], "ascii");
+    assert_eq!(report["parses"], false);
+    assert_eq!(report["size"], 234);
+    assert_eq!(
+        report["last_good"],
+        serde_json::json!({"byte": 90, "line": 5})
+    );
+    assert_eq!(
+        report["parsed"],
+        serde_json::json!({"bytes": 90, "lines": 5})
+    );
+    assert_eq!(
+        report["remaining"],
+        serde_json::json!({"bytes": 144, "lines": 6})
+    );
+    assert_eq!(
+        report["unparsed_excerpt"],
+        serde_json::json!([
+            {"line": 6, "text": ""},
+            {"line": 7, "text": "_incomplete_loop"}
+        ])
+    );
+
+    let error = &report["error"];
+    assert_eq!(error["code"], "E0005");
+    assert_eq!(error["line"], 7);
+    assert_eq!(error["column"], 1);
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Expected double_quote_string"));
+    let formatted = error["formatted"].as_str().unwrap();
+    assert!(formatted.starts_with("Parse error in tests/test_data/invalid_syntax.star at l7:c1"));
+    assert!(
+        !formatted.contains('\u{1b}'),
+        "the formatted error should be plain text"
+    );
+}
+
+#[test]
+fn test_ustar_parse_debugger_unicode_encoding() {
+    use std::path::Path;
+
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-parse-debugger"])
+        .output()
+        .expect("Failed to build ustar-parse-debugger");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-parse-debugger: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let path = temp_dir.path().join("unicode.star");
+    std::fs::write(&path, "data_unicode\n_sample.name café\n_sample.id 1\n")
+        .expect("Failed to write file");
+
+    // The probe parser is the one parse chooses, so a valid UTF-8 file isn't a failure
+    let output = Command::new(Path::new("../target/debug/ustar-parse-debugger"))
+        .args(&["--encoding", "unicode", "--format", "json"])
+        .arg(&path)
+        .output()
+        .expect("Failed to run ustar-parse-debugger");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["parses"], true);
+    assert_eq!(report["encoding"], "unicode");
+}
+
+#[test]
+fn test_ustar_parse_debugger_batch() {
+    use std::path::Path;
+
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-parse-debugger"])
+        .output()
+        .expect("Failed to build ustar-parse-debugger");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-parse-debugger: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    let report_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let output = Command::new(Path::new("../target/debug/ustar-parse-debugger"))
+        .args(&["--batch", "tests/test_data/batch", "--report-dir"])
+        .arg(report_dir.path())
+        .output()
+        .expect("Failed to run ustar-parse-debugger");
+
+    // bad.str fails, so the batch does
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .filter(|row: &Vec<&str>| !row.is_empty())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            vec!["file", "size", "outcome", "failure", "line", "last", "good", "line"],
+            vec!["bad.str", "60", "failed", "3", "2"],
+            vec!["good.star", "22", "ok", "-", "-"],
+            vec!["nested/loop.cif", "52", "ok", "-", "-"],
+            vec!["files:", "3", "passed:", "2", "failed:", "1"],
+        ]
+    );
+
+    // only the failing file has a report, notes.txt isn't debugged
+    let reports: Vec<_> = std::fs::read_dir(report_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(reports, vec!["bad.str.txt"]);
+    let report = std::fs::read_to_string(report_dir.path().join("bad.str.txt")).unwrap();
+    assert!(report.contains("✓ Found successful parse at byte position: 20"));
+    assert!(report.contains("File: tests/test_data/batch/bad.str"));
+}
+
+#[test]
+fn test_ustar_parse_debugger_large_failing_file() {
+    use std::fmt::Write as _;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    // Release, a debug build parses too slowly for the timing to mean much
+    let build_output = Command::new("cargo")
+        .args(&["build", "--release", "--bin", "ustar-parse-debugger"])
+        .output()
+        .expect("Failed to build ustar-parse-debugger");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-parse-debugger: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    // A 10MB file whose save frame is never closed, so the last good parse ends near the start
+    let mut input = String::from(
+        "data_big\n_entry.id 1\n\nsave_peaks\n_peaks.count 1\nloop_\n_peak.id _peak.shift _peak.name\n",
+    );
+    let mut row = 0;
+    while input.len() < 10_000_000 {
+        writeln!(
+            input,
+            "{} {}.{:03} peak_{}",
+            row,
+            row / 1000,
+            row % 1000,
+            row
+        )
+        .unwrap();
+        row += 1;
+    }
+    input.push_str("stop_\n");
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let path = temp_dir.path().join("large_failing.star");
+    std::fs::write(&path, input).expect("Failed to write large file");
+
+    let start = Instant::now();
+    let output = Command::new(Path::new("../target/release/ustar-parse-debugger"))
+        .arg(&path)
+        .output()
+        .expect("Failed to run ustar-parse-debugger");
+    let elapsed = start.elapsed();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    assert!(
+        stdout.contains("Found successful parse at byte position: 20"),
+        "Should find the parse ending before the save frame"
+    );
+    assert!(
+        elapsed < Duration::from_secs(60),
+        "Finding the last good parse took {:?}",
+        elapsed
+    );
+}
+
 #[test]
 fn test_ustar_grammar_railroad_svg_generation() {
     use std::fs;
@@ -565,3 +1082,571 @@ fn test_ustar_grammar_railroad_svg_generation() {
     // Clean up
     fs::remove_dir_all(&temp_dir).expect("Failed to clean up temp directory");
 }
+
+/// Build ustar-validate and run it with `args`
+fn run_ustar_validate(args: &[&str]) -> std::process::Output {
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-validate"])
+        .output()
+        .expect("Failed to build ustar-validate");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-validate: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    Command::new("../target/debug/ustar-validate")
+        .args(args)
+        .output()
+        .expect("Failed to run ustar-validate")
+}
+
+#[test]
+fn test_ustar_validate_passing_file() {
+    let output = run_ustar_validate(&["tests/test_data/simple_star_file.star"]);
+
+    assert!(
+        output.status.success(),
+        "ustar-validate should pass a valid file"
+    );
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    assert_eq!(
+        stdout,
+        "tests/test_data/simple_star_file.star: ok\n\nfiles: 1 passed: 1 failed: 0\n"
+    );
+}
+
+#[test]
+fn test_ustar_validate_failing_file() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let failing = temp_dir.path().join("duplicates.star");
+    std::fs::write(
+        &failing,
+        "data_test\n_entry.id 1\n_entry.link $nowhere\n_Entry.ID 2\n",
+    )
+    .expect("Failed to write test file");
+
+    let output = run_ustar_validate(&[
+        "tests/test_data/simple_star_file.star",
+        failing.to_str().unwrap(),
+    ]);
+
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "ustar-validate should fail if any file fails"
+    );
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    assert!(stdout.contains("duplicates.star: 2 issue(s)"));
+    assert!(stdout.contains("l3:c13 E0018 frame code $nowhere"));
+    assert!(stdout.contains("l4:c1 E0016 duplicate data name _Entry.ID"));
+    assert!(stdout.ends_with("files: 2 passed: 1 failed: 1\n"));
+}
+
+#[test]
+fn test_ustar_validate_dialect_and_directories() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let nested = temp_dir.path().join("nested");
+    std::fs::create_dir(&nested).expect("Failed to create nested directory");
+    std::fs::write(nested.join("outside.nef"), "data_test\n_entry.id 1\n")
+        .expect("Failed to write test file");
+    std::fs::write(nested.join("notes.txt"), "not a STAR file").expect("Failed to write notes");
+    let dir = temp_dir.path().to_str().unwrap();
+
+    // directories need --recursive
+    let output = run_ustar_validate(&[dir]);
+    assert_eq!(output.status.code(), Some(2));
+
+    // without a dialect data outside save frames is fine, NEF keeps all data in save frames
+    let output = run_ustar_validate(&["--recursive", dir]);
+    assert!(output.status.success());
+    let output = run_ustar_validate(&["--recursive", "--dialect", "nef", dir]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    assert!(stdout.contains("E0011"));
+    assert!(!stdout.contains("notes.txt"));
+}
+
+#[test]
+fn test_ustar_validate_json_output() {
+    let output = run_ustar_validate(&[
+        "--format",
+        "json",
+        "tests/test_data/simple_star_file.star",
+        "tests/test_data/invalid_syntax.star",
+        "tests/test_data/comprehensive_example.star",
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    let report: serde_json::Value = serde_json::from_str(&stdout).expect("Output should be JSON");
+    assert_eq!(report["passed"], 1);
+    assert_eq!(report["failed"], 2);
+
+    assert_snapshot_gz(
+        "binary_integration_tests__ustar_validate_json_output",
+        &stdout,
+    );
+}
+
+/// The optional features of these tests, passed on when building the binaries they run so
+/// ustar-benchmark is built the same way by every test
+const BUILD_FEATURES: &[&str] = if cfg!(feature = "alloc-tracking") {
+    &["--features", "alloc-tracking"]
+} else {
+    &[]
+};
+
+/// Build ustar-benchmark and run it with `args`
+fn run_ustar_benchmark(args: &[&str]) -> std::process::Output {
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-benchmark"])
+        .args(BUILD_FEATURES)
+        .output()
+        .expect("Failed to build ustar-benchmark");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-benchmark: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    Command::new("../target/debug/ustar-benchmark")
+        .args(args)
+        .output()
+        .expect("Failed to run ustar-benchmark")
+}
+
+#[test]
+fn test_ustar_benchmark_json_output() {
+    let output = run_ustar_benchmark(&[
+        "--format",
+        "json",
+        "--iterations",
+        "5",
+        "--warmup",
+        "1",
+        "tests/test_data/comprehensive_example.star",
+    ]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    let results: serde_json::Value = serde_json::from_str(&stdout).expect("Output should be JSON");
+    let results = results
+        .as_object()
+        .expect("the results should be an object");
+    let keys: Vec<&str> = results.keys().map(String::as_str).collect();
+    assert_eq!(
+        keys,
+        vec![
+            "average_bytes_per_sec",
+            "average_ms",
+            "baseline_ns_per_byte",
+            "baseline_ratio",
+            "file",
+            "iterations",
+            "max_ms",
+            "median_ms",
+            "min_ms",
+            "ns_per_byte",
+            "p95_ms",
+            "p99_ms",
+            "peak_bytes_per_sec",
+            "size_bytes",
+            "total_ms",
+            "warmup",
+        ]
+    );
+    assert_eq!(
+        results["file"],
+        "tests/test_data/comprehensive_example.star"
+    );
+    assert_eq!(results["size_bytes"], 4566);
+    assert_eq!(results["iterations"], 5);
+    assert_eq!(results["warmup"], 1);
+    for (key, value) in results {
+        if key.ends_with("_ms") || key.contains("per_") || key == "baseline_ratio" {
+            assert!(
+                value.as_f64().is_some_and(|number| number > 0.0),
+                "{} should be a positive number, found {}",
+                key,
+                value
+            );
+        }
+    }
+    assert!(results["min_ms"].as_f64() <= results["max_ms"].as_f64());
+}
+
+#[test]
+fn test_ustar_benchmark_csv_output() {
+    let output = run_ustar_benchmark(&[
+        "--format",
+        "csv",
+        "--iterations",
+        "3",
+        "--warmup",
+        "0",
+        "tests/test_data/comprehensive_example.star",
+    ]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    let header: Vec<&str> = lines[0].split(',').collect();
+    let values: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(header.len(), values.len());
+    assert_eq!(&header[..4], ["file", "size_bytes", "iterations", "warmup"]);
+    assert_eq!(
+        &values[..4],
+        [
+            "tests/test_data/comprehensive_example.star",
+            "4566",
+            "3",
+            "0"
+        ]
+    );
+
+    // the text-only comparisons can't be combined with a machine readable format
+    let output = run_ustar_benchmark(&[
+        "--format",
+        "json",
+        "--walk",
+        "tests/test_data/comprehensive_example.star",
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+#[cfg(not(feature = "alloc-tracking"))]
+fn test_ustar_benchmark_heap_needs_alloc_tracking() {
+    for flag in ["--heap", "--memory", "--mmap"] {
+        let output = run_ustar_benchmark(&[flag, "tests/test_data/comprehensive_example.star"]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("alloc-tracking feature"), "{}", stderr);
+    }
+}
+
+#[test]
+#[cfg(feature = "alloc-tracking")]
+fn test_ustar_benchmark_memory_phases() {
+    // the example and the example twice over, as two data blocks
+    let example = std::fs::read_to_string("tests/test_data/comprehensive_example.star").unwrap();
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let doubled = temp_dir.path().join("doubled.star");
+    std::fs::write(&doubled, format!("{}\n{}", example, example)).unwrap();
+
+    let phases = |path: &str| -> Vec<serde_json::Value> {
+        let output = run_ustar_benchmark(&[
+            "--format",
+            "json",
+            "--memory",
+            "--iterations",
+            "1",
+            "--warmup",
+            "0",
+            path,
+        ]);
+        assert!(output.status.success());
+        let results: serde_json::Value =
+            serde_json::from_slice(&output.stdout).expect("Output should be JSON");
+        results["memory"]
+            .as_array()
+            .expect("--memory should add the phases")
+            .clone()
+    };
+    let single = phases("tests/test_data/comprehensive_example.star");
+    let double = phases(doubled.to_str().unwrap());
+
+    let names: Vec<_> = single.iter().map(|phase| phase["phase"].clone()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "pest parse",
+            "mutable pair",
+            "decompose strings",
+            "sas walk"
+        ]
+    );
+    for (one, two) in single.iter().zip(&double) {
+        for key in ["peak_bytes", "allocations"] {
+            let (one, two) = (one[key].as_u64().unwrap(), two[key].as_u64().unwrap());
+            assert!(one > 0, "{} should be counted", key);
+            assert!(two >= one, "{} should grow with the input", key);
+        }
+        assert!(one["bytes_per_input_byte"].as_f64().unwrap() > 0.0);
+    }
+}
+
+/// Records the events of a walk without positions, delimiters or comments, which JSON doesn't keep
+#[derive(Default)]
+struct EventRecorder {
+    events: Vec<String>,
+}
+
+impl EventRecorder {
+    fn record(&mut self, event: String) -> WalkControl {
+        self.events.push(event);
+        WalkControl::Continue
+    }
+}
+
+impl SASContentHandler for EventRecorder {
+    fn start_stream(&mut self, _name: Option<&str>) -> WalkControl {
+        self.record("start_stream".to_string())
+    }
+    fn end_stream(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_stream".to_string())
+    }
+    fn start_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_global".to_string())
+    }
+    fn end_global(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_global".to_string())
+    }
+    fn start_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_data {}", name))
+    }
+    fn end_data(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_data {}", name))
+    }
+    fn start_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("start_saveframe {}", name))
+    }
+    fn end_saveframe(&mut self, _position: LineColumn, name: &str) -> WalkControl {
+        self.record(format!("end_saveframe {}", name))
+    }
+    fn start_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("start_loop".to_string())
+    }
+    fn end_loop(&mut self, _position: LineColumn) -> WalkControl {
+        self.record("end_loop".to_string())
+    }
+    fn loop_definition(
+        &mut self,
+        _position: LineColumn,
+        tags: &[&str],
+        nesting_level: usize,
+    ) -> WalkControl {
+        self.record(format!(
+            "loop_definition {} {}",
+            nesting_level,
+            tags.join(" ")
+        ))
+    }
+    fn start_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("start_loop_row {}", row_index))
+    }
+    fn end_loop_row(&mut self, _position: LineColumn, row_index: usize) -> WalkControl {
+        self.record(format!("end_loop_row {}", row_index))
+    }
+    fn comment(&mut self, _position: LineColumn, _text: &str) -> WalkControl {
+        WalkControl::Continue
+    }
+    fn data(
+        &mut self,
+        tag: &str,
+        _tag_position: LineColumn,
+        value: &str,
+        _value_position: LineColumn,
+        _delimiter: ValueDelimiter,
+        loop_level: usize,
+    ) -> WalkControl {
+        self.record(format!("data {} {} {:?}", loop_level, tag, value))
+    }
+}
+
+/// The events of walking the STAR file at `path`
+fn star_events(path: &std::path::Path) -> Vec<String> {
+    let mut config = ustar_parser::default_config();
+    config.insert(
+        ustar_parser::ConfigKey::Encoding,
+        ustar_parser::ConfigValue::Encoding(ustar_parser::EncodingMode::Unicode),
+    );
+    let input = std::fs::read_to_string(path).expect("Failed to read STAR file");
+    let mut recorder = EventRecorder::default();
+    ustar_parser::walk(&input, &config, &mut recorder).expect("Failed to walk STAR file");
+    recorder.events
+}
+
+/// Build ustar-convert and run it with `args`, checking it succeeds
+fn run_ustar_convert(args: &[&str]) {
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-convert"])
+        .output()
+        .expect("Failed to build ustar-convert");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-convert: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    let output = Command::new("../target/debug/ustar-convert")
+        .args(args)
+        .output()
+        .expect("Failed to run ustar-convert");
+    assert!(
+        output.status.success(),
+        "ustar-convert {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_ustar_convert_round_trip() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let json = temp_dir.path().join("converted.json");
+    let star = temp_dir.path().join("converted.star");
+
+    for name in ["loop1.str", "bmr25679_3.str", "bmr18587_3.str", "3fke.cif"] {
+        let original =
+            std::path::Path::new("../ustar-parser/tests/test_data/sas_test_files").join(name);
+        run_ustar_convert(&[
+            original.to_str().unwrap(),
+            "--encoding",
+            "unicode",
+            "--pretty",
+            "--output",
+            json.to_str().unwrap(),
+        ]);
+        run_ustar_convert(&[json.to_str().unwrap(), "--output", star.to_str().unwrap()]);
+
+        assert_eq!(
+            star_events(&original),
+            star_events(&star),
+            "round trip of {}",
+            name
+        );
+
+        run_ustar_convert(&[
+            json.to_str().unwrap(),
+            "--style",
+            "pynmrstar",
+            "--output",
+            star.to_str().unwrap(),
+        ]);
+        assert_eq!(
+            star_events(&original),
+            star_events(&star),
+            "round trip of {} in pynmrstar style",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_ustar_convert_rename_map() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let original = temp_dir.path().join("old.nef");
+    let map = temp_dir.path().join("renames.csv");
+    let renamed = temp_dir.path().join("new.nef");
+    let json = temp_dir.path().join("new.json");
+
+    let star = "\u{FEFF}data_test\nsave_nef_nmr_meta_data\n   \
+                _nef_nmr_meta_data.sf_category nef_nmr_meta_data   # category\n   \
+                _nef_nmr_meta_data.program_name  old\n   loop_\n      \
+                _nef_sequence.index   _nef_sequence.residue_name\n      1 ALA\n   stop_\nsave_\n";
+    std::fs::write(&original, star).unwrap();
+    std::fs::write(
+        &map,
+        "old,new\n\"_nef_nmr_meta_data.program_name\", _nef_nmr_meta_data.software_name\n\n\
+         _NEF_SEQUENCE.RESIDUE_NAME,_nef_sequence.residue\n_missing.tag,_found.tag\n",
+    )
+    .unwrap();
+
+    run_ustar_convert(&[
+        original.to_str().unwrap(),
+        "--to",
+        "star",
+        "--rename-map",
+        map.to_str().unwrap(),
+        "--output",
+        renamed.to_str().unwrap(),
+    ]);
+    assert_eq!(
+        std::fs::read_to_string(&renamed).unwrap(),
+        star.replace("program_name", "software_name")
+            .replace("residue_name", "residue")
+    );
+
+    run_ustar_convert(&[
+        original.to_str().unwrap(),
+        "--rename-map",
+        map.to_str().unwrap(),
+        "--rename-categories",
+        "--output",
+        json.to_str().unwrap(),
+    ]);
+    let json = std::fs::read_to_string(&json).unwrap();
+    assert!(json.contains("_nef_nmr_meta_data.software_name"));
+    assert!(json.contains("_nef_sequence.residue\""));
+    assert!(!json.contains("program_name"));
+}
+
+/// Build ustar-diff and run it with `args`
+fn run_ustar_diff(args: &[&str]) -> std::process::Output {
+    let build_output = Command::new("cargo")
+        .args(&["build", "--bin", "ustar-diff"])
+        .output()
+        .expect("Failed to build ustar-diff");
+
+    if !build_output.status.success() {
+        panic!(
+            "Failed to build ustar-diff: {}",
+            String::from_utf8_lossy(&build_output.stderr)
+        );
+    }
+
+    Command::new("../target/debug/ustar-diff")
+        .args(args)
+        .output()
+        .expect("Failed to run ustar-diff")
+}
+
+#[test]
+fn test_ustar_diff() {
+    let (a, b) = (
+        "tests/test_data/diff/shifts_a.nef",
+        "tests/test_data/diff/shifts_b.nef",
+    );
+    let output = run_ustar_diff(&[a, a]);
+    assert!(
+        output.status.success(),
+        "a file has no differences to itself"
+    );
+    assert!(output.stdout.is_empty());
+
+    let key = "_nef_chemical_shift.chain_code,_nef_chemical_shift.sequence_code,\
+               _nef_chemical_shift.atom_name";
+    let output = run_ustar_diff(&[a, b, "--key", key]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    assert_eq!(
+        stdout,
+        "~ data_nef_shifts/save_nef_nmr_meta_data/_nef_nmr_meta_data.format_version 1.0 -> 1.1 \
+         (l4:c4, l5:c5)\n\
+         - data_nef_shifts/save_nef_chemical_shift_list_1/_nef_chemical_shift.chain_code[2] \
+         A 2 N 120.3 (l13:c7)\n\
+         ~ data_nef_shifts/save_nef_chemical_shift_list_1/_nef_chemical_shift.value[3] 4.20 -> \
+         4.25 (l14:c14, l16:c16)\n\
+         + data_nef_shifts/save_nef_chemical_shift_list_1/_nef_chemical_shift.chain_code[3] \
+         A 4 H 7.95 (l18:c9)\n"
+    );
+
+    let output = run_ustar_diff(&[a, b, "--key", key, "--format", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).expect("Failed to parse stdout");
+    let report: serde_json::Value = serde_json::from_str(&stdout).expect("Output should be JSON");
+    assert_eq!(report["differences"].as_array().unwrap().len(), 4);
+    assert_snapshot_gz("binary_integration_tests__ustar_diff_json", &stdout);
+
+    let output = run_ustar_diff(&[a, "tests/test_data/invalid_syntax.star"]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -3,7 +3,7 @@
 
 use std::path::PathBuf;
 use std::sync::Arc;
-use ustar_test_utils::MockHttpClient;
+use ustar_test_utils::{MockHttpClient, MockResponse};
 use ustar_tools::downloader_common::{
     DataSource, DownloadError, DownloaderConfig, GenericDownloader,
 };
@@ -95,8 +95,10 @@ fn test_mock_pdb_downloader_basic_functionality() {
 #[test]
 fn test_generic_downloader_with_mock_data_source() {
     let data_source = MockPdbDataSource::new_with_fixtures();
+    let client = Arc::clone(&data_source.http_client);
 
     let temp_dir = std::env::temp_dir().join("mock_downloader_test");
+    let _ = std::fs::remove_dir_all(&temp_dir);
     let config = DownloaderConfig::new()
         .output_dir(temp_dir.to_str().unwrap())
         .verbose(false)
@@ -127,6 +129,21 @@ fn test_generic_downloader_with_mock_data_source() {
     let ids: Vec<&String> = batch.iter().map(|(id, _)| id).collect();
     assert_ne!(ids[0], ids[1], "Should download different files");
 
+    // The holdings are fetched once, then each entry tried once: a missing entry (404) isn't
+    // retried, and another is downloaded in its place
+    let requests = client.requests();
+    assert_eq!(
+        requests[0],
+        "https://files.rcsb.org/pub/pdb/holdings/current_holdings.txt"
+    );
+    for url in &requests {
+        assert_eq!(client.request_count(url), 1, "{} requested again", url);
+    }
+    for id in ids {
+        let url = format!("https://files.rcsb.org/download/{}.cif", id);
+        assert!(requests.contains(&url));
+    }
+
     // Clean up
     std::fs::remove_dir_all(&temp_dir).unwrap();
 }
@@ -321,3 +338,444 @@ fn test_mock_cod_downloader() {
     // Clean up
     std::fs::remove_dir_all(&temp_dir).unwrap();
 }
+
+// Mock data source listing numbered entries, for batch downloads
+struct NumberedDataSource {
+    http_client: Arc<MockHttpClient>,
+}
+
+const NUMBERED_LIST_URL: &str = "https://example.org/entries.txt";
+
+fn numbered_entry_url(entry_id: &str) -> String {
+    format!("https://example.org/entries/{}.cif", entry_id)
+}
+
+/// A client listing entries 1 to `count`, all but those in `missing` downloadable, each
+/// answered after `delay`
+fn numbered_client(count: usize, missing: &[usize], delay: std::time::Duration) -> MockHttpClient {
+    let ids: Vec<String> = (1..=count).map(|n| format!("{:04}", n)).collect();
+    let available: Vec<String> = (1..=count)
+        .filter(|n| !missing.contains(n))
+        .map(|n| format!("{:04}", n))
+        .collect();
+    MockHttpClient::new()
+        .with_response(NUMBERED_LIST_URL, &ids.join("\n"))
+        .with_pattern(&numbered_entry_url("*"), move |url| {
+            let id = url.rsplit('/').next().unwrap().trim_end_matches(".cif");
+            if available.iter().any(|available| available == id) {
+                MockResponse::text(&format!("data_{}\n_entry.id {}\n", id, id))
+            } else {
+                MockResponse::Status(404)
+            }
+        })
+        .with_latency(delay)
+}
+
+impl NumberedDataSource {
+    fn new(count: usize, missing: &[usize], delay: std::time::Duration) -> Self {
+        Self {
+            http_client: Arc::new(numbered_client(count, missing, delay)),
+        }
+    }
+}
+
+impl DataSource for NumberedDataSource {
+    fn get_available_entries(&self) -> Result<Vec<String>, DownloadError> {
+        let text = self.http_client.get(NUMBERED_LIST_URL)?;
+        Ok(text.lines().map(str::to_string).collect())
+    }
+
+    fn download_entry(
+        &self,
+        entry_id: &str,
+        output_path: &PathBuf,
+    ) -> Result<PathBuf, DownloadError> {
+        let content = self.http_client.get(&numbered_entry_url(entry_id))?;
+        std::fs::write(output_path, content)?;
+        Ok(output_path.clone())
+    }
+
+    fn entry_url(&self, entry_id: &str) -> Option<String> {
+        Some(numbered_entry_url(entry_id))
+    }
+}
+
+fn batch_config(output_dir: &std::path::Path, concurrency: usize) -> DownloaderConfig {
+    DownloaderConfig::new()
+        .output_dir(output_dir)
+        .verbose(false)
+        .file_extension("cif")
+        .concurrency(concurrency)
+}
+
+#[test]
+fn test_batch_downloads_overlap() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let data_source = NumberedDataSource::new(12, &[], std::time::Duration::from_millis(50));
+    let client = data_source.http_client.clone();
+    let downloader = GenericDownloader::new(batch_config(temp_dir.path(), 4), data_source);
+
+    let report = downloader.download_batch(8, 7).unwrap();
+    assert_eq!(report.downloaded.len(), 8);
+    assert!(report.failed.is_empty());
+    assert!(
+        client.max_in_flight() > 1,
+        "downloads should run at the same time"
+    );
+    assert!(
+        client.max_in_flight() <= 4,
+        "no more than 4 downloads should run at once, found {}",
+        client.max_in_flight()
+    );
+}
+
+#[test]
+fn test_batch_selection_is_stable_across_concurrency() {
+    // entries 3 and 5 fail, so later entries are tried in their place
+    let batch_ids = |concurrency: usize| {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_source = NumberedDataSource::new(20, &[3, 5], std::time::Duration::ZERO);
+        let downloader =
+            GenericDownloader::new(batch_config(temp_dir.path(), concurrency), data_source);
+        let report = downloader.download_batch(10, 42).unwrap();
+        let downloaded: Vec<String> = report.downloaded.into_iter().map(|(id, _)| id).collect();
+        let failed: Vec<String> = report.failed.into_iter().map(|(id, _)| id).collect();
+        (downloaded, failed)
+    };
+
+    let (serial, serial_failed) = batch_ids(1);
+    assert_eq!(serial.len(), 10);
+    for concurrency in [2, 4, 8] {
+        let (downloaded, failed) = batch_ids(concurrency);
+        assert_eq!(downloaded, serial, "concurrency {}", concurrency);
+        assert_eq!(failed, serial_failed, "concurrency {}", concurrency);
+    }
+}
+
+#[test]
+fn test_batch_reports_failures_and_existing_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    std::fs::write(temp_dir.path().join("0002.cif"), "data_0002\n").unwrap();
+    let data_source = NumberedDataSource::new(4, &[1], std::time::Duration::ZERO);
+    let downloader = GenericDownloader::new(batch_config(temp_dir.path(), 2), data_source);
+
+    let report = downloader.download_batch(4, 1).unwrap();
+    let mut downloaded: Vec<&str> = report
+        .downloaded
+        .iter()
+        .map(|(id, _)| id.as_str())
+        .collect();
+    downloaded.sort();
+    assert_eq!(downloaded, ["0003", "0004"]);
+    assert_eq!(report.existing, ["0002"]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, "0001");
+}
+
+#[test]
+fn test_batch_respects_rate_limit() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let data_source = NumberedDataSource::new(6, &[], std::time::Duration::ZERO);
+    let config = batch_config(temp_dir.path(), 4).max_requests_per_second(20.0);
+    let downloader = GenericDownloader::new(config, data_source);
+
+    // the list and 5 entries: 6 request starts at least 50ms apart
+    let start = std::time::Instant::now();
+    let report = downloader.download_batch(5, 3).unwrap();
+    assert_eq!(report.downloaded.len(), 5);
+    assert!(
+        start.elapsed() >= std::time::Duration::from_millis(250),
+        "6 requests at 20 per second should take at least 250ms, took {:?}",
+        start.elapsed()
+    );
+}
+
+#[test]
+fn test_second_batch_skips_cached_entries() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let client = NumberedDataSource::new(6, &[], std::time::Duration::ZERO).http_client;
+    let downloader = || {
+        let data_source = NumberedDataSource {
+            http_client: client.clone(),
+        };
+        GenericDownloader::new(batch_config(temp_dir.path(), 2), data_source)
+    };
+
+    let first_ids: Vec<String> = downloader()
+        .download_batch(3, 1)
+        .unwrap()
+        .downloaded
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    assert!(temp_dir.path().join("manifest.json").exists());
+
+    // all six entries are wanted, so the second run overlaps the first
+    let second = downloader();
+    let report = second.download_batch(6, 2).unwrap();
+    let mut existing = report.existing.clone();
+    existing.sort();
+    let mut expected = first_ids.clone();
+    expected.sort();
+    assert_eq!(existing, expected);
+    assert_eq!(report.downloaded.len(), 3);
+    for id in &first_ids {
+        assert_eq!(
+            client.request_count(&numbered_entry_url(id)),
+            1,
+            "{} should only be fetched by the first run",
+            id
+        );
+    }
+    assert!(second.verify_manifest().unwrap().is_ok());
+}
+
+#[test]
+fn test_manifest_records_and_verifies_downloads() {
+    use ustar_tools::download_manifest::Manifest;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let make_downloader = |config: DownloaderConfig| {
+        GenericDownloader::new(
+            config,
+            NumberedDataSource::new(3, &[], std::time::Duration::ZERO),
+        )
+    };
+    let downloader = make_downloader(batch_config(temp_dir.path(), 1));
+    downloader.download_batch(3, 5).unwrap();
+
+    let manifest = Manifest::load(temp_dir.path()).unwrap();
+    let entry = &manifest.entries["0001"];
+    let content = "data_0001\n_entry.id 0001\n";
+    assert_eq!(entry.path, PathBuf::from("0001.cif"));
+    assert_eq!(entry.size, content.len() as u64);
+    assert_eq!(entry.sha1, "09965e6d5bd3acd47f2aad6710370a7c8422d4f6");
+    assert_eq!(entry.url, Some(numbered_entry_url("0001")));
+    assert!(entry.downloaded_at > 0);
+
+    // change one file and remove another
+    std::fs::write(temp_dir.path().join("0002.cif"), "data_changed\n").unwrap();
+    std::fs::remove_file(temp_dir.path().join("0003.cif")).unwrap();
+    let check = downloader.verify_manifest().unwrap();
+    let ids = |entries: &[ustar_tools::download_manifest::ManifestEntry]| {
+        entries
+            .iter()
+            .map(|entry| entry.id.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&check.mismatched), ["0002"]);
+    assert_eq!(ids(&check.missing), ["0003"]);
+
+    // a new run fetches the changed and missing files again
+    let report = make_downloader(batch_config(temp_dir.path(), 1))
+        .download_batch(3, 5)
+        .unwrap();
+    assert_eq!(report.existing, ["0001"]);
+    assert_eq!(report.downloaded.len(), 2);
+    assert!(downloader.verify_manifest().unwrap().is_ok());
+}
+
+#[test]
+fn test_invalid_downloads_are_rejected_and_replaced() {
+    use ustar_tools::downloader_common::REJECTED_DIRECTORY;
+
+    // the entry the seed draws first is served as an HTML error page
+    let probe_dir = tempfile::tempdir().unwrap();
+    let first = GenericDownloader::new(
+        batch_config(probe_dir.path(), 1),
+        NumberedDataSource::new(5, &[], std::time::Duration::ZERO),
+    )
+    .download_batch(1, 9)
+    .unwrap()
+    .downloaded
+    .remove(0)
+    .0;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let client = numbered_client(5, &[], std::time::Duration::ZERO).with_response(
+        &numbered_entry_url(&first),
+        "<html><body><h1>Service Unavailable</h1></body></html>\n",
+    );
+    let downloader = GenericDownloader::new(
+        batch_config(temp_dir.path(), 2).validate_parse(true),
+        NumberedDataSource {
+            http_client: Arc::new(client),
+        },
+    );
+
+    let report = downloader.download_batch(4, 9).unwrap();
+    assert_eq!(report.downloaded.len(), 4, "a replacement should be drawn");
+    assert!(report.downloaded.iter().all(|(id, _)| *id != first));
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, first);
+    assert!(matches!(
+        report.failed[0].1,
+        DownloadError::InvalidContent(_)
+    ));
+
+    let name = format!("{}.cif", first);
+    assert!(!temp_dir.path().join(&name).exists());
+    assert!(temp_dir
+        .path()
+        .join(REJECTED_DIRECTORY)
+        .join(&name)
+        .exists());
+    let manifest = ustar_tools::download_manifest::Manifest::load(temp_dir.path()).unwrap();
+    assert!(!manifest.entries.contains_key(&first));
+    assert_eq!(manifest.entries.len(), 4);
+}
+
+#[test]
+fn test_download_named_ids() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let downloader = GenericDownloader::new(
+        batch_config(temp_dir.path(), 2),
+        NumberedDataSource::new(6, &[], std::time::Duration::ZERO),
+    );
+
+    let ids: Vec<String> = ["0005", "0002", "0009", "0005"]
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    let report = downloader.download_ids(&ids).unwrap();
+
+    let downloaded: Vec<&str> = report
+        .downloaded
+        .iter()
+        .map(|(id, _)| id.as_str())
+        .collect();
+    assert_eq!(downloaded, ["0005", "0002"]);
+    assert_eq!(report.not_found, ["0009"]);
+    assert!(report.failed.is_empty());
+
+    let mut files: Vec<String> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["0002.cif", "0005.cif", "manifest.json"]);
+}
+
+#[test]
+fn test_download_forced_ids_skip_the_lookup() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let data_source = NumberedDataSource::new(3, &[], std::time::Duration::ZERO);
+    let client = data_source.http_client.clone();
+    let downloader =
+        GenericDownloader::new(batch_config(temp_dir.path(), 1).force(true), data_source);
+
+    let ids = vec!["0001".to_string(), "0009".to_string()];
+    let report = downloader.download_ids(&ids).unwrap();
+    assert_eq!(client.request_count(NUMBERED_LIST_URL), 0);
+    assert_eq!(report.downloaded.len(), 1);
+    assert!(report.not_found.is_empty());
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, "0009");
+    assert!(temp_dir.path().join("0001.cif").exists());
+}
+
+#[test]
+fn test_requested_ids_from_options_and_file() {
+    use clap::Parser;
+    use ustar_tools::downloader_common::CommonDownloaderCli;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let ids_file = temp_dir.path().join("ids.txt");
+    std::fs::write(&ids_file, "# from a bug report\n1abc\n\n  2def  \n").unwrap();
+
+    let cli = CommonDownloaderCli::try_parse_from([
+        "download",
+        "--id",
+        "7abc",
+        "--ids-file",
+        ids_file.to_str().unwrap(),
+    ])
+    .unwrap();
+    assert_eq!(cli.requested_ids().unwrap(), ["7abc", "1abc", "2def"]);
+}
+
+/// A mock client as an `HttpClient`, keeping the HTTP status of failed requests
+struct MockClient(MockHttpClient);
+
+fn mock_error(error: ustar_test_utils::MockHttpError) -> DownloadError {
+    match error.status {
+        Some(status) => DownloadError::HttpStatus {
+            status,
+            url: error.url,
+        },
+        None => DownloadError::Connection {
+            message: error.message,
+            url: error.url,
+        },
+    }
+}
+
+impl ustar_tools::downloader_common::HttpClient for MockClient {
+    fn get(&self, url: &str) -> Result<String, DownloadError> {
+        self.0.request(url).map_err(mock_error)
+    }
+
+    fn get_bytes(&self, url: &str) -> Result<Vec<u8>, DownloadError> {
+        self.0.request_bytes(url).map_err(mock_error)
+    }
+}
+
+/// A PDBe source whose search lists 1abc and 2def, with 1abc served gzipped
+fn mock_pdbe_source() -> ustar_tools::pdbe_source::PdbeSource {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use ustar_tools::pdbe_source::{PdbeSource, PDBE_ENTRIES_URL, PDBE_MMCIF_URL};
+
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"data_1ABC\n_entry.id 1ABC\n").unwrap();
+    let client = MockHttpClient::new()
+        .with_response(
+            PDBE_ENTRIES_URL,
+            r#"{"response": {"numFound": 3, "docs": [{"pdb_id": "2def"}, {"pdb_id": "1abc"}, {"pdb_id": "1abc"}]}}"#,
+        )
+        .with_binary_response(
+            &format!("{}/1abc.cif.gz", PDBE_MMCIF_URL),
+            encoder.finish().unwrap(),
+        );
+    PdbeSource::with_client(false, Arc::new(MockClient(client)))
+}
+
+#[test]
+fn test_pdbe_source_decompresses_entries() {
+    let data_source = mock_pdbe_source();
+    assert_eq!(
+        data_source.get_available_entries().unwrap(),
+        ["1abc", "2def"]
+    );
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let config = batch_config(temp_dir.path(), 1).file_extension(data_source.file_extension());
+    let downloader = GenericDownloader::new(config, data_source);
+    let report = downloader.download_ids(&["1ABC".to_string()]).unwrap();
+
+    assert_eq!(report.downloaded.len(), 1);
+    let (id, path) = &report.downloaded[0];
+    assert_eq!(id, "1abc");
+    assert_eq!(path, &temp_dir.path().join("1abc.cif"));
+    let content = std::fs::read_to_string(path).unwrap();
+    assert!(content.starts_with("data_"), "got {:?}", content);
+}
+
+#[test]
+fn test_pdbe_source_keeps_compressed_entries() {
+    let data_source = mock_pdbe_source().compressed(true);
+    let temp_dir = tempfile::tempdir().unwrap();
+    let config = batch_config(temp_dir.path(), 1)
+        .file_extension(format!(".{}", data_source.file_extension()))
+        .validate_parse(true);
+    assert_eq!(config.file_extension, "cif.gz");
+    assert_eq!(config.file_name("1abc"), "1abc.cif.gz");
+    assert_eq!(config.entry_id("1abc.cif.gz"), Some("1abc"));
+    assert_eq!(config.entry_id("1abc.gz"), None);
+
+    let downloader = GenericDownloader::new(config, data_source);
+    let report = downloader.download_ids(&["1abc".to_string()]).unwrap();
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    let bytes = std::fs::read(temp_dir.path().join("1abc.cif.gz")).unwrap();
+    assert_eq!(bytes[..2], [0x1f, 0x8b]);
+}
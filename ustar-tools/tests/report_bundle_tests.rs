@@ -0,0 +1,124 @@
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use ustar_parser::{default_config, parse, ConfigFile, ParserConfig};
+use ustar_tools::report_bundle::{
+    self, ReportBundleError, CONFIG_ENTRY, ERROR_ENTRY, SNIPPET_ENTRY, VERSION_ENTRY,
+};
+use zip::ZipArchive;
+
+const INVALID_FILE: &str = "tests/test_data/invalid_syntax.star";
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Vec<u8> {
+    let mut entry = archive
+        .by_name(name)
+        .unwrap_or_else(|e| panic!("Bundle is missing {}: {}", name, e));
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn test_report_bundle_for_invalid_syntax() {
+    let input_path = Path::new(INVALID_FILE);
+    let content = fs::read_to_string(input_path).unwrap();
+    let config = default_config();
+    let original_error = match parse(&content, &config) {
+        Ok(_) => panic!("{} should fail to parse", INVALID_FILE),
+        Err(e) => e.to_string(),
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let bundle_path = dir.path().join("report.zip");
+    let entries = report_bundle::create(&bundle_path, input_path, &content, &config, true)
+        .expect("Failed to create report bundle");
+
+    let mut archive = ZipArchive::new(File::open(&bundle_path).unwrap()).unwrap();
+    let mut names: Vec<&str> = archive.file_names().collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            CONFIG_ENTRY,
+            ERROR_ENTRY,
+            "input/invalid_syntax.star.gz",
+            SNIPPET_ENTRY,
+            VERSION_ENTRY
+        ]
+    );
+    assert_eq!(entries.len(), 5);
+
+    // the minimized snippet is smaller but still reproduces the failure
+    let snippet = String::from_utf8(read_entry(&mut archive, SNIPPET_ENTRY)).unwrap();
+    assert!(snippet.len() < content.len());
+    let snippet_error = parse(&snippet, &config).expect_err("Snippet should still fail to parse");
+    let error: serde_json::Value =
+        serde_json::from_slice(&read_entry(&mut archive, ERROR_ENTRY)).unwrap();
+    assert!(snippet_error
+        .to_string()
+        .contains(error["message"].as_str().unwrap()));
+    assert_eq!(error["source"], INVALID_FILE);
+    assert!(original_error.contains(error["message"].as_str().unwrap()));
+    // the error is in the parser's JSON error format
+    let original = parse(&content, &config).unwrap_err();
+    assert_eq!(error["code"], original.code().as_str());
+    assert!(error["span"]["offset"].is_u64());
+    assert!(error["expected"].is_array());
+
+    let version: serde_json::Value =
+        serde_json::from_slice(&read_entry(&mut archive, VERSION_ENTRY)).unwrap();
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(version["parser_version"], ustar_parser::VERSION);
+    assert_eq!(
+        version["capabilities"]["error_formats"],
+        serde_json::json!(["Basic", "Ascii", "Fancy", "Json"])
+    );
+
+    // the configuration is a JSON configuration file that reads back as the one used
+    let bundled_config = String::from_utf8(read_entry(&mut archive, CONFIG_ENTRY)).unwrap();
+    assert_eq!(
+        ParserConfig::from_json_str(&bundled_config).unwrap(),
+        config
+    );
+
+    let mut original = String::new();
+    GzDecoder::new(&read_entry(&mut archive, "input/invalid_syntax.star.gz")[..])
+        .read_to_string(&mut original)
+        .unwrap();
+    assert_eq!(original, content);
+}
+
+#[test]
+fn test_report_bundle_excludes_input_by_default() {
+    let input_path = Path::new(INVALID_FILE);
+    let content = fs::read_to_string(input_path).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let bundle_path = dir.path().join("report.zip");
+    let entries =
+        report_bundle::create(&bundle_path, input_path, &content, &default_config(), false)
+            .unwrap();
+
+    assert_eq!(
+        entries,
+        vec![SNIPPET_ENTRY, ERROR_ENTRY, VERSION_ENTRY, CONFIG_ENTRY]
+    );
+}
+
+#[test]
+fn test_report_bundle_rejects_valid_input() {
+    let input_path = Path::new("tests/test_data/simple_star_file.star");
+    let content = fs::read_to_string(input_path).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let result = report_bundle::create(
+        &dir.path().join("report.zip"),
+        input_path,
+        &content,
+        &default_config(),
+        false,
+    );
+
+    assert!(matches!(result, Err(ReportBundleError::NoParseError)));
+}
@@ -1,11 +1,16 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str;
 use std::sync::OnceLock;
-use ustar_test_utils::assert_snapshot_gz;
+use ustar_test_utils::{
+    assert_snapshot_gz, assert_snapshot_gz_filtered, ensure_test_data_available, SnapshotFilter,
+    TestDataPolicy,
+};
 
 static DUMPER_BINARY: OnceLock<PathBuf> = OnceLock::new();
 
+ustar_test_utils::snapshot_orphan_check!("ustar_dumper_tests");
+
 /// Build the ustar-dumper binary once and return its path
 fn get_dumper_binary() -> &'static PathBuf {
     DUMPER_BINARY.get_or_init(|| {
@@ -33,13 +38,13 @@ fn get_dumper_binary() -> &'static PathBuf {
     })
 }
 
-/// Test helper to run ustar-dumper and capture output
+/// Test helper to run ustar-dumper and capture output, uncolored so it is the same on a terminal
 fn run_ustar_parser(input_file: &str) -> Result<String, Box<dyn std::error::Error>> {
     let binary_path = get_dumper_binary();
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
     let output = Command::new(binary_path)
-        .arg(input_file)
+        .args(&["--color", "never", input_file])
         .current_dir(workspace_root)
         .output()?;
 
@@ -57,7 +62,7 @@ fn run_ustar_parser_with_tree(input_file: &str) -> Result<String, Box<dyn std::e
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
     let output = Command::new(binary_path)
-        .args(&["--tree", input_file])
+        .args(&["--color", "never", "--tree", input_file])
         .current_dir(workspace_root)
         .output()?;
 
@@ -71,10 +76,19 @@ fn run_ustar_parser_with_tree(input_file: &str) -> Result<String, Box<dyn std::e
 
 /// Test helper to run ustar-dumper with stdin and capture output
 fn run_ustar_parser_stdin(input: &str) -> Result<String, Box<dyn std::error::Error>> {
+    run_ustar_parser_stdin_with_args(input, &["--color", "never"])
+}
+
+/// Test helper to run ustar-dumper with `args` and stdin and capture output
+fn run_ustar_parser_stdin_with_args(
+    input: &str,
+    args: &[&str],
+) -> Result<String, Box<dyn std::error::Error>> {
     let binary_path = get_dumper_binary();
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
     let mut child = Command::new(binary_path)
+        .args(args)
         .current_dir(workspace_root)
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
@@ -118,8 +132,11 @@ fn test_cli_output_format() {
 
 #[test]
 fn test_cli_whitespace_visualization() {
-    let output = run_ustar_parser_stdin("data_test\n_item \"hello world\"\n")
-        .expect("Failed to run ustar-dumper");
+    let output = run_ustar_parser_stdin_with_args(
+        "data_test\n_item \"hello world\"\n",
+        &["--color", "always"],
+    )
+    .expect("Failed to run ustar-dumper");
 
     // Check that spaces in quoted strings are highlighted with dots
     // The dot character is surrounded by ANSI escape sequences, so we check for the parts
@@ -134,6 +151,25 @@ fn test_cli_whitespace_visualization() {
     );
 }
 
+#[test]
+fn test_cli_whitespace_without_color_or_markers() {
+    let input = "data_test\n_item \"hello world\"\n";
+    let plain = run_ustar_parser_stdin(input).expect("Failed to run ustar-dumper");
+    assert!(!plain.contains('\x1b'), "--color never should not color");
+    assert!(plain.contains("hello·world"));
+
+    // the default, auto, doesn't color output that isn't a terminal
+    let unmarked = run_ustar_parser_stdin_with_args(input, &["--no-whitespace-markers"])
+        .expect("Failed to run ustar-dumper");
+    assert!(
+        !unmarked.contains('\x1b'),
+        "piped output should not be colored"
+    );
+    assert!(unmarked.contains("\"hello world\""));
+    assert!(unmarked.contains("data_test\\n_item"));
+    assert!(!unmarked.contains('·'));
+}
+
 #[test]
 fn test_cli_test_input_star_file() {
     let output = run_ustar_parser("ustar-parser/tests/test_data/test_input.star")
@@ -219,6 +255,23 @@ fn test_cli_error_handling() {
     assert!(result.is_err(), "Should fail on invalid syntax");
 }
 
+#[test]
+fn test_cli_error_names_input_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let input = directory.path().join("broken.cif");
+    std::fs::write(&input, "data_test\n_entry.id\n_entry.title  'a title'\n").unwrap();
+
+    let error = run_ustar_parser(input.to_str().unwrap()).unwrap_err();
+
+    assert!(
+        error
+            .to_string()
+            .contains(&format!("{}:3:1", input.display())),
+        "error should name the file: {}",
+        error
+    );
+}
+
 #[test]
 fn test_cli_comprehensive_example_without_tree() {
     let output = run_ustar_parser("ustar-parser/tests/test_data/comprehensive_example.star")
@@ -249,7 +302,7 @@ fn test_cli_simple_example_with_tree() {
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
     let mut child = Command::new(binary_path)
-        .arg("--tree")
+        .args(&["--color", "never", "--tree"])
         .current_dir(workspace_root)
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
@@ -270,3 +323,479 @@ fn test_cli_simple_example_with_tree() {
 
     assert_snapshot_gz("ustar_dumper_tests__simple_example_with_tree", &output_str);
 }
+
+/// Test helper to run ustar-dumper with arguments and capture output
+fn run_ustar_dumper_args(args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let binary_path = get_dumper_binary();
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
+    let output = Command::new(binary_path)
+        .args(args)
+        .current_dir(workspace_root)
+        .output()?;
+
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else {
+        let stderr = String::from_utf8(output.stderr)?;
+        Err(format!("Command failed: {}", stderr).into())
+    }
+}
+
+#[test]
+fn test_cli_comprehensive_example_as_json() {
+    let output = run_ustar_dumper_args(&[
+        "--format",
+        "json",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to run ustar-dumper with --format json");
+
+    // content is neither shortened nor colored
+    assert!(!output.contains('\x1b'));
+    let tree: serde_json::Value = serde_json::from_str(&output).expect("Output is not JSON");
+    assert_eq!(
+        tree["symbols"][0]["end"],
+        tree["symbols"][0]["content"].as_str().unwrap().len()
+    );
+
+    assert_snapshot_gz("ustar_dumper_tests__comprehensive_example_as_json", &output);
+}
+
+#[test]
+fn test_cli_comprehensive_example_as_ndjson() {
+    let output = run_ustar_dumper_args(&[
+        "--format",
+        "ndjson",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to run ustar-dumper with --format ndjson");
+
+    for (index, line) in output.lines().enumerate() {
+        let symbol: serde_json::Value = serde_json::from_str(line).expect("Line is not JSON");
+        assert_eq!(symbol["index"], index + 1);
+    }
+
+    assert_snapshot_gz(
+        "ustar_dumper_tests__comprehensive_example_as_ndjson",
+        &output,
+    );
+}
+
+/// The symbols of comprehensive_example.star written as NDJSON with the filter `args`
+fn ndjson_symbols(args: &[&str]) -> Vec<serde_json::Value> {
+    let mut all_args = vec!["--format", "ndjson"];
+    all_args.extend_from_slice(args);
+    all_args.push("ustar-parser/tests/test_data/comprehensive_example.star");
+    let output = run_ustar_dumper_args(&all_args).expect("Failed to run ustar-dumper");
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Line is not JSON"))
+        .collect()
+}
+
+#[test]
+fn test_cli_filters_compose() {
+    let all = ndjson_symbols(&[]);
+    let names = ndjson_symbols(&["--block", "second_example", "--rule", "data_name"]);
+
+    let tags: Vec<_> = names
+        .iter()
+        .map(|symbol| symbol["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(tags.len(), 22);
+    assert_eq!(tags[0], "_special_chars");
+    assert_eq!(tags[21], "_final_multiline");
+    // filtered symbols keep their numbers and levels in the full dump
+    for symbol in &names {
+        let index = symbol["index"].as_u64().unwrap() as usize;
+        assert_eq!(&all[index - 1], symbol);
+    }
+}
+
+#[test]
+fn test_cli_saveframe_with_max_depth() {
+    let symbols = ndjson_symbols(&["--saveframe", "FRAME_EXAMPLE_1", "--max-depth", "3"]);
+
+    let rules: Vec<_> = symbols
+        .iter()
+        .map(|symbol| symbol["rule"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        rules,
+        vec![
+            "save_frame",
+            "save_heading",
+            "data",
+            "data",
+            "data",
+            "data",
+            "data",
+            "data",
+            "save_keyword"
+        ]
+    );
+    assert!(symbols
+        .iter()
+        .all(|symbol| symbol["level"] == 2 || symbol["level"] == 3));
+}
+
+#[test]
+fn test_cli_select() {
+    let output = run_ustar_dumper_args(&[
+        "--color",
+        "never",
+        "--select",
+        "data_comprehensive_example/save_frame_example_1/_bond_*",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to run ustar-dumper");
+    assert_snapshot_gz("ustar_dumper_tests__comprehensive_example_select", &output);
+
+    let all = ndjson_symbols(&[]);
+    let selected = ndjson_symbols(&["--select", "//_bond_length[2]", "--rule", "string"]);
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0]["content"], "1.52");
+    let index = selected[0]["index"].as_u64().unwrap() as usize;
+    assert_eq!(all[index - 1], selected[0]);
+
+    let invalid = run_ustar_dumper_output(&[
+        "--select",
+        "data_comprehensive_example/frame",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ]);
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&invalid.stderr)
+        .contains("c28 'frame' isn't a data_, global_, save_ or data name segment or *"));
+}
+
+/// Redacts the parse time in --stats output, which changes from run to run
+const PARSE_TIME_FILTER: SnapshotFilter = (
+    r#"(parse time\s+|"parse_time_ms": )[0-9.e+-]+"#,
+    "${1}[time]",
+);
+
+#[test]
+fn test_cli_comprehensive_example_stats() {
+    let output = run_ustar_dumper_args(&[
+        "--stats",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to run ustar-dumper with --stats");
+
+    assert_snapshot_gz_filtered(
+        "ustar_dumper_tests__comprehensive_example_stats",
+        &output,
+        &[PARSE_TIME_FILTER],
+    );
+}
+
+#[test]
+fn test_cli_comprehensive_example_stats_as_json() {
+    let output = run_ustar_dumper_args(&[
+        "--stats",
+        "--format",
+        "json",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to run ustar-dumper with --stats --format json");
+
+    let stats: serde_json::Value = serde_json::from_str(&output).expect("Output is not JSON");
+    assert!(stats["parse_time_ms"].is_f64());
+    assert_snapshot_gz_filtered(
+        "ustar_dumper_tests__comprehensive_example_stats_as_json",
+        &output,
+        &[PARSE_TIME_FILTER],
+    );
+}
+
+#[test]
+fn test_cli_loop_format_without_extract_loop_fails() {
+    let error = run_ustar_dumper_args(&[
+        "--format",
+        "csv",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect_err("csv without --extract-loop should fail");
+
+    assert!(error
+        .to_string()
+        .contains("--format csv and tsv require --extract-loop"));
+}
+
+#[test]
+fn test_cli_extract_nested_loop_as_csv() {
+    let output = run_ustar_dumper_args(&[
+        "--extract-loop",
+        "_struct_conf",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to extract loop");
+
+    // the nested loop is flattened with the outer value repeated on each inner row
+    assert_eq!(
+        output,
+        "_struct_conf_type_id,_struct_conf_atom_site_label,_struct_conf_atom_site_auth_seq_id\n\
+         HELX_P1,CA,123\n\
+         HELX_P1,CB,124\n\
+         HELX_P1,CG,125\n\
+         STRN_S1,CA,456\n\
+         STRN_S1,CB,457\n"
+    );
+}
+
+#[test]
+fn test_cli_extract_loop_as_tsv_to_output_file() {
+    let output_path = std::env::temp_dir().join("ustar_dumper_tests_software.tsv");
+    let output = run_ustar_dumper_args(&[
+        "--extract-loop",
+        "_software",
+        "--format",
+        "tsv",
+        "--output",
+        output_path.to_str().unwrap(),
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ])
+    .expect("Failed to extract loop");
+    let written = std::fs::read_to_string(&output_path).expect("Failed to read output file");
+    let _ = std::fs::remove_file(&output_path);
+
+    assert_eq!(output, "");
+    assert_eq!(
+        written,
+        "_software_name\t_software_version\t_software_author\n\
+         ustar\t1.0\tGary Thompson\n\
+         crystallography\t2024.1\tVarious Authors\n"
+    );
+}
+
+#[test]
+fn test_cli_extract_loop_without_match_fails() {
+    let result = run_ustar_dumper_args(&[
+        "--extract-loop",
+        "_no_such_loop",
+        "ustar-parser/tests/test_data/comprehensive_example.star",
+    ]);
+
+    let error = result
+        .expect_err("Should fail when no loop matches")
+        .to_string();
+    assert!(
+        error.contains("No loop in") && error.contains("_no_such_loop"),
+        "Unexpected error: {}",
+        error
+    );
+}
+
+#[test]
+fn test_cli_warnings() {
+    let input = "data_test\r\n_entry.state  stop\n_entry.note  'a\tb'\r\n";
+    let run = |args: &[&str]| {
+        let mut child = Command::new(get_dumper_binary())
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("Failed to run ustar-dumper");
+        use std::io::Write;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    let (tree, warnings) = run(&["--warnings"]);
+    assert_eq!(
+        warnings,
+        "-: l2:c15 warning[W004] unquoted value stop looks like the reserved word stop_\n\
+         -: l2:c19 warning[W001] line ends with LF but earlier lines end with CR LF\n\
+         -: l3:c16 warning[W002] quoted string contains a tab\n"
+    );
+
+    // the warnings don't change the dump and are only printed when asked for
+    assert_eq!(run(&[]), (tree, String::new()));
+}
+
+#[test]
+fn test_cli_config_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let input = directory.path().join("broken.cif");
+    std::fs::write(&input, "data_test\n_entry.id\n_entry.title  'a title'\n").unwrap();
+    let config = directory.path().join("ustar.toml");
+    std::fs::write(&config, "error_format = \"json\"\ncontext_lines = 0\n").unwrap();
+
+    let error = run_ustar_dumper_args(&[
+        "--config",
+        config.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ])
+    .expect_err("Should fail on invalid syntax")
+    .to_string();
+    assert!(
+        error.contains("{\"code\":\"E0005\""),
+        "error should be JSON: {}",
+        error
+    );
+
+    std::fs::write(&config, "contxt_lines = 5\n").unwrap();
+    let error = run_ustar_dumper_args(&[
+        "--config",
+        config.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ])
+    .expect_err("Should fail on a misspelt key")
+    .to_string();
+    assert!(
+        error.contains("unknown configuration key 'contxt_lines'"),
+        "Unexpected error: {}",
+        error
+    );
+}
+
+/// Run ustar-dumper with `args` from the workspace root, returning its output whether it fails or not
+fn run_ustar_dumper_output(args: &[&str]) -> std::process::Output {
+    let binary_path = get_dumper_binary();
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let workspace_root = manifest_dir.split("/ustar-tools").next().unwrap();
+    Command::new(binary_path)
+        .args(args)
+        .current_dir(workspace_root)
+        .output()
+        .expect("Failed to run ustar-dumper")
+}
+
+#[test]
+fn test_cli_multiple_files_with_a_failure() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let broken = temp_dir.path().join("broken.star");
+    std::fs::write(&broken, "data_broken\n_entry.id\n").unwrap();
+    let broken = broken.to_str().unwrap();
+
+    let output = run_ustar_dumper_output(&[
+        "ustar-parser/tests/test_data/sas_test_files/loop1.str",
+        broken,
+        "ustar-parser/tests/test_data/sas_test_files/loop2.str",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    // the files either side of the broken one are still dumped, each under a header
+    assert_eq!(output.status.code(), Some(1));
+    let headers: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("==> "))
+        .collect();
+    assert_eq!(
+        headers,
+        vec![
+            "==> ustar-parser/tests/test_data/sas_test_files/loop1.str <==",
+            "==> ustar-parser/tests/test_data/sas_test_files/loop2.str <==",
+        ]
+    );
+    assert!(stdout.contains("source: ustar-parser/tests/test_data/sas_test_files/loop2.str"));
+    assert!(stderr.contains(&format!("Syntax error in {}", broken)));
+    assert!(stderr.contains(&format!("1 of 3 files failed:\n  {}: l3:c1 E0004", broken)));
+
+    // with --quiet-success only the failure is reported
+    let quiet = run_ustar_dumper_output(&[
+        "--quiet-success",
+        "ustar-parser/tests/test_data/sas_test_files/loop1.str",
+        broken,
+    ]);
+    assert_eq!(quiet.status.code(), Some(1));
+    assert_eq!(String::from_utf8(quiet.stdout).unwrap(), "");
+    assert!(String::from_utf8(quiet.stderr)
+        .unwrap()
+        .contains("1 of 2 files failed:"));
+
+    let passing = run_ustar_dumper_output(&[
+        "--quiet-success",
+        "ustar-parser/tests/test_data/sas_test_files/loop1.str",
+        "ustar-parser/tests/test_data/sas_test_files/loop2.str",
+    ]);
+    assert!(passing.status.success());
+    assert!(passing.stdout.is_empty());
+}
+
+#[test]
+fn test_cli_reformat_messy_file() {
+    let output = run_ustar_dumper_args(&[
+        "--reformat",
+        "ustar-parser/tests/test_data/messy_layout.star",
+    ])
+    .expect("Failed to run ustar-dumper");
+    assert_snapshot_gz("ustar_dumper_tests__messy_layout_reformatted", &output);
+
+    // the layout is settled, reformatting again changes nothing
+    let again = run_ustar_parser_stdin_with_args(&output, &["--reformat"])
+        .expect("Failed to reformat from stdin");
+    assert_eq!(again, output);
+
+    let unaligned = run_ustar_dumper_args(&[
+        "--reformat",
+        "--align-loops",
+        "false",
+        "ustar-parser/tests/test_data/messy_layout.star",
+    ])
+    .expect("Failed to run ustar-dumper");
+    assert!(unaligned.contains("    1 CA 1.5 # first atom\n"));
+    assert!(output.contains("    1   CA 1.5 # first atom\n"));
+}
+
+#[test]
+fn test_cli_reformat_pynmrstar_style() {
+    ensure_test_data_available(
+        Path::new("../ustar-parser/tests/test_data/bmrb_stars"),
+        TestDataPolicy::DownloadIfMissing,
+    )
+    .expect("Failed to verify test data integrity for BMRB stars");
+
+    let output = run_ustar_dumper_args(&[
+        "--reformat",
+        "--style",
+        "pynmrstar",
+        "ustar-parser/tests/test_data/bmrb_stars/bmr51928_3.str",
+    ])
+    .expect("Failed to run ustar-dumper");
+    assert_snapshot_gz("ustar_dumper_tests__bmr51928_pynmrstar", &output);
+    assert!(output.starts_with("data_51928\n\nsave_entry_information_1\n"));
+
+    let without_reformat = run_ustar_dumper_output(&[
+        "--style",
+        "pynmrstar",
+        "ustar-parser/tests/test_data/messy_layout.star",
+    ]);
+    assert_eq!(without_reformat.status.code(), Some(2));
+}
+
+#[test]
+fn test_cli_reformat_in_place() {
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+    let original =
+        std::fs::read_to_string("../ustar-parser/tests/test_data/messy_layout.star").unwrap();
+    let path = temp_dir.path().join("messy.star");
+    std::fs::write(&path, &original).unwrap();
+    let path_arg = path.to_str().unwrap();
+
+    let expected = run_ustar_dumper_args(&["--reformat", path_arg]).unwrap();
+    let output = run_ustar_dumper_output(&["--reformat", "--in-place", path_arg]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("messy.star.bak")).unwrap(),
+        original
+    );
+
+    let from_stdin = run_ustar_dumper_output(&["--reformat", "--in-place", "-"]);
+    assert_eq!(from_stdin.status.code(), Some(2));
+}